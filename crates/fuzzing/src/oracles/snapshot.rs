@@ -0,0 +1,273 @@
+//! Differential testing of snapshot and restore.
+//!
+//! A [`SnapshotFuzzer`] runs a sequence of calls against a fresh instance of
+//! a module, taking a [`Snapshot`] every few calls. Each snapshot is then
+//! restored into an instance in a new store and the remaining calls are
+//! replayed there. Any difference in the results of the calls or in the
+//! state of the two instances is a bug in snapshotting or restoring.
+
+use crate::generators::DiffValue;
+use crate::oracles::dummy::{dummy_linker, dummy_value};
+use anyhow::{bail, Result};
+use std::fmt;
+use wasmtime::*;
+
+/// A call of an exported function, by name, with the given arguments.
+///
+/// Functions are named rather than given as [`Func`]s, since a [`Func`] is
+/// tied to the store it was created in while every replay uses a new store.
+#[derive(Clone, Debug)]
+pub struct CallSpec {
+    /// The name of the exported function.
+    pub func: String,
+    /// The arguments to the call.
+    pub params: Vec<Val>,
+}
+
+impl CallSpec {
+    /// Creates a call of the exported function `func` with `params`.
+    pub fn new(func: impl Into<String>, params: Vec<Val>) -> CallSpec {
+        CallSpec {
+            func: func.into(),
+            params,
+        }
+    }
+}
+
+/// How often a [`SnapshotFuzzer`] takes a snapshot: before every `N`th call,
+/// and after the last call.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotInterval(pub usize);
+
+/// Configuration of a [`SnapshotFuzzer::run`].
+#[derive(Clone, Debug)]
+pub struct FuzzerConfig {
+    /// How often to snapshot.
+    pub interval: SnapshotInterval,
+}
+
+impl Default for FuzzerConfig {
+    fn default() -> Self {
+        FuzzerConfig {
+            interval: SnapshotInterval(1),
+        }
+    }
+}
+
+/// Runs calls against a module, snapshotting and restoring along the way.
+pub struct SnapshotFuzzer {
+    module: Module,
+    calls: Vec<CallSpec>,
+}
+
+/// The outcome of a [`SnapshotFuzzer::run`].
+#[derive(Clone, Debug, Default)]
+pub struct FuzzReport {
+    /// The number of snapshots which were taken and restored.
+    pub snapshots: usize,
+    /// The number of calls which were replayed after restoring a snapshot.
+    pub replayed_calls: usize,
+    /// Every divergence which was found.
+    pub divergences: Vec<Divergence>,
+}
+
+/// A difference between the original run and a run restored from a snapshot.
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    /// The index of the call before which the restored snapshot was taken.
+    /// This is the number of calls when the snapshot was taken after the
+    /// last call.
+    pub snapshot_at: usize,
+    /// The index of the call whose result differed, or `None` if the state
+    /// of the instances differed.
+    pub call_index: Option<usize>,
+    /// A description of the difference.
+    pub message: String,
+}
+
+type CallResult = Result<Vec<DiffValue>, String>;
+
+impl SnapshotFuzzer {
+    /// Creates a fuzzer running `calls` against instances of `module`.
+    ///
+    /// Imports of `module` are satisfied with dummy definitions.
+    pub fn new(module: Module, calls: Vec<CallSpec>) -> SnapshotFuzzer {
+        SnapshotFuzzer { module, calls }
+    }
+
+    /// Runs all calls once, taking snapshots according to `config`, and then
+    /// replays the remaining calls from each snapshot in a new store.
+    ///
+    /// Traps are results like any other and must be reproduced by the
+    /// replay.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module fails to instantiate, an exported
+    /// function is missing, or a snapshot cannot be taken or restored.
+    /// Divergences are not errors and are returned in the [`FuzzReport`].
+    pub fn run(&self, config: FuzzerConfig) -> Result<FuzzReport> {
+        let SnapshotInterval(interval) = config.interval;
+        if interval == 0 {
+            bail!("snapshot interval must be at least 1");
+        }
+
+        // Run all calls once, recording their results and snapshotting
+        // before every `interval`th call and at the end.
+        let (mut store, instance) = self.instantiate()?;
+        let mut results = Vec::new();
+        let mut snapshots = Vec::new();
+        for (i, call) in self.calls.iter().enumerate() {
+            if i % interval == 0 {
+                snapshots.push((i, instance.snapshot(&mut store)?));
+            }
+            results.push(self.call(&mut store, &instance, call)?);
+        }
+        snapshots.push((self.calls.len(), instance.snapshot(&mut store)?));
+
+        let mut report = FuzzReport::default();
+        for (at, snapshot) in snapshots.iter() {
+            log::debug!("replaying from snapshot before call {at}");
+            report.snapshots += 1;
+            let (mut store, instance) = self.instantiate()?;
+            instance.restore(&mut store, snapshot)?;
+
+            for (i, call) in self.calls.iter().enumerate().skip(*at) {
+                // Compare the state wherever the original run snapshotted.
+                if let Some((_, expected)) = snapshots.iter().find(|(j, _)| *j == i) {
+                    check_state(&mut store, &instance, expected, *at, &mut report)?;
+                }
+                report.replayed_calls += 1;
+                let result = self.call(&mut store, &instance, call)?;
+                if result != results[i] {
+                    report.divergences.push(Divergence {
+                        snapshot_at: *at,
+                        call_index: Some(i),
+                        message: format!(
+                            "call to `{}` returned {:?}, expected {:?}",
+                            call.func, result, results[i]
+                        ),
+                    });
+                }
+            }
+            let (_, last) = snapshots.last().unwrap();
+            check_state(&mut store, &instance, last, *at, &mut report)?;
+        }
+        Ok(report)
+    }
+
+    fn instantiate(&self) -> Result<(Store<()>, Instance)> {
+        let mut store = Store::new(self.module.engine(), ());
+        let linker = dummy_linker(&mut store, &self.module)?;
+        let instance = linker.instantiate(&mut store, &self.module)?;
+        Ok((store, instance))
+    }
+
+    fn call(
+        &self,
+        store: &mut Store<()>,
+        instance: &Instance,
+        call: &CallSpec,
+    ) -> Result<CallResult> {
+        let func = match instance.get_func(&mut *store, &call.func) {
+            Some(func) => func,
+            None => bail!("no exported function named `{}`", call.func),
+        };
+        let mut results = func
+            .ty(&*store)
+            .results()
+            .map(dummy_value)
+            .collect::<Vec<_>>();
+        Ok(match func.call(&mut *store, &call.params, &mut results) {
+            Ok(()) => Ok(results.into_iter().map(|v| v.into()).collect()),
+            Err(e) => Err(match e.downcast_ref::<Trap>() {
+                Some(trap) => trap.to_string(),
+                None => format!("{e:?}"),
+            }),
+        })
+    }
+}
+
+fn check_state(
+    store: &mut Store<()>,
+    instance: &Instance,
+    expected: &Snapshot,
+    snapshot_at: usize,
+    report: &mut FuzzReport,
+) -> Result<()> {
+    let actual = instance.snapshot(&mut *store)?;
+    let diff = Snapshot::diff_report(expected, &actual)?;
+    if !diff.is_identical() {
+        report.divergences.push(Divergence {
+            snapshot_at,
+            call_index: None,
+            message: diff.to_string(),
+        });
+    }
+    Ok(())
+}
+
+impl FuzzReport {
+    /// Returns whether no divergence was found.
+    pub fn is_ok(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "restored from before call {}: ", self.snapshot_at)?;
+        match self.call_index {
+            Some(i) => write!(f, "call {i}: {}", self.message),
+            None => write!(f, "state differs: {}", self.message),
+        }
+    }
+}
+
+#[test]
+fn counter() -> Result<()> {
+    let engine = Engine::default();
+    let module = Module::new(
+        &engine,
+        r#"
+            (module
+                (memory (export "memory") 1)
+                (global $count (mut i32) (i32.const 0))
+                (func (export "bump") (param i32) (result i32)
+                    (global.set $count (i32.add (global.get $count) (local.get 0)))
+                    (i32.store (i32.const 16) (global.get $count))
+                    global.get $count)
+                (func (export "trap-if-big") (result i32)
+                    (if (i32.gt_u (global.get $count) (i32.const 5))
+                        (then unreachable))
+                    (i32.load (i32.const 16)))
+            )
+        "#,
+    )?;
+    let calls = vec![
+        CallSpec::new("bump", vec![Val::I32(1)]),
+        CallSpec::new("trap-if-big", vec![]),
+        CallSpec::new("bump", vec![Val::I32(2)]),
+        CallSpec::new("bump", vec![Val::I32(3)]),
+        CallSpec::new("trap-if-big", vec![]),
+    ];
+    let fuzzer = SnapshotFuzzer::new(module, calls);
+
+    let report = fuzzer.run(FuzzerConfig::default())?;
+    assert!(report.is_ok(), "{:?}", report.divergences);
+    assert_eq!(report.snapshots, 6);
+    assert_eq!(report.replayed_calls, 5 + 4 + 3 + 2 + 1);
+
+    let report = fuzzer.run(FuzzerConfig {
+        interval: SnapshotInterval(2),
+    })?;
+    assert!(report.is_ok(), "{:?}", report.divergences);
+    assert_eq!(report.snapshots, 4);
+
+    assert!(fuzzer
+        .run(FuzzerConfig {
+            interval: SnapshotInterval(0)
+        })
+        .is_err());
+    Ok(())
+}
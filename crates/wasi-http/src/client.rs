@@ -0,0 +1,37 @@
+//! Pluggable HTTP clients for the `wasi:http/outgoing-handler` interface.
+
+use crate::body::{HyperIncomingBody, HyperOutgoingBody};
+
+/// A request made by the guest through `wasi:http/outgoing-handler`.
+///
+/// The URI is absolute, and the `Host` header is set to its authority.
+pub type WasiHttpRequest = hyper::Request<HyperOutgoingBody>;
+
+/// The response to a [`WasiHttpRequest`].
+pub type WasiHttpResponse = hyper::Response<HyperIncomingBody>;
+
+/// Sends the outgoing HTTP requests of the guest, installed with
+/// [`WasiHttpCtx::with_http_outbound`](crate::WasiHttpCtx::with_http_outbound).
+///
+/// This allows routing requests through a proxy, adding authentication,
+/// recording them or mocking them in tests. Without a client, requests are
+/// sent with a built-in client based on `hyper`.
+///
+/// The first-byte timeout requested by the guest is applied to
+/// [`WasiHttpClient::send`], and the between-bytes timeout to reading the
+/// body of the response. Other options, such as the connect timeout, are only
+/// honored by the built-in client.
+///
+/// Requests in flight are not part of a [`Snapshot`](wasmtime::Snapshot) of
+/// the component: after restoring a snapshot taken while a request was
+/// pending, the guest has to make the request again.
+#[async_trait::async_trait]
+pub trait WasiHttpClient: Send + Sync {
+    /// Sends `request` and returns its response once the headers were
+    /// received.
+    ///
+    /// An error is reported to the guest as the outcome of its request. It
+    /// may be a [`types::Error`](crate::bindings::http::types::Error) to
+    /// report a specific kind of failure.
+    async fn send(&self, request: WasiHttpRequest) -> anyhow::Result<WasiHttpResponse>;
+}
@@ -92,6 +92,24 @@ impl<T: WasiHttpView> outgoing_handler::Host for T {
 
         let request = builder.body(body).map_err(http_protocol_error)?;
 
+        if let Some(client) = self.ctx().client.clone() {
+            let handle = preview2::spawn(async move {
+                let resp = timeout(first_byte_timeout, client.send(request))
+                    .await
+                    .map_err(|_| timeout_error("first byte"))??;
+                // The client owns its connections, so there is no worker to
+                // drive them.
+                let worker = preview2::spawn(async { Ok(()) });
+                Ok(IncomingResponseInternal {
+                    resp,
+                    worker,
+                    between_bytes_timeout,
+                })
+            });
+            let fut = self.table().push(HostFutureIncomingResponse::new(handle))?;
+            return Ok(Ok(fut));
+        }
+
         let handle = preview2::spawn(async move {
             let tcp_stream = TcpStream::connect(authority.clone())
                 .await
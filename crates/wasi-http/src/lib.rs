@@ -1,6 +1,8 @@
+pub use crate::client::{WasiHttpClient, WasiHttpRequest, WasiHttpResponse};
 pub use crate::types::{WasiHttpCtx, WasiHttpView};
 
 pub mod body;
+pub mod client;
 pub mod http_impl;
 pub mod proxy;
 pub mod types;
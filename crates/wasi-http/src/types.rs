@@ -4,13 +4,33 @@
 use crate::{
     bindings::http::types::{self, Method, Scheme},
     body::{HostIncomingBodyBuilder, HyperIncomingBody, HyperOutgoingBody},
+    WasiHttpClient,
 };
 use std::any::Any;
+use std::sync::Arc;
 use wasmtime::component::Resource;
 use wasmtime_wasi::preview2::{AbortOnDropJoinHandle, Subscribe, Table};
 
 /// Capture the state necessary for use in the wasi-http API implementation.
-pub struct WasiHttpCtx;
+#[derive(Clone, Default)]
+pub struct WasiHttpCtx {
+    pub(crate) client: Option<Arc<dyn WasiHttpClient>>,
+}
+
+impl WasiHttpCtx {
+    /// Creates a context which sends outgoing requests with the built-in
+    /// client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send the outgoing requests of the guest with `client` instead of the
+    /// built-in client. See [`WasiHttpClient`] for details.
+    pub fn with_http_outbound(&mut self, client: impl WasiHttpClient + 'static) -> &mut Self {
+        self.client = Some(Arc::new(client));
+        self
+    }
+}
 
 pub trait WasiHttpView: Send {
     fn ctx(&mut self) -> &mut WasiHttpCtx;
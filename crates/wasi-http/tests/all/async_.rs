@@ -5,13 +5,17 @@ use wasmtime_wasi::preview2::command::Command;
 foreach_http!(assert_test_exists);
 
 async fn run(path: &str, server: &Server) -> Result<()> {
+    run_with(path, &server.addr().to_string(), WasiHttpCtx::new()).await
+}
+
+async fn run_with(path: &str, addr: &str, http: WasiHttpCtx) -> Result<()> {
     let mut config = Config::new();
     config.wasm_backtrace_details(wasmtime::WasmBacktraceDetails::Enable);
     config.wasm_component_model(true);
     config.async_support(true);
     let engine = Engine::new(&config)?;
     let component = Component::from_file(&engine, path)?;
-    let mut store = store(&engine, server);
+    let mut store = store_with(&engine, addr, http);
     let mut linker = Linker::new(&engine);
     wasmtime_wasi_http::proxy::add_to_linker(&mut linker)?;
     let (command, _instance) = Command::instantiate_async(&mut store, &component, &linker).await?;
@@ -78,3 +82,45 @@ async fn http_outbound_request_response_build() -> Result<()> {
     let server = Server::http1()?;
     run(HTTP_OUTBOUND_REQUEST_RESPONSE_BUILD_COMPONENT, &server).await
 }
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn http_outbound_request_custom_client() -> Result<()> {
+    use http_body_util::{BodyExt, Empty};
+    use std::sync::{Arc, Mutex};
+    use wasmtime_wasi_http::{WasiHttpClient, WasiHttpRequest, WasiHttpResponse};
+
+    // Answers like the test server, without touching the network.
+    struct EchoClient(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl WasiHttpClient for EchoClient {
+        async fn send(&self, request: WasiHttpRequest) -> Result<WasiHttpResponse> {
+            let uri = request.uri().to_string();
+            self.0.lock().unwrap().push(uri.clone());
+            Ok(hyper::Response::builder()
+                .status(http::StatusCode::OK)
+                .header("x-wasmtime-test-method", request.method().as_str())
+                .header("x-wasmtime-test-uri", uri)
+                .body(
+                    Empty::<bytes::Bytes>::new()
+                        .map_err(|_| anyhow::anyhow!("empty error"))
+                        .boxed(),
+                )?)
+        }
+    }
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let mut http = WasiHttpCtx::new();
+    http.with_http_outbound(EchoClient(requests.clone()));
+    run_with(
+        HTTP_OUTBOUND_REQUEST_GET_COMPONENT,
+        "example.invalid:8080",
+        http,
+    )
+    .await?;
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec!["http://example.invalid:8080/get?some=arg&goes=here".to_string()]
+    );
+    Ok(())
+}
@@ -43,6 +43,10 @@ impl WasiHttpView for Ctx {
 }
 
 fn store(engine: &Engine, server: &Server) -> Store<Ctx> {
+    store_with(engine, &server.addr().to_string(), WasiHttpCtx::new())
+}
+
+fn store_with(engine: &Engine, addr: &str, http: WasiHttpCtx) -> Store<Ctx> {
     let stdout = MemoryOutputPipe::new(4096);
     let stderr = MemoryOutputPipe::new(4096);
 
@@ -50,11 +54,11 @@ fn store(engine: &Engine, server: &Server) -> Store<Ctx> {
     let mut builder = WasiCtxBuilder::new();
     builder.stdout(stdout.clone());
     builder.stderr(stderr.clone());
-    builder.env("HTTP_SERVER", server.addr().to_string());
+    builder.env("HTTP_SERVER", addr);
     let ctx = Ctx {
         table: Table::new(),
         wasi: builder.build(),
-        http: WasiHttpCtx {},
+        http,
         stderr,
         stdout,
     };
@@ -105,7 +109,7 @@ async fn wasi_http_proxy_tests() -> anyhow::Result<()> {
     builder.stdout(stdout.clone());
     builder.stderr(stderr.clone());
     let wasi = builder.build();
-    let http = WasiHttpCtx;
+    let http = WasiHttpCtx::new();
     let ctx = Ctx {
         table,
         wasi,
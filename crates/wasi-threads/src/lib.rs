@@ -5,15 +5,20 @@
 use anyhow::{anyhow, Result};
 use rand::Rng;
 use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::Arc;
 use std::thread;
 use wasmtime::{Caller, ExternType, InstancePre, Linker, Module, SharedMemory, Store, ValType};
 use wasmtime_wasi::maybe_exit_on_error;
+use wasmtime_wasi::preview2::WasiView;
 
 // This name is a function export designated by the wasi-threads specification:
 // https://github.com/WebAssembly/wasi-threads/#detailed-design-discussion
 const WASI_ENTRY_POINT: &str = "wasi_thread_start";
 
+/// The largest thread ID allowed by the wasi-threads specification.
+const MAX_THREAD_ID: i32 = 0x1FFF_FFFF;
+
 pub struct WasiThreadsCtx<T> {
     instance_pre: Arc<InstancePre<T>>,
 }
@@ -132,13 +137,81 @@ pub fn add_to_linker<T: Clone + Send + 'static>(
         },
     )?;
 
-    // Find the shared memory import and satisfy it with a newly-created shared
-    // memory import.
+    define_shared_memory(linker, store, module)?;
+    Ok(())
+}
+
+/// Add the WASI `thread_spawn` function to the linker, forwarding spawn
+/// requests to the [`WasiThreadSpawner`] of the store's preview2
+/// [`WasiCtx`], which was installed with
+/// [`WasiCtxBuilder::with_thread_spawner`].
+///
+/// Unlike [`add_to_linker`], this leaves creating the instance of each
+/// thread and calling its `wasi_thread_start` export to the spawner. Thread
+/// IDs are handed out in order, starting at 1, and the guest is told that
+/// spawning failed if no spawner has been configured.
+///
+/// # Errors
+///
+/// Returns an error if `module` imports a memory which is not shared or
+/// does not import a memory at all.
+///
+/// [`WasiThreadSpawner`]: wasmtime_wasi::preview2::WasiThreadSpawner
+/// [`WasiCtx`]: wasmtime_wasi::preview2::WasiCtx
+/// [`WasiCtxBuilder::with_thread_spawner`]: wasmtime_wasi::preview2::WasiCtxBuilder::with_thread_spawner
+pub fn add_to_linker_with_spawner<T: WasiView + 'static>(
+    linker: &mut Linker<T>,
+    store: &Store<T>,
+    module: &Module,
+) -> Result<()> {
+    let memory = define_shared_memory(linker, store, module)?
+        .ok_or_else(|| anyhow!("a `wasi-threads` module must import a shared memory"))?;
+    let next_thread_id = Arc::new(AtomicI32::new(1));
+    linker.func_wrap(
+        "wasi",
+        "thread-spawn",
+        move |caller: Caller<'_, T>, start_arg: i32| -> i32 {
+            let ctx = caller.data().ctx();
+            let prefix = ctx.log_prefix();
+            log::trace!("{prefix}new thread requested via `wasi::thread_spawn` call");
+            let spawner = match ctx.thread_spawner() {
+                Some(spawner) => spawner.clone(),
+                None => {
+                    log::error!("{prefix}failed to spawn thread: no thread spawner configured");
+                    return -1;
+                }
+            };
+            let thread_id = next_thread_id.fetch_add(1, Ordering::Relaxed);
+            if thread_id > MAX_THREAD_ID {
+                log::error!("{prefix}failed to spawn thread: out of thread IDs");
+                return -1;
+            }
+            match spawner.spawn(memory.clone(), thread_id, start_arg) {
+                Ok(()) => thread_id,
+                Err(e) => {
+                    log::error!("{prefix}failed to spawn thread: {e}");
+                    -1
+                }
+            }
+        },
+    )?;
+    Ok(())
+}
+
+/// Find the shared memory import of `module` and satisfy it with a
+/// newly-created shared memory, which is returned.
+fn define_shared_memory<T>(
+    linker: &mut Linker<T>,
+    store: &Store<T>,
+    module: &Module,
+) -> Result<Option<SharedMemory>> {
+    let mut memory = None;
     for import in module.imports() {
         if let Some(m) = import.ty().memory() {
             if m.is_shared() {
                 let mem = SharedMemory::new(module.engine(), m.clone())?;
                 linker.define(store, import.module(), import.name(), mem.clone())?;
+                memory = Some(mem);
             } else {
                 return Err(anyhow!(
                     "memory was not shared; a `wasi-threads` must import \
@@ -147,7 +220,7 @@ pub fn add_to_linker<T: Clone + Send + 'static>(
             }
         }
     }
-    Ok(())
+    Ok(memory)
 }
 
 /// Check if wasi-threads' `wasi_thread_start` export is present.
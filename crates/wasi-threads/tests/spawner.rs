@@ -0,0 +1,97 @@
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use wasmtime::{Config, Engine, Linker, Module, SharedMemory, Store};
+use wasmtime_wasi::preview2::{Table, WasiCtx, WasiCtxBuilder, WasiThreadSpawner, WasiView};
+use wasmtime_wasi_threads::add_to_linker_with_spawner;
+
+struct Ctx {
+    table: Table,
+    wasi: WasiCtx,
+}
+
+impl WasiView for Ctx {
+    fn table(&self) -> &Table {
+        &self.table
+    }
+    fn table_mut(&mut self) -> &mut Table {
+        &mut self.table
+    }
+    fn ctx(&self) -> &WasiCtx {
+        &self.wasi
+    }
+    fn ctx_mut(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+#[derive(Clone, Default)]
+struct RecordingSpawner(Arc<Mutex<Vec<(i32, i32, u64)>>>);
+
+impl WasiThreadSpawner for RecordingSpawner {
+    fn spawn(&self, memory: SharedMemory, thread_id: i32, start_arg: i32) -> Result<()> {
+        if start_arg < 0 {
+            anyhow::bail!("refusing to spawn");
+        }
+        self.0
+            .lock()
+            .unwrap()
+            .push((thread_id, start_arg, memory.size()));
+        Ok(())
+    }
+}
+
+#[test]
+fn spawn_with_spawner() -> Result<()> {
+    let mut config = Config::new();
+    config.wasm_threads(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(
+        &engine,
+        r#"(module
+            (import "env" "memory" (memory 1 1 shared))
+            (import "wasi" "thread-spawn" (func $spawn (param i32) (result i32)))
+            (func (export "spawn") (param i32) (result i32)
+                local.get 0
+                call $spawn))"#,
+    )?;
+
+    let spawner = RecordingSpawner::default();
+    let wasi = WasiCtxBuilder::new()
+        .with_thread_spawner(spawner.clone())
+        .build();
+    let mut store = Store::new(
+        &engine,
+        Ctx {
+            table: Table::new(),
+            wasi,
+        },
+    );
+    let mut linker = Linker::new(&engine);
+    add_to_linker_with_spawner(&mut linker, &store, &module)?;
+    let instance = linker.instantiate(&mut store, &module)?;
+    let spawn = instance.get_typed_func::<i32, i32>(&mut store, "spawn")?;
+    assert_eq!(spawn.call(&mut store, 7)?, 1);
+    assert_eq!(spawn.call(&mut store, 8)?, 2);
+    assert_eq!(spawn.call(&mut store, -1)?, -1);
+    assert_eq!(*spawner.0.lock().unwrap(), [(1, 7, 1), (2, 8, 1)]);
+
+    // Without a spawner, spawning fails.
+    let mut store = Store::new(
+        &engine,
+        Ctx {
+            table: Table::new(),
+            wasi: WasiCtxBuilder::new().build(),
+        },
+    );
+    let mut linker = Linker::new(&engine);
+    add_to_linker_with_spawner(&mut linker, &store, &module)?;
+    let instance = linker.instantiate(&mut store, &module)?;
+    let spawn = instance.get_typed_func::<i32, i32>(&mut store, "spawn")?;
+    assert_eq!(spawn.call(&mut store, 7)?, -1);
+
+    let unshared = Module::new(&engine, r#"(module (import "env" "memory" (memory 1)))"#)?;
+    assert!(add_to_linker_with_spawner(&mut linker, &store, &unshared).is_err());
+    let memoryless = Module::new(&engine, "(module)")?;
+    assert!(add_to_linker_with_spawner(&mut linker, &store, &memoryless).is_err());
+    Ok(())
+}
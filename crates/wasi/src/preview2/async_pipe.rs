@@ -0,0 +1,270 @@
+use crate::preview2::{
+    HostInputStream, HostOutputStream, StdinStream, StdoutStream, StreamError, StreamResult,
+    Subscribe,
+};
+use anyhow::anyhow;
+use bytes::{Bytes, BytesMut};
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+
+/// The number of bytes each end of a pipe buffers on top of the capacity of
+/// the pipe itself.
+const PIPE_BUFFER_SIZE: usize = 4096;
+
+/// The capacity of a pipe created by
+/// [`WasiCtxBuilder::pipe`](crate::preview2::WasiCtxBuilder::pipe), as of a
+/// pipe on Linux.
+pub(crate) const PIPE_CAPACITY: usize = 65536;
+
+/// Creates a pipe of `capacity` bytes, returning the stdout of its producer
+/// and the stdin of its consumer.
+pub(crate) fn pipe(capacity: usize) -> (AsyncPipeOutputStream, AsyncPipeInputStream) {
+    let (writer, reader) = tokio::io::duplex(capacity);
+    (
+        AsyncPipeOutputStream {
+            end: Arc::new(Mutex::new(WriteEnd {
+                stream: writer,
+                pending: BytesMut::new(),
+                flush_pending: false,
+                error: None,
+                closed: false,
+            })),
+        },
+        AsyncPipeInputStream {
+            end: Arc::new(Mutex::new(ReadEnd {
+                stream: reader,
+                buffer: BytesMut::new(),
+                error: None,
+                closed: false,
+            })),
+        },
+    )
+}
+
+/// Polls `poll` once without registering for a wakeup, for making progress
+/// on a pipe outside of [`Subscribe::ready`].
+fn poll_now<T>(poll: impl FnOnce(&mut Context<'_>) -> Poll<T>) -> Poll<T> {
+    poll(&mut Context::from_waker(futures::task::noop_waker_ref()))
+}
+
+struct WriteEnd {
+    stream: DuplexStream,
+    pending: BytesMut,
+    flush_pending: bool,
+    error: Option<anyhow::Error>,
+    closed: bool,
+}
+
+impl WriteEnd {
+    fn check_error(&mut self) -> StreamResult<()> {
+        if let Some(e) = self.error.take() {
+            return Err(StreamError::LastOperationFailed(e));
+        }
+        if self.closed {
+            return Err(StreamError::Closed);
+        }
+        Ok(())
+    }
+
+    /// Moves pending bytes into the pipe until they are all written or the
+    /// pipe is full.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        while !self.pending.is_empty() && !self.closed {
+            match Pin::new(&mut self.stream).poll_write(cx, &self.pending) {
+                Poll::Ready(Ok(n)) => {
+                    let _ = self.pending.split_to(n);
+                }
+                Poll::Ready(Err(e)) => {
+                    // The consumer is gone, so the bytes can never be read.
+                    self.pending.clear();
+                    self.error = Some(e.into());
+                    self.closed = true;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.flush_pending = false;
+        Poll::Ready(())
+    }
+
+    fn capacity(&self) -> usize {
+        if self.flush_pending {
+            0
+        } else {
+            PIPE_BUFFER_SIZE - self.pending.len()
+        }
+    }
+}
+
+impl Drop for WriteEnd {
+    fn drop(&mut self) {
+        // Hand over what the pipe can still take before closing it.
+        let _ = poll_now(|cx| self.poll_drain(cx));
+    }
+}
+
+/// The write end of a pipe created by
+/// [`WasiCtxBuilder::pipe`](crate::preview2::WasiCtxBuilder::pipe), used as
+/// the stdout of the producer.
+///
+/// All streams opened on it share the same end, which is closed once the
+/// last of them is dropped together with the producer's context.
+#[derive(Clone)]
+pub(crate) struct AsyncPipeOutputStream {
+    end: Arc<Mutex<WriteEnd>>,
+}
+
+impl AsyncPipeOutputStream {
+    fn end(&self) -> std::sync::MutexGuard<'_, WriteEnd> {
+        self.end.lock().unwrap()
+    }
+}
+
+impl HostOutputStream for AsyncPipeOutputStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let mut end = self.end();
+        end.check_error()?;
+        if bytes.len() > end.capacity() {
+            return Err(StreamError::Trap(anyhow!("write exceeded budget")));
+        }
+        end.pending.extend_from_slice(&bytes);
+        let _ = poll_now(|cx| end.poll_drain(cx));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        let mut end = self.end();
+        end.check_error()?;
+        end.flush_pending = !end.pending.is_empty();
+        let _ = poll_now(|cx| end.poll_drain(cx));
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        let mut end = self.end();
+        let _ = poll_now(|cx| end.poll_drain(cx));
+        end.check_error()?;
+        Ok(end.capacity())
+    }
+
+    fn drain_written(&mut self) -> Vec<u8> {
+        let mut end = self.end();
+        end.flush_pending = false;
+        end.pending.split().to_vec()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for AsyncPipeOutputStream {
+    async fn ready(&mut self) {
+        poll_fn(|cx| {
+            let mut end = self.end();
+            let _ = end.poll_drain(cx);
+            if end.error.is_some() || end.closed || end.capacity() > 0 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl StdoutStream for AsyncPipeOutputStream {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+struct ReadEnd {
+    stream: DuplexStream,
+    buffer: BytesMut,
+    error: Option<anyhow::Error>,
+    closed: bool,
+}
+
+impl ReadEnd {
+    /// Reads from the pipe into the buffer if it is empty.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if !self.buffer.is_empty() || self.error.is_some() || self.closed {
+            return Poll::Ready(());
+        }
+        let mut bytes = [0; PIPE_BUFFER_SIZE];
+        let mut buf = ReadBuf::new(&mut bytes);
+        match Pin::new(&mut self.stream).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) if buf.filled().is_empty() => self.closed = true,
+            Poll::Ready(Ok(())) => self.buffer.extend_from_slice(buf.filled()),
+            Poll::Ready(Err(e)) => self.error = Some(e.into()),
+            Poll::Pending => return Poll::Pending,
+        }
+        Poll::Ready(())
+    }
+}
+
+/// The read end of a pipe created by
+/// [`WasiCtxBuilder::pipe`](crate::preview2::WasiCtxBuilder::pipe), used as
+/// the stdin of the consumer.
+///
+/// All streams opened on it share the same end, and so make progress
+/// through the input together.
+#[derive(Clone)]
+pub(crate) struct AsyncPipeInputStream {
+    end: Arc<Mutex<ReadEnd>>,
+}
+
+impl AsyncPipeInputStream {
+    fn end(&self) -> std::sync::MutexGuard<'_, ReadEnd> {
+        self.end.lock().unwrap()
+    }
+}
+
+impl HostInputStream for AsyncPipeInputStream {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let mut end = self.end();
+        let _ = poll_now(|cx| end.poll_fill(cx));
+        if !end.buffer.is_empty() {
+            let len = end.buffer.len().min(size);
+            return Ok(end.buffer.split_to(len).freeze());
+        }
+        if let Some(e) = end.error.take() {
+            end.closed = true;
+            return Err(StreamError::LastOperationFailed(e));
+        }
+        if end.closed {
+            return Err(StreamError::Closed);
+        }
+        Ok(Bytes::new())
+    }
+
+    fn num_ready_bytes(&self) -> usize {
+        self.end().buffer.len()
+    }
+
+    fn drain_pending(&mut self) -> Vec<u8> {
+        self.end().buffer.split().to_vec()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for AsyncPipeInputStream {
+    async fn ready(&mut self) {
+        poll_fn(|cx| self.end().poll_fill(cx)).await
+    }
+}
+
+impl StdinStream for AsyncPipeInputStream {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
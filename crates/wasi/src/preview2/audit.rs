@@ -0,0 +1,108 @@
+use crate::preview2::trace::write_json_string;
+use std::fmt::{self, Write as _};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Writes a record of the network accesses of the guest to a writer,
+/// configured with
+/// [`WasiCtxBuilder::network_audit_log`](crate::preview2::WasiCtxBuilder::network_audit_log).
+///
+/// Each access is written as a single line of JSON such as
+///
+/// ```text
+/// {"ts":"2023-11-20T08:15:00.123456Z","event":"tcp_connect","addr":"10.0.0.1:5432","allowed":true}
+/// ```
+///
+/// where `ts` is the UTC wall-clock time of the host. If the context has a
+/// [debug label](crate::preview2::WasiCtxBuilder::debug_label), it is
+/// written as a `label` field after `ts`.
+pub(crate) struct NetworkAuditLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+    label: Option<String>,
+}
+
+impl NetworkAuditLog {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        NetworkAuditLog {
+            writer: Mutex::new(writer),
+            label: None,
+        }
+    }
+
+    /// Labels all records with `label`.
+    pub(crate) fn with_label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Writes a record of an access to `addr`. Errors writing the record are
+    /// ignored so that auditing never affects the guest.
+    pub(crate) fn record(&self, event: &str, addr: impl fmt::Display, allowed: bool) {
+        let mut line = String::new();
+        line.push_str("{\"ts\":");
+        write_json_string(&mut line, &rfc3339(SystemTime::now()));
+        if let Some(label) = &self.label {
+            line.push_str(",\"label\":");
+            write_json_string(&mut line, label);
+        }
+        line.push_str(",\"event\":");
+        write_json_string(&mut line, event);
+        line.push_str(",\"addr\":");
+        write_json_string(&mut line, &addr.to_string());
+        writeln!(line, ",\"allowed\":{allowed}}}").unwrap();
+        let _ = self.writer.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with microseconds. Times
+/// before the Unix epoch are written as the epoch.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Convert days since the epoch to a civil date in the proleptic
+    // Gregorian calendar, counting eras of 400 years from 0000-03-01.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:06}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_micros(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn timestamps_are_rfc3339() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000000Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::new(951_782_400, 1_500)),
+            "2000-02-29T00:00:00.000001Z"
+        );
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(1_700_468_100)),
+            "2023-11-20T08:15:00.000000Z"
+        );
+        assert_eq!(
+            rfc3339(UNIX_EPOCH - Duration::from_secs(1)),
+            "1970-01-01T00:00:00.000000Z"
+        );
+    }
+}
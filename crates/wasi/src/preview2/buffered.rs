@@ -0,0 +1,195 @@
+use crate::preview2::{HostOutputStream, StdoutStream, StreamError, StreamResult, Subscribe};
+use anyhow::anyhow;
+use bytes::{Bytes, BytesMut};
+
+/// When a [`BufferedOutputStream`] passes buffered bytes on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum BufferMode {
+    /// Up to the last newline, or everything once the buffer is full.
+    Line,
+    /// Everything once the buffer is full.
+    Full,
+}
+
+/// Standard output whose streams buffer writes, configured with
+/// [`WasiCtxBuilder::line_buffered_stdout`](crate::preview2::WasiCtxBuilder::line_buffered_stdout)
+/// and
+/// [`WasiCtxBuilder::fully_buffered_stdout`](crate::preview2::WasiCtxBuilder::fully_buffered_stdout).
+pub(crate) struct BufferedStdout {
+    inner: Box<dyn StdoutStream>,
+    mode: BufferMode,
+    capacity: usize,
+}
+
+impl BufferedStdout {
+    pub(crate) fn new(inner: Box<dyn StdoutStream>, mode: BufferMode, capacity: usize) -> Self {
+        Self {
+            inner,
+            mode,
+            capacity,
+        }
+    }
+}
+
+impl StdoutStream for BufferedStdout {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(BufferedOutputStream {
+            inner: self.inner.stream(),
+            mode: self.mode,
+            capacity: self.capacity,
+            buffer: BytesMut::new(),
+            flush_pending: false,
+            inner_flushing: false,
+            error: None,
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.inner.isatty()
+    }
+}
+
+/// An output stream which collects small writes into a buffer of
+/// `capacity` bytes before passing them on, so that a guest writing one byte
+/// at a time does not cause a write to the underlying stream for every byte.
+///
+/// The permit of [`HostOutputStream::check_write`] is the room left in the
+/// buffer. A flush passes all buffered bytes on and then flushes the
+/// underlying stream. Bytes still buffered when the stream is dropped are
+/// passed on as far as the underlying stream accepts them without waiting.
+struct BufferedOutputStream {
+    inner: Box<dyn HostOutputStream>,
+    mode: BufferMode,
+    capacity: usize,
+    buffer: BytesMut,
+    /// Whether a flush was requested which waits for the buffer to drain
+    /// before being passed on.
+    flush_pending: bool,
+    /// Whether a flush was passed on which the underlying stream has not
+    /// completed yet.
+    inner_flushing: bool,
+    /// An error of the underlying stream raised while waiting for it, to be
+    /// reported by the next operation.
+    error: Option<StreamError>,
+}
+
+impl BufferedOutputStream {
+    /// Returns the number of buffered bytes which are due to be passed on.
+    fn due(&self) -> usize {
+        if self.flush_pending || self.buffer.len() >= self.capacity {
+            return self.buffer.len();
+        }
+        match self.mode {
+            BufferMode::Line => self
+                .buffer
+                .iter()
+                .rposition(|b| *b == b'\n')
+                .map_or(0, |i| i + 1),
+            BufferMode::Full => 0,
+        }
+    }
+
+    /// Passes as many due bytes on as the underlying stream accepts without
+    /// waiting, followed by a pending flush once the buffer is empty.
+    fn forward(&mut self) -> StreamResult<()> {
+        let mut due = self.due();
+        while due > 0 {
+            let permit = self.inner.check_write()?;
+            if permit == 0 {
+                return Ok(());
+            }
+            let n = permit.min(due);
+            self.inner.write(self.buffer.split_to(n).freeze())?;
+            due -= n;
+        }
+        if self.flush_pending && self.buffer.is_empty() {
+            self.inner.flush()?;
+            self.flush_pending = false;
+            self.inner_flushing = true;
+        }
+        Ok(())
+    }
+
+    fn take_error(&mut self) -> StreamResult<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl HostOutputStream for BufferedOutputStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.take_error()?;
+        if self.flush_pending {
+            return Err(StreamError::Trap(anyhow!(
+                "write not permitted while flush pending"
+            )));
+        }
+        if bytes.len() > self.capacity - self.buffer.len() {
+            return Err(StreamError::Trap(anyhow!("write exceeded budget")));
+        }
+        self.buffer.extend_from_slice(&bytes);
+        self.forward()
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.take_error()?;
+        self.flush_pending = true;
+        self.forward()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.take_error()?;
+        self.forward()?;
+        if self.flush_pending {
+            return Ok(0);
+        }
+        if self.inner_flushing {
+            if self.inner.check_write()? == 0 {
+                return Ok(0);
+            }
+            self.inner_flushing = false;
+        }
+        Ok(self.capacity - self.buffer.len())
+    }
+
+    fn drain_written(&mut self) -> Vec<u8> {
+        let mut drained = std::mem::take(&mut self.buffer).to_vec();
+        drained.extend(self.inner.drain_written());
+        self.flush_pending = false;
+        drained
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for BufferedOutputStream {
+    async fn ready(&mut self) {
+        loop {
+            if self.error.is_some() {
+                return;
+            }
+            if self.inner_flushing {
+                self.inner.ready().await;
+                return;
+            }
+            if let Err(e) = self.forward() {
+                self.error = Some(e);
+                return;
+            }
+            if !self.flush_pending && !self.inner_flushing && self.buffer.len() < self.capacity {
+                return;
+            }
+            self.inner.ready().await;
+        }
+    }
+}
+
+impl Drop for BufferedOutputStream {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            self.flush_pending = true;
+            let _ = self.forward();
+        }
+    }
+}
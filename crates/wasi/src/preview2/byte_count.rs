@@ -0,0 +1,154 @@
+use crate::preview2::{
+    HostInputStream, HostOutputStream, StdinStream, StdoutStream, StreamResult, Subscribe,
+};
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Standard output or error which counts the bytes written to all of its
+/// streams, read with
+/// [`WasiCtx::stdout_bytes_written`](crate::preview2::WasiCtx::stdout_bytes_written)
+/// and [`WasiCtx::stderr_bytes_written`](crate::preview2::WasiCtx::stderr_bytes_written).
+pub(crate) struct CountingStdout {
+    inner: Box<dyn StdoutStream>,
+    written: Arc<AtomicU64>,
+}
+
+impl CountingStdout {
+    pub(crate) fn new(inner: Box<dyn StdoutStream>, written: Arc<AtomicU64>) -> Self {
+        Self { inner, written }
+    }
+}
+
+impl StdoutStream for CountingStdout {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(CountingOutputStream {
+            inner: self.inner.stream(),
+            written: Arc::clone(&self.written),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.inner.isatty()
+    }
+}
+
+/// An output stream which adds the length of every successful write to a
+/// shared counter.
+struct CountingOutputStream {
+    inner: Box<dyn HostOutputStream>,
+    written: Arc<AtomicU64>,
+}
+
+impl CountingOutputStream {
+    fn count(&self, len: usize) {
+        self.written.fetch_add(len as u64, Ordering::Relaxed);
+    }
+}
+
+impl HostOutputStream for CountingOutputStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let len = bytes.len();
+        self.inner.write(bytes)?;
+        self.count(len);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.inner.check_write()
+    }
+
+    fn write_zeroes(&mut self, nelem: usize) -> StreamResult<()> {
+        self.inner.write_zeroes(nelem)?;
+        self.count(nelem);
+        Ok(())
+    }
+
+    fn drain_written(&mut self) -> Vec<u8> {
+        // Drained bytes are counted again when they are written back.
+        let drained = self.inner.drain_written();
+        self.written
+            .fetch_sub(drained.len() as u64, Ordering::Relaxed);
+        drained
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for CountingOutputStream {
+    async fn ready(&mut self) {
+        self.inner.ready().await;
+    }
+}
+
+/// Standard input which counts the bytes read from all of its streams,
+/// reported by
+/// [`WasiCtx::execution_stats`](crate::preview2::WasiCtx::execution_stats).
+pub(crate) struct CountingStdin {
+    inner: Box<dyn StdinStream>,
+    read: Arc<AtomicU64>,
+}
+
+impl CountingStdin {
+    pub(crate) fn new(inner: Box<dyn StdinStream>, read: Arc<AtomicU64>) -> Self {
+        Self { inner, read }
+    }
+}
+
+impl StdinStream for CountingStdin {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(CountingInputStream {
+            inner: self.inner.stream(),
+            read: Arc::clone(&self.read),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.inner.isatty()
+    }
+
+    fn fork(&self) -> Option<Box<dyn StdinStream>> {
+        let inner = self.inner.fork()?;
+        Some(Box::new(CountingStdin::new(inner, Arc::clone(&self.read))))
+    }
+}
+
+/// An input stream which adds the number of bytes read or skipped to a
+/// shared counter.
+struct CountingInputStream {
+    inner: Box<dyn HostInputStream>,
+    read: Arc<AtomicU64>,
+}
+
+#[async_trait::async_trait]
+impl HostInputStream for CountingInputStream {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let bytes = self.inner.read(size)?;
+        self.read.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, nelem: usize) -> StreamResult<usize> {
+        let skipped = self.inner.skip(nelem)?;
+        self.read.fetch_add(skipped as u64, Ordering::Relaxed);
+        Ok(skipped)
+    }
+
+    fn num_ready_bytes(&self) -> usize {
+        self.inner.num_ready_bytes()
+    }
+
+    fn drain_pending(&mut self) -> Vec<u8> {
+        self.inner.drain_pending()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for CountingInputStream {
+    async fn ready(&mut self) {
+        self.inner.ready().await;
+    }
+}
@@ -0,0 +1,79 @@
+use cap_rand::RngCore;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A proof that the holder was authorized by a [`CapabilityIssuer`] to grant
+/// network access to a guest, checked by
+/// [`WasiCtxBuilder::insert_ip_net_with_token`](crate::preview2::WasiCtxBuilder::insert_ip_net_with_token).
+///
+/// A token is 32 cryptographically random bytes which cannot be guessed, so
+/// only code which was handed a token by the issuer can use it. Unlike the
+/// `AmbientAuthority` marker used elsewhere, which only documents that
+/// authority was checked at the call site, tokens are verified at runtime.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct CapabilityToken([u8; 32]);
+
+impl CapabilityToken {
+    /// Reconstructs a token from its bytes, for example after passing it
+    /// between processes.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Returns the bytes of this token.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for CapabilityToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The token is a secret, so keep it out of logs.
+        f.write_str("CapabilityToken(..)")
+    }
+}
+
+/// The trusted issuer of [`CapabilityToken`]s, registered with
+/// [`WasiCtxBuilder::capability_issuer`](crate::preview2::WasiCtxBuilder::capability_issuer).
+///
+/// Clones share the set of issued tokens, so an issuer can be created once at
+/// startup and shared with every builder.
+#[derive(Clone, Default)]
+pub struct CapabilityIssuer {
+    issued: Arc<Mutex<HashSet<CapabilityToken>>>,
+}
+
+impl CapabilityIssuer {
+    /// Creates an issuer which has not issued any tokens.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Issues a new token, valid until it is revoked.
+    pub fn issue(&self) -> CapabilityToken {
+        let mut bytes = [0; 32];
+        cap_rand::thread_rng(cap_rand::ambient_authority()).fill_bytes(&mut bytes);
+        let token = CapabilityToken(bytes);
+        self.issued.lock().unwrap().insert(token.clone());
+        token
+    }
+
+    /// Revokes `token`, returning whether it was valid.
+    pub fn revoke(&self, token: &CapabilityToken) -> bool {
+        self.issued.lock().unwrap().remove(token)
+    }
+
+    /// Returns whether `token` was issued by this issuer and not revoked.
+    pub fn verify(&self, token: &CapabilityToken) -> bool {
+        self.issued.lock().unwrap().contains(token)
+    }
+}
+
+impl fmt::Debug for CapabilityIssuer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CapabilityIssuer")
+            .field("issued", &self.issued.lock().unwrap().len())
+            .finish()
+    }
+}
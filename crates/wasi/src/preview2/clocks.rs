@@ -1,5 +1,8 @@
 pub mod host;
 use cap_std::time::Duration;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 
 pub trait HostWallClock: Send + Sync {
     fn resolution(&self) -> Duration;
@@ -10,3 +13,231 @@ pub trait HostMonotonicClock: Send + Sync {
     fn resolution(&self) -> u64;
     fn now(&self) -> u64;
 }
+
+/// A wall and monotonic clock which is stopped at zero, i.e. the Unix epoch
+/// for the wall clock.
+pub(crate) struct EpochClock;
+
+impl HostWallClock for EpochClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+impl HostMonotonicClock for EpochClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        0
+    }
+}
+
+/// A wall and monotonic clock which stands still at a given reading until it
+/// is advanced explicitly, for testing components which depend on time.
+///
+/// As a wall clock, the reading is the time since the Unix epoch.
+/// [`WasiCtx::freeze_monotonic_clock`](crate::preview2::WasiCtx::freeze_monotonic_clock)
+/// installs a frozen clock into an existing context.
+#[derive(Debug, Default)]
+pub struct FrozenClock {
+    now: AtomicU64,
+}
+
+impl FrozenClock {
+    /// Creates a clock which reads `ns` nanoseconds.
+    pub fn at_ns(ns: u64) -> Self {
+        Self {
+            now: AtomicU64::new(ns),
+        }
+    }
+
+    /// Moves the clock forward by `delta_ns` nanoseconds, saturating at
+    /// `u64::MAX`.
+    pub fn advance(&self, delta_ns: u64) {
+        let _ = self
+            .now
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |now| {
+                Some(now.saturating_add(delta_ns))
+            });
+    }
+
+    /// Returns the current reading of the clock in nanoseconds.
+    pub fn now_ns(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+impl HostWallClock for FrozenClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.now_ns())
+    }
+}
+
+impl HostMonotonicClock for FrozenClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        self.now_ns()
+    }
+}
+
+/// A clock of the host operating system, read with `clock_gettime`, for
+/// [`WasiCtxBuilder::wall_clock_source`](crate::preview2::WasiCtxBuilder::wall_clock_source).
+///
+/// A source can also be installed as the monotonic clock with
+/// [`WasiCtxBuilder::monotonic_clock`](crate::preview2::WasiCtxBuilder::monotonic_clock),
+/// which makes [`ClockSource::ProcessCpu`] useful for measuring the time the
+/// process spends running. A source which is not
+/// [available](ClockSource::is_available) always reads zero, with a
+/// resolution of one nanosecond.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ClockSource {
+    /// The system-wide real-time clock, `CLOCK_REALTIME`, which follows leap
+    /// seconds and adjustments of the system time.
+    Realtime,
+    /// The clock counting from an unspecified point in the past which is not
+    /// affected by adjustments of the system time, `CLOCK_MONOTONIC`.
+    Monotonic,
+    /// International Atomic Time, `CLOCK_TAI`, which has no leap second
+    /// discontinuities. Only available on Linux.
+    Tai,
+    /// The CPU time consumed by the process, `CLOCK_PROCESS_CPUTIME_ID`.
+    ProcessCpu,
+}
+
+impl ClockSource {
+    /// Returns whether this clock can be read on this platform.
+    pub fn is_available(&self) -> bool {
+        self.read(ClockRead::Resolution).is_some()
+    }
+
+    #[cfg(unix)]
+    fn id(&self) -> Option<libc::clockid_t> {
+        match self {
+            ClockSource::Realtime => Some(libc::CLOCK_REALTIME),
+            ClockSource::Monotonic => Some(libc::CLOCK_MONOTONIC),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            ClockSource::Tai => Some(libc::CLOCK_TAI),
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd"
+            ))]
+            ClockSource::ProcessCpu => Some(libc::CLOCK_PROCESS_CPUTIME_ID),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    #[cfg(unix)]
+    fn read(&self, what: ClockRead) -> Option<Duration> {
+        let id = self.id()?;
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: `ts` is a valid, writable `timespec`.
+        let ret = unsafe {
+            match what {
+                ClockRead::Now => libc::clock_gettime(id, &mut ts),
+                ClockRead::Resolution => libc::clock_getres(id, &mut ts),
+            }
+        };
+        if ret != 0 {
+            return None;
+        }
+        Some(Duration::new(
+            u64::try_from(ts.tv_sec).ok()?,
+            u32::try_from(ts.tv_nsec).ok()?,
+        ))
+    }
+
+    #[cfg(not(unix))]
+    fn read(&self, _what: ClockRead) -> Option<Duration> {
+        None
+    }
+
+    fn read_or_default(&self, what: ClockRead) -> Duration {
+        self.read(what).unwrap_or(match what {
+            ClockRead::Now => Duration::ZERO,
+            ClockRead::Resolution => Duration::from_nanos(1),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ClockRead {
+    Now,
+    Resolution,
+}
+
+impl HostWallClock for ClockSource {
+    fn resolution(&self) -> Duration {
+        self.read_or_default(ClockRead::Resolution)
+    }
+
+    fn now(&self) -> Duration {
+        self.read_or_default(ClockRead::Now)
+    }
+}
+
+impl HostMonotonicClock for ClockSource {
+    fn resolution(&self) -> u64 {
+        let resolution = self.read_or_default(ClockRead::Resolution);
+        resolution.as_nanos().try_into().unwrap_or(u64::MAX)
+    }
+
+    fn now(&self) -> u64 {
+        let now = self.read_or_default(ClockRead::Now);
+        now.as_nanos().try_into().unwrap_or(u64::MAX)
+    }
+}
+
+/// What a clock read returns once the clock has passed a deadline set with
+/// [`WasiCtxBuilder::deadline_wall_clock`](crate::preview2::WasiCtxBuilder::deadline_wall_clock)
+/// or
+/// [`WasiCtxBuilder::deadline_monotonic_clock`](crate::preview2::WasiCtxBuilder::deadline_monotonic_clock).
+pub enum DeadlineBehavior {
+    /// Fail the read with a [`DeadlineExceeded`] error, which traps the guest.
+    ReturnError,
+    /// Return the given time instead of the current time. Monotonic clocks
+    /// ignore the given time and stop at their deadline instead.
+    ReturnEpoch(SystemTime),
+    /// Invoke the callback on every read and return the current time as usual.
+    CallCallback(Box<dyn Fn() + Send + Sync>),
+}
+
+/// The error returned from a clock read past its deadline when configured
+/// with [`DeadlineBehavior::ReturnError`].
+///
+/// Embedders can test if an error returned from wasm is this error.
+#[derive(Debug)]
+pub struct DeadlineExceeded;
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "clock deadline exceeded")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// A deadline for a clock, as a value of that clock.
+pub(crate) struct Deadline<T> {
+    pub(crate) at: T,
+    pub(crate) behavior: DeadlineBehavior,
+}
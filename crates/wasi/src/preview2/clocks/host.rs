@@ -71,3 +71,55 @@ pub fn monotonic_clock() -> Box<dyn HostMonotonicClock + Send + Sync> {
 pub fn wall_clock() -> Box<dyn HostWallClock + Send + Sync> {
     Box::new(WallClock::new(ambient_authority()))
 }
+
+/// The host's wall clock, shifted by a fixed number of nanoseconds.
+pub(crate) struct OffsetWallClock {
+    clock: WallClock,
+    offset: i64,
+}
+
+impl OffsetWallClock {
+    pub(crate) fn new(offset: i64) -> Self {
+        Self {
+            clock: WallClock::new(ambient_authority()),
+            offset,
+        }
+    }
+}
+
+impl HostWallClock for OffsetWallClock {
+    fn resolution(&self) -> Duration {
+        self.clock.resolution()
+    }
+
+    fn now(&self) -> Duration {
+        let now = self.clock.now().as_nanos() as i128 + i128::from(self.offset);
+        let now = u64::try_from(now.max(0)).unwrap_or(u64::MAX);
+        Duration::from_nanos(now)
+    }
+}
+
+/// The host's monotonic clock, starting at a given reading instead of zero.
+pub(crate) struct OffsetMonotonicClock {
+    clock: MonotonicClock,
+    start: u64,
+}
+
+impl OffsetMonotonicClock {
+    pub(crate) fn new(start: u64) -> Self {
+        Self {
+            clock: MonotonicClock::new(ambient_authority()),
+            start,
+        }
+    }
+}
+
+impl HostMonotonicClock for OffsetMonotonicClock {
+    fn resolution(&self) -> u64 {
+        self.clock.resolution()
+    }
+
+    fn now(&self) -> u64 {
+        self.start.saturating_add(self.clock.now())
+    }
+}
@@ -0,0 +1,224 @@
+use crate::preview2::{DirPerms, FilePerms, TextEncoding, WasiCtxBuilder};
+use anyhow::{ensure, Context, Result};
+use cap_std::ambient_authority;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// A configuration file read by [`WasiCtxBuilder::from_toml`] and
+/// [`WasiCtxBuilder::from_json`], whose settings live in a `wasi` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    wasi: WasiConfig,
+}
+
+/// The settings of a configuration file, each named after the builder
+/// method it calls.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct WasiConfig {
+    sandbox_mode: bool,
+    env: BTreeMap<String, String>,
+    args: Vec<String>,
+    stdin: Option<String>,
+    inherit_stdin: bool,
+    inherit_stdout: bool,
+    inherit_stderr: bool,
+    inherit_stdio: bool,
+    line_buffered_stdout: Option<usize>,
+    fully_buffered_stdout: Option<usize>,
+    stdout_encoding: Option<EncodingConfig>,
+    preopens: Vec<PreopenConfig>,
+    network: Option<NetworkConfig>,
+    allow_ip_name_lookup: Option<bool>,
+    insecure_random_seed: Option<u128>,
+    insecure_random_from_seed: Option<u64>,
+    memory_limit_bytes: Option<usize>,
+    max_table_entries: Option<u32>,
+    max_open_files: Option<u32>,
+    max_open_dirs: Option<u32>,
+    max_open_sockets: Option<u32>,
+    debug_label: Option<String>,
+}
+
+#[derive(Deserialize)]
+enum EncodingConfig {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[serde(rename = "latin1")]
+    Latin1,
+    #[serde(rename = "windows-1252")]
+    Windows1252,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PreopenConfig {
+    host: PathBuf,
+    guest: String,
+    #[serde(default)]
+    readonly: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NetworkConfig {
+    None,
+    Inherit,
+}
+
+impl WasiCtxBuilder {
+    /// Creates a builder configured by the JSON document `config`, for hosts
+    /// whose operators configure components with files rather than code.
+    ///
+    /// The settings are read from the `wasi` object and are named after the
+    /// builder methods they call:
+    ///
+    /// ```text
+    /// {
+    ///     "wasi": {
+    ///         "env": {"FOO": "bar"},
+    ///         "args": ["--verbose"],
+    ///         "inherit_stdio": true,
+    ///         "max_open_files": 100,
+    ///         "network": "none",
+    ///         "preopens": [{"host": "/srv/data", "guest": "/data", "readonly": true}]
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// Settings which take a value, such as `max_table_entries`,
+    /// `memory_limit_bytes`, `debug_label` or `stdout_encoding` (one of
+    /// `"utf-8"`, `"latin1"` or `"windows-1252"`), call the method of the
+    /// same name, and flags such as `inherit_stdout` or `sandbox_mode` call
+    /// it when `true`. `stdin` is a string read by the guest. Each preopen is
+    /// opened at its `host` path with
+    /// [`preopened_dir_at_host_path`](WasiCtxBuilder::preopened_dir_at_host_path),
+    /// with all permissions unless it is `readonly`. `network` is either
+    /// `"none"`, the default, or `"inherit"` to
+    /// [inherit the network](WasiCtxBuilder::inherit_network) of the host.
+    /// `sandbox_mode` is applied before all other settings, which can
+    /// loosen it.
+    ///
+    /// Settings which take host objects, such as streams, clocks or
+    /// callbacks, cannot be expressed in a file and are configured on the
+    /// returned builder instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `config` is not valid JSON, contains an unknown
+    /// setting or a value of the wrong type, or if a preopened directory
+    /// cannot be opened.
+    pub fn from_json(config: &str) -> Result<WasiCtxBuilder> {
+        let config: ConfigFile =
+            serde_json::from_str(config).context("invalid WASI configuration")?;
+        config.wasi.apply()
+    }
+
+    /// Same as [`WasiCtxBuilder::from_json`], but for a TOML document whose
+    /// settings live in a `wasi` table.
+    ///
+    /// ```toml
+    /// [wasi]
+    /// env = { FOO = "bar" }
+    /// args = ["--verbose"]
+    /// max_open_files = 100
+    /// preopens = [{ host = "/srv/data", guest = "/data", readonly = true }]
+    /// ```
+    ///
+    /// This is only available with the `toml` feature.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(config: &str) -> Result<WasiCtxBuilder> {
+        let config: ConfigFile = toml::from_str(config).context("invalid WASI configuration")?;
+        config.wasi.apply()
+    }
+}
+
+impl WasiConfig {
+    fn apply(self) -> Result<WasiCtxBuilder> {
+        let mut builder = WasiCtxBuilder::new();
+        if self.sandbox_mode {
+            builder.sandbox_mode();
+        }
+        for (key, value) in &self.env {
+            builder.env(key, value);
+        }
+        builder.args(self.args.as_slice());
+        if let Some(stdin) = self.stdin {
+            builder.stdin_from_str(stdin);
+        }
+        if self.inherit_stdio {
+            builder.inherit_stdio();
+        }
+        if self.inherit_stdin {
+            builder.inherit_stdin();
+        }
+        if self.inherit_stdout {
+            builder.inherit_stdout();
+        }
+        if self.inherit_stderr {
+            builder.inherit_stderr();
+        }
+        if let Some(capacity) = self.line_buffered_stdout {
+            ensure!(capacity > 0, "stdout buffer capacity must not be zero");
+            builder.line_buffered_stdout(capacity);
+        }
+        if let Some(capacity) = self.fully_buffered_stdout {
+            ensure!(capacity > 0, "stdout buffer capacity must not be zero");
+            builder.fully_buffered_stdout(capacity);
+        }
+        if let Some(encoding) = self.stdout_encoding {
+            builder.stdout_encoding(match encoding {
+                EncodingConfig::Utf8 => TextEncoding::Utf8,
+                EncodingConfig::Latin1 => TextEncoding::Latin1,
+                EncodingConfig::Windows1252 => TextEncoding::Windows1252,
+            });
+        }
+        for preopen in &self.preopens {
+            let (perms, file_perms) = if preopen.readonly {
+                (DirPerms::READ, FilePerms::READ)
+            } else {
+                (DirPerms::all(), FilePerms::all())
+            };
+            builder
+                .preopened_dir_at_host_path(&preopen.host, perms, file_perms, &preopen.guest)
+                .with_context(|| format!("failed to preopen `{}`", preopen.host.display()))?;
+        }
+        match self.network {
+            Some(NetworkConfig::Inherit) => {
+                builder.inherit_network(ambient_authority());
+            }
+            Some(NetworkConfig::None) | None => {}
+        }
+        if let Some(enable) = self.allow_ip_name_lookup {
+            builder.allow_ip_name_lookup(enable);
+        }
+        if let Some(seed) = self.insecure_random_seed {
+            builder.insecure_random_seed(seed);
+        }
+        if let Some(seed) = self.insecure_random_from_seed {
+            builder.insecure_random_from_seed(seed);
+        }
+        if let Some(limit) = self.memory_limit_bytes {
+            builder.memory_limit_bytes(limit);
+        }
+        if let Some(max_entries) = self.max_table_entries {
+            builder.max_table_entries(max_entries);
+        }
+        if let Some(limit) = self.max_open_files {
+            builder.max_open_files(limit);
+        }
+        if let Some(limit) = self.max_open_dirs {
+            builder.max_open_dirs(limit);
+        }
+        if let Some(limit) = self.max_open_sockets {
+            builder.max_open_sockets(limit);
+        }
+        if let Some(label) = self.debug_label {
+            builder.debug_label(label);
+        }
+        Ok(builder)
+    }
+}
@@ -1,33 +1,115 @@
 use super::clocks::host::{monotonic_clock, wall_clock};
 use crate::preview2::{
-    clocks::{self, HostMonotonicClock, HostWallClock},
-    filesystem::Dir,
-    pipe, random, stdio,
-    stdio::{StdinStream, StdoutStream},
-    DirPerms, FilePerms, Table,
+    async_pipe::{self, PIPE_CAPACITY},
+    audit::NetworkAuditLog,
+    buffered::{BufferMode, BufferedStdout},
+    byte_count::{CountingStdin, CountingStdout},
+    clocks::{self, Deadline, DeadlineBehavior, FrozenClock, HostMonotonicClock, HostWallClock},
+    encoding::TranscodingStdout,
+    events::{EventQueue, EventStdin},
+    fake_filesystem::{CopiedRoots, FakeRoot, OverlayDir, UnionDir},
+    filesystem::{Descriptor, Dir},
+    memory_limit::{MemoryLimiter, Unlimited},
+    metrics::WasiMetrics,
+    migration::PreopenExport,
+    network::{self, NetworkPoolCapture},
+    pause::{PausableStdout, PauseBuffer, PausedIoBuffers},
+    pipe,
+    profiler::ComponentProfiler,
+    quota::QuotaTracker,
+    random::{self, ReplayDivergence},
+    rate_limit::{RateLimitedStdin, RateLimitedStdout, TokenBucket},
+    scheduler::Scheduling,
+    signals::SignalQueue,
+    stats::{ExecutionCounters, ResourceCallback, StatsLimiter},
+    stdio,
+    stdio::{StdinStream, StdoutStream, STDIO_BUFFER_SIZE},
+    stream_buffers,
+    tee::{SharedWriter, TeeStdin, TeeStdout, WriteOutputStream},
+    trace::{SyscallTrace, TraceValue},
+    tracing_stream::TracingStdout,
+    BoundedMemoryCreator, CapabilityIssuer, CapabilityPolicy, CapabilityToken, ClockSource,
+    DirPerms, DirQuota, DirQuotaUsage, ExecutionStats, ExitBehavior, FakeFilesystem, FilePerms,
+    HostOutputStream, IoGuard, IoRateLimitConfig, IsATTY, PosixSignalHandler, RecordedRng,
+    ReplayRng, ResourceKind, ResourceUsageEvent, ScopePolicy, SecretProvider, SignalAction,
+    StreamBufferCapture, Table, TableError, TcpSocketFactory, TeeOutputStream, TextEncoding,
+    WasiEvent, WasiStateExport, WasiThreadSpawner, WasmScheduler,
 };
+use anyhow::Context as _;
 use cap_rand::{Rng, RngCore, SeedableRng};
 use cap_std::ipnet::{self, IpNet};
-use cap_std::net::Pool;
+use cap_std::net::{Pool, ToSocketAddrs};
 use cap_std::{ambient_authority, AmbientAuthority};
+use std::any::Any;
 use std::mem;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use wasmtime::{
+    CallHook, Engine, ResourceLimiterAsync, Snapshot, Store, SuspendState, UpdateDeadline,
+};
 
 pub struct WasiCtxBuilder {
     stdin: Box<dyn StdinStream>,
     stdout: Box<dyn StdoutStream>,
     stderr: Box<dyn StdoutStream>,
+    io_rate_limit: IoRateLimitConfig,
+    stdout_buffering: Option<(BufferMode, usize)>,
+    stdout_encoding: TextEncoding,
     env: Vec<(String, String)>,
+    env_secrets: Vec<(String, Arc<dyn SecretProvider>)>,
     args: Vec<String>,
     preopens: Vec<(Dir, String)>,
+    preopen_exports: Vec<PreopenExport>,
+    dir_quotas: Vec<(String, DirQuota)>,
+    fake_root: Option<FakeRoot>,
+    overlay_dirs: Vec<(String, OverlayDir)>,
+    union_dirs: Vec<UnionDir>,
 
     pool: Pool,
+    pool_capture: NetworkPoolCapture,
+    socket_factory: Option<Arc<dyn TcpSocketFactory>>,
+    unix_socket_dir: Option<PathBuf>,
+    network_audit_log: Option<NetworkAuditLog>,
+    capability_issuer: Option<CapabilityIssuer>,
     random: Box<dyn RngCore + Send + Sync>,
+    random_replay: Option<ReplayDivergence>,
     insecure_random: Box<dyn RngCore + Send + Sync>,
+    insecure_random_replay: Option<ReplayDivergence>,
     insecure_random_seed: u128,
     wall_clock: Box<dyn HostWallClock + Send + Sync>,
+    /// The source of `wall_clock` if it was set with
+    /// [`WasiCtxBuilder::wall_clock_source`] to a clock which is not
+    /// available, which fails the build.
+    unavailable_wall_clock: Option<ClockSource>,
     monotonic_clock: Box<dyn HostMonotonicClock + Send + Sync>,
+    wall_clock_deadline: Option<Deadline<Duration>>,
+    monotonic_clock_deadline: Option<Deadline<u64>>,
     allow_ip_name_lookup: bool,
+    component_id: Option<Arc<dyn Any + Send + Sync>>,
+    capability_policy: CapabilityPolicy,
+    preopen_scope: ScopePolicy,
+    memory_creator: Option<Arc<BoundedMemoryCreator>>,
+    resource_limiter: Option<Box<dyn ResourceLimiterAsync + Send + Sync>>,
+    thread_spawner: Option<Arc<dyn WasiThreadSpawner>>,
+    signal_handler: Option<Box<dyn PosixSignalHandler>>,
+    profiler: Option<(Box<dyn ComponentProfiler>, u64)>,
+    scheduler: Option<Arc<dyn WasmScheduler>>,
+    enable_metrics: bool,
+    exit_handler: Option<Arc<dyn Fn(i32) -> ExitBehavior + Send + Sync>>,
+    on_resource_create: Option<ResourceHook>,
+    on_resource_drop: Option<ResourceHook>,
+    resource_callback: Option<ResourceCallback>,
+    max_table_entries: Option<u32>,
+    handle_limits: HandleLimits,
+    prebound_resources: Vec<(u32, Box<dyn Any + Send + Sync>)>,
+    trace: Option<SyscallTrace>,
+    debug_label: Option<String>,
+    request_id: Option<String>,
+    stdout_tracing: Option<tracing::Level>,
+    stderr_tracing: Option<tracing::Level>,
     built: bool,
 }
 
@@ -68,16 +150,57 @@ impl WasiCtxBuilder {
             stdin: Box::new(pipe::ClosedInputStream),
             stdout: Box::new(pipe::SinkOutputStream),
             stderr: Box::new(pipe::SinkOutputStream),
+            io_rate_limit: IoRateLimitConfig::default(),
+            stdout_buffering: None,
+            stdout_encoding: TextEncoding::Utf8,
             env: Vec::new(),
+            env_secrets: Vec::new(),
             args: Vec::new(),
             preopens: Vec::new(),
+            preopen_exports: Vec::new(),
+            dir_quotas: Vec::new(),
+            fake_root: None,
+            overlay_dirs: Vec::new(),
+            union_dirs: Vec::new(),
             pool: Pool::new(),
+            pool_capture: NetworkPoolCapture::default(),
+            socket_factory: None,
+            unix_socket_dir: None,
+            network_audit_log: None,
+            capability_issuer: None,
             random: random::thread_rng(),
+            random_replay: None,
             insecure_random,
+            insecure_random_replay: None,
             insecure_random_seed,
             wall_clock: wall_clock(),
+            unavailable_wall_clock: None,
             monotonic_clock: monotonic_clock(),
+            wall_clock_deadline: None,
+            monotonic_clock_deadline: None,
             allow_ip_name_lookup: false,
+            component_id: None,
+            capability_policy: CapabilityPolicy::new(),
+            preopen_scope: ScopePolicy::default(),
+            memory_creator: None,
+            resource_limiter: None,
+            thread_spawner: None,
+            signal_handler: None,
+            profiler: None,
+            scheduler: None,
+            enable_metrics: false,
+            exit_handler: None,
+            on_resource_create: None,
+            on_resource_drop: None,
+            resource_callback: None,
+            max_table_entries: None,
+            handle_limits: HandleLimits::default(),
+            prebound_resources: Vec::new(),
+            trace: None,
+            debug_label: None,
+            request_id: None,
+            stdout_tracing: None,
+            stderr_tracing: None,
             built: false,
         }
     }
@@ -87,13 +210,65 @@ impl WasiCtxBuilder {
         self
     }
 
+    /// Use `data` as the contents of stdin, which is not a TTY and is closed
+    /// once all of `data` has been read.
+    pub fn stdin_from_bytes(&mut self, data: impl Into<Vec<u8>>) -> &mut Self {
+        self.stdin(pipe::MemoryInputPipe::new(data.into().into()))
+    }
+
+    /// Use the UTF-8 encoding of `s` as the contents of stdin, like
+    /// [`stdin_from_bytes`](WasiCtxBuilder::stdin_from_bytes).
+    pub fn stdin_from_str(&mut self, s: impl Into<String>) -> &mut Self {
+        self.stdin_from_bytes(s.into())
+    }
+
+    /// Use the chunks returned by `gen` as the contents of stdin, which is not
+    /// a TTY and is closed once `gen` returns `None`.
+    ///
+    /// `gen` is called with the number of times it was called before, each
+    /// time the guest reads after consuming the previous chunk; see
+    /// [`GeneratorInputStream`](pipe::GeneratorInputStream). To record the
+    /// generated input for replay, pass a `GeneratorInputStream` to
+    /// [`stdin`](WasiCtxBuilder::stdin) and keep a clone of it instead.
+    pub fn stdin_generator(
+        &mut self,
+        gen: impl Fn(u64) -> Option<Vec<u8>> + Send + 'static,
+    ) -> &mut Self {
+        self.stdin(pipe::GeneratorInputStream::new(gen))
+    }
+
     pub fn stdout(&mut self, stdout: impl StdoutStream + 'static) -> &mut Self {
         self.stdout = Box::new(stdout);
+        self.stdout_tracing = None;
         self
     }
 
     pub fn stderr(&mut self, stderr: impl StdoutStream + 'static) -> &mut Self {
         self.stderr = Box::new(stderr);
+        self.stderr_tracing = None;
+        self
+    }
+
+    /// Emit every line the guest writes to stdout as a `tracing` event at
+    /// `level`, so that its output flows into structured logging pipelines.
+    ///
+    /// Events are emitted within a `wasi` span whose `component` field is
+    /// the [debug label](WasiCtxBuilder::debug_label) of the context, and
+    /// carry a `stream` field naming the stream. A line is emitted once its
+    /// newline is written; a trailing line without a newline is emitted when
+    /// the guest drops the stream. Invalid UTF-8 is replaced.
+    ///
+    /// This replaces any stdout configured before, and is replaced by any
+    /// stdout configured after.
+    pub fn stdout_to_tracing(&mut self, level: tracing::Level) -> &mut Self {
+        self.stdout_tracing = Some(level);
+        self
+    }
+
+    /// Same as [`stdout_to_tracing`](WasiCtxBuilder::stdout_to_tracing), but
+    /// for stderr.
+    pub fn stderr_to_tracing(&mut self, level: tracing::Level) -> &mut Self {
+        self.stderr_tracing = Some(level);
         self
     }
 
@@ -113,6 +288,141 @@ impl WasiCtxBuilder {
         self.inherit_stdin().inherit_stdout().inherit_stderr()
     }
 
+    /// Connect the stdout of `producer` to the stdin of `consumer` through a
+    /// pipe, as in a Unix shell pipeline.
+    ///
+    /// The pipe holds up to 64 KiB, beyond which writes of the producer wait
+    /// for the consumer to read. The consumer reads the end of its input
+    /// once the producer's context and the streams opened on its stdout
+    /// are dropped, and the producer's writes fail once the consumer's are.
+    pub fn pipe(producer: &mut WasiCtxBuilder, consumer: &mut WasiCtxBuilder) {
+        let (writer, reader) = async_pipe::pipe(PIPE_CAPACITY);
+        producer.stdout(writer);
+        consumer.stdin(reader);
+    }
+
+    /// Use a stdout which writes everything into both `primary` and
+    /// `secondary`, through a [`TeeOutputStream`] which buffers writes for
+    /// whichever of the two is slower.
+    pub fn tee_stdout(
+        &mut self,
+        primary: impl HostOutputStream,
+        secondary: impl HostOutputStream,
+        isatty: IsATTY,
+    ) -> &mut Self {
+        let stream = TeeOutputStream::new(STDIO_BUFFER_SIZE, primary, secondary);
+        self.stdout(TeeStdout::new(stream, isatty))
+    }
+
+    /// Like [`tee_stdout`](WasiCtxBuilder::tee_stdout), but for stderr.
+    pub fn tee_stderr(
+        &mut self,
+        primary: impl HostOutputStream,
+        secondary: impl HostOutputStream,
+        isatty: IsATTY,
+    ) -> &mut Self {
+        let stream = TeeOutputStream::new(STDIO_BUFFER_SIZE, primary, secondary);
+        self.stderr(TeeStdout::new(stream, isatty))
+    }
+
+    /// Use `stdin` as stdin, writing every chunk the guest reads from it
+    /// into `log`.
+    pub fn tee_stdin(
+        &mut self,
+        stdin: impl StdinStream + 'static,
+        log: impl std::io::Write + Send + 'static,
+    ) -> &mut Self {
+        self.stdin(TeeStdin::new(Box::new(stdin), Arc::new(Mutex::new(log))))
+    }
+
+    /// Inherit the stdout of the host process, and write a copy of all
+    /// output into `writer` as well.
+    ///
+    /// `writer` is written to synchronously, so it should be fast, such as an
+    /// in-memory log buffer.
+    pub fn inherit_stdout_with_tee(
+        &mut self,
+        writer: impl std::io::Write + Send + 'static,
+    ) -> &mut Self {
+        let writer: SharedWriter = Arc::new(Mutex::new(writer));
+        let (stdout, isatty) = tee_host_output(&stdio::stdout(), writer);
+        self.stdout(TeeStdout::new(stdout, isatty))
+    }
+
+    /// Inherit the stdio of the host process, and copy everything the guest
+    /// reads from stdin or writes to stdout and stderr into `writer` as well.
+    ///
+    /// All three streams share `writer`, so their contents are interleaved
+    /// in the order in which they are read or written.
+    pub fn inherit_stdio_with_tee(
+        &mut self,
+        writer: impl std::io::Write + Send + 'static,
+    ) -> &mut Self {
+        let writer: SharedWriter = Arc::new(Mutex::new(writer));
+        let (stdout, stdout_isatty) = tee_host_output(&stdio::stdout(), writer.clone());
+        let (stderr, stderr_isatty) = tee_host_output(&stdio::stderr(), writer.clone());
+        self.stdin(TeeStdin::new(Box::new(stdio::stdin()), writer))
+            .stdout(TeeStdout::new(stdout, stdout_isatty))
+            .stderr(TeeStdout::new(stderr, stderr_isatty))
+    }
+
+    /// Limit the rate at which the guest may read from stdin and write to
+    /// stdout and stderr.
+    ///
+    /// The limits apply to whichever stdio streams are configured when the
+    /// context is built. Writes beyond the limit are not rejected: the
+    /// output streams permit fewer bytes to be written at a time, and
+    /// become ready again as the budget refills, so that blocking writes
+    /// proceed at the configured rate.
+    pub fn rate_limit_io(&mut self, config: IoRateLimitConfig) -> &mut Self {
+        self.io_rate_limit = config;
+        self
+    }
+
+    /// Buffer writes to stdout until a newline is written or `capacity`
+    /// bytes are buffered, whichever comes first, like a line-buffered
+    /// terminal.
+    ///
+    /// This greatly reduces the number of writes to the configured stdout
+    /// for guests writing one byte at a time, as is common for interpreters.
+    /// Flushes by the guest pass buffered bytes on immediately. Buffering
+    /// applies to whichever stdout is configured when the context is built,
+    /// after any rate limit of [`rate_limit_io`](WasiCtxBuilder::rate_limit_io).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn line_buffered_stdout(&mut self, capacity: usize) -> &mut Self {
+        assert!(capacity > 0, "stdout buffer capacity must not be zero");
+        self.stdout_buffering = Some((BufferMode::Line, capacity));
+        self
+    }
+
+    /// Like [`line_buffered_stdout`](WasiCtxBuilder::line_buffered_stdout),
+    /// but buffers writes until `capacity` bytes are buffered regardless of
+    /// newlines.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn fully_buffered_stdout(&mut self, capacity: usize) -> &mut Self {
+        assert!(capacity > 0, "stdout buffer capacity must not be zero");
+        self.stdout_buffering = Some((BufferMode::Full, capacity));
+        self
+    }
+
+    /// Treat the guest's output to stdout as text in `encoding`, converting
+    /// it to UTF-8 before it is written to the configured stdout, for guests
+    /// running legacy code which does not produce UTF-8.
+    ///
+    /// The conversion applies to whichever stdout is configured when the
+    /// context is built. [`WasiCtx::stdout_bytes_written`] counts the bytes
+    /// written by the guest, before conversion.
+    pub fn stdout_encoding(&mut self, encoding: TextEncoding) -> &mut Self {
+        self.stdout_encoding = encoding;
+        self
+    }
+
     pub fn envs(&mut self, env: &[(impl AsRef<str>, impl AsRef<str>)]) -> &mut Self {
         self.env.extend(
             env.iter()
@@ -127,6 +437,59 @@ impl WasiCtxBuilder {
         self
     }
 
+    /// Add the environment variable `key` holding a secret, such as an API
+    /// key, whose value is requested from `provider` every time the guest
+    /// reads its environment.
+    ///
+    /// Unlike variables added with [`WasiCtxBuilder::env`], the value is not
+    /// stored in the context, so it is not part of
+    /// [`WasiCtx::export_state`] either. Secrets follow the other variables
+    /// in the environment, in the order they were added. The copies of the
+    /// value made by the preview 1 adapter are zeroed once they were written
+    /// to the guest's memory; the preview 2 `get-environment` function hands
+    /// its result to the component bindings, which cannot zero it.
+    ///
+    /// ```
+    /// use wasmtime_wasi::preview2::{EnvSecretProvider, WasiCtxBuilder};
+    ///
+    /// let wasi = WasiCtxBuilder::new()
+    ///     .env("USER", "app")
+    ///     .env_secret("DB_PASSWORD", EnvSecretProvider::new("hunter2"))
+    ///     .build();
+    /// ```
+    pub fn env_secret(
+        &mut self,
+        key: impl AsRef<str>,
+        provider: impl SecretProvider + 'static,
+    ) -> &mut Self {
+        self.env_secrets
+            .push((key.as_ref().to_owned(), Arc::new(provider)));
+        self
+    }
+
+    /// Add the environment variables defined in the `.env` file at `path`.
+    ///
+    /// Each non-empty line which isn't a `#` comment must have the form
+    /// `KEY=VALUE`, optionally preceded by `export`. Values may be wrapped in
+    /// single or double quotes to preserve surrounding whitespace or a `#`;
+    /// double-quoted values also support the escapes `\n`, `\"` and `\\`.
+    /// Variables which are already set, for example with
+    /// [`WasiCtxBuilder::env`], are not overwritten.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or contains a line which
+    /// is not a valid definition.
+    pub fn env_from_dotenv(&mut self, path: impl AsRef<Path>) -> std::io::Result<&mut Self> {
+        let contents = std::fs::read_to_string(path)?;
+        for (k, v) in parse_dotenv(&contents)? {
+            if !self.env.iter().any(|(existing, _)| *existing == k) {
+                self.env.push((k, v));
+            }
+        }
+        Ok(self)
+    }
+
     pub fn args(&mut self, args: &[impl AsRef<str>]) -> &mut Self {
         self.args.extend(args.iter().map(|a| a.as_ref().to_owned()));
         self
@@ -149,6 +512,198 @@ impl WasiCtxBuilder {
         self
     }
 
+    /// Preopen `dir` at `path` in the guest with read-only directory and file
+    /// permissions.
+    pub fn preopened_readonly_dir(
+        &mut self,
+        dir: cap_std::fs::Dir,
+        path: impl AsRef<str>,
+    ) -> &mut Self {
+        self.preopened_dir(dir, DirPerms::READ, FilePerms::READ, path)
+    }
+
+    /// Preopen `dir` at `path` in the guest with all directory and file
+    /// permissions.
+    pub fn preopened_readwrite_dir(
+        &mut self,
+        dir: cap_std::fs::Dir,
+        path: impl AsRef<str>,
+    ) -> &mut Self {
+        self.preopened_dir(dir, DirPerms::all(), FilePerms::all(), path)
+    }
+
+    /// Open the directory at `host_path` with ambient authority and preopen it
+    /// at `guest_path` with the given permissions.
+    pub fn preopened_dir_at_host_path(
+        &mut self,
+        host_path: impl AsRef<Path>,
+        perms: DirPerms,
+        file_perms: FilePerms,
+        guest_path: impl AsRef<str>,
+    ) -> std::io::Result<&mut Self> {
+        let dir = cap_std::fs::Dir::open_ambient_dir(host_path.as_ref(), ambient_authority())?;
+        self.preopen_exports.push(PreopenExport::new(
+            host_path.as_ref().to_owned(),
+            guest_path.as_ref().to_owned(),
+            perms,
+            file_perms,
+        ));
+        Ok(self.preopened_dir(dir, perms, file_perms, guest_path))
+    }
+
+    /// Create a new temporary directory on the host and preopen it at
+    /// `guest_path` with all directory and file permissions.
+    ///
+    /// The directory and its contents are deleted when the returned
+    /// [`tempfile::TempDir`] is dropped, so it must be kept alive for as long
+    /// as the guest uses it. This gives each test its own writable scratch
+    /// space.
+    ///
+    /// This is only available with the `tmpdir` feature.
+    #[cfg(feature = "tmpdir")]
+    pub fn preopen_tmpdir(
+        &mut self,
+        guest_path: impl AsRef<str>,
+    ) -> std::io::Result<(tempfile::TempDir, &mut Self)> {
+        let tmpdir = tempfile::tempdir()?;
+        let this = self.preopened_dir_at_host_path(
+            tmpdir.path(),
+            DirPerms::all(),
+            FilePerms::all(),
+            guest_path,
+        )?;
+        Ok((tmpdir, this))
+    }
+
+    /// Limit the storage the guest may use in the directory preopened at
+    /// `path`, including its subdirectories, to `quota`.
+    ///
+    /// Writes, truncations and file creations which would exceed the quota
+    /// fail with `error-code::quota`, the `EDQUOT` of WASI preview 1. The
+    /// usage counted so far can be read with [`WasiCtx::dir_quota_usage`].
+    /// Setting a quota again for the same path replaces it.
+    ///
+    /// # Panics
+    ///
+    /// [`WasiCtxBuilder::build`] panics if no directory is preopened at
+    /// `path` by then.
+    pub fn preopen_dir_quota(&mut self, path: impl AsRef<str>, quota: DirQuota) -> &mut Self {
+        let path = path.as_ref();
+        self.dir_quotas.retain(|(p, _)| p != path);
+        self.dir_quotas.push((path.to_owned(), quota));
+        self
+    }
+
+    /// Preopen all directories preopened in `other`, at the same guest paths
+    /// and with the same permissions.
+    ///
+    /// The directory handles are duplicated, so the new context is
+    /// independent of `other` but grants access to the same directories.
+    /// This allows a child component to share the filesystem of its parent.
+    /// The capability policy of `other` is not inherited.
+    pub fn inherit_preopens_from(&mut self, other: &WasiCtx) -> std::io::Result<&mut Self> {
+        for (dir, path) in other.preopens.iter() {
+            self.preopens.push((dir.try_clone()?, path.clone()));
+        }
+        self.preopen_exports
+            .extend(other.preopen_exports.iter().cloned());
+        Ok(self)
+    }
+
+    /// Preopen a directory populated with the entries of `root` at `/` in the
+    /// guest, with all directory and file permissions.
+    ///
+    /// See [`FakeFilesystem`] for how the filesystem is stored. Its current
+    /// state can be read back with [`WasiCtx::fake_filesystem`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the filesystem cannot be written to the host, or if a fake
+    /// filesystem was already configured for this context.
+    pub fn fake_filesystem(&mut self, root: FakeFilesystem) -> std::io::Result<&mut Self> {
+        if self.fake_root.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "a fake filesystem was already configured",
+            ));
+        }
+        let fake_root = root.materialize()?;
+        let dir = fake_root.dir()?;
+        self.fake_root = Some(fake_root);
+        Ok(self.preopened_readwrite_dir(dir, "/"))
+    }
+
+    /// Preopen the union of the host directory `base` and the entries of
+    /// `overlay` at `guest_path`, with all directory and file permissions.
+    ///
+    /// The guest sees the entries of `overlay` where both have an entry at
+    /// the same path, and reads of all other paths fall through to `base`
+    /// as it is at the time of the read. Its writes only go to the overlay,
+    /// which is stored in a temporary directory on the host like a
+    /// [`FakeFilesystem`]: files of `base` are copied into the overlay when
+    /// the guest opens them for writing, so `base` is never modified. The
+    /// guest cannot remove or rename entries of `base`, which fails with
+    /// `read-only`. The changes can be read back with
+    /// [`WasiCtx::overlay_dir`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the overlay cannot be written to the host.
+    pub fn preopen_overlay_dir(
+        &mut self,
+        base: cap_std::fs::Dir,
+        overlay: FakeFilesystem,
+        guest_path: impl AsRef<str>,
+    ) -> std::io::Result<&mut Self> {
+        let overlay_dir = overlay.materialize_over(base)?;
+        let dir = overlay_dir.preopen(DirPerms::all(), FilePerms::all())?;
+        let guest_path = guest_path.as_ref().to_owned();
+        self.overlay_dirs.push((guest_path.clone(), overlay_dir));
+        self.preopens.push((dir, guest_path));
+        Ok(self)
+    }
+
+    /// Preopen the union of the host directories `dirs` at `guest_path`, for
+    /// components which expect a single directory whose contents actually
+    /// come from several places.
+    ///
+    /// Entries present in several directories resolve in order, so the
+    /// first directory which has an entry of a given name wins, except that
+    /// subdirectories present in several directories are merged in the same
+    /// way. The union is granted the permissions common to all of `dirs`.
+    ///
+    /// The union is stored in a temporary directory on the host, into which
+    /// the directories and regular files of `dirs` are copied when this
+    /// method is called. Writes by the guest therefore never reach `dirs`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `dirs` is empty, if one of them cannot be read, or if the
+    /// union cannot be written to the host.
+    pub fn preopen_union(
+        &mut self,
+        dirs: Vec<(cap_std::fs::Dir, DirPerms, FilePerms)>,
+        guest_path: impl AsRef<str>,
+    ) -> std::io::Result<&mut Self> {
+        if dirs.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a union of directories needs at least one directory",
+            ));
+        }
+        let (mut perms, mut file_perms) = (DirPerms::all(), FilePerms::all());
+        let mut host_dirs = Vec::with_capacity(dirs.len());
+        for (dir, dir_perms, dir_file_perms) in dirs {
+            perms &= dir_perms;
+            file_perms &= dir_file_perms;
+            host_dirs.push(dir);
+        }
+        let union_dir = UnionDir::materialize(&host_dirs)?;
+        let dir = union_dir.dir()?;
+        self.union_dirs.push(union_dir);
+        Ok(self.preopened_dir(dir, perms, file_perms, guest_path))
+    }
+
     /// Set the generator for the secure random number generator to the custom
     /// generator specified.
     ///
@@ -160,17 +715,52 @@ impl WasiCtxBuilder {
     /// and ideally should use the insecure random API otherwise, so using any
     /// prerecorded or otherwise predictable data may compromise security.
     pub fn secure_random(&mut self, random: impl RngCore + Send + Sync + 'static) -> &mut Self {
+        self.random_replay = replay_divergence(&random);
         self.random = Box::new(random);
         self
     }
 
+    /// Set the secure random number generator to a cryptographically secure
+    /// generator seeded with `seed`.
+    ///
+    /// Contexts built from the same seed produce identical sequences from
+    /// the `wasi:random/random` interface, which is useful when replaying a
+    /// recorded execution. The same security caveats as for
+    /// [`secure_random`](WasiCtxBuilder::secure_random) apply: the seed must
+    /// be kept secret for the output to remain unpredictable.
+    pub fn secure_random_from_seed(&mut self, seed: [u8; 32]) -> &mut Self {
+        self.secure_random(cap_rand::rngs::StdRng::from_seed(seed))
+    }
+
+    /// Set the secure random number generator to a [`ReplayRng`] serving the
+    /// bytes of `recorded`, as captured by a [`RecordingRng`] installed with
+    /// [`secure_random`](WasiCtxBuilder::secure_random) during an earlier
+    /// run.
+    ///
+    /// As long as the guest makes the same requests for random data, it
+    /// observes exactly the values of the recorded run. Requesting more than
+    /// was recorded fails the host call which made the request, trapping the
+    /// guest. The same security caveats as for
+    /// [`secure_random`](WasiCtxBuilder::secure_random) apply.
+    pub fn with_prng_replay(&mut self, recorded: RecordedRng) -> &mut Self {
+        self.secure_random(ReplayRng::new(recorded))
+    }
+
     pub fn insecure_random(
         &mut self,
         insecure_random: impl RngCore + Send + Sync + 'static,
     ) -> &mut Self {
+        self.insecure_random_replay = replay_divergence(&insecure_random);
         self.insecure_random = Box::new(insecure_random);
         self
     }
+
+    /// Set the insecure random number generator to the default fast generator
+    /// seeded with `seed`, making `wasi:random/insecure` deterministic.
+    pub fn insecure_random_from_seed(&mut self, seed: u64) -> &mut Self {
+        self.insecure_random(cap_rand::rngs::SmallRng::seed_from_u64(seed))
+    }
+
     pub fn insecure_random_seed(&mut self, insecure_random_seed: u128) -> &mut Self {
         self.insecure_random_seed = insecure_random_seed;
         self
@@ -178,6 +768,7 @@ impl WasiCtxBuilder {
 
     pub fn wall_clock(&mut self, clock: impl clocks::HostWallClock + 'static) -> &mut Self {
         self.wall_clock = Box::new(clock);
+        self.unavailable_wall_clock = None;
         self
     }
 
@@ -189,31 +780,183 @@ impl WasiCtxBuilder {
         self
     }
 
+    /// Back the wall clock with the given clock of the host, for example to
+    /// give components International Atomic Time without leap second
+    /// discontinuities.
+    ///
+    /// The wall clock reports the time since the Unix epoch, or since the
+    /// unspecified starting point of the clock for
+    /// [`ClockSource::Monotonic`] and [`ClockSource::ProcessCpu`].
+    ///
+    /// If `source` is not available on this platform, which can be checked
+    /// with [`ClockSource::is_available`], [`try_build`](Self::try_build)
+    /// fails.
+    pub fn wall_clock_source(&mut self, source: ClockSource) -> &mut Self {
+        self.wall_clock(source);
+        if !source.is_available() {
+            self.unavailable_wall_clock = Some(source);
+        }
+        self
+    }
+
+    /// Apply `behavior` to all reads of the wall clock which happen at or
+    /// after `deadline`.
+    ///
+    /// The deadline is compared against the configured wall clock, so it also
+    /// applies to custom clocks set with [`WasiCtxBuilder::wall_clock`].
+    pub fn deadline_wall_clock(
+        &mut self,
+        deadline: SystemTime,
+        behavior: DeadlineBehavior,
+    ) -> &mut Self {
+        self.wall_clock_deadline = Some(Deadline {
+            at: deadline
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+            behavior,
+        });
+        self
+    }
+
+    /// Apply `behavior` to all reads of the monotonic clock which happen once
+    /// the clock has reached `deadline`.
+    ///
+    /// The deadline is a value of the configured monotonic clock. For the
+    /// default clock, that is the time elapsed since the builder was created.
+    pub fn deadline_monotonic_clock(
+        &mut self,
+        deadline: Duration,
+        behavior: DeadlineBehavior,
+    ) -> &mut Self {
+        self.monotonic_clock_deadline = Some(Deadline {
+            at: deadline.as_nanos().try_into().unwrap_or(u64::MAX),
+            behavior,
+        });
+        self
+    }
+
     /// Add all network addresses accessable to the host to the pool.
     pub fn inherit_network(&mut self, ambient_authority: AmbientAuthority) -> &mut Self {
-        self.pool.insert_ip_net_port_any(
+        for ip_net in [
             IpNet::new(Ipv4Addr::UNSPECIFIED.into(), 0).unwrap(),
-            ambient_authority,
-        );
-        self.pool.insert_ip_net_port_any(
             IpNet::new(Ipv6Addr::UNSPECIFIED.into(), 0).unwrap(),
-            ambient_authority,
-        );
+        ] {
+            self.pool.insert_ip_net_port_any(ip_net, ambient_authority);
+            self.pool_capture.ip_net_port_any(ip_net);
+        }
         self
     }
 
-    /// Add network addresses to the pool.
-    pub fn insert_addr<A: cap_std::net::ToSocketAddrs>(
+    /// Add the addresses of the host's network interfaces, as well as the
+    /// unspecified IPv4 and IPv6 addresses, to the pool, keeping only those
+    /// for which `filter` returns `true`.
+    ///
+    /// `filter` is called once for each address, with port 0, and the
+    /// addresses it accepts are granted on all ports. Remote hosts are not
+    /// granted, so connections to them have to be allowed separately, for
+    /// example with [`insert_ip_net_port_any`](Self::insert_ip_net_port_any).
+    /// On platforms other than Unix, where the interfaces cannot be
+    /// enumerated, the loopback addresses stand in for them.
+    ///
+    /// ```
+    /// use cap_std::ambient_authority;
+    /// use wasmtime_wasi::preview2::WasiCtxBuilder;
+    ///
+    /// # fn main() -> std::io::Result<()> {
+    /// // Only allow loopback traffic.
+    /// let wasi = WasiCtxBuilder::new()
+    ///     .inherit_network_filtered(ambient_authority(), |addr| addr.ip().is_loopback())?
+    ///     .build();
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if the network interfaces of the host cannot be enumerated.
+    pub fn inherit_network_filtered(
         &mut self,
-        addrs: A,
+        ambient_authority: AmbientAuthority,
+        filter: impl Fn(&SocketAddr) -> bool + 'static,
     ) -> std::io::Result<&mut Self> {
-        self.pool.insert(addrs, ambient_authority())?;
+        for addr in network::host_addresses()? {
+            if filter(&SocketAddr::new(addr, 0)) {
+                let ip_net = IpNet::from(addr);
+                self.pool.insert_ip_net_port_any(ip_net, ambient_authority);
+                self.pool_capture.ip_net_port_any(ip_net);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Make outgoing TCP connections through `factory` instead of the OS.
+    ///
+    /// The network pool still decides which addresses the guest may connect
+    /// to. See [`TcpSocketFactory`] for details.
+    pub fn with_socket_factory(&mut self, factory: impl TcpSocketFactory + 'static) -> &mut Self {
+        self.socket_factory = Some(Arc::new(factory));
+        self
+    }
+
+    /// Carry TCP connections to loopback addresses over Unix domain sockets
+    /// in `socket_dir`, so that components in the same process can talk to
+    /// each other without a TCP port.
+    ///
+    /// A guest binding a TCP socket to an address in `127.0.0.0/8` with a
+    /// non-zero port instead binds a Unix domain socket named after that
+    /// address, such as `127.0.0.1-8080.sock`, which is removed when the
+    /// socket is closed. A guest connecting to such an address connects to
+    /// that Unix domain socket if it exists, and through TCP otherwise. The
+    /// network pool still decides which addresses the guest may use, so the
+    /// loopback addresses have to be granted as well.
+    ///
+    /// The OS does not know the TCP addresses of redirected sockets: the
+    /// local address of a connecting socket and the remote address of an
+    /// accepted one are reported with port 0, and socket options which only
+    /// apply to TCP fail.
+    #[cfg(unix)]
+    pub fn allow_unix_sockets(&mut self, socket_dir: impl AsRef<Path>) -> &mut Self {
+        self.unix_socket_dir = Some(socket_dir.as_ref().to_owned());
+        self
+    }
+
+    /// Log every network access of the guest to `writer`, for deployments
+    /// which must keep a record of the connections a component makes.
+    ///
+    /// Each TCP connect, UDP connect and send, and name lookup is written as
+    /// a line of JSON such as
+    ///
+    /// ```text
+    /// {"ts":"2023-11-20T08:15:00.123456Z","event":"tcp_connect","addr":"10.0.0.1:5432","allowed":true}
+    /// ```
+    ///
+    /// where `ts` is the UTC wall-clock time and `event` is one of
+    /// `tcp_connect`, `udp_connect`, `udp_send` and `dns_resolve`. The `addr`
+    /// of a name lookup is the name being resolved. Accesses denied by the
+    /// network pool, the capability policy or a network filter are logged
+    /// with `"allowed":false` before the error is returned to the guest. If
+    /// the context has a [debug label](WasiCtxBuilder::debug_label), it is
+    /// written as a `label` field.
+    ///
+    /// Errors writing to `writer` are ignored. Forks of the context write to
+    /// the same log.
+    pub fn network_audit_log(&mut self, writer: impl std::io::Write + Send + 'static) -> &mut Self {
+        self.network_audit_log = Some(NetworkAuditLog::new(Box::new(writer)));
+        self
+    }
+
+    /// Add network addresses to the pool.
+    pub fn insert_addr<A: ToSocketAddrs>(&mut self, addrs: A) -> std::io::Result<&mut Self> {
+        for addr in addrs.to_socket_addrs()? {
+            self.insert_socket_addr(addr);
+        }
         Ok(self)
     }
 
     /// Add a specific [`cap_std::net::SocketAddr`] to the pool.
     pub fn insert_socket_addr(&mut self, addr: cap_std::net::SocketAddr) -> &mut Self {
         self.pool.insert_socket_addr(addr, ambient_authority());
+        self.pool_capture.socket_addr(addr);
         self
     }
 
@@ -223,6 +966,7 @@ impl WasiCtxBuilder {
     pub fn insert_ip_net_port_any(&mut self, ip_net: ipnet::IpNet) -> &mut Self {
         self.pool
             .insert_ip_net_port_any(ip_net, ambient_authority());
+        self.pool_capture.ip_net_port_any(ip_net);
         self
     }
 
@@ -239,87 +983,1901 @@ impl WasiCtxBuilder {
     ) -> &mut Self {
         self.pool
             .insert_ip_net_port_range(ip_net, ports_start, ports_end, ambient_authority());
+        self.pool_capture
+            .ip_net_port_range(ip_net, ports_start, ports_end);
         self
     }
 
     /// Add a range of network addresses with a specific port to the pool.
     pub fn insert_ip_net(&mut self, ip_net: ipnet::IpNet, port: u16) -> &mut Self {
         self.pool.insert_ip_net(ip_net, port, ambient_authority());
+        self.pool_capture.ip_net(ip_net, port);
         self
     }
 
-    /// Allow usage of `wasi:sockets/ip-name-lookup`
-    pub fn allow_ip_name_lookup(&mut self, enable: bool) -> &mut Self {
-        self.allow_ip_name_lookup = enable;
+    /// Register the issuer whose tokens are accepted by
+    /// [`insert_ip_net_with_token`](WasiCtxBuilder::insert_ip_net_with_token).
+    pub fn capability_issuer(&mut self, issuer: CapabilityIssuer) -> &mut Self {
+        self.capability_issuer = Some(issuer);
         self
     }
 
-    /// Uses the configured context so far to construct the final `WasiCtx`.
+    /// Add a range of network addresses, accepting a range of ports, to the
+    /// pool, after verifying that `token` was issued by the
+    /// [`CapabilityIssuer`] registered with
+    /// [`capability_issuer`](WasiCtxBuilder::capability_issuer).
     ///
-    /// Note that each `WasiCtxBuilder` can only be used to "build" once, and
-    /// calling this method twice will panic.
+    /// Ports are granted as for
+    /// [`insert_ip_net_port_range`](WasiCtxBuilder::insert_ip_net_port_range).
+    /// This lets code which configures contexts on behalf of others grant
+    /// network access only with the authorization of a trusted party.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if this method is called twice.
-    pub fn build(&mut self) -> WasiCtx {
-        assert!(!self.built);
+    /// Fails with [`std::io::ErrorKind::PermissionDenied`] if no issuer is
+    /// registered or the token was not issued by it or has been revoked, in
+    /// which case nothing is added to the pool.
+    pub fn insert_ip_net_with_token(
+        &mut self,
+        ip_net: ipnet::IpNet,
+        ports_start: u16,
+        ports_end: Option<u16>,
+        token: &CapabilityToken,
+    ) -> std::io::Result<&mut Self> {
+        let verified = match &self.capability_issuer {
+            Some(issuer) => issuer.verify(token),
+            None => false,
+        };
+        if !verified {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "capability token was not issued by the registered issuer",
+            ));
+        }
+        Ok(self.insert_ip_net_port_range(ip_net, ports_start, ports_end))
+    }
 
-        let Self {
-            stdin,
-            stdout,
-            stderr,
-            env,
-            args,
-            preopens,
-            pool,
-            random,
-            insecure_random,
-            insecure_random_seed,
-            wall_clock,
-            monotonic_clock,
-            allow_ip_name_lookup,
-            built: _,
-        } = mem::replace(self, Self::new());
-        self.built = true;
+    /// Add all network addresses recorded in `capture` to the pool.
+    ///
+    /// This restores the network permissions of a context previously captured
+    /// with [`WasiCtx::capture_pool`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if `capture` contains an invalid address range, which can only
+    /// happen if it was deserialized from corrupt data.
+    pub fn pool_from_capture(
+        &mut self,
+        capture: &NetworkPoolCapture,
+    ) -> std::io::Result<&mut Self> {
+        capture.apply(&mut self.pool)?;
+        self.pool_capture.grants_from(capture);
+        Ok(self)
+    }
 
-        WasiCtx {
-            stdin,
-            stdout,
-            stderr,
-            env,
-            args,
-            preopens,
-            pool,
-            random,
-            insecure_random,
-            insecure_random_seed,
-            wall_clock,
-            monotonic_clock,
-            allow_ip_name_lookup,
+    /// Replay the portable WASI state of a context exported with
+    /// [`WasiCtx::export_state`], possibly in another process.
+    ///
+    /// The environment and arguments are appended to those configured so
+    /// far, the preopens are opened again at the same host paths, the network
+    /// addresses are added to the pool, and the clocks are replaced with host
+    /// clocks continuing from the exported readings. See [`WasiStateExport`]
+    /// for what is part of the state.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `state` was exported with an incompatible format version, if
+    /// a preopened directory cannot be opened, or if the network pool is
+    /// invalid.
+    pub fn import_wasi_state(&mut self, state: WasiStateExport) -> std::io::Result<&mut Self> {
+        if state.version != WasiStateExport::VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported WASI state version {} (expected {})",
+                    state.version,
+                    WasiStateExport::VERSION
+                ),
+            ));
         }
+        self.env.extend(state.env);
+        self.args.extend(state.args);
+        for preopen in state.preopens.iter() {
+            self.preopened_dir_at_host_path(
+                &preopen.host_path,
+                preopen.dir_perms(),
+                preopen.file_perms(),
+                &preopen.guest_path,
+            )?;
+        }
+        self.pool_from_capture(&state.network)?;
+        self.allow_ip_name_lookup = state.allow_ip_name_lookup;
+        self.wall_clock(clocks::host::OffsetWallClock::new(state.wall_clock_offset));
+        self.monotonic_clock(clocks::host::OffsetMonotonicClock::new(
+            state.monotonic_clock,
+        ));
+        Ok(self)
     }
-}
 
-pub trait WasiView: Send {
+    /// Allow usage of `wasi:sockets/ip-name-lookup`
+    pub fn allow_ip_name_lookup(&mut self, enable: bool) -> &mut Self {
+        self.allow_ip_name_lookup = enable;
+        self
+    }
+
+    /// Restrict access to filesystem paths and network addresses with the
+    /// rules of `policy`, on top of the preopens and network addresses
+    /// granted by the other builder methods.
+    ///
+    /// The policy is evaluated every time the guest accesses a path or
+    /// address. See [`CapabilityPolicy`] for details.
+    pub fn capability_policy(&mut self, policy: CapabilityPolicy) -> &mut Self {
+        self.capability_policy = policy;
+        self
+    }
+
+    /// Confine the paths the guest opens beneath its directories according
+    /// to `policy`. See [`ScopePolicy`] for details.
+    pub fn preopen_scope_policy(&mut self, policy: ScopePolicy) -> &mut Self {
+        self.preopen_scope = policy;
+        self
+    }
+
+    /// Limit the total size of the linear memories of the component to
+    /// `limit` bytes.
+    ///
+    /// Memories are created and grown by the engine rather than by WASI, so
+    /// setting the limit has no effect on its own. It is enforced in one of
+    /// two ways:
+    ///
+    /// * For the memories of a single store, by
+    ///   [`WasiCtx::resource_limiter`] once the store is told to consult it
+    ///   with [`Store::limiter_async`](wasmtime::Store::limiter_async), as
+    ///   for [`WasiCtxBuilder::with_resource_limiter`]. Contexts created with
+    ///   [`WasiCtx::fork`] enforce the same limit on their own stores.
+    /// * For all memories of an engine, by the [`BoundedMemoryCreator`]
+    ///   returned by [`WasiCtx::memory_creator`], once it is installed in the
+    ///   engine's [`Config`](wasmtime::Config) as described there. This also
+    ///   works for stores without async support.
+    pub fn memory_limit_bytes(&mut self, limit: usize) -> &mut Self {
+        self.memory_creator = Some(Arc::new(BoundedMemoryCreator::new(limit)));
+        self
+    }
+
+    /// Limit the growth of the component's memories and tables with
+    /// `limiter`, which is owned by the [`WasiCtx`] instead of having to be
+    /// implemented by the store's data.
+    ///
+    /// Growth is performed by the engine rather than by WASI, so the limiter
+    /// only takes effect once the store is told to consult it:
+    ///
+    /// ```ignore
+    /// store.limiter_async(|data| data.ctx_mut().resource_limiter());
+    /// ```
+    ///
+    /// The limiter is not carried over to contexts created with
+    /// [`WasiCtx::fork`], which allow all growth up to the limit set with
+    /// [`WasiCtxBuilder::memory_limit_bytes`].
+    pub fn with_resource_limiter(
+        &mut self,
+        limiter: impl ResourceLimiterAsync + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.resource_limiter = Some(Box::new(limiter));
+        self
+    }
+
+    /// Support the [wasi-threads] proposal by starting threads with
+    /// `spawner`.
+    ///
+    /// The `thread-spawn` function must also be added to the core module's
+    /// linker with `add_to_linker_with_spawner` of the
+    /// `wasmtime-wasi-threads` crate. Without a spawner, requests to spawn a
+    /// thread fail.
+    ///
+    /// [wasi-threads]: https://github.com/WebAssembly/wasi-threads
+    pub fn with_thread_spawner(&mut self, spawner: impl WasiThreadSpawner + 'static) -> &mut Self {
+        self.thread_spawner = Some(Arc::new(spawner));
+        self
+    }
+
+    /// React to the `SIGINT` and `SIGTERM` signals received by the host
+    /// process as decided by `handler`, for example to let the component be
+    /// snapshotted before the host exits.
+    ///
+    /// Signals for which the handler does not return
+    /// [`SignalAction::Ignore`] are queued, to be taken with
+    /// [`WasiCtx::take_signal`]. The running component is interrupted at its
+    /// next yield point by incrementing the epoch of the engines passed to
+    /// [`WasiCtx::interrupt_on_signal`], so signals only take effect once the
+    /// store checks the queue when its epoch deadline is reached:
+    ///
+    /// ```ignore
+    /// store.data().ctx().interrupt_on_signal(store.engine());
+    /// store.set_epoch_deadline(1);
+    /// store.epoch_deadline_callback(|store| match store.data().ctx().take_signal() {
+    ///     Some((_, SignalAction::Terminate)) => bail!("terminated by a signal"),
+    ///     Some((_, SignalAction::Snapshot)) => {
+    ///         // Capture the instance with `Instance::snapshot` here.
+    ///         Ok(UpdateDeadline::Continue(1))
+    ///     }
+    ///     _ => Ok(UpdateDeadline::Continue(1)),
+    /// });
+    /// ```
+    ///
+    /// See [`PosixSignalHandler`] for how installing a handler affects the
+    /// host process.
+    pub fn with_signal_handler(
+        &mut self,
+        handler: impl PosixSignalHandler + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.signal_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Sample the running component with `profiler` every
+    /// `sample_interval_epochs` increments of the engine's epoch.
+    ///
+    /// Samples are only taken once the store calls
+    /// [`WasiCtx::sample_profiler`] from its epoch deadline callback, as
+    /// described there. [`SamplingProfiler`](crate::preview2::SamplingProfiler)
+    /// is a profiler which keeps all samples for later analysis.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sample_interval_epochs` is zero.
+    pub fn with_profiler(
+        &mut self,
+        profiler: impl ComponentProfiler + Send + Sync + 'static,
+        sample_interval_epochs: u64,
+    ) -> &mut Self {
+        assert!(
+            sample_interval_epochs > 0,
+            "profiler sample interval must not be zero"
+        );
+        self.profiler = Some((Box::new(profiler), sample_interval_epochs));
+        self
+    }
+
+    /// Let `scheduler` decide when the running component yields to the
+    /// async executor, so that it does not starve other components running
+    /// in the same runtime.
+    ///
+    /// The scheduler is only consulted once the store calls
+    /// [`WasiCtx::schedule`] from its epoch deadline callback, as described
+    /// there. [`RoundRobinScheduler`](crate::preview2::RoundRobinScheduler)
+    /// and [`LatencyBoundScheduler`](crate::preview2::LatencyBoundScheduler)
+    /// yield after a given amount of fuel or time respectively.
+    pub fn with_scheduler(
+        &mut self,
+        scheduler: impl WasmScheduler + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.scheduler = Some(Arc::new(scheduler));
+        self
+    }
+
+    /// Count invocations of WASI host functions, which can then be read
+    /// through [`WasiCtx::metrics`].
+    ///
+    /// Counting is disabled by default to avoid its overhead.
+    pub fn enable_metrics(&mut self) -> &mut Self {
+        self.enable_metrics = true;
+        self
+    }
+
+    /// Install a handler which is called with the exit code whenever the
+    /// guest calls `exit`, and which decides how the exit is handled.
+    ///
+    /// Without a handler, exits behave as with [`ExitBehavior::Propagate`].
+    pub fn with_exit_handler(
+        &mut self,
+        handler: impl Fn(i32) -> ExitBehavior + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.exit_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Install a hook which is called with the kind and table index of every
+    /// resource WASI creates on behalf of the guest, such as a stream, an
+    /// open file or a socket.
+    pub fn on_resource_create(
+        &mut self,
+        hook: impl Fn(ResourceKind, u32) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_resource_create = Some(Arc::new(hook));
+        self
+    }
+
+    /// Install a hook which is called with the kind and table index of every
+    /// resource created by WASI when the guest drops its handle to it.
+    pub fn on_resource_drop(
+        &mut self,
+        hook: impl Fn(ResourceKind, u32) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_resource_drop = Some(Arc::new(hook));
+        self
+    }
+
+    /// Install a callback which is called with every
+    /// [`ResourceUsageEvent`] of the component: files opened and closed,
+    /// bytes read from and written to streams, and growth of its linear
+    /// memories.
+    ///
+    /// Unlike [`WasiCtx::execution_stats`], which sums up these uses, the
+    /// callback sees each of them as it happens. It runs synchronously
+    /// within the host call, on the thread executing wasm, so it must
+    /// return quickly; see [`WasiCtxBuilder::resource_usage_channel`] to
+    /// handle events asynchronously instead.
+    pub fn with_resource_callback(
+        &mut self,
+        callback: impl Fn(ResourceUsageEvent) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.resource_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Install a [resource callback](WasiCtxBuilder::with_resource_callback)
+    /// which sends every [`ResourceUsageEvent`] to the returned channel, for
+    /// a consumer running on another task.
+    ///
+    /// The channel holds at most `capacity` events. Events which arrive
+    /// while it is full, or after the receiver is dropped, are discarded
+    /// rather than blocking the component.
+    pub fn resource_usage_channel(
+        &mut self,
+        capacity: usize,
+    ) -> tokio::sync::mpsc::Receiver<ResourceUsageEvent> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(capacity);
+        self.with_resource_callback(move |event| {
+            let _ = sender.try_send(event);
+        });
+        receiver
+    }
+
+    /// Write a record of WASI host calls to `writer`, one line of JSON per
+    /// call such as
+    /// `{"ts":1234,"call":"output-stream.write","args":[3,"68690a"],"ret":null}`.
+    ///
+    /// `ts` is the number of nanoseconds since the context was built, `args`
+    /// holds the resource indices and values passed by the guest, and `ret`
+    /// the value returned to it, or `{"error":"..."}` for failed calls. Byte
+    /// buffers are written as hex strings.
+    ///
+    /// Only some calls are recorded: the clocks' `now`, all of `wasi:random`,
+    /// reads, skips and writes on streams, `read`, `write` and `open-at` on
+    /// descriptors, and `exit`. Other calls, such as polling, listing
+    /// directories or using sockets, are not, so the trace shows what a
+    /// component read and wrote but is not enough on its own to replay a run.
+    pub fn trace_syscalls(&mut self, writer: impl std::io::Write + Send + 'static) -> &mut Self {
+        self.trace = Some(SyscallTrace::new(Box::new(writer)));
+        self
+    }
+
+    /// Label the context with `label`, to attribute log output and
+    /// [syscall traces](WasiCtxBuilder::trace_syscalls) to it when many
+    /// contexts run concurrently.
+    ///
+    /// The label is available to host code through
+    /// [`WasiCtx::debug_label`].
+    pub fn debug_label(&mut self, label: impl Into<String>) -> &mut Self {
+        self.debug_label = Some(label.into());
+        self
+    }
+
+    /// Tag the context with the ID of the request it handles, for servers
+    /// which create a context per request, to correlate the output of WASI
+    /// with the request.
+    ///
+    /// The ID is passed to the guest as the environment variable
+    /// `WASMTIME_REQUEST_ID`, replacing any value set with
+    /// [`WasiCtxBuilder::env`], and recorded as the `request_id` field of a
+    /// `wasi_request` span which [`WasiCtx::record_call_hook`] enters for the
+    /// duration of every host call, so that the spans and events of WASI
+    /// host functions fall within it. The spans of
+    /// [`WasiCtxBuilder::stdout_to_tracing`] carry it as well.
+    ///
+    /// The ID is available to host code through [`WasiCtx::request_id`].
+    pub fn with_request_id(&mut self, id: impl Into<String>) -> &mut Self {
+        self.request_id = Some(id.into());
+        self
+    }
+
+    /// Limit the number of entries of tables created with
+    /// [`WasiCtx::new_table`].
+    pub fn max_table_entries(&mut self, max_entries: u32) -> &mut Self {
+        self.max_table_entries = Some(max_entries);
+        self
+    }
+
+    /// Limit the number of files the guest may have open at once.
+    ///
+    /// Opening a file beyond the limit fails with
+    /// [`ErrorCode::Quota`](crate::preview2::bindings::filesystem::types::ErrorCode::Quota),
+    /// which preview1 reports as `EMFILE`.
+    pub fn max_open_files(&mut self, limit: u32) -> &mut Self {
+        self.handle_limits.files = Some(limit);
+        self
+    }
+
+    /// Limit the number of directories the guest may have open at once,
+    /// including the preopened directories it has obtained handles to.
+    ///
+    /// Opening a directory beyond the limit fails like opening a file beyond
+    /// the limit of [`max_open_files`](WasiCtxBuilder::max_open_files).
+    pub fn max_open_dirs(&mut self, limit: u32) -> &mut Self {
+        self.handle_limits.dirs = Some(limit);
+        self
+    }
+
+    /// Limit the number of TCP and UDP sockets the guest may have open at
+    /// once.
+    ///
+    /// Creating or accepting a socket beyond the limit fails with
+    /// [`ErrorCode::NewSocketLimit`](crate::preview2::bindings::sockets::network::ErrorCode::NewSocketLimit).
+    pub fn max_open_sockets(&mut self, limit: u32) -> &mut Self {
+        self.handle_limits.sockets = Some(limit);
+        self
+    }
+
+    /// Add an arbitrary host-side resource, such as an already connected
+    /// [`TcpSocket`](crate::preview2::TcpSocket), which is placed into the
+    /// table with [`WasiCtx::bind_resources`] before the guest runs.
+    ///
+    /// Returns the table index the resource will have, which is one past the
+    /// highest index of any resource added so far, and at least 3.
+    pub fn push_resource<T: Send + Sync + 'static>(&mut self, resource: T) -> (u32, &mut Self) {
+        let index = self
+            .prebound_resources
+            .iter()
+            .map(|(i, _)| i + 1)
+            .max()
+            .unwrap_or(3)
+            .max(3);
+        (index, self.with_prebound_resource(index, resource))
+    }
+
+    /// Add an arbitrary host-side resource at the table index `index`, which
+    /// is placed into the table with [`WasiCtx::bind_resources`] before the
+    /// guest runs.
+    ///
+    /// A resource previously added at the same index is replaced. Fixing the
+    /// index allows handles held by a guest to stay valid when its state is
+    /// restored into a new store.
+    pub fn with_prebound_resource<T: Send + Sync + 'static>(
+        &mut self,
+        index: u32,
+        resource: T,
+    ) -> &mut Self {
+        self.prebound_resources.retain(|(i, _)| *i != index);
+        self.prebound_resources.push((index, Box::new(resource)));
+        self
+    }
+
+    /// Reset this builder to a strict baseline for running untrusted code:
+    ///
+    /// * no network access and no name lookups
+    /// * no preopened directories
+    /// * stdin is closed, stdout and stderr discard all output
+    /// * both clocks are stopped at the Unix epoch
+    /// * all random generators are deterministic and seeded with zero
+    /// * tables created with [`WasiCtx::new_table`] hold at most 256 entries
+    ///
+    /// This is a starting point rather than a lock: every setting can be
+    /// loosened again by subsequent builder calls, for example with
+    /// [`inherit_network`](WasiCtxBuilder::inherit_network).
+    pub fn sandbox_mode(&mut self) -> &mut Self {
+        self.pool = Pool::new();
+        self.pool_capture = NetworkPoolCapture::default();
+        self.allow_ip_name_lookup = false;
+        self.preopens.clear();
+        self.preopen_exports.clear();
+        self.dir_quotas.clear();
+        self.fake_root = None;
+        self.overlay_dirs.clear();
+        self.union_dirs.clear();
+        self.stdin(pipe::ClosedInputStream)
+            .stdout(pipe::SinkOutputStream)
+            .stderr(pipe::SinkOutputStream)
+            .wall_clock(clocks::EpochClock)
+            .monotonic_clock(clocks::EpochClock)
+            .secure_random_from_seed([0; 32])
+            .insecure_random_from_seed(0)
+            .insecure_random_seed(0)
+            .max_table_entries(256)
+    }
+
+    /// Associate an arbitrary identifier with the context being built.
+    ///
+    /// This has no effect on the behavior of WASI itself, but allows hosts
+    /// which manage many stores to find out which component a `WasiCtx`
+    /// belongs to through [`WasiCtx::component_id`].
+    pub fn component_id(&mut self, id: impl Any + Send + Sync + 'static) -> &mut Self {
+        self.component_id = Some(Arc::new(id));
+        self
+    }
+
+    /// Uses the configured context so far to construct the final `WasiCtx`.
+    ///
+    /// Note that each `WasiCtxBuilder` can only be used to "build" once, and
+    /// calling this method twice will panic.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this method is called twice, or if the context cannot be
+    /// built for one of the reasons documented on
+    /// [`try_build`](Self::try_build).
+    pub fn build(&mut self) -> WasiCtx {
+        self.try_build().expect("failed to build the WASI context")
+    }
+
+    /// Like [`build`](Self::build), but returns an error when the host cannot
+    /// provide what was configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the signal handlers of the process could not be
+    /// installed for [`with_signal_handler`](Self::with_signal_handler), or
+    /// if the clock of [`wall_clock_source`](Self::wall_clock_source) is not
+    /// available on this platform.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this method, or [`build`](Self::build), is called twice.
+    pub fn try_build(&mut self) -> anyhow::Result<WasiCtx> {
+        assert!(!self.built);
+
+        let Self {
+            stdin,
+            stdout,
+            stderr,
+            io_rate_limit,
+            stdout_buffering,
+            stdout_encoding,
+            mut env,
+            env_secrets,
+            args,
+            preopens,
+            preopen_exports,
+            dir_quotas,
+            fake_root,
+            overlay_dirs,
+            union_dirs,
+            pool,
+            pool_capture,
+            socket_factory,
+            unix_socket_dir,
+            network_audit_log,
+            capability_issuer: _,
+            random,
+            random_replay,
+            insecure_random,
+            insecure_random_replay,
+            insecure_random_seed,
+            wall_clock,
+            unavailable_wall_clock,
+            monotonic_clock,
+            wall_clock_deadline,
+            monotonic_clock_deadline,
+            allow_ip_name_lookup,
+            component_id,
+            capability_policy,
+            preopen_scope,
+            memory_creator,
+            resource_limiter,
+            thread_spawner,
+            signal_handler,
+            profiler,
+            scheduler,
+            enable_metrics,
+            exit_handler,
+            on_resource_create,
+            on_resource_drop,
+            resource_callback,
+            max_table_entries,
+            handle_limits,
+            prebound_resources,
+            trace,
+            debug_label,
+            request_id,
+            stdout_tracing,
+            stderr_tracing,
+            built: _,
+        } = mem::replace(self, Self::new());
+        self.built = true;
+
+        if let Some(source) = unavailable_wall_clock {
+            anyhow::bail!("clock source {source:?} is not available on this platform");
+        }
+        let signals = signal_handler
+            .map(SignalQueue::listen)
+            .transpose()
+            .context("failed to install the signal handlers")?;
+
+        if let Some(id) = &request_id {
+            env.retain(|(key, _)| key != REQUEST_ID_ENV);
+            env.push((REQUEST_ID_ENV.to_owned(), id.clone()));
+        }
+        let stdout: Box<dyn StdoutStream> = match stdout_tracing {
+            Some(level) => Box::new(TracingStdout::new(
+                level,
+                "stdout",
+                debug_label.clone(),
+                request_id.clone(),
+            )),
+            None => stdout,
+        };
+        let stderr: Box<dyn StdoutStream> = match stderr_tracing {
+            Some(level) => Box::new(TracingStdout::new(
+                level,
+                "stderr",
+                debug_label.clone(),
+                request_id.clone(),
+            )),
+            None => stderr,
+        };
+        let (stdin, mut stdout, stderr) = rate_limit_stdio(io_rate_limit, stdin, stdout, stderr);
+        if let Some((mode, capacity)) = stdout_buffering {
+            stdout = Box::new(BufferedStdout::new(stdout, mode, capacity));
+        }
+        if !matches!(stdout_encoding, TextEncoding::Utf8) {
+            stdout = Box::new(TranscodingStdout::new(stdout, stdout_encoding));
+        }
+        let paused_io = PausedIoBuffers::default();
+        let events = EventQueue::new();
+        let stdin_bytes_read = Arc::new(AtomicU64::new(0));
+        let stdout_bytes_written = Arc::new(AtomicU64::new(0));
+        let stderr_bytes_written = Arc::new(AtomicU64::new(0));
+        let stdin_source: Arc<dyn StdinStream> = stdin.into();
+        let stdin = event_stdin(stdin_source.clone(), &events, &stdin_bytes_read);
+        let stdout_source: Arc<dyn StdoutStream> = stdout.into();
+        let stderr_source: Arc<dyn StdoutStream> = stderr.into();
+        let stdout = pausable_stdout(
+            Box::new(stdout_source.clone()),
+            &paused_io.stdout,
+            &stdout_bytes_written,
+        );
+        let stderr = pausable_stdout(
+            Box::new(stderr_source.clone()),
+            &paused_io.stderr,
+            &stderr_bytes_written,
+        );
+        let stats = ExecutionCounters::new([
+            stdin_bytes_read,
+            stdout_bytes_written.clone(),
+            stderr_bytes_written.clone(),
+        ]);
+        let capability_policy = Arc::new(capability_policy);
+        let dir_quotas: Vec<(String, Arc<QuotaTracker>)> = dir_quotas
+            .into_iter()
+            .map(|(path, quota)| {
+                assert!(
+                    preopens.iter().any(|(_, p)| *p == path),
+                    "a quota was set for `{path}`, but no directory is preopened there"
+                );
+                (path, Arc::new(QuotaTracker::new(quota)))
+            })
+            .collect();
+        let preopens = preopens
+            .into_iter()
+            .map(|(dir, path)| {
+                let mut dir = dir.with_policy(path.clone(), capability_policy.clone());
+                dir.quota = dir_quotas
+                    .iter()
+                    .find(|(p, _)| *p == path)
+                    .map(|(_, tracker)| tracker.clone());
+                (dir, path)
+            })
+            .collect();
+        let resource_limiter = MemoryLimiter::wrap(
+            resource_limiter.unwrap_or_else(|| Box::new(Unlimited)),
+            memory_creator.as_ref().map(|creator| creator.limit()),
+        );
+
+        Ok(WasiCtx {
+            events,
+            stdin_source,
+            stdin,
+            stdout_source,
+            stdout,
+            stderr_source,
+            stderr,
+            stdout_bytes_written,
+            paused_io,
+            stderr_bytes_written,
+            env,
+            env_secrets,
+            env_namespace: None,
+            args,
+            preopens,
+            preopen_exports,
+            dir_quotas,
+            fake_root: fake_root.map(Arc::new),
+            overlay_dirs: overlay_dirs
+                .into_iter()
+                .map(|(path, dir)| (path, Arc::new(dir)))
+                .collect(),
+            union_dirs: union_dirs.into_iter().map(Arc::new).collect(),
+            pool,
+            pool_capture,
+            socket_factory,
+            unix_socket_dir,
+            network_audit_log: network_audit_log
+                .map(|log| Arc::new(log.with_label(debug_label.clone()))),
+            random,
+            random_replay,
+            insecure_random,
+            insecure_random_replay,
+            insecure_random_seed,
+            wall_clock: wall_clock.into(),
+            monotonic_clock: monotonic_clock.into(),
+            monotonic_clock_offset: 0,
+            wall_clock_deadline: wall_clock_deadline.map(Arc::new),
+            monotonic_clock_deadline: monotonic_clock_deadline.map(Arc::new),
+            frozen_monotonic_clock: None,
+            allow_ip_name_lookup,
+            component_id,
+            capability_policy,
+            preopen_scope,
+            memory_creator,
+            resource_limiter: Box::new(StatsLimiter::new(
+                resource_limiter,
+                stats.clone(),
+                resource_callback.clone(),
+            )),
+            stats,
+            thread_spawner,
+            signals,
+            profiler,
+            scheduling: scheduler.map(Scheduling::new),
+            metrics: WasiMetrics::new(enable_metrics),
+            exit_handler,
+            exit_snapshot_handler: None,
+            on_resource_create,
+            on_resource_drop,
+            resource_callback,
+            max_table_entries,
+            handle_limits,
+            prebound_resources,
+            trace: trace.map(|trace| trace.with_label(debug_label.clone())),
+            debug_label,
+            request_span: request_span(request_id.as_deref()),
+            request_id,
+        })
+    }
+}
+
+pub trait WasiView: Send {
     fn table(&self) -> &Table;
     fn table_mut(&mut self) -> &mut Table;
     fn ctx(&self) -> &WasiCtx;
     fn ctx_mut(&mut self) -> &mut WasiCtx;
+
+    /// Injects `event` into the component, as described for
+    /// [`WasiCtx::emit_event`].
+    fn emit_event(&mut self, event: WasiEvent) -> anyhow::Result<()> {
+        self.ctx_mut().emit_event(event)
+    }
+}
+
+/// Limits on the number of open handles of each kind, configured with
+/// [`WasiCtxBuilder::max_open_files`] and related methods.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct HandleLimits {
+    files: Option<u32>,
+    dirs: Option<u32>,
+    sockets: Option<u32>,
+}
+
+/// A monotonic clock frozen with [`WasiCtx::freeze_monotonic_clock`], along
+/// with the clock and offset it replaced.
+#[derive(Clone)]
+pub(crate) struct FrozenMonotonicClock {
+    clock: Arc<FrozenClock>,
+    real: Arc<dyn HostMonotonicClock + Send + Sync>,
+    real_offset: u64,
 }
 
 pub struct WasiCtx {
     pub(crate) random: Box<dyn RngCore + Send + Sync>,
+    pub(crate) random_replay: Option<ReplayDivergence>,
     pub(crate) insecure_random: Box<dyn RngCore + Send + Sync>,
+    pub(crate) insecure_random_replay: Option<ReplayDivergence>,
     pub(crate) insecure_random_seed: u128,
-    pub(crate) wall_clock: Box<dyn HostWallClock + Send + Sync>,
-    pub(crate) monotonic_clock: Box<dyn HostMonotonicClock + Send + Sync>,
+    pub(crate) wall_clock: Arc<dyn HostWallClock + Send + Sync>,
+    pub(crate) monotonic_clock: Arc<dyn HostMonotonicClock + Send + Sync>,
+    pub(crate) monotonic_clock_offset: u64,
+    pub(crate) wall_clock_deadline: Option<Arc<Deadline<Duration>>>,
+    pub(crate) monotonic_clock_deadline: Option<Arc<Deadline<u64>>>,
+    pub(crate) frozen_monotonic_clock: Option<FrozenMonotonicClock>,
     pub(crate) env: Vec<(String, String)>,
+    pub(crate) env_secrets: Vec<(String, Arc<dyn SecretProvider>)>,
+    pub(crate) env_namespace: Option<String>,
     pub(crate) args: Vec<String>,
     pub(crate) preopens: Vec<(Dir, String)>,
-    pub(crate) stdin: Box<dyn StdinStream>,
-    pub(crate) stdout: Box<dyn StdoutStream>,
-    pub(crate) stderr: Box<dyn StdoutStream>,
+    pub(crate) preopen_exports: Vec<PreopenExport>,
+    pub(crate) dir_quotas: Vec<(String, Arc<QuotaTracker>)>,
+    pub(crate) fake_root: Option<Arc<FakeRoot>>,
+    pub(crate) overlay_dirs: Vec<(String, Arc<OverlayDir>)>,
+    /// Keeps the directories preopened with
+    /// [`WasiCtxBuilder::preopen_union`] alive.
+    pub(crate) union_dirs: Vec<Arc<UnionDir>>,
+    pub(crate) events: Arc<EventQueue>,
+    /// The stdin configured on the builder, which `stdin` wraps, and from
+    /// which forks take their stdin.
+    pub(crate) stdin_source: Arc<dyn StdinStream>,
+    pub(crate) stdin: Arc<dyn StdinStream>,
+    /// The stdout configured on the builder or with [`WasiCtx::set_stdout`],
+    /// which `stdout` wraps, and which forks share.
+    pub(crate) stdout_source: Arc<dyn StdoutStream>,
+    pub(crate) stdout: Arc<dyn StdoutStream>,
+    /// Like `stdout_source`, but for stderr.
+    pub(crate) stderr_source: Arc<dyn StdoutStream>,
+    pub(crate) stderr: Arc<dyn StdoutStream>,
+    pub(crate) stdout_bytes_written: Arc<AtomicU64>,
+    pub(crate) paused_io: PausedIoBuffers,
+    pub(crate) stderr_bytes_written: Arc<AtomicU64>,
     pub(crate) pool: Pool,
+    pub(crate) pool_capture: NetworkPoolCapture,
+    pub(crate) socket_factory: Option<Arc<dyn TcpSocketFactory>>,
+    pub(crate) unix_socket_dir: Option<PathBuf>,
+    pub(crate) network_audit_log: Option<Arc<NetworkAuditLog>>,
     pub(crate) allow_ip_name_lookup: bool,
+    pub(crate) component_id: Option<Arc<dyn Any + Send + Sync>>,
+    pub(crate) capability_policy: Arc<CapabilityPolicy>,
+    pub(crate) preopen_scope: ScopePolicy,
+    pub(crate) memory_creator: Option<Arc<BoundedMemoryCreator>>,
+    pub(crate) resource_limiter: Box<dyn ResourceLimiterAsync + Send + Sync>,
+    pub(crate) stats: Arc<ExecutionCounters>,
+    pub(crate) thread_spawner: Option<Arc<dyn WasiThreadSpawner>>,
+    pub(crate) signals: Option<Arc<SignalQueue>>,
+    pub(crate) profiler: Option<(Box<dyn ComponentProfiler>, u64)>,
+    pub(crate) scheduling: Option<Scheduling>,
+    pub(crate) metrics: WasiMetrics,
+    pub(crate) exit_handler: Option<Arc<dyn Fn(i32) -> ExitBehavior + Send + Sync>>,
+    pub(crate) exit_snapshot_handler: Option<Box<dyn Fn(Snapshot) + Send + Sync>>,
+    pub(crate) on_resource_create: Option<ResourceHook>,
+    pub(crate) on_resource_drop: Option<ResourceHook>,
+    pub(crate) resource_callback: Option<ResourceCallback>,
+    pub(crate) max_table_entries: Option<u32>,
+    pub(crate) handle_limits: HandleLimits,
+    pub(crate) prebound_resources: Vec<(u32, Box<dyn Any + Send + Sync>)>,
+    pub(crate) trace: Option<SyscallTrace>,
+    pub(crate) debug_label: Option<String>,
+    pub(crate) request_id: Option<String>,
+    pub(crate) request_span: tracing::Span,
+}
+
+type ResourceHook = Arc<dyn Fn(ResourceKind, u32) + Send + Sync>;
+
+/// The environment variable holding the ID set with
+/// [`WasiCtxBuilder::with_request_id`].
+const REQUEST_ID_ENV: &str = "WASMTIME_REQUEST_ID";
+
+/// Returns the span entered during host calls for the request `id`, or a
+/// disabled span if there is none.
+fn request_span(id: Option<&str>) -> tracing::Span {
+    match id {
+        Some(id) => tracing::info_span!("wasi_request", request_id = id),
+        None => tracing::Span::none(),
+    }
+}
+
+/// The name of the host state of a [`Snapshot`] in which
+/// [`WasiCtx::attach_monotonic_clock`] records the monotonic clock.
+const MONOTONIC_CLOCK_STATE: &str = "wasi:clocks/monotonic-clock";
+
+/// The name of the host state of a [`Snapshot`] in which [`add_to_snapshots`]
+/// records the network pool.
+const NETWORK_POOL_STATE: &str = "wasi:sockets/network-pool";
+
+/// Makes snapshots of instances in `store` record the WASI state of its
+/// [`WasiCtx`] that the guest can observe but that is not part of the
+/// instance itself, and restoring them bring that state back.
+///
+/// This registers host states with
+/// [`Store::snapshot_host_state`](wasmtime::Store::snapshot_host_state) for:
+///
+/// * the monotonic clock, with [`WasiCtx::attach_monotonic_clock`] and
+///   [`WasiCtx::restore_monotonic_clock`], so that guests restored into a new
+///   store observe no discontinuity in the clock;
+/// * the network pool, as captured by [`WasiCtx::capture_pool`], which
+///   replaces the pool of the context restored into, so that the guest keeps
+///   access to exactly the addresses it was granted.
+pub fn add_to_snapshots<T: WasiView + 'static>(store: &mut Store<T>) {
+    store.snapshot_host_state(
+        MONOTONIC_CLOCK_STATE,
+        |data: &mut T| Ok(data.ctx().monotonic_clock_state()),
+        |data: &mut T, state| data.ctx_mut().restore_monotonic_clock_state(state),
+    );
+    store.snapshot_host_state(
+        NETWORK_POOL_STATE,
+        |data: &mut T| data.ctx().network_pool_state(),
+        |data: &mut T, state| data.ctx_mut().restore_network_pool_state(state),
+    );
+}
+
+/// Returns the divergence of `random` if it is a [`ReplayRng`], for host
+/// calls to fail once it runs out of recorded bytes.
+fn replay_divergence(random: &dyn Any) -> Option<ReplayDivergence> {
+    random
+        .downcast_ref::<ReplayRng>()
+        .map(ReplayRng::divergence)
+}
+
+/// Wraps the stdio streams of a context in the rate limits of `config`.
+fn rate_limit_stdio(
+    config: IoRateLimitConfig,
+    mut stdin: Box<dyn StdinStream>,
+    mut stdout: Box<dyn StdoutStream>,
+    mut stderr: Box<dyn StdoutStream>,
+) -> (
+    Box<dyn StdinStream>,
+    Box<dyn StdoutStream>,
+    Box<dyn StdoutStream>,
+) {
+    if let Some(rate) = config.read_bytes_per_sec {
+        stdin = Box::new(RateLimitedStdin::new(stdin, TokenBucket::new(rate)));
+    }
+    if let Some(rate) = config.write_bytes_per_sec {
+        let bucket = TokenBucket::new(rate);
+        stdout = Box::new(RateLimitedStdout::new(stdout, bucket.clone()));
+        stderr = Box::new(RateLimitedStdout::new(stderr, bucket));
+    }
+    (stdin, stdout, stderr)
+}
+
+/// Wraps the stdin of a context so that it delivers the stdin events of
+/// `events` and counts the bytes read into `read`.
+fn event_stdin(
+    stdin: Arc<dyn StdinStream>,
+    events: &Arc<EventQueue>,
+    read: &Arc<AtomicU64>,
+) -> Arc<dyn StdinStream> {
+    Arc::new(CountingStdin::new(
+        Box::new(EventStdin::new(stdin, events.clone())),
+        read.clone(),
+    ))
+}
+
+/// Wraps the stdout or stderr of a context so that it is held back in
+/// `paused` while I/O is paused and counts the bytes written into `written`.
+fn pausable_stdout(
+    stdout: Box<dyn StdoutStream>,
+    paused: &PauseBuffer,
+    written: &Arc<AtomicU64>,
+) -> Arc<dyn StdoutStream> {
+    Arc::new(CountingStdout::new(
+        Box::new(PausableStdout::new(stdout, paused.clone())),
+        written.clone(),
+    ))
+}
+
+/// Tees a stream of the host process `output` into `writer`, returning the
+/// tee and whether `output` is a TTY.
+fn tee_host_output(output: &dyn StdoutStream, writer: SharedWriter) -> (TeeOutputStream, IsATTY) {
+    let isatty = if output.isatty() {
+        IsATTY::Yes
+    } else {
+        IsATTY::No
+    };
+    let log = WriteOutputStream::new(writer, STDIO_BUFFER_SIZE);
+    let stream = TeeOutputStream::from_boxed(STDIO_BUFFER_SIZE, output.stream(), Box::new(log));
+    (stream, isatty)
+}
+
+impl WasiCtx {
+    /// Returns the identifier configured with
+    /// [`WasiCtxBuilder::component_id`], if any was set and it is of type `T`.
+    pub fn component_id<T: Any>(&self) -> Option<&T> {
+        self.component_id.as_ref()?.downcast_ref()
+    }
+
+    /// Returns a serializable record of the network addresses this context
+    /// was granted access to.
+    ///
+    /// The record can be replayed into a new context with
+    /// [`WasiCtxBuilder::pool_from_capture`].
+    pub fn capture_pool(&self) -> NetworkPoolCapture {
+        self.pool_capture.clone()
+    }
+
+    /// Creates an empty [`Table`] for use with this context, limited to the
+    /// number of entries configured with
+    /// [`WasiCtxBuilder::max_table_entries`].
+    pub fn new_table(&self) -> Table {
+        match self.max_table_entries {
+            Some(max_entries) => Table::with_max_entries(max_entries),
+            None => Table::new(),
+        }
+    }
+
+    /// Moves the resources added with [`WasiCtxBuilder::push_resource`] and
+    /// [`WasiCtxBuilder::with_prebound_resource`] into `table` at their
+    /// indices.
+    ///
+    /// This should be called on the table of the store, before the guest
+    /// runs. Resources are only bound once, so subsequent calls do nothing.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`TableError::Occupied`] if one of the indices is already
+    /// in use in `table`, or with [`TableError::Full`] if `table` has no room
+    /// for all resources.
+    pub fn bind_resources(&mut self, table: &mut Table) -> Result<(), TableError> {
+        table.push_boxed_at(mem::take(&mut self.prebound_resources))
+    }
+
+    /// Returns the value of the environment variable `key`, as configured
+    /// with [`WasiCtxBuilder::env`], or `None` if it is not set.
+    ///
+    /// If the variable is set more than once, the first value is returned.
+    /// Secrets added with [`WasiCtxBuilder::env_secret`] are never returned,
+    /// and the [namespace](WasiCtx::set_env_namespace) of the environment is
+    /// not applied.
+    pub fn get_env(&self, key: &str) -> Option<&str> {
+        self.env
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns all environment variables, as configured with
+    /// [`WasiCtxBuilder::env`] and [`WasiCtxBuilder::envs`], in the order
+    /// they were added.
+    ///
+    /// Like [`WasiCtx::get_env`], this excludes secrets and ignores the
+    /// namespace of the environment.
+    pub fn get_all_envs(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    /// Returns the arguments passed to the guest, as configured with
+    /// [`WasiCtxBuilder::args`] and [`WasiCtxBuilder::arg`].
+    pub fn get_args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Scopes the environment of the guest to the variables whose names
+    /// start with `prefix`, for hosts sharing one environment between
+    /// several tenants.
+    ///
+    /// The guest only sees those variables, including secrets, with `prefix`
+    /// stripped from their names: with the prefix `TENANT_A__`, the variable
+    /// `TENANT_A__FOO` appears to the guest as `FOO`. The namespace replaces
+    /// any namespace set before and is kept by [`WasiCtx::fork`].
+    pub fn set_env_namespace(&mut self, prefix: impl Into<String>) {
+        self.env_namespace = Some(prefix.into());
+    }
+
+    /// Returns the current reading of the monotonic clock, as seen by the
+    /// guest.
+    pub(crate) fn monotonic_now(&self) -> u64 {
+        self.monotonic_clock
+            .now()
+            .saturating_add(self.monotonic_clock_offset)
+    }
+
+    /// Shifts all readings of the monotonic clock forward by `offset`.
+    ///
+    /// The offset replaces any offset set before, and also applies to
+    /// subscriptions for absolute instants and to the monotonic clock
+    /// deadline.
+    pub fn set_monotonic_clock_offset(&mut self, offset: Duration) {
+        self.monotonic_clock_offset = u64::try_from(offset.as_nanos()).unwrap_or(u64::MAX);
+    }
+
+    /// Stops the monotonic clock at `ns` nanoseconds, as read by the guest,
+    /// until it is moved with [`WasiCtx::advance_frozen_clock`] or restarted
+    /// with [`WasiCtx::unfreeze_clock`].
+    ///
+    /// This allows tests to drive components which use the monotonic clock
+    /// for timeouts, rate limiting or backoff through any sequence of
+    /// readings. Freezing a clock which is already frozen moves it to `ns`.
+    /// The frozen clock is shared with contexts created with
+    /// [`WasiCtx::fork`] afterwards.
+    pub fn freeze_monotonic_clock(&mut self, ns: u64) {
+        let clock = Arc::new(FrozenClock::at_ns(ns));
+        let frozen = match self.frozen_monotonic_clock.take() {
+            Some(frozen) => FrozenMonotonicClock { clock, ..frozen },
+            None => FrozenMonotonicClock {
+                clock,
+                real: self.monotonic_clock.clone(),
+                real_offset: self.monotonic_clock_offset,
+            },
+        };
+        self.monotonic_clock = frozen.clock.clone();
+        self.monotonic_clock_offset = 0;
+        self.frozen_monotonic_clock = Some(frozen);
+    }
+
+    /// Moves the monotonic clock frozen with
+    /// [`WasiCtx::freeze_monotonic_clock`] forward by `delta_ns` nanoseconds.
+    ///
+    /// Pending subscriptions to the clock are moved forward as well, as for
+    /// a [`WasiEvent::MonotonicAlarm`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the clock is not frozen, or if it would overflow.
+    pub fn advance_frozen_clock(&mut self, delta_ns: u64) -> anyhow::Result<()> {
+        let frozen = self
+            .frozen_monotonic_clock
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("the monotonic clock is not frozen"))?;
+        if self.monotonic_now().checked_add(delta_ns).is_none() {
+            anyhow::bail!("advancing the frozen clock overflows it");
+        }
+        frozen.clock.advance(delta_ns);
+        self.events.advance_clock(Duration::from_nanos(delta_ns));
+        Ok(())
+    }
+
+    /// Restarts the monotonic clock replaced by
+    /// [`WasiCtx::freeze_monotonic_clock`], doing nothing if it is not
+    /// frozen.
+    ///
+    /// The clock continues from the later of its frozen reading and the
+    /// reading it would have had if it had never been frozen, so the guest
+    /// never observes it going backwards.
+    pub fn unfreeze_clock(&mut self) {
+        if let Some(frozen) = self.frozen_monotonic_clock.take() {
+            let frozen_now = self.monotonic_now();
+            let real_now = frozen.real.now();
+            self.monotonic_clock_offset =
+                frozen_now.saturating_sub(real_now).max(frozen.real_offset);
+            self.monotonic_clock = frozen.real;
+        }
+    }
+
+    /// Injects a synthetic event into the component.
+    ///
+    /// Stdin bytes are delivered to the guest's next reads of stdin, and
+    /// pending subscriptions to stdin become ready. A monotonic alarm moves
+    /// the monotonic clock forward, so that pending subscriptions to the
+    /// clock whose deadline has now passed become ready. Custom events are
+    /// queued until they are taken with [`WasiCtx::take_custom_events`].
+    /// All of these are observed by the guest's next call to `poll`, which
+    /// allows test harnesses to drive a component without real I/O.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a monotonic alarm would overflow the clock.
+    pub fn emit_event(&mut self, event: WasiEvent) -> anyhow::Result<()> {
+        match event {
+            WasiEvent::Stdin(bytes) => self.events.push_stdin(bytes),
+            WasiEvent::MonotonicAlarm(duration) => {
+                let nanos = u64::try_from(duration.as_nanos())
+                    .ok()
+                    .and_then(|nanos| self.monotonic_clock_offset.checked_add(nanos))
+                    .ok_or_else(|| anyhow::anyhow!("monotonic alarm overflows the clock"))?;
+                self.monotonic_clock_offset = nanos;
+                self.events.advance_clock(duration);
+            }
+            WasiEvent::Custom { fd, payload } => self.events.push_custom(fd, payload),
+        }
+        Ok(())
+    }
+
+    /// Takes the payloads of all [`WasiEvent::Custom`] events emitted for
+    /// `fd` so far, in the order they were emitted.
+    pub fn take_custom_events(&self, fd: u32) -> Vec<Vec<u8>> {
+        self.events.take_custom(fd)
+    }
+
+    /// Records the current reading of the monotonic clock as a host state of
+    /// `snapshot`, replacing any reading recorded before.
+    ///
+    /// After restoring the snapshot into an instance in another store, pass
+    /// it to [`WasiCtx::restore_monotonic_clock`] of that store's context to
+    /// continue the clock where it left off. Stores set up with
+    /// [`add_to_snapshots`] do both automatically.
+    pub fn attach_monotonic_clock(&self, snapshot: Snapshot) -> Snapshot {
+        let mut states = snapshot
+            .host_states()
+            .iter()
+            .filter(|(name, _)| name != MONOTONIC_CLOCK_STATE)
+            .cloned()
+            .collect::<Vec<_>>();
+        states.push((
+            MONOTONIC_CLOCK_STATE.to_string(),
+            self.monotonic_clock_state(),
+        ));
+        snapshot.with_host_states(states)
+    }
+
+    /// Sets the offset of the monotonic clock such that its next reading
+    /// continues from the reading recorded in `snapshot` with
+    /// [`WasiCtx::attach_monotonic_clock`].
+    ///
+    /// `Instance::restore` only calls this for stores set up with
+    /// [`add_to_snapshots`]. If the clock of this context is
+    /// already ahead of the recorded reading, no offset is applied, since
+    /// the clock cannot go backwards. Returns whether `snapshot` recorded a
+    /// reading.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the recorded reading is malformed.
+    pub fn restore_monotonic_clock(&mut self, snapshot: &Snapshot) -> anyhow::Result<bool> {
+        match snapshot
+            .host_states()
+            .iter()
+            .find(|(name, _)| name == MONOTONIC_CLOCK_STATE)
+        {
+            Some((_, state)) => {
+                self.restore_monotonic_clock_state(state)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the current reading of the monotonic clock as a host state.
+    fn monotonic_clock_state(&self) -> SuspendState {
+        SuspendState::new(self.monotonic_now().to_le_bytes().to_vec())
+    }
+
+    /// Continues the monotonic clock from the reading recorded in `state`
+    /// by [`WasiCtx::monotonic_clock_state`].
+    fn restore_monotonic_clock_state(&mut self, state: &SuspendState) -> anyhow::Result<()> {
+        let recorded = u64::from_le_bytes(
+            state
+                .data()
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("malformed monotonic clock state in snapshot"))?,
+        );
+        let offset = recorded.saturating_sub(self.monotonic_clock.now());
+        self.set_monotonic_clock_offset(Duration::from_nanos(offset));
+        Ok(())
+    }
+
+    /// Returns the grants of the network pool as a host state.
+    fn network_pool_state(&self) -> anyhow::Result<SuspendState> {
+        Ok(SuspendState::new(serde_json::to_vec(&self.pool_capture)?))
+    }
+
+    /// Replaces the network pool with the grants recorded in `state` by
+    /// [`WasiCtx::network_pool_state`].
+    fn restore_network_pool_state(&mut self, state: &SuspendState) -> anyhow::Result<()> {
+        let capture: NetworkPoolCapture = serde_json::from_slice(state.data())
+            .map_err(|e| anyhow::anyhow!("malformed network pool state in snapshot: {e}"))?;
+        let mut pool = Pool::new();
+        capture.apply(&mut pool)?;
+        self.pool = pool;
+        self.pool_capture = capture;
+        Ok(())
+    }
+
+    /// Exports the portable WASI state of this context, which can be replayed
+    /// into a new context with [`WasiCtxBuilder::import_wasi_state`].
+    ///
+    /// See [`WasiStateExport`] for what is part of the state.
+    pub fn export_state(&self) -> WasiStateExport {
+        let host_now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let wall_clock_offset =
+            self.wall_clock.now().as_nanos() as i128 - host_now.as_nanos() as i128;
+        WasiStateExport {
+            version: WasiStateExport::VERSION,
+            env: self.env.clone(),
+            args: self.args.clone(),
+            preopens: self.preopen_exports.clone(),
+            network: self.pool_capture.clone(),
+            allow_ip_name_lookup: self.allow_ip_name_lookup,
+            wall_clock_offset: wall_clock_offset.clamp(i64::MIN.into(), i64::MAX.into()) as i64,
+            monotonic_clock: self.monotonic_now(),
+        }
+    }
+
+    /// Reads the current state of the filesystem configured with
+    /// [`WasiCtxBuilder::fake_filesystem`], including any changes made by the
+    /// guest, or returns `None` if none was configured.
+    ///
+    /// The result can be used to configure a new context with the same
+    /// filesystem contents, for example after restoring a snapshot.
+    pub fn fake_filesystem(&self) -> std::io::Result<Option<FakeFilesystem>> {
+        self.fake_root
+            .as_ref()
+            .map(|root| root.capture())
+            .transpose()
+    }
+
+    /// Reads the overlay of the directory preopened at `guest_path` with
+    /// [`WasiCtxBuilder::preopen_overlay_dir`], or returns `None` if there is
+    /// no such directory.
+    ///
+    /// The result holds the entries which differ from the base directory:
+    /// new files and directories, and files whose contents changed,
+    /// including changes made by the guest. Deletions of entries of the base
+    /// are not recorded.
+    pub fn overlay_dir(&self, guest_path: &str) -> std::io::Result<Option<FakeFilesystem>> {
+        self.overlay_dirs
+            .iter()
+            .find(|(path, _)| path == guest_path)
+            .map(|(_, dir)| dir.capture_changes())
+            .transpose()
+    }
+
+    /// Creates an independent copy of this context, for example to run
+    /// several workers from one template context.
+    ///
+    /// The fork has the same environment, arguments, preopens, network
+    /// access, clocks, deadlines, limits and handlers as this context, except
+    /// for the resource limiter, which cannot be shared. Its random
+    /// generators are freshly seeded from this context's secure generator.
+    /// Preopened directories, as well as the files and directories open in
+    /// `table`, the table of this context, are duplicated with `try_clone`;
+    /// the latter are added to the fork at the same indices, to be placed
+    /// into its table with [`WasiCtx::bind_resources`].
+    ///
+    /// The temporary directories of [`WasiCtxBuilder::fake_filesystem`],
+    /// [`WasiCtxBuilder::preopen_overlay_dir`] and
+    /// [`WasiCtxBuilder::preopen_union`] are copied, along with the guest's
+    /// changes so far, and the preopens and handles the fork inherits within
+    /// them are moved to the copies, so that neither context sees the
+    /// other's writes. The base of an overlay is shared, since it is never
+    /// written.
+    ///
+    /// Stdin is copied if it supports [`StdinStream::fork`], such as an
+    /// in-memory pipe, and shared with this context otherwise. Stdout and
+    /// stderr are shared: the fork writes to the same outputs as this
+    /// context, through streams of its own, so the output of both contexts
+    /// is interleaved, while each context counts its own bytes written and
+    /// pauses its own I/O. To give the fork outputs of its own, or to discard
+    /// its output, replace them with [`WasiCtx::set_stdout`] and
+    /// [`WasiCtx::set_stderr`]. Events emitted
+    /// into either context are only delivered to that context. Metrics and
+    /// execution stats start at zero, no profiler is installed, syscalls of
+    /// the fork are not traced, and resources added to this context with
+    /// [`WasiCtxBuilder::push_resource`] which were not bound yet are not
+    /// carried over.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a file or directory cannot be duplicated or copied, or if the
+    /// secure generator is a [`ReplayRng`] which cannot serve the seeds.
+    pub fn fork(&mut self, table: &mut Table) -> std::io::Result<WasiCtx> {
+        let mut copies = CopiedRoots::default();
+        let fake_root = match &self.fake_root {
+            Some(root) => Some(Arc::new(root.copy(&mut copies)?)),
+            None => None,
+        };
+        let overlay_dirs = self
+            .overlay_dirs
+            .iter()
+            .map(|(path, dir)| Ok((path.clone(), Arc::new(dir.copy(&mut copies)?))))
+            .collect::<std::io::Result<_>>()?;
+        let union_dirs = self
+            .union_dirs
+            .iter()
+            .map(|dir| Ok(Arc::new(dir.copy(&mut copies)?)))
+            .collect::<std::io::Result<_>>()?;
+        let preopens = self
+            .preopens
+            .iter()
+            .map(|(dir, path)| Ok((copies.dir(dir)?, path.clone())))
+            .collect::<std::io::Result<_>>()?;
+        let mut prebound_resources: Vec<(u32, Box<dyn Any + Send + Sync>)> = Vec::new();
+        for (index, dir) in table.iter_dirs() {
+            prebound_resources.push((index, Box::new(Descriptor::Dir(copies.dir(dir)?))));
+        }
+        for (index, file) in table.iter_files() {
+            prebound_resources.push((index, Box::new(Descriptor::File(copies.file(file)?))));
+        }
+
+        let paused_io = PausedIoBuffers::default();
+        let events = EventQueue::new();
+        let stdin_bytes_read = Arc::new(AtomicU64::new(0));
+        let stdout_bytes_written = Arc::new(AtomicU64::new(0));
+        let stderr_bytes_written = Arc::new(AtomicU64::new(0));
+        let stdin_source = match self.stdin_source.fork() {
+            Some(stdin) => stdin.into(),
+            None => self.stdin_source.clone(),
+        };
+        let stdin = event_stdin(Arc::clone(&stdin_source), &events, &stdin_bytes_read);
+        let stdout_source = self.stdout_source.clone();
+        let stderr_source = self.stderr_source.clone();
+        let stdout = pausable_stdout(
+            Box::new(stdout_source.clone()),
+            &paused_io.stdout,
+            &stdout_bytes_written,
+        );
+        let stderr = pausable_stdout(
+            Box::new(stderr_source.clone()),
+            &paused_io.stderr,
+            &stderr_bytes_written,
+        );
+        let stats = ExecutionCounters::new([
+            stdin_bytes_read,
+            stdout_bytes_written.clone(),
+            stderr_bytes_written.clone(),
+        ]);
+        let random = cap_rand::rngs::StdRng::from_seed(self.random.gen());
+        let insecure_random = cap_rand::rngs::SmallRng::seed_from_u64(self.random.gen());
+        let insecure_random_seed = self.random.gen();
+        if let Some(replay) = &self.random_replay {
+            replay
+                .check()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        }
+
+        Ok(WasiCtx {
+            random: Box::new(random),
+            random_replay: None,
+            insecure_random: Box::new(insecure_random),
+            insecure_random_replay: None,
+            insecure_random_seed,
+            wall_clock: self.wall_clock.clone(),
+            monotonic_clock: self.monotonic_clock.clone(),
+            monotonic_clock_offset: self.monotonic_clock_offset,
+            wall_clock_deadline: self.wall_clock_deadline.clone(),
+            monotonic_clock_deadline: self.monotonic_clock_deadline.clone(),
+            frozen_monotonic_clock: self.frozen_monotonic_clock.clone(),
+            env: self.env.clone(),
+            env_secrets: self.env_secrets.clone(),
+            env_namespace: self.env_namespace.clone(),
+            args: self.args.clone(),
+            preopens,
+            preopen_exports: self.preopen_exports.clone(),
+            dir_quotas: self.dir_quotas.clone(),
+            fake_root,
+            overlay_dirs,
+            union_dirs,
+            events,
+            stdin_source,
+            stdin,
+            stdout_source,
+            stdout,
+            stderr_source,
+            stderr,
+            stdout_bytes_written,
+            paused_io,
+            stderr_bytes_written,
+            pool: self.pool.clone(),
+            pool_capture: self.pool_capture.clone(),
+            socket_factory: self.socket_factory.clone(),
+            unix_socket_dir: self.unix_socket_dir.clone(),
+            network_audit_log: self.network_audit_log.clone(),
+            allow_ip_name_lookup: self.allow_ip_name_lookup,
+            component_id: self.component_id.clone(),
+            capability_policy: self.capability_policy.clone(),
+            preopen_scope: self.preopen_scope,
+            memory_creator: self.memory_creator.clone(),
+            resource_limiter: Box::new(StatsLimiter::new(
+                MemoryLimiter::wrap(
+                    Box::new(Unlimited),
+                    self.memory_creator.as_ref().map(|creator| creator.limit()),
+                ),
+                stats.clone(),
+                self.resource_callback.clone(),
+            )),
+            stats,
+            thread_spawner: self.thread_spawner.clone(),
+            signals: self.signals.clone(),
+            profiler: None,
+            scheduling: self.scheduling.as_ref().map(Scheduling::fork),
+            metrics: WasiMetrics::new(self.metrics.is_enabled()),
+            exit_handler: self.exit_handler.clone(),
+            exit_snapshot_handler: None,
+            on_resource_create: self.on_resource_create.clone(),
+            on_resource_drop: self.on_resource_drop.clone(),
+            resource_callback: self.resource_callback.clone(),
+            max_table_entries: self.max_table_entries,
+            handle_limits: self.handle_limits,
+            prebound_resources,
+            trace: None,
+            debug_label: self.debug_label.clone(),
+            request_id: self.request_id.clone(),
+            request_span: request_span(self.request_id.as_deref()),
+        })
+    }
+
+    /// Returns whether `table` already holds as many handles of `kind` as
+    /// the limits of this context allow, so that no more may be opened.
+    pub(crate) fn handle_limit_reached(&self, table: &Table, kind: ResourceKind) -> bool {
+        let (limit, open) = match kind {
+            ResourceKind::File => (self.handle_limits.files, table.iter_files().count()),
+            ResourceKind::Dir => (self.handle_limits.dirs, table.iter_dirs().count()),
+            ResourceKind::TcpSocket | ResourceKind::UdpSocket => (
+                self.handle_limits.sockets,
+                table.iter_tcp_sockets().count() + table.iter_udp_sockets().count(),
+            ),
+            _ => (None, 0),
+        };
+        limit.map_or(false, |limit| open >= limit as usize)
+    }
+
+    pub(crate) fn resource_created(&self, kind: ResourceKind, index: u32) {
+        if let Some(hook) = &self.on_resource_create {
+            hook(kind, index);
+        }
+    }
+
+    pub(crate) fn resource_dropped(&self, kind: ResourceKind, index: u32) {
+        if let Some(hook) = &self.on_resource_drop {
+            hook(kind, index);
+        }
+    }
+
+    /// Passes the event built by `event` to the resource callback, if one
+    /// is installed.
+    pub(crate) fn resource_used(&self, event: impl FnOnce() -> ResourceUsageEvent) {
+        if let Some(callback) = &self.resource_callback {
+            callback(event());
+        }
+    }
+
+    /// Returns the label set with [`WasiCtxBuilder::debug_label`], if any.
+    pub fn debug_label(&self) -> Option<&str> {
+        self.debug_label.as_deref()
+    }
+
+    /// Returns the ID set with [`WasiCtxBuilder::with_request_id`], if any.
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// Returns the storage the guest used so far in the directory preopened
+    /// at `path`, or `None` if no quota was set for it with
+    /// [`WasiCtxBuilder::preopen_dir_quota`].
+    ///
+    /// Forked contexts share the usage of their parent.
+    pub fn dir_quota_usage(&self, path: &str) -> Option<DirQuotaUsage> {
+        self.dir_quotas
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, tracker)| tracker.usage())
+    }
+
+    /// Returns the spawner installed with
+    /// [`WasiCtxBuilder::with_thread_spawner`], if any.
+    pub fn thread_spawner(&self) -> Option<&Arc<dyn WasiThreadSpawner>> {
+        self.thread_spawner.as_ref()
+    }
+
+    /// Returns a prefix for log messages identifying this context by its
+    /// [debug label](WasiCtx::debug_label), for host code logging on behalf
+    /// of the context, or an empty string if it has no label.
+    pub fn log_prefix(&self) -> String {
+        match &self.debug_label {
+            Some(label) => format!("[{label}] "),
+            None => String::new(),
+        }
+    }
+
+    pub(crate) fn trace(&self, call: &str, args: &[TraceValue<'_>], ret: &TraceValue<'_>) {
+        if let Some(trace) = &self.trace {
+            trace.record(call, args, ret);
+        }
+    }
+
+    /// Records an access to `addr` in the
+    /// [network audit log](WasiCtxBuilder::network_audit_log), if there is
+    /// one.
+    pub(crate) fn audit_network(&self, event: &str, addr: impl std::fmt::Display, allowed: bool) {
+        if let Some(log) = &self.network_audit_log {
+            log.record(event, addr, allowed);
+        }
+    }
+
+    /// Returns the memory creator enforcing the limit configured with
+    /// [`WasiCtxBuilder::memory_limit_bytes`], if any.
+    pub fn memory_creator(&self) -> Option<Arc<BoundedMemoryCreator>> {
+        self.memory_creator.clone()
+    }
+
+    /// Returns the resource limiter configured with
+    /// [`WasiCtxBuilder::with_resource_limiter`], or one allowing all growth
+    /// if there is none, to be installed with
+    /// [`Store::limiter_async`](wasmtime::Store::limiter_async).
+    ///
+    /// The limiter also enforces [`WasiCtxBuilder::memory_limit_bytes`] on
+    /// the memories of the store, if set.
+    pub fn resource_limiter(&mut self) -> &mut (dyn ResourceLimiterAsync + Send + Sync) {
+        &mut *self.resource_limiter
+    }
+
+    /// Increments the epoch of `engine` whenever a signal is queued for
+    /// [`WasiCtx::take_signal`], to interrupt the component running in it.
+    ///
+    /// Does nothing if no handler was installed with
+    /// [`WasiCtxBuilder::with_signal_handler`].
+    pub fn interrupt_on_signal(&self, engine: &Engine) {
+        if let Some(signals) = &self.signals {
+            signals.interrupt(engine);
+        }
+    }
+
+    /// Takes the oldest signal received by the host process which the
+    /// handler installed with [`WasiCtxBuilder::with_signal_handler`] did not
+    /// ignore, along with the action the handler decided on.
+    pub fn take_signal(&self) -> Option<(i32, SignalAction)> {
+        self.signals.as_ref()?.take()
+    }
+
+    /// Returns the number of bytes buffered in stdin which the guest can
+    /// read without waiting, including those injected with
+    /// [`WasiEvent::Stdin`].
+    ///
+    /// Nothing is consumed. Bytes buffered by the host process's own stdin
+    /// are not known and not counted, so this is a lower bound, which is
+    /// exact for in-memory stdin such as
+    /// [`WasiCtxBuilder::stdin_from_bytes`].
+    pub fn stdin_bytes_available(&self) -> usize {
+        self.stdin.stream().num_ready_bytes()
+    }
+
+    /// Takes the bytes buffered in the streams of `table`, the table of this
+    /// context, out of them: bytes received by input streams which the guest
+    /// has not read yet and bytes written by the guest which output streams
+    /// have not passed on yet. Without this, such bytes are lost when the
+    /// component is snapshotted and restored.
+    ///
+    /// Only streams which buffer in the host process can be drained, such as
+    /// in-memory pipes, stdin and stdout; see
+    /// [`HostInputStream::drain_pending`](crate::preview2::HostInputStream::drain_pending)
+    /// and [`HostOutputStream::drain_written`]. Since the bytes are taken out,
+    /// they have to be put back with [`WasiCtx::restore_stream_buffers`] if
+    /// this context keeps running.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a stream in `table` cannot be accessed.
+    pub fn capture_stream_buffers(&self, table: &mut Table) -> anyhow::Result<StreamBufferCapture> {
+        stream_buffers::capture(table)
+    }
+
+    /// Puts the bytes of a [`StreamBufferCapture`] back into the streams of
+    /// `table`, so that the guest reads and writes them as if the capture had
+    /// not happened.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `table` does not hold a stream of the right direction at the
+    /// index of a captured stream, or if an output stream does not accept the
+    /// captured bytes.
+    pub fn restore_stream_buffers(
+        &self,
+        table: &mut Table,
+        capture: StreamBufferCapture,
+    ) -> anyhow::Result<()> {
+        stream_buffers::restore(table, capture)
+    }
+
+    /// Holds back the output of the guest to stdout and stderr until the
+    /// returned guard is released or dropped, so that the output of a call
+    /// can be captured as a whole, without racing with the configured
+    /// streams.
+    ///
+    /// The output is held back as the guest wrote it, before the buffering
+    /// and encoding configured on the builder apply. It is still counted by
+    /// [`WasiCtx::stdout_bytes_written`] and
+    /// [`WasiCtx::stderr_bytes_written`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if I/O is already paused.
+    pub fn pause_io(&self) -> IoGuard {
+        IoGuard::new(&self.paused_io)
+    }
+
+    /// Replaces the stdout of the guest with `stdout`, for example to give a
+    /// context created with [`WasiCtx::fork`] an output of its own instead of
+    /// sharing the stdout of its parent.
+    ///
+    /// Streams the guest already obtained keep writing to the previous
+    /// stdout. The tracing, rate limit, buffering and encoding configured on
+    /// the builder are not applied to `stdout`, but its output is held back
+    /// by [`WasiCtx::pause_io`] and counted by
+    /// [`WasiCtx::stdout_bytes_written`].
+    pub fn set_stdout(&mut self, stdout: impl StdoutStream + 'static) {
+        self.stdout_source = Arc::new(stdout);
+        self.stdout = pausable_stdout(
+            Box::new(self.stdout_source.clone()),
+            &self.paused_io.stdout,
+            &self.stdout_bytes_written,
+        );
+    }
+
+    /// Like [`WasiCtx::set_stdout`], but for stderr.
+    pub fn set_stderr(&mut self, stderr: impl StdoutStream + 'static) {
+        self.stderr_source = Arc::new(stderr);
+        self.stderr = pausable_stdout(
+            Box::new(self.stderr_source.clone()),
+            &self.paused_io.stderr,
+            &self.stderr_bytes_written,
+        );
+    }
+
+    /// Returns the total number of bytes the guest has written to stdout.
+    pub fn stdout_bytes_written(&self) -> u64 {
+        self.stdout_bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Like [`WasiCtx::stdout_bytes_written`], but for stderr.
+    pub fn stderr_bytes_written(&self) -> u64 {
+        self.stderr_bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Returns the live counters of WASI host function invocations.
+    ///
+    /// The counters are only updated if metrics were enabled with
+    /// [`WasiCtxBuilder::enable_metrics`].
+    pub fn metrics(&self) -> &WasiMetrics {
+        &self.metrics
+    }
+
+    /// Returns the resources used by the component since this context was
+    /// built or its stats were last reset with [`WasiCtx::reset_stats`].
+    ///
+    /// Unlike [`WasiCtx::metrics`], these are always counted. The CPU time
+    /// and number of host calls are only measured once the store reports its
+    /// calls with [`WasiCtx::record_call_hook`], and the memory peak once the
+    /// store consults [`WasiCtx::resource_limiter`].
+    pub fn execution_stats(&self) -> ExecutionStats {
+        self.stats.get()
+    }
+
+    /// Returns the same as [`WasiCtx::execution_stats`] and sets all stats
+    /// back to zero, for example at the end of each billing period.
+    ///
+    /// The memory peak restarts at the current size of the memories.
+    pub fn reset_stats(&self) -> ExecutionStats {
+        self.stats.reset()
+    }
+
+    /// Passes a sample to the profiler installed with
+    /// [`WasiCtxBuilder::with_profiler`] and returns the number of epochs
+    /// until the next sample is due, or `None` if there is no profiler.
+    ///
+    /// This is meant to be called from the store's epoch deadline callback,
+    /// which also knows the fuel consumed:
+    ///
+    /// ```ignore
+    /// store.set_epoch_deadline(1);
+    /// store.epoch_deadline_callback(|mut store| {
+    ///     let fuel = store.fuel_consumed().unwrap_or(0);
+    ///     let next = store.data_mut().ctx_mut().sample_profiler(fuel);
+    ///     Ok(UpdateDeadline::Continue(next.unwrap_or(u64::MAX)))
+    /// });
+    /// ```
+    ///
+    /// The memory used is measured like the memory peak of
+    /// [`WasiCtx::execution_stats`], so it is only known once the store
+    /// consults [`WasiCtx::resource_limiter`].
+    pub fn sample_profiler(&mut self, fuel_consumed: u64) -> Option<u64> {
+        let (profiler, interval) = self.profiler.as_mut()?;
+        profiler.sample(Instant::now(), fuel_consumed, self.stats.memory_used());
+        Some(*interval)
+    }
+
+    /// Asks the scheduler installed with [`WasiCtxBuilder::with_scheduler`]
+    /// whether the component should yield, given the fuel the store has
+    /// consumed so far, and returns how its epoch deadline callback should
+    /// proceed: [`UpdateDeadline::Yield`] if it should yield, and
+    /// [`UpdateDeadline::Continue`] otherwise or if there is no scheduler.
+    ///
+    /// This is meant to be called from the store's epoch deadline callback,
+    /// with the store configured for async support so that it can yield:
+    ///
+    /// ```ignore
+    /// store.set_epoch_deadline(1);
+    /// store.epoch_deadline_callback(|mut store| {
+    ///     let fuel = store.fuel_consumed().unwrap_or(0);
+    ///     Ok(store.data_mut().ctx_mut().schedule(fuel))
+    /// });
+    /// ```
+    pub fn schedule(&mut self, fuel_consumed: u64) -> UpdateDeadline {
+        match self
+            .scheduling
+            .as_mut()
+            .map(|s| s.should_yield(fuel_consumed))
+        {
+            Some(true) => UpdateDeadline::Yield(1),
+            _ => UpdateDeadline::Continue(1),
+        }
+    }
+
+    /// Measures the CPU time spent running wasm and counts the calls into
+    /// the host for [`WasiCtx::execution_stats`], given every transition
+    /// reported to the store's call hook:
+    ///
+    /// ```ignore
+    /// store.call_hook(|data, hook| {
+    ///     data.ctx().record_call_hook(hook);
+    ///     Ok(())
+    /// });
+    /// ```
+    ///
+    /// CPU time is that of the thread running wasm on Unix and wall-clock
+    /// time elsewhere.
+    ///
+    /// If the context has a [request ID](WasiCtxBuilder::with_request_id),
+    /// its span is also entered from the moment wasm calls into the host
+    /// until the call returns.
+    pub fn record_call_hook(&self, hook: CallHook) {
+        self.stats.record_call_hook(hook);
+        match hook {
+            CallHook::CallingHost => {
+                self.request_span
+                    .with_subscriber(|(id, dispatch)| dispatch.enter(id));
+            }
+            CallHook::ReturningFromHost => {
+                self.request_span
+                    .with_subscriber(|(id, dispatch)| dispatch.exit(id));
+            }
+            CallHook::CallingWasm | CallHook::ReturningFromWasm => {}
+        }
+    }
+
+    /// Takes the snapshot callback of the most recent exit which was handled
+    /// with [`ExitBehavior::SnapshotAndPropagate`], if any.
+    ///
+    /// The embedder should pass a [`Snapshot`] of the exiting instance to the
+    /// returned callback.
+    pub fn take_exit_snapshot_handler(&mut self) -> Option<Box<dyn Fn(Snapshot) + Send + Sync>> {
+        self.exit_snapshot_handler.take()
+    }
+}
+
+/// Parses the contents of a `.env` file as documented on
+/// [`WasiCtxBuilder::env_from_dotenv`].
+fn parse_dotenv(contents: &str) -> std::io::Result<Vec<(String, String)>> {
+    let invalid = |lineno: usize, msg: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid .env file at line {}: {msg}", lineno + 1),
+        )
+    };
+
+    let mut vars = Vec::new();
+    for (lineno, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid(lineno, "expected `KEY=VALUE`"))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(invalid(lineno, "empty variable name"));
+        }
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('"') {
+            let mut unescaped = String::new();
+            let mut chars = quoted.chars();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => unescaped.push('\n'),
+                        Some(c @ ('"' | '\\')) => unescaped.push(c),
+                        Some(c) => {
+                            unescaped.push('\\');
+                            unescaped.push(c);
+                        }
+                        None => return Err(invalid(lineno, "unterminated quoted value")),
+                    },
+                    Some(c) => unescaped.push(c),
+                    None => return Err(invalid(lineno, "unterminated quoted value")),
+                }
+            }
+            unescaped
+        } else if let Some(quoted) = value.strip_prefix('\'') {
+            match quoted.split_once('\'') {
+                Some((value, _)) => value.to_owned(),
+                None => return Err(invalid(lineno, "unterminated quoted value")),
+            }
+        } else {
+            // Unquoted values end at a comment.
+            match value.find(" #") {
+                Some(i) => value[..i].trim_end().to_owned(),
+                None => value.to_owned(),
+            }
+        };
+        vars.push((key.to_owned(), value));
+    }
+    Ok(vars)
 }
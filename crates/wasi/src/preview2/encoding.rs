@@ -0,0 +1,176 @@
+use crate::preview2::{HostOutputStream, StdoutStream, StreamError, StreamResult, Subscribe};
+use bytes::{Bytes, BytesMut};
+use std::fmt;
+use std::sync::Arc;
+
+/// The character encoding of a guest's output, converted to UTF-8 by
+/// [`WasiCtxBuilder::stdout_encoding`](crate::preview2::WasiCtxBuilder::stdout_encoding).
+pub enum TextEncoding {
+    /// UTF-8, which is passed on unchanged.
+    Utf8,
+    /// ISO-8859-1, in which every byte is the code point of the same value.
+    Latin1,
+    /// Windows-1252, the superset of ISO-8859-1 used by legacy Windows
+    /// software.
+    Windows1252,
+    /// A custom conversion to UTF-8, called with the bytes of each write.
+    ///
+    /// The conversion must not depend on how the output is split into
+    /// writes, so stateful encodings such as UTF-16 are not supported.
+    Custom(TextConversion),
+}
+
+/// A conversion of the bytes of a write to UTF-8, for
+/// [`TextEncoding::Custom`].
+pub type TextConversion = Box<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+impl TextEncoding {
+    /// Converts `bytes` from this encoding to UTF-8.
+    fn to_utf8(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            TextEncoding::Utf8 => bytes.to_vec(),
+            TextEncoding::Latin1 => bytes
+                .iter()
+                .map(|b| char::from(*b))
+                .collect::<String>()
+                .into_bytes(),
+            TextEncoding::Windows1252 => encoding_rs::WINDOWS_1252
+                .decode_without_bom_handling(bytes)
+                .0
+                .into_owned()
+                .into_bytes(),
+            TextEncoding::Custom(convert) => convert(bytes),
+        }
+    }
+}
+
+impl fmt::Debug for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextEncoding::Utf8 => f.write_str("Utf8"),
+            TextEncoding::Latin1 => f.write_str("Latin1"),
+            TextEncoding::Windows1252 => f.write_str("Windows1252"),
+            TextEncoding::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Standard output whose streams convert the guest's output to UTF-8.
+pub(crate) struct TranscodingStdout {
+    inner: Box<dyn StdoutStream>,
+    encoding: Arc<TextEncoding>,
+}
+
+impl TranscodingStdout {
+    pub(crate) fn new(inner: Box<dyn StdoutStream>, encoding: TextEncoding) -> Self {
+        Self {
+            inner,
+            encoding: Arc::new(encoding),
+        }
+    }
+}
+
+impl StdoutStream for TranscodingStdout {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(TranscodingOutputStream {
+            inner: self.inner.stream(),
+            encoding: Arc::clone(&self.encoding),
+            pending: BytesMut::new(),
+            flush_pending: false,
+            error: None,
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.inner.isatty()
+    }
+}
+
+/// An output stream which converts everything written to it to UTF-8.
+///
+/// Converted text may be longer than what was written, so the permit of
+/// [`HostOutputStream::check_write`] is that of the underlying stream, and
+/// whatever it does not accept is kept until it does. No more writes are
+/// permitted until then.
+struct TranscodingOutputStream {
+    inner: Box<dyn HostOutputStream>,
+    encoding: Arc<TextEncoding>,
+    /// Converted bytes which the underlying stream has not accepted yet.
+    pending: BytesMut,
+    /// Whether a flush waits for `pending` to drain before being passed on.
+    flush_pending: bool,
+    /// An error of the underlying stream raised while waiting for it, to be
+    /// reported by the next operation.
+    error: Option<StreamError>,
+}
+
+impl TranscodingOutputStream {
+    /// Passes as many pending bytes on as the underlying stream accepts
+    /// without waiting, followed by a pending flush once all are passed on.
+    fn forward(&mut self) -> StreamResult<()> {
+        while !self.pending.is_empty() {
+            let permit = self.inner.check_write()?;
+            if permit == 0 {
+                return Ok(());
+            }
+            let n = permit.min(self.pending.len());
+            self.inner.write(self.pending.split_to(n).freeze())?;
+        }
+        if self.flush_pending {
+            self.inner.flush()?;
+            self.flush_pending = false;
+        }
+        Ok(())
+    }
+
+    fn take_error(&mut self) -> StreamResult<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl HostOutputStream for TranscodingOutputStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.take_error()?;
+        self.pending
+            .extend_from_slice(&self.encoding.to_utf8(&bytes));
+        self.forward()
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.take_error()?;
+        self.flush_pending = true;
+        self.forward()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.take_error()?;
+        self.forward()?;
+        if self.flush_pending || !self.pending.is_empty() {
+            return Ok(0);
+        }
+        self.inner.check_write()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for TranscodingOutputStream {
+    async fn ready(&mut self) {
+        loop {
+            if self.error.is_some() {
+                return;
+            }
+            if let Err(e) = self.forward() {
+                self.error = Some(e);
+                return;
+            }
+            let drained = self.pending.is_empty() && !self.flush_pending;
+            self.inner.ready().await;
+            if drained {
+                return;
+            }
+        }
+    }
+}
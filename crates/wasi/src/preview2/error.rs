@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::fmt;
 use std::marker;
+use wasmtime::Snapshot;
 
 /// An error returned from the `proc_exit` host syscall.
 ///
@@ -17,6 +18,28 @@ impl fmt::Display for I32Exit {
 
 impl std::error::Error for I32Exit {}
 
+/// What to do when the guest calls `exit`, as decided by a handler installed
+/// with [`WasiCtxBuilder::with_exit_handler`](crate::preview2::WasiCtxBuilder::with_exit_handler).
+pub enum ExitBehavior {
+    /// Return an [`I32Exit`] error from `exit`, which unwinds the guest. This
+    /// is the behavior when no handler is installed.
+    Propagate,
+    /// Return normally from `exit`, leaving the store usable. Note that most
+    /// guests do not expect `exit` to return and will trap right after it,
+    /// and preview1's `proc_exit`, which cannot return, traps instead.
+    Ignore,
+    /// Like [`ExitBehavior::Propagate`], but also request a [`Snapshot`] of the
+    /// exiting instance.
+    ///
+    /// Host functions have no access to the calling instance, so the snapshot
+    /// cannot be taken within `exit` itself. Instead the callback is stored
+    /// in the [`WasiCtx`](crate::preview2::WasiCtx) and should be taken with
+    /// [`WasiCtx::take_exit_snapshot_handler`](crate::preview2::WasiCtx::take_exit_snapshot_handler)
+    /// and invoked by the embedder once the [`I32Exit`] error has been
+    /// returned from the call into wasm, before the instance is modified.
+    SnapshotAndPropagate(Box<dyn Fn(Snapshot) + Send + Sync>),
+}
+
 /// A helper error type used by many other modules through type aliases.
 ///
 /// This type is an `Error` itself and is intended to be a representation of
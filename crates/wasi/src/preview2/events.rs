@@ -0,0 +1,159 @@
+use crate::preview2::{HostInputStream, StdinStream, StreamResult, Subscribe};
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+
+/// An event injected into a running component by the host with
+/// [`WasiView::emit_event`](crate::preview2::WasiView::emit_event).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WasiEvent {
+    /// Bytes to deliver on stdin ahead of any input from the configured
+    /// stdin stream.
+    Stdin(Vec<u8>),
+    /// Moves the monotonic clock forward by the given duration, firing
+    /// monotonic clock pollables whose deadline is reached as a result.
+    MonotonicAlarm(Duration),
+    /// An event for a host-defined source identified by `fd`, which custom
+    /// host interfaces read with
+    /// [`WasiCtx::take_custom_events`](crate::preview2::WasiCtx::take_custom_events).
+    Custom {
+        /// The event source.
+        fd: u32,
+        /// The event data.
+        payload: Vec<u8>,
+    },
+}
+
+/// The queues of events injected into a [`WasiCtx`](crate::preview2::WasiCtx).
+///
+/// Shared with the stdin streams and monotonic clock pollables of the
+/// context, which observe new events as soon as they are emitted.
+pub(crate) struct EventQueue {
+    stdin: Mutex<VecDeque<u8>>,
+    stdin_ready: Notify,
+    clock_advance: watch::Sender<Duration>,
+    custom: Mutex<HashMap<u32, VecDeque<Vec<u8>>>>,
+}
+
+impl EventQueue {
+    pub(crate) fn new() -> Arc<EventQueue> {
+        Arc::new(EventQueue {
+            stdin: Mutex::new(VecDeque::new()),
+            stdin_ready: Notify::new(),
+            clock_advance: watch::channel(Duration::ZERO).0,
+            custom: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) fn push_stdin(&self, bytes: Vec<u8>) {
+        self.stdin.lock().unwrap().extend(bytes);
+        self.stdin_ready.notify_waiters();
+    }
+
+    /// Moves monotonic clock pollables forward by `by`.
+    pub(crate) fn advance_clock(&self, by: Duration) {
+        self.clock_advance.send_modify(|advance| *advance += by);
+    }
+
+    /// Returns a receiver of the total amount by which monotonic clock
+    /// pollables have been moved forward.
+    pub(crate) fn clock_advance(&self) -> watch::Receiver<Duration> {
+        self.clock_advance.subscribe()
+    }
+
+    pub(crate) fn push_custom(&self, fd: u32, payload: Vec<u8>) {
+        self.custom
+            .lock()
+            .unwrap()
+            .entry(fd)
+            .or_default()
+            .push_back(payload);
+    }
+
+    pub(crate) fn take_custom(&self, fd: u32) -> Vec<Vec<u8>> {
+        self.custom
+            .lock()
+            .unwrap()
+            .remove(&fd)
+            .map(Vec::from)
+            .unwrap_or_default()
+    }
+}
+
+/// Standard input which delivers bytes injected with [`WasiEvent::Stdin`]
+/// before those of the wrapped stdin.
+pub(crate) struct EventStdin {
+    inner: Arc<dyn StdinStream>,
+    events: Arc<EventQueue>,
+}
+
+impl EventStdin {
+    pub(crate) fn new(inner: Arc<dyn StdinStream>, events: Arc<EventQueue>) -> Self {
+        Self { inner, events }
+    }
+}
+
+impl StdinStream for EventStdin {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(EventInputStream {
+            inner: self.inner.stream(),
+            events: Arc::clone(&self.events),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.inner.isatty()
+    }
+
+    fn fork(&self) -> Option<Box<dyn StdinStream>> {
+        let inner = self.inner.fork()?;
+        Some(Box::new(EventStdin::new(
+            inner.into(),
+            Arc::clone(&self.events),
+        )))
+    }
+}
+
+struct EventInputStream {
+    inner: Box<dyn HostInputStream>,
+    events: Arc<EventQueue>,
+}
+
+#[async_trait::async_trait]
+impl HostInputStream for EventInputStream {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        {
+            let mut injected = self.events.stdin.lock().unwrap();
+            if !injected.is_empty() {
+                let n = size.min(injected.len());
+                return Ok(injected.drain(..n).collect::<Vec<_>>().into());
+            }
+        }
+        self.inner.read(size)
+    }
+
+    fn num_ready_bytes(&self) -> usize {
+        self.events.stdin.lock().unwrap().len() + self.inner.num_ready_bytes()
+    }
+
+    fn drain_pending(&mut self) -> Vec<u8> {
+        let mut pending = Vec::from(std::mem::take(&mut *self.events.stdin.lock().unwrap()));
+        pending.extend(self.inner.drain_pending());
+        pending
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for EventInputStream {
+    async fn ready(&mut self) {
+        let injected = self.events.stdin_ready.notified();
+        if !self.events.stdin.lock().unwrap().is_empty() {
+            return;
+        }
+        let inner = self.inner.ready();
+        futures::pin_mut!(injected, inner);
+        futures::future::select(injected, inner).await;
+    }
+}
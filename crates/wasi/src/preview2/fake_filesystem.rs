@@ -0,0 +1,411 @@
+use crate::preview2::{DirPerms, FilePerms};
+use cap_std::ambient_authority;
+use cap_std::fs::{Dir, OpenOptions};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A description of a small filesystem tree, used to give a guest a
+/// deterministic root directory through
+/// [`WasiCtxBuilder::fake_filesystem`](crate::preview2::WasiCtxBuilder::fake_filesystem).
+///
+/// The tree is not kept in memory: all file access in this crate goes
+/// through `cap-std`, which only operates on directories of the host. It is
+/// written to a new temporary directory with a random name when the context
+/// is built, and that directory is removed again when the context is
+/// dropped. The current state of the tree, including any modifications made
+/// by the guest, can be read back with
+/// [`WasiCtx::fake_filesystem`](crate::preview2::WasiCtx::fake_filesystem)
+/// and used to populate a new context.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FakeFilesystem {
+    entries: Vec<FakeEntry>,
+}
+
+/// A single entry of a [`FakeFilesystem`].
+///
+/// Paths are relative to the root of the filesystem and use `/` as the
+/// separator. Parent directories are created as necessary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FakeEntry {
+    /// A regular file. `mode` holds the Unix permission bits of the file and
+    /// is ignored on other platforms.
+    File {
+        path: String,
+        content: Vec<u8>,
+        mode: u32,
+    },
+    /// A directory.
+    Directory { path: String },
+}
+
+impl FakeFilesystem {
+    /// Creates an empty filesystem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file with `content` and mode `0o644` at `path`.
+    pub fn file(&mut self, path: impl Into<String>, content: impl Into<Vec<u8>>) -> &mut Self {
+        self.entries.push(FakeEntry::File {
+            path: path.into(),
+            content: content.into(),
+            mode: 0o644,
+        });
+        self
+    }
+
+    /// Adds an empty directory at `path`.
+    pub fn directory(&mut self, path: impl Into<String>) -> &mut Self {
+        self.entries
+            .push(FakeEntry::Directory { path: path.into() });
+        self
+    }
+
+    /// Returns the entries of this filesystem.
+    pub fn entries(&self) -> &[FakeEntry] {
+        &self.entries
+    }
+
+    /// Writes this filesystem into a new temporary directory on the host.
+    pub(crate) fn materialize(&self) -> io::Result<FakeRoot> {
+        let root = FakeRoot::create()?;
+        self.write_into(&root)?;
+        Ok(root)
+    }
+
+    /// Writes this filesystem into a new temporary directory on the host,
+    /// as the writable layer of an overlay of `base`.
+    pub(crate) fn materialize_over(&self, base: Dir) -> io::Result<OverlayDir> {
+        let root = FakeRoot::create()?;
+        self.write_into(&root)?;
+        Ok(OverlayDir {
+            root,
+            base: Arc::new(base),
+        })
+    }
+
+    fn write_into(&self, root: &FakeRoot) -> io::Result<()> {
+        for entry in self.entries.iter() {
+            match entry {
+                FakeEntry::Directory { path } => {
+                    root.dir.create_dir_all(relative_path(path)?)?;
+                }
+                FakeEntry::File {
+                    path,
+                    content,
+                    mode,
+                } => {
+                    let path = relative_path(path)?;
+                    if let Some(parent) = path.parent() {
+                        root.dir.create_dir_all(parent)?;
+                    }
+                    root.dir.write(&path, content)?;
+                    set_mode(&root.dir, &path, *mode)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<Vec<FakeEntry>> for FakeFilesystem {
+    fn from(entries: Vec<FakeEntry>) -> Self {
+        Self { entries }
+    }
+}
+
+/// The temporary host directory a [`FakeFilesystem`] was materialized in,
+/// which is removed on drop.
+pub(crate) struct FakeRoot {
+    dir: cap_tempfile::TempDir,
+}
+
+impl FakeRoot {
+    fn create() -> io::Result<FakeRoot> {
+        Ok(FakeRoot {
+            dir: cap_tempfile::TempDir::new(ambient_authority())?,
+        })
+    }
+
+    /// Opens a new handle to this directory, for preopening it.
+    pub(crate) fn dir(&self) -> io::Result<Dir> {
+        self.dir.try_clone()
+    }
+
+    /// Reads the current contents of this directory back into a
+    /// [`FakeFilesystem`], with entries sorted by path.
+    pub(crate) fn capture(&self) -> io::Result<FakeFilesystem> {
+        let mut entries = Vec::new();
+        capture_dir(&self.dir, "", &mut entries)?;
+        Ok(FakeFilesystem { entries })
+    }
+
+    /// Copies this directory into a new temporary directory on the host,
+    /// recording the copy in `copies`.
+    pub(crate) fn copy(&self, copies: &mut CopiedRoots) -> io::Result<FakeRoot> {
+        let copy = FakeRoot::create()?;
+        self.capture()?.write_into(&copy)?;
+        copies.add(self, &copy)?;
+        Ok(copy)
+    }
+}
+
+/// The temporary directories copied for a context created with
+/// [`WasiCtx::fork`](crate::preview2::WasiCtx::fork), used to move the
+/// handles the fork inherits into the copies.
+///
+/// Entries are matched by their device and inode numbers, so a handle is
+/// moved as long as the entry it refers to is still linked into the
+/// directory which was copied.
+#[derive(Default)]
+pub(crate) struct CopiedRoots {
+    entries: HashMap<(u64, u64), (usize, PathBuf)>,
+    copies: Vec<Dir>,
+}
+
+impl CopiedRoots {
+    /// Records that `copy` is a copy of `root`.
+    fn add(&mut self, root: &FakeRoot, copy: &FakeRoot) -> io::Result<()> {
+        let index = self.copies.len();
+        self.copies.push(copy.dir()?);
+        self.index_dir(index, &root.dir, PathBuf::new())
+    }
+
+    fn index_dir(&mut self, index: usize, dir: &Dir, path: PathBuf) -> io::Result<()> {
+        self.entries
+            .insert(inode(&dir.dir_metadata()?), (index, path.clone()));
+        for child in dir.entries()? {
+            let child = child?;
+            let child_path = path.join(child.file_name());
+            let metadata = child.metadata()?;
+            if metadata.is_dir() {
+                self.index_dir(index, &child.open_dir()?, child_path)?;
+            } else {
+                self.entries.insert(inode(&metadata), (index, child_path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the copy of the directory and the path within it of the
+    /// entry described by `metadata`, if it was copied.
+    fn find(&self, metadata: &cap_std::fs::Metadata) -> Option<(&Dir, &Path)> {
+        let (index, path) = self.entries.get(&inode(metadata))?;
+        Some((&self.copies[*index], path))
+    }
+
+    /// Duplicates `dir` for the fork, opening its copy if it is in a copied
+    /// directory.
+    pub(crate) fn dir(&self, dir: &crate::preview2::Dir) -> io::Result<crate::preview2::Dir> {
+        let copy = match self.find(&dir.dir.dir_metadata()?) {
+            Some((copy, path)) if path.as_os_str().is_empty() => copy.try_clone()?,
+            Some((copy, path)) => copy.open_dir(path)?,
+            None => return dir.try_clone(),
+        };
+        Ok(crate::preview2::Dir {
+            dir: Arc::new(copy),
+            ..dir.clone()
+        })
+    }
+
+    /// Duplicates `file` for the fork, opening its copy with the same
+    /// permissions if it is in a copied directory.
+    pub(crate) fn file(&self, file: &crate::preview2::File) -> io::Result<crate::preview2::File> {
+        let (copy, path) = match self.find(&file.file.metadata()?) {
+            Some(found) => found,
+            None => return file.try_clone(),
+        };
+        let write = file.perms.contains(FilePerms::WRITE);
+        let mut options = OpenOptions::new();
+        options
+            .read(file.perms.contains(FilePerms::READ) || !write)
+            .write(write);
+        Ok(crate::preview2::File {
+            file: Arc::new(copy.open_with(path, &options)?),
+            perms: file.perms,
+            quota: file.quota.clone(),
+        })
+    }
+}
+
+/// Returns the device and inode numbers of the entry described by
+/// `metadata`.
+fn inode(metadata: &cap_std::fs::Metadata) -> (u64, u64) {
+    use cap_fs_ext::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+/// Converts a `/`-separated path of a [`FakeEntry`] into a path relative to
+/// the root of the filesystem.
+fn relative_path(path: &str) -> io::Result<PathBuf> {
+    let mut result = PathBuf::new();
+    for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        if segment == ".." {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("fake filesystem path `{path}` must not contain `..`"),
+            ));
+        }
+        result.push(segment);
+    }
+    Ok(result)
+}
+
+/// A directory combining a read-only base directory of the host with a
+/// writable layer, installed with
+/// [`WasiCtxBuilder::preopen_overlay_dir`](crate::preview2::WasiCtxBuilder::preopen_overlay_dir).
+///
+/// The writable layer is materialized in a temporary host directory like a
+/// [`FakeFilesystem`], while reads of entries which are not in it fall
+/// through to the base. Entries of the base are copied into the writable
+/// layer before they are modified, so the guest's writes never reach the
+/// base.
+pub(crate) struct OverlayDir {
+    root: FakeRoot,
+    base: Arc<Dir>,
+}
+
+impl OverlayDir {
+    /// Returns the directory to preopen for this overlay.
+    pub(crate) fn preopen(
+        &self,
+        perms: DirPerms,
+        file_perms: FilePerms,
+    ) -> io::Result<crate::preview2::Dir> {
+        let mut dir = crate::preview2::Dir::new(self.root.dir()?, perms, file_perms);
+        dir.base = Some(self.base.clone());
+        Ok(dir)
+    }
+
+    /// Copies the writable layer of this overlay into a new temporary
+    /// directory, over the same base, recording the copy in `copies`.
+    pub(crate) fn copy(&self, copies: &mut CopiedRoots) -> io::Result<OverlayDir> {
+        Ok(OverlayDir {
+            root: self.root.copy(copies)?,
+            base: self.base.clone(),
+        })
+    }
+
+    /// Reads the entries of this directory which differ from the base back
+    /// into a [`FakeFilesystem`]: files and directories which are new, and
+    /// files whose contents changed.
+    pub(crate) fn capture_changes(&self) -> io::Result<FakeFilesystem> {
+        let mut entries = self.root.capture()?.entries;
+        entries.retain(|entry| match entry {
+            FakeEntry::Directory { path } => {
+                !self.base.metadata(path).map_or(false, |m| m.is_dir())
+            }
+            FakeEntry::File { path, content, .. } => {
+                self.base.read(path).ok().as_ref() != Some(content)
+            }
+        });
+        Ok(FakeFilesystem { entries })
+    }
+}
+
+/// A directory presenting the merged contents of several host directories,
+/// installed with
+/// [`WasiCtxBuilder::preopen_union`](crate::preview2::WasiCtxBuilder::preopen_union).
+///
+/// Like an [`OverlayDir`], the union is materialized in a temporary host
+/// directory, into which the directories are copied when the context is
+/// built.
+pub(crate) struct UnionDir {
+    root: FakeRoot,
+}
+
+impl UnionDir {
+    /// Copies `dirs` into a new temporary directory on the host, in order.
+    /// Subdirectories present in several of them are merged, and other
+    /// entries are taken from the first directory which has them.
+    pub(crate) fn materialize(dirs: &[Dir]) -> io::Result<UnionDir> {
+        let root = FakeRoot::create()?;
+        for dir in dirs {
+            merge_dir(dir, &root.dir)?;
+        }
+        Ok(UnionDir { root })
+    }
+
+    pub(crate) fn dir(&self) -> io::Result<Dir> {
+        self.root.dir()
+    }
+    /// Copies this union into a new temporary directory, recording the copy
+    /// in `copies`.
+    pub(crate) fn copy(&self, copies: &mut CopiedRoots) -> io::Result<UnionDir> {
+        Ok(UnionDir {
+            root: self.root.copy(copies)?,
+        })
+    }
+}
+
+/// Copies the directories and regular files beneath `src` into `dst`,
+/// keeping the entries already in `dst` where both have one of the same name.
+fn merge_dir(src: &Dir, dst: &Dir) -> io::Result<()> {
+    for entry in src.entries()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let file_type = entry.file_type()?;
+        let existing = dst.symlink_metadata(&name).ok();
+        if file_type.is_dir() {
+            match existing {
+                Some(existing) if !existing.is_dir() => continue,
+                Some(_) => {}
+                None => dst.create_dir(&name)?,
+            }
+            merge_dir(&entry.open_dir()?, &dst.open_dir(&name)?)?;
+        } else if file_type.is_file() && existing.is_none() {
+            dst.write(&name, src.read(&name)?)?;
+        }
+    }
+    Ok(())
+}
+
+fn capture_dir(dir: &Dir, prefix: &str, entries: &mut Vec<FakeEntry>) -> io::Result<()> {
+    let mut children = dir.entries()?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|e| e.file_name());
+    for child in children {
+        let name = child.file_name();
+        let display = name.to_string_lossy();
+        let path = if prefix.is_empty() {
+            display.into_owned()
+        } else {
+            format!("{prefix}/{display}")
+        };
+        let metadata = child.metadata()?;
+        if metadata.is_dir() {
+            entries.push(FakeEntry::Directory { path: path.clone() });
+            capture_dir(&child.open_dir()?, &path, entries)?;
+        } else {
+            entries.push(FakeEntry::File {
+                content: dir.read(&name)?,
+                mode: mode(&metadata),
+                path,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(dir: &Dir, path: &std::path::Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    dir.set_permissions(path, cap_std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_dir: &Dir, _path: &std::path::Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode(metadata: &cap_std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &cap_std::fs::Metadata) -> u32 {
+    0o644
+}
@@ -1,7 +1,8 @@
 use crate::preview2::bindings::filesystem::types;
+use crate::preview2::quota::{QuotaExceeded, QuotaTracker};
 use crate::preview2::{
-    spawn_blocking, AbortOnDropJoinHandle, HostOutputStream, StreamError, Subscribe, TableError,
-    TrappableError,
+    overlay, policy, spawn_blocking, AbortOnDropJoinHandle, CapabilityPolicy, HostOutputStream,
+    StreamError, Subscribe, TableError, TrappableError,
 };
 use anyhow::anyhow;
 use bytes::{Bytes, BytesMut};
@@ -25,6 +26,12 @@ impl From<io::Error> for FsError {
     }
 }
 
+impl From<QuotaExceeded> for FsError {
+    fn from(_: QuotaExceeded) -> Self {
+        types::ErrorCode::Quota.into()
+    }
+}
+
 pub enum Descriptor {
     File(File),
     Dir(Dir),
@@ -75,6 +82,9 @@ pub struct File {
     /// [`spawn_blocking`]: Self::spawn_blocking
     pub file: Arc<cap_std::fs::File>,
     pub perms: FilePerms,
+    /// The quota of the preopened directory the file was opened from, if
+    /// any, against which its growth is counted.
+    pub(crate) quota: Option<Arc<QuotaTracker>>,
 }
 
 impl File {
@@ -82,7 +92,46 @@ impl File {
         Self {
             file: Arc::new(file),
             perms,
+            quota: None,
+        }
+    }
+
+    /// Counts the growth of the file to `new_len` bytes against its quota,
+    /// failing if the quota would be exceeded.
+    pub(crate) fn grow_to(&self, new_len: u64) -> FsResult<()> {
+        if let Some(quota) = &self.quota {
+            let len = self.file.metadata()?.len();
+            quota.grow(len, new_len)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the size of the file to `new_len` bytes, counting its growth
+    /// against its quota, or crediting its shrinking once it is truncated.
+    pub(crate) async fn set_len(&self, new_len: u64) -> FsResult<()> {
+        let len = match &self.quota {
+            Some(quota) => {
+                let len = self.file.metadata()?.len();
+                quota.grow(len, new_len)?;
+                Some((quota, len))
+            }
+            None => None,
+        };
+        self.spawn_blocking(move |f| f.set_len(new_len)).await?;
+        if let Some((quota, len)) = len {
+            quota.shrink(len, new_len);
         }
+        Ok(())
+    }
+
+    /// Duplicates the underlying file handle, as with
+    /// [`cap_std::fs::File::try_clone`].
+    pub(crate) fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Self {
+            file: Arc::new(self.file.try_clone()?),
+            perms: self.perms,
+            quota: self.quota.clone(),
+        })
     }
 
     /// Spawn a task on tokio's blocking thread for performing blocking
@@ -110,6 +159,21 @@ pub struct Dir {
     pub dir: Arc<cap_std::fs::Dir>,
     pub perms: DirPerms,
     pub file_perms: FilePerms,
+    /// The guest path of this directory, against which `policy` is evaluated.
+    ///
+    /// Path rules cannot be evaluated for a directory whose guest path is
+    /// unknown, so if this is empty while `policy` has path rules, access to
+    /// all paths beneath it is denied.
+    pub path: String,
+    pub policy: Arc<CapabilityPolicy>,
+    /// The quota of the preopened directory this directory was opened from,
+    /// if any.
+    pub(crate) quota: Option<Arc<QuotaTracker>>,
+    /// For a directory of an overlay, the corresponding directory of the
+    /// read-only base. Entries of `dir` hide those of the base at the same
+    /// path, and all other paths are looked up in the base. Entries of the
+    /// base are copied into `dir` before they are modified.
+    pub(crate) base: Option<Arc<cap_std::fs::Dir>>,
 }
 
 impl Dir {
@@ -118,7 +182,61 @@ impl Dir {
             dir: Arc::new(dir),
             perms,
             file_perms,
+            path: String::new(),
+            policy: Arc::new(CapabilityPolicy::new()),
+            quota: None,
+            base: None,
+        }
+    }
+
+    /// Duplicates the underlying directory handle, as with
+    /// [`cap_std::fs::Dir::try_clone`].
+    pub(crate) fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Dir {
+            dir: Arc::new(self.dir.try_clone()?),
+            ..self.clone()
+        })
+    }
+
+    /// Set the guest path of this directory and the policy governing access
+    /// to paths beneath it.
+    pub(crate) fn with_policy(mut self, path: String, policy: Arc<CapabilityPolicy>) -> Self {
+        self.path = path;
+        self.policy = policy;
+        self
+    }
+
+    /// Returns the guest path of `path`, relative to this directory.
+    pub(crate) fn child_path(&self, path: &str) -> String {
+        policy::join(&self.path, path)
+    }
+
+    /// Fails with `NotPermitted` if the capability policy denies access to
+    /// `path`, relative to this directory, or if it has path rules but the
+    /// guest path of this directory is unknown.
+    pub(crate) fn check_policy(&self, path: &str) -> FsResult<()> {
+        let unknown_path = self.path.is_empty() && self.policy.has_path_rules();
+        if !unknown_path && self.policy.is_path_allowed(&self.child_path(path)) {
+            Ok(())
+        } else {
+            Err(types::ErrorCode::NotPermitted.into())
+        }
+    }
+
+    /// Like [`Dir::check_policy`], but also checks the path that `path`
+    /// refers to once symlinks within this directory are resolved, so that a
+    /// symlink can't be used to reach a denied path. The final component of
+    /// `path` is only resolved if `follow` is set.
+    pub(crate) async fn check_policy_resolved(&self, path: &str, follow: bool) -> FsResult<()> {
+        self.check_policy(path)?;
+        if !self.policy.has_path_rules() {
+            return Ok(());
         }
+        let path = path.to_owned();
+        let resolved = self
+            .spawn_blocking_layered(move |d, base| policy::resolve(d, base, &path, follow))
+            .await;
+        self.check_policy(&resolved)
     }
 
     /// Spawn a task on tokio's blocking thread for performing blocking
@@ -131,6 +249,73 @@ impl Dir {
         let d = self.dir.clone();
         spawn_blocking(move || body(&d)).await
     }
+
+    /// Copies the entry at `path` from the base of an overlay into this
+    /// directory before it is modified, as described for [`Dir::base`].
+    pub(crate) async fn copy_up(&self, path: &str) -> FsResult<()> {
+        let path = path.to_owned();
+        self.copy_up_with(move |d, base| overlay::copy_up(d, base, &path))
+            .await
+    }
+
+    /// Copies the parent directory of `path` from the base of an overlay
+    /// into this directory, so that an entry can be created at `path`.
+    pub(crate) async fn copy_up_parent(&self, path: &str) -> FsResult<()> {
+        let path = path.to_owned();
+        self.copy_up_with(move |d, base| overlay::copy_up_parent(d, base, &path))
+            .await
+    }
+
+    /// Prepares the creation of a new entry at `path`, failing with `exist`
+    /// if the base of an overlay already has one.
+    pub(crate) async fn prepare_create(&self, path: &str) -> FsResult<()> {
+        let path = path.to_owned();
+        self.copy_up_with(move |d, base| {
+            if overlay::in_base(base, &path) {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            overlay::copy_up_parent(d, base, &path)
+        })
+        .await
+    }
+
+    /// Fails with `read-only` if the base of an overlay has an entry at
+    /// `path`, which the guest cannot remove or rename.
+    pub(crate) async fn check_not_in_base(&self, path: &str) -> FsResult<()> {
+        let path = path.to_owned();
+        if self.base.is_some()
+            && self
+                .spawn_blocking_layered(move |_, base| overlay::in_base(base, &path))
+                .await
+        {
+            return Err(types::ErrorCode::ReadOnly.into());
+        }
+        Ok(())
+    }
+
+    async fn copy_up_with(
+        &self,
+        body: impl FnOnce(&cap_std::fs::Dir, Option<&cap_std::fs::Dir>) -> io::Result<()>
+            + Send
+            + 'static,
+    ) -> FsResult<()> {
+        if self.base.is_some() {
+            self.spawn_blocking_layered(body).await?;
+        }
+        Ok(())
+    }
+
+    /// Like [`Dir::spawn_blocking`], but also passes the base directory of
+    /// an overlay, if this is one.
+    pub(crate) async fn spawn_blocking_layered<F, R>(&self, body: F) -> R
+    where
+        F: FnOnce(&cap_std::fs::Dir, Option<&cap_std::fs::Dir>) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let d = self.dir.clone();
+        let base = self.base.clone();
+        spawn_blocking(move || body(&d, base.as_deref())).await
+    }
 }
 
 pub struct FileInputStream {
@@ -183,6 +368,7 @@ pub(crate) struct FileOutputStream {
     file: Arc<cap_std::fs::File>,
     mode: FileOutputMode,
     state: OutputState,
+    quota: Option<Arc<QuotaTracker>>,
 }
 
 enum OutputState {
@@ -201,6 +387,7 @@ impl FileOutputStream {
             file,
             mode: FileOutputMode::Position(position),
             state: OutputState::Ready,
+            quota: None,
         }
     }
     pub fn append(file: Arc<cap_std::fs::File>) -> Self {
@@ -208,8 +395,37 @@ impl FileOutputStream {
             file,
             mode: FileOutputMode::Append,
             state: OutputState::Ready,
+            quota: None,
         }
     }
+
+    /// Counts the growth of the file by writes to this stream against
+    /// `quota`.
+    pub(crate) fn with_quota(mut self, quota: Option<Arc<QuotaTracker>>) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Counts the growth of the file by a write of `len` bytes against the
+    /// quota of the stream, if any.
+    fn check_quota(&self, len: usize) -> Result<(), StreamError> {
+        let quota = match &self.quota {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+        let file_len = self
+            .file
+            .metadata()
+            .map_err(|e| StreamError::LastOperationFailed(e.into()))?
+            .len();
+        let start = match self.mode {
+            FileOutputMode::Position(p) => p,
+            FileOutputMode::Append => file_len,
+        };
+        quota
+            .grow(file_len, start.saturating_add(len as u64))
+            .map_err(|e| StreamError::LastOperationFailed(e.into()))
+    }
 }
 
 // FIXME: configurable? determine from how much space left in file?
@@ -229,6 +445,8 @@ impl HostOutputStream for FileOutputStream {
             }
         }
 
+        self.check_quota(buf.len())?;
+
         let f = Arc::clone(&self.file);
         let m = self.mode;
         let task = spawn_blocking(move || match m {
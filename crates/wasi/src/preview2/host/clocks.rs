@@ -5,10 +5,14 @@ use crate::preview2::bindings::{
     clocks::timezone::{self, TimezoneDisplay},
     clocks::wall_clock::{self, Datetime},
 };
+use crate::preview2::metrics::Counter;
 use crate::preview2::poll::{subscribe, Subscribe};
-use crate::preview2::{Pollable, WasiView};
+use crate::preview2::trace::TraceValue;
+use crate::preview2::{DeadlineBehavior, DeadlineExceeded, Pollable, WasiView};
 use cap_std::time::SystemTime;
+use futures::future::Either;
 use std::time::Duration;
+use tokio::sync::watch;
 use wasmtime::component::Resource;
 
 impl TryFrom<SystemTime> for Datetime {
@@ -27,7 +31,33 @@ impl TryFrom<SystemTime> for Datetime {
 
 impl<T: WasiView> wall_clock::Host for T {
     fn now(&mut self) -> anyhow::Result<Datetime> {
-        let now = self.ctx().wall_clock.now();
+        self.ctx().metrics.increment(Counter::Clock);
+        let mut now = self.ctx().wall_clock.now();
+        if let Some(deadline) = &self.ctx().wall_clock_deadline {
+            if now >= deadline.at {
+                match &deadline.behavior {
+                    DeadlineBehavior::ReturnError => {
+                        let err = TraceValue::Error(DeadlineExceeded.to_string());
+                        self.ctx().trace("wall-clock.now", &[], &err);
+                        return Err(DeadlineExceeded.into());
+                    }
+                    DeadlineBehavior::ReturnEpoch(time) => {
+                        now = time
+                            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                            .unwrap_or_default();
+                    }
+                    DeadlineBehavior::CallCallback(callback) => callback(),
+                }
+            }
+        }
+        self.ctx().trace(
+            "wall-clock.now",
+            &[],
+            &TraceValue::List(vec![
+                TraceValue::U64(now.as_secs()),
+                TraceValue::U64(now.subsec_nanos().into()),
+            ]),
+        );
         Ok(Datetime {
             seconds: now.as_secs(),
             nanoseconds: now.subsec_nanos(),
@@ -45,7 +75,24 @@ impl<T: WasiView> wall_clock::Host for T {
 
 impl<T: WasiView> monotonic_clock::Host for T {
     fn now(&mut self) -> anyhow::Result<Instant> {
-        Ok(self.ctx().monotonic_clock.now())
+        self.ctx().metrics.increment(Counter::Clock);
+        let mut now = self.ctx().monotonic_now();
+        if let Some(deadline) = &self.ctx().monotonic_clock_deadline {
+            if now >= deadline.at {
+                match &deadline.behavior {
+                    DeadlineBehavior::ReturnError => {
+                        let err = TraceValue::Error(DeadlineExceeded.to_string());
+                        self.ctx().trace("monotonic-clock.now", &[], &err);
+                        return Err(DeadlineExceeded.into());
+                    }
+                    DeadlineBehavior::ReturnEpoch(_) => now = deadline.at,
+                    DeadlineBehavior::CallCallback(callback) => callback(),
+                }
+            }
+        }
+        self.ctx()
+            .trace("monotonic-clock.now", &[], &TraceValue::U64(now));
+        Ok(now)
     }
 
     fn resolution(&mut self) -> anyhow::Result<Instant> {
@@ -53,7 +100,7 @@ impl<T: WasiView> monotonic_clock::Host for T {
     }
 
     fn subscribe(&mut self, when: Instant, absolute: bool) -> anyhow::Result<Resource<Pollable>> {
-        let clock_now = self.ctx().monotonic_clock.now();
+        let clock_now = self.ctx().monotonic_now();
         let duration = if absolute {
             Duration::from_nanos(when - clock_now)
         } else {
@@ -65,17 +112,48 @@ impl<T: WasiView> monotonic_clock::Host for T {
         // NB: this resource created here is not actually exposed to wasm, it's
         // only an internal implementation detail used to match the signature
         // expected by `subscribe`.
-        let sleep = self.table_mut().push(Sleep(deadline))?;
+        let advance = self.ctx().events.clock_advance();
+        let baseline = *advance.borrow();
+        let sleep = self.table_mut().push(Sleep {
+            deadline,
+            advance,
+            baseline,
+        })?;
         subscribe(self.table_mut(), sleep)
     }
 }
 
-struct Sleep(tokio::time::Instant);
+/// A subscription to the monotonic clock, which is moved forward by
+/// [`WasiEvent::MonotonicAlarm`](crate::preview2::WasiEvent::MonotonicAlarm)
+/// events emitted after it was created.
+struct Sleep {
+    deadline: tokio::time::Instant,
+    advance: watch::Receiver<Duration>,
+    baseline: Duration,
+}
 
 #[async_trait::async_trait]
 impl Subscribe for Sleep {
     async fn ready(&mut self) {
-        tokio::time::sleep_until(self.0).await;
+        loop {
+            let advanced = self
+                .advance
+                .borrow_and_update()
+                .saturating_sub(self.baseline);
+            let deadline = self
+                .deadline
+                .checked_sub(advanced)
+                .unwrap_or_else(tokio::time::Instant::now);
+            let sleep = tokio::time::sleep_until(deadline);
+            let changed = self.advance.changed();
+            futures::pin_mut!(sleep, changed);
+            match futures::future::select(sleep, changed).await {
+                Either::Left(_) => return,
+                Either::Right((Ok(()), _)) => continue,
+                // The context is gone, so the clock can't move anymore.
+                Either::Right((Err(_), sleep)) => return sleep.await,
+            }
+        }
     }
 }
 
@@ -1,9 +1,34 @@
 use crate::preview2::bindings::cli::environment;
 use crate::preview2::WasiView;
+use anyhow::Context;
 
 impl<T: WasiView> environment::Host for T {
     fn get_environment(&mut self) -> anyhow::Result<Vec<(String, String)>> {
-        Ok(self.ctx().env.clone())
+        let ctx = self.ctx();
+        // Only variables in the namespace are visible, with the namespace
+        // stripped from their names. A variable named like the namespace
+        // itself would have no name left, so it is hidden too.
+        let namespace = ctx.env_namespace.as_deref().unwrap_or("");
+        fn visible<'a>(namespace: &str, key: &'a str) -> Option<&'a str> {
+            key.strip_prefix(namespace)
+                .filter(|name| namespace.is_empty() || !name.is_empty())
+        }
+        let mut env = ctx
+            .env
+            .iter()
+            .filter_map(|(key, value)| Some((visible(namespace, key)?.to_string(), value.clone())))
+            .collect::<Vec<_>>();
+        for (key, provider) in ctx.env_secrets.iter() {
+            let name = match visible(namespace, key) {
+                Some(name) => name,
+                None => continue,
+            };
+            let value = provider
+                .get()
+                .with_context(|| format!("failed to get the secret `{key}`"))?;
+            env.push((name.to_string(), value));
+        }
+        Ok(env)
     }
     fn get_arguments(&mut self) -> anyhow::Result<Vec<String>> {
         Ok(self.ctx().args.clone())
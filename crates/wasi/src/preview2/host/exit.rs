@@ -1,4 +1,4 @@
-use crate::preview2::{bindings::cli::exit, I32Exit, WasiView};
+use crate::preview2::{bindings::cli::exit, trace::TraceValue, ExitBehavior, I32Exit, WasiView};
 
 impl<T: WasiView> exit::Host for T {
     fn exit(&mut self, status: Result<(), ()>) -> anyhow::Result<()> {
@@ -6,6 +6,22 @@ impl<T: WasiView> exit::Host for T {
             Ok(()) => 0,
             Err(()) => 1,
         };
+        self.ctx().trace(
+            "exit.exit",
+            &[TraceValue::U64(status as u64)],
+            &TraceValue::Null,
+        );
+        let behavior = match &self.ctx().exit_handler {
+            Some(handler) => handler(status),
+            None => ExitBehavior::Propagate,
+        };
+        match behavior {
+            ExitBehavior::Propagate => {}
+            ExitBehavior::Ignore => return Ok(()),
+            ExitBehavior::SnapshotAndPropagate(f) => {
+                self.ctx_mut().exit_snapshot_handler = Some(f);
+            }
+        }
         Err(anyhow::anyhow!(I32Exit(status)))
     }
 }
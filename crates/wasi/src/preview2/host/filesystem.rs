@@ -6,8 +6,18 @@ use crate::preview2::bindings::filesystem::types::{
 use crate::preview2::bindings::io::streams::{InputStream, OutputStream};
 use crate::preview2::filesystem::{Descriptor, Dir, File, ReaddirIterator};
 use crate::preview2::filesystem::{FileInputStream, FileOutputStream};
-use crate::preview2::{DirPerms, FilePerms, FsError, FsResult, Table, WasiView};
+use crate::preview2::metrics::Counter;
+use crate::preview2::quota::QuotaExceeded;
+use crate::preview2::trace::TraceValue;
+use crate::preview2::{
+    overlay, policy, DirPerms, FilePerms, FsError, FsResult, ResourceKind, ResourceUsageEvent,
+    ScopePolicy, Table, WasiView,
+};
 use anyhow::Context;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::Arc;
 use wasmtime::component::Resource;
 
 mod sync;
@@ -22,6 +32,7 @@ impl<T: WasiView> preopens::Host for T {
                 .table_mut()
                 .push(Descriptor::Dir(dir))
                 .with_context(|| format!("failed to push preopen {name}"))?;
+            self.ctx().resource_created(ResourceKind::Dir, fd.rep());
             results.push((fd, name));
         }
         Ok(results)
@@ -45,6 +56,9 @@ impl<T: WasiView> types::Host for T {
         if let Some(err) = err.downcast_ref::<std::io::Error>() {
             return Ok(Some(ErrorCode::from(err)));
         }
+        if err.is::<QuotaExceeded>() {
+            return Ok(Some(ErrorCode::Quota));
+        }
 
         Ok(None)
     }
@@ -176,8 +190,7 @@ impl<T: WasiView> HostDescriptor for T {
         if !f.perms.contains(FilePerms::WRITE) {
             Err(ErrorCode::NotPermitted)?;
         }
-        f.spawn_blocking(move |f| f.set_len(size)).await?;
-        Ok(())
+        f.set_len(size).await
     }
 
     async fn set_times(
@@ -217,36 +230,20 @@ impl<T: WasiView> HostDescriptor for T {
         len: types::Filesize,
         offset: types::Filesize,
     ) -> FsResult<(Vec<u8>, bool)> {
-        use std::io::IoSliceMut;
-        use system_interface::fs::FileIoExt;
-
-        let table = self.table();
-
-        let f = table.get(&fd)?.file()?;
-        if !f.perms.contains(FilePerms::READ) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-
-        let (mut buffer, r) = f
-            .spawn_blocking(move |f| {
-                let mut buffer = vec![0; len.try_into().unwrap_or(usize::MAX)];
-                let r = f.read_vectored_at(&mut [IoSliceMut::new(&mut buffer)], offset);
-                (buffer, r)
-            })
-            .await;
-
-        let (bytes_read, state) = match r? {
-            0 => (0, true),
-            n => (n, false),
-        };
-
-        buffer.truncate(
-            bytes_read
-                .try_into()
-                .expect("bytes read into memory as u64 fits in usize"),
+        let index = fd.rep();
+        let result = untraced::UntracedDescriptor::read(self, fd, len, offset).await;
+        self.ctx().trace(
+            "descriptor.read",
+            &[
+                TraceValue::U64(index.into()),
+                TraceValue::U64(len),
+                TraceValue::U64(offset),
+            ],
+            &TraceValue::result(&result, |(bytes, eof)| {
+                TraceValue::List(vec![TraceValue::Bytes(bytes), TraceValue::Bool(*eof)])
+            }),
         );
-
-        Ok((buffer, state))
+        result
     }
 
     async fn write(
@@ -255,20 +252,20 @@ impl<T: WasiView> HostDescriptor for T {
         buf: Vec<u8>,
         offset: types::Filesize,
     ) -> FsResult<types::Filesize> {
-        use std::io::IoSlice;
-        use system_interface::fs::FileIoExt;
-
-        let table = self.table();
-        let f = table.get(&fd)?.file()?;
-        if !f.perms.contains(FilePerms::WRITE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-
-        let bytes_written = f
-            .spawn_blocking(move |f| f.write_vectored_at(&[IoSlice::new(&buf)], offset))
-            .await?;
-
-        Ok(types::Filesize::try_from(bytes_written).expect("usize fits in Filesize"))
+        let index = fd.rep();
+        // Only copy the buffer if it is going to be traced.
+        let data = self.ctx().trace.is_some().then(|| buf.clone());
+        let result = untraced::UntracedDescriptor::write(self, fd, buf, offset).await;
+        self.ctx().trace(
+            "descriptor.write",
+            &[
+                TraceValue::U64(index.into()),
+                TraceValue::Bytes(data.as_deref().unwrap_or_default()),
+                TraceValue::U64(offset),
+            ],
+            &TraceValue::result(&result, |n| TraceValue::U64(*n)),
+        );
+        result
     }
 
     async fn read_directory(
@@ -292,24 +289,42 @@ impl<T: WasiView> HostDescriptor for T {
         }
 
         let entries = d
-            .spawn_blocking(|d| {
+            .spawn_blocking_layered(|d, base| {
                 // Both `entries` and `metadata` perform syscalls, which is why they are done
                 // within this `block` call, rather than delay calculating the metadata
                 // for entries when they're demanded later in the iterator chain.
-                Ok::<_, std::io::Error>(
-                    d.entries()?
-                        .map(|entry| {
-                            let entry = entry?;
-                            let meta = entry.metadata()?;
-                            let type_ = descriptortype_from(meta.file_type());
-                            let name = entry
-                                .file_name()
-                                .into_string()
-                                .map_err(|_| ReaddirError::IllegalSequence)?;
-                            Ok(types::DirectoryEntry { type_, name })
-                        })
-                        .collect::<Vec<Result<types::DirectoryEntry, ReaddirError>>>(),
-                )
+                let read = |d: &cap_std::fs::Dir, hidden: &HashSet<OsString>| {
+                    Ok::<_, std::io::Error>(
+                        d.entries()?
+                            .filter(|entry| {
+                                entry
+                                    .as_ref()
+                                    .map_or(true, |entry| !hidden.contains(&entry.file_name()))
+                            })
+                            .map(|entry| {
+                                let entry = entry?;
+                                let meta = entry.metadata()?;
+                                let type_ = descriptortype_from(meta.file_type());
+                                let name = entry
+                                    .file_name()
+                                    .into_string()
+                                    .map_err(|_| ReaddirError::IllegalSequence)?;
+                                Ok(types::DirectoryEntry { type_, name })
+                            })
+                            .collect::<Vec<Result<types::DirectoryEntry, ReaddirError>>>(),
+                    )
+                };
+                let mut entries = read(d, &HashSet::new())?;
+                // The entries of the base of an overlay are listed after
+                // those of the overlay, except where the overlay hides them.
+                if let Some(base) = base {
+                    let hidden = d
+                        .entries()?
+                        .filter_map(|entry| Some(entry.ok()?.file_name()))
+                        .collect();
+                    entries.extend(read(base, &hidden)?);
+                }
+                Ok::<_, std::io::Error>(entries)
             })
             .await?
             .into_iter();
@@ -368,11 +383,14 @@ impl<T: WasiView> HostDescriptor for T {
         fd: Resource<types::Descriptor>,
         path: String,
     ) -> FsResult<()> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         let table = self.table();
         let d = table.get(&fd)?.dir()?;
         if !d.perms.contains(DirPerms::MUTATE) {
             return Err(ErrorCode::NotPermitted.into());
         }
+        d.check_policy_resolved(&path, false).await?;
+        d.prepare_create(&path).await?;
         d.spawn_blocking(move |d| d.create_dir(&path)).await?;
         Ok(())
     }
@@ -399,16 +417,25 @@ impl<T: WasiView> HostDescriptor for T {
         path_flags: types::PathFlags,
         path: String,
     ) -> FsResult<types::DescriptorStat> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         let table = self.table();
         let d = table.get(&fd)?.dir()?;
         if !d.perms.contains(DirPerms::READ) {
             return Err(ErrorCode::NotPermitted.into());
         }
+        d.check_policy_resolved(&path, symlink_follow(path_flags))
+            .await?;
 
         let meta = if symlink_follow(path_flags) {
-            d.spawn_blocking(move |d| d.metadata(&path)).await?
+            d.spawn_blocking_layered(move |d, base| {
+                overlay::fall_through(d, base, |d| d.metadata(&path))
+            })
+            .await?
         } else {
-            d.spawn_blocking(move |d| d.symlink_metadata(&path)).await?
+            d.spawn_blocking_layered(move |d, base| {
+                overlay::fall_through(d, base, |d| d.symlink_metadata(&path))
+            })
+            .await?
         };
         Ok(descriptorstat_from(meta))
     }
@@ -421,6 +448,7 @@ impl<T: WasiView> HostDescriptor for T {
         atim: types::NewTimestamp,
         mtim: types::NewTimestamp,
     ) -> FsResult<()> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         use cap_fs_ext::DirExt;
 
         let table = self.table();
@@ -428,6 +456,9 @@ impl<T: WasiView> HostDescriptor for T {
         if !d.perms.contains(DirPerms::MUTATE) {
             return Err(ErrorCode::NotPermitted.into());
         }
+        d.check_policy_resolved(&path, symlink_follow(path_flags))
+            .await?;
+        d.copy_up(&path).await?;
         let atim = systemtimespec_from(atim)?;
         let mtim = systemtimespec_from(mtim)?;
         if symlink_follow(path_flags) {
@@ -461,6 +492,7 @@ impl<T: WasiView> HostDescriptor for T {
         new_descriptor: Resource<types::Descriptor>,
         new_path: String,
     ) -> FsResult<()> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         let table = self.table();
         let old_dir = table.get(&fd)?.dir()?;
         if !old_dir.perms.contains(DirPerms::MUTATE) {
@@ -470,9 +502,13 @@ impl<T: WasiView> HostDescriptor for T {
         if !new_dir.perms.contains(DirPerms::MUTATE) {
             return Err(ErrorCode::NotPermitted.into());
         }
+        old_dir.check_policy_resolved(&old_path, false).await?;
+        new_dir.check_policy_resolved(&new_path, false).await?;
         if symlink_follow(old_path_flags) {
             return Err(ErrorCode::Invalid.into());
         }
+        old_dir.copy_up(&old_path).await?;
+        new_dir.prepare_create(&new_path).await?;
         let new_dir_handle = std::sync::Arc::clone(&new_dir.dir);
         old_dir
             .spawn_blocking(move |d| d.hard_link(&old_path, &new_dir_handle, &new_path))
@@ -487,118 +523,28 @@ impl<T: WasiView> HostDescriptor for T {
         path: String,
         oflags: types::OpenFlags,
         flags: types::DescriptorFlags,
-        // TODO: These are the permissions to use when creating a new file.
-        // Not implemented yet.
-        _mode: types::Modes,
+        mode: types::Modes,
     ) -> FsResult<Resource<types::Descriptor>> {
-        use cap_fs_ext::{FollowSymlinks, OpenOptionsFollowExt, OpenOptionsMaybeDirExt};
-        use system_interface::fs::{FdFlags, GetSetFdFlags};
-        use types::{DescriptorFlags, OpenFlags};
-
-        let table = self.table_mut();
-        let d = table.get(&fd)?.dir()?;
-        if !d.perms.contains(DirPerms::READ) {
-            Err(ErrorCode::NotPermitted)?;
-        }
-
-        if !d.perms.contains(DirPerms::MUTATE) {
-            if oflags.contains(OpenFlags::CREATE) || oflags.contains(OpenFlags::TRUNCATE) {
-                Err(ErrorCode::NotPermitted)?;
-            }
-            if flags.contains(DescriptorFlags::WRITE) {
-                Err(ErrorCode::NotPermitted)?;
-            }
-        }
-
-        let mut opts = cap_std::fs::OpenOptions::new();
-        opts.maybe_dir(true);
-
-        if oflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) {
-            opts.create_new(true);
-            opts.write(true);
-        } else if oflags.contains(OpenFlags::CREATE) {
-            opts.create(true);
-            opts.write(true);
-        }
-        if oflags.contains(OpenFlags::TRUNCATE) {
-            opts.truncate(true);
-        }
-        if flags.contains(DescriptorFlags::READ) {
-            opts.read(true);
-        }
-        if flags.contains(DescriptorFlags::WRITE) {
-            opts.write(true);
-        } else {
-            // If not opened write, open read. This way the OS lets us open
-            // the file, but we can use perms to reject use of the file later.
-            opts.read(true);
-        }
-        if symlink_follow(path_flags) {
-            opts.follow(FollowSymlinks::Yes);
-        } else {
-            opts.follow(FollowSymlinks::No);
-        }
-
-        // These flags are not yet supported in cap-std:
-        if flags.contains(DescriptorFlags::FILE_INTEGRITY_SYNC)
-            | flags.contains(DescriptorFlags::DATA_INTEGRITY_SYNC)
-            | flags.contains(DescriptorFlags::REQUESTED_WRITE_SYNC)
-        {
-            Err(ErrorCode::Unsupported)?;
-        }
-
-        if oflags.contains(OpenFlags::DIRECTORY) {
-            if oflags.contains(OpenFlags::CREATE)
-                || oflags.contains(OpenFlags::EXCLUSIVE)
-                || oflags.contains(OpenFlags::TRUNCATE)
-            {
-                Err(ErrorCode::Invalid)?;
-            }
-        }
-
-        // Represents each possible outcome from the spawn_blocking operation.
-        // This makes sure we don't have to give spawn_blocking any way to
-        // manipulate the table.
-        enum OpenResult {
-            Dir(cap_std::fs::Dir),
-            File(cap_std::fs::File),
-            NotDir,
-        }
-
-        let opened = d
-            .spawn_blocking::<_, std::io::Result<OpenResult>>(move |d| {
-                let mut opened = d.open_with(&path, &opts)?;
-                if opened.metadata()?.is_dir() {
-                    Ok(OpenResult::Dir(cap_std::fs::Dir::from_std_file(
-                        opened.into_std(),
-                    )))
-                } else if oflags.contains(OpenFlags::DIRECTORY) {
-                    Ok(OpenResult::NotDir)
-                } else {
-                    // FIXME cap-std needs a nonblocking open option so that files reads and writes
-                    // are nonblocking. Instead we set it after opening here:
-                    let set_fd_flags = opened.new_set_fd_flags(FdFlags::NONBLOCK)?;
-                    opened.set_fd_flags(set_fd_flags)?;
-                    Ok(OpenResult::File(opened))
-                }
-            })
-            .await?;
-
-        match opened {
-            OpenResult::Dir(dir) => {
-                Ok(table.push(Descriptor::Dir(Dir::new(dir, d.perms, d.file_perms)))?)
-            }
-
-            OpenResult::File(file) => Ok(table.push(Descriptor::File(File::new(
-                file,
-                mask_file_perms(d.file_perms, flags),
-            )))?),
-
-            OpenResult::NotDir => Err(ErrorCode::NotDirectory.into()),
+        let index = fd.rep();
+        let guest_path = path.clone();
+        let result =
+            untraced::UntracedDescriptor::open_at(self, fd, path_flags, path, oflags, flags, mode)
+                .await;
+        if let Ok(fd) = &result {
+            self.ctx().resource_used(|| {
+                ResourceUsageEvent::FileOpened(fd.rep(), guest_path.clone().into())
+            });
         }
+        self.ctx().trace(
+            "descriptor.open-at",
+            &[TraceValue::U64(index.into()), TraceValue::Str(&guest_path)],
+            &TraceValue::result(&result, |fd| TraceValue::U64(fd.rep().into())),
+        );
+        result
     }
 
     fn drop(&mut self, fd: Resource<types::Descriptor>) -> anyhow::Result<()> {
+        let index = fd.rep();
         let table = self.table_mut();
 
         // The Drop will close the file/dir, but if the close syscall
@@ -606,7 +552,13 @@ impl<T: WasiView> HostDescriptor for T {
         // tokio::fs::File just uses std::fs::File's Drop impl to close, so
         // it doesn't appear anyone else has found this to be a problem.
         // (Not that they could solve it without async drop...)
-        table.delete(fd)?;
+        let kind = match table.delete(fd)? {
+            Descriptor::Dir(_) => ResourceKind::Dir,
+            Descriptor::File(_) => ResourceKind::File,
+        };
+        self.ctx().resource_dropped(kind, index);
+        self.ctx()
+            .resource_used(|| ResourceUsageEvent::FileClosed(index));
 
         Ok(())
     }
@@ -616,12 +568,18 @@ impl<T: WasiView> HostDescriptor for T {
         fd: Resource<types::Descriptor>,
         path: String,
     ) -> FsResult<String> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         let table = self.table();
         let d = table.get(&fd)?.dir()?;
         if !d.perms.contains(DirPerms::READ) {
             return Err(ErrorCode::NotPermitted.into());
         }
-        let link = d.spawn_blocking(move |d| d.read_link(&path)).await?;
+        d.check_policy_resolved(&path, false).await?;
+        let link = d
+            .spawn_blocking_layered(move |d, base| {
+                overlay::fall_through(d, base, |d| d.read_link(&path))
+            })
+            .await?;
         Ok(link
             .into_os_string()
             .into_string()
@@ -633,11 +591,14 @@ impl<T: WasiView> HostDescriptor for T {
         fd: Resource<types::Descriptor>,
         path: String,
     ) -> FsResult<()> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         let table = self.table();
         let d = table.get(&fd)?.dir()?;
         if !d.perms.contains(DirPerms::MUTATE) {
             return Err(ErrorCode::NotPermitted.into());
         }
+        d.check_policy_resolved(&path, false).await?;
+        d.check_not_in_base(&path).await?;
         Ok(d.spawn_blocking(move |d| d.remove_dir(&path)).await?)
     }
 
@@ -648,6 +609,7 @@ impl<T: WasiView> HostDescriptor for T {
         new_fd: Resource<types::Descriptor>,
         new_path: String,
     ) -> FsResult<()> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         let table = self.table();
         let old_dir = table.get(&fd)?.dir()?;
         if !old_dir.perms.contains(DirPerms::MUTATE) {
@@ -657,10 +619,50 @@ impl<T: WasiView> HostDescriptor for T {
         if !new_dir.perms.contains(DirPerms::MUTATE) {
             return Err(ErrorCode::NotPermitted.into());
         }
+        old_dir.check_policy_resolved(&old_path, false).await?;
+        new_dir.check_policy_resolved(&new_path, false).await?;
+        old_dir.check_not_in_base(&old_path).await?;
+        new_dir.copy_up_parent(&new_path).await?;
+
+        // Files moved between directories with different quotas are counted
+        // against the quota they are moved into and credited to the one they
+        // leave.
+        let moves_quota = match (&old_dir.quota, &new_dir.quota) {
+            (None, None) => false,
+            (Some(old), Some(new)) => !Arc::ptr_eq(old, new),
+            _ => true,
+        };
+        let moved = if moves_quota {
+            let old_path = old_path.clone();
+            old_dir
+                .spawn_blocking(move |d| {
+                    let mut sizes = Vec::new();
+                    file_sizes(d, old_path.as_ref(), &mut sizes)?;
+                    Ok::<_, std::io::Error>(sizes)
+                })
+                .await?
+        } else {
+            Vec::new()
+        };
+        if let Some(quota) = new_dir.quota.as_ref().filter(|_| moves_quota) {
+            quota.move_in(&moved)?;
+        }
+
         let new_dir_handle = std::sync::Arc::clone(&new_dir.dir);
-        Ok(old_dir
+        let result = old_dir
             .spawn_blocking(move |d| d.rename(&old_path, &new_dir_handle, &new_path))
-            .await?)
+            .await;
+        if moves_quota {
+            let quota = if result.is_ok() {
+                &old_dir.quota
+            } else {
+                &new_dir.quota
+            };
+            if let Some(quota) = quota {
+                quota.move_out(&moved);
+            }
+        }
+        Ok(result?)
     }
 
     async fn symlink_at(
@@ -669,6 +671,7 @@ impl<T: WasiView> HostDescriptor for T {
         src_path: String,
         dest_path: String,
     ) -> FsResult<()> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         // On windows, Dir.symlink is provided by DirExt
         #[cfg(windows)]
         use cap_fs_ext::DirExt;
@@ -678,6 +681,18 @@ impl<T: WasiView> HostDescriptor for T {
         if !d.perms.contains(DirPerms::MUTATE) {
             return Err(ErrorCode::NotPermitted.into());
         }
+        d.check_policy_resolved(&dest_path, false).await?;
+        // The target of the link is resolved relative to the directory
+        // containing it, so check it as a path through the link would be.
+        match dest_path.rsplit_once('/') {
+            _ if src_path.starts_with('/') => d.check_policy(&src_path)?,
+            Some((parent, _)) => {
+                d.check_policy_resolved(&format!("{parent}/{src_path}"), true)
+                    .await?
+            }
+            None => d.check_policy_resolved(&src_path, true).await?,
+        }
+        d.prepare_create(&dest_path).await?;
         Ok(d.spawn_blocking(move |d| d.symlink(&src_path, &dest_path))
             .await?)
     }
@@ -687,6 +702,7 @@ impl<T: WasiView> HostDescriptor for T {
         fd: Resource<types::Descriptor>,
         path: String,
     ) -> FsResult<()> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         use cap_fs_ext::DirExt;
 
         let table = self.table();
@@ -694,8 +710,22 @@ impl<T: WasiView> HostDescriptor for T {
         if !d.perms.contains(DirPerms::MUTATE) {
             return Err(ErrorCode::NotPermitted.into());
         }
-        Ok(d.spawn_blocking(move |d| d.remove_file_or_symlink(&path))
-            .await?)
+        d.check_policy_resolved(&path, false).await?;
+        d.check_not_in_base(&path).await?;
+        let removed = d
+            .spawn_blocking(move |d| {
+                let metadata = d.symlink_metadata(&path)?;
+                d.remove_file_or_symlink(&path)?;
+                Ok::<_, std::io::Error>(metadata)
+            })
+            .await?;
+        // The space of a file is only freed once its last link is removed.
+        if let Some(quota) = &d.quota {
+            if removed.is_file() && cap_fs_ext::MetadataExt::nlink(&removed) == 1 {
+                quota.file_removed(removed.len());
+            }
+        }
+        Ok(())
     }
 
     async fn access_at(
@@ -767,6 +797,8 @@ impl<T: WasiView> HostDescriptor for T {
 
         // Insert the stream view into the table. Trap if the table is full.
         let index = self.table_mut().push(InputStream::File(reader))?;
+        self.ctx()
+            .resource_created(ResourceKind::InputStream, index.rep());
 
         Ok(index)
     }
@@ -787,11 +819,13 @@ impl<T: WasiView> HostDescriptor for T {
         let clone = std::sync::Arc::clone(&f.file);
 
         // Create a stream view for it.
-        let writer = FileOutputStream::write_at(clone, offset);
+        let writer = FileOutputStream::write_at(clone, offset).with_quota(f.quota.clone());
         let writer: OutputStream = Box::new(writer);
 
         // Insert the stream view into the table. Trap if the table is full.
         let index = self.table_mut().push(writer)?;
+        self.ctx()
+            .resource_created(ResourceKind::OutputStream, index.rep());
 
         Ok(index)
     }
@@ -810,11 +844,13 @@ impl<T: WasiView> HostDescriptor for T {
         let clone = std::sync::Arc::clone(&f.file);
 
         // Create a stream view for it.
-        let appender = FileOutputStream::append(clone);
+        let appender = FileOutputStream::append(clone).with_quota(f.quota.clone());
         let appender: OutputStream = Box::new(appender);
 
         // Insert the stream view into the table. Trap if the table is full.
         let index = self.table_mut().push(appender)?;
+        self.ctx()
+            .resource_created(ResourceKind::OutputStream, index.rep());
 
         Ok(index)
     }
@@ -859,22 +895,354 @@ impl<T: WasiView> HostDescriptor for T {
         path_flags: types::PathFlags,
         path: String,
     ) -> FsResult<types::MetadataHashValue> {
+        self.ctx().metrics.increment(Counter::Filesystem);
         let table = self.table();
         let d = table.get(&fd)?.dir()?;
+        d.check_policy_resolved(&path, symlink_follow(path_flags))
+            .await?;
         // No permissions check on metadata: if dir opened, allowed to stat it
         let meta = d
-            .spawn_blocking(move |d| {
-                if symlink_follow(path_flags) {
-                    d.metadata(path)
-                } else {
-                    d.symlink_metadata(path)
-                }
+            .spawn_blocking_layered(move |d, base| {
+                overlay::fall_through(d, base, |d| {
+                    if symlink_follow(path_flags) {
+                        d.metadata(&path)
+                    } else {
+                        d.symlink_metadata(&path)
+                    }
+                })
             })
             .await?;
         Ok(calculate_metadata_hash(&meta))
     }
 }
 
+/// The calls of [`HostDescriptor`] which are
+/// [traced](crate::preview2::WasiCtxBuilder::trace_syscalls), implemented
+/// without tracing. The trait implementation records each call around these.
+mod untraced {
+    use super::*;
+
+    #[async_trait::async_trait]
+    pub(super) trait UntracedDescriptor {
+        async fn read(
+            &mut self,
+            fd: Resource<types::Descriptor>,
+            len: types::Filesize,
+            offset: types::Filesize,
+        ) -> FsResult<(Vec<u8>, bool)>;
+
+        async fn write(
+            &mut self,
+            fd: Resource<types::Descriptor>,
+            buf: Vec<u8>,
+            offset: types::Filesize,
+        ) -> FsResult<types::Filesize>;
+
+        async fn open_at(
+            &mut self,
+            fd: Resource<types::Descriptor>,
+            path_flags: types::PathFlags,
+            path: String,
+            oflags: types::OpenFlags,
+            flags: types::DescriptorFlags,
+            // TODO: These are the permissions to use when creating a new file.
+            // Not implemented yet.
+            _mode: types::Modes,
+        ) -> FsResult<Resource<types::Descriptor>>;
+    }
+
+    #[async_trait::async_trait]
+    impl<T: WasiView> UntracedDescriptor for T {
+        async fn read(
+            &mut self,
+            fd: Resource<types::Descriptor>,
+            len: types::Filesize,
+            offset: types::Filesize,
+        ) -> FsResult<(Vec<u8>, bool)> {
+            self.ctx().metrics.increment(Counter::StreamRead);
+            use std::io::IoSliceMut;
+            use system_interface::fs::FileIoExt;
+
+            let table = self.table();
+
+            let f = table.get(&fd)?.file()?;
+            if !f.perms.contains(FilePerms::READ) {
+                return Err(ErrorCode::NotPermitted.into());
+            }
+
+            let (mut buffer, r) = f
+                .spawn_blocking(move |f| {
+                    let mut buffer = vec![0; len.try_into().unwrap_or(usize::MAX)];
+                    let r = f.read_vectored_at(&mut [IoSliceMut::new(&mut buffer)], offset);
+                    (buffer, r)
+                })
+                .await;
+
+            let (bytes_read, state) = match r? {
+                0 => (0, true),
+                n => (n, false),
+            };
+
+            buffer.truncate(bytes_read);
+
+            Ok((buffer, state))
+        }
+
+        async fn write(
+            &mut self,
+            fd: Resource<types::Descriptor>,
+            buf: Vec<u8>,
+            offset: types::Filesize,
+        ) -> FsResult<types::Filesize> {
+            self.ctx().metrics.increment(Counter::StreamWrite);
+            use std::io::IoSlice;
+            use system_interface::fs::FileIoExt;
+
+            let table = self.table();
+            let f = table.get(&fd)?.file()?;
+            if !f.perms.contains(FilePerms::WRITE) {
+                return Err(ErrorCode::NotPermitted.into());
+            }
+            f.grow_to(offset.saturating_add(buf.len() as u64))?;
+
+            let bytes_written = f
+                .spawn_blocking(move |f| f.write_vectored_at(&[IoSlice::new(&buf)], offset))
+                .await?;
+
+            Ok(types::Filesize::try_from(bytes_written).expect("usize fits in Filesize"))
+        }
+
+        async fn open_at(
+            &mut self,
+            fd: Resource<types::Descriptor>,
+            path_flags: types::PathFlags,
+            path: String,
+            oflags: types::OpenFlags,
+            flags: types::DescriptorFlags,
+            // TODO: These are the permissions to use when creating a new file.
+            // Not implemented yet.
+            _mode: types::Modes,
+        ) -> FsResult<Resource<types::Descriptor>> {
+            self.ctx().metrics.increment(Counter::PathOpen);
+            use cap_fs_ext::{FollowSymlinks, OpenOptionsFollowExt, OpenOptionsMaybeDirExt};
+            use system_interface::fs::{FdFlags, GetSetFdFlags};
+            use types::{DescriptorFlags, OpenFlags};
+
+            let scope = self.ctx().preopen_scope;
+            let files_full = self
+                .ctx()
+                .handle_limit_reached(self.table(), ResourceKind::File);
+            let dirs_full = self
+                .ctx()
+                .handle_limit_reached(self.table(), ResourceKind::Dir);
+            let table = self.table_mut();
+            let d = table.get(&fd)?.dir()?;
+            if !d.perms.contains(DirPerms::READ) {
+                Err(ErrorCode::NotPermitted)?;
+            }
+            d.check_policy_resolved(&path, symlink_follow(path_flags))
+                .await?;
+            if scope.denies_dot_dot(&path) {
+                Err(ErrorCode::Access)?;
+            }
+            if scope == ScopePolicy::DenySymlinkEscape {
+                let path = path.clone();
+                if !d
+                    .spawn_blocking(move |d| policy::resolves_within(d, &path))
+                    .await
+                {
+                    Err(ErrorCode::Access)?;
+                }
+            }
+            let child_path = d.child_path(&path);
+
+            if !d.perms.contains(DirPerms::MUTATE) {
+                if oflags.contains(OpenFlags::CREATE) || oflags.contains(OpenFlags::TRUNCATE) {
+                    Err(ErrorCode::NotPermitted)?;
+                }
+                if flags.contains(DescriptorFlags::WRITE) {
+                    Err(ErrorCode::NotPermitted)?;
+                }
+            }
+
+            let mut opts = cap_std::fs::OpenOptions::new();
+            opts.maybe_dir(true);
+
+            if oflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) {
+                opts.create_new(true);
+                opts.write(true);
+            } else if oflags.contains(OpenFlags::CREATE) {
+                opts.create(true);
+                opts.write(true);
+            }
+            if oflags.contains(OpenFlags::TRUNCATE) {
+                opts.truncate(true);
+            }
+            if flags.contains(DescriptorFlags::READ) {
+                opts.read(true);
+            }
+            if flags.contains(DescriptorFlags::WRITE) {
+                opts.write(true);
+            } else {
+                // If not opened write, open read. This way the OS lets us open
+                // the file, but we can use perms to reject use of the file later.
+                opts.read(true);
+            }
+            if symlink_follow(path_flags) {
+                opts.follow(FollowSymlinks::Yes);
+            } else {
+                opts.follow(FollowSymlinks::No);
+            }
+
+            // These flags are not yet supported in cap-std:
+            if flags.contains(DescriptorFlags::FILE_INTEGRITY_SYNC)
+                | flags.contains(DescriptorFlags::DATA_INTEGRITY_SYNC)
+                | flags.contains(DescriptorFlags::REQUESTED_WRITE_SYNC)
+            {
+                Err(ErrorCode::Unsupported)?;
+            }
+
+            if oflags.contains(OpenFlags::DIRECTORY)
+                && (oflags.contains(OpenFlags::CREATE)
+                    || oflags.contains(OpenFlags::EXCLUSIVE)
+                    || oflags.contains(OpenFlags::TRUNCATE))
+            {
+                Err(ErrorCode::Invalid)?;
+            }
+
+            // Represents each possible outcome from the spawn_blocking operation.
+            // This makes sure we don't have to give spawn_blocking any way to
+            // manipulate the table.
+            enum OpenResult {
+                /// A directory, along with the corresponding directory of the
+                /// base of an overlay.
+                Dir(cap_std::fs::Dir, Option<cap_std::fs::Dir>),
+                /// A file, whether it is a file of the base of an overlay,
+                /// which is only opened for reading, and whether it was
+                /// created by this call.
+                File(cap_std::fs::File, bool, bool),
+                NotDir,
+                /// The file does not exist, and may not be created since the
+                /// quota of the directory allows no more files.
+                QuotaExceeded,
+            }
+
+            // Files created in a directory with a quota count against it. A
+            // slot is reserved up front, and the file is opened exclusively
+            // first to learn whether this call creates it. Without a slot,
+            // only existing files may be opened.
+            let quota = d.quota.clone();
+            let reservation = match &quota {
+                Some(quota) if oflags.contains(OpenFlags::CREATE) => Some(quota.reserve_file()),
+                _ => None,
+            };
+            let creation = match &reservation {
+                None => Creation::Uncounted,
+                Some(Ok(_)) => Creation::Counted {
+                    exclusive: oflags.contains(OpenFlags::EXCLUSIVE),
+                },
+                Some(Err(_)) => Creation::Denied {
+                    exclusive: oflags.contains(OpenFlags::EXCLUSIVE),
+                },
+            };
+            // Truncating a file of the directory credits its length.
+            let truncated = match &quota {
+                Some(quota) if oflags.contains(OpenFlags::TRUNCATE) => Some(quota.clone()),
+                _ => None,
+            };
+
+            // Entries of the base of an overlay are read from the base, and
+            // copied into the overlay first if they are opened for writing.
+            let writes = oflags.intersects(OpenFlags::CREATE | OpenFlags::TRUNCATE)
+                || flags.contains(DescriptorFlags::WRITE);
+            let opened = d
+                .spawn_blocking_layered::<_, std::io::Result<OpenResult>>(move |d, base| {
+                    let from_base = !writes && overlay::only_in_base(d, base, &path);
+                    // Files copied up from the base of an overlay were never
+                    // counted, so only files of the overlay itself are.
+                    let truncated = truncated.and_then(|quota| {
+                        let metadata = d.metadata(&path).ok()?;
+                        Some((quota, metadata.len())).filter(|_| metadata.is_file())
+                    });
+                    if writes {
+                        overlay::copy_up(d, base, &path)?;
+                    }
+                    let layer = if from_base { base.unwrap() } else { d };
+                    let (mut opened, created) = match creation.open(layer, &path, &opts) {
+                        Ok(opened) => opened,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound && creation.denied() => {
+                            return Ok(OpenResult::QuotaExceeded)
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    if let Some((quota, len)) = truncated {
+                        quota.shrink(len, 0);
+                    }
+                    if opened.metadata()?.is_dir() {
+                        let base_dir = match base {
+                            Some(base) if overlay::in_base(Some(base), &path) => {
+                                Some(base.open_dir(&path)?)
+                            }
+                            _ => None,
+                        };
+                        let opened = if from_base {
+                            // Directories of the base are created in the
+                            // overlay, so that entries can be added to them.
+                            drop(opened);
+                            d.create_dir_all(&path)?;
+                            d.open_dir(&path)?
+                        } else {
+                            cap_std::fs::Dir::from_std_file(opened.into_std())
+                        };
+                        Ok(OpenResult::Dir(opened, base_dir))
+                    } else if oflags.contains(OpenFlags::DIRECTORY) {
+                        Ok(OpenResult::NotDir)
+                    } else {
+                        // FIXME cap-std needs a nonblocking open option so that files reads and writes
+                        // are nonblocking. Instead we set it after opening here:
+                        let set_fd_flags = opened.new_set_fd_flags(FdFlags::NONBLOCK)?;
+                        opened.set_fd_flags(set_fd_flags)?;
+                        Ok(OpenResult::File(opened, from_base, created))
+                    }
+                })
+                .await?;
+            if let (OpenResult::File(_, _, true), Some(Ok(reservation))) = (&opened, reservation) {
+                reservation.commit();
+            }
+
+            // The limits on open handles are checked once the kind of the
+            // opened entry is known, closing it again if the limit is reached.
+            let (kind, fd) = match opened {
+                OpenResult::Dir(..) if dirs_full => return Err(ErrorCode::Quota.into()),
+                OpenResult::File(..) if files_full => return Err(ErrorCode::Quota.into()),
+                OpenResult::Dir(dir, base) => {
+                    let mut dir = Dir::new(dir, d.perms, d.file_perms)
+                        .with_policy(child_path, d.policy.clone());
+                    dir.quota = quota;
+                    dir.base = base.map(Arc::new);
+                    (ResourceKind::Dir, table.push(Descriptor::Dir(dir))?)
+                }
+
+                OpenResult::File(file, from_base, _) => {
+                    let mut perms = mask_file_perms(d.file_perms, flags);
+                    if from_base {
+                        perms &= FilePerms::READ;
+                    }
+                    let mut file = File::new(file, perms);
+                    file.quota = quota;
+                    (ResourceKind::File, table.push(Descriptor::File(file))?)
+                }
+
+                OpenResult::NotDir => return Err(ErrorCode::NotDirectory.into()),
+                OpenResult::QuotaExceeded => return Err(ErrorCode::Quota.into()),
+            };
+            self.ctx().resource_created(kind, fd.rep());
+            self.ctx().stats.file_opened();
+            Ok(fd)
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl<T: WasiView> HostDirectoryEntryStream for T {
     async fn read_directory_entry(
@@ -892,6 +1260,58 @@ impl<T: WasiView> HostDirectoryEntryStream for T {
     }
 }
 
+/// How `open-at` counts the files it creates in a directory with a quota.
+#[derive(Clone, Copy)]
+enum Creation {
+    /// Files are not counted, or the call creates none.
+    Uncounted,
+    /// A slot was reserved for the file, which is kept if the file is
+    /// created. With `exclusive`, opening an existing file fails.
+    Counted { exclusive: bool },
+    /// No slot is left, so only existing files may be opened, and with
+    /// `exclusive` none at all.
+    Denied { exclusive: bool },
+}
+
+impl Creation {
+    /// Opens `path` in `dir` with `opts`, returning the file and whether it
+    /// was created.
+    fn open(
+        self,
+        dir: &cap_std::fs::Dir,
+        path: &str,
+        opts: &cap_std::fs::OpenOptions,
+    ) -> std::io::Result<(cap_std::fs::File, bool)> {
+        let mut existing = opts.clone();
+        existing.create(false).create_new(false);
+        match self {
+            Creation::Uncounted => Ok((dir.open_with(path, opts)?, false)),
+            Creation::Counted { exclusive } => {
+                let mut new = opts.clone();
+                new.create_new(true);
+                match dir.open_with(path, &new) {
+                    Ok(file) => Ok((file, true)),
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && !exclusive => {
+                        Ok((dir.open_with(path, &existing)?, false))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Creation::Denied { exclusive } => {
+                let file = dir.open_with(path, &existing)?;
+                if exclusive {
+                    return Err(std::io::ErrorKind::AlreadyExists.into());
+                }
+                Ok((file, false))
+            }
+        }
+    }
+
+    fn denied(self) -> bool {
+        matches!(self, Creation::Denied { .. })
+    }
+}
+
 async fn get_descriptor_metadata(
     table: &Table,
     fd: Resource<types::Descriptor>,
@@ -908,6 +1328,21 @@ async fn get_descriptor_metadata(
     }
 }
 
+/// Collects the sizes of the files at or beneath `path`, which count against
+/// a directory quota.
+fn file_sizes(d: &cap_std::fs::Dir, path: &Path, sizes: &mut Vec<u64>) -> std::io::Result<()> {
+    let metadata = d.symlink_metadata(path)?;
+    if metadata.is_file() {
+        sizes.push(metadata.len());
+    } else if metadata.is_dir() {
+        let dir = d.open_dir(path)?;
+        for entry in dir.entries()? {
+            file_sizes(&dir, entry?.file_name().as_ref(), sizes)?;
+        }
+    }
+    Ok(())
+}
+
 fn calculate_metadata_hash(meta: &cap_std::fs::Metadata) -> types::MetadataHashValue {
     use cap_fs_ext::MetadataExt;
     // Without incurring any deps, std provides us with a 64 bit hash
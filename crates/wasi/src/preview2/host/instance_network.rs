@@ -1,6 +1,6 @@
 use crate::preview2::bindings::sockets::instance_network;
 use crate::preview2::network::Network;
-use crate::preview2::WasiView;
+use crate::preview2::{ResourceKind, WasiView};
 use wasmtime::component::Resource;
 
 impl<T: WasiView> instance_network::Host for T {
@@ -8,8 +8,14 @@ impl<T: WasiView> instance_network::Host for T {
         let network = Network {
             pool: self.ctx().pool.clone(),
             allow_ip_name_lookup: self.ctx().allow_ip_name_lookup,
+            policy: self.ctx().capability_policy.clone(),
+            socket_factory: self.ctx().socket_factory.clone(),
+            unix_socket_dir: self.ctx().unix_socket_dir.clone(),
+            audit_log: self.ctx().network_audit_log.clone(),
         };
         let network = self.table_mut().push(network)?;
+        self.ctx()
+            .resource_created(ResourceKind::Network, network.rep());
         Ok(network)
     }
 }
@@ -1,7 +1,9 @@
 use crate::preview2::{
     bindings::io::streams::{self, InputStream, OutputStream},
+    metrics::Counter,
     poll::subscribe,
-    Pollable, StreamError, StreamResult, WasiView,
+    trace::TraceValue,
+    Pollable, ResourceKind, ResourceUsageEvent, StreamError, StreamResult, WasiView,
 };
 use wasmtime::component::Resource;
 
@@ -31,7 +33,10 @@ impl<T: WasiView> streams::HostError for T {
 #[async_trait::async_trait]
 impl<T: WasiView> streams::HostOutputStream for T {
     fn drop(&mut self, stream: Resource<OutputStream>) -> anyhow::Result<()> {
+        let index = stream.rep();
         self.table_mut().delete(stream)?;
+        self.ctx()
+            .resource_dropped(ResourceKind::OutputStream, index);
         Ok(())
     }
 
@@ -41,8 +46,24 @@ impl<T: WasiView> streams::HostOutputStream for T {
     }
 
     fn write(&mut self, stream: Resource<OutputStream>, bytes: Vec<u8>) -> StreamResult<()> {
-        self.table_mut().get_mut(&stream)?.write(bytes.into())?;
-        Ok(())
+        let index = stream.rep();
+        let len = bytes.len();
+        // Only copy the buffer if it is going to be traced.
+        let data = self.ctx().trace.is_some().then(|| bytes.clone());
+        let result = untraced::UntracedOutputStream::write(self, stream, bytes);
+        if result.is_ok() {
+            self.ctx()
+                .resource_used(|| ResourceUsageEvent::BytesWritten(index, len));
+        }
+        self.ctx().trace(
+            "output-stream.write",
+            &[
+                TraceValue::U64(index.into()),
+                TraceValue::Bytes(data.as_deref().unwrap_or_default()),
+            ],
+            &TraceValue::result(&result, |()| TraceValue::Null),
+        );
+        result
     }
 
     fn subscribe(&mut self, stream: Resource<OutputStream>) -> anyhow::Result<Resource<Pollable>> {
@@ -54,26 +75,24 @@ impl<T: WasiView> streams::HostOutputStream for T {
         stream: Resource<OutputStream>,
         bytes: Vec<u8>,
     ) -> StreamResult<()> {
-        let s = self.table_mut().get_mut(&stream)?;
-
-        if bytes.len() > 4096 {
-            return Err(StreamError::trap(
-                "Buffer too large for blocking-write-and-flush (expected at most 4096)",
-            ));
-        }
-
-        let mut bytes = bytes::Bytes::from(bytes);
-        while !bytes.is_empty() {
-            let permit = s.write_ready().await?;
-            let len = bytes.len().min(permit);
-            let chunk = bytes.split_to(len);
-            s.write(chunk)?;
+        let index = stream.rep();
+        let len = bytes.len();
+        let data = self.ctx().trace.is_some().then(|| bytes.clone());
+        let result =
+            untraced::UntracedOutputStream::blocking_write_and_flush(self, stream, bytes).await;
+        if result.is_ok() {
+            self.ctx()
+                .resource_used(|| ResourceUsageEvent::BytesWritten(index, len));
         }
-
-        s.flush()?;
-        s.write_ready().await?;
-
-        Ok(())
+        self.ctx().trace(
+            "output-stream.blocking-write-and-flush",
+            &[
+                TraceValue::U64(index.into()),
+                TraceValue::Bytes(data.as_deref().unwrap_or_default()),
+            ],
+            &TraceValue::result(&result, |()| TraceValue::Null),
+        );
+        result
     }
 
     async fn blocking_write_zeroes_and_flush(
@@ -81,33 +100,35 @@ impl<T: WasiView> streams::HostOutputStream for T {
         stream: Resource<OutputStream>,
         len: u64,
     ) -> StreamResult<()> {
-        let s = self.table_mut().get_mut(&stream)?;
-
-        if len > 4096 {
-            return Err(StreamError::trap(
-                "Buffer too large for blocking-write-zeroes-and-flush (expected at most 4096)",
-            ));
-        }
-
-        let mut len = len;
-        while len > 0 {
-            let permit = s.write_ready().await?;
-            let this_len = len.min(permit as u64);
-            s.write_zeroes(this_len as usize)?;
-            len -= this_len;
+        let index = stream.rep();
+        let result =
+            untraced::UntracedOutputStream::blocking_write_zeroes_and_flush(self, stream, len)
+                .await;
+        if result.is_ok() {
+            self.ctx()
+                .resource_used(|| ResourceUsageEvent::BytesWritten(index, len as usize));
         }
-
-        s.flush()?;
-        s.write_ready().await?;
-
-        Ok(())
+        self.ctx().trace(
+            "output-stream.blocking-write-zeroes-and-flush",
+            &[TraceValue::U64(index.into()), TraceValue::U64(len)],
+            &TraceValue::result(&result, |()| TraceValue::Null),
+        );
+        result
     }
 
     fn write_zeroes(&mut self, stream: Resource<OutputStream>, len: u64) -> StreamResult<()> {
-        self.table_mut()
-            .get_mut(&stream)?
-            .write_zeroes(len as usize)?;
-        Ok(())
+        let index = stream.rep();
+        let result = untraced::UntracedOutputStream::write_zeroes(self, stream, len);
+        if result.is_ok() {
+            self.ctx()
+                .resource_used(|| ResourceUsageEvent::BytesWritten(index, len as usize));
+        }
+        self.ctx().trace(
+            "output-stream.write-zeroes",
+            &[TraceValue::U64(index.into()), TraceValue::U64(len)],
+            &TraceValue::result(&result, |()| TraceValue::Null),
+        );
+        result
     }
 
     fn flush(&mut self, stream: Resource<OutputStream>) -> StreamResult<()> {
@@ -128,6 +149,8 @@ impl<T: WasiView> streams::HostOutputStream for T {
         src: Resource<InputStream>,
         len: u64,
     ) -> StreamResult<u64> {
+        self.ctx().metrics.increment(Counter::StreamRead);
+        self.ctx().metrics.increment(Counter::StreamWrite);
         let len = len.try_into().unwrap_or(usize::MAX);
 
         let permit = {
@@ -151,6 +174,10 @@ impl<T: WasiView> streams::HostOutputStream for T {
 
         let output = self.table_mut().get_mut(&dest)?;
         output.write(contents)?;
+        self.ctx()
+            .resource_used(|| ResourceUsageEvent::BytesRead(src.rep(), len));
+        self.ctx()
+            .resource_used(|| ResourceUsageEvent::BytesWritten(dest.rep(), len));
         Ok(len.try_into().expect("usize can fit in u64"))
     }
 
@@ -173,18 +200,26 @@ impl<T: WasiView> streams::HostOutputStream for T {
 #[async_trait::async_trait]
 impl<T: WasiView> streams::HostInputStream for T {
     fn drop(&mut self, stream: Resource<InputStream>) -> anyhow::Result<()> {
+        let index = stream.rep();
         self.table_mut().delete(stream)?;
+        self.ctx()
+            .resource_dropped(ResourceKind::InputStream, index);
         Ok(())
     }
 
     async fn read(&mut self, stream: Resource<InputStream>, len: u64) -> StreamResult<Vec<u8>> {
-        let len = len.try_into().unwrap_or(usize::MAX);
-        let bytes = match self.table_mut().get_mut(&stream)? {
-            InputStream::Host(s) => s.read(len)?,
-            InputStream::File(s) => s.read(len).await?,
-        };
-        debug_assert!(bytes.len() <= len as usize);
-        Ok(bytes.into())
+        let index = stream.rep();
+        let result = untraced::UntracedInputStream::read(self, stream, len).await;
+        if let Ok(bytes) = &result {
+            self.ctx()
+                .resource_used(|| ResourceUsageEvent::BytesRead(index, bytes.len()));
+        }
+        self.ctx().trace(
+            "input-stream.read",
+            &[TraceValue::U64(index.into()), TraceValue::U64(len)],
+            &TraceValue::result(&result, |bytes| TraceValue::Bytes(bytes)),
+        );
+        result
     }
 
     async fn blocking_read(
@@ -199,12 +234,14 @@ impl<T: WasiView> streams::HostInputStream for T {
     }
 
     async fn skip(&mut self, stream: Resource<InputStream>, len: u64) -> StreamResult<u64> {
-        let len = len.try_into().unwrap_or(usize::MAX);
-        let written = match self.table_mut().get_mut(&stream)? {
-            InputStream::Host(s) => s.skip(len)?,
-            InputStream::File(s) => s.skip(len).await?,
-        };
-        Ok(written.try_into().expect("usize always fits in u64"))
+        let index = stream.rep();
+        let result = untraced::UntracedInputStream::skip(self, stream, len).await;
+        self.ctx().trace(
+            "input-stream.skip",
+            &[TraceValue::U64(index.into()), TraceValue::U64(len)],
+            &TraceValue::result(&result, |n| TraceValue::U64(*n)),
+        );
+        result
     }
 
     async fn blocking_skip(
@@ -223,6 +260,134 @@ impl<T: WasiView> streams::HostInputStream for T {
     }
 }
 
+/// The calls of [`streams::HostOutputStream`] and
+/// [`streams::HostInputStream`] which are
+/// [traced](crate::preview2::WasiCtxBuilder::trace_syscalls), implemented
+/// without tracing. The trait implementations record each call around
+/// these.
+mod untraced {
+    use super::*;
+
+    #[async_trait::async_trait]
+    pub(super) trait UntracedOutputStream {
+        fn write(&mut self, stream: Resource<OutputStream>, bytes: Vec<u8>) -> StreamResult<()>;
+        async fn blocking_write_and_flush(
+            &mut self,
+            stream: Resource<OutputStream>,
+            bytes: Vec<u8>,
+        ) -> StreamResult<()>;
+        async fn blocking_write_zeroes_and_flush(
+            &mut self,
+            stream: Resource<OutputStream>,
+            len: u64,
+        ) -> StreamResult<()>;
+        fn write_zeroes(&mut self, stream: Resource<OutputStream>, len: u64) -> StreamResult<()>;
+    }
+
+    #[async_trait::async_trait]
+    impl<T: WasiView> UntracedOutputStream for T {
+        fn write(&mut self, stream: Resource<OutputStream>, bytes: Vec<u8>) -> StreamResult<()> {
+            self.ctx().metrics.increment(Counter::StreamWrite);
+            self.table_mut().get_mut(&stream)?.write(bytes.into())?;
+            Ok(())
+        }
+
+        async fn blocking_write_and_flush(
+            &mut self,
+            stream: Resource<OutputStream>,
+            bytes: Vec<u8>,
+        ) -> StreamResult<()> {
+            self.ctx().metrics.increment(Counter::StreamWrite);
+            let s = self.table_mut().get_mut(&stream)?;
+
+            if bytes.len() > 4096 {
+                return Err(StreamError::trap(
+                    "Buffer too large for blocking-write-and-flush (expected at most 4096)",
+                ));
+            }
+
+            let mut bytes = bytes::Bytes::from(bytes);
+            while !bytes.is_empty() {
+                let permit = s.write_ready().await?;
+                let len = bytes.len().min(permit);
+                let chunk = bytes.split_to(len);
+                s.write(chunk)?;
+            }
+
+            s.flush()?;
+            s.write_ready().await?;
+
+            Ok(())
+        }
+
+        async fn blocking_write_zeroes_and_flush(
+            &mut self,
+            stream: Resource<OutputStream>,
+            len: u64,
+        ) -> StreamResult<()> {
+            self.ctx().metrics.increment(Counter::StreamWrite);
+            let s = self.table_mut().get_mut(&stream)?;
+
+            if len > 4096 {
+                return Err(StreamError::trap(
+                    "Buffer too large for blocking-write-zeroes-and-flush (expected at most 4096)",
+                ));
+            }
+
+            let mut len = len;
+            while len > 0 {
+                let permit = s.write_ready().await?;
+                let this_len = len.min(permit as u64);
+                s.write_zeroes(this_len as usize)?;
+                len -= this_len;
+            }
+
+            s.flush()?;
+            s.write_ready().await?;
+
+            Ok(())
+        }
+
+        fn write_zeroes(&mut self, stream: Resource<OutputStream>, len: u64) -> StreamResult<()> {
+            self.ctx().metrics.increment(Counter::StreamWrite);
+            self.table_mut()
+                .get_mut(&stream)?
+                .write_zeroes(len as usize)?;
+            Ok(())
+        }
+    }
+
+    #[async_trait::async_trait]
+    pub(super) trait UntracedInputStream {
+        async fn read(&mut self, stream: Resource<InputStream>, len: u64) -> StreamResult<Vec<u8>>;
+        async fn skip(&mut self, stream: Resource<InputStream>, len: u64) -> StreamResult<u64>;
+    }
+
+    #[async_trait::async_trait]
+    impl<T: WasiView> UntracedInputStream for T {
+        async fn read(&mut self, stream: Resource<InputStream>, len: u64) -> StreamResult<Vec<u8>> {
+            self.ctx().metrics.increment(Counter::StreamRead);
+            let len = len.try_into().unwrap_or(usize::MAX);
+            let bytes = match self.table_mut().get_mut(&stream)? {
+                InputStream::Host(s) => s.read(len)?,
+                InputStream::File(s) => s.read(len).await?,
+            };
+            debug_assert!(bytes.len() <= len);
+            Ok(bytes.into())
+        }
+
+        async fn skip(&mut self, stream: Resource<InputStream>, len: u64) -> StreamResult<u64> {
+            self.ctx().metrics.increment(Counter::StreamRead);
+            let len = len.try_into().unwrap_or(usize::MAX);
+            let written = match self.table_mut().get_mut(&stream)? {
+                InputStream::Host(s) => s.skip(len)?,
+                InputStream::File(s) => s.skip(len).await?,
+            };
+            Ok(written.try_into().expect("usize always fits in u64"))
+        }
+    }
+}
+
 pub mod sync {
     use crate::preview2::{
         bindings::io::streams::{
@@ -2,7 +2,7 @@ use crate::preview2::bindings::sockets::network::{
     self, ErrorCode, IpAddressFamily, IpSocketAddress, Ipv4Address, Ipv4SocketAddress, Ipv6Address,
     Ipv6SocketAddress,
 };
-use crate::preview2::{SocketError, WasiView};
+use crate::preview2::{ResourceKind, SocketError, WasiView};
 use rustix::io::Errno;
 use std::io;
 use wasmtime::component::Resource;
@@ -15,9 +15,11 @@ impl<T: WasiView> network::Host for T {
 
 impl<T: WasiView> crate::preview2::bindings::sockets::network::HostNetwork for T {
     fn drop(&mut self, this: Resource<network::Network>) -> Result<(), anyhow::Error> {
+        let index = this.rep();
         let table = self.table_mut();
 
         table.delete(this)?;
+        self.ctx().resource_dropped(ResourceKind::Network, index);
 
         Ok(())
     }
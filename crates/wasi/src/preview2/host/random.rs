@@ -1,36 +1,84 @@
 use crate::preview2::bindings::random::{insecure, insecure_seed, random};
+use crate::preview2::metrics::Counter;
+use crate::preview2::random::ReplayDivergence;
+use crate::preview2::trace::TraceValue;
 use crate::preview2::WasiView;
 use cap_rand::{distributions::Standard, Rng};
 
+/// Fails if the generator is a [`ReplayRng`](crate::preview2::ReplayRng)
+/// which ran out of recorded bytes, rather than handing the guest zeros.
+fn check_replay(replay: &Option<ReplayDivergence>) -> anyhow::Result<()> {
+    match replay {
+        Some(replay) => replay.check(),
+        None => Ok(()),
+    }
+}
+
 impl<T: WasiView> random::Host for T {
     fn get_random_bytes(&mut self, len: u64) -> anyhow::Result<Vec<u8>> {
-        Ok((&mut self.ctx_mut().random)
+        self.ctx().metrics.increment(Counter::Random);
+        let bytes: Vec<u8> = (&mut self.ctx_mut().random)
             .sample_iter(Standard)
             .take(len as usize)
-            .collect())
+            .collect();
+        check_replay(&self.ctx().random_replay)?;
+        self.ctx().trace(
+            "random.get-random-bytes",
+            &[TraceValue::U64(len)],
+            &TraceValue::Bytes(&bytes),
+        );
+        Ok(bytes)
     }
 
     fn get_random_u64(&mut self) -> anyhow::Result<u64> {
-        Ok(self.ctx_mut().random.sample(Standard))
+        self.ctx().metrics.increment(Counter::Random);
+        let value = self.ctx_mut().random.sample(Standard);
+        check_replay(&self.ctx().random_replay)?;
+        self.ctx()
+            .trace("random.get-random-u64", &[], &TraceValue::U64(value));
+        Ok(value)
     }
 }
 
 impl<T: WasiView> insecure::Host for T {
     fn get_insecure_random_bytes(&mut self, len: u64) -> anyhow::Result<Vec<u8>> {
-        Ok((&mut self.ctx_mut().insecure_random)
+        self.ctx().metrics.increment(Counter::Random);
+        let bytes: Vec<u8> = (&mut self.ctx_mut().insecure_random)
             .sample_iter(Standard)
             .take(len as usize)
-            .collect())
+            .collect();
+        check_replay(&self.ctx().insecure_random_replay)?;
+        self.ctx().trace(
+            "insecure.get-insecure-random-bytes",
+            &[TraceValue::U64(len)],
+            &TraceValue::Bytes(&bytes),
+        );
+        Ok(bytes)
     }
 
     fn get_insecure_random_u64(&mut self) -> anyhow::Result<u64> {
-        Ok(self.ctx_mut().insecure_random.sample(Standard))
+        self.ctx().metrics.increment(Counter::Random);
+        let value = self.ctx_mut().insecure_random.sample(Standard);
+        check_replay(&self.ctx().insecure_random_replay)?;
+        self.ctx().trace(
+            "insecure.get-insecure-random-u64",
+            &[],
+            &TraceValue::U64(value),
+        );
+        Ok(value)
     }
 }
 
 impl<T: WasiView> insecure_seed::Host for T {
     fn insecure_seed(&mut self) -> anyhow::Result<(u64, u64)> {
+        self.ctx().metrics.increment(Counter::Random);
         let seed: u128 = self.ctx_mut().insecure_random_seed;
-        Ok((seed as u64, (seed >> 64) as u64))
+        let seed = (seed as u64, (seed >> 64) as u64);
+        self.ctx().trace(
+            "insecure-seed.insecure-seed",
+            &[],
+            &TraceValue::List(vec![TraceValue::U64(seed.0), TraceValue::U64(seed.1)]),
+        );
+        Ok(seed)
     }
 }
@@ -1,3 +1,4 @@
+use crate::preview2::metrics::Counter;
 use crate::preview2::tcp::{TcpSocket, TcpState};
 use crate::preview2::{
     bindings::{
@@ -7,7 +8,7 @@ use crate::preview2::{
     },
     tcp::SocketAddressFamily,
 };
-use crate::preview2::{Pollable, SocketResult, WasiView};
+use crate::preview2::{Pollable, ResourceKind, SocketResult, WasiView};
 use cap_net_ext::{Blocking, PoolExt, TcpListenerExt};
 use cap_std::net::TcpListener;
 use io_lifetimes::AsSocketlike;
@@ -26,6 +27,7 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
         network: Resource<Network>,
         local_address: IpSocketAddress,
     ) -> SocketResult<()> {
+        self.ctx().metrics.increment(Counter::Socket);
         let table = self.table_mut();
         let socket = table.get(&this)?;
         let network = table.get(&network)?;
@@ -40,8 +42,20 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
         validate_unicast(&local_address)?;
         validate_address_family(&socket, &local_address)?;
 
+        network.check_policy(&local_address)?;
         let binder = network.pool.tcp_binder(local_address)?;
 
+        // Bind a Unix domain socket instead if the address is redirected to
+        // one, carrying over the options set so far.
+        #[cfg(unix)]
+        if let Some(path) = network.unix_socket_path(&local_address) {
+            let mut bound = TcpSocket::bind_unix(&path, local_address, socket.family)?;
+            bound.listen_backlog_size = socket.listen_backlog_size;
+            bound.tcp_state = TcpState::BindStarted;
+            *table.get_mut(&this)? = bound;
+            return Ok(());
+        }
+
         // Perform the OS bind call.
         binder
             .bind_existing_tcp_listener(&*socket.tcp_socket().as_socketlike_view::<TcpListener>())
@@ -78,6 +92,7 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
         network: Resource<Network>,
         remote_address: IpSocketAddress,
     ) -> SocketResult<()> {
+        self.ctx().metrics.increment(Counter::Socket);
         let table = self.table_mut();
         let r = {
             let socket = table.get(&this)?;
@@ -100,7 +115,38 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
             validate_remote_address(&remote_address)?;
             validate_address_family(&socket, &remote_address)?;
 
-            let connecter = network.pool.tcp_connecter(remote_address)?;
+            let connecter = network
+                .check_policy(&remote_address)
+                .and_then(|()| Ok(network.pool.tcp_connecter(remote_address)?));
+            network.audit("tcp_connect", remote_address, connecter.is_ok());
+            let connecter = connecter?;
+
+            // Connect to the Unix domain socket standing in for the address,
+            // if a guest has bound it.
+            #[cfg(unix)]
+            if let Some(path) = network
+                .unix_socket_path(&remote_address)
+                .filter(|path| path.exists())
+            {
+                let mut connected = TcpSocket::connect_unix(&path, remote_address, socket.family)?;
+                connected.tcp_state = TcpState::ConnectReady;
+                *table.get_mut(&this)? = connected;
+                return Ok(());
+            }
+
+            // Let the embedder's factory make the connection, if there is one,
+            // and replace the unconnected socket with it.
+            if let Some(factory) = network.socket_factory.clone() {
+                let stream = factory.connect(remote_address)?;
+                stream.set_nonblocking(true)?;
+                let mut connected = TcpSocket::from_tcp_stream(
+                    cap_std::net::TcpStream::from_std(stream),
+                    socket.family,
+                )?;
+                connected.tcp_state = TcpState::ConnectReady;
+                *table.get_mut(&this)? = connected;
+                return Ok(());
+            }
 
             // Do an OS `connect`. Our socket is non-blocking, so it'll either...
             {
@@ -174,6 +220,10 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
         let (input, output) = socket.as_split();
         let input_stream = self.table_mut().push_child(input, &this)?;
         let output_stream = self.table_mut().push_child(output, &this)?;
+        self.ctx()
+            .resource_created(ResourceKind::InputStream, input_stream.rep());
+        self.ctx()
+            .resource_created(ResourceKind::OutputStream, output_stream.rep());
 
         Ok((input_stream, output_stream))
     }
@@ -231,6 +281,7 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
         Resource<InputStream>,
         Resource<OutputStream>,
     )> {
+        self.ctx().metrics.increment(Counter::Socket);
         let table = self.table();
         let socket = table.get(&this)?;
 
@@ -238,6 +289,17 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
             TcpState::Listening => {}
             _ => return Err(ErrorCode::InvalidState.into()),
         }
+        if self
+            .ctx()
+            .handle_limit_reached(table, ResourceKind::TcpSocket)
+        {
+            return Err(ErrorCode::NewSocketLimit.into());
+        }
+
+        #[cfg(unix)]
+        if let Some(accepted) = socket.accept_unix() {
+            return push_accepted(self, accepted?);
+        }
 
         // Do the OS accept call.
         let tcp_socket = socket.tcp_socket();
@@ -292,19 +354,8 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
             }
         }
 
-        let mut tcp_socket = TcpSocket::from_tcp_stream(connection, socket.family)?;
-
-        // Mark the socket as connected so that we can exit early from methods like `start-bind`.
-        tcp_socket.tcp_state = TcpState::Connected;
-
-        let (input, output) = tcp_socket.as_split();
-        let output: OutputStream = output;
-
-        let tcp_socket = self.table_mut().push(tcp_socket)?;
-        let input_stream = self.table_mut().push_child(input, &tcp_socket)?;
-        let output_stream = self.table_mut().push_child(output, &tcp_socket)?;
-
-        Ok((tcp_socket, input_stream, output_stream))
+        let tcp_socket = TcpSocket::from_tcp_stream(connection, socket.family)?;
+        push_accepted(self, tcp_socket)
     }
 
     fn local_address(&mut self, this: Resource<tcp::TcpSocket>) -> SocketResult<IpSocketAddress> {
@@ -317,6 +368,9 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
             _ => {}
         }
 
+        if let Some(addrs) = socket.unix_addrs {
+            return Ok(addrs.local.into());
+        }
         let addr = socket
             .tcp_socket()
             .as_socketlike_view::<std::net::TcpStream>()
@@ -333,6 +387,9 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
             _ => return Err(ErrorCode::InvalidState.into()),
         }
 
+        if let Some(addrs) = socket.unix_addrs {
+            return Ok(addrs.remote.into());
+        }
         let addr = socket
             .tcp_socket()
             .as_socketlike_view::<std::net::TcpStream>()
@@ -598,17 +655,48 @@ impl<T: WasiView> crate::preview2::host::tcp::tcp::HostTcpSocket for T {
     }
 
     fn drop(&mut self, this: Resource<tcp::TcpSocket>) -> Result<(), anyhow::Error> {
+        let index = this.rep();
         let table = self.table_mut();
 
         // As in the filesystem implementation, we assume closing a socket
         // doesn't block.
         let dropped = table.delete(this)?;
         drop(dropped);
+        self.ctx().resource_dropped(ResourceKind::TcpSocket, index);
 
         Ok(())
     }
 }
 
+/// Adds a socket returned by `accept` to the table, together with its
+/// streams.
+fn push_accepted<T: WasiView>(
+    view: &mut T,
+    mut tcp_socket: TcpSocket,
+) -> SocketResult<(
+    Resource<tcp::TcpSocket>,
+    Resource<InputStream>,
+    Resource<OutputStream>,
+)> {
+    // Mark the socket as connected so that we can exit early from methods like `start-bind`.
+    tcp_socket.tcp_state = TcpState::Connected;
+
+    let (input, output) = tcp_socket.as_split();
+    let output: OutputStream = output;
+
+    let tcp_socket = view.table_mut().push(tcp_socket)?;
+    let input_stream = view.table_mut().push_child(input, &tcp_socket)?;
+    let output_stream = view.table_mut().push_child(output, &tcp_socket)?;
+    view.ctx()
+        .resource_created(ResourceKind::TcpSocket, tcp_socket.rep());
+    view.ctx()
+        .resource_created(ResourceKind::InputStream, input_stream.rep());
+    view.ctx()
+        .resource_created(ResourceKind::OutputStream, output_stream.rep());
+
+    Ok((tcp_socket, input_stream, output_stream))
+}
+
 // On POSIX, non-blocking TCP socket `connect` uses `EINPROGRESS`.
 // <https://pubs.opengroup.org/onlinepubs/9699919799/functions/connect.html>
 #[cfg(not(windows))]
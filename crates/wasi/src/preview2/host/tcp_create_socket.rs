@@ -1,6 +1,9 @@
-use crate::preview2::bindings::{sockets::network::IpAddressFamily, sockets::tcp_create_socket};
+use crate::preview2::bindings::{
+    sockets::network::{ErrorCode, IpAddressFamily},
+    sockets::tcp_create_socket,
+};
 use crate::preview2::tcp::TcpSocket;
-use crate::preview2::{SocketResult, WasiView};
+use crate::preview2::{ResourceKind, SocketResult, WasiView};
 use wasmtime::component::Resource;
 
 impl<T: WasiView> tcp_create_socket::Host for T {
@@ -8,8 +11,16 @@ impl<T: WasiView> tcp_create_socket::Host for T {
         &mut self,
         address_family: IpAddressFamily,
     ) -> SocketResult<Resource<TcpSocket>> {
+        if self
+            .ctx()
+            .handle_limit_reached(self.table(), ResourceKind::TcpSocket)
+        {
+            return Err(ErrorCode::NewSocketLimit.into());
+        }
         let socket = TcpSocket::new(address_family.into())?;
         let socket = self.table_mut().push(socket)?;
+        self.ctx()
+            .resource_created(ResourceKind::TcpSocket, socket.rep());
         Ok(socket)
     }
 }
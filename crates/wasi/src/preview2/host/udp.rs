@@ -1,5 +1,6 @@
 use std::net::SocketAddr;
 
+use crate::preview2::metrics::Counter;
 use crate::preview2::{
     bindings::{
         sockets::network::{ErrorCode, IpAddressFamily, IpSocketAddress, Network},
@@ -7,7 +8,7 @@ use crate::preview2::{
     },
     udp::UdpState,
 };
-use crate::preview2::{Pollable, SocketResult, WasiView};
+use crate::preview2::{Pollable, ResourceKind, SocketResult, WasiView};
 use cap_net_ext::{AddressFamily, PoolExt};
 use io_lifetimes::AsSocketlike;
 use rustix::io::Errno;
@@ -28,6 +29,7 @@ impl<T: WasiView> crate::preview2::host::udp::udp::HostUdpSocket for T {
         network: Resource<Network>,
         local_address: IpSocketAddress,
     ) -> SocketResult<()> {
+        self.ctx().metrics.increment(Counter::Socket);
         let table = self.table_mut();
         let socket = table.get(&this)?;
 
@@ -40,6 +42,7 @@ impl<T: WasiView> crate::preview2::host::udp::udp::HostUdpSocket for T {
         }
 
         let network = table.get(&network)?;
+        network.check_policy(&local_address.into())?;
         let binder = network.pool.udp_binder(local_address)?;
 
         // Perform the OS bind call.
@@ -74,6 +77,7 @@ impl<T: WasiView> crate::preview2::host::udp::udp::HostUdpSocket for T {
         network: Resource<Network>,
         remote_address: IpSocketAddress,
     ) -> SocketResult<()> {
+        self.ctx().metrics.increment(Counter::Socket);
         let table = self.table_mut();
         let socket = table.get(&this)?;
         let network = table.get(&network)?;
@@ -86,7 +90,12 @@ impl<T: WasiView> crate::preview2::host::udp::udp::HostUdpSocket for T {
             UdpState::Connected(..) => return Err(ErrorCode::InvalidState.into()),
         }
 
-        let connecter = network.pool.udp_connecter(remote_address)?;
+        let addr = SocketAddr::from(remote_address);
+        let connecter = network
+            .check_policy(&addr)
+            .and_then(|()| Ok(network.pool.udp_connecter(addr)?));
+        network.audit("udp_connect", addr, connecter.is_ok());
+        let connecter = connecter?;
 
         // Do an OS `connect`.
         connecter.connect_existing_udp_socket(
@@ -118,6 +127,7 @@ impl<T: WasiView> crate::preview2::host::udp::udp::HostUdpSocket for T {
         this: Resource<udp::UdpSocket>,
         max_results: u64,
     ) -> SocketResult<Vec<udp::Datagram>> {
+        self.ctx().metrics.increment(Counter::Socket);
         if max_results == 0 {
             return Ok(vec![]);
         }
@@ -167,6 +177,7 @@ impl<T: WasiView> crate::preview2::host::udp::udp::HostUdpSocket for T {
         this: Resource<udp::UdpSocket>,
         datagrams: Vec<udp::Datagram>,
     ) -> SocketResult<u64> {
+        self.ctx().metrics.increment(Counter::Socket);
         if datagrams.is_empty() {
             return Ok(0);
         };
@@ -183,7 +194,9 @@ impl<T: WasiView> crate::preview2::host::udp::udp::HostUdpSocket for T {
                     remote_address,
                 } in datagrams
                 {
-                    match udp_socket.try_send_to(&data, remote_address.into()) {
+                    let remote_address = SocketAddr::from(remote_address);
+                    self.ctx().audit_network("udp_send", remote_address, true);
+                    match udp_socket.try_send_to(&data, remote_address) {
                         Ok(_size) => count += 1,
                         Err(_e) if count > 0 => {
                             return Ok(count);
@@ -208,6 +221,7 @@ impl<T: WasiView> crate::preview2::host::udp::udp::HostUdpSocket for T {
                             return Ok(count);
                         }
                     }
+                    self.ctx().audit_network("udp_send", addr, true);
                     match udp_socket.try_send(&data) {
                         Ok(_size) => count += 1,
                         Err(_e) if count > 0 => {
@@ -344,12 +358,14 @@ impl<T: WasiView> crate::preview2::host::udp::udp::HostUdpSocket for T {
     }
 
     fn drop(&mut self, this: Resource<udp::UdpSocket>) -> Result<(), anyhow::Error> {
+        let index = this.rep();
         let table = self.table_mut();
 
         // As in the filesystem implementation, we assume closing a socket
         // doesn't block.
         let dropped = table.delete(this)?;
         drop(dropped);
+        self.ctx().resource_dropped(ResourceKind::UdpSocket, index);
 
         Ok(())
     }
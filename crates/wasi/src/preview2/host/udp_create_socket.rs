@@ -1,6 +1,9 @@
-use crate::preview2::bindings::{sockets::network::IpAddressFamily, sockets::udp_create_socket};
+use crate::preview2::bindings::{
+    sockets::network::{ErrorCode, IpAddressFamily},
+    sockets::udp_create_socket,
+};
 use crate::preview2::udp::UdpSocket;
-use crate::preview2::{SocketResult, WasiView};
+use crate::preview2::{ResourceKind, SocketResult, WasiView};
 use wasmtime::component::Resource;
 
 impl<T: WasiView> udp_create_socket::Host for T {
@@ -8,8 +11,16 @@ impl<T: WasiView> udp_create_socket::Host for T {
         &mut self,
         address_family: IpAddressFamily,
     ) -> SocketResult<Resource<UdpSocket>> {
+        if self
+            .ctx()
+            .handle_limit_reached(self.table(), ResourceKind::UdpSocket)
+        {
+            return Err(ErrorCode::NewSocketLimit.into());
+        }
         let socket = UdpSocket::new(address_family.into())?;
         let socket = self.table_mut().push(socket)?;
+        self.ctx()
+            .resource_created(ResourceKind::UdpSocket, socket.rep());
         Ok(socket)
     }
 }
@@ -34,6 +34,7 @@ impl<T: WasiView> Host for T {
             url::Host::Ipv6(_) => return Err(ErrorCode::InvalidArgument.into()),
         };
 
+        network.audit("dns_resolve", &name, network.allow_ip_name_lookup);
         if !network.allow_ip_name_lookup {
             return Err(ErrorCode::PermanentResolverFailure.into());
         }
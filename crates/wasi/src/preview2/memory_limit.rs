@@ -0,0 +1,265 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use wasmtime::{LinearMemory, MemoryCreator, MemoryType, ResourceLimiterAsync};
+
+/// A [`MemoryCreator`] which limits the total size of all linear memories it
+/// creates, set up with
+/// [`WasiCtxBuilder::memory_limit_bytes`](crate::preview2::WasiCtxBuilder::memory_limit_bytes).
+///
+/// Creating or growing a memory fails once the combined size of all live
+/// memories would exceed the limit. This is a simpler alternative to
+/// implementing [`wasmtime::ResourceLimiter`] for the common case of a single
+/// component.
+///
+/// A memory creator is installed on the whole engine through
+/// [`Config::with_host_memory`](wasmtime::Config::with_host_memory), so all
+/// stores of that engine share the limit. Memories are allocated on the heap
+/// and may move when they grow, so the engine must be configured to use
+/// dynamic memories without guard regions:
+///
+/// ```
+/// use std::sync::Arc;
+/// use wasmtime::{Config, Engine};
+/// use wasmtime_wasi::preview2::BoundedMemoryCreator;
+///
+/// let creator = Arc::new(BoundedMemoryCreator::new(16 << 20));
+/// let mut config = Config::new();
+/// config
+///     .with_host_memory(creator.clone())
+///     .static_memory_maximum_size(0)
+///     .dynamic_memory_guard_size(0);
+/// let engine = Engine::new(&config).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct BoundedMemoryCreator {
+    limit: usize,
+    usage: Arc<AtomicUsize>,
+}
+
+impl BoundedMemoryCreator {
+    /// Creates a memory creator allowing at most `limit` bytes of memory.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            usage: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns the limit on the total size of all memories, in bytes.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the total size of all live memories created by this creator,
+    /// in bytes.
+    pub fn current_usage(&self) -> usize {
+        self.usage.load(Ordering::Relaxed)
+    }
+}
+
+/// Adds `bytes` to `usage` unless that would exceed `limit`.
+fn try_reserve(usage: &AtomicUsize, limit: usize, bytes: usize) -> bool {
+    usage
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+            usage.checked_add(bytes).filter(|total| *total <= limit)
+        })
+        .is_ok()
+}
+
+unsafe impl MemoryCreator for BoundedMemoryCreator {
+    fn new_memory(
+        &self,
+        _ty: MemoryType,
+        minimum: usize,
+        maximum: Option<usize>,
+        reserved_size_in_bytes: Option<usize>,
+        guard_size_in_bytes: usize,
+    ) -> Result<Box<dyn LinearMemory>, String> {
+        if reserved_size_in_bytes.is_some() || guard_size_in_bytes > 0 {
+            return Err(
+                "BoundedMemoryCreator requires dynamic memories without guard regions".to_string(),
+            );
+        }
+        if !try_reserve(&self.usage, self.limit, minimum) {
+            return Err(format!(
+                "memory of {minimum} bytes would exceed the limit of {} bytes",
+                self.limit
+            ));
+        }
+        let mut data = vec![0; minimum];
+        Ok(Box::new(BoundedMemory {
+            ptr: data.as_mut_ptr(),
+            data,
+            maximum,
+            limit: self.limit,
+            usage: self.usage.clone(),
+        }))
+    }
+}
+
+/// A heap-allocated linear memory whose size counts towards the limit of a
+/// [`BoundedMemoryCreator`].
+struct BoundedMemory {
+    data: Vec<u8>,
+    // Derived from `data` through a mutable borrow, since wasm writes to the
+    // memory through it.
+    ptr: *mut u8,
+    maximum: Option<usize>,
+    limit: usize,
+    usage: Arc<AtomicUsize>,
+}
+
+// The raw pointer only ever points into `data`, which is owned.
+unsafe impl Send for BoundedMemory {}
+unsafe impl Sync for BoundedMemory {}
+
+unsafe impl LinearMemory for BoundedMemory {
+    fn byte_size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn maximum_byte_size(&self) -> Option<usize> {
+        self.maximum
+    }
+
+    fn grow_to(&mut self, new_size: usize) -> wasmtime::Result<()> {
+        let delta = new_size.saturating_sub(self.data.len());
+        if !try_reserve(&self.usage, self.limit, delta) {
+            anyhow::bail!(
+                "growing memory to {new_size} bytes would exceed the limit of {} bytes",
+                self.limit
+            );
+        }
+        self.data.resize(new_size, 0);
+        self.ptr = self.data.as_mut_ptr();
+        Ok(())
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    fn wasm_accessible(&self) -> Range<usize> {
+        let base = self.ptr as usize;
+        base..base + self.data.len()
+    }
+}
+
+impl Drop for BoundedMemory {
+    fn drop(&mut self) {
+        self.usage.fetch_sub(self.data.len(), Ordering::Relaxed);
+    }
+}
+
+/// The resource limiter of a [`WasiCtx`](crate::preview2::WasiCtx) when none
+/// is configured, which allows all growth and has the default instance,
+/// table and memory limits.
+pub(crate) struct Unlimited;
+
+#[async_trait::async_trait]
+impl ResourceLimiterAsync for Unlimited {
+    async fn memory_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+
+    async fn table_growing(
+        &mut self,
+        _current: u32,
+        _desired: u32,
+        _maximum: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// The resource limiter of a [`WasiCtx`](crate::preview2::WasiCtx) with
+/// [`WasiCtxBuilder::memory_limit_bytes`](crate::preview2::WasiCtxBuilder::memory_limit_bytes),
+/// enforcing the limit on the memories of the store it is installed in
+/// before consulting `inner`.
+///
+/// Memories are only freed with their store, so growth is never given back
+/// except when it fails.
+pub(crate) struct MemoryLimiter {
+    inner: Box<dyn ResourceLimiterAsync + Send + Sync>,
+    limit: usize,
+    usage: usize,
+    /// The growth allowed by the last call to `memory_growing`, undone if
+    /// the growth fails.
+    last_growth: usize,
+}
+
+impl MemoryLimiter {
+    /// Wraps `inner` into a limiter enforcing `limit`, if any.
+    pub(crate) fn wrap(
+        inner: Box<dyn ResourceLimiterAsync + Send + Sync>,
+        limit: Option<usize>,
+    ) -> Box<dyn ResourceLimiterAsync + Send + Sync> {
+        match limit {
+            Some(limit) => Box::new(MemoryLimiter {
+                inner,
+                limit,
+                usage: 0,
+                last_growth: 0,
+            }),
+            None => inner,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ResourceLimiterAsync for MemoryLimiter {
+    async fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let growth = desired.saturating_sub(current);
+        match self.usage.checked_add(growth) {
+            Some(total) if total <= self.limit => {}
+            _ => return Ok(false),
+        }
+        let allowed = self.inner.memory_growing(current, desired, maximum).await?;
+        if allowed {
+            self.usage += growth;
+            self.last_growth = growth;
+        }
+        Ok(allowed)
+    }
+
+    fn memory_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.usage -= std::mem::take(&mut self.last_growth);
+        self.inner.memory_grow_failed(error)
+    }
+
+    async fn table_growing(
+        &mut self,
+        current: u32,
+        desired: u32,
+        maximum: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        self.inner.table_growing(current, desired, maximum).await
+    }
+
+    fn table_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.inner.table_grow_failed(error)
+    }
+
+    fn instances(&self) -> usize {
+        self.inner.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.inner.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.inner.memories()
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters for the WASI host functions invoked through a
+/// [`WasiCtx`](crate::preview2::WasiCtx), grouped by function family.
+///
+/// Counting is disabled by default and must be enabled with
+/// [`WasiCtxBuilder::enable_metrics`](crate::preview2::WasiCtxBuilder::enable_metrics).
+/// When disabled, all counters stay at zero.
+#[derive(Debug, Default)]
+pub struct WasiMetrics {
+    enabled: bool,
+    stream_reads: AtomicU64,
+    stream_writes: AtomicU64,
+    path_opens: AtomicU64,
+    filesystem_ops: AtomicU64,
+    socket_ops: AtomicU64,
+    random_calls: AtomicU64,
+    clock_calls: AtomicU64,
+}
+
+/// A point-in-time copy of the counters of a [`WasiMetrics`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WasiMetricsSnapshot {
+    /// Number of reads and skips on input streams, and of reads from file
+    /// descriptors.
+    pub stream_reads: u64,
+    /// Number of writes on output streams, including writes of zeroes, and of
+    /// writes to file descriptors.
+    pub stream_writes: u64,
+    /// Number of files and directories opened with `open-at`.
+    pub path_opens: u64,
+    /// Number of other path-based filesystem operations, such as `stat-at`,
+    /// `unlink-file-at` or `rename-at`.
+    pub filesystem_ops: u64,
+    /// Number of socket binds, connects and accepts, and of UDP sends and
+    /// receives.
+    pub socket_ops: u64,
+    /// Number of requests for random data.
+    pub random_calls: u64,
+    /// Number of reads of the wall and monotonic clocks.
+    pub clock_calls: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Counter {
+    StreamRead,
+    StreamWrite,
+    PathOpen,
+    Filesystem,
+    Socket,
+    Random,
+    Clock,
+}
+
+impl WasiMetrics {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Self::default()
+        }
+    }
+
+    /// Returns whether counting is enabled for this context.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn increment(&self, counter: Counter) {
+        if !self.enabled {
+            return;
+        }
+        let counter = match counter {
+            Counter::StreamRead => &self.stream_reads,
+            Counter::StreamWrite => &self.stream_writes,
+            Counter::PathOpen => &self.path_opens,
+            Counter::Filesystem => &self.filesystem_ops,
+            Counter::Socket => &self.socket_ops,
+            Counter::Random => &self.random_calls,
+            Counter::Clock => &self.clock_calls,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns a copy of the current value of all counters.
+    ///
+    /// Counters are read individually, so a snapshot taken while the guest
+    /// is running is not necessarily consistent across counters.
+    pub fn snapshot(&self) -> WasiMetricsSnapshot {
+        WasiMetricsSnapshot {
+            stream_reads: self.stream_reads.load(Ordering::Relaxed),
+            stream_writes: self.stream_writes.load(Ordering::Relaxed),
+            path_opens: self.path_opens.load(Ordering::Relaxed),
+            filesystem_ops: self.filesystem_ops.load(Ordering::Relaxed),
+            socket_ops: self.socket_ops.load(Ordering::Relaxed),
+            random_calls: self.random_calls.load(Ordering::Relaxed),
+            clock_calls: self.clock_calls.load(Ordering::Relaxed),
+        }
+    }
+}
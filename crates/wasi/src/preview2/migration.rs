@@ -0,0 +1,73 @@
+use crate::preview2::{DirPerms, FilePerms, NetworkPoolCapture};
+use serde_derive::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// The portable WASI state of a [`WasiCtx`](crate::preview2::WasiCtx), used
+/// to hand a running component off to another host process.
+///
+/// Obtained with [`WasiCtx::export_state`](crate::preview2::WasiCtx::export_state)
+/// and replayed into a new context with
+/// [`WasiCtxBuilder::import_wasi_state`](crate::preview2::WasiCtxBuilder::import_wasi_state).
+/// Together with a [`Snapshot`](wasmtime::Snapshot) of the component's
+/// instances this is enough to continue the component elsewhere.
+///
+/// Only state which can be recreated in another process is exported: the
+/// environment except for secrets, the arguments, the preopens made by host path with
+/// [`WasiCtxBuilder::preopened_dir_at_host_path`](crate::preview2::WasiCtxBuilder::preopened_dir_at_host_path),
+/// the network pool and the offsets of the clocks. Open files, streams and
+/// sockets, stdio, random generators, and host callbacks are not exported.
+///
+/// The state is serialized with `serde`. It carries a format version which is
+/// checked upon import.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WasiStateExport {
+    pub(crate) version: u32,
+    pub(crate) env: Vec<(String, String)>,
+    pub(crate) args: Vec<String>,
+    pub(crate) preopens: Vec<PreopenExport>,
+    pub(crate) network: NetworkPoolCapture,
+    pub(crate) allow_ip_name_lookup: bool,
+    /// Nanoseconds the wall clock was ahead of the host's system clock.
+    pub(crate) wall_clock_offset: i64,
+    /// The reading of the monotonic clock at export.
+    pub(crate) monotonic_clock: u64,
+}
+
+impl WasiStateExport {
+    /// The current version of the format, bumped whenever the exported
+    /// state changes in an incompatible way.
+    pub(crate) const VERSION: u32 = 1;
+}
+
+/// A directory preopened by host path.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PreopenExport {
+    pub(crate) host_path: PathBuf,
+    pub(crate) guest_path: String,
+    pub(crate) dir_perms: usize,
+    pub(crate) file_perms: usize,
+}
+
+impl PreopenExport {
+    pub(crate) fn new(
+        host_path: PathBuf,
+        guest_path: String,
+        dir_perms: DirPerms,
+        file_perms: FilePerms,
+    ) -> PreopenExport {
+        PreopenExport {
+            host_path,
+            guest_path,
+            dir_perms: dir_perms.bits(),
+            file_perms: file_perms.bits(),
+        }
+    }
+
+    pub(crate) fn dir_perms(&self) -> DirPerms {
+        DirPerms::from_bits_truncate(self.dir_perms)
+    }
+
+    pub(crate) fn file_perms(&self) -> FilePerms {
+        FilePerms::from_bits_truncate(self.file_perms)
+    }
+}
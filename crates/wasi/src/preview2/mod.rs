@@ -19,40 +19,90 @@ use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+mod async_pipe;
+mod audit;
+mod buffered;
+mod byte_count;
+mod capability;
 mod clocks;
 pub mod command;
+mod config;
 mod ctx;
+mod encoding;
 mod error;
+mod events;
+mod fake_filesystem;
 mod filesystem;
 mod host;
 mod ip_name_lookup;
+mod memory_limit;
+mod metrics;
+mod migration;
 mod network;
+mod overlay;
+mod pause;
 pub mod pipe;
+mod policy;
 mod poll;
 #[cfg(feature = "preview1-on-preview2")]
 pub mod preview1;
+mod profiler;
+mod quota;
 mod random;
+mod rate_limit;
+mod scheduler;
+mod secrets;
+mod signals;
+mod stats;
 mod stdio;
 mod stream;
+mod stream_buffers;
 mod table;
 mod tcp;
+mod tee;
+mod threads;
+mod trace;
+mod tracing_stream;
 mod udp;
 mod write_stream;
 
-pub use self::clocks::{HostMonotonicClock, HostWallClock};
-pub use self::ctx::{WasiCtx, WasiCtxBuilder, WasiView};
-pub use self::error::{I32Exit, TrappableError};
-pub use self::filesystem::{DirPerms, FilePerms, FsError, FsResult};
-pub use self::network::{Network, SocketError, SocketResult};
+pub use self::capability::{CapabilityIssuer, CapabilityToken};
+pub use self::clocks::{
+    ClockSource, DeadlineBehavior, DeadlineExceeded, FrozenClock, HostMonotonicClock, HostWallClock,
+};
+pub use self::ctx::{add_to_snapshots, WasiCtx, WasiCtxBuilder, WasiView};
+pub use self::encoding::{TextConversion, TextEncoding};
+pub use self::error::{ExitBehavior, I32Exit, TrappableError};
+pub use self::events::WasiEvent;
+pub use self::fake_filesystem::{FakeEntry, FakeFilesystem};
+pub use self::filesystem::{Dir, DirPerms, File, FilePerms, FsError, FsResult};
+pub use self::memory_limit::BoundedMemoryCreator;
+pub use self::metrics::{WasiMetrics, WasiMetricsSnapshot};
+pub use self::migration::WasiStateExport;
+pub use self::network::{Network, NetworkPoolCapture, SocketError, SocketResult, TcpSocketFactory};
+pub use self::pause::{IoGuard, PausedIo};
+pub use self::policy::{CapabilityPolicy, ScopePolicy};
 pub use self::poll::{subscribe, ClosureFuture, MakeFuture, Pollable, PollableFuture, Subscribe};
-pub use self::random::{thread_rng, Deterministic};
+pub use self::profiler::{ComponentProfiler, SamplingProfiler};
+pub use self::quota::{DirQuota, DirQuotaUsage};
+pub use self::random::{thread_rng, Deterministic, RecordedRng, RecordingRng, ReplayRng};
+pub use self::rate_limit::IoRateLimitConfig;
+pub use self::scheduler::{LatencyBoundScheduler, RoundRobinScheduler, WasmScheduler};
+pub use self::secrets::{EnvSecretProvider, SecretProvider};
+pub use self::signals::{PosixSignalHandler, SignalAction};
+pub use self::stats::{ExecutionStats, ResourceUsageEvent};
 pub use self::stdio::{
     stderr, stdin, stdout, IsATTY, Stderr, Stdin, StdinStream, Stdout, StdoutStream,
 };
 pub use self::stream::{
     HostInputStream, HostOutputStream, InputStream, OutputStream, StreamError, StreamResult,
 };
-pub use self::table::{Table, TableError};
+pub use self::stream_buffers::StreamBufferCapture;
+pub use self::table::{ReservationHandle, ResourceKind, Table, TableCompactionResult, TableError};
+pub use self::tcp::TcpSocket;
+pub use self::tee::{TeeInputStream, TeeOutputStream};
+pub use self::threads::WasiThreadSpawner;
+pub use self::udp::UdpSocket;
 pub use cap_fs_ext::SystemTimeSpec;
 pub use cap_rand::RngCore;
 
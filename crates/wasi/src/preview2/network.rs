@@ -1,10 +1,149 @@
+use crate::preview2::audit::NetworkAuditLog;
 use crate::preview2::bindings::wasi::sockets::network::ErrorCode;
-use crate::preview2::{TableError, TrappableError};
+use crate::preview2::{CapabilityPolicy, TableError, TrappableError};
+use cap_std::ambient_authority;
+use cap_std::ipnet::IpNet;
 use cap_std::net::Pool;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 pub struct Network {
     pub pool: Pool,
     pub allow_ip_name_lookup: bool,
+    pub policy: Arc<CapabilityPolicy>,
+    pub(crate) socket_factory: Option<Arc<dyn TcpSocketFactory>>,
+    pub(crate) unix_socket_dir: Option<PathBuf>,
+    pub(crate) audit_log: Option<Arc<NetworkAuditLog>>,
+}
+
+/// Creates the connections of TCP sockets in place of the OS, as set with
+/// [`WasiCtxBuilder::with_socket_factory`](crate::preview2::WasiCtxBuilder::with_socket_factory).
+///
+/// This allows mocking the network in tests, proxying connections or
+/// measuring them. Connections are only requested for addresses the guest is
+/// granted access to, after the network pool and the capability policy were
+/// consulted.
+pub trait TcpSocketFactory: Send + Sync {
+    /// Connects to `addr`, returning the connected stream.
+    ///
+    /// This is called from `start-connect` and blocks the guest until it
+    /// returns. An error is reported to the guest as a failure to connect.
+    fn connect(&self, addr: SocketAddr) -> std::io::Result<std::net::TcpStream>;
+}
+
+impl<F> TcpSocketFactory for F
+where
+    F: Fn(SocketAddr) -> std::io::Result<std::net::TcpStream> + Send + Sync,
+{
+    fn connect(&self, addr: SocketAddr) -> std::io::Result<std::net::TcpStream> {
+        self(addr)
+    }
+}
+
+impl Network {
+    /// Fails with `AccessDenied` if the capability policy denies access to
+    /// `addr`.
+    pub(crate) fn check_policy(&self, addr: &SocketAddr) -> SocketResult<()> {
+        if self.policy.is_addr_allowed(addr.ip()) {
+            Ok(())
+        } else {
+            Err(ErrorCode::AccessDenied.into())
+        }
+    }
+
+    /// Returns the Unix domain socket which stands in for the TCP address
+    /// `addr`, if it is a loopback address with a port and
+    /// [Unix sockets](crate::preview2::WasiCtxBuilder::allow_unix_sockets)
+    /// are enabled.
+    #[cfg(unix)]
+    pub(crate) fn unix_socket_path(&self, addr: &SocketAddr) -> Option<PathBuf> {
+        let dir = self.unix_socket_dir.as_ref()?;
+        match addr {
+            SocketAddr::V4(addr) if addr.ip().is_loopback() && addr.port() != 0 => {
+                Some(dir.join(format!("{}-{}.sock", addr.ip(), addr.port())))
+            }
+            _ => None,
+        }
+    }
+
+    /// Records an access to `addr` in the
+    /// [network audit log](crate::preview2::WasiCtxBuilder::network_audit_log),
+    /// if there is one.
+    pub(crate) fn audit(&self, event: &str, addr: impl fmt::Display, allowed: bool) {
+        if let Some(log) = &self.audit_log {
+            log.record(event, addr, allowed);
+        }
+    }
+}
+
+/// Returns the unspecified IPv4 and IPv6 addresses followed by the
+/// addresses of the network interfaces of the host, for
+/// [`WasiCtxBuilder::inherit_network_filtered`](crate::preview2::WasiCtxBuilder::inherit_network_filtered).
+#[cfg(unix)]
+pub(crate) fn host_addresses() -> std::io::Result<Vec<IpAddr>> {
+    let mut addrs: Vec<IpAddr> = vec![Ipv4Addr::UNSPECIFIED.into(), Ipv6Addr::UNSPECIFIED.into()];
+    let mut ifaddrs = std::ptr::null_mut();
+    // SAFETY: `ifaddrs` is a valid location for the list to be stored in.
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut cursor = ifaddrs;
+    while !cursor.is_null() {
+        // SAFETY: `cursor` points into the list returned by `getifaddrs`,
+        // which is only freed below.
+        let ifaddr = unsafe { &*cursor };
+        // SAFETY: `ifa_addr` is null or points to a socket address of the
+        // family it is tagged with.
+        if let Some(addr) = unsafe { ip_of(ifaddr.ifa_addr) } {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        cursor = ifaddr.ifa_next;
+    }
+    // SAFETY: `ifaddrs` was returned by `getifaddrs` and is not used again.
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    Ok(addrs)
+}
+
+/// Returns the IP address of `sa`, if it is an IPv4 or IPv6 address.
+///
+/// # Safety
+///
+/// `sa` must be null or point to a socket address of the family it is tagged
+/// with.
+#[cfg(unix)]
+unsafe fn ip_of(sa: *const libc::sockaddr) -> Option<IpAddr> {
+    if sa.is_null() {
+        return None;
+    }
+    match i32::from((*sa).sa_family) {
+        libc::AF_INET => {
+            let sin = &*sa.cast::<libc::sockaddr_in>();
+            Some(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)).into())
+        }
+        libc::AF_INET6 => {
+            let sin6 = &*sa.cast::<libc::sockaddr_in6>();
+            Some(Ipv6Addr::from(sin6.sin6_addr.s6_addr).into())
+        }
+        _ => None,
+    }
+}
+
+/// Returns the unspecified IPv4 and IPv6 addresses followed by the loopback
+/// addresses, as the interfaces of the host cannot be enumerated on this
+/// platform.
+#[cfg(not(unix))]
+pub(crate) fn host_addresses() -> std::io::Result<Vec<IpAddr>> {
+    Ok(vec![
+        Ipv4Addr::UNSPECIFIED.into(),
+        Ipv6Addr::UNSPECIFIED.into(),
+        Ipv4Addr::LOCALHOST.into(),
+        Ipv6Addr::LOCALHOST.into(),
+    ])
 }
 
 pub type SocketResult<T> = Result<T, SocketError>;
@@ -28,3 +167,121 @@ impl From<rustix::io::Errno> for SocketError {
         ErrorCode::from(error).into()
     }
 }
+
+/// A serializable record of the network addresses granted to a
+/// [`WasiCtx`](crate::preview2::WasiCtx).
+///
+/// [`Pool`] does not expose the addresses it grants access to, so
+/// [`WasiCtxBuilder`](crate::preview2::WasiCtxBuilder) records each grant as
+/// it is made. The record can be obtained with
+/// [`WasiCtx::capture_pool`](crate::preview2::WasiCtx::capture_pool) and
+/// replayed into a new context with
+/// [`WasiCtxBuilder::pool_from_capture`](crate::preview2::WasiCtxBuilder::pool_from_capture).
+/// Snapshots of stores set up with
+/// [`add_to_snapshots`](crate::preview2::add_to_snapshots) record it too.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkPoolCapture {
+    grants: Vec<NetworkGrant>,
+}
+
+/// A single grant, mirroring the `Pool` method it was made with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum NetworkGrant {
+    SocketAddr(SocketAddr),
+    IpNet {
+        addr: IpAddr,
+        prefix_len: u8,
+        port: u16,
+    },
+    IpNetPortAny {
+        addr: IpAddr,
+        prefix_len: u8,
+    },
+    IpNetPortRange {
+        addr: IpAddr,
+        prefix_len: u8,
+        ports_start: u16,
+        ports_end: Option<u16>,
+    },
+}
+
+impl NetworkPoolCapture {
+    pub(crate) fn socket_addr(&mut self, addr: SocketAddr) {
+        self.grants.push(NetworkGrant::SocketAddr(addr));
+    }
+
+    pub(crate) fn ip_net(&mut self, ip_net: IpNet, port: u16) {
+        self.grants.push(NetworkGrant::IpNet {
+            addr: ip_net.addr(),
+            prefix_len: ip_net.prefix_len(),
+            port,
+        });
+    }
+
+    pub(crate) fn ip_net_port_any(&mut self, ip_net: IpNet) {
+        self.grants.push(NetworkGrant::IpNetPortAny {
+            addr: ip_net.addr(),
+            prefix_len: ip_net.prefix_len(),
+        });
+    }
+
+    pub(crate) fn ip_net_port_range(
+        &mut self,
+        ip_net: IpNet,
+        ports_start: u16,
+        ports_end: Option<u16>,
+    ) {
+        self.grants.push(NetworkGrant::IpNetPortRange {
+            addr: ip_net.addr(),
+            prefix_len: ip_net.prefix_len(),
+            ports_start,
+            ports_end,
+        });
+    }
+
+    pub(crate) fn grants_from(&mut self, other: &NetworkPoolCapture) {
+        self.grants.extend(other.grants.iter().cloned());
+    }
+
+    /// Inserts every recorded grant into `pool`.
+    ///
+    /// Fails if the capture contains an invalid prefix length, which can only
+    /// happen if it was deserialized from corrupt data.
+    pub(crate) fn apply(&self, pool: &mut Pool) -> std::io::Result<()> {
+        let net = |addr: IpAddr, prefix_len: u8| {
+            IpNet::new(addr, prefix_len)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+        };
+        for grant in self.grants.iter() {
+            match *grant {
+                NetworkGrant::SocketAddr(addr) => {
+                    pool.insert_socket_addr(addr, ambient_authority());
+                }
+                NetworkGrant::IpNet {
+                    addr,
+                    prefix_len,
+                    port,
+                } => {
+                    pool.insert_ip_net(net(addr, prefix_len)?, port, ambient_authority());
+                }
+                NetworkGrant::IpNetPortAny { addr, prefix_len } => {
+                    pool.insert_ip_net_port_any(net(addr, prefix_len)?, ambient_authority());
+                }
+                NetworkGrant::IpNetPortRange {
+                    addr,
+                    prefix_len,
+                    ports_start,
+                    ports_end,
+                } => {
+                    pool.insert_ip_net_port_range(
+                        net(addr, prefix_len)?,
+                        ports_start,
+                        ports_end,
+                        ambient_authority(),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
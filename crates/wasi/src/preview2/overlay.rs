@@ -0,0 +1,58 @@
+use cap_std::fs::Dir;
+use std::io;
+use std::path::Path;
+
+/// Runs `op` on `path` in `upper`, or in `base` if `upper` has no entry at
+/// `path`.
+pub(crate) fn fall_through<R>(
+    upper: &Dir,
+    base: Option<&Dir>,
+    op: impl Fn(&Dir) -> io::Result<R>,
+) -> io::Result<R> {
+    match (op(upper), base) {
+        (Err(e), Some(base)) if e.kind() == io::ErrorKind::NotFound => op(base),
+        (result, _) => result,
+    }
+}
+
+/// Returns whether `path` exists in `base` but not in `upper`.
+pub(crate) fn only_in_base(upper: &Dir, base: Option<&Dir>, path: &str) -> bool {
+    match base {
+        Some(base) => upper.symlink_metadata(path).is_err() && base.symlink_metadata(path).is_ok(),
+        None => false,
+    }
+}
+
+/// Returns whether `path` exists in `base`.
+pub(crate) fn in_base(base: Option<&Dir>, path: &str) -> bool {
+    base.map_or(false, |base| base.symlink_metadata(path).is_ok())
+}
+
+/// Copies the entry at `path` of `base` into `upper`, along with its parent
+/// directories, unless `upper` already has an entry at `path`.
+///
+/// Regular files are copied with their contents, and directories without
+/// their entries, which keep falling through to the base.
+pub(crate) fn copy_up(upper: &Dir, base: Option<&Dir>, path: &str) -> io::Result<()> {
+    if !only_in_base(upper, base, path) {
+        return Ok(());
+    }
+    let base = base.unwrap();
+    if base.symlink_metadata(path)?.is_dir() {
+        return upper.create_dir_all(path);
+    }
+    copy_up_parent(upper, Some(base), path)?;
+    upper.write(path, base.read(path)?)
+}
+
+/// Copies the parent directory of `path` into `upper` if it only exists in
+/// `base`, so that an entry can be created at `path` in `upper`.
+pub(crate) fn copy_up_parent(upper: &Dir, base: Option<&Dir>, path: &str) -> io::Result<()> {
+    match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            let parent = parent.to_string_lossy();
+            copy_up(upper, base, &parent)
+        }
+        _ => Ok(()),
+    }
+}
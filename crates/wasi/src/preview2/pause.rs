@@ -0,0 +1,159 @@
+use crate::preview2::{HostOutputStream, StdoutStream, StreamResult, Subscribe};
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+
+/// The number of bytes a paused stream reports it can accept, so that the
+/// guest is never blocked while its output is held back.
+const PAUSED_READY_SIZE: usize = 1024 * 1024;
+
+/// The output held back while I/O is paused with
+/// [`WasiCtx::pause_io`](crate::preview2::WasiCtx::pause_io), or `None` if it
+/// is not paused.
+pub(crate) type PauseBuffer = Arc<Mutex<Option<Vec<u8>>>>;
+
+/// The pause buffers of stdout and stderr shared by a context and its
+/// streams.
+#[derive(Clone, Default)]
+pub(crate) struct PausedIoBuffers {
+    pub(crate) stdout: PauseBuffer,
+    pub(crate) stderr: PauseBuffer,
+}
+
+/// Holds back the output of the guest to stdout and stderr, returned by
+/// [`WasiCtx::pause_io`](crate::preview2::WasiCtx::pause_io).
+///
+/// Writes are collected in memory until the guard is released with
+/// [`IoGuard::release`], which returns them, or dropped, which discards
+/// them. Either way, later writes go to the configured streams again.
+#[must_use = "output is only held back while the guard is alive"]
+pub struct IoGuard {
+    buffers: PausedIoBuffers,
+}
+
+/// The output held back by an [`IoGuard`], returned by [`IoGuard::release`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PausedIo {
+    /// The bytes written to stdout, in order.
+    pub stdout: Vec<u8>,
+    /// The bytes written to stderr, in order.
+    pub stderr: Vec<u8>,
+}
+
+impl IoGuard {
+    /// Pauses `buffers`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if they are already paused.
+    pub(crate) fn new(buffers: &PausedIoBuffers) -> Self {
+        for buffer in [&buffers.stdout, &buffers.stderr] {
+            let mut buffer = buffer.lock().unwrap();
+            assert!(buffer.is_none(), "I/O is already paused");
+            *buffer = Some(Vec::new());
+        }
+        Self {
+            buffers: buffers.clone(),
+        }
+    }
+
+    /// Resumes I/O and returns the output held back since it was paused.
+    pub fn release(self) -> PausedIo {
+        PausedIo {
+            stdout: take(&self.buffers.stdout),
+            stderr: take(&self.buffers.stderr),
+        }
+    }
+}
+
+impl Drop for IoGuard {
+    fn drop(&mut self) {
+        take(&self.buffers.stdout);
+        take(&self.buffers.stderr);
+    }
+}
+
+/// Resumes `buffer`, returning the bytes it holds.
+fn take(buffer: &PauseBuffer) -> Vec<u8> {
+    buffer.lock().unwrap().take().unwrap_or_default()
+}
+
+/// Standard output or error whose writes are held back in a buffer while
+/// I/O is paused.
+pub(crate) struct PausableStdout {
+    inner: Box<dyn StdoutStream>,
+    buffer: PauseBuffer,
+}
+
+impl PausableStdout {
+    pub(crate) fn new(inner: Box<dyn StdoutStream>, buffer: PauseBuffer) -> Self {
+        Self { inner, buffer }
+    }
+}
+
+impl StdoutStream for PausableStdout {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(PausableOutputStream {
+            inner: self.inner.stream(),
+            buffer: Arc::clone(&self.buffer),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.inner.isatty()
+    }
+}
+
+struct PausableOutputStream {
+    inner: Box<dyn HostOutputStream>,
+    buffer: PauseBuffer,
+}
+
+impl HostOutputStream for PausableOutputStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        match &mut *self.buffer.lock().unwrap() {
+            Some(buffer) => {
+                buffer.extend_from_slice(&bytes);
+                Ok(())
+            }
+            None => self.inner.write(bytes),
+        }
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        if self.buffer.lock().unwrap().is_some() {
+            return Ok(());
+        }
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        if self.buffer.lock().unwrap().is_some() {
+            return Ok(PAUSED_READY_SIZE);
+        }
+        self.inner.check_write()
+    }
+
+    fn write_zeroes(&mut self, nelem: usize) -> StreamResult<()> {
+        match &mut *self.buffer.lock().unwrap() {
+            Some(buffer) => {
+                buffer.resize(buffer.len() + nelem, 0);
+                Ok(())
+            }
+            None => self.inner.write_zeroes(nelem),
+        }
+    }
+
+    fn drain_written(&mut self) -> Vec<u8> {
+        self.inner.drain_written()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for PausableOutputStream {
+    async fn ready(&mut self) {
+        if self.buffer.lock().unwrap().is_some() {
+            return;
+        }
+        self.inner.ready().await;
+    }
+}
@@ -31,6 +31,12 @@ impl MemoryInputPipe {
     pub fn is_empty(&self) -> bool {
         self.buffer.lock().unwrap().is_empty()
     }
+
+    /// Returns a new pipe holding the remaining contents of this one, which
+    /// unlike a clone does not share its progress with this pipe.
+    pub(crate) fn detached(&self) -> Self {
+        Self::new(self.buffer.lock().unwrap().clone())
+    }
 }
 
 #[async_trait::async_trait]
@@ -45,6 +51,14 @@ impl HostInputStream for MemoryInputPipe {
         let read = buffer.split_to(size);
         Ok(read)
     }
+
+    fn num_ready_bytes(&self) -> usize {
+        self.buffer.lock().unwrap().len()
+    }
+
+    fn drain_pending(&mut self) -> Vec<u8> {
+        std::mem::take(&mut *self.buffer.lock().unwrap()).to_vec()
+    }
 }
 
 #[async_trait::async_trait]
@@ -52,6 +66,119 @@ impl Subscribe for MemoryInputPipe {
     async fn ready(&mut self) {}
 }
 
+/// An input stream whose contents are produced by a generator, for example
+/// to feed procedurally generated input to a guest when fuzzing.
+///
+/// The generator is called with the number of times it has been called
+/// before, whenever the guest reads and the bytes of the previous call have
+/// all been read. It returns the next chunk of input, or `None` to close the
+/// stream. A chunk larger than a read is handed out over several reads, so the
+/// guest receives the concatenation of all chunks regardless of how it reads.
+///
+/// Clones share their progress, so a clone kept by the host can inspect the
+/// chunks generated so far with [`GeneratorInputStream::capture`], and replay
+/// them later with [`GeneratorInputStream::from_capture`].
+#[derive(Clone)]
+pub struct GeneratorInputStream {
+    state: Arc<Mutex<GeneratorState>>,
+}
+
+/// The function generating the chunks of a [`GeneratorInputStream`].
+type Generator = Arc<Mutex<Box<dyn Fn(u64) -> Option<Vec<u8>> + Send>>>;
+
+struct GeneratorState {
+    generator: Generator,
+    calls: u64,
+    pending: Bytes,
+    closed: bool,
+    capture: Vec<Vec<u8>>,
+}
+
+impl GeneratorInputStream {
+    /// Creates a stream reading the chunks returned by `generator`.
+    pub fn new(generator: impl Fn(u64) -> Option<Vec<u8>> + Send + 'static) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(GeneratorState {
+                generator: Arc::new(Mutex::new(Box::new(generator))),
+                calls: 0,
+                pending: Bytes::new(),
+                closed: false,
+                capture: Vec::new(),
+            })),
+        }
+    }
+
+    /// Creates a stream reading the chunks of `capture`, as returned by
+    /// [`GeneratorInputStream::capture`], and closed after the last one.
+    pub fn from_capture(capture: Vec<Vec<u8>>) -> Self {
+        Self::new(move |n| {
+            usize::try_from(n)
+                .ok()
+                .and_then(|n| capture.get(n).cloned())
+        })
+    }
+
+    /// Returns the chunks the generator has returned so far, in order.
+    pub fn capture(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().capture.clone()
+    }
+
+    /// Returns a new stream continuing from the current position of this
+    /// one, which unlike a clone does not share its progress with this
+    /// stream.
+    pub(crate) fn detached(&self) -> Self {
+        let state = self.state.lock().unwrap();
+        Self {
+            state: Arc::new(Mutex::new(GeneratorState {
+                generator: state.generator.clone(),
+                calls: state.calls,
+                pending: state.pending.clone(),
+                closed: state.closed,
+                capture: state.capture.clone(),
+            })),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HostInputStream for GeneratorInputStream {
+    fn read(&mut self, size: usize) -> Result<Bytes, StreamError> {
+        let mut state = self.state.lock().unwrap();
+        if state.pending.is_empty() {
+            if state.closed {
+                return Err(StreamError::Closed);
+            }
+            let chunk = (state.generator.lock().unwrap())(state.calls);
+            state.calls += 1;
+            match chunk {
+                Some(chunk) => {
+                    state.capture.push(chunk.clone());
+                    state.pending = chunk.into();
+                }
+                None => {
+                    state.closed = true;
+                    return Err(StreamError::Closed);
+                }
+            }
+        }
+        let size = size.min(state.pending.len());
+        Ok(state.pending.split_to(size))
+    }
+
+    fn num_ready_bytes(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    fn drain_pending(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.state.lock().unwrap().pending).to_vec()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for GeneratorInputStream {
+    async fn ready(&mut self) {}
+}
+
 #[derive(Debug, Clone)]
 pub struct MemoryOutputPipe {
     capacity: usize,
@@ -190,6 +317,33 @@ impl HostInputStream for AsyncReadStream {
             ))),
         }
     }
+
+    fn num_ready_bytes(&self) -> usize {
+        match &self.buffer {
+            Some(Ok(bytes)) => bytes.len(),
+            _ => 0,
+        }
+    }
+
+    fn drain_pending(&mut self) -> Vec<u8> {
+        let mut pending = Vec::new();
+        loop {
+            match self.buffer.take() {
+                Some(Ok(bytes)) => pending.extend_from_slice(&bytes),
+                // Errors, including closure, are reported by the next read.
+                Some(Err(e)) => {
+                    self.buffer = Some(Err(e));
+                    break;
+                }
+                None => {}
+            }
+            match self.receiver.try_recv() {
+                Ok(res) => self.buffer = Some(res),
+                Err(_) => break,
+            }
+        }
+        pending
+    }
 }
 #[async_trait::async_trait]
 impl Subscribe for AsyncReadStream {
@@ -0,0 +1,343 @@
+use crate::preview2::overlay;
+use cap_std::ipnet::IpNet;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::path::{Component, Path};
+
+/// An ordered list of allow and deny rules for filesystem paths and network
+/// addresses, evaluated each time the guest accesses a path or address.
+///
+/// A policy further restricts the capabilities granted through
+/// [`WasiCtxBuilder`](crate::preview2::WasiCtxBuilder): access is only
+/// possible if it is both granted (by a preopen or by the network pool) and
+/// permitted by the policy. Rules are evaluated in the order they were added
+/// and the first matching rule decides. If no rule matches, access is allowed.
+///
+/// Path rules are glob patterns matched against the guest path, where `*`
+/// matches any sequence of characters within a path segment, `?` matches any
+/// single character and `**` matches any number of path segments. Paths are
+/// normalized lexically before matching. When the guest accesses a path, the
+/// policy is checked against both that path and the path it refers to once
+/// symlinks within the preopen are resolved, and access is only possible if
+/// both are allowed.
+///
+/// ```
+/// use wasmtime_wasi::preview2::CapabilityPolicy;
+///
+/// let mut policy = CapabilityPolicy::new();
+/// policy
+///     .deny_path("**/secrets/**")
+///     .deny_network("10.0.0.0/8".parse().unwrap());
+/// assert!(policy.is_path_allowed("/data/report.txt"));
+/// assert!(!policy.is_path_allowed("/data/secrets/key"));
+/// assert!(!policy.is_addr_allowed("10.1.2.3".parse().unwrap()));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CapabilityPolicy {
+    path_rules: Vec<(Rule, String)>,
+    network_rules: Vec<(Rule, IpNet)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Rule {
+    Allow,
+    Deny,
+}
+
+impl CapabilityPolicy {
+    /// Creates a policy without any rules, which allows all access.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule allowing access to guest paths matching `pattern`.
+    pub fn allow_path(&mut self, pattern: impl Into<String>) -> &mut Self {
+        self.path_rules.push((Rule::Allow, pattern.into()));
+        self
+    }
+
+    /// Adds a rule denying access to guest paths matching `pattern`.
+    pub fn deny_path(&mut self, pattern: impl Into<String>) -> &mut Self {
+        self.path_rules.push((Rule::Deny, pattern.into()));
+        self
+    }
+
+    /// Adds a rule allowing access to addresses within `net`.
+    pub fn allow_network(&mut self, net: IpNet) -> &mut Self {
+        self.network_rules.push((Rule::Allow, net));
+        self
+    }
+
+    /// Adds a rule denying access to addresses within `net`.
+    pub fn deny_network(&mut self, net: IpNet) -> &mut Self {
+        self.network_rules.push((Rule::Deny, net));
+        self
+    }
+
+    /// Returns whether this policy permits access to the guest path `path`.
+    pub fn is_path_allowed(&self, path: &str) -> bool {
+        let path = normalize(path);
+        self.path_rules
+            .iter()
+            .find(|(_, pattern)| glob_match(&segments(pattern), &path))
+            .map_or(true, |(rule, _)| *rule == Rule::Allow)
+    }
+
+    /// Returns whether this policy has any rules for paths.
+    pub(crate) fn has_path_rules(&self) -> bool {
+        !self.path_rules.is_empty()
+    }
+
+    /// Returns whether this policy permits access to `addr`.
+    pub fn is_addr_allowed(&self, addr: IpAddr) -> bool {
+        self.network_rules
+            .iter()
+            .find(|(_, net)| net.contains(&addr))
+            .map_or(true, |(rule, _)| *rule == Rule::Allow)
+    }
+}
+
+/// How strictly paths passed to `open-at` are confined to the directory
+/// they are relative to, configured with
+/// [`WasiCtxBuilder::preopen_scope_policy`](crate::preview2::WasiCtxBuilder::preopen_scope_policy).
+///
+/// Directories are accessed through `cap-std`, which never resolves a path
+/// to a location outside of the directory, whether through `..` or through
+/// symlinks. The stricter policies reject such paths up front with an
+/// `access` error, before the path is resolved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScopePolicy {
+    /// Perform no checks beyond those of `cap-std`. This is the default.
+    #[default]
+    AllowSymlinkEscape,
+    /// Reject paths with `..` components, and paths which resolve to a
+    /// location outside of the directory when following symlinks.
+    DenySymlinkEscape,
+    /// Reject paths with `..` components.
+    DenyDotDot,
+}
+
+impl ScopePolicy {
+    /// Returns whether this policy rejects `path` for its `..` components.
+    pub(crate) fn denies_dot_dot(&self, path: &str) -> bool {
+        *self != ScopePolicy::AllowSymlinkEscape && segments(path).contains(&"..")
+    }
+}
+
+/// Returns whether `path` resolves to a location beneath `dir`, following
+/// symlinks.
+///
+/// A path which does not exist yet is checked through its parent, so that
+/// files can still be created.
+pub(crate) fn resolves_within(dir: &cap_std::fs::Dir, path: &str) -> bool {
+    let path = Path::new(path);
+    let resolved = match dir.canonicalize(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => dir.canonicalize(parent),
+            _ => return true,
+        },
+        resolved => resolved,
+    };
+    match resolved {
+        Ok(resolved) => !resolved.starts_with(".."),
+        // Opening a path beneath a missing directory fails anyway.
+        Err(e) => e.kind() == std::io::ErrorKind::NotFound,
+    }
+}
+
+/// Resolves the symlinks in `path`, relative to `dir`, and returns the path
+/// of the entry it refers to, relative to `dir`. The final component is only
+/// resolved if `follow` is set.
+///
+/// Components which don't exist are kept as they are. Resolution stops at
+/// absolute symlink targets, which `cap-std` refuses to follow anyway. For a
+/// directory of an overlay, symlinks missing from `dir` are looked up in
+/// `base`.
+pub(crate) fn resolve(
+    dir: &cap_std::fs::Dir,
+    base: Option<&cap_std::fs::Dir>,
+    path: &str,
+    follow: bool,
+) -> String {
+    let mut resolved: Vec<String> = Vec::new();
+    let mut pending: VecDeque<String> = segments(path).into_iter().map(String::from).collect();
+    let mut links = 0;
+    while let Some(segment) = pending.pop_front() {
+        match segment.as_str() {
+            "." => continue,
+            ".." => {
+                resolved.pop();
+                continue;
+            }
+            _ => resolved.push(segment),
+        }
+        if pending.is_empty() && !follow {
+            break;
+        }
+        let current = resolved.join("/");
+        let target = match overlay::fall_through(dir, base, |d| d.read_link(&current)) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        let target = match relative_segments(&target) {
+            Some(target) if links < MAX_SYMLINKS => target,
+            _ => break,
+        };
+        links += 1;
+        resolved.pop();
+        for segment in target.into_iter().rev() {
+            pending.push_front(segment);
+        }
+    }
+    resolved.extend(pending);
+    resolved.join("/")
+}
+
+/// The number of symlinks [`resolve`] follows before giving up, as with
+/// `MAXSYMLINKS` on Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// Returns the segments of the relative symlink target `target`, or `None`
+/// if it is absolute or not valid UTF-8.
+fn relative_segments(target: &Path) -> Option<Vec<String>> {
+    target
+        .components()
+        .map(|component| match component {
+            Component::Normal(s) => s.to_str().map(String::from),
+            Component::CurDir => Some(".".to_string()),
+            Component::ParentDir => Some("..".to_string()),
+            Component::RootDir | Component::Prefix(_) => None,
+        })
+        .collect()
+}
+
+/// Joins the guest path `base` of a directory with a `path` relative to it.
+pub(crate) fn join(base: &str, path: &str) -> String {
+    if path.starts_with('/') || base.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", base.trim_end_matches('/'), path)
+    }
+}
+
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+/// Splits `path` into segments, resolving `.` and `..` lexically.
+fn normalize(path: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    for segment in segments(path) {
+        match segment {
+            "." => {}
+            ".." => {
+                result.pop();
+            }
+            s => result.push(s),
+        }
+    }
+    result
+}
+
+fn glob_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path)) => {
+                segment_match(first.as_bytes(), segment.as_bytes()) && glob_match(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn segment_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| segment_match(rest, &s[i..])),
+        Some((b'?', rest)) => !s.is_empty() && segment_match(rest, &s[1..]),
+        Some((c, rest)) => s.first() == Some(c) && segment_match(rest, &s[1..]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn path_rules() {
+        let mut policy = CapabilityPolicy::new();
+        policy
+            .allow_path("/data/secrets/public.txt")
+            .deny_path("**/secrets/**")
+            .deny_path("/tmp/*.log");
+
+        assert!(policy.is_path_allowed("/data/file"));
+        assert!(policy.is_path_allowed("/data/secrets/public.txt"));
+        assert!(!policy.is_path_allowed("/data/secrets"));
+        assert!(!policy.is_path_allowed("/data/secrets/nested/key"));
+        assert!(!policy.is_path_allowed("/data/x/../secrets/key"));
+        assert!(!policy.is_path_allowed("/tmp/app.log"));
+        assert!(policy.is_path_allowed("/tmp/app.txt"));
+    }
+
+    #[test]
+    fn network_rules() {
+        let mut policy = CapabilityPolicy::new();
+        policy
+            .allow_network("10.1.0.0/16".parse().unwrap())
+            .deny_network("10.0.0.0/8".parse().unwrap());
+
+        assert!(policy.is_addr_allowed("10.1.2.3".parse().unwrap()));
+        assert!(!policy.is_addr_allowed("10.2.2.3".parse().unwrap()));
+        assert!(policy.is_addr_allowed("192.168.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn unknown_dir_path_denies() {
+        use crate::preview2::{Dir, DirPerms, FilePerms};
+        use std::sync::Arc;
+
+        let tmp = cap_tempfile::TempDir::new(cap_std::ambient_authority()).unwrap();
+        let dir = Dir::new(
+            tmp.open_dir(".").unwrap(),
+            DirPerms::all(),
+            FilePerms::all(),
+        );
+        assert!(dir.check_policy("file").is_ok());
+
+        let mut policy = CapabilityPolicy::new();
+        policy.deny_path("/data/secret");
+        let policy = Arc::new(policy);
+        let dir = dir.with_policy(String::new(), policy.clone());
+        assert!(dir.check_policy("file").is_err());
+        let dir = dir.with_policy("/data".to_string(), policy);
+        assert!(dir.check_policy("file").is_ok());
+        assert!(dir.check_policy("secret").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn resolve_symlinks() {
+        let tmp = cap_tempfile::TempDir::new(cap_std::ambient_authority()).unwrap();
+        tmp.create_dir("secrets").unwrap();
+        tmp.symlink("secrets", "link").unwrap();
+        tmp.symlink("link", "chain").unwrap();
+        tmp.symlink("loop", "loop").unwrap();
+
+        assert_eq!(resolve(&tmp, None, "link/key", false), "secrets/key");
+        assert_eq!(resolve(&tmp, None, "chain/key", false), "secrets/key");
+        assert_eq!(resolve(&tmp, None, "link", false), "link");
+        assert_eq!(resolve(&tmp, None, "link", true), "secrets");
+        assert_eq!(resolve(&tmp, None, "missing/../link/", true), "secrets");
+        assert_eq!(resolve(&tmp, None, "loop", true), "loop");
+    }
+
+    #[test]
+    fn join_paths() {
+        assert_eq!(join("/", "a/b"), "/a/b");
+        assert_eq!(join("/data/", "a"), "/data/a");
+        assert_eq!(join(".", "a"), "./a");
+    }
+}
@@ -23,6 +23,14 @@ pub struct Pollable {
     remove_index_on_delete: Option<fn(&mut Table, u32) -> Result<()>>,
 }
 
+impl Pollable {
+    /// Updates the table index of the resource this pollable is subscribed
+    /// to, after the table was compacted.
+    pub(crate) fn remap(&mut self, remap: impl Fn(u32) -> u32) {
+        self.index = remap(self.index);
+    }
+}
+
 #[async_trait::async_trait]
 pub trait Subscribe: Send + Sync + 'static {
     async fn ready(&mut self);
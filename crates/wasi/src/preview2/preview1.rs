@@ -8,7 +8,10 @@ use crate::preview2::bindings::{
     filesystem::{preopens, types as filesystem},
     io::{poll, streams},
 };
-use crate::preview2::{FsError, IsATTY, StreamError, StreamResult, TableError, WasiView};
+use crate::preview2::secrets::zero_string;
+use crate::preview2::{
+    FsError, IsATTY, ResourceKind, StreamError, StreamResult, TableError, WasiView,
+};
 use anyhow::{anyhow, bail, Context};
 use std::borrow::Borrow;
 use std::collections::{BTreeMap, HashSet};
@@ -903,40 +906,47 @@ impl<
         environ: &GuestPtr<'b, GuestPtr<'b, u8>>,
         environ_buf: &GuestPtr<'b, u8>,
     ) -> Result<(), types::Error> {
-        self.get_environment()
+        let mut environment = self
+            .get_environment()
             .context("failed to call `get-environment`")
-            .map_err(types::Error::trap)?
-            .into_iter()
-            .try_fold(
-                (*environ, *environ_buf),
-                |(environ, environ_buf), (k, v)| -> Result<_, types::Error> {
-                    environ.write(environ_buf)?;
-                    let environ = environ.add(1)?;
-
-                    let environ_buf = write_bytes(environ_buf, k.as_bytes())?;
-                    let environ_buf = write_byte(environ_buf, b'=')?;
-                    let environ_buf = write_bytes(environ_buf, v.as_bytes())?;
-                    let environ_buf = write_byte(environ_buf, 0)?;
-
-                    Ok((environ, environ_buf))
-                },
-            )?;
+            .map_err(types::Error::trap)?;
+        let written = environment.iter().try_fold(
+            (*environ, *environ_buf),
+            |(environ, environ_buf), (k, v)| -> Result<_, types::Error> {
+                environ.write(environ_buf)?;
+                let environ = environ.add(1)?;
+
+                let environ_buf = write_bytes(environ_buf, k.as_bytes())?;
+                let environ_buf = write_byte(environ_buf, b'=')?;
+                let environ_buf = write_bytes(environ_buf, v.as_bytes())?;
+                let environ_buf = write_byte(environ_buf, 0)?;
+
+                Ok((environ, environ_buf))
+            },
+        );
+        // The values may hold secrets, which should not linger in memory.
+        for (_, v) in environment.iter_mut() {
+            zero_string(v);
+        }
+        written?;
         Ok(())
     }
 
     #[instrument(skip(self))]
     fn environ_sizes_get(&mut self) -> Result<(types::Size, types::Size), types::Error> {
-        let environ = self
+        let mut environ = self
             .get_environment()
             .context("failed to call `get-environment`")
             .map_err(types::Error::trap)?;
-        let num = environ.len().try_into()?;
+        let num = environ.len();
         let len = environ
             .iter()
             .map(|(k, v)| k.len() + 1 + v.len() + 1) // Key/value pairs are expected to be joined with `=`s, and terminated with `\0`s.
-            .sum::<usize>()
-            .try_into()?;
-        Ok((num, len))
+            .sum::<usize>();
+        for (_, v) in environ.iter_mut() {
+            zero_string(v);
+        }
+        Ok((num.try_into()?, len.try_into()?))
     }
 
     #[instrument(skip(self))]
@@ -1903,6 +1913,18 @@ impl<
                 e.try_into()
                     .context("failed to call `open-at`")
                     .unwrap_or_else(types::Error::trap)
+            })
+            .map_err(|e| {
+                // `open-at` reports reaching a limit on open handles as a
+                // quota error, which is `EMFILE` here.
+                let ctx = self.ctx();
+                let table = self.table();
+                let limited = ctx.handle_limit_reached(table, ResourceKind::File)
+                    || ctx.handle_limit_reached(table, ResourceKind::Dir);
+                match e.downcast_ref() {
+                    Some(types::Errno::Dquot) if limited => types::Errno::Mfile.into(),
+                    _ => e,
+                }
             })?;
         let fd = self.transact()?.descriptors.push_file(File {
             fd,
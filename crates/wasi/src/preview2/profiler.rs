@@ -0,0 +1,49 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Receives periodic samples of a running component, installed with
+/// [`WasiCtxBuilder::with_profiler`](crate::preview2::WasiCtxBuilder::with_profiler).
+///
+/// Samples are taken from the store's epoch deadline callback, as described
+/// for [`WasiCtx::sample_profiler`](crate::preview2::WasiCtx::sample_profiler),
+/// so they are only as regular as the engine's epoch is incremented.
+pub trait ComponentProfiler: Send + Sync {
+    /// Records a sample taken at `timestamp`.
+    ///
+    /// `fuel_consumed` is the fuel the store has consumed so far, or zero if
+    /// fuel is not enabled, and `memory_used` the combined size in bytes of
+    /// the component's linear memories, as reported in
+    /// [`ExecutionStats`](crate::preview2::ExecutionStats).
+    fn sample(&mut self, timestamp: Instant, fuel_consumed: u64, memory_used: usize);
+}
+
+/// A [`ComponentProfiler`] which keeps every sample in memory.
+///
+/// Clones share the recorded samples, so a clone can be kept to read the
+/// samples after the original was handed to the context.
+#[derive(Clone, Debug, Default)]
+pub struct SamplingProfiler {
+    samples: Arc<Mutex<Vec<(Instant, u64, usize)>>>,
+}
+
+impl SamplingProfiler {
+    /// Creates a profiler which has not recorded any samples.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the samples recorded so far, as the timestamp, fuel consumed
+    /// and memory used of each, oldest first.
+    pub fn samples(&self) -> Vec<(Instant, u64, usize)> {
+        self.samples.lock().unwrap().clone()
+    }
+}
+
+impl ComponentProfiler for SamplingProfiler {
+    fn sample(&mut self, timestamp: Instant, fuel_consumed: u64, memory_used: usize) {
+        self.samples
+            .lock()
+            .unwrap()
+            .push((timestamp, fuel_consumed, memory_used));
+    }
+}
@@ -0,0 +1,243 @@
+use std::fmt;
+use std::sync::Mutex;
+
+/// Storage limits of a preopened directory, set with
+/// [`WasiCtxBuilder::preopen_dir_quota`](crate::preview2::WasiCtxBuilder::preopen_dir_quota).
+///
+/// The limits apply to what the guest adds to the directory and its
+/// subdirectories from the time the context is built, not to the files they
+/// already contained.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirQuota {
+    /// The number of bytes by which the guest may grow files in total.
+    pub max_total_bytes: Option<u64>,
+    /// The number of files the guest may create.
+    pub max_file_count: Option<u32>,
+    /// The size beyond which the guest may not grow a file.
+    pub max_file_size_bytes: Option<u64>,
+}
+
+/// The storage used by the guest in a directory with a [`DirQuota`],
+/// returned by
+/// [`WasiCtx::dir_quota_usage`](crate::preview2::WasiCtx::dir_quota_usage).
+///
+/// Truncating files and removing them credits the bytes and files they
+/// accounted for, down to zero, so usage follows what the guest currently
+/// stores rather than what it stored in total.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirQuotaUsage {
+    /// The number of bytes by which the guest grew files.
+    pub total_bytes: u64,
+    /// The number of files the guest created.
+    pub file_count: u32,
+}
+
+/// The quota of a preopened directory and the usage counted against it,
+/// shared by the directory and the files and subdirectories opened from it.
+#[derive(Debug)]
+pub(crate) struct QuotaTracker {
+    quota: DirQuota,
+    usage: Mutex<DirQuotaUsage>,
+}
+
+impl QuotaTracker {
+    pub(crate) fn new(quota: DirQuota) -> QuotaTracker {
+        QuotaTracker {
+            quota,
+            usage: Mutex::new(DirQuotaUsage::default()),
+        }
+    }
+
+    pub(crate) fn usage(&self) -> DirQuotaUsage {
+        *self.usage.lock().unwrap()
+    }
+
+    /// Reserves a slot for a file the guest is about to create, or fails if
+    /// it may not create another file.
+    ///
+    /// The slot is taken and checked against the quota atomically, so that
+    /// concurrent creations cannot exceed it. It is released again when the
+    /// returned reservation is dropped without being committed.
+    pub(crate) fn reserve_file(&self) -> Result<FileReservation<'_>, QuotaExceeded> {
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(max) = self.quota.max_file_count {
+            if usage.file_count >= max {
+                return Err(QuotaExceeded);
+            }
+        }
+        usage.file_count += 1;
+        Ok(FileReservation {
+            tracker: self,
+            committed: false,
+        })
+    }
+
+    /// Credits a file of `len` bytes removed by the guest.
+    pub(crate) fn file_removed(&self, len: u64) {
+        let mut usage = self.usage.lock().unwrap();
+        usage.file_count = usage.file_count.saturating_sub(1);
+        usage.total_bytes = usage.total_bytes.saturating_sub(len);
+    }
+
+    /// Counts the growth of a file of `len` bytes to `new_len` bytes, or
+    /// fails without counting it if it exceeds the quota.
+    pub(crate) fn grow(&self, len: u64, new_len: u64) -> Result<(), QuotaExceeded> {
+        if new_len <= len {
+            return Ok(());
+        }
+        if self
+            .quota
+            .max_file_size_bytes
+            .map_or(false, |max| new_len > max)
+        {
+            return Err(QuotaExceeded);
+        }
+        let mut usage = self.usage.lock().unwrap();
+        let total = usage.total_bytes.saturating_add(new_len - len);
+        if self.quota.max_total_bytes.map_or(false, |max| total > max) {
+            return Err(QuotaExceeded);
+        }
+        usage.total_bytes = total;
+        Ok(())
+    }
+
+    /// Credits the shrinking of a file of `len` bytes to `new_len` bytes.
+    pub(crate) fn shrink(&self, len: u64, new_len: u64) {
+        if new_len < len {
+            let mut usage = self.usage.lock().unwrap();
+            usage.total_bytes = usage.total_bytes.saturating_sub(len - new_len);
+        }
+    }
+
+    /// Counts files of `sizes` bytes moved into the directory by the guest,
+    /// or fails without counting them if they exceed the quota.
+    pub(crate) fn move_in(&self, sizes: &[u64]) -> Result<(), QuotaExceeded> {
+        if let (Some(max), Some(largest)) = (self.quota.max_file_size_bytes, sizes.iter().max()) {
+            if *largest > max {
+                return Err(QuotaExceeded);
+            }
+        }
+        let mut usage = self.usage.lock().unwrap();
+        let file_count = u32::try_from(sizes.len())
+            .ok()
+            .and_then(|n| usage.file_count.checked_add(n))
+            .ok_or(QuotaExceeded)?;
+        let total_bytes = sizes
+            .iter()
+            .fold(usage.total_bytes, |total, len| total.saturating_add(*len));
+        if self
+            .quota
+            .max_file_count
+            .map_or(false, |max| file_count > max)
+            || self
+                .quota
+                .max_total_bytes
+                .map_or(false, |max| total_bytes > max)
+        {
+            return Err(QuotaExceeded);
+        }
+        *usage = DirQuotaUsage {
+            total_bytes,
+            file_count,
+        };
+        Ok(())
+    }
+
+    /// Credits files of `sizes` bytes moved out of the directory by the
+    /// guest.
+    pub(crate) fn move_out(&self, sizes: &[u64]) {
+        for len in sizes {
+            self.file_removed(*len);
+        }
+    }
+}
+
+/// A file slot taken with [`QuotaTracker::reserve_file`].
+pub(crate) struct FileReservation<'a> {
+    tracker: &'a QuotaTracker,
+    committed: bool,
+}
+
+impl FileReservation<'_> {
+    /// Keeps the slot for the file, once it was created.
+    pub(crate) fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for FileReservation<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let mut usage = self.tracker.usage.lock().unwrap();
+            usage.file_count = usage.file_count.saturating_sub(1);
+        }
+    }
+}
+
+/// The error of a write which would exceed a [`DirQuota`], reported to the
+/// guest as `quota`.
+#[derive(Debug)]
+pub(crate) struct QuotaExceeded;
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("directory quota exceeded")
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn concurrent_reservations() {
+        let tracker = QuotaTracker::new(DirQuota {
+            max_file_count: Some(4),
+            ..DirQuota::default()
+        });
+        let created = std::sync::atomic::AtomicU32::new(0);
+        std::thread::scope(|s| {
+            for _ in 0..16 {
+                s.spawn(|| {
+                    if let Ok(reservation) = tracker.reserve_file() {
+                        created.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        reservation.commit();
+                    }
+                });
+            }
+        });
+        assert_eq!(created.into_inner(), 4);
+        assert_eq!(tracker.usage().file_count, 4);
+
+        // Reservations which are not committed are released.
+        tracker.file_removed(0);
+        drop(tracker.reserve_file().unwrap());
+        tracker.reserve_file().unwrap().commit();
+        assert!(tracker.reserve_file().is_err());
+    }
+
+    #[test]
+    fn move_in_and_out() {
+        let tracker = QuotaTracker::new(DirQuota {
+            max_total_bytes: Some(10),
+            max_file_count: Some(2),
+            max_file_size_bytes: Some(6),
+        });
+        assert!(tracker.move_in(&[7]).is_err());
+        assert!(tracker.move_in(&[1, 1, 1]).is_err());
+        tracker.move_in(&[6]).unwrap();
+        assert!(tracker.move_in(&[5]).is_err());
+        tracker.move_in(&[4]).unwrap();
+        assert_eq!(
+            tracker.usage(),
+            DirQuotaUsage {
+                total_bytes: 10,
+                file_count: 2,
+            }
+        );
+        tracker.move_out(&[6, 4]);
+        assert_eq!(tracker.usage(), DirQuotaUsage::default());
+    }
+}
@@ -1,4 +1,6 @@
 use cap_rand::RngCore;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
 
 /// Implement `insecure-random` using a deterministic cycle of bytes.
 pub struct Deterministic {
@@ -56,3 +58,187 @@ pub fn thread_rng() -> Box<dyn RngCore + Send + Sync> {
     let mut rng = cap_rand::thread_rng(cap_rand::ambient_authority());
     Box::new(cap_rand::rngs::StdRng::from_seed(rng.gen()))
 }
+
+/// The bytes produced by a [`RecordingRng`], in the order they were
+/// generated, which can be replayed with
+/// [`WasiCtxBuilder::with_prng_replay`](crate::preview2::WasiCtxBuilder::with_prng_replay).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRng {
+    bytes: Vec<u8>,
+}
+
+impl RecordedRng {
+    /// Creates a recording holding `bytes`.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    /// Returns the recorded bytes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// A generator which records every byte produced by another generator.
+///
+/// Clones share the wrapped generator and the recording, so a clone can be
+/// installed with
+/// [`WasiCtxBuilder::secure_random`](crate::preview2::WasiCtxBuilder::secure_random)
+/// while the original is kept to read the recording with
+/// [`RecordingRng::recorded`].
+#[derive(Clone)]
+pub struct RecordingRng {
+    state: Arc<Mutex<RecordingState>>,
+}
+
+struct RecordingState {
+    inner: Box<dyn RngCore + Send + Sync>,
+    recorded: Vec<u8>,
+}
+
+impl RecordingRng {
+    /// Creates a generator recording the output of `inner`.
+    pub fn new(inner: impl RngCore + Send + Sync + 'static) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(RecordingState {
+                inner: Box::new(inner),
+                recorded: Vec::new(),
+            })),
+        }
+    }
+
+    /// Returns a copy of the bytes produced so far.
+    pub fn recorded(&self) -> RecordedRng {
+        RecordedRng::new(self.state.lock().unwrap().recorded.clone())
+    }
+}
+
+impl RngCore for RecordingRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        let value = state.inner.next_u32();
+        state.recorded.extend_from_slice(&value.to_le_bytes());
+        value
+    }
+    fn next_u64(&mut self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let value = state.inner.next_u64();
+        state.recorded.extend_from_slice(&value.to_le_bytes());
+        value
+    }
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.inner.fill_bytes(buf);
+        state.recorded.extend_from_slice(buf);
+    }
+    fn try_fill_bytes(&mut self, buf: &mut [u8]) -> Result<(), cap_rand::Error> {
+        let mut state = self.state.lock().unwrap();
+        state.inner.try_fill_bytes(buf)?;
+        state.recorded.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// A generator which produces the bytes of a [`RecordedRng`] in order,
+/// reproducing the output of the recorded generator as long as the same
+/// requests are made.
+///
+/// Requesting more bytes than were recorded means that the replayed
+/// execution has diverged from the recorded one. [`RngCore::try_fill_bytes`]
+/// then fails, while the other methods produce zeros and record the
+/// divergence, so that a context using the generator fails the host call
+/// which made the request.
+pub struct ReplayRng {
+    recorded: RecordedRng,
+    position: usize,
+    divergence: ReplayDivergence,
+}
+
+/// The first request a [`ReplayRng`] could not serve, shared with the
+/// contexts it is installed in.
+#[derive(Clone, Default)]
+pub(crate) struct ReplayDivergence(Arc<Mutex<Option<String>>>);
+
+impl ReplayDivergence {
+    /// Returns an error describing the first request which could not be
+    /// served, if any.
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        match &*self.0.lock().unwrap() {
+            Some(message) => Err(anyhow::anyhow!("{message}")),
+            None => Ok(()),
+        }
+    }
+
+    fn record(&self, message: String) {
+        self.0.lock().unwrap().get_or_insert(message);
+    }
+}
+
+impl ReplayRng {
+    /// Creates a generator replaying `recorded`.
+    pub fn new(recorded: RecordedRng) -> Self {
+        Self {
+            recorded,
+            position: 0,
+            divergence: ReplayDivergence::default(),
+        }
+    }
+
+    /// Returns the number of recorded bytes which have not been replayed
+    /// yet.
+    pub fn remaining(&self) -> usize {
+        self.recorded.bytes.len() - self.position
+    }
+
+    pub(crate) fn divergence(&self) -> ReplayDivergence {
+        self.divergence.clone()
+    }
+
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self
+            .recorded
+            .bytes
+            .get(self.position..self.position + len)?;
+        self.position += len;
+        Some(bytes)
+    }
+
+    /// Fills `buf` with the next `buf.len()` recorded bytes, or with zeros
+    /// after recording the divergence if there are not enough left.
+    fn fill_or_record(&mut self, buf: &mut [u8]) {
+        if let Err(e) = self.try_fill_bytes(buf) {
+            buf.fill(0);
+            self.divergence.record(e.to_string());
+        }
+    }
+}
+
+impl RngCore for ReplayRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.fill_or_record(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        self.fill_or_record(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        self.fill_or_record(buf);
+    }
+    fn try_fill_bytes(&mut self, buf: &mut [u8]) -> Result<(), cap_rand::Error> {
+        let remaining = self.remaining();
+        match self.take(buf.len()) {
+            Some(bytes) => {
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+            None => Err(cap_rand::Error::new(format!(
+                "replayed random generator exhausted: requested {} bytes but only {remaining} \
+                 remain",
+                buf.len()
+            ))),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::preview2::{
+    HostInputStream, HostOutputStream, StdinStream, StdoutStream, StreamError, StreamResult,
+    Subscribe,
+};
+use anyhow::anyhow;
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits on the rate at which a guest may read from stdin and write to
+/// stdout and stderr, configured with
+/// [`WasiCtxBuilder::rate_limit_io`](crate::preview2::WasiCtxBuilder::rate_limit_io).
+///
+/// Stdout and stderr share the write limit. A limit of `None` leaves that
+/// direction unlimited, while a limit of zero blocks it entirely.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoRateLimitConfig {
+    /// The maximum number of bytes per second which may be read from stdin.
+    pub read_bytes_per_sec: Option<u64>,
+    /// The maximum number of bytes per second which may be written to stdout
+    /// and stderr together.
+    pub write_bytes_per_sec: Option<u64>,
+}
+
+/// The fraction of a second of traffic which may be sent in a single burst.
+const BURST_DIVISOR: u64 = 20;
+
+/// A token bucket holding one token per byte which may be transferred.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: u64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket which refills at `rate` bytes per second and
+    /// holds up to a twentieth of a second of traffic.
+    pub(crate) fn new(rate: u64) -> Arc<Mutex<TokenBucket>> {
+        let capacity = (rate / BURST_DIVISOR).max(1) as f64;
+        Arc::new(Mutex::new(TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }))
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Returns the number of bytes which may be transferred right now.
+    fn available(&mut self) -> usize {
+        self.refill();
+        self.tokens as usize
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.tokens = (self.tokens - len as f64).max(0.0);
+    }
+
+    /// Returns how long to wait until at least one byte may be transferred,
+    /// or `None` if that never happens.
+    fn wait_time(&mut self) -> Option<Duration> {
+        if self.available() > 0 {
+            return Some(Duration::ZERO);
+        }
+        if self.rate == 0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.rate as f64,
+        ))
+    }
+}
+
+/// Waits until at least one byte may be transferred through `bucket`.
+async fn bucket_ready(bucket: &Mutex<TokenBucket>) {
+    loop {
+        let wait = bucket.lock().unwrap().wait_time();
+        match wait {
+            Some(wait) if wait.is_zero() => return,
+            Some(wait) => tokio::time::sleep(wait).await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// An input stream whose reads are limited by a [`TokenBucket`].
+struct RateLimitedInputStream {
+    inner: Box<dyn HostInputStream>,
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+#[async_trait::async_trait]
+impl HostInputStream for RateLimitedInputStream {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let available = bucket.available();
+        // Reads of zero bytes are passed through so that closure of the
+        // stream is still reported when out of budget.
+        if available == 0 && size > 0 {
+            return Ok(Bytes::new());
+        }
+        let bytes = self.inner.read(size.min(available))?;
+        bucket.consume(bytes.len());
+        Ok(bytes)
+    }
+
+    fn num_ready_bytes(&self) -> usize {
+        self.inner.num_ready_bytes()
+    }
+
+    fn drain_pending(&mut self) -> Vec<u8> {
+        self.inner.drain_pending()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for RateLimitedInputStream {
+    async fn ready(&mut self) {
+        bucket_ready(&self.bucket).await;
+        self.inner.ready().await;
+    }
+}
+
+/// An output stream whose writes are limited by a [`TokenBucket`].
+///
+/// The limit is enforced through [`HostOutputStream::check_write`], which
+/// permits no more than the bytes currently in the bucket, so that a guest
+/// writing more than that makes progress with partial writes.
+struct RateLimitedOutputStream {
+    inner: Box<dyn HostOutputStream>,
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl HostOutputStream for RateLimitedOutputStream {
+    fn write(&mut self, bytes: Bytes) -> Result<(), StreamError> {
+        let mut bucket = self.bucket.lock().unwrap();
+        if bytes.len() > bucket.available() {
+            return Err(StreamError::Trap(anyhow!("write exceeded rate limit")));
+        }
+        bucket.consume(bytes.len());
+        self.inner.write(bytes)
+    }
+
+    fn flush(&mut self) -> Result<(), StreamError> {
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> Result<usize, StreamError> {
+        let permit = self.inner.check_write()?;
+        Ok(permit.min(self.bucket.lock().unwrap().available()))
+    }
+
+    fn drain_written(&mut self) -> Vec<u8> {
+        self.inner.drain_written()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for RateLimitedOutputStream {
+    async fn ready(&mut self) {
+        bucket_ready(&self.bucket).await;
+        self.inner.ready().await;
+    }
+}
+
+/// Standard input whose streams share a read limit.
+pub(crate) struct RateLimitedStdin {
+    inner: Box<dyn StdinStream>,
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimitedStdin {
+    pub(crate) fn new(inner: Box<dyn StdinStream>, bucket: Arc<Mutex<TokenBucket>>) -> Self {
+        Self { inner, bucket }
+    }
+}
+
+impl StdinStream for RateLimitedStdin {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(RateLimitedInputStream {
+            inner: self.inner.stream(),
+            bucket: Arc::clone(&self.bucket),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.inner.isatty()
+    }
+
+    fn fork(&self) -> Option<Box<dyn StdinStream>> {
+        let inner = self.inner.fork()?;
+        let rate = self.bucket.lock().unwrap().rate;
+        Some(Box::new(RateLimitedStdin::new(
+            inner,
+            TokenBucket::new(rate),
+        )))
+    }
+}
+
+/// Standard output or error whose streams share a write limit.
+pub(crate) struct RateLimitedStdout {
+    inner: Box<dyn StdoutStream>,
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimitedStdout {
+    pub(crate) fn new(inner: Box<dyn StdoutStream>, bucket: Arc<Mutex<TokenBucket>>) -> Self {
+        Self { inner, bucket }
+    }
+}
+
+impl StdoutStream for RateLimitedStdout {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(RateLimitedOutputStream {
+            inner: self.inner.stream(),
+            bucket: Arc::clone(&self.bucket),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.inner.isatty()
+    }
+}
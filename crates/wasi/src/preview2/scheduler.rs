@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Decides when a running component yields to the async executor, so that
+/// components sharing a runtime take turns, installed with
+/// [`WasiCtxBuilder::with_scheduler`](crate::preview2::WasiCtxBuilder::with_scheduler).
+///
+/// The scheduler is consulted from the store's epoch deadline callback, as
+/// described for [`WasiCtx::schedule`](crate::preview2::WasiCtx::schedule),
+/// so it is only as precise as the engine's epoch is incremented.
+pub trait WasmScheduler: Send + Sync {
+    /// Returns whether the component should yield, given the fuel it
+    /// consumed and the nanoseconds of wall-clock time elapsed since it last
+    /// yielded or started running.
+    ///
+    /// `fuel_consumed` is zero if fuel is not enabled.
+    fn should_yield(&self, fuel_consumed: u64, elapsed_ns: u64) -> bool;
+}
+
+/// A [`WasmScheduler`] which gives every component the same amount of fuel
+/// before it yields.
+#[derive(Clone, Copy, Debug)]
+pub struct RoundRobinScheduler {
+    /// The fuel a component may consume before it yields.
+    pub max_fuel_per_slice: u64,
+}
+
+impl WasmScheduler for RoundRobinScheduler {
+    fn should_yield(&self, fuel_consumed: u64, _elapsed_ns: u64) -> bool {
+        fuel_consumed >= self.max_fuel_per_slice
+    }
+}
+
+/// A [`WasmScheduler`] which bounds the time a component runs before it
+/// yields, and so the latency it adds to the other tasks of the runtime.
+#[derive(Clone, Copy, Debug)]
+pub struct LatencyBoundScheduler {
+    /// The milliseconds a component may run before it yields.
+    pub max_ms: u64,
+}
+
+impl WasmScheduler for LatencyBoundScheduler {
+    fn should_yield(&self, _fuel_consumed: u64, elapsed_ns: u64) -> bool {
+        Duration::from_nanos(elapsed_ns) >= Duration::from_millis(self.max_ms)
+    }
+}
+
+/// The scheduler of a context and the start of the current time slice.
+pub(crate) struct Scheduling {
+    scheduler: Arc<dyn WasmScheduler>,
+    slice_started: Instant,
+    slice_fuel: u64,
+}
+
+impl Scheduling {
+    pub(crate) fn new(scheduler: Arc<dyn WasmScheduler>) -> Scheduling {
+        Scheduling {
+            scheduler,
+            slice_started: Instant::now(),
+            slice_fuel: 0,
+        }
+    }
+
+    /// Returns a fresh state sharing the same scheduler, for forked
+    /// contexts.
+    pub(crate) fn fork(&self) -> Scheduling {
+        Scheduling::new(self.scheduler.clone())
+    }
+
+    /// Asks the scheduler whether to yield now that the store consumed
+    /// `fuel_consumed` in total, starting a new slice if so.
+    pub(crate) fn should_yield(&mut self, fuel_consumed: u64) -> bool {
+        let now = Instant::now();
+        let elapsed_ns = now.duration_since(self.slice_started).as_nanos() as u64;
+        let fuel = fuel_consumed.saturating_sub(self.slice_fuel);
+        if !self.scheduler.should_yield(fuel, elapsed_ns) {
+            return false;
+        }
+        self.slice_started = now;
+        self.slice_fuel = fuel_consumed;
+        true
+    }
+}
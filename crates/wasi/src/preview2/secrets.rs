@@ -0,0 +1,60 @@
+use std::fmt;
+
+/// Supplies the value of an environment variable holding a secret, added with
+/// [`WasiCtxBuilder::env_secret`](crate::preview2::WasiCtxBuilder::env_secret).
+///
+/// The value is requested every time the guest reads its environment and is
+/// not kept by the context, so a provider can fetch it from a vault or keep
+/// it encrypted in memory.
+pub trait SecretProvider: Send + Sync {
+    /// Returns the value of the secret.
+    ///
+    /// An error traps the guest reading its environment.
+    fn get(&self) -> anyhow::Result<String>;
+}
+
+/// A [`SecretProvider`] holding its secret in memory, for example for tests.
+///
+/// The secret is zeroed when the provider is dropped, and is not printed by
+/// the `Debug` implementation.
+pub struct EnvSecretProvider {
+    value: String,
+}
+
+impl EnvSecretProvider {
+    /// Creates a provider returning `value`.
+    pub fn new(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+        }
+    }
+}
+
+impl SecretProvider for EnvSecretProvider {
+    fn get(&self) -> anyhow::Result<String> {
+        Ok(self.value.clone())
+    }
+}
+
+impl fmt::Debug for EnvSecretProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvSecretProvider").finish_non_exhaustive()
+    }
+}
+
+impl Drop for EnvSecretProvider {
+    fn drop(&mut self) {
+        zero_string(&mut self.value);
+    }
+}
+
+/// Overwrites the contents of `s` with zeroes, in a way the compiler does not
+/// optimize away, and empties it.
+pub(crate) fn zero_string(s: &mut String) {
+    let mut bytes = std::mem::take(s).into_bytes();
+    for byte in bytes.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned reference to a `u8`.
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex, Weak};
+use wasmtime::Engine;
+
+/// What to do with the component when the host process receives a signal,
+/// as decided by a [`PosixSignalHandler`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignalAction {
+    /// Stop the component at its next yield point.
+    Terminate,
+    /// Capture the state of the component at its next yield point, for
+    /// example to resume it in a new process.
+    Snapshot,
+    /// Carry on as if the signal was not received.
+    Ignore,
+}
+
+/// Decides how a component reacts to the signals received by the host
+/// process, installed with
+/// [`WasiCtxBuilder::with_signal_handler`](crate::preview2::WasiCtxBuilder::with_signal_handler).
+///
+/// Signal actions are process-wide: building the first context with a
+/// handler installs `SIGINT` and `SIGTERM` actions for the whole process, so
+/// that it no longer exits on its own when receiving them, and every context
+/// with a handler receives every signal. These actions stay installed for as
+/// long as any such context, or a fork of one, is alive, and the previous
+/// actions are only put back once the last of them is dropped. Actions
+/// installed by other code in the meantime are overwritten at that point.
+/// Signals are only received on Unix.
+pub trait PosixSignalHandler: Send + Sync {
+    /// Returns the action to take for `signal`, such as `libc::SIGTERM`.
+    ///
+    /// This is called on a thread owned by WASI as soon as the signal is
+    /// received, while the component may be running.
+    fn handle(&self, signal: i32) -> SignalAction;
+}
+
+impl<F> PosixSignalHandler for F
+where
+    F: Fn(i32) -> SignalAction + Send + Sync,
+{
+    fn handle(&self, signal: i32) -> SignalAction {
+        self(signal)
+    }
+}
+
+/// The signals received by one [`WasiCtx`](crate::preview2::WasiCtx), shared
+/// with its forks.
+pub(crate) struct SignalQueue {
+    handler: Box<dyn PosixSignalHandler>,
+    /// Signals whose action is not [`SignalAction::Ignore`], oldest first.
+    pending: Mutex<VecDeque<(i32, SignalAction)>>,
+    /// Engines whose epoch is incremented when a signal is queued.
+    engines: Mutex<Vec<Engine>>,
+}
+
+impl SignalQueue {
+    /// Creates a queue for the signals `handler` does not ignore and starts
+    /// delivering signals to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if this is the only queue and the signal handlers of
+    /// the process could not be installed.
+    pub(crate) fn listen(handler: Box<dyn PosixSignalHandler>) -> io::Result<Arc<SignalQueue>> {
+        let queue = Arc::new(SignalQueue {
+            handler,
+            pending: Mutex::new(VecDeque::new()),
+            engines: Mutex::new(Vec::new()),
+        });
+        let mut signals = SIGNALS.lock().unwrap();
+        signals.queues.retain(|queue| queue.strong_count() > 0);
+        #[cfg(unix)]
+        if signals.listener.is_none() {
+            signals.listener = Some(listener::Listener::start(deliver)?);
+        }
+        signals.queues.push(Arc::downgrade(&queue));
+        Ok(queue)
+    }
+
+    pub(crate) fn interrupt(&self, engine: &Engine) {
+        self.engines.lock().unwrap().push(engine.clone());
+    }
+
+    pub(crate) fn take(&self) -> Option<(i32, SignalAction)> {
+        self.pending.lock().unwrap().pop_front()
+    }
+
+    fn deliver(&self, signal: i32) {
+        let action = self.handler.handle(signal);
+        if action == SignalAction::Ignore {
+            return;
+        }
+        self.pending.lock().unwrap().push_back((signal, action));
+        for engine in self.engines.lock().unwrap().iter() {
+            engine.increment_epoch();
+        }
+    }
+}
+
+impl Drop for SignalQueue {
+    fn drop(&mut self) {
+        let mut signals = SIGNALS.lock().unwrap();
+        signals.queues.retain(|queue| queue.strong_count() > 0);
+        // Hand the signals back to the action they had before the first queue
+        // was created.
+        #[cfg(unix)]
+        if signals.queues.is_empty() {
+            signals.listener = None;
+        }
+    }
+}
+
+/// The queues of every context with a signal handler, along with the
+/// listener delivering the signals of the process to them while there are
+/// any.
+struct GlobalSignals {
+    queues: Vec<Weak<SignalQueue>>,
+    #[cfg(unix)]
+    listener: Option<listener::Listener>,
+}
+
+static SIGNALS: Mutex<GlobalSignals> = Mutex::new(GlobalSignals {
+    queues: Vec::new(),
+    #[cfg(unix)]
+    listener: None,
+});
+
+#[cfg_attr(not(unix), allow(dead_code))]
+fn deliver(signal: i32) {
+    let queues = SIGNALS
+        .lock()
+        .unwrap()
+        .queues
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect::<Vec<_>>();
+    // The lock is released before delivering, as dropping the last reference
+    // to a queue here takes it again.
+    for queue in queues {
+        queue.deliver(signal);
+    }
+}
+
+#[cfg(unix)]
+mod listener {
+    use std::io;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+    /// The write end of the pipe of the running listener, or -1.
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn on_signal(signum: libc::c_int) {
+        let fd = PIPE.load(Ordering::SeqCst);
+        if fd >= 0 {
+            // Only async-signal-safe calls are allowed here. A full pipe
+            // drops the signal, as the listener is already behind.
+            let byte = signum as u8;
+            unsafe {
+                libc::write(fd, (&byte as *const u8).cast(), 1);
+            }
+        }
+    }
+
+    /// Passes `SIGINT` and `SIGTERM` to a thread through a pipe, for as long
+    /// as it is alive. Dropping it restores the previous signal actions and
+    /// stops the thread.
+    pub(super) struct Listener {
+        write: libc::c_int,
+        previous: Vec<(libc::c_int, libc::sigaction)>,
+    }
+
+    impl Listener {
+        /// Installs the signal handlers, then calls `deliver` on a new
+        /// thread with each signal received.
+        ///
+        /// The handlers are installed before this returns, so no signal
+        /// received after a context was built is missed.
+        pub(super) fn start(deliver: fn(i32)) -> io::Result<Listener> {
+            let mut fds = [0; 2];
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let [read, write] = fds;
+            let mut listener = Listener {
+                write,
+                previous: Vec::new(),
+            };
+            unsafe {
+                libc::fcntl(read, libc::F_SETFD, libc::FD_CLOEXEC);
+                libc::fcntl(write, libc::F_SETFD, libc::FD_CLOEXEC);
+                libc::fcntl(write, libc::F_SETFL, libc::O_NONBLOCK);
+            }
+            let spawned = std::thread::Builder::new()
+                .name("wasi-signals".to_owned())
+                .spawn(move || {
+                    let mut byte = 0u8;
+                    loop {
+                        match unsafe { libc::read(read, (&mut byte as *mut u8).cast(), 1) } {
+                            1 => deliver(byte.into()),
+                            -1 if io::Error::last_os_error().kind()
+                                == io::ErrorKind::Interrupted => {}
+                            // The listener was dropped and closed the write
+                            // end.
+                            _ => break,
+                        }
+                    }
+                    unsafe { libc::close(read) };
+                });
+            if let Err(e) = spawned {
+                unsafe { libc::close(read) };
+                return Err(e);
+            }
+            PIPE.store(write, Ordering::SeqCst);
+            for signum in SIGNALS {
+                let previous = unsafe {
+                    let mut action: libc::sigaction = std::mem::zeroed();
+                    action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as usize;
+                    action.sa_flags = libc::SA_RESTART;
+                    libc::sigemptyset(&mut action.sa_mask);
+                    let mut previous: libc::sigaction = std::mem::zeroed();
+                    if libc::sigaction(signum, &action, &mut previous) != 0 {
+                        // Dropping the listener undoes the signals installed
+                        // so far.
+                        return Err(io::Error::last_os_error());
+                    }
+                    previous
+                };
+                listener.previous.push((signum, previous));
+            }
+            Ok(listener)
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            unsafe {
+                for (signum, previous) in &self.previous {
+                    libc::sigaction(*signum, previous, std::ptr::null_mut());
+                }
+                PIPE.store(-1, Ordering::SeqCst);
+                libc::close(self.write);
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use wasmtime::{CallHook, ResourceLimiterAsync};
+
+/// The resources used by a component, returned by
+/// [`WasiCtx::execution_stats`](crate::preview2::WasiCtx::execution_stats),
+/// for example to bill tenants of a shared host.
+///
+/// All values cover the time since the context was built or since
+/// [`WasiCtx::reset_stats`](crate::preview2::WasiCtx::reset_stats) was last
+/// called.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionStats {
+    /// Wall-clock time elapsed.
+    pub wall_time_ns: u64,
+    /// CPU time spent running wasm code, measured with the call hook
+    /// described for
+    /// [`WasiCtx::record_call_hook`](crate::preview2::WasiCtx::record_call_hook).
+    pub cpu_time_ns: u64,
+    /// The largest combined size of the component's linear memories, as
+    /// observed by the resource limiter of the context.
+    pub memory_peak_bytes: usize,
+    /// Number of bytes read from stdin.
+    pub bytes_stdin_read: u64,
+    /// Number of bytes written to stdout.
+    pub bytes_stdout_written: u64,
+    /// Number of bytes written to stderr.
+    pub bytes_stderr_written: u64,
+    /// Number of files and directories opened with `open-at`.
+    pub files_opened: u64,
+    /// Number of calls from wasm into the host, measured with the call hook
+    /// described for
+    /// [`WasiCtx::record_call_hook`](crate::preview2::WasiCtx::record_call_hook).
+    pub syscalls_total: u64,
+}
+
+/// The size of a page of linear memory.
+const WASM_PAGE_SIZE: usize = 0x10000;
+
+/// A single use of a resource by a component, passed to the callback
+/// installed with
+/// [`WasiCtxBuilder::with_resource_callback`](crate::preview2::WasiCtxBuilder::with_resource_callback).
+///
+/// Descriptors and streams are identified by their index in the table of
+/// the context, as also passed to the hooks installed with
+/// [`WasiCtxBuilder::on_resource_create`](crate::preview2::WasiCtxBuilder::on_resource_create).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResourceUsageEvent {
+    /// A file or directory was opened with `open-at`, at the given path
+    /// relative to the descriptor it was opened from.
+    FileOpened(u32, PathBuf),
+    /// The guest dropped its handle to a file or directory.
+    FileClosed(u32),
+    /// A linear memory was grown to the given number of 64 KiB pages, by the
+    /// given number of bytes.
+    MemoryGrown(u32, usize),
+    /// The given number of bytes were written to an output stream.
+    BytesWritten(u32, usize),
+    /// The given number of bytes were read from an input stream.
+    BytesRead(u32, usize),
+}
+
+/// The callback installed with
+/// [`WasiCtxBuilder::with_resource_callback`](crate::preview2::WasiCtxBuilder::with_resource_callback).
+pub(crate) type ResourceCallback = Arc<dyn Fn(ResourceUsageEvent) + Send + Sync>;
+
+/// The counters behind the [`ExecutionStats`] of a context.
+pub(crate) struct ExecutionCounters {
+    created: Instant,
+    /// The byte counters of stdin, stdout and stderr, which are shared with
+    /// the stdio streams and so are never set back to zero.
+    stdio: [Arc<AtomicU64>; 3],
+    /// The values of `stdio` when the stats were last reset.
+    stdio_base: [AtomicU64; 3],
+    /// Nanoseconds after `created` at which the stats were last reset.
+    reset_at_ns: AtomicU64,
+    cpu_time_ns: AtomicU64,
+    /// The CPU time at which wasm was last entered, while it is running.
+    wasm_entered_ns: Mutex<Option<u64>>,
+    memory_bytes: AtomicUsize,
+    memory_peak_bytes: AtomicUsize,
+    files_opened: AtomicU64,
+    syscalls_total: AtomicU64,
+}
+
+impl ExecutionCounters {
+    /// Creates counters starting at zero, with the stdio byte counts
+    /// measured from the current values of `stdio`.
+    pub(crate) fn new(stdio: [Arc<AtomicU64>; 3]) -> Arc<ExecutionCounters> {
+        let stdio_base = [0, 1, 2].map(|i| AtomicU64::new(stdio[i].load(Ordering::Relaxed)));
+        Arc::new(ExecutionCounters {
+            created: Instant::now(),
+            stdio,
+            stdio_base,
+            reset_at_ns: AtomicU64::new(0),
+            cpu_time_ns: AtomicU64::new(0),
+            wasm_entered_ns: Mutex::new(None),
+            memory_bytes: AtomicUsize::new(0),
+            memory_peak_bytes: AtomicUsize::new(0),
+            files_opened: AtomicU64::new(0),
+            syscalls_total: AtomicU64::new(0),
+        })
+    }
+
+    fn elapsed_ns(&self) -> u64 {
+        self.created.elapsed().as_nanos() as u64
+    }
+
+    /// Returns the CPU time of the current thread, or the wall-clock time on
+    /// platforms which do not measure it.
+    fn cpu_now_ns(&self) -> u64 {
+        #[cfg(unix)]
+        {
+            let mut ts = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            // SAFETY: `ts` is a valid timespec to write the time into.
+            if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) } == 0 {
+                return ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64;
+            }
+        }
+        self.elapsed_ns()
+    }
+
+    pub(crate) fn record_call_hook(&self, hook: CallHook) {
+        let mut entered = self.wasm_entered_ns.lock().unwrap();
+        match hook {
+            CallHook::CallingWasm | CallHook::ReturningFromHost => {
+                *entered = Some(self.cpu_now_ns());
+            }
+            CallHook::CallingHost | CallHook::ReturningFromWasm => {
+                if let CallHook::CallingHost = hook {
+                    self.syscalls_total.fetch_add(1, Ordering::Relaxed);
+                }
+                if let Some(start) = entered.take() {
+                    let spent = self.cpu_now_ns().saturating_sub(start);
+                    self.cpu_time_ns.fetch_add(spent, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn file_opened(&self) {
+        self.files_opened.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the combined size of the memories, as seen by the resource
+    /// limiter.
+    pub(crate) fn memory_used(&self) -> usize {
+        self.memory_bytes.load(Ordering::Relaxed)
+    }
+
+    fn memory_grown(&self, by: usize) {
+        let total = self.memory_bytes.fetch_add(by, Ordering::Relaxed) + by;
+        self.memory_peak_bytes.fetch_max(total, Ordering::Relaxed);
+    }
+
+    fn memory_shrunk(&self, by: usize) {
+        self.memory_bytes.fetch_sub(by, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> ExecutionStats {
+        let [stdin, stdout, stderr] = [0, 1, 2].map(|i| {
+            self.stdio[i]
+                .load(Ordering::Relaxed)
+                .saturating_sub(self.stdio_base[i].load(Ordering::Relaxed))
+        });
+        ExecutionStats {
+            wall_time_ns: self
+                .elapsed_ns()
+                .saturating_sub(self.reset_at_ns.load(Ordering::Relaxed)),
+            cpu_time_ns: self.cpu_time_ns.load(Ordering::Relaxed),
+            memory_peak_bytes: self.memory_peak_bytes.load(Ordering::Relaxed),
+            files_opened: self.files_opened.load(Ordering::Relaxed),
+            syscalls_total: self.syscalls_total.load(Ordering::Relaxed),
+            bytes_stdin_read: stdin,
+            bytes_stdout_written: stdout,
+            bytes_stderr_written: stderr,
+        }
+    }
+
+    /// Like [`ExecutionCounters::get`], but also sets the counters back to
+    /// zero. The memory peak restarts at the current size of the memories.
+    pub(crate) fn reset(&self) -> ExecutionStats {
+        let [stdin, stdout, stderr] = [0, 1, 2].map(|i| {
+            let now = self.stdio[i].load(Ordering::Relaxed);
+            now.saturating_sub(self.stdio_base[i].swap(now, Ordering::Relaxed))
+        });
+        let reset_at_ns = self.elapsed_ns();
+        let previous = self.reset_at_ns.swap(reset_at_ns, Ordering::Relaxed);
+        let current_memory = self.memory_bytes.load(Ordering::Relaxed);
+        ExecutionStats {
+            wall_time_ns: reset_at_ns.saturating_sub(previous),
+            cpu_time_ns: self.cpu_time_ns.swap(0, Ordering::Relaxed),
+            memory_peak_bytes: self
+                .memory_peak_bytes
+                .swap(current_memory, Ordering::Relaxed),
+            files_opened: self.files_opened.swap(0, Ordering::Relaxed),
+            syscalls_total: self.syscalls_total.swap(0, Ordering::Relaxed),
+            bytes_stdin_read: stdin,
+            bytes_stdout_written: stdout,
+            bytes_stderr_written: stderr,
+        }
+    }
+}
+
+/// A resource limiter which records the memory growth it allows in the
+/// [`ExecutionCounters`] of a context, and reports it to its resource
+/// callback, before deferring to the limiter it wraps.
+pub(crate) struct StatsLimiter {
+    inner: Box<dyn ResourceLimiterAsync + Send + Sync>,
+    counters: Arc<ExecutionCounters>,
+    callback: Option<ResourceCallback>,
+    /// The growth allowed by the last call to `memory_growing`, undone if
+    /// the growth fails.
+    last_growth: usize,
+}
+
+impl StatsLimiter {
+    pub(crate) fn new(
+        inner: Box<dyn ResourceLimiterAsync + Send + Sync>,
+        counters: Arc<ExecutionCounters>,
+        callback: Option<ResourceCallback>,
+    ) -> Self {
+        Self {
+            inner,
+            counters,
+            callback,
+            last_growth: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl ResourceLimiterAsync for StatsLimiter {
+    async fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let allowed = self.inner.memory_growing(current, desired, maximum).await?;
+        if allowed {
+            self.last_growth = desired.saturating_sub(current);
+            self.counters.memory_grown(self.last_growth);
+            if let Some(callback) = &self.callback {
+                let pages = u32::try_from(desired / WASM_PAGE_SIZE).unwrap_or(u32::MAX);
+                callback(ResourceUsageEvent::MemoryGrown(pages, self.last_growth));
+            }
+        }
+        Ok(allowed)
+    }
+
+    fn memory_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.counters
+            .memory_shrunk(std::mem::take(&mut self.last_growth));
+        self.inner.memory_grow_failed(error)
+    }
+
+    async fn table_growing(
+        &mut self,
+        current: u32,
+        desired: u32,
+        maximum: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        self.inner.table_growing(current, desired, maximum).await
+    }
+
+    fn table_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.inner.table_grow_failed(error)
+    }
+
+    fn instances(&self) -> usize {
+        self.inner.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.inner.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.inner.memories()
+    }
+}
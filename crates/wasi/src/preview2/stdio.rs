@@ -4,8 +4,9 @@ use crate::preview2::bindings::cli::{
 };
 use crate::preview2::bindings::io::streams;
 use crate::preview2::pipe::{self, AsyncWriteStream};
-use crate::preview2::{HostInputStream, HostOutputStream, WasiView};
+use crate::preview2::{HostInputStream, HostOutputStream, ResourceKind, WasiView};
 use std::io::IsTerminal;
+use std::sync::Arc;
 use wasmtime::component::Resource;
 
 /// A trait used to represent the standard input to a guest program.
@@ -14,7 +15,8 @@ use wasmtime::component::Resource;
 /// below.
 ///
 /// Built-in implementations are provided for [`Stdin`],
-/// [`pipe::MemoryInputPipe`], and [`pipe::ClosedInputStream`].
+/// [`pipe::MemoryInputPipe`], [`pipe::GeneratorInputStream`], and
+/// [`pipe::ClosedInputStream`].
 pub trait StdinStream: Send + Sync {
     /// Creates a fresh stream which is reading stdin.
     ///
@@ -31,6 +33,16 @@ pub trait StdinStream: Send + Sync {
 
     /// Returns whether this stream is backed by a TTY.
     fn isatty(&self) -> bool;
+
+    /// Creates a copy of this stdin which makes progress through the input
+    /// independently, for use by a context created with
+    /// [`WasiCtx::fork`](crate::preview2::WasiCtx::fork).
+    ///
+    /// Returns `None` by default, in which case the forked context shares
+    /// this stdin.
+    fn fork(&self) -> Option<Box<dyn StdinStream>> {
+        None
+    }
 }
 
 impl StdinStream for pipe::MemoryInputPipe {
@@ -41,6 +53,24 @@ impl StdinStream for pipe::MemoryInputPipe {
     fn isatty(&self) -> bool {
         false
     }
+
+    fn fork(&self) -> Option<Box<dyn StdinStream>> {
+        Some(Box::new(self.detached()))
+    }
+}
+
+impl StdinStream for pipe::GeneratorInputStream {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+
+    fn fork(&self) -> Option<Box<dyn StdinStream>> {
+        Some(Box::new(self.detached()))
+    }
 }
 
 impl StdinStream for pipe::ClosedInputStream {
@@ -60,7 +90,7 @@ pub use self::worker_thread_stdin::{stdin, Stdin};
 // buffer more than that to implement a wrapper on the host process's stdio. If users
 // really need more, they can write their own implementation using AsyncWriteStream
 // and tokio's stdout/err.
-const STDIO_BUFFER_SIZE: usize = 4096;
+pub(crate) const STDIO_BUFFER_SIZE: usize = 4096;
 
 /// Similar to [`StdinStream`], except for output.
 pub trait StdoutStream: Send + Sync {
@@ -82,6 +112,16 @@ pub trait StdoutStream: Send + Sync {
     fn isatty(&self) -> bool;
 }
 
+impl StdoutStream for Arc<dyn StdoutStream> {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        (**self).stream()
+    }
+
+    fn isatty(&self) -> bool {
+        (**self).isatty()
+    }
+}
+
 impl StdoutStream for pipe::MemoryOutputPipe {
     fn stream(&self) -> Box<dyn HostOutputStream> {
         Box::new(self.clone())
@@ -159,21 +199,30 @@ pub enum IsATTY {
 impl<T: WasiView> stdin::Host for T {
     fn get_stdin(&mut self) -> Result<Resource<streams::InputStream>, anyhow::Error> {
         let stream = self.ctx_mut().stdin.stream();
-        Ok(self.table_mut().push(streams::InputStream::Host(stream))?)
+        let stream = self.table_mut().push(streams::InputStream::Host(stream))?;
+        self.ctx()
+            .resource_created(ResourceKind::InputStream, stream.rep());
+        Ok(stream)
     }
 }
 
 impl<T: WasiView> stdout::Host for T {
     fn get_stdout(&mut self) -> Result<Resource<streams::OutputStream>, anyhow::Error> {
         let stream = self.ctx_mut().stdout.stream();
-        Ok(self.table_mut().push(stream)?)
+        let stream = self.table_mut().push(stream)?;
+        self.ctx()
+            .resource_created(ResourceKind::OutputStream, stream.rep());
+        Ok(stream)
     }
 }
 
 impl<T: WasiView> stderr::Host for T {
     fn get_stderr(&mut self) -> Result<Resource<streams::OutputStream>, anyhow::Error> {
         let stream = self.ctx_mut().stderr.stream();
-        Ok(self.table_mut().push(stream)?)
+        let stream = self.table_mut().push(stream)?;
+        self.ctx()
+            .resource_created(ResourceKind::OutputStream, stream.rep());
+        Ok(stream)
     }
 }
 
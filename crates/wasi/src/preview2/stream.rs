@@ -30,6 +30,26 @@ pub trait HostInputStream: Subscribe {
         let bs = self.read(nelem)?;
         Ok(bs.len())
     }
+
+    /// Returns the number of bytes which a [`read`](Self::read) could
+    /// return right now, without consuming them.
+    ///
+    /// This is a lower bound: streams which cannot tell how much input is
+    /// buffered, such as those reading from the host process, return zero,
+    /// which is the default.
+    fn num_ready_bytes(&self) -> usize {
+        0
+    }
+
+    /// Takes all bytes this stream has received but the guest has not read
+    /// yet out of the stream, used by
+    /// [`WasiCtx::capture_stream_buffers`](crate::preview2::WasiCtx::capture_stream_buffers).
+    ///
+    /// Streams without a buffer of their own, such as those reading directly
+    /// from a socket, return nothing, which is the default.
+    fn drain_pending(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
 }
 
 /// Representation of the `error` resource type in the `wasi:io/streams`
@@ -154,6 +174,17 @@ pub trait HostOutputStream: Subscribe {
         self.ready().await;
         self.check_write()
     }
+
+    /// Takes all bytes the guest has written but which have not been handed
+    /// to the underlying sink yet out of the stream, returning their write
+    /// budget to the stream. Used by
+    /// [`WasiCtx::capture_stream_buffers`](crate::preview2::WasiCtx::capture_stream_buffers).
+    ///
+    /// Bytes which are already being written cannot be taken back. Streams
+    /// which do not buffer writes return nothing, which is the default.
+    fn drain_written(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
 }
 
 #[async_trait::async_trait]
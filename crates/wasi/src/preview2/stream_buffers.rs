@@ -0,0 +1,161 @@
+use crate::preview2::pipe::ClosedInputStream;
+use crate::preview2::{HostInputStream, InputStream, OutputStream, StreamResult, Subscribe, Table};
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The bytes buffered in the streams of a [`Table`], captured with
+/// [`WasiCtx::capture_stream_buffers`](crate::preview2::WasiCtx::capture_stream_buffers)
+/// and put back with
+/// [`WasiCtx::restore_stream_buffers`](crate::preview2::WasiCtx::restore_stream_buffers).
+///
+/// Streams are identified by their index in the table, so the capture can
+/// only be restored into a table holding the same streams at the same
+/// indices, such as the table of a restored snapshot. The capture is
+/// serialized with `serde`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamBufferCapture {
+    /// Bytes received by input streams which the guest has not read yet.
+    pub(crate) inputs: BTreeMap<u32, Vec<u8>>,
+    /// Bytes written by the guest which output streams have not passed on
+    /// yet.
+    pub(crate) outputs: BTreeMap<u32, Vec<u8>>,
+}
+
+impl StreamBufferCapture {
+    /// Returns whether no stream had any buffered bytes.
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty() && self.outputs.is_empty()
+    }
+
+    /// Returns the total number of captured bytes.
+    pub fn len(&self) -> usize {
+        self.inputs
+            .values()
+            .chain(self.outputs.values())
+            .map(Vec::len)
+            .sum()
+    }
+}
+
+/// Takes the buffered bytes out of every stream in `table`.
+pub(crate) fn capture(table: &mut Table) -> Result<StreamBufferCapture> {
+    let mut capture = StreamBufferCapture::default();
+    let inputs = table
+        .iter_input_streams()
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    for index in inputs {
+        // Files are read directly at their current position, so they have
+        // nothing buffered.
+        if let InputStream::Host(stream) = input_stream(table, index)? {
+            let pending = stream.drain_pending();
+            if !pending.is_empty() {
+                capture.inputs.insert(index, pending);
+            }
+        }
+    }
+    let outputs = table
+        .iter_output_streams()
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    for index in outputs {
+        let written = output_stream(table, index)?.drain_written();
+        if !written.is_empty() {
+            capture.outputs.insert(index, written);
+        }
+    }
+    Ok(capture)
+}
+
+/// Puts the bytes of `capture` back into the streams of `table`.
+pub(crate) fn restore(table: &mut Table, capture: StreamBufferCapture) -> Result<()> {
+    // Check every index before touching any stream, so that a capture which
+    // does not belong to this table leaves it unchanged.
+    for index in capture.inputs.keys() {
+        if !table.contains_typed::<InputStream>(*index) {
+            bail!("table entry {index} is not an input stream");
+        }
+    }
+    for index in capture.outputs.keys() {
+        if !table.contains_typed::<OutputStream>(*index) {
+            bail!("table entry {index} is not an output stream");
+        }
+    }
+    for (index, pending) in capture.inputs {
+        match input_stream(table, index)? {
+            InputStream::Host(stream) => {
+                let inner = std::mem::replace(stream, Box::new(ClosedInputStream));
+                *stream = Box::new(PrefixedInputStream {
+                    prefix: pending.into(),
+                    inner,
+                });
+            }
+            InputStream::File(_) => bail!("input stream {index} is a file and cannot buffer"),
+        }
+    }
+    for (index, written) in capture.outputs {
+        let stream = output_stream(table, index)?;
+        let permit = stream.check_write()?;
+        if permit < written.len() {
+            bail!(
+                "output stream {index} accepts {permit} bytes but {} were captured",
+                written.len()
+            );
+        }
+        stream.write(written.into())?;
+    }
+    Ok(())
+}
+
+fn input_stream(table: &mut Table, index: u32) -> Result<&mut InputStream> {
+    table
+        .get_any_mut(index)?
+        .downcast_mut()
+        .ok_or_else(|| anyhow!("table entry {index} is not an input stream"))
+}
+
+fn output_stream(table: &mut Table, index: u32) -> Result<&mut OutputStream> {
+    table
+        .get_any_mut(index)?
+        .downcast_mut()
+        .ok_or_else(|| anyhow!("table entry {index} is not an output stream"))
+}
+
+/// An input stream which delivers restored bytes before those of the
+/// stream it wraps.
+struct PrefixedInputStream {
+    prefix: Bytes,
+    inner: Box<dyn HostInputStream>,
+}
+
+#[async_trait::async_trait]
+impl HostInputStream for PrefixedInputStream {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        if self.prefix.is_empty() {
+            return self.inner.read(size);
+        }
+        let n = size.min(self.prefix.len());
+        Ok(self.prefix.split_to(n))
+    }
+
+    fn num_ready_bytes(&self) -> usize {
+        self.prefix.len() + self.inner.num_ready_bytes()
+    }
+
+    fn drain_pending(&mut self) -> Vec<u8> {
+        let mut pending = std::mem::take(&mut self.prefix).to_vec();
+        pending.extend(self.inner.drain_pending());
+        pending
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for PrefixedInputStream {
+    async fn ready(&mut self) {
+        if self.prefix.is_empty() {
+            self.inner.ready().await;
+        }
+    }
+}
@@ -1,5 +1,11 @@
+use crate::preview2::filesystem::{Descriptor, Dir, File};
+use crate::preview2::tcp::TcpSocket;
+use crate::preview2::udp::UdpSocket;
+use crate::preview2::{HostOutputStream, InputStream, Network, OutputStream, Pollable};
 use std::any::Any;
 use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use wasmtime::component::Resource;
 
 #[derive(thiserror::Error, Debug)]
@@ -12,6 +18,48 @@ pub enum TableError {
     WrongType,
     #[error("entry still has children")]
     HasChildren,
+    #[error("key is already in use")]
+    Occupied,
+}
+
+/// The kind of a WASI resource, as reported to the hooks installed with
+/// [`WasiCtxBuilder::on_resource_create`](crate::preview2::WasiCtxBuilder::on_resource_create)
+/// and
+/// [`WasiCtxBuilder::on_resource_drop`](crate::preview2::WasiCtxBuilder::on_resource_drop).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    InputStream,
+    OutputStream,
+    Dir,
+    File,
+    TcpSocket,
+    UdpSocket,
+    Network,
+}
+
+impl ResourceKind {
+    /// Returns the kind of the table entry `entry`, or `None` if it is not
+    /// one of the resources above.
+    fn of(entry: &dyn Any) -> Option<ResourceKind> {
+        if entry.is::<InputStream>() {
+            Some(ResourceKind::InputStream)
+        } else if entry.is::<OutputStream>() {
+            Some(ResourceKind::OutputStream)
+        } else if let Some(descriptor) = entry.downcast_ref::<Descriptor>() {
+            match descriptor {
+                Descriptor::Dir(_) => Some(ResourceKind::Dir),
+                Descriptor::File(_) => Some(ResourceKind::File),
+            }
+        } else if entry.is::<TcpSocket>() {
+            Some(ResourceKind::TcpSocket)
+        } else if entry.is::<UdpSocket>() {
+            Some(ResourceKind::UdpSocket)
+        } else if entry.is::<Network>() {
+            Some(ResourceKind::Network)
+        } else {
+            None
+        }
+    }
 }
 
 /// The `Table` type is designed to map u32 handles to resources. The table is now part of the
@@ -25,6 +73,19 @@ pub enum TableError {
 pub struct Table {
     map: HashMap<u32, TableEntry>,
     next_key: u32,
+    max_entries: usize,
+    /// The number of slots claimed by outstanding [`ReservationHandle`]s,
+    /// which release their slots when dropped.
+    reserved: Arc<AtomicUsize>,
+}
+
+/// The outcome of [`Table::compact`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableCompactionResult {
+    /// The new index of every entry which was moved, by its old index.
+    pub remapped: HashMap<u32, u32>,
+    /// The number of bytes of storage released by the table.
+    pub freed_bytes: usize,
 }
 
 /// This structure tracks parent and child relationships for a given table entry.
@@ -74,9 +135,44 @@ impl Table {
             // Once we have a full implementation of resources, this confusion should hopefully be
             // impossible :)
             next_key: 3,
+            max_entries: u32::MAX as usize,
+            reserved: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Create an empty table which holds at most `max_entries` entries,
+    /// counting slots claimed with [`Table::reserve`].
+    pub fn with_max_entries(max_entries: u32) -> Self {
+        Table {
+            max_entries: max_entries as usize,
+            ..Table::new()
         }
     }
 
+    /// Claims `n` slots of this table, which can then only be filled through
+    /// the returned [`ReservationHandle`].
+    ///
+    /// Fails with [`TableError::Full`] if the current entries plus all
+    /// outstanding reservations would exceed the table's limit. Slots which
+    /// are not filled are released when the handle is dropped.
+    ///
+    /// The handle does not borrow the table, so it can be kept across other
+    /// uses of the table, such as pushes by concurrent host calls.
+    pub fn reserve(&mut self, n: u32) -> Result<ReservationHandle, TableError> {
+        if self.map.len() + self.reserved() + n as usize > self.max_entries {
+            return Err(TableError::Full);
+        }
+        self.reserved.fetch_add(n as usize, Ordering::Relaxed);
+        Ok(ReservationHandle {
+            reserved: self.reserved.clone(),
+            remaining: n,
+        })
+    }
+
+    fn reserved(&self) -> usize {
+        self.reserved.load(Ordering::Relaxed)
+    }
+
     /// Inserts a new value `T` into this table, returning a corresponding
     /// `Resource<T>` which can be used to refer to it after it was inserted.
     pub fn push<T>(&mut self, entry: T) -> Result<Resource<T>, TableError>
@@ -87,10 +183,57 @@ impl Table {
         Ok(Resource::new_own(idx))
     }
 
+    /// Inserts a new value `T` into this table at the key `index`, which
+    /// must not be in use yet.
+    ///
+    /// Keys assigned by [`Table::push`] skip over keys in use, so this can be
+    /// used to place resources at predetermined keys before any other
+    /// resources are pushed.
+    pub fn push_at<T>(&mut self, index: u32, entry: T) -> Result<Resource<T>, TableError>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.push_at_(index, Box::new(entry))?;
+        Ok(Resource::new_own(index))
+    }
+
+    fn push_at_(
+        &mut self,
+        index: u32,
+        entry: Box<dyn Any + Send + Sync>,
+    ) -> Result<(), TableError> {
+        if self.map.contains_key(&index) {
+            return Err(TableError::Occupied);
+        }
+        if self.map.len() + self.reserved() >= self.max_entries {
+            return Err(TableError::Full);
+        }
+        self.map.insert(index, TableEntry::new(entry, None));
+        Ok(())
+    }
+
+    /// Inserts already boxed values at their keys, as with [`Table::push_at`].
+    /// Nothing is inserted if any of the values can't be.
+    pub(crate) fn push_boxed_at(
+        &mut self,
+        entries: Vec<(u32, Box<dyn Any + Send + Sync>)>,
+    ) -> Result<(), TableError> {
+        if entries.iter().any(|(i, _)| self.map.contains_key(i)) {
+            return Err(TableError::Occupied);
+        }
+        if self.map.len() + self.reserved() + entries.len() > self.max_entries {
+            return Err(TableError::Full);
+        }
+        for (index, entry) in entries {
+            self.push_at_(index, entry)?;
+        }
+        Ok(())
+    }
+
     fn push_(&mut self, e: TableEntry) -> Result<u32, TableError> {
         // NOTE: The performance of this new key calculation could be very bad once keys wrap
         // around.
-        if self.map.len() == u32::MAX as usize {
+        if self.map.len() + self.reserved() >= self.max_entries {
             return Err(TableError::Full);
         }
         loop {
@@ -161,6 +304,21 @@ impl Table {
             .ok_or(TableError::WrongType)
     }
 
+    /// Returns whether the table holds a resource at the `key` index
+    /// provided.
+    ///
+    /// Indices of deleted resources, or indices which belong to another
+    /// table, are not contained unless the index has been reused.
+    pub fn contains(&self, key: u32) -> bool {
+        self.map.contains_key(&key)
+    }
+
+    /// Returns whether the table holds a resource of type `T` at the `key`
+    /// index provided, without borrowing it.
+    pub fn contains_typed<T: Any>(&self, key: u32) -> bool {
+        self.map.get(&key).map_or(false, |r| r.entry.is::<T>())
+    }
+
     fn get_(&self, key: u32) -> Result<&dyn Any, TableError> {
         let r = self.map.get(&key).ok_or(TableError::NotPresent)?;
         Ok(&*r.entry)
@@ -216,6 +374,121 @@ impl Table {
         Ok(e)
     }
 
+    /// Moves all entries to the lowest free keys, preserving their order, and
+    /// releases unused storage.
+    ///
+    /// Keys 0 to 2 are never assigned by the table and entries placed there
+    /// with [`Table::push_at`] stay where they are. Parent and child
+    /// relationships, and the resources pollables are subscribed to, are
+    /// updated. Any other indices held outside of the table, including
+    /// [`Resource`]s held by the host or by a guest, refer to the old keys
+    /// and must be translated through
+    /// [`TableCompactionResult::remapped`]. Compaction is therefore only
+    /// safe while no guest holds handles into this table.
+    pub fn compact(&mut self) -> TableCompactionResult {
+        let entry_size = std::mem::size_of::<(u32, TableEntry)>();
+        let capacity = self.map.capacity();
+
+        let mut keys = self
+            .map
+            .keys()
+            .copied()
+            .filter(|k| *k >= 3)
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        let remapped = keys
+            .iter()
+            .zip(3u32..)
+            .filter(|(old, new)| **old != *new)
+            .map(|(old, new)| (*old, new))
+            .collect::<HashMap<_, _>>();
+        let remap = |key: u32| remapped.get(&key).copied().unwrap_or(key);
+
+        self.map = std::mem::take(&mut self.map)
+            .into_iter()
+            .map(|(key, mut entry)| {
+                entry.parent = entry.parent.map(remap);
+                entry.children = entry.children.iter().map(|c| remap(*c)).collect();
+                if let Some(pollable) = entry.entry.downcast_mut::<Pollable>() {
+                    pollable.remap(remap);
+                }
+                (remap(key), entry)
+            })
+            .collect();
+        self.map.shrink_to_fit();
+        self.next_key = 3 + keys.len() as u32;
+
+        TableCompactionResult {
+            remapped,
+            freed_bytes: capacity.saturating_sub(self.map.capacity()) * entry_size,
+        }
+    }
+
+    /// Iterates over the index and value of every entry holding a resource of
+    /// `kind`, in no particular order.
+    ///
+    /// Directories and files are both stored as a
+    /// [`Descriptor`](crate::preview2::bindings::filesystem::types::Descriptor),
+    /// which is what their values downcast to.
+    pub fn iter_by_kind(&self, kind: ResourceKind) -> impl Iterator<Item = (u32, &dyn Any)> {
+        self.map
+            .iter()
+            .map(|(k, e)| (*k, &*e.entry as &dyn Any))
+            .filter(move |(_, e)| ResourceKind::of(*e) == Some(kind))
+    }
+
+    /// Returns the number of entries holding each kind of resource. Kinds
+    /// without any entries are omitted.
+    pub fn count_by_kind(&self) -> HashMap<ResourceKind, usize> {
+        let mut counts = HashMap::new();
+        for e in self.map.values() {
+            if let Some(kind) = ResourceKind::of(&*e.entry) {
+                *counts.entry(kind).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    fn iter_typed<T: Any>(&self) -> impl Iterator<Item = (u32, &T)> {
+        self.map
+            .iter()
+            .filter_map(|(k, e)| Some((*k, e.entry.downcast_ref::<T>()?)))
+    }
+
+    /// Iterates over all input streams in this table, in no particular order.
+    pub fn iter_input_streams(&self) -> impl Iterator<Item = (u32, &InputStream)> {
+        self.iter_typed::<InputStream>()
+    }
+
+    /// Iterates over all output streams in this table, in no particular
+    /// order.
+    pub fn iter_output_streams(&self) -> impl Iterator<Item = (u32, &dyn HostOutputStream)> {
+        self.iter_typed::<OutputStream>().map(|(k, s)| (k, &**s))
+    }
+
+    /// Iterates over all open directories in this table, in no particular
+    /// order.
+    pub fn iter_dirs(&self) -> impl Iterator<Item = (u32, &Dir)> {
+        self.iter_typed::<Descriptor>()
+            .filter_map(|(k, d)| Some((k, d.dir().ok()?)))
+    }
+
+    /// Iterates over all open files in this table, in no particular order.
+    pub fn iter_files(&self) -> impl Iterator<Item = (u32, &File)> {
+        self.iter_typed::<Descriptor>()
+            .filter_map(|(k, d)| Some((k, d.file().ok()?)))
+    }
+
+    /// Iterates over all TCP sockets in this table, in no particular order.
+    pub fn iter_tcp_sockets(&self) -> impl Iterator<Item = (u32, &TcpSocket)> {
+        self.iter_typed::<TcpSocket>()
+    }
+
+    /// Iterates over all UDP sockets in this table, in no particular order.
+    pub fn iter_udp_sockets(&self) -> impl Iterator<Item = (u32, &UdpSocket)> {
+        self.iter_typed::<UdpSocket>()
+    }
+
     /// Zip the values of the map with mutable references to table entries corresponding to each
     /// key. As the keys in the [HashMap] are unique, this iterator can give mutable references
     /// with the same lifetime as the mutable reference to the [Table].
@@ -236,6 +509,68 @@ impl Table {
     }
 }
 
+/// Slots of a [`Table`] claimed with [`Table::reserve`].
+///
+/// Each push consumes one slot. Any slots left when the handle is dropped are
+/// released back to the table.
+#[derive(Debug)]
+pub struct ReservationHandle {
+    /// The reservation count of the table the slots were claimed in.
+    reserved: Arc<AtomicUsize>,
+    remaining: u32,
+}
+
+impl ReservationHandle {
+    /// The number of reserved slots which have not been filled yet.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Inserts `entry` into one of the reserved slots of `table`, like
+    /// [`Table::push`].
+    ///
+    /// Fails with [`TableError::Full`] if all reserved slots have been used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slots were not reserved in `table`.
+    pub fn push<T>(&mut self, table: &mut Table, entry: T) -> Result<Resource<T>, TableError>
+    where
+        T: Send + Sync + 'static,
+    {
+        assert!(
+            Arc::ptr_eq(&self.reserved, &table.reserved),
+            "slots were reserved in another table"
+        );
+        if self.remaining == 0 {
+            return Err(TableError::Full);
+        }
+        self.remaining -= 1;
+        self.reserved.fetch_sub(1, Ordering::Relaxed);
+        table.push(entry)
+    }
+
+    /// Inserts `stream` into one of the reserved slots of `table`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slots were not reserved in `table`.
+    pub fn push_input_stream(
+        &mut self,
+        table: &mut Table,
+        stream: InputStream,
+    ) -> Result<Resource<InputStream>, TableError> {
+        self.push(table, stream)
+    }
+}
+
+impl Drop for ReservationHandle {
+    fn drop(&mut self) {
+        self.reserved
+            .fetch_sub(self.remaining as usize, Ordering::Relaxed);
+    }
+}
+
 impl Default for Table {
     fn default() -> Self {
         Table::new()
@@ -6,9 +6,15 @@ use anyhow::{Error, Result};
 use cap_net_ext::{AddressFamily, Blocking, TcpListenerExt};
 use cap_std::net::TcpListener;
 use io_lifetimes::raw::{FromRawSocketlike, IntoRawSocketlike};
+#[cfg(unix)]
+use io_lifetimes::AsSocketlike;
 use rustix::net::sockopt;
 use std::io;
 use std::mem;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::io::Interest;
 
@@ -72,6 +78,22 @@ pub struct TcpSocket {
     /// The manually configured TTL. `None` means: no preference, use system default.
     #[cfg(target_os = "macos")]
     pub(crate) hop_limit: Option<u8>,
+
+    /// The TCP addresses of this socket if it is carried over a Unix domain
+    /// socket, which the OS cannot report.
+    pub(crate) unix_addrs: Option<UnixSocketAddrs>,
+
+    /// The Unix domain socket file bound by this socket, which is removed
+    /// when the socket is dropped.
+    unix_path: Option<PathBuf>,
+}
+
+/// The TCP addresses of a socket carried over a Unix domain socket, as set up
+/// by [`WasiCtxBuilder::allow_unix_sockets`](crate::preview2::WasiCtxBuilder::allow_unix_sockets).
+#[derive(Copy, Clone)]
+pub(crate) struct UnixSocketAddrs {
+    pub(crate) local: SocketAddr,
+    pub(crate) remote: SocketAddr,
 }
 
 #[derive(Copy, Clone)]
@@ -306,9 +328,78 @@ impl TcpSocket {
             send_buffer_size: None,
             #[cfg(target_os = "macos")]
             hop_limit: None,
+            unix_addrs: None,
+            unix_path: None,
         })
     }
 
+    /// Creates a socket bound to the Unix domain socket at `path`, standing
+    /// in for the TCP address `addr`.
+    #[cfg(unix)]
+    pub(crate) fn bind_unix(
+        path: &Path,
+        addr: SocketAddr,
+        family: SocketAddressFamily,
+    ) -> io::Result<Self> {
+        let listener = std::os::unix::net::UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        let addrs = UnixSocketAddrs {
+            local: addr,
+            remote: SocketAddr::new(addr.ip(), 0),
+        };
+        let mut socket = Self::from_unix_socket(listener.into(), family, addrs)?;
+        socket.unix_path = Some(path.to_owned());
+        Ok(socket)
+    }
+
+    /// Creates a socket connected to the Unix domain socket at `path`,
+    /// standing in for the TCP address `addr`.
+    #[cfg(unix)]
+    pub(crate) fn connect_unix(
+        path: &Path,
+        addr: SocketAddr,
+        family: SocketAddressFamily,
+    ) -> io::Result<Self> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        stream.set_nonblocking(true)?;
+        let addrs = UnixSocketAddrs {
+            local: SocketAddr::new(addr.ip(), 0),
+            remote: addr,
+        };
+        Self::from_unix_socket(stream.into(), family, addrs)
+    }
+
+    /// Accepts a connection if this socket was bound with
+    /// [`TcpSocket::bind_unix`], returning `None` for other sockets.
+    #[cfg(unix)]
+    pub(crate) fn accept_unix(&self) -> Option<io::Result<Self>> {
+        let addrs = self.unix_addrs?;
+        let accepted = self.inner.try_io(Interest::READABLE, || {
+            self.inner
+                .as_socketlike_view::<std::os::unix::net::UnixListener>()
+                .accept()
+        });
+        Some(accepted.and_then(|(stream, _)| {
+            stream.set_nonblocking(true)?;
+            let addrs = UnixSocketAddrs {
+                local: addrs.local,
+                remote: SocketAddr::new(addrs.local.ip(), 0),
+            };
+            Self::from_unix_socket(stream.into(), self.family, addrs)
+        }))
+    }
+
+    #[cfg(unix)]
+    fn from_unix_socket(
+        fd: rustix::fd::OwnedFd,
+        family: SocketAddressFamily,
+        addrs: UnixSocketAddrs,
+    ) -> io::Result<Self> {
+        let mut socket = Self::from_tcp_listener(TcpListener::from(fd), family)?;
+        socket.unix_addrs = Some(addrs);
+        Ok(socket)
+    }
+
     pub fn tcp_socket(&self) -> &tokio::net::TcpStream {
         &self.inner
     }
@@ -321,6 +412,15 @@ impl TcpSocket {
     }
 }
 
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        if let Some(path) = &self.unix_path {
+            // Ignore errors, as the embedder may have removed the file already.
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl Subscribe for TcpSocket {
     async fn ready(&mut self) {
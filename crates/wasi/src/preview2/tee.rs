@@ -0,0 +1,494 @@
+use crate::preview2::{
+    HostInputStream, HostOutputStream, IsATTY, StdinStream, StdoutStream, StreamError,
+    StreamResult, Subscribe,
+};
+use anyhow::anyhow;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// A writer shared between the streams which log to it.
+pub(crate) type SharedWriter = Arc<Mutex<dyn Write + Send>>;
+
+#[derive(Debug)]
+struct SinkState {
+    alive: bool,
+    items: VecDeque<Bytes>,
+    buffered: usize,
+    flush_pending: bool,
+    error: Option<anyhow::Error>,
+}
+
+impl SinkState {
+    fn check_error(&mut self) -> Result<(), StreamError> {
+        if let Some(e) = self.error.take() {
+            return Err(StreamError::LastOperationFailed(e));
+        }
+        if !self.alive {
+            return Err(StreamError::Closed);
+        }
+        Ok(())
+    }
+}
+
+/// The buffer in front of one of the sinks of a [`TeeOutputStream`], and the
+/// task writing it into the sink.
+struct Sink {
+    buffer_size: usize,
+    state: Mutex<SinkState>,
+    new_work: tokio::sync::Notify,
+    write_ready_changed: tokio::sync::Notify,
+}
+
+enum Job {
+    Flush,
+    Write(Bytes),
+}
+
+impl Sink {
+    fn new(buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            state: Mutex::new(SinkState {
+                alive: true,
+                items: VecDeque::new(),
+                buffered: 0,
+                flush_pending: false,
+                error: None,
+            }),
+            new_work: tokio::sync::Notify::new(),
+            write_ready_changed: tokio::sync::Notify::new(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<SinkState> {
+        self.state.lock().unwrap()
+    }
+
+    async fn ready(&self) {
+        loop {
+            {
+                let state = self.state();
+                if state.error.is_some()
+                    || !state.alive
+                    || (!state.flush_pending && state.buffered < self.buffer_size)
+                {
+                    return;
+                }
+            }
+            self.write_ready_changed.notified().await;
+        }
+    }
+
+    /// Returns how many more bytes this sink can buffer, or zero while a
+    /// flush is pending.
+    fn check_write(&self) -> Result<usize, StreamError> {
+        let mut state = self.state();
+        state.check_error()?;
+        if state.flush_pending {
+            return Ok(0);
+        }
+        Ok(self.buffer_size - state.buffered)
+    }
+
+    fn pop(&self) -> Option<Job> {
+        let mut state = self.state();
+        match state.items.pop_front() {
+            Some(bytes) => Some(Job::Write(bytes)),
+            None if state.flush_pending => Some(Job::Flush),
+            None => None,
+        }
+    }
+
+    fn report_error(&self, e: StreamError) {
+        {
+            let mut state = self.state();
+            state.alive = false;
+            state.flush_pending = false;
+            match e {
+                StreamError::Closed => {}
+                StreamError::LastOperationFailed(e) | StreamError::Trap(e) => state.error = Some(e),
+            }
+        }
+        self.write_ready_changed.notify_one();
+    }
+
+    async fn work(&self, mut sink: Box<dyn HostOutputStream>) {
+        loop {
+            while let Some(job) = self.pop() {
+                let result = match job {
+                    Job::Write(bytes) => self.write_all(&mut *sink, bytes).await,
+                    Job::Flush => Self::flush(&mut *sink).await.map(|()| {
+                        self.state().flush_pending = false;
+                    }),
+                };
+                if let Err(e) = result {
+                    self.report_error(e);
+                    return;
+                }
+                self.write_ready_changed.notify_one();
+            }
+            self.new_work.notified().await;
+        }
+    }
+
+    /// Writes all of `bytes` into `sink`, as fast as the sink permits.
+    async fn write_all(
+        &self,
+        sink: &mut dyn HostOutputStream,
+        mut bytes: Bytes,
+    ) -> StreamResult<()> {
+        while !bytes.is_empty() {
+            sink.ready().await;
+            let permit = sink.check_write()?;
+            if permit == 0 {
+                continue;
+            }
+            let chunk = bytes.split_to(permit.min(bytes.len()));
+            let len = chunk.len();
+            sink.write(chunk)?;
+            self.state().buffered -= len;
+            self.write_ready_changed.notify_one();
+        }
+        Ok(())
+    }
+
+    async fn flush(sink: &mut dyn HostOutputStream) -> StreamResult<()> {
+        sink.flush()?;
+        // Completion of a flush is signalled by the stream becoming writable.
+        sink.ready().await;
+        sink.check_write()?;
+        Ok(())
+    }
+}
+
+/// A [`HostOutputStream`] which writes everything written to it into two
+/// other output streams.
+///
+/// Each of the two sinks is fed by its own task from a buffer of
+/// `buffer_size` bytes, so that a slow sink does not hold up the other one
+/// until its buffer is full. Writes are permitted while both buffers have
+/// room, and a flush is complete once both sinks have been flushed. An error
+/// or closure of either sink is reported by the tee, after which the other
+/// sink receives no more data.
+///
+/// Clones of a `TeeOutputStream` share its buffers and sinks.
+#[derive(Clone)]
+pub struct TeeOutputStream {
+    sinks: [Arc<Sink>; 2],
+    _join_handles: Arc<[crate::preview2::AbortOnDropJoinHandle<()>; 2]>,
+}
+
+impl TeeOutputStream {
+    /// Creates a stream which writes into both `primary` and `secondary`,
+    /// buffering up to `buffer_size` bytes for each of them.
+    pub fn new(
+        buffer_size: usize,
+        primary: impl HostOutputStream,
+        secondary: impl HostOutputStream,
+    ) -> Self {
+        Self::from_boxed(buffer_size, Box::new(primary), Box::new(secondary))
+    }
+
+    pub(crate) fn from_boxed(
+        buffer_size: usize,
+        primary: Box<dyn HostOutputStream>,
+        secondary: Box<dyn HostOutputStream>,
+    ) -> Self {
+        let sinks = [
+            Arc::new(Sink::new(buffer_size)),
+            Arc::new(Sink::new(buffer_size)),
+        ];
+        let spawn = |sink: &Arc<Sink>, stream: Box<dyn HostOutputStream>| {
+            let sink = Arc::clone(sink);
+            crate::preview2::spawn(async move { sink.work(stream).await })
+        };
+        let join_handles = [spawn(&sinks[0], primary), spawn(&sinks[1], secondary)];
+        TeeOutputStream {
+            sinks,
+            _join_handles: Arc::new(join_handles),
+        }
+    }
+}
+
+impl HostOutputStream for TeeOutputStream {
+    fn write(&mut self, bytes: Bytes) -> Result<(), StreamError> {
+        let mut states = self.sinks.iter().map(|s| s.state()).collect::<Vec<_>>();
+        for state in states.iter_mut() {
+            state.check_error()?;
+            if state.flush_pending {
+                return Err(StreamError::Trap(anyhow!(
+                    "write not permitted while flush pending"
+                )));
+            }
+        }
+        for (sink, state) in self.sinks.iter().zip(&states) {
+            if bytes.len() > sink.buffer_size - state.buffered {
+                return Err(StreamError::Trap(anyhow!("write exceeded budget")));
+            }
+        }
+        for state in states.iter_mut() {
+            state.buffered += bytes.len();
+            state.items.push_back(bytes.clone());
+        }
+        drop(states);
+        for sink in self.sinks.iter() {
+            sink.new_work.notify_one();
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StreamError> {
+        for sink in self.sinks.iter() {
+            let mut state = sink.state();
+            state.check_error()?;
+            state.flush_pending = true;
+        }
+        for sink in self.sinks.iter() {
+            sink.new_work.notify_one();
+        }
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> Result<usize, StreamError> {
+        let primary = self.sinks[0].check_write()?;
+        let secondary = self.sinks[1].check_write()?;
+        Ok(primary.min(secondary))
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for TeeOutputStream {
+    async fn ready(&mut self) {
+        for sink in self.sinks.iter() {
+            sink.ready().await;
+        }
+    }
+}
+
+/// A [`HostInputStream`] which writes every chunk read from another input
+/// stream into a log.
+///
+/// Bytes which are skipped are logged as well. If writing to the log fails,
+/// the read fails with [`StreamError::LastOperationFailed`] and the chunk is
+/// lost.
+pub struct TeeInputStream {
+    input: Box<dyn HostInputStream>,
+    log: SharedWriter,
+}
+
+impl TeeInputStream {
+    /// Creates a stream reading from `input` and copying everything it reads
+    /// into `log`.
+    pub fn new(input: impl HostInputStream, log: impl Write + Send + 'static) -> Self {
+        Self {
+            input: Box::new(input),
+            log: Arc::new(Mutex::new(log)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HostInputStream for TeeInputStream {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        let bytes = self.input.read(size)?;
+        if !bytes.is_empty() {
+            self.log
+                .lock()
+                .unwrap()
+                .write_all(&bytes)
+                .map_err(|e| StreamError::LastOperationFailed(e.into()))?;
+        }
+        Ok(bytes)
+    }
+
+    fn num_ready_bytes(&self) -> usize {
+        self.input.num_ready_bytes()
+    }
+
+    fn drain_pending(&mut self) -> Vec<u8> {
+        // Pending bytes have not been read, so they have not been logged.
+        self.input.drain_pending()
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for TeeInputStream {
+    async fn ready(&mut self) {
+        self.input.ready().await;
+    }
+}
+
+/// A [`HostOutputStream`] writing into a [`std::io::Write`].
+///
+/// Writes block the calling thread, so this is only suitable for writers
+/// which are fast, such as in-memory logs.
+pub(crate) struct WriteOutputStream {
+    writer: SharedWriter,
+    budget: usize,
+}
+
+impl WriteOutputStream {
+    pub(crate) fn new(writer: SharedWriter, budget: usize) -> Self {
+        Self { writer, budget }
+    }
+}
+
+impl HostOutputStream for WriteOutputStream {
+    fn write(&mut self, bytes: Bytes) -> Result<(), StreamError> {
+        self.writer
+            .lock()
+            .unwrap()
+            .write_all(&bytes)
+            .map_err(|e| StreamError::LastOperationFailed(e.into()))
+    }
+
+    fn flush(&mut self) -> Result<(), StreamError> {
+        self.writer
+            .lock()
+            .unwrap()
+            .flush()
+            .map_err(|e| StreamError::LastOperationFailed(e.into()))
+    }
+
+    fn check_write(&mut self) -> Result<usize, StreamError> {
+        Ok(self.budget)
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for WriteOutputStream {
+    async fn ready(&mut self) {}
+}
+
+/// Standard output or error writing into a [`TeeOutputStream`].
+pub(crate) struct TeeStdout {
+    stream: TeeOutputStream,
+    isatty: IsATTY,
+}
+
+impl TeeStdout {
+    pub(crate) fn new(stream: TeeOutputStream, isatty: IsATTY) -> Self {
+        Self { stream, isatty }
+    }
+}
+
+impl StdoutStream for TeeStdout {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.stream.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        self.isatty == IsATTY::Yes
+    }
+}
+
+/// Standard input copying everything read from it into a log.
+pub(crate) struct TeeStdin {
+    stdin: Box<dyn StdinStream>,
+    log: SharedWriter,
+}
+
+impl TeeStdin {
+    pub(crate) fn new(stdin: Box<dyn StdinStream>, log: SharedWriter) -> Self {
+        Self { stdin, log }
+    }
+}
+
+impl StdinStream for TeeStdin {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(TeeInputStream {
+            input: self.stdin.stream(),
+            log: Arc::clone(&self.log),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        self.stdin.isatty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::preview2::pipe::{MemoryInputPipe, MemoryOutputPipe};
+    use std::time::Duration;
+
+    /// An output stream accepting at most two bytes at a time, becoming
+    /// writable again only after a delay.
+    struct SlowSink {
+        written: MemoryOutputPipe,
+        permit: usize,
+    }
+
+    impl HostOutputStream for SlowSink {
+        fn write(&mut self, bytes: Bytes) -> Result<(), StreamError> {
+            assert!(bytes.len() <= self.permit);
+            self.permit -= bytes.len();
+            self.written.write(bytes)
+        }
+        fn flush(&mut self) -> Result<(), StreamError> {
+            Ok(())
+        }
+        fn check_write(&mut self) -> Result<usize, StreamError> {
+            Ok(self.permit)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Subscribe for SlowSink {
+        async fn ready(&mut self) {
+            if self.permit == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                self.permit = 2;
+            }
+        }
+    }
+
+    #[test_log::test(tokio::test(flavor = "multi_thread"))]
+    async fn tee_buffers_for_slow_sink() {
+        let fast = MemoryOutputPipe::new(1024);
+        let slow = MemoryOutputPipe::new(1024);
+        let mut tee = TeeOutputStream::new(
+            16,
+            fast.clone(),
+            SlowSink {
+                written: slow.clone(),
+                permit: 2,
+            },
+        );
+
+        // The whole buffer can be written at once even though the slow sink
+        // only takes two bytes at a time.
+        assert_eq!(tee.check_write().unwrap(), 16);
+        tee.write(Bytes::from_static(b"hello, ")).unwrap();
+        tee.write(Bytes::from_static(b"world!")).unwrap();
+        assert!(matches!(
+            tee.write(Bytes::from_static(b"too much")),
+            Err(StreamError::Trap(_))
+        ));
+
+        tee.flush().unwrap();
+        tokio::time::timeout(Duration::from_secs(2), tee.ready())
+            .await
+            .expect("flush timed out");
+        assert_eq!(tee.check_write().unwrap(), 16);
+        assert_eq!(&*fast.contents(), b"hello, world!");
+        assert_eq!(&*slow.contents(), b"hello, world!");
+    }
+
+    #[test]
+    fn tee_input_logs_reads() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut input = TeeInputStream {
+            input: Box::new(MemoryInputPipe::new(Bytes::from_static(b"audit me"))),
+            log: log.clone(),
+        };
+        assert_eq!(&*input.read(5).unwrap(), b"audit");
+        assert_eq!(input.skip(1).unwrap(), 1);
+        assert_eq!(&*input.read(5).unwrap(), b"me");
+        assert!(matches!(input.read(5), Err(StreamError::Closed)));
+        assert_eq!(&*log.lock().unwrap(), b"audit me");
+    }
+}
@@ -0,0 +1,28 @@
+use anyhow::Result;
+use wasmtime::SharedMemory;
+
+/// A host callback which starts the threads of the [wasi-threads] proposal,
+/// installed with
+/// [`WasiCtxBuilder::with_thread_spawner`](crate::preview2::WasiCtxBuilder::with_thread_spawner).
+///
+/// Spawn requests reach the spawner through the `thread-spawn` function
+/// defined by `add_to_linker_with_spawner` of the `wasmtime-wasi-threads`
+/// crate. Each thread is a new instance of the module running on its own OS
+/// thread, importing the same shared memory as the instance which spawned
+/// it. The spawner is responsible for creating that instance and calling
+/// its `wasi_thread_start` export with `thread_id` and `start_arg`.
+///
+/// [wasi-threads]: https://github.com/WebAssembly/wasi-threads
+pub trait WasiThreadSpawner: Send + Sync {
+    /// Starts a new thread sharing `shared_memory`.
+    ///
+    /// `shared_memory` is a reference-counted handle which may be sent to
+    /// the new thread and used there concurrently; all threads observe the
+    /// same memory contents.
+    ///
+    /// # Errors
+    ///
+    /// Returning an error reports to the guest that the thread could not be
+    /// spawned.
+    fn spawn(&self, shared_memory: SharedMemory, thread_id: i32, start_arg: i32) -> Result<()>;
+}
@@ -0,0 +1,174 @@
+use std::fmt::{self, Write as _};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Writes a record of WASI host calls to a writer, configured with
+/// [`WasiCtxBuilder::trace_syscalls`](crate::preview2::WasiCtxBuilder::trace_syscalls).
+///
+/// Each call is written as a single line of JSON such as
+/// `{"ts":1234,"call":"output-stream.write","args":[3,"68690a"],"ret":null}`,
+/// where `ts` is the number of nanoseconds since the context was built. Byte
+/// buffers are written as hex strings and failed calls have a `ret` of the
+/// form `{"error":"..."}`. If the context has a
+/// [debug label](crate::preview2::WasiCtxBuilder::debug_label), it is
+/// written as a `label` field after `ts`.
+pub(crate) struct SyscallTrace {
+    writer: Mutex<Box<dyn Write + Send>>,
+    start: Instant,
+    label: Option<String>,
+}
+
+/// An argument or return value of a traced call.
+pub(crate) enum TraceValue<'a> {
+    Null,
+    Bool(bool),
+    U64(u64),
+    Str(&'a str),
+    Bytes(&'a [u8]),
+    List(Vec<TraceValue<'a>>),
+    Error(String),
+}
+
+impl<'a> TraceValue<'a> {
+    /// Converts the result of a call, using `ok` for successful results.
+    pub(crate) fn result<T, E: fmt::Debug>(
+        result: &'a Result<T, E>,
+        ok: impl FnOnce(&'a T) -> TraceValue<'a>,
+    ) -> TraceValue<'a> {
+        match result {
+            Ok(value) => ok(value),
+            Err(e) => TraceValue::Error(format!("{e:?}")),
+        }
+    }
+
+    fn write_json(&self, out: &mut String) {
+        match self {
+            TraceValue::Null => out.push_str("null"),
+            TraceValue::Bool(b) => write!(out, "{b}").unwrap(),
+            TraceValue::U64(n) => write!(out, "{n}").unwrap(),
+            TraceValue::Str(s) => write_json_string(out, s),
+            TraceValue::Bytes(bytes) => {
+                out.push('"');
+                for b in bytes.iter() {
+                    write!(out, "{b:02x}").unwrap();
+                }
+                out.push('"');
+            }
+            TraceValue::List(values) => {
+                out.push('[');
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    v.write_json(out);
+                }
+                out.push(']');
+            }
+            TraceValue::Error(e) => {
+                out.push_str("{\"error\":");
+                write_json_string(out, e);
+                out.push('}');
+            }
+        }
+    }
+}
+
+impl SyscallTrace {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        SyscallTrace {
+            writer: Mutex::new(writer),
+            start: Instant::now(),
+            label: None,
+        }
+    }
+
+    /// Labels all records with `label`.
+    pub(crate) fn with_label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Writes a record of the call `call`. Errors writing the record are
+    /// ignored so that tracing never affects the guest.
+    pub(crate) fn record(&self, call: &str, args: &[TraceValue<'_>], ret: &TraceValue<'_>) {
+        let mut line = String::new();
+        write!(line, "{{\"ts\":{},", self.start.elapsed().as_nanos()).unwrap();
+        if let Some(label) = &self.label {
+            line.push_str("\"label\":");
+            write_json_string(&mut line, label);
+            line.push(',');
+        }
+        line.push_str("\"call\":");
+        write_json_string(&mut line, call);
+        line.push_str(",\"args\":[");
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            arg.write_json(&mut line);
+        }
+        line.push_str("],\"ret\":");
+        ret.write_json(&mut line);
+        line.push_str("}\n");
+        let _ = self.writer.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+pub(crate) fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_are_json_lines() {
+        let buffer = Buffer::default();
+        let trace = SyscallTrace::new(Box::new(buffer.clone()));
+        trace.record(
+            "descriptor.open-at",
+            &[TraceValue::U64(3), TraceValue::Str("a \"b\"\n")],
+            &TraceValue::Error("NotPermitted".to_string()),
+        );
+        trace.record(
+            "input-stream.read",
+            &[TraceValue::U64(4), TraceValue::U64(2)],
+            &TraceValue::List(vec![TraceValue::Bytes(&[0xab, 1]), TraceValue::Bool(false)]),
+        );
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(
+            r#","call":"descriptor.open-at","args":[3,"a \"b\"\n"],"ret":{"error":"NotPermitted"}}"#
+        ));
+        assert!(lines[1].ends_with(r#","args":[4,2],"ret":["ab01",false]}"#));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::preview2::{HostOutputStream, StdoutStream, StreamResult, Subscribe};
+use bytes::{Bytes, BytesMut};
+use tracing::{Level, Span};
+
+/// Standard output or error whose lines are emitted as `tracing` events,
+/// configured with
+/// [`WasiCtxBuilder::stdout_to_tracing`](crate::preview2::WasiCtxBuilder::stdout_to_tracing)
+/// and
+/// [`WasiCtxBuilder::stderr_to_tracing`](crate::preview2::WasiCtxBuilder::stderr_to_tracing).
+pub(crate) struct TracingStdout {
+    level: Level,
+    stream: &'static str,
+    component: Option<String>,
+    request_id: Option<String>,
+}
+
+impl TracingStdout {
+    pub(crate) fn new(
+        level: Level,
+        stream: &'static str,
+        component: Option<String>,
+        request_id: Option<String>,
+    ) -> Self {
+        Self {
+            level,
+            stream,
+            component,
+            request_id,
+        }
+    }
+}
+
+impl StdoutStream for TracingStdout {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        let span = match self.level {
+            Level::ERROR => tracing::error_span!(
+                "wasi",
+                component = tracing::field::Empty,
+                request_id = tracing::field::Empty
+            ),
+            Level::WARN => tracing::warn_span!(
+                "wasi",
+                component = tracing::field::Empty,
+                request_id = tracing::field::Empty
+            ),
+            Level::INFO => tracing::info_span!(
+                "wasi",
+                component = tracing::field::Empty,
+                request_id = tracing::field::Empty
+            ),
+            Level::DEBUG => tracing::debug_span!(
+                "wasi",
+                component = tracing::field::Empty,
+                request_id = tracing::field::Empty
+            ),
+            _ => tracing::trace_span!(
+                "wasi",
+                component = tracing::field::Empty,
+                request_id = tracing::field::Empty
+            ),
+        };
+        if let Some(component) = &self.component {
+            span.record("component", component.as_str());
+        }
+        if let Some(request_id) = &self.request_id {
+            span.record("request_id", request_id.as_str());
+        }
+        Box::new(TracingOutputStream {
+            level: self.level,
+            stream: self.stream,
+            span,
+            partial: BytesMut::new(),
+        })
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+/// An output stream which emits every line written to it as a `tracing`
+/// event, within a span whose `component` and `request_id` fields are the
+/// debug label and request ID of the context.
+///
+/// A line is emitted once its newline is written, so a line written in
+/// several pieces is still a single event. A trailing line without a newline
+/// is emitted when the stream is dropped.
+struct TracingOutputStream {
+    level: Level,
+    stream: &'static str,
+    span: Span,
+    partial: BytesMut,
+}
+
+impl TracingOutputStream {
+    fn emit(&self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        let stream = self.stream;
+        let _enter = self.span.enter();
+        match self.level {
+            Level::ERROR => tracing::error!(stream, "{line}"),
+            Level::WARN => tracing::warn!(stream, "{line}"),
+            Level::INFO => tracing::info!(stream, "{line}"),
+            Level::DEBUG => tracing::debug!(stream, "{line}"),
+            _ => tracing::trace!(stream, "{line}"),
+        }
+    }
+}
+
+impl HostOutputStream for TracingOutputStream {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.partial.extend_from_slice(&bytes);
+        while let Some(newline) = self.partial.iter().position(|b| *b == b'\n') {
+            let line = self.partial.split_to(newline + 1);
+            self.emit(&line[..newline]);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(usize::MAX)
+    }
+}
+
+impl Drop for TracingOutputStream {
+    fn drop(&mut self) {
+        if !self.partial.is_empty() {
+            self.emit(&self.partial);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Subscribe for TracingOutputStream {
+    async fn ready(&mut self) {}
+}
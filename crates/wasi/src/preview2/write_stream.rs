@@ -194,6 +194,18 @@ impl HostOutputStream for AsyncWriteStream {
     fn check_write(&mut self) -> Result<usize, StreamError> {
         self.worker.check_write()
     }
+
+    fn drain_written(&mut self) -> Vec<u8> {
+        let mut state = self.worker.state();
+        let mut drained = Vec::new();
+        for bytes in state.items.drain(..) {
+            drained.extend_from_slice(&bytes);
+        }
+        state.write_budget += drained.len();
+        drop(state);
+        self.worker.write_ready_changed.notify_one();
+        drained
+    }
 }
 #[async_trait::async_trait]
 impl Subscribe for AsyncWriteStream {
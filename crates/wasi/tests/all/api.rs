@@ -2,7 +2,7 @@ use anyhow::Result;
 use cap_std::ambient_authority;
 use cap_std::fs::Dir;
 use std::io::Write;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use wasmtime::component::{Component, Linker};
 use wasmtime::{Config, Engine, Store};
@@ -34,6 +34,20 @@ impl WasiView for CommandCtx {
     }
 }
 
+/// A writer appending to a buffer shared by its clones, for inspecting what
+/// was written to it.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 use test_programs_artifacts::*;
 
 foreach_api!(assert_test_exists);
@@ -211,3 +225,3641 @@ async fn api_reactor() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn api_random_from_seed() -> Result<()> {
+    use preview2::bindings::random::{insecure, random};
+
+    let ctx = || CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .secure_random_from_seed([7; 32])
+            .insecure_random_from_seed(42)
+            .build(),
+    };
+    let (mut a, mut b) = (ctx(), ctx());
+
+    assert_eq!(
+        random::Host::get_random_bytes(&mut a, 64)?,
+        random::Host::get_random_bytes(&mut b, 64)?
+    );
+    assert_eq!(
+        random::Host::get_random_u64(&mut a)?,
+        random::Host::get_random_u64(&mut b)?
+    );
+    assert_eq!(
+        insecure::Host::get_insecure_random_bytes(&mut a, 64)?,
+        insecure::Host::get_insecure_random_bytes(&mut b, 64)?
+    );
+    Ok(())
+}
+
+#[test]
+fn api_component_id() {
+    let wasi = WasiCtxBuilder::new()
+        .component_id(String::from("tenant-1"))
+        .build();
+    assert_eq!(wasi.component_id::<String>().unwrap(), "tenant-1");
+    assert!(wasi.component_id::<u32>().is_none());
+    assert!(WasiCtxBuilder::new()
+        .build()
+        .component_id::<String>()
+        .is_none());
+}
+
+#[test]
+fn api_preopened_dir_at_host_path() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut builder = WasiCtxBuilder::new();
+    builder.preopened_dir_at_host_path(dir.path(), DirPerms::READ, FilePerms::READ, "/")?;
+    assert!(builder
+        .preopened_dir_at_host_path(
+            dir.path().join("missing"),
+            DirPerms::all(),
+            FilePerms::all(),
+            "/missing",
+        )
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_preopened_dir_shorthands() -> Result<()> {
+    use filesystem::{DescriptorFlags, HostDescriptor as _};
+    use preview2::bindings::filesystem::preopens::Host as _;
+
+    let dir = tempfile::tempdir()?;
+    let open_dir = || Dir::open_ambient_dir(dir.path(), ambient_authority());
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .preopened_readonly_dir(open_dir()?, "/ro")
+            .preopened_readwrite_dir(open_dir()?, "/rw")
+            .build(),
+    };
+    let dirs = ctx.get_directories()?;
+    let mut flags = Vec::new();
+    for (dir, path) in dirs {
+        flags.push((path, ctx.get_flags(dir).await?));
+    }
+    assert_eq!(
+        flags,
+        [
+            ("/ro".to_string(), DescriptorFlags::READ),
+            (
+                "/rw".to_string(),
+                DescriptorFlags::READ | DescriptorFlags::MUTATE_DIRECTORY
+            ),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn api_builder_from_config() -> Result<()> {
+    use preview2::bindings::cli::environment::Host as _;
+
+    let dir = tempfile::tempdir()?;
+    let json = format!(
+        r#"{{"wasi": {{
+            "env": {{"FOO": "bar"}},
+            "args": ["prog", "--verbose"],
+            "max_open_files": 100,
+            "network": "none",
+            "debug_label": "worker-1",
+            "stdout_encoding": "latin1",
+            "preopens": [{{"host": {:?}, "guest": "/data", "readonly": true}}]
+        }}}}"#,
+        dir.path().to_str().unwrap()
+    );
+    let wasi = WasiCtxBuilder::from_json(&json)?.build();
+    assert_eq!(wasi.debug_label(), Some("worker-1"));
+    let mut ctx = CommandCtx {
+        table: wasi.new_table(),
+        wasi,
+    };
+    assert_eq!(
+        ctx.get_environment()?,
+        [("FOO".to_string(), "bar".to_string())]
+    );
+    assert_eq!(ctx.get_arguments()?, ["prog", "--verbose"]);
+
+    // Unknown settings, values of the wrong type and missing directories
+    // are rejected.
+    assert!(WasiCtxBuilder::from_json(r#"{"wasi": {"max_open_file": 1}}"#).is_err());
+    assert!(WasiCtxBuilder::from_json(r#"{"wasi": {"network": "some"}}"#).is_err());
+    assert!(WasiCtxBuilder::from_json(r#"{"wasi": {"args": "prog"}}"#).is_err());
+    let missing = format!(
+        r#"{{"wasi": {{"preopens": [{{"host": {:?}, "guest": "/"}}]}}}}"#,
+        dir.path().join("missing").to_str().unwrap()
+    );
+    assert!(WasiCtxBuilder::from_json(&missing).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "toml")]
+fn api_builder_from_toml() -> Result<()> {
+    use preview2::bindings::cli::environment::Host as _;
+
+    let dir = tempfile::tempdir()?;
+    let toml = format!(
+        r#"
+[wasi]
+env = {{ FOO = "bar" }}
+args = ["prog", "--verbose"]
+max_open_files = 100
+network = "none"
+debug_label = "worker-1"
+preopens = [{{ host = {:?}, guest = "/data", readonly = true }}]
+"#,
+        dir.path().to_str().unwrap()
+    );
+    let wasi = WasiCtxBuilder::from_toml(&toml)?.build();
+    assert_eq!(wasi.debug_label(), Some("worker-1"));
+    let mut ctx = CommandCtx {
+        table: wasi.new_table(),
+        wasi,
+    };
+    assert_eq!(
+        ctx.get_environment()?,
+        [("FOO".to_string(), "bar".to_string())]
+    );
+    assert_eq!(ctx.get_arguments()?, ["prog", "--verbose"]);
+
+    assert!(WasiCtxBuilder::from_toml("[wasi]\nmax_open_file = 1").is_err());
+    assert!(WasiCtxBuilder::from_toml("[wasi]\nnetwork = \"some\"").is_err());
+    let missing = format!(
+        "[wasi]\npreopens = [{{ host = {:?}, guest = \"/\" }}]",
+        dir.path().join("missing").to_str().unwrap()
+    );
+    assert!(WasiCtxBuilder::from_toml(&missing).is_err());
+    Ok(())
+}
+
+#[test]
+fn api_env_from_dotenv() -> Result<()> {
+    use preview2::bindings::cli::environment::Host as _;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join(".env");
+    std::fs::write(
+        &path,
+        r#"
+# database settings
+DB_HOST=localhost
+export DB_PORT=5432 # inline comment
+GREETING="hello world"
+ESCAPED="a \"b\"\nc"
+RAW='# not a comment'
+EMPTY=
+KEEP=from-file
+"#,
+    )?;
+
+    let wasi = WasiCtxBuilder::new()
+        .env("KEEP", "from-builder")
+        .env_from_dotenv(&path)?
+        .build();
+    let mut ctx = CommandCtx {
+        table: wasi.new_table(),
+        wasi,
+    };
+    let env = ctx.get_environment()?;
+    let get = |k: &str| {
+        env.iter()
+            .find(|(key, _)| key == k)
+            .map(|(_, v)| v.as_str())
+    };
+    assert_eq!(get("DB_HOST"), Some("localhost"));
+    assert_eq!(get("DB_PORT"), Some("5432"));
+    assert_eq!(get("GREETING"), Some("hello world"));
+    assert_eq!(get("ESCAPED"), Some("a \"b\"\nc"));
+    assert_eq!(get("RAW"), Some("# not a comment"));
+    assert_eq!(get("EMPTY"), Some(""));
+    assert_eq!(get("KEEP"), Some("from-builder"));
+    assert_eq!(env.iter().filter(|(k, _)| k == "KEEP").count(), 1);
+
+    assert!(WasiCtxBuilder::new()
+        .env_from_dotenv(dir.path().join("missing.env"))
+        .is_err());
+    std::fs::write(&path, "NOT A DEFINITION\n")?;
+    assert!(WasiCtxBuilder::new().env_from_dotenv(&path).is_err());
+    Ok(())
+}
+
+#[test]
+fn api_env_secret() -> Result<()> {
+    use preview2::bindings::cli::environment::Host as _;
+    use preview2::{EnvSecretProvider, SecretProvider};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct CountingProvider(Arc<AtomicUsize>);
+
+    impl SecretProvider for CountingProvider {
+        fn get(&self) -> Result<String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok("rotated".to_string())
+        }
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let wasi = WasiCtxBuilder::new()
+        .env("USER", "app")
+        .env_secret("DB_PASSWORD", EnvSecretProvider::new("hunter2"))
+        .env_secret("TOKEN", CountingProvider(calls.clone()))
+        .build();
+    let mut ctx = CommandCtx {
+        table: wasi.new_table(),
+        wasi,
+    };
+
+    // Secrets are only requested when the environment is read.
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+    assert_eq!(
+        ctx.get_environment()?,
+        vec![
+            ("USER".to_string(), "app".to_string()),
+            ("DB_PASSWORD".to_string(), "hunter2".to_string()),
+            ("TOKEN".to_string(), "rotated".to_string()),
+        ]
+    );
+    ctx.get_environment()?;
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert!(!format!("{:?}", EnvSecretProvider::new("hunter2")).contains("hunter2"));
+
+    // Secrets are not exported with the rest of the environment.
+    let state = format!("{:?}", ctx.wasi.export_state());
+    assert!(state.contains("USER"));
+    assert!(!state.contains("DB_PASSWORD") && !state.contains("hunter2"));
+
+    // Failing to get a secret traps.
+    struct FailingProvider;
+
+    impl SecretProvider for FailingProvider {
+        fn get(&self) -> Result<String> {
+            anyhow::bail!("vault unavailable")
+        }
+    }
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .env_secret("TOKEN", FailingProvider)
+            .build(),
+    };
+    let err = ctx.get_environment().unwrap_err();
+    assert!(format!("{err:#}").contains("vault unavailable"), "{err:#}");
+    Ok(())
+}
+
+#[test]
+fn api_env_namespace() -> Result<()> {
+    use preview2::bindings::cli::environment::Host as _;
+    use preview2::EnvSecretProvider;
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .env("TENANT_A__FOO", "a")
+            .env("TENANT_B__FOO", "b")
+            .env("TENANT_A__", "unnamed")
+            .env("PATH", "/usr/bin")
+            .env_secret("TENANT_A__TOKEN", EnvSecretProvider::new("secret"))
+            .env_secret("TENANT_B__TOKEN", EnvSecretProvider::new("other"))
+            .build(),
+    };
+    assert_eq!(ctx.get_environment()?.len(), 6);
+
+    ctx.wasi.set_env_namespace("TENANT_A__");
+    assert_eq!(
+        ctx.get_environment()?,
+        [
+            ("FOO".to_string(), "a".to_string()),
+            ("TOKEN".to_string(), "secret".to_string()),
+        ]
+    );
+
+    let mut fork = CommandCtx {
+        table: Table::new(),
+        wasi: ctx.wasi.fork(&mut ctx.table)?,
+    };
+    fork.wasi.set_env_namespace("TENANT_B__");
+    assert_eq!(
+        fork.get_environment()?[0],
+        ("FOO".to_string(), "b".to_string())
+    );
+    assert_eq!(
+        ctx.get_environment()?[0],
+        ("FOO".to_string(), "a".to_string())
+    );
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "tmpdir")]
+fn api_preopen_tmpdir() -> Result<()> {
+    use preview2::bindings::filesystem::preopens::Host as _;
+
+    let mut builder = WasiCtxBuilder::new();
+    let (tmpdir, builder) = builder.preopen_tmpdir("/scratch")?;
+    let wasi = builder.build();
+    let mut ctx = CommandCtx {
+        table: wasi.new_table(),
+        wasi,
+    };
+    let dirs = ctx.get_directories()?;
+    assert_eq!(dirs.len(), 1);
+    assert_eq!(dirs[0].1, "/scratch");
+
+    let path = tmpdir.path().to_owned();
+    assert!(path.is_dir());
+    drop(tmpdir);
+    assert!(!path.exists());
+    Ok(())
+}
+
+#[test]
+fn api_network_pool_capture() -> Result<()> {
+    let original = WasiCtxBuilder::new()
+        .insert_socket_addr("127.0.0.1:8080".parse()?)
+        .insert_ip_net_port_range("10.0.0.0/8".parse()?, 1000, Some(2000))
+        .build();
+    let capture = original.capture_pool();
+
+    let restored = WasiCtxBuilder::new().pool_from_capture(&capture)?.build();
+    assert_eq!(restored.capture_pool(), capture);
+    assert_ne!(WasiCtxBuilder::new().build().capture_pool(), capture);
+    Ok(())
+}
+
+#[test]
+fn api_capability_token() -> Result<()> {
+    use preview2::{CapabilityIssuer, CapabilityToken};
+
+    let net = "10.0.0.0/8".parse()?;
+    let issuer = CapabilityIssuer::new();
+    let token = issuer.issue();
+    assert!(issuer.verify(&token));
+
+    let mut builder = WasiCtxBuilder::new();
+    builder.capability_issuer(issuer.clone());
+    builder.insert_ip_net_with_token(net, 1000, Some(2000), &token)?;
+    let expected = WasiCtxBuilder::new()
+        .insert_ip_net_port_range(net, 1000, Some(2000))
+        .build()
+        .capture_pool();
+    assert_eq!(builder.build().capture_pool(), expected);
+
+    // Forged, revoked and unverifiable tokens are refused.
+    let forged = CapabilityToken::from_bytes([0; 32]);
+    let mut builder = WasiCtxBuilder::new();
+    builder.capability_issuer(issuer.clone());
+    let err = builder
+        .insert_ip_net_with_token(net, 1000, None, &forged)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    assert!(issuer.revoke(&token));
+    assert!(builder
+        .insert_ip_net_with_token(net, 1000, None, &token)
+        .is_err());
+    assert_eq!(
+        builder.build().capture_pool(),
+        WasiCtxBuilder::new().build().capture_pool()
+    );
+    assert!(WasiCtxBuilder::new()
+        .insert_ip_net_with_token(net, 1000, None, &issuer.issue())
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn api_wasi_state_export() -> Result<()> {
+    use preview2::bindings::cli::environment::Host as _;
+    use preview2::bindings::clocks::monotonic_clock;
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use std::time::SystemTime;
+
+    struct AheadWallClock;
+
+    impl HostWallClock for AheadWallClock {
+        fn resolution(&self) -> Duration {
+            Duration::from_secs(1)
+        }
+
+        fn now(&self) -> Duration {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                + Duration::from_secs(3600)
+        }
+    }
+
+    struct FixedMonotonicClock;
+
+    impl HostMonotonicClock for FixedMonotonicClock {
+        fn resolution(&self) -> u64 {
+            1
+        }
+
+        fn now(&self) -> u64 {
+            5_000_000_000
+        }
+    }
+
+    let dir = tempfile::tempdir()?;
+    let original = WasiCtxBuilder::new()
+        .env("KEY", "value")
+        .arg("prog")
+        .preopened_dir_at_host_path(dir.path(), DirPerms::READ, FilePerms::READ, "/data")?
+        .insert_socket_addr("127.0.0.1:8080".parse()?)
+        .wall_clock(AheadWallClock)
+        .monotonic_clock(FixedMonotonicClock)
+        .build();
+    let state = original.export_state();
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .import_wasi_state(state.clone())?
+            .build(),
+    };
+    assert_eq!(
+        ctx.get_environment()?,
+        [("KEY".to_string(), "value".to_string())]
+    );
+    assert_eq!(ctx.get_arguments()?, ["prog"]);
+    let dirs = ctx.get_directories()?;
+    assert_eq!(dirs.len(), 1);
+    assert_eq!(dirs[0].1, "/data");
+    assert_eq!(ctx.wasi.capture_pool(), original.capture_pool());
+
+    // The clocks continue from where they were in the original context.
+    assert!(monotonic_clock::Host::now(&mut ctx)? >= 5_000_000_000);
+    let host_now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    let now = wall_clock::Host::now(&mut ctx)?.seconds;
+    assert!(now >= host_now + 3500 && now <= host_now + 3700, "{now}");
+
+    // Preopens whose host directory is gone cannot be imported.
+    drop(dir);
+    assert!(WasiCtxBuilder::new().import_wasi_state(state).is_err());
+    Ok(())
+}
+
+#[test]
+fn api_sandbox_mode() -> Result<()> {
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::bindings::random::random::Host as _;
+
+    let dir = tempfile::tempdir()?;
+    let wasi = WasiCtxBuilder::new()
+        .inherit_network(ambient_authority())
+        .preopened_dir_at_host_path(dir.path(), DirPerms::all(), FilePerms::all(), "/")?
+        .sandbox_mode()
+        .build();
+    let mut ctx = CommandCtx {
+        table: wasi.new_table(),
+        wasi,
+    };
+    assert!(ctx.get_directories()?.is_empty());
+    assert_eq!(
+        ctx.wasi.capture_pool(),
+        WasiCtxBuilder::new().build().capture_pool()
+    );
+    assert_eq!(wall_clock::Host::now(&mut ctx)?.seconds, 0);
+    assert_eq!(
+        preview2::bindings::clocks::monotonic_clock::Host::now(&mut ctx)?,
+        0
+    );
+    let bytes = ctx.get_random_bytes(16)?;
+    let mut other = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().sandbox_mode().build(),
+    };
+    assert_eq!(other.get_random_bytes(16)?, bytes);
+
+    for _ in 0..256 {
+        ctx.table.push(())?;
+    }
+    assert!(ctx.table.push(()).is_err());
+
+    // Settings can be loosened again after entering sandbox mode.
+    let wasi = WasiCtxBuilder::new()
+        .sandbox_mode()
+        .inherit_network(ambient_authority())
+        .build();
+    assert_eq!(
+        wasi.capture_pool(),
+        WasiCtxBuilder::new()
+            .inherit_network(ambient_authority())
+            .build()
+            .capture_pool()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_stdin_from_bytes() -> Result<()> {
+    use preview2::bindings::cli::stdin::Host as _;
+    use preview2::bindings::cli::terminal_stdin::Host as _;
+    use preview2::bindings::io::streams::HostInputStream;
+    use preview2::StreamError;
+    use wasmtime::component::Resource;
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().stdin_from_str("hello").build(),
+    };
+    assert!(ctx.get_terminal_stdin()?.is_none());
+    let stdin = ctx.get_stdin()?.rep();
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 3).await;
+    assert_eq!(read.ok(), Some(b"hel".to_vec()));
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 10).await;
+    assert_eq!(read.ok(), Some(b"lo".to_vec()));
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 10).await;
+    assert!(matches!(read, Err(StreamError::Closed)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_stdin_generator() -> Result<()> {
+    use preview2::bindings::cli::stdin::Host as _;
+    use preview2::bindings::io::streams::HostInputStream;
+    use preview2::pipe::GeneratorInputStream;
+    use preview2::StreamError;
+    use wasmtime::component::Resource;
+
+    async fn read_all(ctx: &mut CommandCtx) -> Result<Vec<u8>> {
+        let stdin = ctx.get_stdin()?.rep();
+        let mut contents = Vec::new();
+        loop {
+            match HostInputStream::read(&mut *ctx, Resource::new_borrow(stdin), 4).await {
+                Ok(bytes) => contents.extend(bytes),
+                Err(StreamError::Closed) => return Ok(contents),
+                Err(e) => panic!("unexpected error: {e}"),
+            }
+        }
+    }
+
+    // Five chunks of 1 to 5 bytes, read 4 bytes at a time.
+    let generator = |n: u64| (n < 5).then(|| vec![n as u8; n as usize + 1]);
+    let expected = (0..5u8)
+        .flat_map(|n| vec![n; n as usize + 1])
+        .collect::<Vec<_>>();
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().stdin_generator(generator).build(),
+    };
+    assert_eq!(read_all(&mut ctx).await?, expected);
+
+    let stream = GeneratorInputStream::new(generator);
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().stdin(stream.clone()).build(),
+    };
+    assert_eq!(read_all(&mut ctx).await?, expected);
+    let capture = stream.capture();
+    assert_eq!(capture.len(), 5);
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .stdin(GeneratorInputStream::from_capture(capture))
+            .build(),
+    };
+    assert_eq!(read_all(&mut ctx).await?, expected);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn api_pipe() -> Result<()> {
+    use preview2::bindings::cli::stdin::Host as _;
+    use preview2::bindings::cli::stdout::Host as _;
+    use preview2::bindings::io::streams::{HostInputStream, HostOutputStream};
+    use preview2::StreamError;
+    use wasmtime::component::Resource;
+
+    const LEN: usize = 200_000;
+    let data = (0..LEN).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+    let mut producer = WasiCtxBuilder::new();
+    let mut consumer = WasiCtxBuilder::new();
+    WasiCtxBuilder::pipe(&mut producer, &mut consumer);
+    let mut producer = CommandCtx {
+        table: Table::new(),
+        wasi: producer.build(),
+    };
+    let mut consumer = CommandCtx {
+        table: Table::new(),
+        wasi: consumer.build(),
+    };
+
+    // Until the consumer reads, the producer can only fill the pipe.
+    let stdout = producer.get_stdout()?.rep();
+    let mut written = 0;
+    loop {
+        let permit = HostOutputStream::check_write(&mut producer, Resource::new_borrow(stdout))?;
+        if permit == 0 {
+            break;
+        }
+        let chunk = data[written..written + permit as usize].to_vec();
+        HostOutputStream::write(&mut producer, Resource::new_borrow(stdout), chunk)?;
+        written += permit as usize;
+    }
+    assert_eq!(written, 65536 + 4096);
+
+    let writer = tokio::spawn({
+        let data = data.clone();
+        async move {
+            for chunk in data[written..].chunks(4096) {
+                HostOutputStream::blocking_write_and_flush(
+                    &mut producer,
+                    Resource::new_borrow(stdout),
+                    chunk.to_vec(),
+                )
+                .await
+                .unwrap();
+            }
+            // Dropping the producer closes the pipe.
+        }
+    });
+
+    let stdin = consumer.get_stdin()?.rep();
+    let mut read = Vec::new();
+    loop {
+        match HostInputStream::blocking_read(&mut consumer, Resource::new_borrow(stdin), 1000).await
+        {
+            Ok(bytes) => read.extend(bytes),
+            Err(StreamError::Closed) => break,
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+    writer.await?;
+    assert_eq!(read.len(), LEN);
+    assert!(read == data);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn api_tee_stdio() -> Result<()> {
+    use preview2::bindings::cli::stdin::Host as _;
+    use preview2::bindings::cli::stdout::Host as _;
+    use preview2::bindings::io::streams::{HostInputStream, HostOutputStream};
+    use preview2::pipe::{MemoryInputPipe, MemoryOutputPipe};
+    use preview2::IsATTY;
+    use wasmtime::component::Resource;
+
+    let primary = MemoryOutputPipe::new(1024);
+    let secondary = MemoryOutputPipe::new(1024);
+    let audit = Buffer::default();
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .tee_stdin(MemoryInputPipe::new("input".into()), audit.clone())
+            .tee_stdout(primary.clone(), secondary.clone(), IsATTY::No)
+            .build(),
+    };
+
+    let stdin = ctx.get_stdin()?.rep();
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 3).await;
+    assert_eq!(read.ok(), Some(b"inp".to_vec()));
+    assert_eq!(&*audit.0.lock().unwrap(), b"inp");
+
+    let stdout = ctx.get_stdout()?.rep();
+    for chunk in [&b"hello, "[..], b"world"] {
+        HostOutputStream::blocking_write_and_flush(
+            &mut ctx,
+            Resource::new_borrow(stdout),
+            chunk.to_vec(),
+        )
+        .await?;
+    }
+    assert_eq!(&*primary.contents(), b"hello, world");
+    assert_eq!(&*secondary.contents(), b"hello, world");
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn api_rate_limit_io() -> Result<()> {
+    use preview2::bindings::cli::stdin::Host as _;
+    use preview2::bindings::cli::stdout::Host as _;
+    use preview2::bindings::io::streams::{HostInputStream, HostOutputStream};
+    use preview2::pipe::{MemoryInputPipe, MemoryOutputPipe};
+    use preview2::IoRateLimitConfig;
+    use std::time::{Duration, Instant};
+    use wasmtime::component::Resource;
+
+    const RATE: u64 = 10_000;
+    const LEN: usize = 10_000;
+
+    /// Checks that transferring `len` bytes took at least as long as the
+    /// limit allows. Only a lower bound is checked, since a loaded machine
+    /// may always be slower than the limit.
+    fn assert_limited(len: usize, start: Instant) {
+        // A twentieth of a second of traffic may be sent in a burst up front.
+        let paced = len as u64 - RATE / 20;
+        let min = Duration::from_secs_f64(paced as f64 / RATE as f64);
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed + Duration::from_millis(10) >= min,
+            "transferred {len} bytes in {elapsed:?}, faster than {RATE} bytes/s"
+        );
+    }
+
+    let stdout = MemoryOutputPipe::new(2 * LEN);
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .stdin(MemoryInputPipe::new(vec![1; LEN].into()))
+            .stdout(stdout.clone())
+            .rate_limit_io(IoRateLimitConfig {
+                read_bytes_per_sec: Some(RATE),
+                write_bytes_per_sec: Some(RATE),
+            })
+            .build(),
+    };
+
+    let output = ctx.get_stdout()?.rep();
+    let start = Instant::now();
+    for _ in 0..LEN / 1000 {
+        HostOutputStream::blocking_write_and_flush(
+            &mut ctx,
+            Resource::new_borrow(output),
+            vec![2; 1000],
+        )
+        .await?;
+    }
+    assert_limited(LEN, start);
+    assert_eq!(stdout.contents().len(), LEN);
+
+    let input = ctx.get_stdin()?.rep();
+    let start = Instant::now();
+    let mut read = 0;
+    while read < LEN {
+        read += HostInputStream::blocking_read(&mut ctx, Resource::new_borrow(input), 1000)
+            .await?
+            .len();
+    }
+    assert_limited(read, start);
+    Ok(())
+}
+
+struct FixedMonotonicClock(u64);
+
+impl HostMonotonicClock for FixedMonotonicClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+#[test]
+fn api_monotonic_clock_offset() -> Result<()> {
+    use preview2::bindings::clocks::monotonic_clock::Host as _;
+    use wasmtime::{Instance, Module};
+
+    let ctx = |now| CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .monotonic_clock(FixedMonotonicClock(now))
+            .build(),
+    };
+
+    let mut store = Store::new(&Engine::default(), ());
+    let module = Module::new(store.engine(), "(module (memory 1))")?;
+    let instance = Instance::new(&mut store, &module, &[])?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let mut before = ctx(1_000);
+    before
+        .wasi
+        .set_monotonic_clock_offset(Duration::from_nanos(500));
+    assert_eq!(before.now()?, 1_500);
+    let snapshot = before.wasi.attach_monotonic_clock(snapshot);
+
+    // A context whose clock starts over continues from the recorded reading.
+    let mut after = ctx(10);
+    assert!(after.wasi.restore_monotonic_clock(&snapshot)?);
+    assert_eq!(after.now()?, 1_500);
+
+    // A context whose clock is already ahead is left alone.
+    let mut ahead = ctx(2_000);
+    assert!(ahead.wasi.restore_monotonic_clock(&snapshot)?);
+    assert_eq!(ahead.now()?, 2_000);
+
+    let snapshot = instance.snapshot(&mut store)?;
+    assert!(!ahead.wasi.restore_monotonic_clock(&snapshot)?);
+    Ok(())
+}
+
+#[test]
+fn api_monotonic_clock_restored_with_instance() -> Result<()> {
+    use preview2::bindings::clocks::monotonic_clock::Host as _;
+    use wasmtime::{Instance, Module};
+
+    let engine = Engine::default();
+    let store = |now| {
+        let mut store = Store::new(
+            &engine,
+            CommandCtx {
+                table: Table::new(),
+                wasi: WasiCtxBuilder::new()
+                    .monotonic_clock(FixedMonotonicClock(now))
+                    .build(),
+            },
+        );
+        preview2::add_to_snapshots(&mut store);
+        store
+    };
+    let module = Module::new(&engine, "(module (memory 1))")?;
+
+    let mut before = store(1_000);
+    let instance = Instance::new(&mut before, &module, &[])?;
+    let snapshot = instance.snapshot(&mut before)?;
+    assert_eq!(snapshot.host_states().len(), 2);
+
+    let mut after = store(10);
+    let instance = Instance::new(&mut after, &module, &[])?;
+    instance.restore(&mut after, &snapshot)?;
+    assert_eq!(after.data_mut().now()?, 1_000);
+    Ok(())
+}
+
+#[test]
+fn api_network_pool_restored_with_instance() -> Result<()> {
+    use preview2::bindings::sockets::instance_network::Host as _;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use wasmtime::{Instance, Module};
+
+    let engine = Engine::default();
+    let store = |builder: &mut WasiCtxBuilder| {
+        let mut store = Store::new(
+            &engine,
+            CommandCtx {
+                table: Table::new(),
+                wasi: builder.build(),
+            },
+        );
+        preview2::add_to_snapshots(&mut store);
+        store
+    };
+    let module = Module::new(&engine, "(module (memory 1))")?;
+    let loopback = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+    let mut before = store(WasiCtxBuilder::new().inherit_network(ambient_authority()));
+    let instance = Instance::new(&mut before, &module, &[])?;
+    let snapshot = instance.snapshot(&mut before)?;
+
+    let mut after = store(&mut WasiCtxBuilder::new());
+    let bind = |store: &mut Store<CommandCtx>| -> Result<std::io::Result<()>> {
+        let ctx = store.data_mut();
+        let network = ctx.instance_network()?;
+        let pool = &ctx.table.get(&network)?.pool;
+        Ok(pool.bind_udp_socket(loopback).map(drop))
+    };
+    assert_eq!(
+        bind(&mut after)?.unwrap_err().kind(),
+        std::io::ErrorKind::PermissionDenied
+    );
+    let instance = Instance::new(&mut after, &module, &[])?;
+    instance.restore(&mut after, &snapshot)?;
+    assert_eq!(
+        after.data().wasi.capture_pool(),
+        before.data().wasi.capture_pool()
+    );
+    bind(&mut after)??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_freeze_monotonic_clock() -> Result<()> {
+    use preview2::bindings::clocks::monotonic_clock::Host as _;
+    use preview2::bindings::io::poll::Host as _;
+    use preview2::FrozenClock;
+
+    struct FixedMonotonicClock(u64);
+
+    impl HostMonotonicClock for FixedMonotonicClock {
+        fn resolution(&self) -> u64 {
+            1
+        }
+
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+
+    let clock = FrozenClock::at_ns(5);
+    clock.advance(10);
+    assert_eq!(HostMonotonicClock::now(&clock), 15);
+    assert_eq!(HostWallClock::now(&clock), Duration::from_nanos(15));
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .monotonic_clock(FixedMonotonicClock(1_000))
+            .build(),
+    };
+    assert!(ctx.wasi.advance_frozen_clock(1).is_err());
+
+    ctx.wasi.freeze_monotonic_clock(100);
+    assert_eq!(ctx.now()?, 100);
+    assert_eq!(ctx.now()?, 100);
+
+    // Advancing the clock also fires pending subscriptions.
+    let hour = Duration::from_secs(3600).as_nanos() as u64;
+    let pollable =
+        preview2::bindings::clocks::monotonic_clock::Host::subscribe(&mut ctx, hour, false)?;
+    ctx.wasi.advance_frozen_clock(hour)?;
+    tokio::time::timeout(Duration::from_secs(5), ctx.poll_one(pollable)).await??;
+    assert_eq!(ctx.now()?, 100 + hour);
+    assert!(ctx.wasi.advance_frozen_clock(u64::MAX).is_err());
+
+    // The real clock continues from the frozen reading, since it is behind.
+    ctx.wasi.unfreeze_clock();
+    assert_eq!(ctx.now()?, 100 + hour);
+    assert!(ctx.wasi.advance_frozen_clock(1).is_err());
+
+    // A clock frozen behind the real reading continues from the real reading.
+    ctx.wasi.freeze_monotonic_clock(10);
+    assert_eq!(ctx.now()?, 10);
+    ctx.wasi.freeze_monotonic_clock(20);
+    assert_eq!(ctx.now()?, 20);
+    ctx.wasi.unfreeze_clock();
+    assert_eq!(ctx.now()?, 100 + hour);
+    Ok(())
+}
+
+#[test]
+fn api_memory_limit_bytes() -> Result<()> {
+    use wasmtime::{Instance, Module};
+
+    const PAGE: usize = 65536;
+
+    let wasi = WasiCtxBuilder::new().memory_limit_bytes(3 * PAGE).build();
+    let creator = wasi.memory_creator().unwrap();
+    let mut config = Config::new();
+    config
+        .with_host_memory(creator.clone())
+        .static_memory_maximum_size(0)
+        .dynamic_memory_guard_size(0);
+    let engine = Engine::new(&config)?;
+    let mut store = Store::new(&engine, ());
+
+    let module = Module::new(
+        &engine,
+        r#"(module (memory (export "mem") 1) (func (export "grow") (param i32) (result i32)
+            local.get 0
+            memory.grow))"#,
+    )?;
+    let instance = Instance::new(&mut store, &module, &[])?;
+    assert_eq!(creator.current_usage(), PAGE);
+
+    let grow = instance.get_typed_func::<i32, i32>(&mut store, "grow")?;
+    assert_eq!(grow.call(&mut store, 1)?, 1);
+    assert_eq!(creator.current_usage(), 2 * PAGE);
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    mem.data_mut(&mut store)[2 * PAGE - 1] = 42;
+
+    // The limit applies across all memories.
+    assert_eq!(grow.call(&mut store, 2)?, -1);
+    let big = Module::new(&engine, "(module (memory 2))")?;
+    assert!(Instance::new(&mut store, &big, &[]).is_err());
+    assert!(Instance::new(&mut store, &module, &[]).is_ok());
+    assert_eq!(creator.current_usage(), 3 * PAGE);
+
+    drop(store);
+    assert_eq!(creator.current_usage(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_memory_limit_bytes_resource_limiter() -> Result<()> {
+    use wasmtime::{Instance, Module};
+
+    const PAGE: usize = 65536;
+
+    // Without a memory creator in the engine, the limit is enforced per store
+    // by the context's resource limiter.
+    let mut config = Config::new();
+    config.async_support(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(
+        &engine,
+        r#"(module (memory 1) (func (export "grow") (param i32) (result i32)
+            local.get 0
+            memory.grow))"#,
+    )?;
+    let big = Module::new(&engine, "(module (memory 2))")?;
+
+    let mut wasi = WasiCtxBuilder::new().memory_limit_bytes(3 * PAGE).build();
+    let fork = wasi.fork(&mut Table::new())?;
+    for wasi in [wasi, fork] {
+        let mut store = Store::new(
+            &engine,
+            CommandCtx {
+                table: Table::new(),
+                wasi,
+            },
+        );
+        store.limiter_async(|data| data.ctx_mut().resource_limiter());
+        let instance = Instance::new_async(&mut store, &module, &[]).await?;
+        let grow = instance.get_typed_func::<i32, i32>(&mut store, "grow")?;
+        assert_eq!(grow.call_async(&mut store, 1).await?, 1);
+
+        // The limit applies across all memories of the store.
+        assert_eq!(grow.call_async(&mut store, 2).await?, -1);
+        assert!(Instance::new_async(&mut store, &big, &[]).await.is_err());
+        Instance::new_async(&mut store, &module, &[]).await?;
+        assert_eq!(grow.call_async(&mut store, 1).await?, -1);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_emit_event() -> Result<()> {
+    use preview2::bindings::cli::stdin::Host as _;
+    use preview2::bindings::clocks::monotonic_clock::Host as _;
+    use preview2::bindings::io::poll::Host as _;
+    use preview2::bindings::io::streams::HostInputStream;
+    use preview2::pipe::MemoryInputPipe;
+    use preview2::WasiEvent;
+    use wasmtime::component::Resource;
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .stdin(MemoryInputPipe::new("tail".into()))
+            .build(),
+    };
+
+    // Injected bytes are read before the configured stdin.
+    ctx.emit_event(WasiEvent::Stdin(b"head".to_vec()))?;
+    let stdin = ctx.get_stdin()?.rep();
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 10).await;
+    assert_eq!(read.ok(), Some(b"head".to_vec()));
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 10).await;
+    assert_eq!(read.ok(), Some(b"tail".to_vec()));
+
+    // An alarm fires a pending subscription long before its real deadline.
+    let before = ctx.now()?;
+    let hour = Duration::from_secs(3600);
+    let pollable = preview2::bindings::clocks::monotonic_clock::Host::subscribe(
+        &mut ctx,
+        hour.as_nanos() as u64,
+        false,
+    )?;
+    ctx.emit_event(WasiEvent::MonotonicAlarm(2 * hour))?;
+    tokio::time::timeout(Duration::from_secs(5), ctx.poll_one(pollable)).await??;
+    assert!(ctx.now()? >= before + (2 * hour).as_nanos() as u64);
+
+    ctx.emit_event(WasiEvent::Custom {
+        fd: 3,
+        payload: b"one".to_vec(),
+    })?;
+    ctx.emit_event(WasiEvent::Custom {
+        fd: 3,
+        payload: b"two".to_vec(),
+    })?;
+    assert_eq!(ctx.wasi.take_custom_events(3), [b"one", b"two"]);
+    assert!(ctx.wasi.take_custom_events(3).is_empty());
+    assert!(ctx.wasi.take_custom_events(4).is_empty());
+    Ok(())
+}
+
+#[test]
+fn api_prebound_resources() -> Result<()> {
+    use preview2::TableError;
+    use wasmtime::component::Resource;
+
+    let mut builder = WasiCtxBuilder::new();
+    let (first, _) = builder.push_resource("first".to_string());
+    builder.with_prebound_resource(10, 42u32);
+    let (second, _) = builder.push_resource("second".to_string());
+    assert_eq!((first, second), (3, 11));
+    let mut wasi = builder.build();
+
+    let mut table = wasi.new_table();
+    wasi.bind_resources(&mut table)?;
+    assert_eq!(table.get(&Resource::<String>::new_borrow(3))?, "first");
+    assert_eq!(*table.get(&Resource::<u32>::new_borrow(10))?, 42);
+    assert_eq!(table.get(&Resource::<String>::new_borrow(11))?, "second");
+    assert_eq!(table.push(())?.rep(), 4);
+
+    let mut wasi = WasiCtxBuilder::new()
+        .with_prebound_resource(5, ())
+        .with_prebound_resource(6, ())
+        .build();
+    let mut table = Table::new();
+    table.push_at(6, ())?;
+    assert!(matches!(
+        wasi.bind_resources(&mut table),
+        Err(TableError::Occupied)
+    ));
+    assert!(table.get(&Resource::<()>::new_borrow(5)).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_fork() -> Result<()> {
+    use preview2::bindings::cli::environment::Host as _;
+    use preview2::bindings::cli::stdin::Host as _;
+    use preview2::bindings::io::streams::HostInputStream;
+
+    async fn read(ctx: &mut CommandCtx, len: u64) -> Vec<u8> {
+        let stdin = ctx.get_stdin().unwrap();
+        HostInputStream::read(ctx, stdin, len).await.ok().unwrap()
+    }
+
+    let mut parent = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .env("NAME", "parent")
+            .arg("worker")
+            .stdin_from_str("hello")
+            .build(),
+    };
+    assert_eq!(read(&mut parent, 2).await, b"he");
+
+    let mut fork = CommandCtx {
+        wasi: parent.wasi.fork(&mut parent.table)?,
+        table: Table::new(),
+    };
+    fork.wasi.bind_resources(&mut fork.table)?;
+    assert_eq!(
+        fork.get_environment()?,
+        vec![("NAME".to_string(), "parent".to_string())]
+    );
+    assert_eq!(fork.get_arguments()?, vec!["worker".to_string()]);
+
+    // Both contexts continue reading stdin from where the parent was.
+    assert_eq!(read(&mut fork, 10).await, b"llo");
+    assert_eq!(read(&mut parent, 1).await, b"l");
+    assert_eq!(read(&mut parent, 10).await, b"lo");
+    let stdin = fork.get_stdin()?;
+    assert!(HostInputStream::read(&mut fork, stdin, 1).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_fork_isolates_filesystem_and_stdio() -> Result<()> {
+    use filesystem::{DescriptorFlags, HostDescriptor, Modes, OpenFlags, PathFlags};
+    use preview2::bindings::cli::stdout::Host as _;
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::bindings::io::streams::HostOutputStream;
+    use preview2::pipe::MemoryOutputPipe;
+    use preview2::{FakeEntry, FakeFilesystem, WasiEvent};
+    use wasmtime::component::Resource;
+
+    async fn write_stdout(ctx: &mut CommandCtx, bytes: &[u8]) -> Result<()> {
+        let stdout = ctx.get_stdout()?;
+        HostOutputStream::blocking_write_and_flush(ctx, stdout, bytes.to_vec()).await?;
+        Ok(())
+    }
+
+    fn file(path: &str, content: &str) -> FakeEntry {
+        FakeEntry::File {
+            path: path.to_string(),
+            content: content.into(),
+            mode: 0o644,
+        }
+    }
+
+    let mut fs = FakeFilesystem::new();
+    fs.file("data.txt", "parent");
+    let parent_stdout = MemoryOutputPipe::new(1024);
+    let mut parent = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .fake_filesystem(fs)?
+            .stdout(parent_stdout.clone())
+            .build(),
+    };
+    let (root, _) = parent.get_directories()?.remove(0);
+    let data = parent
+        .open_at(
+            Resource::new_borrow(root.rep()),
+            PathFlags::empty(),
+            "data.txt".to_string(),
+            OpenFlags::empty(),
+            DescriptorFlags::READ | DescriptorFlags::WRITE,
+            Modes::empty(),
+        )
+        .await?;
+
+    let mut fork = CommandCtx {
+        wasi: parent.wasi.fork(&mut parent.table)?,
+        table: Table::new(),
+    };
+    fork.wasi.bind_resources(&mut fork.table)?;
+
+    // Writes through the inherited handle and the preopen only reach the
+    // fork's copy of the filesystem.
+    HostDescriptor::write(
+        &mut fork,
+        Resource::new_borrow(data.rep()),
+        b"forked".to_vec(),
+        0,
+    )
+    .await?;
+    let (root, _) = fork.get_directories()?.remove(0);
+    let created = fork
+        .open_at(
+            root,
+            PathFlags::empty(),
+            "created.txt".to_string(),
+            OpenFlags::CREATE,
+            DescriptorFlags::WRITE,
+            Modes::empty(),
+        )
+        .await?;
+    HostDescriptor::write(&mut fork, created, b"new".to_vec(), 0).await?;
+    assert_eq!(
+        fork.wasi.fake_filesystem()?.unwrap().entries(),
+        [file("created.txt", "new"), file("data.txt", "forked")]
+    );
+    assert_eq!(
+        parent.wasi.fake_filesystem()?.unwrap().entries(),
+        [file("data.txt", "parent")]
+    );
+    let (contents, _) = parent.read(data, 100, 0).await?;
+    assert_eq!(contents, b"parent");
+
+    // The fork shares the parent's stdout until it is given one of its own,
+    // but counts its own bytes.
+    write_stdout(&mut fork, b"shared ").await?;
+    let fork_stdout = MemoryOutputPipe::new(1024);
+    fork.wasi.set_stdout(fork_stdout.clone());
+    write_stdout(&mut fork, b"fork").await?;
+    write_stdout(&mut parent, b"parent").await?;
+    assert_eq!(&fork_stdout.contents()[..], b"fork");
+    assert_eq!(&parent_stdout.contents()[..], b"shared parent");
+    assert_eq!(fork.wasi.stdout_bytes_written(), 11);
+    assert_eq!(parent.wasi.stdout_bytes_written(), 6);
+
+    // Events are only delivered to the context they were emitted into.
+    parent.emit_event(WasiEvent::Custom {
+        fd: 3,
+        payload: b"event".to_vec(),
+    })?;
+    assert!(fork.wasi.take_custom_events(3).is_empty());
+    assert_eq!(parent.wasi.take_custom_events(3), [b"event".to_vec()]);
+    Ok(())
+}
+
+#[test]
+fn api_inherit_network_filtered() -> Result<()> {
+    use preview2::bindings::sockets::instance_network::Host as _;
+    use std::net::SocketAddr;
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .inherit_network_filtered(ambient_authority(), |addr| addr.ip().is_loopback())?
+            .build(),
+    };
+    let network = ctx.instance_network()?;
+    let pool = &ctx.table.get(&network)?.pool;
+    pool.bind_udp_socket("127.0.0.1:0")?;
+    for denied in ["0.0.0.0:0", "[::]:0", "192.0.2.1:0", "127.0.0.2:0"] {
+        let denied: SocketAddr = denied.parse()?;
+        let err = pool.bind_udp_socket(denied).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied, "{denied}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_socket_factory() -> Result<()> {
+    use preview2::bindings::sockets::instance_network::Host as _;
+    use preview2::bindings::sockets::network::IpAddressFamily;
+    use preview2::bindings::sockets::tcp::HostTcpSocket;
+    use preview2::bindings::sockets::tcp_create_socket::Host as _;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::Arc;
+    use wasmtime::component::Resource;
+
+    // Redirect all connections to a local listener.
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let local = listener.local_addr()?;
+    let requested = Arc::new(Mutex::new(Vec::new()));
+    let factory = {
+        let requested = requested.clone();
+        move |addr: SocketAddr| {
+            requested.lock().unwrap().push(addr);
+            TcpStream::connect(local)
+        }
+    };
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .inherit_network(cap_std::ambient_authority())
+            .with_socket_factory(factory)
+            .build(),
+    };
+    let network = ctx.instance_network()?;
+    let socket = ctx.create_tcp_socket(IpAddressFamily::Ipv4).ok().unwrap();
+    let remote: SocketAddr = "192.0.2.1:80".parse()?;
+    HostTcpSocket::start_connect(
+        &mut ctx,
+        Resource::new_borrow(socket.rep()),
+        network,
+        remote.into(),
+    )
+    .ok()
+    .unwrap();
+    let (_input, _output) =
+        HostTcpSocket::finish_connect(&mut ctx, Resource::new_borrow(socket.rep()))
+            .ok()
+            .unwrap();
+
+    let (_server, peer) = listener.accept()?;
+    assert_eq!(*requested.lock().unwrap(), vec![remote]);
+    let remote_address =
+        HostTcpSocket::remote_address(&mut ctx, Resource::new_borrow(socket.rep()))
+            .ok()
+            .unwrap();
+    assert_eq!(SocketAddr::from(remote_address), local);
+    let local_address = HostTcpSocket::local_address(&mut ctx, socket).ok().unwrap();
+    assert_eq!(SocketAddr::from(local_address), peer);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn api_unix_sockets() -> Result<()> {
+    use preview2::bindings::io::streams::{HostInputStream, HostOutputStream};
+    use preview2::bindings::sockets::instance_network::Host as _;
+    use preview2::bindings::sockets::network::{ErrorCode, IpAddressFamily};
+    use preview2::bindings::sockets::tcp::HostTcpSocket;
+    use preview2::bindings::sockets::tcp_create_socket::Host as _;
+    use std::net::SocketAddr;
+    use wasmtime::component::Resource;
+
+    let dir = tempfile::tempdir()?;
+    let new_ctx = || CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .inherit_network(ambient_authority())
+            .allow_unix_sockets(dir.path())
+            .build(),
+    };
+    let addr: SocketAddr = "127.0.0.1:4000".parse()?;
+    let path = dir.path().join("127.0.0.1-4000.sock");
+
+    // One component listens on a loopback address...
+    let mut server = new_ctx();
+    let network = server.instance_network()?;
+    let listener = server
+        .create_tcp_socket(IpAddressFamily::Ipv4)
+        .ok()
+        .unwrap();
+    let rep = listener.rep();
+    HostTcpSocket::start_bind(&mut server, Resource::new_borrow(rep), network, addr.into())
+        .ok()
+        .unwrap();
+    HostTcpSocket::finish_bind(&mut server, Resource::new_borrow(rep))
+        .ok()
+        .unwrap();
+    HostTcpSocket::start_listen(&mut server, Resource::new_borrow(rep))
+        .ok()
+        .unwrap();
+    HostTcpSocket::finish_listen(&mut server, Resource::new_borrow(rep))
+        .ok()
+        .unwrap();
+    assert!(path.exists());
+
+    // ... and another one connects to it through the Unix domain socket.
+    let mut client = new_ctx();
+    let network = client.instance_network()?;
+    let socket = client
+        .create_tcp_socket(IpAddressFamily::Ipv4)
+        .ok()
+        .unwrap();
+    HostTcpSocket::start_connect(
+        &mut client,
+        Resource::new_borrow(socket.rep()),
+        network,
+        addr.into(),
+    )
+    .ok()
+    .unwrap();
+    let (_input, output) =
+        HostTcpSocket::finish_connect(&mut client, Resource::new_borrow(socket.rep()))
+            .ok()
+            .unwrap();
+    let remote = HostTcpSocket::remote_address(&mut client, socket)
+        .ok()
+        .unwrap();
+    assert_eq!(SocketAddr::from(remote), addr);
+
+    let (accepted, input, _output) = loop {
+        match HostTcpSocket::accept(&mut server, Resource::new_borrow(rep)) {
+            Ok(accepted) => break accepted,
+            Err(e) => {
+                assert!(matches!(e.downcast()?, ErrorCode::WouldBlock));
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    };
+    let local = HostTcpSocket::local_address(&mut server, accepted)
+        .ok()
+        .unwrap();
+    assert_eq!(SocketAddr::from(local), addr);
+
+    HostOutputStream::blocking_write_and_flush(&mut client, output, b"hello".to_vec())
+        .await
+        .ok()
+        .unwrap();
+    let mut received = Vec::new();
+    while received.len() < 5 {
+        let read =
+            HostInputStream::blocking_read(&mut server, Resource::new_borrow(input.rep()), 5)
+                .await
+                .ok()
+                .unwrap();
+        received.extend(read);
+    }
+    assert_eq!(received, b"hello");
+
+    // Closing the listener removes its socket file.
+    HostTcpSocket::drop(&mut server, listener)?;
+    assert!(!path.exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_network_audit_log() -> Result<()> {
+    use preview2::bindings::sockets::instance_network::Host as _;
+    use preview2::bindings::sockets::network::{ErrorCode, IpAddressFamily};
+    use preview2::bindings::sockets::tcp::HostTcpSocket;
+    use preview2::bindings::sockets::tcp_create_socket::Host as _;
+    use std::net::{SocketAddr, TcpListener};
+    use wasmtime::component::Resource;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let local = listener.local_addr()?;
+    let log = Buffer::default();
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .insert_socket_addr(local)
+            .network_audit_log(log.clone())
+            .build(),
+    };
+    let network = ctx.instance_network()?;
+    let connect = |ctx: &mut CommandCtx, remote: SocketAddr| {
+        let socket = ctx.create_tcp_socket(IpAddressFamily::Ipv4).ok().unwrap();
+        HostTcpSocket::start_connect(
+            ctx,
+            socket,
+            Resource::new_borrow(network.rep()),
+            remote.into(),
+        )
+    };
+
+    // The denied attempt is logged before the error reaches the guest.
+    let blocked: SocketAddr = "10.0.0.1:5432".parse()?;
+    let err = connect(&mut ctx, blocked).unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::AccessDenied));
+    let output = String::from_utf8(log.0.lock().unwrap().clone())?;
+    assert_eq!(output.lines().count(), 1, "{output}");
+    assert!(output.starts_with(r#"{"ts":""#), "{output}");
+    assert!(
+        output.ends_with(
+            "Z\",\"event\":\"tcp_connect\",\"addr\":\"10.0.0.1:5432\",\"allowed\":false}\n"
+        ),
+        "{output}"
+    );
+
+    connect(&mut ctx, local).ok().unwrap();
+    let output = String::from_utf8(log.0.lock().unwrap().clone())?;
+    let allowed = format!(r#","event":"tcp_connect","addr":"{local}","allowed":true}}"#);
+    assert!(
+        output.lines().nth(1).unwrap().ends_with(&allowed),
+        "{output}"
+    );
+    Ok(())
+}
+
+#[test]
+fn api_metrics() -> Result<()> {
+    use preview2::bindings::random::random::Host as _;
+    use wall_clock::Host as _;
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().enable_metrics().build(),
+    };
+    ctx.now()?;
+    ctx.get_random_u64()?;
+    ctx.get_random_bytes(16)?;
+    let metrics = ctx.wasi.metrics().snapshot();
+    assert_eq!(metrics.clock_calls, 1);
+    assert_eq!(metrics.random_calls, 2);
+    assert_eq!(metrics.stream_writes, 0);
+
+    // Metrics are disabled by default.
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().build(),
+    };
+    ctx.now()?;
+    assert!(!ctx.wasi.metrics().is_enabled());
+    assert_eq!(
+        ctx.wasi.metrics().snapshot(),
+        preview2::WasiMetricsSnapshot::default()
+    );
+    Ok(())
+}
+
+#[test]
+fn api_trace_syscalls() -> Result<()> {
+    use preview2::bindings::cli::exit::Host as _;
+    use preview2::bindings::random::random::Host as _;
+
+    struct FixedMonotonicClock;
+
+    impl HostMonotonicClock for FixedMonotonicClock {
+        fn resolution(&self) -> u64 {
+            1
+        }
+
+        fn now(&self) -> u64 {
+            42
+        }
+    }
+
+    let trace = Buffer::default();
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .secure_random(preview2::Deterministic::new(vec![1, 2, 3]))
+            .monotonic_clock(FixedMonotonicClock)
+            .trace_syscalls(trace.clone())
+            .with_exit_handler(|_| preview2::ExitBehavior::Ignore)
+            .build(),
+    };
+    ctx.get_random_bytes(4)?;
+    preview2::bindings::clocks::monotonic_clock::Host::now(&mut ctx)?;
+    ctx.exit(Err(()))?;
+
+    let output = String::from_utf8(trace.0.lock().unwrap().clone())?;
+    let lines = output.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{output}");
+    assert!(lines[0].starts_with(r#"{"ts":"#));
+    assert!(lines[0].ends_with(r#","call":"random.get-random-bytes","args":[4],"ret":"01020301"}"#));
+    assert!(lines[1].ends_with(r#","call":"monotonic-clock.now","args":[],"ret":42}"#));
+    assert!(lines[2].ends_with(r#","call":"exit.exit","args":[1],"ret":null}"#));
+    Ok(())
+}
+
+#[test]
+fn api_debug_label() -> Result<()> {
+    assert_eq!(WasiCtxBuilder::new().build().debug_label(), None);
+
+    let trace = Buffer::default();
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .trace_syscalls(trace.clone())
+            .debug_label("worker-7")
+            .build(),
+    };
+    assert_eq!(ctx.wasi.debug_label(), Some("worker-7"));
+    assert_eq!(
+        ctx.wasi.fork(&mut ctx.table)?.debug_label(),
+        Some("worker-7")
+    );
+
+    preview2::bindings::clocks::monotonic_clock::Host::now(&mut ctx)?;
+    let output = String::from_utf8(trace.0.lock().unwrap().clone())?;
+    assert!(output.contains(r#","label":"worker-7","call":"monotonic-clock.now","#));
+    Ok(())
+}
+
+#[test]
+fn api_env_and_args_accessors() -> Result<()> {
+    use preview2::EnvSecretProvider;
+
+    let wasi = WasiCtxBuilder::new()
+        .env("FOO", "bar")
+        .envs(&[("BAZ", "1"), ("FOO", "shadowed")])
+        .env_secret("TOKEN", EnvSecretProvider::new("hunter2"))
+        .args(&["prog", "--verbose"])
+        .arg("input.txt")
+        .build();
+    assert_eq!(wasi.get_env("FOO"), Some("bar"));
+    assert_eq!(wasi.get_env("BAZ"), Some("1"));
+    assert_eq!(wasi.get_env("TOKEN"), None);
+    assert_eq!(wasi.get_env("MISSING"), None);
+    assert_eq!(
+        wasi.get_all_envs(),
+        [
+            ("FOO".to_string(), "bar".to_string()),
+            ("BAZ".to_string(), "1".to_string()),
+            ("FOO".to_string(), "shadowed".to_string()),
+        ]
+    );
+    assert_eq!(wasi.get_args(), ["prog", "--verbose", "input.txt"]);
+    Ok(())
+}
+
+#[test]
+fn api_exit_handler() -> Result<()> {
+    use preview2::bindings::cli::exit::Host as _;
+    use preview2::{ExitBehavior, I32Exit};
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .with_exit_handler(|code| match code {
+                0 => ExitBehavior::Ignore,
+                _ => ExitBehavior::SnapshotAndPropagate(Box::new(|_snapshot| {})),
+            })
+            .build(),
+    };
+    ctx.exit(Ok(()))?;
+    // The context is still usable after an ignored exit.
+    ctx.exit(Ok(()))?;
+    assert!(ctx.wasi.take_exit_snapshot_handler().is_none());
+
+    let err = ctx.exit(Err(())).unwrap_err();
+    assert_eq!(err.downcast_ref::<I32Exit>().unwrap().0, 1);
+    assert!(ctx.wasi.take_exit_snapshot_handler().is_some());
+    assert!(ctx.wasi.take_exit_snapshot_handler().is_none());
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().build(),
+    };
+    let err = ctx.exit(Ok(())).unwrap_err();
+    assert_eq!(err.downcast_ref::<I32Exit>().unwrap().0, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_exit_handler_ignore_keeps_running() -> Result<()> {
+    use preview2::ExitBehavior;
+
+    let mut config = Config::new();
+    config.async_support(true).wasm_component_model(true);
+    let engine = Engine::new(&config)?;
+    let mut linker = Linker::new(&engine);
+    add_to_linker(&mut linker)?;
+    let mut store = Store::new(
+        &engine,
+        CommandCtx {
+            table: Table::new(),
+            wasi: WasiCtxBuilder::new()
+                .with_exit_handler(|_| ExitBehavior::Ignore)
+                .build(),
+        },
+    );
+    // Exits, then carries on to return 42.
+    let component = Component::new(
+        &engine,
+        r#"
+        (component
+            (import "wasi:cli/exit" (instance $exit
+                (export "exit" (func (param "status" (result))))
+            ))
+            (core func $exit (canon lower (func $exit "exit")))
+            (core module $m
+                (import "wasi" "exit" (func $exit (param i32)))
+                (func (export "run") (result i32)
+                    (call $exit (i32.const 0))
+                    (i32.const 42))
+            )
+            (core instance $i (instantiate $m
+                (with "wasi" (instance (export "exit" (func $exit))))
+            ))
+            (func (export "run") (result u32) (canon lift (core func $i "run")))
+        )
+        "#,
+    )?;
+    let instance = linker.instantiate_async(&mut store, &component).await?;
+    let run = instance.get_typed_func::<(), (u32,)>(&mut store, "run")?;
+    for _ in 0..2 {
+        let (result,) = run.call_async(&mut store, ()).await?;
+        run.post_return_async(&mut store).await?;
+        assert_eq!(result, 42);
+    }
+    Ok(())
+}
+
+#[test]
+fn api_table_reserve() -> Result<()> {
+    use preview2::{pipe::MemoryInputPipe, InputStream, TableError};
+
+    let mut table = Table::with_max_entries(3);
+    table.push(0u32)?;
+    assert!(matches!(table.reserve(3), Err(TableError::Full)));
+
+    let mut reservation = table.reserve(2)?;
+    // The reserved slots can't be taken by other pushes while the
+    // reservation is held.
+    assert!(matches!(table.push(1u32), Err(TableError::Full)));
+    let stream = InputStream::Host(Box::new(MemoryInputPipe::new("hello".into())));
+    reservation.push_input_stream(&mut table, stream)?;
+    assert_eq!(reservation.remaining(), 1);
+    drop(reservation);
+
+    // The unused slot was released, so the table can be filled up again.
+    table.push(1u32)?;
+    assert!(matches!(table.push(2u32), Err(TableError::Full)));
+    assert!(matches!(table.reserve(1), Err(TableError::Full)));
+    Ok(())
+}
+
+#[test]
+fn api_table_contains() -> Result<()> {
+    let mut table = Table::new();
+    let resource = table.push(7u32)?;
+    let index = resource.rep();
+    assert!(table.contains(index));
+    assert!(table.contains_typed::<u32>(index));
+    assert!(!table.contains_typed::<String>(index));
+    assert!(!table.contains(index + 1));
+
+    table.delete(resource)?;
+    assert!(!table.contains(index));
+    assert!(!table.contains_typed::<u32>(index));
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_table_compact() -> Result<()> {
+    use preview2::bindings::io::streams::HostInputStream;
+    use preview2::{pipe::MemoryInputPipe, InputStream, TableError};
+    use wasmtime::component::Resource;
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().build(),
+    };
+    let garbage = (0..100)
+        .map(|i| ctx.table.push(i))
+        .collect::<Result<Vec<_>, _>>()?;
+    let stream = InputStream::Host(Box::new(MemoryInputPipe::new("hello".into())));
+    let stream = ctx.table.push(stream)?.rep();
+    let pollable = HostInputStream::subscribe(&mut ctx, Resource::new_borrow(stream))?.rep();
+    for resource in garbage {
+        ctx.table.delete(resource)?;
+    }
+
+    let result = ctx.table.compact();
+    assert_eq!(result.remapped.len(), 2);
+    let stream = result.remapped[&stream];
+    let pollable = result.remapped[&pollable];
+    assert_eq!((stream, pollable), (3, 4));
+
+    // The stream still works at its new index, and is still the parent of
+    // its pollable.
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stream), 10).await;
+    assert_eq!(read.ok(), Some(b"hello".to_vec()));
+    assert!(matches!(
+        ctx.table.delete(Resource::<InputStream>::new_own(stream)),
+        Err(TableError::HasChildren)
+    ));
+    ctx.table
+        .delete(Resource::<preview2::Pollable>::new_own(pollable))?;
+    ctx.table.delete(Resource::<InputStream>::new_own(stream))?;
+    assert_eq!(ctx.table.push(())?.rep(), 5);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_fake_filesystem() -> Result<()> {
+    use filesystem::{DescriptorFlags, HostDescriptor as _, Modes, OpenFlags, PathFlags};
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::{FakeEntry, FakeFilesystem};
+    use wasmtime::component::Resource;
+
+    let mut fs = FakeFilesystem::new();
+    fs.file("data/hello.txt", "hello").directory("empty");
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().fake_filesystem(fs)?.build(),
+    };
+    let (root, _) = ctx.get_directories()?.remove(0);
+    let file = ctx
+        .open_at(
+            Resource::new_borrow(root.rep()),
+            PathFlags::empty(),
+            "out.txt".to_string(),
+            OpenFlags::CREATE,
+            DescriptorFlags::WRITE,
+            Modes::empty(),
+        )
+        .await?;
+    ctx.write(file, b"saved".to_vec(), 0).await?;
+
+    let saved = ctx.wasi.fake_filesystem()?.unwrap();
+    assert_eq!(saved.entries().len(), 4);
+    assert_eq!(
+        saved.entries()[0],
+        FakeEntry::Directory {
+            path: "data".to_string()
+        }
+    );
+
+    // Replay the saved filesystem into a new context.
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().fake_filesystem(saved)?.build(),
+    };
+    let (root, _) = ctx.get_directories()?.remove(0);
+    let file = ctx
+        .open_at(
+            root,
+            PathFlags::empty(),
+            "out.txt".to_string(),
+            OpenFlags::empty(),
+            DescriptorFlags::READ,
+            Modes::empty(),
+        )
+        .await?;
+    let (contents, _) = ctx.read(file, 100, 0).await?;
+    assert_eq!(contents, b"saved");
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_overlay_dir() -> Result<()> {
+    use filesystem::{DescriptorFlags, HostDescriptor as _, Modes, OpenFlags, PathFlags};
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::{FakeEntry, FakeFilesystem};
+    use wasmtime::component::Resource;
+
+    let base = tempfile::tempdir()?;
+    std::fs::write(base.path().join("asset.txt"), "from base")?;
+    let mut overlay = FakeFilesystem::new();
+    overlay.file("config.txt", "from overlay");
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .preopen_overlay_dir(
+                Dir::open_ambient_dir(base.path(), ambient_authority())?,
+                overlay,
+                "/data",
+            )?
+            .build(),
+    };
+    let (root, path) = ctx.get_directories()?.remove(0);
+    assert_eq!(path, "/data");
+
+    for (name, expected) in [("asset.txt", "from base"), ("config.txt", "from overlay")] {
+        let file = ctx
+            .open_at(
+                Resource::new_borrow(root.rep()),
+                PathFlags::empty(),
+                name.to_string(),
+                OpenFlags::empty(),
+                DescriptorFlags::READ,
+                Modes::empty(),
+            )
+            .await?;
+        let (contents, _) = ctx.read(file, 100, 0).await?;
+        assert_eq!(contents, expected.as_bytes());
+    }
+
+    let file = ctx
+        .open_at(
+            root,
+            PathFlags::empty(),
+            "out.txt".to_string(),
+            OpenFlags::CREATE,
+            DescriptorFlags::WRITE,
+            Modes::empty(),
+        )
+        .await?;
+    ctx.write(file, b"saved".to_vec(), 0).await?;
+
+    assert!(!base.path().join("out.txt").exists());
+    assert!(!base.path().join("config.txt").exists());
+    let changes = ctx.wasi.overlay_dir("/data")?.unwrap();
+    let paths = changes
+        .entries()
+        .iter()
+        .map(|e| match e {
+            FakeEntry::File { path, .. } | FakeEntry::Directory { path } => path.as_str(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["config.txt", "out.txt"]);
+    assert!(ctx.wasi.overlay_dir("/other")?.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_overlay_dir_falls_through_to_base() -> Result<()> {
+    use filesystem::{
+        DescriptorFlags, ErrorCode, HostDescriptor as _, HostDirectoryEntryStream as _, Modes,
+        OpenFlags, PathFlags,
+    };
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::{FakeEntry, FakeFilesystem};
+    use wasmtime::component::Resource;
+
+    async fn open(
+        ctx: &mut CommandCtx,
+        dir: u32,
+        path: &str,
+        flags: DescriptorFlags,
+    ) -> preview2::FsResult<u32> {
+        let fd = ctx
+            .open_at(
+                Resource::new_borrow(dir),
+                PathFlags::empty(),
+                path.to_string(),
+                OpenFlags::empty(),
+                flags,
+                Modes::empty(),
+            )
+            .await?;
+        Ok(fd.rep())
+    }
+
+    async fn read(ctx: &mut CommandCtx, dir: u32, path: &str) -> Result<Vec<u8>> {
+        let file = open(ctx, dir, path, DescriptorFlags::READ).await?;
+        Ok(ctx.read(Resource::new_own(file), 100, 0).await?.0)
+    }
+
+    let base = tempfile::tempdir()?;
+    std::fs::write(base.path().join("asset.txt"), "original")?;
+    std::fs::create_dir(base.path().join("sub"))?;
+    std::fs::write(base.path().join("sub/nested.txt"), "nested")?;
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .preopen_overlay_dir(
+                Dir::open_ambient_dir(base.path(), ambient_authority())?,
+                FakeFilesystem::new(),
+                "/data",
+            )?
+            .build(),
+    };
+    let (root, _) = ctx.get_directories()?.remove(0);
+    let root = root.rep();
+
+    // Reads see the base as it is at the time of the read.
+    std::fs::write(base.path().join("asset.txt"), "updated")?;
+    assert_eq!(read(&mut ctx, root, "asset.txt").await?, b"updated");
+    assert_eq!(read(&mut ctx, root, "sub/nested.txt").await?, b"nested");
+
+    // Files of the base are copied into the overlay when written to.
+    let file = open(&mut ctx, root, "asset.txt", DescriptorFlags::WRITE).await?;
+    ctx.write(Resource::new_own(file), b"U".to_vec(), 0).await?;
+    assert_eq!(read(&mut ctx, root, "asset.txt").await?, b"Updated");
+    assert_eq!(std::fs::read(base.path().join("asset.txt"))?, b"updated");
+
+    // New entries can be created in directories of the base.
+    let sub = open(&mut ctx, root, "sub", DescriptorFlags::READ).await?;
+    let file = ctx
+        .open_at(
+            Resource::new_borrow(sub),
+            PathFlags::empty(),
+            "new.txt".to_string(),
+            OpenFlags::CREATE,
+            DescriptorFlags::WRITE,
+            Modes::empty(),
+        )
+        .await?;
+    ctx.write(file, b"new".to_vec(), 0).await?;
+    assert_eq!(read(&mut ctx, sub, "nested.txt").await?, b"nested");
+    assert!(!base.path().join("sub/new.txt").exists());
+
+    let stream = ctx.read_directory(Resource::new_borrow(sub)).await?;
+    let stream = stream.rep();
+    let mut names = Vec::new();
+    while let Some(entry) = ctx
+        .read_directory_entry(Resource::new_borrow(stream))
+        .await?
+    {
+        names.push(entry.name);
+    }
+    names.sort();
+    assert_eq!(names, ["nested.txt", "new.txt"]);
+
+    // Entries of the base cannot be removed.
+    let err = ctx
+        .unlink_file_at(Resource::new_borrow(root), "asset.txt".to_string())
+        .await
+        .unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::ReadOnly));
+    assert!(ctx
+        .unlink_file_at(Resource::new_borrow(sub), "new.txt".to_string())
+        .await
+        .is_ok());
+
+    let changes = ctx.wasi.overlay_dir("/data")?.unwrap();
+    let paths = changes
+        .entries()
+        .iter()
+        .map(|e| match e {
+            FakeEntry::File { path, .. } | FakeEntry::Directory { path } => path.as_str(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["asset.txt"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_preopen_union() -> Result<()> {
+    use filesystem::{DescriptorFlags, HostDescriptor as _, Modes, OpenFlags, PathFlags};
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use wasmtime::component::Resource;
+
+    let a = tempfile::tempdir()?;
+    std::fs::write(a.path().join("a.txt"), "only in a")?;
+    std::fs::write(a.path().join("shared.txt"), "from a")?;
+    std::fs::create_dir(a.path().join("sub"))?;
+    std::fs::write(a.path().join("sub/x.txt"), "x")?;
+    let b = tempfile::tempdir()?;
+    std::fs::write(b.path().join("b.txt"), "only in b")?;
+    std::fs::write(b.path().join("shared.txt"), "from b")?;
+    std::fs::create_dir(b.path().join("sub"))?;
+    std::fs::write(b.path().join("sub/y.txt"), "y")?;
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .preopen_union(
+                vec![
+                    (
+                        Dir::open_ambient_dir(a.path(), ambient_authority())?,
+                        DirPerms::all(),
+                        FilePerms::all(),
+                    ),
+                    (
+                        Dir::open_ambient_dir(b.path(), ambient_authority())?,
+                        DirPerms::READ,
+                        FilePerms::READ,
+                    ),
+                ],
+                "/plugins",
+            )?
+            .build(),
+    };
+    let (root, path) = ctx.get_directories()?.remove(0);
+    assert_eq!(path, "/plugins");
+
+    for (name, expected) in [
+        ("a.txt", "only in a"),
+        ("b.txt", "only in b"),
+        ("shared.txt", "from a"),
+        ("sub/x.txt", "x"),
+        ("sub/y.txt", "y"),
+    ] {
+        let file = ctx
+            .open_at(
+                Resource::new_borrow(root.rep()),
+                PathFlags::empty(),
+                name.to_string(),
+                OpenFlags::empty(),
+                DescriptorFlags::READ,
+                Modes::empty(),
+            )
+            .await?;
+        let (contents, _) = ctx.read(file, 100, 0).await?;
+        assert_eq!(contents, expected.as_bytes(), "{name}");
+    }
+
+    // Only the permissions common to all directories are granted.
+    let write = ctx
+        .open_at(
+            root,
+            PathFlags::empty(),
+            "a.txt".to_string(),
+            OpenFlags::empty(),
+            DescriptorFlags::WRITE,
+            Modes::empty(),
+        )
+        .await;
+    assert!(write.is_err());
+
+    assert!(WasiCtxBuilder::new()
+        .preopen_union(Vec::new(), "/empty")
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_inherit_preopens_from() -> Result<()> {
+    use filesystem::{DescriptorFlags, HostDescriptor as _, Modes, OpenFlags, PathFlags};
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use wasmtime::component::Resource;
+
+    let tmpdir = tempfile::tempdir()?;
+    let mut builder = WasiCtxBuilder::new();
+    let builder = builder.preopened_dir_at_host_path(
+        tmpdir.path(),
+        DirPerms::all(),
+        FilePerms::all(),
+        "/data",
+    )?;
+    let parent = builder.build();
+    std::fs::write(tmpdir.path().join("hello.txt"), "hello")?;
+
+    let mut child = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .inherit_preopens_from(&parent)?
+            .build(),
+    };
+    drop(parent);
+    let mut dirs = child.get_directories()?;
+    assert_eq!(dirs.len(), 1);
+    let (root, path) = dirs.remove(0);
+    assert_eq!(path, "/data");
+    child
+        .open_at(
+            Resource::new_borrow(root.rep()),
+            PathFlags::empty(),
+            "hello.txt".to_string(),
+            OpenFlags::empty(),
+            DescriptorFlags::READ | DescriptorFlags::WRITE,
+            Modes::empty(),
+        )
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_preopen_dir_quota() -> Result<()> {
+    use filesystem::{
+        DescriptorFlags, ErrorCode, HostDescriptor as _, Modes, OpenFlags, PathFlags,
+    };
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::{DirQuota, DirQuotaUsage};
+    use wasmtime::component::Resource;
+
+    async fn create(
+        ctx: &mut CommandCtx,
+        root: u32,
+        path: &str,
+    ) -> preview2::FsResult<Resource<filesystem::Descriptor>> {
+        ctx.open_at(
+            Resource::new_borrow(root),
+            PathFlags::empty(),
+            path.to_string(),
+            OpenFlags::CREATE,
+            DescriptorFlags::READ | DescriptorFlags::WRITE,
+            Modes::empty(),
+        )
+        .await
+    }
+
+    let tmpdir = tempfile::tempdir()?;
+    let mut builder = WasiCtxBuilder::new();
+    let builder = builder.preopened_dir_at_host_path(
+        tmpdir.path(),
+        DirPerms::all(),
+        FilePerms::all(),
+        "/data",
+    )?;
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: builder
+            .preopen_dir_quota(
+                "/data",
+                DirQuota {
+                    max_total_bytes: Some(8),
+                    max_file_count: Some(1),
+                    max_file_size_bytes: None,
+                },
+            )
+            .build(),
+    };
+    assert_eq!(ctx.wasi.dir_quota_usage("/other"), None);
+    let (root, _) = ctx.get_directories()?.remove(0);
+    let root = root.rep();
+
+    let file = create(&mut ctx, root, "a.txt").await?;
+    let file = file.rep();
+    ctx.write(Resource::new_borrow(file), b"hello".to_vec(), 0)
+        .await?;
+    // Overwriting existing bytes does not count against the quota.
+    ctx.write(Resource::new_borrow(file), b"HELLO".to_vec(), 0)
+        .await?;
+    let err = ctx
+        .write(Resource::new_borrow(file), b" world".to_vec(), 5)
+        .await
+        .unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::Quota));
+    let err = ctx
+        .set_size(Resource::new_borrow(file), 100)
+        .await
+        .unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::Quota));
+    assert_eq!(std::fs::read(tmpdir.path().join("a.txt"))?, b"HELLO");
+
+    // Opening the existing file again is not a new file.
+    create(&mut ctx, root, "a.txt").await?;
+    let err = create(&mut ctx, root, "b.txt").await.unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::Quota));
+    assert!(!tmpdir.path().join("b.txt").exists());
+
+    assert_eq!(
+        ctx.wasi.dir_quota_usage("/data"),
+        Some(DirQuotaUsage {
+            total_bytes: 5,
+            file_count: 1,
+        })
+    );
+
+    // Creating a file exclusively fails as usual if it exists.
+    let err = ctx
+        .open_at(
+            Resource::new_borrow(root),
+            PathFlags::empty(),
+            "a.txt".to_string(),
+            OpenFlags::CREATE | OpenFlags::EXCLUSIVE,
+            DescriptorFlags::READ | DescriptorFlags::WRITE,
+            Modes::empty(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::Exist));
+
+    // Truncating and removing files credits what they used.
+    ctx.set_size(Resource::new_borrow(file), 2).await?;
+    assert_eq!(ctx.wasi.dir_quota_usage("/data").unwrap().total_bytes, 2);
+    ctx.open_at(
+        Resource::new_borrow(root),
+        PathFlags::empty(),
+        "a.txt".to_string(),
+        OpenFlags::TRUNCATE,
+        DescriptorFlags::WRITE,
+        Modes::empty(),
+    )
+    .await?;
+    assert_eq!(ctx.wasi.dir_quota_usage("/data").unwrap().total_bytes, 0);
+    ctx.write(Resource::new_borrow(file), b"hello".to_vec(), 0)
+        .await?;
+    ctx.unlink_file_at(Resource::new_borrow(root), "a.txt".to_string())
+        .await?;
+    assert_eq!(
+        ctx.wasi.dir_quota_usage("/data"),
+        Some(DirQuotaUsage::default())
+    );
+    create(&mut ctx, root, "b.txt").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_preopen_dir_quota_rename() -> Result<()> {
+    use filesystem::{ErrorCode, HostDescriptor as _};
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::{DirQuota, DirQuotaUsage};
+    use wasmtime::component::Resource;
+
+    let data = tempfile::tempdir()?;
+    let inbox = tempfile::tempdir()?;
+    std::fs::write(inbox.path().join("big.txt"), b"0123456789")?;
+    std::fs::write(inbox.path().join("a.txt"), b"abc")?;
+    std::fs::write(inbox.path().join("b.txt"), b"def")?;
+    std::fs::create_dir(inbox.path().join("dir"))?;
+    std::fs::write(inbox.path().join("dir/c.txt"), b"c")?;
+
+    let mut builder = WasiCtxBuilder::new();
+    builder
+        .preopened_dir_at_host_path(data.path(), DirPerms::all(), FilePerms::all(), "/data")?
+        .preopened_dir_at_host_path(inbox.path(), DirPerms::all(), FilePerms::all(), "/inbox")?;
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: builder
+            .preopen_dir_quota(
+                "/data",
+                DirQuota {
+                    max_total_bytes: Some(8),
+                    max_file_count: Some(1),
+                    max_file_size_bytes: None,
+                },
+            )
+            .build(),
+    };
+    let mut dirs = ctx.get_directories()?;
+    let (inbox_fd, _) = dirs.remove(1);
+    let (data_fd, _) = dirs.remove(0);
+    let (data_fd, inbox_fd) = (data_fd.rep(), inbox_fd.rep());
+
+    async fn rename(
+        ctx: &mut CommandCtx,
+        from: u32,
+        path: &str,
+        to: u32,
+    ) -> preview2::FsResult<()> {
+        ctx.rename_at(
+            Resource::new_borrow(from),
+            path.to_string(),
+            Resource::new_borrow(to),
+            path.to_string(),
+        )
+        .await
+    }
+
+    // Moving in more bytes than the quota allows is refused.
+    let err = rename(&mut ctx, inbox_fd, "big.txt", data_fd)
+        .await
+        .unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::Quota));
+    assert!(inbox.path().join("big.txt").exists());
+
+    rename(&mut ctx, inbox_fd, "a.txt", data_fd).await?;
+    assert_eq!(
+        ctx.wasi.dir_quota_usage("/data"),
+        Some(DirQuotaUsage {
+            total_bytes: 3,
+            file_count: 1,
+        })
+    );
+
+    // The quota is full, so neither files nor directories of files fit.
+    for path in ["b.txt", "dir"] {
+        let err = rename(&mut ctx, inbox_fd, path, data_fd).await.unwrap_err();
+        assert!(
+            matches!(err.downcast()?, ErrorCode::Quota),
+            "moving `{path}`"
+        );
+        assert!(inbox.path().join(path).exists());
+    }
+    assert_eq!(ctx.wasi.dir_quota_usage("/data").unwrap().file_count, 1);
+
+    // Moving a file out credits what it used.
+    rename(&mut ctx, data_fd, "a.txt", inbox_fd).await?;
+    assert_eq!(
+        ctx.wasi.dir_quota_usage("/data"),
+        Some(DirQuotaUsage::default())
+    );
+    rename(&mut ctx, inbox_fd, "b.txt", data_fd).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_resource_limiter() -> Result<()> {
+    use wasmtime::{Instance, Module, ResourceLimiterAsync};
+
+    struct MaxPages(usize);
+
+    #[async_trait::async_trait]
+    impl ResourceLimiterAsync for MaxPages {
+        async fn memory_growing(
+            &mut self,
+            _current: usize,
+            desired: usize,
+            _maximum: Option<usize>,
+        ) -> Result<bool> {
+            Ok(desired <= self.0 * 65536)
+        }
+
+        async fn table_growing(
+            &mut self,
+            _current: u32,
+            _desired: u32,
+            _maximum: Option<u32>,
+        ) -> Result<bool> {
+            Ok(true)
+        }
+    }
+
+    let mut config = Config::new();
+    config.async_support(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(
+        &engine,
+        r#"(module
+            (memory 1)
+            (func (export "grow") (param i32) (result i32)
+                local.get 0
+                memory.grow))"#,
+    )?;
+
+    let mut store = Store::new(
+        &engine,
+        CommandCtx {
+            table: Table::new(),
+            wasi: WasiCtxBuilder::new()
+                .with_resource_limiter(MaxPages(2))
+                .build(),
+        },
+    );
+    store.limiter_async(|data| data.ctx_mut().resource_limiter());
+    let instance = Instance::new_async(&mut store, &module, &[]).await?;
+    let grow = instance.get_typed_func::<i32, i32>(&mut store, "grow")?;
+    assert_eq!(grow.call_async(&mut store, 1).await?, 1);
+    assert_eq!(grow.call_async(&mut store, 1).await?, -1);
+
+    // Without a limiter, growth is allowed.
+    let mut store = Store::new(
+        &engine,
+        CommandCtx {
+            table: Table::new(),
+            wasi: WasiCtxBuilder::new().build(),
+        },
+    );
+    store.limiter_async(|data| data.ctx_mut().resource_limiter());
+    let instance = Instance::new_async(&mut store, &module, &[]).await?;
+    let grow = instance.get_typed_func::<i32, i32>(&mut store, "grow")?;
+    assert_eq!(grow.call_async(&mut store, 2).await?, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_stdio_introspection() -> Result<()> {
+    use preview2::bindings::cli::stderr::Host as _;
+    use preview2::bindings::cli::stdin::Host as _;
+    use preview2::bindings::cli::stdout::Host as _;
+    use preview2::bindings::io::streams::{HostInputStream, HostOutputStream};
+    use preview2::pipe::MemoryInputPipe;
+    use preview2::WasiEvent;
+    use wasmtime::component::Resource;
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .stdin(MemoryInputPipe::new("hello".into()))
+            .build(),
+    };
+    assert_eq!(ctx.wasi.stdin_bytes_available(), 5);
+    ctx.emit_event(WasiEvent::Stdin(b"> ".to_vec()))?;
+    assert_eq!(ctx.wasi.stdin_bytes_available(), 7);
+
+    let stdin = ctx.get_stdin()?.rep();
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 4).await;
+    assert_eq!(read.ok(), Some(b"> ".to_vec()));
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 4).await;
+    assert_eq!(read.ok(), Some(b"hell".to_vec()));
+    assert_eq!(ctx.wasi.stdin_bytes_available(), 1);
+
+    assert_eq!(ctx.wasi.stdout_bytes_written(), 0);
+    let stdout = ctx.get_stdout()?.rep();
+    HostOutputStream::blocking_write_and_flush(
+        &mut ctx,
+        Resource::new_borrow(stdout),
+        b"out".to_vec(),
+    )
+    .await?;
+    HostOutputStream::blocking_write_zeroes_and_flush(&mut ctx, Resource::new_borrow(stdout), 2)
+        .await?;
+    let stderr = ctx.get_stderr()?.rep();
+    HostOutputStream::blocking_write_and_flush(
+        &mut ctx,
+        Resource::new_borrow(stderr),
+        b"error".to_vec(),
+    )
+    .await?;
+    assert_eq!(ctx.wasi.stdout_bytes_written(), 5);
+    assert_eq!(ctx.wasi.stderr_bytes_written(), 5);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_stream_buffers() -> Result<()> {
+    use preview2::bindings::cli::stdin::Host as _;
+    use preview2::bindings::io::streams::HostInputStream;
+    use preview2::pipe::MemoryInputPipe;
+    use wasmtime::component::Resource;
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .stdin(MemoryInputPipe::new("hello".into()))
+            .build(),
+    };
+    let stdin = ctx.get_stdin()?.rep();
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 2).await;
+    assert_eq!(read.ok(), Some(b"he".to_vec()));
+
+    let capture = ctx.wasi.capture_stream_buffers(&mut ctx.table)?;
+    assert_eq!(capture.len(), 3);
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 4).await;
+    assert!(read.is_err());
+
+    ctx.wasi
+        .restore_stream_buffers(&mut ctx.table, capture.clone())?;
+    let read = HostInputStream::read(&mut ctx, Resource::new_borrow(stdin), 4).await;
+    assert_eq!(read.ok(), Some(b"llo".to_vec()));
+
+    // The capture refers to streams by their index in the table.
+    assert!(ctx
+        .wasi
+        .restore_stream_buffers(&mut Table::new(), capture)
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_buffered_stdout() -> Result<()> {
+    use preview2::bindings::cli::stdout::Host as _;
+    use preview2::bindings::io::streams::HostOutputStream as _;
+    use preview2::{HostOutputStream, StdoutStream, StreamResult, Subscribe};
+    use std::sync::Arc;
+    use wasmtime::component::Resource;
+
+    /// Records the size of every write it receives.
+    #[derive(Clone, Default)]
+    struct RecordingStdout(Arc<Mutex<Vec<usize>>>);
+
+    impl StdoutStream for RecordingStdout {
+        fn stream(&self) -> Box<dyn HostOutputStream> {
+            Box::new(self.clone())
+        }
+        fn isatty(&self) -> bool {
+            false
+        }
+    }
+
+    impl HostOutputStream for RecordingStdout {
+        fn write(&mut self, bytes: bytes::Bytes) -> StreamResult<()> {
+            self.0.lock().unwrap().push(bytes.len());
+            Ok(())
+        }
+        fn flush(&mut self) -> StreamResult<()> {
+            Ok(())
+        }
+        fn check_write(&mut self) -> StreamResult<usize> {
+            Ok(1024)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Subscribe for RecordingStdout {
+        async fn ready(&mut self) {}
+    }
+
+    fn write_bytes(ctx: &mut CommandCtx, stdout: u32, bytes: &[u8]) -> Result<()> {
+        for b in bytes {
+            ctx.write(Resource::new_borrow(stdout), vec![*b])?;
+        }
+        Ok(())
+    }
+
+    let writes = RecordingStdout::default();
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .stdout(writes.clone())
+            .line_buffered_stdout(8)
+            .build(),
+    };
+    // Every stream buffers on its own, so all writes go through one.
+    let stdout = ctx.get_stdout()?.rep();
+    write_bytes(&mut ctx, stdout, b"hi")?;
+    assert!(writes.0.lock().unwrap().is_empty());
+    write_bytes(&mut ctx, stdout, b"\nabcdefghij")?;
+    assert_eq!(*writes.0.lock().unwrap(), [3, 8]);
+    ctx.blocking_flush(Resource::new_borrow(stdout)).await?;
+    assert_eq!(*writes.0.lock().unwrap(), [3, 8, 2]);
+    assert_eq!(ctx.wasi.stdout_bytes_written(), 13);
+
+    let writes = RecordingStdout::default();
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .stdout(writes.clone())
+            .fully_buffered_stdout(4)
+            .build(),
+    };
+    let stdout = ctx.get_stdout()?.rep();
+    write_bytes(&mut ctx, stdout, b"a\nbcde\n")?;
+    assert_eq!(*writes.0.lock().unwrap(), [4]);
+    Ok(())
+}
+
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn api_stdout_encoding() -> Result<()> {
+    use preview2::bindings::cli::stdout::Host as _;
+    use preview2::bindings::io::streams::HostOutputStream as _;
+    use preview2::pipe::MemoryOutputPipe;
+    use preview2::TextEncoding;
+    use wasmtime::component::Resource;
+
+    // The guest copies ISO-8859-1 text from stdin to stdout.
+    let stdout = MemoryOutputPipe::new(4096);
+    let wasi = WasiCtxBuilder::new()
+        .stdin_from_bytes(b"caf\xe9".to_vec())
+        .stdout(stdout.clone())
+        .stdout_encoding(TextEncoding::Latin1)
+        .build();
+    let (mut store, command) = instantiate(
+        CLI_SPLICE_STDIN_COMPONENT,
+        CommandCtx {
+            table: Table::new(),
+            wasi,
+        },
+    )
+    .await?;
+    command
+        .wasi_cli_run()
+        .call_run(&mut store)
+        .await?
+        .map_err(|()| anyhow::anyhow!("command returned with failing exit status"))?;
+    let output = String::from_utf8(stdout.contents().to_vec())?;
+    assert!(output.contains("before splice\ncafé\n"), "{output:?}");
+    assert_eq!(
+        store.data().wasi.stdout_bytes_written(),
+        output.len() as u64 - 1
+    );
+
+    for (encoding, expected) in [
+        (TextEncoding::Windows1252, "€ café"),
+        (
+            TextEncoding::Custom(Box::new(|bytes: &[u8]| {
+                let ascii = bytes.iter().map(|b| if b.is_ascii() { *b } else { b'?' });
+                ascii.collect::<Vec<u8>>()
+            })),
+            "? caf?",
+        ),
+    ] {
+        let stdout = MemoryOutputPipe::new(4096);
+        let mut ctx = CommandCtx {
+            table: Table::new(),
+            wasi: WasiCtxBuilder::new()
+                .stdout(stdout.clone())
+                .stdout_encoding(encoding)
+                .build(),
+        };
+        let handle = ctx.get_stdout()?.rep();
+        ctx.blocking_write_and_flush(Resource::new_borrow(handle), b"\x80 caf\xe9".to_vec())
+            .await?;
+        assert_eq!(stdout.contents(), expected.as_bytes());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_pause_io() -> Result<()> {
+    use preview2::bindings::cli::{stderr::Host as _, stdout::Host as _};
+    use preview2::bindings::io::streams::HostOutputStream as _;
+    use preview2::pipe::MemoryOutputPipe;
+    use wasmtime::component::Resource;
+
+    let stdout = MemoryOutputPipe::new(4096);
+    let stderr = MemoryOutputPipe::new(4096);
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .build(),
+    };
+    let out = ctx.get_stdout()?.rep();
+    let err = ctx.get_stderr()?.rep();
+
+    let guard = ctx.wasi.pause_io();
+    ctx.blocking_write_and_flush(Resource::new_borrow(out), b"held ".to_vec())
+        .await?;
+    ctx.blocking_write_and_flush(Resource::new_borrow(err), b"oops".to_vec())
+        .await?;
+    ctx.blocking_write_and_flush(Resource::new_borrow(out), b"back".to_vec())
+        .await?;
+    assert!(stdout.contents().is_empty());
+    assert!(stderr.contents().is_empty());
+    assert_eq!(ctx.wasi.stdout_bytes_written(), 9);
+
+    let paused = guard.release();
+    assert_eq!(paused.stdout, b"held back");
+    assert_eq!(paused.stderr, b"oops");
+    ctx.blocking_write_and_flush(Resource::new_borrow(out), b"live".to_vec())
+        .await?;
+    assert_eq!(stdout.contents(), &b"live"[..]);
+
+    // Dropping the guard discards the output held back.
+    let guard = ctx.wasi.pause_io();
+    ctx.blocking_write_and_flush(Resource::new_borrow(err), b"lost".to_vec())
+        .await?;
+    drop(guard);
+    assert!(stderr.contents().is_empty());
+    let paused = ctx.wasi.pause_io().release();
+    assert!(paused.stderr.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_stdio_to_tracing() -> Result<()> {
+    use preview2::bindings::cli::{stderr::Host as _, stdout::Host as _};
+    use preview2::bindings::io::streams::HostOutputStream;
+    use std::sync::Arc;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Id, Record};
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use wasmtime::component::Resource;
+
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    struct Fields<'a>(&'a mut Vec<String>);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.push(format!("{}={value:?}", field.name()));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            let mut fields = Vec::new();
+            values.record(&mut Fields(&mut fields));
+            self.0.lock().unwrap().push(fields.join(" "));
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            let mut fields = vec![event.metadata().level().to_string()];
+            event.record(&mut Fields(&mut fields));
+            self.0.lock().unwrap().push(fields.join(" "));
+        }
+    }
+
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = tracing_subscriber::registry().with(Capture(captured.clone()));
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .stdout_to_tracing(Level::INFO)
+            .stderr_to_tracing(Level::WARN)
+            .debug_label("greeter")
+            .build(),
+    };
+    let out = ctx.get_stdout()?;
+    let err = ctx.get_stderr()?;
+    let (out_rep, err_rep) = (out.rep(), err.rep());
+    ctx.blocking_write_and_flush(Resource::new_borrow(out_rep), b"hello, ".to_vec())
+        .await?;
+    ctx.blocking_write_and_flush(Resource::new_borrow(out_rep), b"world\nsecond\n".to_vec())
+        .await?;
+    ctx.blocking_write_and_flush(Resource::new_borrow(err_rep), b"oops\r\ntrailing".to_vec())
+        .await?;
+    HostOutputStream::drop(&mut ctx, out)?;
+    HostOutputStream::drop(&mut ctx, err)?;
+
+    assert_eq!(
+        *captured.lock().unwrap(),
+        [
+            "component=\"greeter\"",
+            "component=\"greeter\"",
+            "INFO message=hello, world stream=\"stdout\"",
+            "INFO message=second stream=\"stdout\"",
+            "WARN message=oops stream=\"stderr\"",
+            "WARN message=trailing stream=\"stderr\"",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn api_request_id() -> Result<()> {
+    use std::sync::Arc;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use wasmtime::CallHook;
+
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    struct Fields<'a>(&'a mut Vec<String>);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.push(format!("{}={value:?}", field.name()));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            let mut fields = vec![attrs.metadata().name().to_string()];
+            attrs.record(&mut Fields(&mut fields));
+            self.0.lock().unwrap().push(fields.join(" "));
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let span = ctx.current_span().metadata().map_or("none", |m| m.name());
+            let mut fields = vec![format!("in {span}")];
+            event.record(&mut Fields(&mut fields));
+            self.0.lock().unwrap().push(fields.join(" "));
+        }
+    }
+
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = tracing_subscriber::registry().with(Capture(captured.clone()));
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let wasi = WasiCtxBuilder::new()
+        .env("WASMTIME_REQUEST_ID", "stale")
+        .env("HOME", "/")
+        .with_request_id("req-42")
+        .build();
+    assert_eq!(wasi.request_id(), Some("req-42"));
+    assert_eq!(wasi.get_env("WASMTIME_REQUEST_ID"), Some("req-42"));
+    assert_eq!(wasi.get_all_envs().len(), 2);
+
+    wasi.record_call_hook(CallHook::CallingHost);
+    tracing::info!("host call");
+    wasi.record_call_hook(CallHook::ReturningFromHost);
+    tracing::info!("after");
+
+    assert_eq!(
+        *captured.lock().unwrap(),
+        [
+            "wasi_request request_id=\"req-42\"",
+            "in wasi_request message=host call",
+            "in none message=after",
+        ]
+    );
+    assert_eq!(WasiCtxBuilder::new().build().request_id(), None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_execution_stats() -> Result<()> {
+    use preview2::pipe::MemoryOutputPipe;
+
+    let stdout = MemoryOutputPipe::new(4096);
+    let mut config = Config::new();
+    config.async_support(true).wasm_component_model(true);
+    let engine = Engine::new(&config)?;
+    let mut linker = Linker::new(&engine);
+    add_to_linker(&mut linker)?;
+    let mut store = Store::new(
+        &engine,
+        CommandCtx {
+            table: Table::new(),
+            wasi: WasiCtxBuilder::new()
+                .stdin_from_bytes(b"hello".to_vec())
+                .stdout(stdout.clone())
+                .build(),
+        },
+    );
+    store.limiter_async(|data| data.wasi.resource_limiter());
+    store.call_hook(|data, hook| {
+        data.wasi.record_call_hook(hook);
+        Ok(())
+    });
+    let component = Component::from_file(&engine, CLI_SPLICE_STDIN_COMPONENT)?;
+    let (command, _instance) = Command::instantiate_async(&mut store, &component, &linker).await?;
+    command
+        .wasi_cli_run()
+        .call_run(&mut store)
+        .await?
+        .map_err(|()| anyhow::anyhow!("command returned with failing exit status"))?;
+
+    let stats = store.data().wasi.execution_stats();
+    assert_eq!(stats.bytes_stdin_read, 5);
+    assert_eq!(stats.bytes_stdout_written, stdout.contents().len() as u64);
+    assert!(stats.wall_time_ns > 0);
+    assert!(stats.cpu_time_ns > 0);
+    assert!(stats.syscalls_total > 0);
+    assert!(stats.memory_peak_bytes >= 64 * 1024);
+
+    let previous = store.data().wasi.reset_stats();
+    assert_eq!(previous.bytes_stdin_read, 5);
+    assert_eq!(previous.syscalls_total, stats.syscalls_total);
+    let stats = store.data().wasi.execution_stats();
+    assert_eq!(stats.bytes_stdin_read, 0);
+    assert_eq!(stats.bytes_stdout_written, 0);
+    assert_eq!(stats.cpu_time_ns, 0);
+    assert_eq!(stats.syscalls_total, 0);
+    // The memories are still alive, so the peak restarts at their size.
+    assert_eq!(stats.memory_peak_bytes, previous.memory_peak_bytes);
+    assert_eq!(
+        store.data().wasi.stdout_bytes_written(),
+        previous.bytes_stdout_written
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_profiler() -> Result<()> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use wasmtime::{Instance, Module, UpdateDeadline};
+    use wasmtime_wasi::preview2::SamplingProfiler;
+
+    let mut config = Config::new();
+    config
+        .async_support(true)
+        .epoch_interruption(true)
+        .consume_fuel(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(
+        &engine,
+        r#"(module (memory 1) (func (export "spin") (loop br 0)))"#,
+    )?;
+    let profiler = SamplingProfiler::new();
+    let mut store = Store::new(
+        &engine,
+        CommandCtx {
+            table: Table::new(),
+            wasi: WasiCtxBuilder::new()
+                .with_profiler(profiler.clone(), 2)
+                .build(),
+        },
+    );
+    store.add_fuel(1 << 40)?;
+    store.limiter_async(|data| data.wasi.resource_limiter());
+    store.set_epoch_deadline(1);
+    let samples = profiler.clone();
+    store.epoch_deadline_callback(move |mut store| {
+        let fuel = store.fuel_consumed().unwrap_or(0);
+        let next = store.data_mut().wasi.sample_profiler(fuel).unwrap();
+        if samples.samples().len() == 3 {
+            anyhow::bail!("collected enough samples");
+        }
+        Ok(UpdateDeadline::Continue(next))
+    });
+    let instance = Instance::new_async(&mut store, &module, &[]).await?;
+    let spin = instance.get_typed_func::<(), ()>(&mut store, "spin")?;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let ticker = std::thread::spawn({
+        let (engine, done) = (engine.clone(), done.clone());
+        move || {
+            while !done.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(1));
+                engine.increment_epoch();
+            }
+        }
+    });
+    let err = spin.call_async(&mut store, ()).await.unwrap_err();
+    done.store(true, Ordering::SeqCst);
+    ticker.join().unwrap();
+    assert!(
+        format!("{err:?}").contains("collected enough samples"),
+        "{err:?}"
+    );
+
+    let samples = profiler.samples();
+    assert_eq!(samples.len(), 3);
+    for pair in samples.windows(2) {
+        assert!(pair[0].0 <= pair[1].0);
+        assert!(pair[0].1 <= pair[1].1);
+    }
+    assert!(samples.iter().all(|(_, _, memory)| *memory == 64 * 1024));
+
+    let mut ctx = WasiCtxBuilder::new().build();
+    assert_eq!(ctx.sample_profiler(0), None);
+    Ok(())
+}
+
+#[test]
+fn api_scheduler() -> Result<()> {
+    use preview2::{LatencyBoundScheduler, RoundRobinScheduler};
+    use wasmtime::UpdateDeadline;
+
+    let mut wasi = WasiCtxBuilder::new()
+        .with_scheduler(RoundRobinScheduler {
+            max_fuel_per_slice: 100,
+        })
+        .build();
+    assert!(matches!(wasi.schedule(50), UpdateDeadline::Continue(1)));
+    assert!(matches!(wasi.schedule(120), UpdateDeadline::Yield(1)));
+    // The fuel of the next slice is counted from the yield.
+    assert!(matches!(wasi.schedule(200), UpdateDeadline::Continue(1)));
+    assert!(matches!(wasi.schedule(220), UpdateDeadline::Yield(1)));
+
+    let mut wasi = WasiCtxBuilder::new()
+        .with_scheduler(LatencyBoundScheduler { max_ms: 0 })
+        .build();
+    assert!(matches!(wasi.schedule(0), UpdateDeadline::Yield(1)));
+
+    let mut wasi = WasiCtxBuilder::new().build();
+    assert!(matches!(
+        wasi.schedule(u64::MAX),
+        UpdateDeadline::Continue(1)
+    ));
+    Ok(())
+}
+
+#[test]
+fn api_prng_replay() -> Result<()> {
+    use preview2::bindings::random::random::Host as _;
+    use preview2::{RecordingRng, ReplayRng, RngCore};
+
+    let recording = RecordingRng::new(preview2::thread_rng());
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .secure_random(recording.clone())
+            .build(),
+    };
+    let bytes = ctx.get_random_bytes(16)?;
+    let value = ctx.get_random_u64()?;
+    let recorded = recording.recorded();
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .with_prng_replay(recorded.clone())
+            .build(),
+    };
+    assert_eq!(ctx.get_random_bytes(16)?, bytes);
+    assert_eq!(ctx.get_random_u64()?, value);
+
+    // Requests beyond the recording fail, both from the guest and directly.
+    assert!(ctx.get_random_u64().is_err());
+    let mut replay = ReplayRng::new(recorded.clone());
+    let mut buf = vec![0; recorded.bytes().len()];
+    replay.try_fill_bytes(&mut buf)?;
+    assert_eq!(buf, recorded.bytes());
+    assert_eq!(replay.remaining(), 0);
+    assert!(replay.try_fill_bytes(&mut [0]).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_max_open_handles() -> Result<()> {
+    use filesystem::{
+        DescriptorFlags, ErrorCode, HostDescriptor as _, Modes, OpenFlags, PathFlags,
+    };
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::bindings::sockets::network::{self, IpAddressFamily};
+    use preview2::bindings::sockets::tcp_create_socket::Host as _;
+    use preview2::bindings::sockets::udp_create_socket::Host as _;
+    use wasmtime::component::Resource;
+
+    async fn open(
+        ctx: &mut CommandCtx,
+        root: u32,
+        path: &str,
+    ) -> preview2::FsResult<Resource<filesystem::Descriptor>> {
+        ctx.open_at(
+            Resource::new_borrow(root),
+            PathFlags::empty(),
+            path.to_string(),
+            OpenFlags::empty(),
+            DescriptorFlags::READ,
+            Modes::empty(),
+        )
+        .await
+    }
+
+    let tmpdir = tempfile::tempdir()?;
+    let mut builder = WasiCtxBuilder::new();
+    let builder = builder.preopened_dir_at_host_path(
+        tmpdir.path(),
+        DirPerms::all(),
+        FilePerms::all(),
+        "/data",
+    )?;
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: builder.max_open_files(100).max_open_sockets(1).build(),
+    };
+    std::fs::write(tmpdir.path().join("file.txt"), "hello")?;
+    std::fs::create_dir(tmpdir.path().join("dir"))?;
+    let (root, _) = ctx.get_directories()?.remove(0);
+    let root = root.rep();
+
+    for _ in 0..100 {
+        open(&mut ctx, root, "file.txt").await?;
+    }
+    let err = open(&mut ctx, root, "file.txt").await.unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::Quota));
+    // Directories are limited separately.
+    open(&mut ctx, root, "dir").await?;
+
+    ctx.create_tcp_socket(IpAddressFamily::Ipv4)?;
+    let err = ctx.create_udp_socket(IpAddressFamily::Ipv4).unwrap_err();
+    assert!(matches!(
+        err.downcast()?,
+        network::ErrorCode::NewSocketLimit
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_capability_policy_filesystem() -> Result<()> {
+    use filesystem::{
+        DescriptorFlags, ErrorCode, HostDescriptor as _, Modes, OpenFlags, PathFlags,
+    };
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::CapabilityPolicy;
+    use wasmtime::component::Resource;
+
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("public.txt"), "public")?;
+    std::fs::write(dir.path().join("secret.txt"), "secret")?;
+    std::fs::create_dir(dir.path().join("sub"))?;
+    std::fs::write(dir.path().join("sub/secret.txt"), "secret")?;
+
+    let mut policy = CapabilityPolicy::new();
+    policy
+        .deny_path("/data/secret.txt")
+        .deny_path("**/sub/*.txt");
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .preopened_dir_at_host_path(dir.path(), DirPerms::READ, FilePerms::READ, "/data")?
+            .capability_policy(policy)
+            .build(),
+    };
+    let (root, _) = ctx.get_directories()?.remove(0);
+    let root = root.rep();
+
+    async fn open(ctx: &mut CommandCtx, dir: u32, path: &str) -> preview2::FsResult<u32> {
+        let fd = ctx
+            .open_at(
+                Resource::new_borrow(dir),
+                PathFlags::empty(),
+                path.to_string(),
+                OpenFlags::empty(),
+                DescriptorFlags::READ,
+                Modes::empty(),
+            )
+            .await?;
+        Ok(fd.rep())
+    }
+
+    open(&mut ctx, root, "public.txt").await?;
+    for denied in [
+        "secret.txt",
+        "./secret.txt",
+        "sub/../secret.txt",
+        "sub/secret.txt",
+    ] {
+        let err = open(&mut ctx, root, denied).await.unwrap_err();
+        assert!(
+            matches!(err.downcast()?, ErrorCode::NotPermitted),
+            "opening `{denied}`"
+        );
+    }
+    // Rules are evaluated against the guest path of directories opened
+    // beneath the preopen too.
+    let sub = open(&mut ctx, root, "sub").await?;
+    let err = open(&mut ctx, sub, "secret.txt").await.unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::NotPermitted));
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_capability_policy_symlinks() -> Result<()> {
+    use filesystem::{
+        DescriptorFlags, ErrorCode, HostDescriptor as _, Modes, OpenFlags, PathFlags,
+    };
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::CapabilityPolicy;
+    use wasmtime::component::Resource;
+
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("public.txt"), "public")?;
+    std::fs::create_dir(dir.path().join("secrets"))?;
+    std::fs::write(dir.path().join("secrets/key"), "secret")?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("secrets", dir.path().join("existing"))?;
+
+    let mut policy = CapabilityPolicy::new();
+    policy.deny_path("**/secrets/**");
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .preopened_dir_at_host_path(dir.path(), DirPerms::all(), FilePerms::all(), "/data")?
+            .capability_policy(policy)
+            .build(),
+    };
+    let (root, _) = ctx.get_directories()?.remove(0);
+    let root = root.rep();
+
+    async fn open(ctx: &mut CommandCtx, dir: u32, path: &str) -> preview2::FsResult<u32> {
+        let fd = ctx
+            .open_at(
+                Resource::new_borrow(dir),
+                PathFlags::SYMLINK_FOLLOW,
+                path.to_string(),
+                OpenFlags::empty(),
+                DescriptorFlags::READ,
+                Modes::empty(),
+            )
+            .await?;
+        Ok(fd.rep())
+    }
+
+    // A link to a denied path can't be created...
+    let err = ctx
+        .symlink_at(
+            Resource::new_borrow(root),
+            "secrets".to_string(),
+            "x".to_string(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::NotPermitted));
+    let err = open(&mut ctx, root, "x/key").await.unwrap_err();
+    assert!(matches!(err.downcast()?, ErrorCode::NoEntry));
+
+    // ...while links to allowed paths still work.
+    ctx.symlink_at(
+        Resource::new_borrow(root),
+        "public.txt".to_string(),
+        "public".to_string(),
+    )
+    .await?;
+    open(&mut ctx, root, "public").await?;
+
+    // Paths through links which already exist are resolved before the policy
+    // is checked.
+    #[cfg(unix)]
+    for denied in ["existing/key", "existing/../existing/key"] {
+        let err = open(&mut ctx, root, denied).await.unwrap_err();
+        assert!(
+            matches!(err.downcast()?, ErrorCode::NotPermitted),
+            "opening `{denied}`"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn api_capability_policy_sockets() -> Result<()> {
+    use preview2::bindings::sockets::instance_network::Host as _;
+    use preview2::bindings::sockets::network::ErrorCode;
+    use preview2::bindings::sockets::tcp::{HostTcpSocket, IpAddressFamily};
+    use preview2::bindings::sockets::tcp_create_socket::Host as _;
+    use preview2::bindings::sockets::udp::HostUdpSocket;
+    use preview2::bindings::sockets::udp_create_socket::Host as _;
+    use preview2::CapabilityPolicy;
+    use std::net::SocketAddr;
+
+    let mut policy = CapabilityPolicy::new();
+    policy
+        .allow_network("127.0.0.1/32".parse()?)
+        .deny_network("127.0.0.0/8".parse()?);
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .inherit_network(ambient_authority())
+            .capability_policy(policy)
+            .build(),
+    };
+    let allowed: SocketAddr = "127.0.0.1:0".parse()?;
+    let denied: SocketAddr = "127.0.0.2:0".parse()?;
+
+    let tcp = |ctx: &mut CommandCtx, addr: SocketAddr| -> Result<Result<(), ErrorCode>> {
+        let network = ctx.instance_network()?;
+        let socket = ctx.create_tcp_socket(IpAddressFamily::Ipv4).ok().unwrap();
+        Ok(HostTcpSocket::start_bind(ctx, socket, network, addr.into())
+            .map_err(|e| e.downcast().unwrap()))
+    };
+    tcp(&mut ctx, allowed)?.unwrap();
+    assert!(matches!(
+        tcp(&mut ctx, denied)?,
+        Err(ErrorCode::AccessDenied)
+    ));
+
+    let udp = |ctx: &mut CommandCtx, addr: SocketAddr| -> Result<Result<(), ErrorCode>> {
+        let network = ctx.instance_network()?;
+        let socket = ctx.create_udp_socket(IpAddressFamily::Ipv4).ok().unwrap();
+        Ok(HostUdpSocket::start_bind(ctx, socket, network, addr.into())
+            .map_err(|e| e.downcast().unwrap()))
+    };
+    udp(&mut ctx, allowed)?.unwrap();
+    assert!(matches!(
+        udp(&mut ctx, denied)?,
+        Err(ErrorCode::AccessDenied)
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_preopen_scope_policy() -> Result<()> {
+    use filesystem::{
+        DescriptorFlags, ErrorCode, HostDescriptor as _, Modes, OpenFlags, PathFlags,
+    };
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::ScopePolicy;
+    use wasmtime::component::Resource;
+
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("root"))?;
+    std::fs::create_dir(dir.path().join("outside"))?;
+    std::fs::write(dir.path().join("root/hello.txt"), "hello")?;
+    std::fs::write(dir.path().join("outside/secret.txt"), "secret")?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("../outside", dir.path().join("root/escape"))?;
+
+    async fn open(policy: ScopePolicy, root: &std::path::Path, path: &str) -> Result<ErrorCode> {
+        let mut builder = WasiCtxBuilder::new();
+        builder
+            .preopened_dir_at_host_path(root, DirPerms::READ, FilePerms::READ, "/")?
+            .preopen_scope_policy(policy);
+        let mut ctx = CommandCtx {
+            table: Table::new(),
+            wasi: builder.build(),
+        };
+        let (root, _) = ctx.get_directories()?.remove(0);
+        let result = ctx
+            .open_at(
+                Resource::new_borrow(root.rep()),
+                PathFlags::SYMLINK_FOLLOW,
+                path.to_string(),
+                OpenFlags::empty(),
+                DescriptorFlags::READ,
+                Modes::empty(),
+            )
+            .await;
+        match result {
+            Ok(_) => anyhow::bail!("opening `{path}` succeeded"),
+            Err(e) => e.downcast(),
+        }
+    }
+
+    let root = dir.path().join("root");
+    // `cap-std` never lets paths escape, but reports it with another error.
+    assert_ne!(
+        open(ScopePolicy::AllowSymlinkEscape, &root, "../../etc/passwd").await?,
+        ErrorCode::Access
+    );
+    for policy in [ScopePolicy::DenyDotDot, ScopePolicy::DenySymlinkEscape] {
+        assert_eq!(
+            open(policy, &root, "../../etc/passwd").await?,
+            ErrorCode::Access
+        );
+        // `..` is rejected even when the path stays within the preopen.
+        assert_eq!(
+            open(policy, &root, "sub/../hello.txt").await?,
+            ErrorCode::Access
+        );
+    }
+    #[cfg(unix)]
+    {
+        assert_eq!(
+            open(ScopePolicy::DenySymlinkEscape, &root, "escape/secret.txt").await?,
+            ErrorCode::Access
+        );
+        assert_ne!(
+            open(ScopePolicy::DenyDotDot, &root, "escape/secret.txt").await?,
+            ErrorCode::Access
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_table_iter_by_kind() -> Result<()> {
+    use filesystem::{DescriptorFlags, HostDescriptor, Modes, OpenFlags, PathFlags};
+    use preview2::bindings::filesystem::preopens::Host as _;
+    use preview2::{FakeFilesystem, ResourceKind};
+    use wasmtime::component::Resource;
+
+    let mut fs = FakeFilesystem::new();
+    for i in 0..5 {
+        fs.file(format!("{i}.txt"), "data");
+    }
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new().fake_filesystem(fs)?.build(),
+    };
+    let (root, _) = ctx.get_directories()?.remove(0);
+    let mut files = Vec::new();
+    for i in 0..5 {
+        let file = ctx
+            .open_at(
+                Resource::new_borrow(root.rep()),
+                PathFlags::empty(),
+                format!("{i}.txt"),
+                OpenFlags::empty(),
+                DescriptorFlags::READ,
+                Modes::empty(),
+            )
+            .await?;
+        files.push(file);
+    }
+    for file in files.drain(..3) {
+        HostDescriptor::drop(&mut ctx, file)?;
+    }
+
+    assert_eq!(ctx.table.iter_files().count(), 2);
+    assert_eq!(ctx.table.iter_dirs().count(), 1);
+    assert_eq!(ctx.table.iter_by_kind(ResourceKind::File).count(), 2);
+    let mut open = ctx.table.iter_files().map(|(i, _)| i).collect::<Vec<_>>();
+    open.sort();
+    assert_eq!(open, files.iter().map(|f| f.rep()).collect::<Vec<_>>());
+
+    let counts = ctx.table.count_by_kind();
+    assert_eq!(counts[&ResourceKind::File], 2);
+    assert_eq!(counts[&ResourceKind::Dir], 1);
+    assert!(!counts.contains_key(&ResourceKind::TcpSocket));
+    Ok(())
+}
+
+#[test]
+fn api_clock_deadline() -> Result<()> {
+    use preview2::{DeadlineBehavior, DeadlineExceeded};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    struct FixedWallClock;
+
+    impl HostWallClock for FixedWallClock {
+        fn resolution(&self) -> Duration {
+            Duration::from_secs(1)
+        }
+
+        fn now(&self) -> Duration {
+            Duration::from_secs(2000)
+        }
+    }
+
+    struct FixedMonotonicClock;
+
+    impl HostMonotonicClock for FixedMonotonicClock {
+        fn resolution(&self) -> u64 {
+            1
+        }
+
+        fn now(&self) -> u64 {
+            2000
+        }
+    }
+
+    let epoch = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+    // Reads before the deadline are unaffected.
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .wall_clock(FixedWallClock)
+            .deadline_wall_clock(epoch(3000), DeadlineBehavior::ReturnError)
+            .build(),
+    };
+    assert_eq!(wall_clock::Host::now(&mut ctx)?.seconds, 2000);
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .wall_clock(FixedWallClock)
+            .deadline_wall_clock(epoch(1000), DeadlineBehavior::ReturnError)
+            .monotonic_clock(FixedMonotonicClock)
+            .deadline_monotonic_clock(
+                Duration::from_nanos(1000),
+                DeadlineBehavior::ReturnEpoch(epoch(0)),
+            )
+            .build(),
+    };
+    let err = wall_clock::Host::now(&mut ctx).unwrap_err();
+    assert!(err.downcast_ref::<DeadlineExceeded>().is_some());
+    assert_eq!(
+        preview2::bindings::clocks::monotonic_clock::Host::now(&mut ctx)?,
+        1000
+    );
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls2 = calls.clone();
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .wall_clock(FixedWallClock)
+            .deadline_wall_clock(
+                epoch(1000),
+                DeadlineBehavior::CallCallback(Box::new(move || {
+                    calls2.fetch_add(1, Ordering::SeqCst);
+                })),
+            )
+            .build(),
+    };
+    assert_eq!(wall_clock::Host::now(&mut ctx)?.seconds, 2000);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .wall_clock(FixedWallClock)
+            .deadline_wall_clock(epoch(1000), DeadlineBehavior::ReturnEpoch(epoch(42)))
+            .build(),
+    };
+    assert_eq!(wall_clock::Host::now(&mut ctx)?.seconds, 42);
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn api_clock_source() -> Result<()> {
+    use preview2::bindings::wasi::clocks::monotonic_clock;
+    use preview2::ClockSource;
+    use std::time::SystemTime;
+
+    assert!(ClockSource::Realtime.is_available());
+    assert!(ClockSource::Monotonic.is_available());
+    assert_eq!(
+        ClockSource::Tai.is_available(),
+        cfg!(any(target_os = "linux", target_os = "android"))
+    );
+
+    // A source the platform lacks fails the build, and reads zero.
+    for source in [ClockSource::Tai, ClockSource::ProcessCpu] {
+        if !source.is_available() {
+            let built = WasiCtxBuilder::new().wall_clock_source(source).try_build();
+            assert!(built.is_err());
+            assert_eq!(HostMonotonicClock::now(&source), 0);
+        }
+    }
+    assert!(WasiCtxBuilder::new()
+        .wall_clock_source(ClockSource::Realtime)
+        .try_build()
+        .is_ok());
+
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .wall_clock_source(ClockSource::Realtime)
+            .monotonic_clock(ClockSource::ProcessCpu)
+            .build(),
+    };
+    let host = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+    let now = wall_clock::Host::now(&mut ctx)?;
+    assert!(now.seconds.abs_diff(host.as_secs()) <= 1);
+    assert!(wall_clock::Host::resolution(&mut ctx)?.nanoseconds > 0);
+
+    // The process has been running for a while, and keeps using CPU time.
+    let before = monotonic_clock::Host::now(&mut ctx)?;
+    let mut x = 0u64;
+    for i in 0..1_000_000 {
+        x = std::hint::black_box(x.wrapping_add(i));
+    }
+    assert!(monotonic_clock::Host::now(&mut ctx)? > before);
+    assert!(before > 0);
+    Ok(())
+}
+
+#[test]
+fn api_resource_hooks() -> Result<()> {
+    use preview2::bindings::cli::stdout::Host as _;
+    use preview2::bindings::io::streams::HostOutputStream;
+    use preview2::ResourceKind;
+    use std::sync::Arc;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let (created, dropped) = (events.clone(), events.clone());
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: WasiCtxBuilder::new()
+            .on_resource_create(move |kind, index| {
+                created.lock().unwrap().push(("create", kind, index))
+            })
+            .on_resource_drop(move |kind, index| {
+                dropped.lock().unwrap().push(("drop", kind, index))
+            })
+            .build(),
+    };
+
+    let stdout = ctx.get_stdout()?;
+    let index = stdout.rep();
+    HostOutputStream::drop(&mut ctx, stdout)?;
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            ("create", ResourceKind::OutputStream, index),
+            ("drop", ResourceKind::OutputStream, index),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn api_resource_callback() -> Result<()> {
+    use preview2::bindings::cli::stdout::Host as _;
+    use preview2::bindings::io::streams::HostOutputStream;
+    use preview2::pipe::MemoryOutputPipe;
+    use preview2::ResourceUsageEvent;
+
+    let mut builder = WasiCtxBuilder::new();
+    builder.stdout(MemoryOutputPipe::new(4096));
+    let mut events = builder.resource_usage_channel(16);
+    let mut ctx = CommandCtx {
+        table: Table::new(),
+        wasi: builder.build(),
+    };
+
+    let stdout = ctx.get_stdout()?;
+    let index = stdout.rep();
+    HostOutputStream::write(&mut ctx, stdout, b"hello".to_vec())?;
+    assert_eq!(
+        events.recv().await,
+        Some(ResourceUsageEvent::BytesWritten(index, 5))
+    );
+
+    assert!(
+        ctx.wasi
+            .resource_limiter()
+            .memory_growing(0, 2 * 65536, None)
+            .await?
+    );
+    assert_eq!(
+        events.recv().await,
+        Some(ResourceUsageEvent::MemoryGrown(2, 2 * 65536))
+    );
+    assert!(events.try_recv().is_err());
+    Ok(())
+}
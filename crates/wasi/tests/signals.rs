@@ -0,0 +1,80 @@
+//! Signal handlers are installed for the whole process, and this test sends
+//! real signals to it, so it runs in its own test binary rather than next to
+//! the other tests in `all`.
+#![cfg(unix)]
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use wasmtime::{Config, Engine, Instance, Module, Store, UpdateDeadline};
+use wasmtime_wasi::preview2::{SignalAction, Table, WasiCtx, WasiCtxBuilder, WasiView};
+
+struct CommandCtx {
+    table: Table,
+    wasi: WasiCtx,
+}
+
+impl WasiView for CommandCtx {
+    fn table(&self) -> &Table {
+        &self.table
+    }
+    fn table_mut(&mut self) -> &mut Table {
+        &mut self.table
+    }
+    fn ctx(&self) -> &WasiCtx {
+        &self.wasi
+    }
+    fn ctx_mut(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+#[test]
+fn signal_handler() -> Result<()> {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(&engine, r#"(module (func (export "spin") (loop br 0)))"#)?;
+    let mut store = Store::new(
+        &engine,
+        CommandCtx {
+            table: Table::new(),
+            wasi: WasiCtxBuilder::new()
+                .with_signal_handler(|signal: i32| match signal {
+                    libc::SIGINT => SignalAction::Snapshot,
+                    _ => SignalAction::Terminate,
+                })
+                .build(),
+        },
+    );
+    store.data().wasi.interrupt_on_signal(&engine);
+    let snapshots = Arc::new(AtomicUsize::new(0));
+    let counter = snapshots.clone();
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |store| match store.data().wasi.take_signal() {
+        Some((signal, SignalAction::Terminate)) => anyhow::bail!("terminated by signal {signal}"),
+        Some((_, SignalAction::Snapshot)) => {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(UpdateDeadline::Continue(1))
+        }
+        _ => Ok(UpdateDeadline::Continue(1)),
+    });
+    let instance = Instance::new(&mut store, &module, &[])?;
+    let spin = instance.get_typed_func::<(), ()>(&mut store, "spin")?;
+
+    let sender = std::thread::spawn(|| unsafe {
+        libc::kill(libc::getpid(), libc::SIGINT);
+        std::thread::sleep(Duration::from_millis(100));
+        libc::kill(libc::getpid(), libc::SIGTERM);
+    });
+    let err = spin.call(&mut store, ()).unwrap_err();
+    sender.join().unwrap();
+    assert!(
+        format!("{err:?}").contains(&format!("terminated by signal {}", libc::SIGTERM)),
+        "{err:?}"
+    );
+    assert_eq!(snapshots.load(Ordering::SeqCst), 1);
+    assert_eq!(store.data().wasi.take_signal(), None);
+    Ok(())
+}
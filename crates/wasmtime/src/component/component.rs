@@ -291,6 +291,10 @@ impl Component {
         &self.inner.static_modules[idx]
     }
 
+    pub(crate) fn static_modules(&self) -> impl Iterator<Item = &Module> + '_ {
+        self.inner.static_modules.values()
+    }
+
     #[inline]
     pub(crate) fn types(&self) -> &Arc<ComponentTypes> {
         self.inner.component_types()
@@ -101,6 +101,8 @@ pub struct FuncData {
     component_instance: RuntimeComponentInstanceIndex,
     post_return: Option<ExportFunction>,
     post_return_arg: Option<ValRaw>,
+    /// The core instance defining the lifted core function, if any.
+    core_instance: Option<crate::Instance>,
 }
 
 impl Func {
@@ -125,6 +127,7 @@ impl Func {
             ExportFunction { func_ref }
         });
         let component_instance = options.instance;
+        let core_instance = data.core_instance(func);
         let options = unsafe { Options::new(store.id(), memory, realloc, options.string_encoding) };
         Func(store.store_data_mut().insert(FuncData {
             export,
@@ -135,6 +138,7 @@ impl Func {
             component_instance,
             post_return,
             post_return_arg: None,
+            core_instance,
         }))
     }
 
@@ -597,6 +601,20 @@ impl Func {
         store.on_fiber(|store| self.post_return_impl(store)).await?
     }
 
+    /// Returns the core instance which defines the core function lifted
+    /// into this function, whose state is captured by
+    /// [`TypedFunc::call_and_snapshot_async`].
+    pub(crate) fn core_instance(&self, store: impl AsContext) -> Option<crate::Instance> {
+        store.as_context().0[self.0].core_instance
+    }
+
+    /// Returns the key identifying this function among the automatic
+    /// snapshots of its instance, which is the same for every [`Func`]
+    /// looked up for the same export.
+    pub(crate) fn auto_snapshot_key(&self, store: impl AsContext) -> usize {
+        store.as_context().0[self.0].export.func_ref.as_ptr() as usize
+    }
+
     #[inline]
     fn post_return_impl(&self, mut store: impl AsContextMut) -> Result<()> {
         let mut store = store.as_context_mut();
@@ -668,6 +686,16 @@ impl Func {
             }
             .exit_call()?;
         }
+
+        let key = self.auto_snapshot_key(&store);
+        let component = store.0[self.0].instance;
+        let handler = store.0[component.0]
+            .as_mut()
+            .unwrap()
+            .auto_snapshot_due(key);
+        if let (Some(handler), Some(core_instance)) = (handler, self.core_instance(&store)) {
+            handler(core_instance.snapshot(&mut store)?);
+        }
         Ok(())
     }
 
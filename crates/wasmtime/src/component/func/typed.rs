@@ -341,6 +341,57 @@ where
     ) -> Result<()> {
         self.func.post_return_async(store).await
     }
+
+    /// Calls this function with `params`, runs its `post-return` and then
+    /// captures a [`Snapshot`](crate::Snapshot) of the core instance
+    /// defining the function, such as the instance of the component's main
+    /// module.
+    ///
+    /// This is the typed equivalent of
+    /// [`Instance::call_and_snapshot_async`](crate::Instance::call_and_snapshot_async).
+    /// The store remains exclusively borrowed from the start of the call
+    /// until the snapshot has been taken, so the snapshot reflects exactly
+    /// the state left behind by the call, including any cleanup done by
+    /// `post-return`. This is useful to persist a component after each call
+    /// for durability.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call or `post-return` fails for any of the
+    /// reasons documented on [`TypedFunc::call_async`], or if the snapshot
+    /// cannot be taken as documented on
+    /// [`Instance::snapshot`](crate::Instance::snapshot). Fails before
+    /// calling if this function is not defined by a core instance, for
+    /// example if it re-exports a lowered host function. No snapshot is
+    /// taken if the call fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called on a function in a synchronous store. This
+    /// only works with functions defined within an asynchronous store. Also
+    /// panics if `store` does not own this function.
+    #[cfg(feature = "async")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+    pub async fn call_and_snapshot_async<T>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        params: Params,
+    ) -> Result<(Return, crate::Snapshot)>
+    where
+        T: Send,
+        Params: Send + Sync,
+        Return: Send + Sync,
+    {
+        let mut store = store.as_context_mut();
+        let instance = self
+            .func
+            .core_instance(&store)
+            .ok_or_else(|| anyhow!("function is not defined by a core instance"))?;
+        let results = self.call_async(&mut store, params).await?;
+        self.post_return_async(&mut store).await?;
+        let snapshot = instance.snapshot(&mut store)?;
+        Ok((results, snapshot))
+    }
 }
 
 /// A trait representing a static list of named types that can be passed to or
@@ -1,12 +1,14 @@
 use crate::component::func::HostFunc;
 use crate::component::matching::InstanceType;
+use crate::component::types::Type;
 use crate::component::{Component, ComponentNamedList, Func, Lift, Lower, ResourceType, TypedFunc};
 use crate::instance::OwnedImports;
 use crate::linker::DefinitionType;
 use crate::store::{StoreOpaque, Stored};
 use crate::{AsContextMut, Module, StoreContextMut};
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use indexmap::IndexMap;
+use std::collections::HashMap;
 use std::marker;
 use std::ptr::NonNull;
 use std::sync::Arc;
@@ -56,6 +58,19 @@ pub(crate) struct InstanceData {
     /// entire list here though we're guaranteed that nothing is lost for the
     /// duration of the lifetime of this instance.
     imports: Arc<PrimaryMap<RuntimeImportIndex, RuntimeImport>>,
+
+    /// The automatic snapshots configured with
+    /// [`Instance::enable_auto_snapshot`], keyed by the address of the core
+    /// function lifted into the export.
+    auto_snapshots: HashMap<usize, AutoSnapshot>,
+}
+
+/// The state of an automatic snapshot configured with
+/// [`Instance::enable_auto_snapshot`].
+struct AutoSnapshot {
+    every_n_calls: u32,
+    calls: u32,
+    handler: Arc<dyn Fn(crate::Snapshot) + Send + Sync>,
 }
 
 impl Instance {
@@ -143,9 +158,100 @@ impl Instance {
     pub fn get_resource(&self, mut store: impl AsContextMut, name: &str) -> Option<ResourceType> {
         self.exports(store.as_context_mut()).root().resource(name)
     }
+
+    /// Returns every export of this instance, including the exports of the
+    /// instances it exports, for tools which work with any component without
+    /// knowing its interface in advance.
+    ///
+    /// Exports are listed in the order the component declares them, with
+    /// each instance listed just before its own exports.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn list_all_exports(&self, mut store: impl AsContextMut) -> Vec<TypedExport> {
+        let mut store = store.as_context_mut();
+        let mut exports = Vec::new();
+        self.exports(&mut store)
+            .root()
+            .collect_exports(&mut Vec::new(), &mut exports);
+        for export in exports.iter_mut() {
+            if let Some(func) = export.func {
+                export.params = func.params(&store);
+                export.results = func.results(&store);
+            }
+        }
+        exports
+    }
+
+    /// Arranges for a [`Snapshot`](crate::Snapshot) to be taken after every
+    /// `every_n_calls` calls to the exported function `export_name`, and
+    /// passed to `handler`.
+    ///
+    /// The snapshot captures the core instance defining the function, as
+    /// [`TypedFunc::call_and_snapshot_async`] does, and is taken at the end
+    /// of [`Func::post_return`] or [`Func::post_return_async`], once the
+    /// instance can be entered again. Calls whose post-return is not
+    /// completed, for example because they trapped, are not counted.
+    ///
+    /// Calling this again for the same export replaces its handler and
+    /// restarts the count.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `export_name` is not an exported function of this
+    /// instance, if it is not defined by a core instance, for example because
+    /// it re-exports a lowered host function, or if `every_n_calls` is zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn enable_auto_snapshot(
+        &self,
+        mut store: impl AsContextMut,
+        export_name: &str,
+        every_n_calls: u32,
+        handler: impl Fn(crate::Snapshot) + Send + Sync + 'static,
+    ) -> Result<()> {
+        let mut store = store.as_context_mut();
+        if every_n_calls == 0 {
+            bail!("automatic snapshots must be taken every one or more calls");
+        }
+        let func = self
+            .get_func(&mut store, export_name)
+            .ok_or_else(|| anyhow!("failed to find function export `{export_name}`"))?;
+        if func.core_instance(&store).is_none() {
+            bail!("function export `{export_name}` is not defined by a core instance");
+        }
+        let key = func.auto_snapshot_key(&store);
+        store.0[self.0].as_mut().unwrap().auto_snapshots.insert(
+            key,
+            AutoSnapshot {
+                every_n_calls,
+                calls: 0,
+                handler: Arc::new(handler),
+            },
+        );
+        Ok(())
+    }
 }
 
 impl InstanceData {
+    /// Counts a completed call to the function identified by `key` and
+    /// returns the handler to pass a snapshot to if one is due.
+    pub(crate) fn auto_snapshot_due(
+        &mut self,
+        key: usize,
+    ) -> Option<Arc<dyn Fn(crate::Snapshot) + Send + Sync>> {
+        let auto = self.auto_snapshots.get_mut(&key)?;
+        auto.calls += 1;
+        if auto.calls < auto.every_n_calls {
+            return None;
+        }
+        auto.calls = 0;
+        Some(auto.handler.clone())
+    }
+
     pub fn lookup_def(&self, store: &mut StoreOpaque, def: &CoreDef) -> wasmtime_runtime::Export {
         match def {
             CoreDef::Export(e) => self.lookup_export(store, e),
@@ -166,6 +272,15 @@ impl InstanceData {
         }
     }
 
+    /// Returns the core instance defining `def`, or `None` if it is not
+    /// defined by a core instance.
+    pub fn core_instance(&self, def: &CoreDef) -> Option<crate::Instance> {
+        match def {
+            CoreDef::Export(e) => Some(self.instances[e.instance]),
+            CoreDef::Trampoline(_) | CoreDef::InstanceFlags(_) => None,
+        }
+    }
+
     pub fn lookup_export<T>(
         &self,
         store: &mut StoreOpaque,
@@ -283,6 +398,7 @@ impl<'a> Instantiator<'a> {
                     store.traitobj(),
                 ),
                 imports: imports.clone(),
+                auto_snapshots: HashMap::new(),
             },
         }
     }
@@ -517,6 +633,10 @@ impl<'a> Instantiator<'a> {
 /// type is created. This type is primarily created through the
 /// [`Linker::instantiate_pre`](crate::component::Linker::instantiate_pre)
 /// method.
+///
+/// Cloning an `InstancePre` is cheap since the component and the resolved
+/// imports are shared, so one can be created up front and a clone handed to
+/// each worker which instantiates the component on demand.
 pub struct InstancePre<T> {
     component: Component,
     imports: Arc<PrimaryMap<RuntimeImportIndex, RuntimeImport>>,
@@ -766,6 +886,32 @@ impl<'a, 'store> ExportInstance<'a, 'store> {
         })
     }
 
+    /// Appends the exports of this instance, and recursively those of the
+    /// instances it exports, to `exports`, with `path` leading to this
+    /// instance.
+    fn collect_exports(&mut self, path: &mut Vec<String>, exports: &mut Vec<TypedExport>) {
+        for (name, export) in self.exports {
+            path.push(name.clone());
+            let (kind, func) = match export {
+                Export::LiftedFunction { .. } => (ExportKind::Function, self.func(name)),
+                Export::ModuleStatic(_) | Export::ModuleImport(_) => (ExportKind::Module, None),
+                Export::Instance(_) => (ExportKind::Instance, None),
+                Export::Type(_) => (ExportKind::Type, None),
+            };
+            exports.push(TypedExport {
+                path: path.clone(),
+                kind,
+                params: Box::new([]),
+                results: Box::new([]),
+                func,
+            });
+            if let Some(mut instance) = self.instance(name) {
+                instance.collect_exports(path, exports);
+            }
+            path.pop();
+        }
+    }
+
     fn as_mut(&mut self) -> ExportInstance<'a, '_> {
         ExportInstance {
             exports: self.exports,
@@ -795,3 +941,41 @@ impl<'a, 'store> ExportInstance<'a, 'store> {
         }
     }
 }
+
+/// The kind of an export listed by [`Instance::list_all_exports`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ExportKind {
+    /// A function, which [`TypedExport::as_func`] returns.
+    Function,
+    /// An instance, whose exports are listed after it.
+    Instance,
+    /// A core wasm module.
+    Module,
+    /// A type, such as a resource.
+    Type,
+}
+
+/// An export of an [`Instance`], as listed by [`Instance::list_all_exports`].
+#[derive(Clone, Debug)]
+pub struct TypedExport {
+    /// The names leading to the export, starting with the name of the
+    /// instance it belongs to for exports of exported instances, and ending
+    /// with its own name.
+    pub path: Vec<String>,
+    /// The kind of the export.
+    pub kind: ExportKind,
+    /// The types of the parameters of a function, and empty for other kinds
+    /// of exports.
+    pub params: Box<[Type]>,
+    /// The types of the results of a function, and empty for other kinds of
+    /// exports.
+    pub results: Box<[Type]>,
+    func: Option<Func>,
+}
+
+impl TypedExport {
+    /// Returns the exported function, if this export is a function.
+    pub fn as_func(&self) -> Option<Func> {
+        self.func
+    }
+}
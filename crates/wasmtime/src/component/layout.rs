@@ -0,0 +1,104 @@
+use crate::component::Component;
+use crate::{GlobalType, MemoryType};
+use anyhow::{bail, Result};
+use wasmtime_environ::component::{GlobalInitializer, InstantiateModule};
+
+/// The memories and globals of the core instances of a [`Component`],
+/// returned by [`Component::static_memory_layout`].
+///
+/// Core instances are numbered in the order the component instantiates them,
+/// which is the same for every instance of the component. Their memories and
+/// globals are listed in instance order and, within an instance, in index
+/// order, including the ones imported from other core instances.
+#[derive(Clone, Debug)]
+pub struct ComponentMemoryLayout {
+    /// The memories of every core instance.
+    pub memories: Vec<MemoryInfo>,
+    /// The globals of every core instance.
+    pub globals: Vec<GlobalInfo>,
+}
+
+/// A memory of a core instance of a component, as listed in a
+/// [`ComponentMemoryLayout`].
+#[derive(Clone, Debug)]
+pub struct MemoryInfo {
+    /// The core instance the memory belongs to.
+    pub instance: usize,
+    /// The index of the memory in the memory index space of its module.
+    pub index: u32,
+    /// The type of the memory, including its minimum and maximum number of
+    /// pages.
+    pub ty: MemoryType,
+    /// Whether the memory is imported from another core instance rather
+    /// than defined by its module.
+    pub imported: bool,
+}
+
+/// A global of a core instance of a component, as listed in a
+/// [`ComponentMemoryLayout`].
+#[derive(Clone, Debug)]
+pub struct GlobalInfo {
+    /// The core instance the global belongs to.
+    pub instance: usize,
+    /// The index of the global in the global index space of its module.
+    pub index: u32,
+    /// The type of the global, including whether it is mutable.
+    pub ty: GlobalType,
+    /// Whether the global is imported from another core instance rather
+    /// than defined by its module.
+    pub imported: bool,
+}
+
+impl Component {
+    /// Returns the memories and globals of the core instances this
+    /// component creates, without instantiating it.
+    ///
+    /// This is the static structure a [`Snapshot`](crate::Snapshot) of one of
+    /// its core instances has to match, for tools which migrate snapshots
+    /// between versions of a component.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the component instantiates a core module it
+    /// imports, whose layout is only known once the component is
+    /// instantiated.
+    pub fn static_memory_layout(&self) -> Result<ComponentMemoryLayout> {
+        let mut layout = ComponentMemoryLayout {
+            memories: Vec::new(),
+            globals: Vec::new(),
+        };
+        let mut instance = 0;
+        for init in &self.env_component().initializers {
+            let index = match init {
+                GlobalInitializer::InstantiateModule(InstantiateModule::Static(index, _)) => index,
+                GlobalInitializer::InstantiateModule(InstantiateModule::Import(..)) => {
+                    bail!("core instance {instance} is created from an imported module")
+                }
+                GlobalInitializer::LowerImport { .. }
+                | GlobalInitializer::ExtractMemory(_)
+                | GlobalInitializer::ExtractRealloc(_)
+                | GlobalInitializer::ExtractPostReturn(_)
+                | GlobalInitializer::Resource(_) => continue,
+            };
+            let env = self.static_module(*index).env_module();
+            for (index, plan) in env.memory_plans.iter() {
+                layout.memories.push(MemoryInfo {
+                    instance,
+                    index: index.as_u32(),
+                    ty: MemoryType::from_wasmtime_memory(&plan.memory),
+                    imported: env.is_imported_memory(index),
+                });
+            }
+            for (index, global) in env.globals.iter() {
+                layout.globals.push(GlobalInfo {
+                    instance,
+                    index: index.as_u32(),
+                    ty: GlobalType::from_wasmtime_global(global),
+                    imported: env.is_imported_global(index),
+                });
+            }
+            instance += 1;
+        }
+        Ok(layout)
+    }
+}
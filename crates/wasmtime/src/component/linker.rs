@@ -4,7 +4,7 @@ use crate::component::matching::TypeChecker;
 use crate::component::{
     Component, ComponentNamedList, Instance, InstancePre, Lift, Lower, ResourceType, Val,
 };
-use crate::{AsContextMut, Engine, Module, StoreContextMut};
+use crate::{AsContextMut, Engine, Module, StoreContextMut, SuspendState};
 use anyhow::{anyhow, bail, Context, Result};
 use indexmap::IndexMap;
 use std::collections::hash_map::{Entry, HashMap};
@@ -28,6 +28,7 @@ pub struct Linker<T> {
     map: NameMap,
     path: Vec<usize>,
     allow_shadowing: bool,
+    suspendables: Suspendables,
     _marker: marker::PhantomData<fn() -> T>,
 }
 
@@ -39,6 +40,7 @@ impl<T> Clone for Linker<T> {
             map: self.map.clone(),
             path: self.path.clone(),
             allow_shadowing: self.allow_shadowing,
+            suspendables: self.suspendables.clone(),
             _marker: self._marker,
         }
     }
@@ -62,9 +64,108 @@ pub struct LinkerInstance<'a, T> {
     strings: &'a mut Strings,
     map: &'a mut NameMap,
     allow_shadowing: bool,
+    suspendables: &'a mut Suspendables,
     _marker: marker::PhantomData<fn() -> T>,
 }
 
+/// A host function which can save its state alongside a
+/// [`Snapshot`](crate::Snapshot) and pick it up again after a restore.
+///
+/// Registered with [`LinkerInstance::func_wrap_suspendable`]. Calls from wasm
+/// go to [`SuspendableHostFn::call`] exactly like a function registered with
+/// [`LinkerInstance::func_wrap`]. When a snapshot is taken the embedder
+/// collects the states of all suspendable functions with
+/// [`Linker::suspend_states`] and attaches them with
+/// [`Snapshot::with_host_states`](crate::Snapshot::with_host_states). On
+/// restore the states are handed back with [`Linker::resume_states`].
+pub trait SuspendableHostFn<T, Params, Return>: Send + Sync + 'static {
+    /// Invoked when the function is called from wasm.
+    fn call(&self, store: StoreContextMut<'_, T>, params: Params) -> Result<Return>;
+
+    /// Returns the state to save alongside a snapshot, or `None` if there is
+    /// nothing to save.
+    fn suspend(&self) -> Option<SuspendState>;
+
+    /// Restores a state previously returned by
+    /// [`SuspendableHostFn::suspend`].
+    fn resume(&self, state: SuspendState) -> Result<()>;
+}
+
+/// Same as [`SuspendableHostFn`], but for async host functions registered
+/// with [`LinkerInstance::func_wrap_suspendable_async`].
+///
+/// This allows a host function which awaits an external operation, such as
+/// a database query, to save the progress of that operation. A snapshot can
+/// only be taken while wasm is not running, so the states are collected
+/// with [`Linker::suspend_states`] either between calls or from within a
+/// call, for example from an epoch deadline callback, and the function is
+/// responsible for tracking what it has in flight.
+#[cfg(feature = "async")]
+#[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+pub trait SuspendableAsyncHostFn<T, Params, Return>: Send + Sync + 'static {
+    /// Invoked when the function is called from wasm.
+    fn call<'a>(
+        &'a self,
+        store: StoreContextMut<'a, T>,
+        params: Params,
+    ) -> Box<dyn Future<Output = Result<Return>> + Send + 'a>;
+
+    /// Returns the state to save alongside a snapshot, or `None` if there is
+    /// nothing to save.
+    fn suspend(&self) -> Option<SuspendState>;
+
+    /// Restores a state previously returned by
+    /// [`SuspendableAsyncHostFn::suspend`].
+    fn resume(&self, state: SuspendState) -> Result<()>;
+}
+
+/// The suspendable functions defined in a [`Linker`], keyed by their path.
+type Suspendables = Vec<(String, Arc<dyn Suspendable>)>;
+
+/// Object-safe view of a [`SuspendableHostFn`], erasing its type parameters.
+trait Suspendable: Send + Sync {
+    fn suspend(&self) -> Option<SuspendState>;
+    fn resume(&self, state: SuspendState) -> Result<()>;
+}
+
+struct SuspendableFunc<F, T, Params, Return> {
+    func: Arc<F>,
+    _marker: marker::PhantomData<fn(T, Params) -> Return>,
+}
+
+impl<F, T, Params, Return> Suspendable for SuspendableFunc<F, T, Params, Return>
+where
+    F: SuspendableHostFn<T, Params, Return>,
+{
+    fn suspend(&self) -> Option<SuspendState> {
+        self.func.suspend()
+    }
+
+    fn resume(&self, state: SuspendState) -> Result<()> {
+        self.func.resume(state)
+    }
+}
+
+#[cfg(feature = "async")]
+struct SuspendableAsyncFunc<F, T, Params, Return> {
+    func: Arc<F>,
+    _marker: marker::PhantomData<fn(T, Params) -> Return>,
+}
+
+#[cfg(feature = "async")]
+impl<F, T, Params, Return> Suspendable for SuspendableAsyncFunc<F, T, Params, Return>
+where
+    F: SuspendableAsyncHostFn<T, Params, Return>,
+{
+    fn suspend(&self) -> Option<SuspendState> {
+        self.func.suspend()
+    }
+
+    fn resume(&self, state: SuspendState) -> Result<()> {
+        self.func.resume(state)
+    }
+}
+
 pub(crate) type NameMap = HashMap<usize, Definition>;
 
 #[derive(Clone)]
@@ -85,6 +186,7 @@ impl<T> Linker<T> {
             map: NameMap::default(),
             allow_shadowing: false,
             path: Vec::new(),
+            suspendables: Vec::new(),
             _marker: marker::PhantomData,
         }
     }
@@ -113,10 +215,45 @@ impl<T> Linker<T> {
             strings: &mut self.strings,
             map: &mut self.map,
             allow_shadowing: self.allow_shadowing,
+            suspendables: &mut self.suspendables,
             _marker: self._marker,
         }
     }
 
+    /// Returns the states of all functions defined with
+    /// [`LinkerInstance::func_wrap_suspendable`] or
+    /// `LinkerInstance::func_wrap_suspendable_async` which have something to
+    /// save, keyed by the path of the function.
+    ///
+    /// The path is the name of the function prefixed by the names of its
+    /// enclosing instances, for example `wasi:clocks/wall-clock#now`.
+    pub fn suspend_states(&self) -> Vec<(String, SuspendState)> {
+        self.suspendables
+            .iter()
+            .filter_map(|(path, func)| Some((path.clone(), func.suspend()?)))
+            .collect()
+    }
+
+    /// Hands the `states` returned by [`Linker::suspend_states`] back to the
+    /// resume path of their suspendable functions.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a state refers to a function which is not defined
+    /// in this linker, or if a function fails to resume.
+    pub fn resume_states(&self, states: &[(String, SuspendState)]) -> Result<()> {
+        for (path, state) in states {
+            let (_, func) = self
+                .suspendables
+                .iter()
+                .find(|(p, _)| p == path)
+                .ok_or_else(|| anyhow!("no suspendable function `{path}` in this linker"))?;
+            func.resume(state.clone())
+                .with_context(|| format!("failed to resume `{path}`"))?;
+        }
+        Ok(())
+    }
+
     /// Returns a builder for the named instance specified.
     ///
     /// # Errors
@@ -267,6 +404,7 @@ impl<T> LinkerInstance<'_, T> {
             strings: self.strings,
             map: self.map,
             allow_shadowing: self.allow_shadowing,
+            suspendables: self.suspendables,
             _marker: self._marker,
         }
     }
@@ -299,6 +437,91 @@ impl<T> LinkerInstance<'_, T> {
         self.insert(name, Definition::Func(HostFunc::from_closure(func)))
     }
 
+    /// Defines a new host-provided function whose state can be saved
+    /// alongside a [`Snapshot`](crate::Snapshot).
+    ///
+    /// This is exactly like [`Self::func_wrap`], except that the state of
+    /// `func` is reported by [`Linker::suspend_states`] and restored by
+    /// [`Linker::resume_states`]. See [`SuspendableHostFn`] for details.
+    pub fn func_wrap_suspendable<F, Params, Return>(&mut self, name: &str, func: F) -> Result<()>
+    where
+        F: SuspendableHostFn<T, Params, Return>,
+        T: 'static,
+        Params: ComponentNamedList + Lift + 'static,
+        Return: ComponentNamedList + Lower + 'static,
+    {
+        let func = Arc::new(func);
+        let path = self.suspendable_path(name);
+        let f = func.clone();
+        self.func_wrap(name, move |store, params| f.call(store, params))?;
+        self.insert_suspendable(
+            path,
+            Arc::new(SuspendableFunc {
+                func,
+                _marker: marker::PhantomData,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Same as [`Self::func_wrap_suspendable`], but for an async host
+    /// function, as with [`Self::func_wrap_async`]. See
+    /// [`SuspendableAsyncHostFn`] for details.
+    #[cfg(feature = "async")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+    pub fn func_wrap_suspendable_async<F, Params, Return>(
+        &mut self,
+        name: &str,
+        func: F,
+    ) -> Result<()>
+    where
+        F: SuspendableAsyncHostFn<T, Params, Return>,
+        T: 'static,
+        Params: ComponentNamedList + Lift + 'static,
+        Return: ComponentNamedList + Lower + 'static,
+    {
+        assert!(
+            self.engine.config().async_support,
+            "cannot use `func_wrap_suspendable_async` without enabling async support in the config"
+        );
+        let func = Arc::new(func);
+        let path = self.suspendable_path(name);
+        let f = func.clone();
+        let ff = move |mut store: StoreContextMut<'_, T>, params: Params| -> Result<Return> {
+            let async_cx = store.as_context_mut().0.async_cx().expect("async cx");
+            let mut future = Pin::from(f.call(store.as_context_mut(), params));
+            unsafe { async_cx.block_on(future.as_mut()) }?
+        };
+        self.func_wrap(name, ff)?;
+        self.insert_suspendable(
+            path,
+            Arc::new(SuspendableAsyncFunc {
+                func,
+                _marker: marker::PhantomData,
+            }),
+        );
+        Ok(())
+    }
+
+    fn insert_suspendable(&mut self, path: String, entry: Arc<dyn Suspendable>) {
+        match self.suspendables.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, slot)) => *slot = entry,
+            None => self.suspendables.push((path, entry)),
+        }
+    }
+
+    fn suspendable_path(&self, name: &str) -> String {
+        let instances = self.path[..self.path_len]
+            .iter()
+            .map(|i| self.strings.strings[*i].deref())
+            .collect::<Vec<_>>();
+        if instances.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}#{name}", instances.join("/"))
+        }
+    }
+
     /// Defines a new host-provided async function into this [`Linker`].
     ///
     /// This is exactly like [`Self::func_wrap`] except it takes an async
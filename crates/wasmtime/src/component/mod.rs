@@ -8,6 +8,7 @@
 mod component;
 mod func;
 mod instance;
+mod layout;
 mod linker;
 mod matching;
 mod resources;
@@ -19,8 +20,11 @@ pub use self::component::Component;
 pub use self::func::{
     ComponentNamedList, ComponentType, Func, Lift, Lower, TypedFunc, WasmList, WasmStr,
 };
-pub use self::instance::{ExportInstance, Exports, Instance, InstancePre};
-pub use self::linker::{Linker, LinkerInstance};
+pub use self::instance::{ExportInstance, ExportKind, Exports, Instance, InstancePre, TypedExport};
+pub use self::layout::{ComponentMemoryLayout, GlobalInfo, MemoryInfo};
+#[cfg(feature = "async")]
+pub use self::linker::SuspendableAsyncHostFn;
+pub use self::linker::{Linker, LinkerInstance, SuspendableHostFn};
 pub use self::resources::{Resource, ResourceAny};
 pub use self::types::{ResourceType, Type};
 pub use self::values::{Enum, Flags, List, OptionVal, Record, ResultVal, Tuple, Val, Variant};
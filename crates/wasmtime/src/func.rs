@@ -1347,6 +1347,16 @@ impl Func {
     pub(crate) fn hash_key(&self, store: &mut StoreOpaque) -> impl std::hash::Hash + Eq {
         self.vm_func_ref(store).as_ptr() as usize
     }
+
+    /// Returns whether `self` and `other` refer to the same entry in a store's
+    /// data, without needing access to the store.
+    ///
+    /// Note that the same underlying function may be added to the
+    /// `StoreData` multiple times, so a `false` result does not imply that
+    /// the functions differ. Use [`Func::hash_key`] when a store is at hand.
+    pub(crate) fn same_store_entry(&self, other: &Func) -> bool {
+        self.0 == other.0
+    }
 }
 
 /// Prepares for entrance into WebAssembly.
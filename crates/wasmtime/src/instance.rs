@@ -3,7 +3,7 @@ use crate::store::{InstanceId, StoreOpaque, Stored};
 use crate::types::matching;
 use crate::{
     AsContextMut, Engine, Export, Extern, Func, Global, Memory, Module, SharedMemory, StoreContext,
-    StoreContextMut, Table, TypedFunc,
+    StoreContextMut, Table, TypedFunc, Val,
 };
 use anyhow::{anyhow, bail, Context, Result};
 use std::mem;
@@ -543,6 +543,53 @@ impl Instance {
         self.get_export(store, name)?.into_global()
     }
 
+    /// Reads the value of the exported global named `name`.
+    ///
+    /// This is a shorthand for [`Instance::get_global`] followed by
+    /// [`Global::get`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming `name` if there is no exported global of that
+    /// name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn read_global(&self, mut store: impl AsContextMut, name: &str) -> Result<Val> {
+        let global = self
+            .get_global(&mut store, name)
+            .ok_or_else(|| anyhow!("no exported global named `{name}`"))?;
+        Ok(global.get(&mut store))
+    }
+
+    /// Writes `value` to the exported global named `name`.
+    ///
+    /// This is a shorthand for [`Instance::get_global`] followed by
+    /// [`Global::set`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming `name` if there is no exported global of that
+    /// name, or if [`Global::set`] fails because the global is immutable or
+    /// `value` has the wrong type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn write_global(&self, mut store: impl AsContextMut, name: &str, value: Val) -> Result<()> {
+        let global = self
+            .get_global(&mut store, name)
+            .ok_or_else(|| anyhow!("no exported global named `{name}`"))?;
+        global
+            .set(&mut store, value)
+            .with_context(|| format!("failed to write global `{name}`"))
+    }
+
+    pub(crate) fn comes_from_same_store(&self, store: &StoreOpaque) -> bool {
+        store.store_data().contains(self.0)
+    }
+
     #[cfg(feature = "component-model")]
     pub(crate) fn id(&self, store: &StoreOpaque) -> InstanceId {
         store[self.0].id
@@ -858,6 +905,107 @@ impl<T> InstancePre<T> {
         // in match the module we're instantiating.
         unsafe { Instance::new_started_async(&mut store, &self.module, imports.as_ref()).await }
     }
+
+    /// Creates a new instance whose memories and globals are set from
+    /// `snapshot`, without running the start function.
+    ///
+    /// This is the synchronous counterpart of
+    /// [`InstancePre::instantiate_from_snapshot_async`]; see its
+    /// documentation for more details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if instantiation fails, if `snapshot` cannot be
+    /// restored into the new instance, or if restoring a host state fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any import closed over by this [`InstancePre`] isn't owned by
+    /// `store`, or if `store` has async support enabled.
+    pub fn instantiate_from_snapshot(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        snapshot: &crate::Snapshot,
+    ) -> Result<Instance> {
+        let mut store = store.as_context_mut();
+        assert!(
+            !store.0.async_support(),
+            "must use async instantiation when async support is enabled",
+        );
+        let imports = pre_instantiate_raw(
+            store.0,
+            &self.module,
+            &self.items,
+            self.host_funcs,
+            &self.func_refs,
+        )?;
+
+        // This unsafety should be handled by the type-checking performed by
+        // the constructor of `InstancePre`.
+        let (instance, _start) =
+            unsafe { Instance::new_raw(store.0, &self.module, imports.as_ref())? };
+        instance.restore(&mut store, snapshot)?;
+        Ok(instance)
+    }
+
+    /// Creates a new instance whose memories and globals are set from
+    /// `snapshot`, without running the start function.
+    ///
+    /// This allows starting many instances from a [`Snapshot`](crate::Snapshot) taken after
+    /// an expensive initialization, rather than repeating the initialization
+    /// for each of them. The instance is created as with
+    /// [`InstancePre::instantiate_async`], but instead of invoking the start
+    /// function, `snapshot` is restored into it as with [`Instance::restore`],
+    /// which includes restoring the host states registered with
+    /// [`Store::snapshot_host_state`](crate::Store::snapshot_host_state).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if instantiation fails or if `snapshot` cannot be
+    /// restored into the new instance, for example because it was taken
+    /// from an instance of another module, or if restoring a host state
+    /// fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any import closed over by this [`InstancePre`] isn't owned by
+    /// `store`, or if `store` does not have async support enabled.
+    #[cfg(feature = "async")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+    pub async fn instantiate_from_snapshot_async(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        snapshot: &crate::Snapshot,
+    ) -> Result<Instance>
+    where
+        T: Send,
+    {
+        let mut store = store.as_context_mut();
+        assert!(
+            store.0.async_support(),
+            "must use sync instantiation when async support is disabled",
+        );
+        let imports = pre_instantiate_raw(
+            store.0,
+            &self.module,
+            &self.items,
+            self.host_funcs,
+            &self.func_refs,
+        )?;
+
+        // Memories may have to be grown, which needs to happen on a fiber in
+        // case the store has an async resource limiter.
+        store
+            .on_fiber(|store| -> Result<Instance> {
+                // This unsafety should be handled by the type-checking
+                // performed by the constructor of `InstancePre`.
+                let (instance, _start) =
+                    unsafe { Instance::new_raw(store.0, &self.module, imports.as_ref())? };
+                instance.restore(store, snapshot)?;
+                Ok(instance)
+            })
+            .await?
+    }
 }
 
 /// Helper function shared between
@@ -288,6 +288,19 @@
 //!   run-time via [`Config::memory_init_cow`] (which is also enabled by
 //!   default).
 //!
+//! * `snapshot-encryption` - Not enabled by default. This feature adds
+//!   [`Snapshot::encrypt`] for encrypting snapshots with AES-256-GCM before
+//!   storing them.
+//!
+//! * `snapshot-hash` - Not enabled by default. This feature adds
+//!   [`Snapshot::compute_hash`] and the [`SnapshotStore`]s keyed by it,
+//!   [`Snapshot::into_chunks`] and [`Snapshot::export_memory_to_file`], which
+//!   hash the contents of snapshots with Blake3.
+//!
+//! * `snapshot-msgpack` - Not enabled by default. This feature adds
+//!   [`Snapshot::to_msgpack`] and [`Snapshot::from_msgpack`] for storing
+//!   snapshots in a documented MessagePack format.
+//!
 //! ## Examples
 //!
 //! In addition to the examples below be sure to check out the [online embedding
@@ -402,11 +415,13 @@ mod instance;
 mod limits;
 mod linker;
 mod memory;
+mod memory_inspector;
 mod module;
 mod profiling;
 mod r#ref;
 mod resources;
 mod signatures;
+mod snapshot;
 mod store;
 mod trampoline;
 mod trap;
@@ -426,10 +441,12 @@ pub use crate::instance::{Instance, InstancePre};
 pub use crate::limits::*;
 pub use crate::linker::*;
 pub use crate::memory::*;
+pub use crate::memory_inspector::MemoryInspector;
 pub use crate::module::Module;
 pub use crate::profiling::GuestProfiler;
 pub use crate::r#ref::ExternRef;
 pub use crate::resources::*;
+pub use crate::snapshot::*;
 #[cfg(feature = "async")]
 pub use crate::store::CallHookHandler;
 pub use crate::store::{
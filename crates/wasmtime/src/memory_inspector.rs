@@ -0,0 +1,131 @@
+use crate::{AsContextMut, Instance, Store};
+use anyhow::{anyhow, bail, Result};
+use std::ops::Range;
+
+/// Helpers for reading and writing the linear memories of an [`Instance`]
+/// directly from a [`Store`], mostly useful for debugging and testing.
+///
+/// Memories are identified by their index in the instance's memory index
+/// space, including imported memories. Shared memories are not supported.
+/// All accesses are bounds-checked against the current size of the memory.
+pub trait MemoryInspector {
+    /// Reads a little-endian `u32` at `offset` of memory `memory_index`.
+    fn read_u32_le(&mut self, instance: &Instance, memory_index: u32, offset: usize)
+        -> Result<u32>;
+
+    /// Copies the bytes in `range` of memory `memory_index`.
+    fn read_bytes(
+        &mut self,
+        instance: &Instance,
+        memory_index: u32,
+        range: Range<usize>,
+    ) -> Result<Vec<u8>>;
+
+    /// Reads a nul-terminated UTF-8 string starting at `offset` of memory
+    /// `memory_index`, looking at no more than `max_len` bytes.
+    ///
+    /// Fails if no nul byte is found within `max_len` bytes or the end of the
+    /// memory, or if the string is not valid UTF-8.
+    fn read_cstring(
+        &mut self,
+        instance: &Instance,
+        memory_index: u32,
+        offset: usize,
+        max_len: usize,
+    ) -> Result<String>;
+
+    /// Writes `value` as a little-endian `u32` at `offset` of memory
+    /// `memory_index`.
+    fn write_u32_le(
+        &mut self,
+        instance: &Instance,
+        memory_index: u32,
+        offset: usize,
+        value: u32,
+    ) -> Result<()>;
+
+    /// Writes `data` at `offset` of memory `memory_index`.
+    fn write_bytes(
+        &mut self,
+        instance: &Instance,
+        memory_index: u32,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()>;
+}
+
+impl<T> MemoryInspector for Store<T> {
+    fn read_u32_le(
+        &mut self,
+        instance: &Instance,
+        memory_index: u32,
+        offset: usize,
+    ) -> Result<u32> {
+        let bytes = self.read_bytes(instance, memory_index, offset..offset.saturating_add(4))?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_bytes(
+        &mut self,
+        instance: &Instance,
+        memory_index: u32,
+        range: Range<usize>,
+    ) -> Result<Vec<u8>> {
+        let mut store = self.as_context_mut();
+        let memory = instance.memory_at(&mut store, memory_index)?;
+        memory
+            .data(&store)
+            .get(range.clone())
+            .map(|bytes| bytes.to_vec())
+            .ok_or_else(|| anyhow!("range {range:?} is out of bounds of memory {memory_index}"))
+    }
+
+    fn read_cstring(
+        &mut self,
+        instance: &Instance,
+        memory_index: u32,
+        offset: usize,
+        max_len: usize,
+    ) -> Result<String> {
+        let mut store = self.as_context_mut();
+        let memory = instance.memory_at(&mut store, memory_index)?;
+        let data = memory
+            .data(&store)
+            .get(offset..)
+            .ok_or_else(|| anyhow!("offset {offset} is out of bounds of memory {memory_index}"))?;
+        let data = &data[..data.len().min(max_len)];
+        let len = match data.iter().position(|b| *b == 0) {
+            Some(len) => len,
+            None => bail!("no nul terminator found within {max_len} bytes of offset {offset}"),
+        };
+        Ok(std::str::from_utf8(&data[..len])?.to_string())
+    }
+
+    fn write_u32_le(
+        &mut self,
+        instance: &Instance,
+        memory_index: u32,
+        offset: usize,
+        value: u32,
+    ) -> Result<()> {
+        self.write_bytes(instance, memory_index, offset, &value.to_le_bytes())
+    }
+
+    fn write_bytes(
+        &mut self,
+        instance: &Instance,
+        memory_index: u32,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        let mut store = self.as_context_mut();
+        let memory = instance.memory_at(&mut store, memory_index)?;
+        memory
+            .data_mut(&mut store)
+            .get_mut(offset..)
+            .and_then(|dst| dst.get_mut(..data.len()))
+            .ok_or_else(|| anyhow!("write of {} bytes at offset {offset} is out of bounds of memory {memory_index}", data.len()))?
+            .copy_from_slice(data);
+        Ok(())
+    }
+}
@@ -0,0 +1,1617 @@
+use crate::{
+    AsContext, AsContextMut, Instance, Memory, Module, Mutability, StoreContextMut, Val, ValType,
+};
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{ControlFlow, Range};
+use wasmtime_environ::EntityIndex;
+
+mod alloc;
+mod assert;
+#[cfg(feature = "snapshot-hash")]
+mod chunk;
+mod compat;
+mod coredump;
+#[cfg(feature = "component-model")]
+mod debug;
+mod delta;
+#[cfg(feature = "snapshot-encryption")]
+mod encryption;
+mod endian;
+mod external;
+mod gc;
+#[cfg(feature = "snapshot-hash")]
+mod hash;
+mod hexdump;
+mod invariants;
+mod io;
+#[cfg(feature = "snapshot-msgpack")]
+mod msgpack;
+#[cfg(feature = "async")]
+mod replay;
+mod serialize;
+mod shard;
+#[cfg(all(feature = "async", feature = "snapshot-hash"))]
+mod store;
+mod strip;
+mod suspend;
+
+pub use self::alloc::{AllocationRecord, WasmAllocator};
+#[cfg(feature = "snapshot-hash")]
+pub use self::chunk::SnapshotChunk;
+pub use self::compat::{CompatibilityReport, SnapshotCompatibility};
+pub use self::delta::{DeltaConfig, SnapshotDelta};
+#[cfg(feature = "snapshot-encryption")]
+pub use self::encryption::EncryptedSnapshot;
+pub use self::endian::{Endianness, WordSize};
+pub use self::gc::GcRemapping;
+#[cfg(feature = "snapshot-hash")]
+pub use self::hash::SnapshotHash;
+pub use self::hexdump::AnnotationMap;
+pub use self::invariants::MemoryInvariant;
+pub use self::io::{SnapshotMemoryReader, SnapshotMemoryWriter};
+#[cfg(feature = "async")]
+pub use self::replay::{CallLog, CallLogEntry, CallLogRecorder, CallResult, ReplayDivergence};
+pub use self::shard::SnapshotShard;
+#[cfg(all(feature = "async", feature = "snapshot-hash"))]
+pub use self::store::{FileSnapshotStore, MemorySnapshotStore, SnapshotMeta, SnapshotStore};
+pub use self::suspend::SuspendedInstance;
+
+/// A point-in-time copy of the linear memories and globals of an
+/// [`Instance`].
+///
+/// Snapshots are created with [`Instance::snapshot`] and can later be written
+/// back into an instance of the same module with [`Instance::restore`]. Only
+/// the state which is observable to the wasm module is captured: the contents
+/// of each linear memory, including imported ones, and the value of each
+/// global. Tables, the call stack, and host state living in the store's `T`
+/// are not part of a snapshot. Neither are host operations in flight, such
+/// as outgoing HTTP requests, which the guest has to make again after the
+/// snapshot is restored.
+///
+/// Memories and globals are identified by their index within the module's
+/// memory and global index spaces, respectively.
+///
+/// Host state which should be saved alongside the wasm state, such as the
+/// [`SuspendState`]s of suspendable component host functions, can be
+/// attached with [`Snapshot::with_host_states`], and metadata for the
+/// embedder, such as the version of the component, with
+/// [`Snapshot::with_annotation`].
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    memories: Vec<SnapshotMemory>,
+    globals: Vec<Val>,
+    host_states: Vec<(String, SuspendState)>,
+    annotations: HashMap<String, String>,
+}
+
+/// Opaque host-side state saved alongside a [`Snapshot`], such as the
+/// progress of an in-flight host call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SuspendState {
+    data: Vec<u8>,
+}
+
+impl SuspendState {
+    /// Creates a new state holding `data`.
+    pub fn new(data: Vec<u8>) -> SuspendState {
+        SuspendState { data }
+    }
+
+    /// Returns the data held by this state.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SnapshotMemory {
+    index: u32,
+    name: String,
+    /// The contents of the memory, empty if it is stored in a file.
+    data: Vec<u8>,
+    /// Where the contents are stored if they were moved out of the snapshot
+    /// with [`Snapshot::export_memory_to_file`].
+    external: Option<external::ExternalMemory>,
+}
+
+/// Size information about a single linear memory of a [`Snapshot`] or an
+/// [`Instance`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MemoryRegionInfo {
+    /// The export name of this memory, or `memory{N}` where `N` is the index
+    /// of the memory if it is not exported.
+    pub name: String,
+    /// The number of bytes this memory occupies.
+    pub size_bytes: usize,
+}
+
+/// A view of the contents of one linear memory of a [`Snapshot`], returned
+/// by [`Snapshot::memory_regions`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRegion<'a> {
+    /// The index of this memory within the module's memory index space.
+    pub index: u32,
+    /// The export name of this memory, or `memory{N}` where `N` is the index
+    /// of the memory if it is not exported.
+    pub name: &'a str,
+    /// The contents of this memory.
+    pub data: &'a [u8],
+    /// The number of wasm pages needed to hold `data`.
+    pub minimum_pages: u64,
+}
+
+/// Statistics about one bucket of a memory, returned by
+/// [`Snapshot::memory_histogram`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BucketStats {
+    /// The byte offsets within the memory covered by this bucket.
+    pub byte_range: Range<usize>,
+    /// The number of bytes of this bucket which are not zero.
+    pub non_zero_bytes: u32,
+    /// The number of distinct byte values in this bucket, including zero.
+    pub distinct_values: u32,
+}
+
+/// An estimate of how large a [`Snapshot`] of an [`Instance`] would be.
+///
+/// Returned by [`Instance::snapshot_size_estimate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotSizeEstimate {
+    /// The total number of bytes the snapshot would occupy, including both
+    /// memories and globals.
+    pub total_bytes: usize,
+    /// Per-memory size information, in memory index order.
+    pub regions: Vec<MemoryRegionInfo>,
+}
+
+/// A copy of the mutable globals of an [`Instance`].
+///
+/// This is a lightweight alternative to a full [`Snapshot`] for modules which
+/// keep all of their interesting state in globals. Created with
+/// [`Instance::snapshot_globals_only`] and restored with
+/// [`Instance::restore_globals`].
+#[derive(Clone, Debug)]
+pub struct GlobalsSnapshot {
+    globals: Vec<(u32, Val)>,
+}
+
+impl GlobalsSnapshot {
+    /// Returns the index and value of each mutable global captured in this
+    /// snapshot, in global index order.
+    pub fn globals(&self) -> &[(u32, Val)] {
+        &self.globals
+    }
+}
+
+/// A copy of a contiguous range of bytes of one linear memory of an
+/// [`Instance`].
+///
+/// Created with [`Instance::snapshot_memory_range`] and restored with
+/// [`Instance::restore_memory_range`].
+#[derive(Clone, Debug)]
+pub struct MemoryRangeSnapshot {
+    memory_index: u32,
+    offset: usize,
+    data: Vec<u8>,
+}
+
+impl MemoryRangeSnapshot {
+    /// The index of the memory this range was copied from.
+    pub fn memory_index(&self) -> u32 {
+        self.memory_index
+    }
+
+    /// The byte offset within the memory at which this range starts.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The copied bytes.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// A set of writes to one memory of a [`Snapshot`], applied with
+/// [`Snapshot::apply_patch`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryPatch {
+    /// The index of the memory to write to.
+    pub memory_index: u32,
+    /// The writes to perform, as pairs of a byte offset and the bytes to
+    /// write there. Writes are applied in order, so later writes win where
+    /// they overlap.
+    pub writes: Vec<(usize, Vec<u8>)>,
+}
+
+/// A structured description of how two [`Snapshot`]s differ.
+///
+/// Created with [`Snapshot::diff_report`]. Only memories and globals which
+/// differ are included. The [`Display`](fmt::Display) implementation prints a
+/// compact human-readable summary.
+#[derive(Clone, Debug)]
+pub struct SnapshotDiffReport {
+    /// The memories which differ between the two snapshots.
+    pub memories: Vec<MemoryDiff>,
+    /// The globals which differ between the two snapshots.
+    pub globals: Vec<GlobalDiff>,
+}
+
+/// How one memory differs between two [`Snapshot`]s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryDiff {
+    /// The index of the memory.
+    pub memory_index: u32,
+    /// The name of the memory, as in [`MemoryRegionInfo::name`].
+    pub name: String,
+    /// The number of differing bytes. Bytes past the end of the shorter of
+    /// the two memories count as differing.
+    pub differing_bytes: usize,
+    /// The offset of the first differing byte.
+    pub first_difference: usize,
+}
+
+/// How one global differs between two [`Snapshot`]s.
+///
+/// A value is `None` if the global is absent from that snapshot.
+#[derive(Clone, Debug)]
+pub struct GlobalDiff {
+    /// The index of the global.
+    pub global_index: u32,
+    /// The value in the first snapshot.
+    pub a: Option<Val>,
+    /// The value in the second snapshot.
+    pub b: Option<Val>,
+}
+
+impl SnapshotDiffReport {
+    /// Returns whether the two snapshots were found to be identical.
+    pub fn is_identical(&self) -> bool {
+        self.memories.is_empty() && self.globals.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_identical() {
+            return write!(f, "snapshots are identical");
+        }
+        write!(
+            f,
+            "{} memories and {} globals differ",
+            self.memories.len(),
+            self.globals.len()
+        )?;
+        for m in self.memories.iter() {
+            write!(
+                f,
+                "\n  memory {} ({}): {} bytes differ, first at {:#x}",
+                m.memory_index, m.name, m.differing_bytes, m.first_difference
+            )?;
+        }
+        for g in self.globals.iter() {
+            write!(f, "\n  global {}: {:?} != {:?}", g.global_index, g.a, g.b)?;
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// Returns the number of bytes of wasm state held by this snapshot.
+    ///
+    /// This is the sum of the size of all memories plus the size of all
+    /// globals, and does not include bookkeeping overhead. Memories
+    /// [stored in files](Snapshot::export_memory_to_file) count with the size
+    /// of their contents.
+    pub fn size_bytes(&self) -> usize {
+        let memories: usize = self.memories.iter().map(|m| m.len()).sum();
+        let globals: usize = self.globals.iter().map(|g| val_type_size(&g.ty())).sum();
+        memories + globals
+    }
+
+    /// Compares two snapshots and reports where they diverge.
+    ///
+    /// Non-null references held in globals can only be compared
+    /// conservatively without a store, so two references to the same function
+    /// may be reported as differing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory of either snapshot is
+    /// [stored in a file](Snapshot::export_memory_to_file).
+    pub fn diff_report(a: &Snapshot, b: &Snapshot) -> Result<SnapshotDiffReport> {
+        a.ensure_internalized()?;
+        b.ensure_internalized()?;
+        let mut memories = Vec::new();
+        let count = a.memories.len().max(b.memories.len());
+        for i in 0..count {
+            let (ma, mb) = (a.memories.get(i), b.memories.get(i));
+            let (da, db) = (
+                ma.map(|m| &m.data[..]).unwrap_or(&[]),
+                mb.map(|m| &m.data[..]).unwrap_or(&[]),
+            );
+            let shared = da.len().min(db.len());
+            let mut differing_bytes = da.len().max(db.len()) - shared;
+            let mut first_difference = None;
+            for (offset, (x, y)) in da.iter().zip(db).enumerate() {
+                if x != y {
+                    differing_bytes += 1;
+                    first_difference.get_or_insert(offset);
+                }
+            }
+            if differing_bytes > 0 {
+                let m = ma.or(mb).unwrap();
+                memories.push(MemoryDiff {
+                    memory_index: m.index,
+                    name: m.name.clone(),
+                    differing_bytes,
+                    first_difference: first_difference.unwrap_or(shared),
+                });
+            }
+        }
+
+        let mut globals = Vec::new();
+        let count = a.globals.len().max(b.globals.len());
+        for i in 0..count {
+            let (ga, gb) = (a.globals.get(i), b.globals.get(i));
+            let same = match (ga, gb) {
+                (Some(x), Some(y)) => val_eq(x, y),
+                _ => false,
+            };
+            if !same {
+                globals.push(GlobalDiff {
+                    global_index: i as u32,
+                    a: ga.cloned(),
+                    b: gb.cloned(),
+                });
+            }
+        }
+
+        Ok(SnapshotDiffReport { memories, globals })
+    }
+
+    /// Returns a copy of this snapshot with the writes of `patch` applied.
+    ///
+    /// This allows external tooling, such as debuggers or migration scripts,
+    /// to modify saved state before it is restored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the patched memory does not exist in this snapshot
+    /// or if any write extends past the end of the memory. In that case no
+    /// write is applied.
+    pub fn apply_patch(&self, patch: MemoryPatch) -> Result<Snapshot> {
+        let mut snapshot = self.clone();
+        let memory = snapshot
+            .memories
+            .iter_mut()
+            .find(|m| m.index == patch.memory_index)
+            .ok_or_else(|| {
+                anyhow!(
+                    "memory {} does not exist in this snapshot",
+                    patch.memory_index
+                )
+            })?;
+        memory.ensure_internalized()?;
+        for (offset, bytes) in patch.writes.iter() {
+            let len = memory.data.len();
+            let dst = offset
+                .checked_add(bytes.len())
+                .and_then(|end| memory.data.get_mut(*offset..end))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "write of {} bytes at offset {offset:#x} is out of bounds of memory {} \
+                         ({len:#x} bytes)",
+                        bytes.len(),
+                        patch.memory_index,
+                    )
+                })?;
+            dst.copy_from_slice(bytes);
+        }
+        Ok(snapshot)
+    }
+
+    /// Returns a copy of this snapshot in which memory `memory_index` is
+    /// exactly `new_size_bytes` long.
+    ///
+    /// If the memory is larger it is truncated, and if it is smaller it is
+    /// padded with zeros. This allows restoring a snapshot into a new version
+    /// of a module which declares a smaller maximum memory size.
+    ///
+    /// Before truncating, `validate` is called with the bytes which would be
+    /// discarded and should return whether they can be dropped safely, for
+    /// example because they hold no live data. It is not called when the
+    /// memory is padded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot or if
+    /// `validate` rejects the truncation.
+    pub fn truncate_memory(
+        &self,
+        memory_index: u32,
+        new_size_bytes: usize,
+        validate: impl Fn(&[u8]) -> bool,
+    ) -> Result<Snapshot> {
+        let mut snapshot = self.clone();
+        let memory = snapshot
+            .memories
+            .iter_mut()
+            .find(|m| m.index == memory_index)
+            .ok_or_else(|| anyhow!("memory {memory_index} does not exist in this snapshot"))?;
+        memory.ensure_internalized()?;
+        if new_size_bytes < memory.data.len() && !validate(&memory.data[new_size_bytes..]) {
+            bail!(
+                "truncating memory {memory_index} to {new_size_bytes:#x} bytes would discard \
+                 live data"
+            );
+        }
+        memory.data.resize(new_size_bytes, 0);
+        Ok(snapshot)
+    }
+
+    /// Returns a copy of this snapshot in which memory `memory_index` is
+    /// extended to `new_size_bytes` by appending zeros.
+    ///
+    /// This allows restoring a snapshot into a new version of a module which
+    /// requires a larger minimum memory size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot or is
+    /// already larger than `new_size_bytes`.
+    pub fn pad_memory(&self, memory_index: u32, new_size_bytes: usize) -> Result<Snapshot> {
+        let mut snapshot = self.clone();
+        let memory = snapshot
+            .memories
+            .iter_mut()
+            .find(|m| m.index == memory_index)
+            .ok_or_else(|| anyhow!("memory {memory_index} does not exist in this snapshot"))?;
+        memory.ensure_internalized()?;
+        if new_size_bytes < memory.data.len() {
+            bail!(
+                "cannot pad memory {memory_index} of {:#x} bytes to {new_size_bytes:#x} bytes",
+                memory.data.len()
+            );
+        }
+        memory.data.resize(new_size_bytes, 0);
+        Ok(snapshot)
+    }
+
+    /// Returns a copy of this snapshot with `default_value` appended to its
+    /// globals until there are `new_count` of them.
+    ///
+    /// This allows restoring a snapshot into a new version of a module which
+    /// defines more globals, with explicit values for the new ones rather
+    /// than their initial values.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if this snapshot already has more than `new_count`
+    /// globals.
+    pub fn extend_globals(&self, new_count: usize, default_value: Val) -> Result<Snapshot> {
+        if new_count < self.globals.len() {
+            bail!(
+                "cannot extend {} globals to {new_count} globals",
+                self.globals.len()
+            );
+        }
+        let mut snapshot = self.clone();
+        snapshot.globals.resize(new_count, default_value);
+        Ok(snapshot)
+    }
+
+    /// Returns a copy of this snapshot holding only the memories with the
+    /// given indices, for restoring them alone with
+    /// [`Instance::restore_partial`].
+    ///
+    /// Globals, host states and annotations are kept. To restore globals
+    /// without any memory, capture them with
+    /// [`Instance::snapshot_globals_only`] instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory does not exist in this snapshot.
+    pub fn retain_only_memories(&self, indices: &[u32]) -> Result<Snapshot> {
+        if let Some(index) = indices
+            .iter()
+            .find(|i| !self.memories.iter().any(|m| m.index == **i))
+        {
+            bail!("memory {index} does not exist in this snapshot");
+        }
+        let mut snapshot = self.clone();
+        snapshot.memories.retain(|m| indices.contains(&m.index));
+        Ok(snapshot)
+    }
+
+    /// Overwrites the given byte ranges with zeros, for example to scrub
+    /// personal data before the snapshot is persisted to untrusted storage.
+    ///
+    /// Each range is given as a memory index and a range of byte offsets
+    /// within that memory. The ranges can be assembled by name with a
+    /// [`SensitiveRegionMap`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory does not exist in this snapshot or a range
+    /// extends past the end of its memory. In that case nothing is zeroed.
+    pub fn zero_sensitive_regions(&mut self, ranges: &[(u32, Range<usize>)]) -> Result<()> {
+        for (memory_index, range) in ranges {
+            let memory = self
+                .memories
+                .iter()
+                .find(|m| m.index == *memory_index)
+                .ok_or_else(|| anyhow!("memory {memory_index} does not exist in this snapshot"))?;
+            memory.ensure_internalized()?;
+            if range.start > range.end || range.end > memory.data.len() {
+                bail!(
+                    "range {:#x}..{:#x} is out of bounds of memory {memory_index} ({:#x} bytes)",
+                    range.start,
+                    range.end,
+                    memory.data.len(),
+                );
+            }
+        }
+        for (memory_index, range) in ranges {
+            let memory = self
+                .memories
+                .iter_mut()
+                .find(|m| m.index == *memory_index)
+                .unwrap();
+            memory.data[range.clone()].fill(0);
+        }
+        Ok(())
+    }
+
+    /// Returns a copy of `base` in which the given byte ranges are replaced
+    /// with the same ranges of `overlay`, for example to keep the heap of an
+    /// old snapshot but take the stack region from a newer one.
+    ///
+    /// Each range is given as a memory index and a range of byte offsets
+    /// within that memory, as for [`Snapshot::zero_sensitive_regions`].
+    /// Later ranges are applied after earlier ones. Globals and host states
+    /// are always taken from `base`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory does not exist in either snapshot or a
+    /// range extends past the end of its memory in either snapshot.
+    pub fn overlay(
+        base: &Snapshot,
+        overlay: &Snapshot,
+        ranges: &[(u32, Range<usize>)],
+    ) -> Result<Snapshot> {
+        let mut snapshot = base.clone();
+        for (memory_index, range) in ranges {
+            let source = overlay.memory_range(*memory_index, range, "overlay")?;
+            snapshot.memory_range(*memory_index, range, "base")?;
+            let memory = snapshot
+                .memories
+                .iter_mut()
+                .find(|m| m.index == *memory_index)
+                .unwrap();
+            memory.data[range.clone()].copy_from_slice(source);
+        }
+        Ok(snapshot)
+    }
+
+    /// Returns the bytes of `range` in memory `memory_index`, or an error
+    /// naming this snapshot `which`.
+    fn memory_range(&self, memory_index: u32, range: &Range<usize>, which: &str) -> Result<&[u8]> {
+        let memory = self
+            .memories
+            .iter()
+            .find(|m| m.index == memory_index)
+            .ok_or_else(|| {
+                anyhow!("memory {memory_index} does not exist in the {which} snapshot")
+            })?;
+        memory.ensure_internalized()?;
+        if range.start > range.end || range.end > memory.data.len() {
+            bail!(
+                "range {:#x}..{:#x} is out of bounds of memory {memory_index} ({:#x} bytes) \
+                 in the {which} snapshot",
+                range.start,
+                range.end,
+                memory.data.len(),
+            );
+        }
+        Ok(&memory.data[range.clone()])
+    }
+
+    /// Calls `transform` on each chunk of each memory of this snapshot,
+    /// allowing the memory contents to be rewritten in place.
+    ///
+    /// Each memory is split into consecutive chunks of `chunk_size` bytes,
+    /// the last of which may be shorter. `transform` receives the memory
+    /// index, the byte offset of the chunk within its memory, and the chunk
+    /// itself. This is the building block for whole-memory migrations, such
+    /// as converting the endianness or width of stored values.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory is
+    /// [stored in a file](Snapshot::export_memory_to_file), in which case
+    /// `transform` is not called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn apply_memory_transform<F>(&mut self, chunk_size: usize, mut transform: F) -> Result<()>
+    where
+        F: FnMut(u32, usize, &mut [u8]),
+    {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+        self.ensure_internalized()?;
+        for memory in self.memories.iter_mut() {
+            for (i, chunk) in memory.data.chunks_mut(chunk_size).enumerate() {
+                transform(memory.index, i * chunk_size, chunk);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a copy of this snapshot in which the given globals are moved
+    /// by an addend, for example to relocate a shadow stack pointer when
+    /// restoring into a module with a different memory layout.
+    ///
+    /// Each adjustment is given as a global index and the amount to add to
+    /// its value. The values of `i32` globals are treated as unsigned 32-bit
+    /// addresses and those of `i64` globals as unsigned 64-bit addresses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a global does not exist in this snapshot, is not
+    /// an `i32` or `i64`, or if its adjusted value would not fit its type.
+    pub fn rebase_globals(&self, adjustments: &[(usize, i64)]) -> Result<Snapshot> {
+        let mut snapshot = self.clone();
+        for (index, addend) in adjustments {
+            let global = snapshot
+                .globals
+                .get_mut(*index)
+                .ok_or_else(|| anyhow!("global {index} does not exist in this snapshot"))?;
+            *global = match global {
+                Val::I32(value) => Val::I32(
+                    i64::from(*value as u32)
+                        .checked_add(*addend)
+                        .and_then(|v| u32::try_from(v).ok())
+                        .ok_or_else(|| anyhow!("adding {addend} to global {index} overflows"))?
+                        as i32,
+                ),
+                Val::I64(value) => Val::I64(
+                    i128::from(*value as u64)
+                        .checked_add(i128::from(*addend))
+                        .and_then(|v| u64::try_from(v).ok())
+                        .ok_or_else(|| anyhow!("adding {addend} to global {index} overflows"))?
+                        as i64,
+                ),
+                other => bail!(
+                    "global {index} holds a {} and cannot be rebased",
+                    other.ty()
+                ),
+            };
+        }
+        Ok(snapshot)
+    }
+
+    /// Returns a copy of this snapshot in which pointers into
+    /// `old_base..old_base + size` stored in the given memory ranges are
+    /// moved to point into `new_base..new_base + size` instead.
+    ///
+    /// Each range is scanned for little-endian words of `pointer_width`,
+    /// starting at the beginning of the range. Since any word holding a value
+    /// in the old region is rewritten, the ranges should only cover memory
+    /// known to hold pointers, such as a table of addresses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory does not exist in this snapshot, if a
+    /// range extends past the end of its memory, or if the new region does
+    /// not fit in the address space of `pointer_width`.
+    pub fn rebase_memory_pointers(
+        &self,
+        old_base: usize,
+        new_base: usize,
+        size: usize,
+        pointer_width: PointerWidth,
+        byte_ranges: &[(u32, Range<usize>)],
+    ) -> Result<Snapshot> {
+        let max = match pointer_width {
+            PointerWidth::U32 => u64::from(u32::MAX),
+            PointerWidth::U64 => u64::MAX,
+        };
+        let old = old_base as u64..(old_base as u64).saturating_add(size as u64);
+        if size > 0
+            && (new_base as u64)
+                .checked_add(size as u64 - 1)
+                .map_or(true, |end| end > max)
+        {
+            bail!("rebased region does not fit in the address space");
+        }
+
+        let mut snapshot = self.clone();
+        for (memory_index, range) in byte_ranges {
+            let memory = snapshot
+                .memories
+                .iter_mut()
+                .find(|m| m.index == *memory_index)
+                .ok_or_else(|| anyhow!("memory {memory_index} does not exist in this snapshot"))?;
+            memory.ensure_internalized()?;
+            let len = memory.data.len();
+            let data = memory.data.get_mut(range.clone()).ok_or_else(|| {
+                anyhow!(
+                    "range {:#x}..{:#x} is out of bounds of memory {memory_index} ({len:#x} bytes)",
+                    range.start,
+                    range.end,
+                )
+            })?;
+            for word in data.chunks_exact_mut(pointer_width.bytes()) {
+                let value = match pointer_width {
+                    PointerWidth::U32 => u64::from(u32::from_le_bytes(word.try_into().unwrap())),
+                    PointerWidth::U64 => u64::from_le_bytes(word.try_into().unwrap()),
+                };
+                if !old.contains(&value) {
+                    continue;
+                }
+                let value = value - old.start + new_base as u64;
+                match pointer_width {
+                    PointerWidth::U32 => word.copy_from_slice(&(value as u32).to_le_bytes()),
+                    PointerWidth::U64 => word.copy_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Attaches named host states to this snapshot, replacing any attached
+    /// before.
+    pub fn with_host_states(mut self, states: Vec<(String, SuspendState)>) -> Snapshot {
+        self.host_states = states;
+        self
+    }
+
+    /// Returns the host states attached with [`Snapshot::with_host_states`].
+    pub fn host_states(&self) -> &[(String, SuspendState)] {
+        &self.host_states
+    }
+
+    /// Annotates this snapshot with `value` under `key`, replacing any value
+    /// annotated under the same key before.
+    ///
+    /// Annotations are metadata for the embedder, such as the version of the
+    /// component or the session the snapshot belongs to. They are kept by
+    /// [`Snapshot::to_bytes`], [`Snapshot::split`] and encryption, and
+    /// saved snapshots can be found by annotation with
+    /// `SnapshotStore::list_by_annotation`. They are not part of the state
+    /// of the instance, so they are ignored by [`Snapshot::compute_hash`] and
+    /// [`Snapshot::diff_report`].
+    pub fn with_annotation(mut self, key: impl Into<String>, value: impl Into<String>) -> Snapshot {
+        self.annotations.insert(key.into(), value.into());
+        self
+    }
+
+    /// Returns the value annotated under `key` with
+    /// [`Snapshot::with_annotation`], if any.
+    pub fn get_annotation(&self, key: &str) -> Option<&str> {
+        self.annotations.get(key).map(|v| v.as_str())
+    }
+
+    /// Returns all annotations attached with [`Snapshot::with_annotation`].
+    pub fn annotations(&self) -> &HashMap<String, String> {
+        &self.annotations
+    }
+
+    /// Returns size information about each memory held by this snapshot, in
+    /// memory index order, including memories
+    /// [stored in files](Snapshot::export_memory_to_file).
+    pub fn region_stats(&self) -> Vec<MemoryRegionInfo> {
+        self.memories
+            .iter()
+            .map(|m| MemoryRegionInfo {
+                name: m.name.clone(),
+                size_bytes: m.len(),
+            })
+            .collect()
+    }
+
+    /// Returns the number of pages of `page_size` bytes spanned by memory
+    /// `memory_index`, including a partial last page.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `page_size` is zero.
+    pub fn page_count(&self, memory_index: u32, page_size: usize) -> Result<u64> {
+        assert!(page_size > 0, "page size must be non-zero");
+        Ok(self.memory_data(memory_index)?.chunks(page_size).len() as u64)
+    }
+
+    /// Returns the number of pages of `page_size` bytes of memory
+    /// `memory_index` which hold at least one non-zero byte, for example to
+    /// size the buffers of a sparse encoding of the memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `page_size` is zero.
+    pub fn non_zero_page_count(&self, memory_index: u32, page_size: usize) -> Result<u64> {
+        assert!(page_size > 0, "page size must be non-zero");
+        Ok(self
+            .memory_data(memory_index)?
+            .chunks(page_size)
+            .filter(|page| page.iter().any(|b| *b != 0))
+            .count() as u64)
+    }
+
+    /// Returns the fraction of the wasm pages of memory `memory_index` which
+    /// hold at least one non-zero byte, or zero for an empty memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot.
+    pub fn sparsity_ratio(&self, memory_index: u32) -> Result<f64> {
+        let page_size = wasmtime_environ::WASM_PAGE_SIZE as usize;
+        let total = self.page_count(memory_index, page_size)?;
+        if total == 0 {
+            return Ok(0.0);
+        }
+        let non_zero = self.non_zero_page_count(memory_index, page_size)?;
+        Ok(non_zero as f64 / total as f64)
+    }
+
+    /// Divides memory `memory_index` into buckets of `bucket_size_bytes`
+    /// bytes and returns statistics about each of them, in address order, to
+    /// show how densely the memory is used. The last bucket is shorter if the
+    /// memory is not a multiple of the bucket size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_size_bytes` is zero.
+    pub fn memory_histogram(
+        &self,
+        memory_index: u32,
+        bucket_size_bytes: usize,
+    ) -> Result<Vec<BucketStats>> {
+        assert!(bucket_size_bytes > 0, "bucket size must be non-zero");
+        let data = self.memory_data(memory_index)?;
+        Ok(data
+            .chunks(bucket_size_bytes)
+            .enumerate()
+            .map(|(i, bucket)| {
+                let mut seen = [false; 256];
+                let mut non_zero = 0u32;
+                for b in bucket {
+                    seen[usize::from(*b)] = true;
+                    non_zero += u32::from(*b != 0);
+                }
+                let start = i * bucket_size_bytes;
+                BucketStats {
+                    byte_range: start..start + bucket.len(),
+                    non_zero_bytes: non_zero,
+                    distinct_values: seen.iter().filter(|s| **s).count() as u32,
+                }
+            })
+            .collect())
+    }
+
+    /// Returns the Shannon entropy of the byte values of memory
+    /// `memory_index`, in bits per byte, or zero for an empty memory.
+    ///
+    /// The result ranges from 0, for a memory holding a single byte value,
+    /// to 8, for uniformly distributed bytes, and estimates how well the
+    /// memory would compress before compressing it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot.
+    pub fn entropy_estimate(&self, memory_index: u32) -> Result<f64> {
+        let data = self.memory_data(memory_index)?;
+        let mut counts = [0u64; 256];
+        for b in data {
+            counts[usize::from(*b)] += 1;
+        }
+        let len = data.len() as f64;
+        Ok(counts
+            .iter()
+            .filter(|c| **c > 0)
+            .map(|c| {
+                let p = *c as f64 / len;
+                -p * p.log2()
+            })
+            .sum())
+    }
+
+    /// Zeroes every wasm page of every memory in which the fraction of
+    /// non-zero bytes is below `threshold`, returning the number of pages
+    /// zeroed.
+    ///
+    /// This discards data, so it is only suitable for memories whose sparse
+    /// pages are known to hold nothing the module relies on, such as freed
+    /// heap memory which was not cleared. In exchange the snapshot compresses
+    /// better and its deltas become smaller.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory is
+    /// [stored in a file](Snapshot::export_memory_to_file), in which case
+    /// nothing is zeroed.
+    pub fn zero_fill_sparse_pages(&mut self, threshold: f64) -> Result<usize> {
+        self.ensure_internalized()?;
+        let page_size = wasmtime_environ::WASM_PAGE_SIZE as usize;
+        let mut zeroed = 0;
+        for memory in self.memories.iter_mut() {
+            for page in memory.data.chunks_mut(page_size) {
+                let non_zero = page.iter().filter(|b| **b != 0).count();
+                if non_zero > 0 && (non_zero as f64 / page.len() as f64) < threshold {
+                    page.fill(0);
+                    zeroed += 1;
+                }
+            }
+        }
+        Ok(zeroed)
+    }
+
+    fn memory_data(&self, memory_index: u32) -> Result<&[u8]> {
+        let memory = self
+            .memories
+            .iter()
+            .find(|m| m.index == memory_index)
+            .ok_or_else(|| anyhow!("memory {memory_index} does not exist in this snapshot"))?;
+        memory.ensure_internalized()?;
+        Ok(&memory.data)
+    }
+
+    /// Returns the contents of each memory held by this snapshot, in memory
+    /// index order, for serializers which need to lay out the raw state
+    /// themselves instead of using [`Snapshot::to_bytes`].
+    ///
+    /// Together with [`Snapshot::globals`] and [`Snapshot::host_states`] this
+    /// exposes everything a snapshot holds, and [`Snapshot::from_parts`]
+    /// reassembles it.
+    ///
+    /// Note that this exposes the internal layout of snapshots, which is not
+    /// stable: future versions may capture more state, such as tables, which
+    /// would not be reflected here.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory is
+    /// [stored in a file](Snapshot::export_memory_to_file).
+    pub fn memory_regions(&self) -> Result<Vec<MemoryRegion<'_>>> {
+        self.ensure_internalized()?;
+        let page_size = wasmtime_environ::WASM_PAGE_SIZE as u64;
+        Ok(self
+            .memories
+            .iter()
+            .map(|m| MemoryRegion {
+                index: m.index,
+                name: &m.name,
+                data: &m.data,
+                minimum_pages: (m.data.len() as u64 + page_size - 1) / page_size,
+            })
+            .collect())
+    }
+
+    /// Returns the value of each global held by this snapshot, in global
+    /// index order.
+    ///
+    /// The same stability caveat as for [`Snapshot::memory_regions`]
+    /// applies.
+    pub fn globals(&self) -> &[Val] {
+        &self.globals
+    }
+
+    /// Assembles a snapshot from the contents of its memories and the values
+    /// of its globals, as returned by [`Snapshot::memory_regions`] and
+    /// [`Snapshot::globals`].
+    ///
+    /// `memories` holds the name and contents of each memory in memory index
+    /// order. Whether the parts match a module is only checked when the
+    /// snapshot is restored, by [`Instance::restore`]. Host states can be
+    /// attached with [`Snapshot::with_host_states`].
+    ///
+    /// The same stability caveat as for [`Snapshot::memory_regions`]
+    /// applies.
+    pub fn from_parts(memories: Vec<(String, Vec<u8>)>, globals: Vec<Val>) -> Snapshot {
+        Snapshot {
+            memories: memories
+                .into_iter()
+                .enumerate()
+                .map(|(index, (name, data))| SnapshotMemory {
+                    index: index as u32,
+                    name,
+                    data,
+                    external: None,
+                })
+                .collect(),
+            globals,
+            host_states: Vec::new(),
+            annotations: HashMap::new(),
+        }
+    }
+}
+
+/// The width of the pointers rewritten by
+/// [`Snapshot::rebase_memory_pointers`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointerWidth {
+    /// 32-bit pointers, as used by 32-bit linear memories.
+    U32,
+    /// 64-bit pointers, as used by 64-bit linear memories.
+    U64,
+}
+
+impl PointerWidth {
+    fn bytes(self) -> usize {
+        match self {
+            PointerWidth::U32 => 4,
+            PointerWidth::U64 => 8,
+        }
+    }
+}
+
+/// A list of memory ranges holding sensitive data, to be scrubbed from a
+/// [`Snapshot`] with [`Snapshot::zero_sensitive_regions`].
+///
+/// Ranges are either given as offsets directly or looked up by symbol name.
+/// Wasmtime does not keep the DWARF debug info of a module around, so symbols
+/// are resolved through exported globals holding the address of the data,
+/// which is how toolchains such as LLVM export data symbols.
+///
+/// ```
+/// # use wasmtime::*;
+/// # fn main() -> anyhow::Result<()> {
+/// # let engine = Engine::default();
+/// let module = Module::new(
+///     &engine,
+///     r#"(module
+///         (memory (export "memory") 1)
+///         (global (export "user_name") i32 (i32.const 0x4000))
+///         (data (i32.const 0x4000) "Jane Doe"))"#,
+/// )?;
+/// let mut store = Store::new(&engine, ());
+/// let instance = Instance::new(&mut store, &module, &[])?;
+///
+/// let mut regions = SensitiveRegionMap::new();
+/// regions
+///     .symbol(&mut store, &instance, "user_name", 0x100)?
+///     .range(0, 0x8000..0x8010);
+/// let mut snapshot = instance.snapshot(&mut store)?;
+/// snapshot.zero_sensitive_regions(regions.ranges())?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SensitiveRegionMap {
+    ranges: Vec<(u32, Range<usize>)>,
+}
+
+impl SensitiveRegionMap {
+    /// Creates an empty map.
+    pub fn new() -> SensitiveRegionMap {
+        SensitiveRegionMap::default()
+    }
+
+    /// Adds the byte range `range` of memory `memory_index`.
+    pub fn range(&mut self, memory_index: u32, range: Range<usize>) -> &mut Self {
+        self.ranges.push((memory_index, range));
+        self
+    }
+
+    /// Adds `len` bytes of memory 0 starting at the address held by the
+    /// global `name` exported by `instance`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `instance` does not export an `i32` or `i64`
+    /// global called `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own `instance`.
+    pub fn symbol(
+        &mut self,
+        store: impl AsContextMut,
+        instance: &Instance,
+        name: &str,
+        len: usize,
+    ) -> Result<&mut Self> {
+        let start = match instance.read_global(store, name)? {
+            Val::I32(address) => address as u32 as usize,
+            Val::I64(address) => usize::try_from(address as u64)?,
+            other => bail!("global `{name}` holds a {} and not an address", other.ty()),
+        };
+        let end = start
+            .checked_add(len)
+            .ok_or_else(|| anyhow!("symbol `{name}` overflows the address space"))?;
+        Ok(self.range(0, start..end))
+    }
+
+    /// Returns the ranges added so far, in the order they were added.
+    pub fn ranges(&self) -> &[(u32, Range<usize>)] {
+        &self.ranges
+    }
+}
+
+impl Instance {
+    /// Captures the current contents of all memories and the values of all
+    /// globals of this instance into a [`Snapshot`], together with the host
+    /// states registered with [`Store::snapshot_host_state`](crate::Store::snapshot_host_state).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if saving a host state fails, or if this instance
+    /// uses a shared memory, whose contents cannot be copied consistently.
+    /// This includes instances using the wasi-threads proposal, whose other
+    /// threads may write to the memory at any time: their state can only be
+    /// captured once all threads have been quiesced, which is up to the
+    /// embedder.
+    ///
+    /// The snapshot is only consistent if no function of this instance is
+    /// running, as reported by [`Instance::call_stack_depth`], or if the
+    /// functions which are running are suspended at a point where the
+    /// module expects to be resumed from its memories and globals alone,
+    /// such as an async yield point. Otherwise it may capture state which
+    /// the running functions were in the middle of updating.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn snapshot(&self, mut store: impl AsContextMut) -> Result<Snapshot> {
+        self._snapshot(store.as_context_mut())
+    }
+
+    /// Returns the number of frames of functions of this instance's module
+    /// on the wasm call stack of `store`, or 0 if none of them is running,
+    /// for embedders which check that an instance is idle before taking a
+    /// [`Snapshot`] of it.
+    ///
+    /// This is meant to be called from host functions and epoch callbacks,
+    /// which wasm calls into. The frames of an async call are only seen from
+    /// within the call, not while it is suspended. Frames are attributed to
+    /// instances by their code, so the frames of other instances of the same
+    /// module in `store` are counted as well.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn call_stack_depth(&self, store: impl AsContext) -> u32 {
+        let store = store.as_context();
+        let module = self.module(&store).compiled_module();
+        let mut depth = 0;
+        wasmtime_runtime::Backtrace::trace(store.0.runtime_limits(), |frame| {
+            // The pc of a frame is after its call instruction, see
+            // `WasmBacktrace::from_captured`.
+            let ours = store
+                .0
+                .modules()
+                .lookup_frame_info(frame.pc() - 1)
+                .map_or(false, |(_, m)| std::ptr::eq(m.compiled_module(), module));
+            if ours {
+                depth += 1;
+            }
+            ControlFlow::Continue(())
+        });
+        depth
+    }
+
+    fn _snapshot<T>(&self, mut store: StoreContextMut<'_, T>) -> Result<Snapshot> {
+        let module = self.module(&store).clone();
+
+        let mut memories = Vec::new();
+        for (index, memory) in self.all_memories(store.0).collect::<Vec<_>>() {
+            if memory.ty(&store).is_shared() {
+                bail!("cannot snapshot an instance with a shared memory");
+            }
+            memories.push(SnapshotMemory {
+                index: index.as_u32(),
+                name: memory_name(&module, index.as_u32()),
+                data: memory.data(&store).to_vec(),
+                external: None,
+            });
+        }
+
+        let globals = self
+            .all_globals(store.0)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|(_, global)| global.get(&mut store))
+            .collect();
+        let host_states = store.0.save_host_states()?;
+
+        Ok(Snapshot {
+            memories,
+            globals,
+            host_states,
+            annotations: HashMap::new(),
+        })
+    }
+
+    /// Calls `func` with `args` and captures a [`Snapshot`] of this instance
+    /// as soon as the call returns.
+    ///
+    /// The store remains exclusively borrowed from the start of the call
+    /// until the snapshot has been taken, so no other code, such as another
+    /// task resumed after an epoch interruption, can observe or modify the
+    /// instance in between. The snapshot therefore reflects exactly the state
+    /// left behind by the call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call traps or fails for any of the reasons
+    /// documented on [`Func::call`](crate::Func::call), or if the snapshot
+    /// cannot be taken as documented on [`Instance::snapshot`]. No snapshot
+    /// is taken if the call fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` is not asynchronous or does not own this instance
+    /// and `func`.
+    #[cfg(feature = "async")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+    pub async fn call_and_snapshot_async<T>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        func: &crate::Func,
+        args: &[Val],
+    ) -> Result<(Vec<Val>, Snapshot)>
+    where
+        T: Send,
+    {
+        let mut store = store.as_context_mut();
+        let mut results = vec![Val::null(); func.ty(&store).results().len()];
+        func.call_async(&mut store, args, &mut results).await?;
+        let snapshot = self._snapshot(store)?;
+        Ok((results, snapshot))
+    }
+
+    /// Writes the state captured in `snapshot` back into this instance, then
+    /// restores the host states registered with
+    /// [`Store::snapshot_host_state`](crate::Store::snapshot_host_state).
+    ///
+    /// Each memory is grown as necessary to hold the snapshot's contents.
+    /// Since memories cannot shrink, any bytes past the end of the snapshot's
+    /// copy of a memory are zeroed instead. Immutable globals are left
+    /// untouched, as are globals added to the module after the snapshot was
+    /// taken.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `snapshot` is incompatible with this instance's
+    /// module, as reported by [`Snapshot::validate_for_module`], if one
+    /// of its memories is [stored in a file](Snapshot::export_memory_to_file),
+    /// if a memory cannot be grown to the required size, if a
+    /// global's value cannot be set, for example because it references a
+    /// function from another store, or if restoring a host state fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    ///
+    /// This function will panic if the store has a
+    /// [`ResourceLimiterAsync`](crate::ResourceLimiterAsync), since growing
+    /// memories requires consulting the limiter.
+    pub fn restore(&self, mut store: impl AsContextMut, snapshot: &Snapshot) -> Result<()> {
+        self._restore(store.as_context_mut(), snapshot)
+    }
+
+    fn _restore<T>(&self, mut store: StoreContextMut<'_, T>, snapshot: &Snapshot) -> Result<()> {
+        snapshot.ensure_internalized()?;
+        let module = self.module(&store).clone();
+        check_compatibility(snapshot.validate_for_module(&module))?;
+
+        let memories = self.all_memories(store.0).collect::<Vec<_>>();
+        for ((index, memory), saved) in memories.iter().zip(&snapshot.memories) {
+            if index.as_u32() != saved.index {
+                bail!("snapshot does not match the shape of this instance");
+            }
+            restore_memory(&mut store, memory, &saved.data)?;
+        }
+        self.restore_mutable_globals(store.as_context_mut(), &snapshot.globals)?;
+        store.0.restore_host_states(&snapshot.host_states)
+    }
+
+    /// Same as [`Instance::restore`], but only restores the memories present
+    /// in `snapshot`, leaving the other memories of this instance untouched.
+    ///
+    /// This is meant for snapshots narrowed down with
+    /// [`Snapshot::retain_only_memories`], for example to roll back a scratch
+    /// memory while keeping the contents of another one. Mutable globals are
+    /// restored as with [`Instance::restore`]; a snapshot without globals
+    /// leaves them untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory of `snapshot` does not exist in this
+    /// instance or exceeds its maximum size, if the globals of `snapshot` do
+    /// not match those of this instance, if a memory cannot be grown to the
+    /// required size, if a global's value cannot be set, or if restoring a
+    /// host state fails. In the first two cases nothing is restored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    ///
+    /// This function will panic if the store has a
+    /// [`ResourceLimiterAsync`](crate::ResourceLimiterAsync), since growing
+    /// memories requires consulting the limiter.
+    pub fn restore_partial(&self, mut store: impl AsContextMut, snapshot: &Snapshot) -> Result<()> {
+        let mut store = store.as_context_mut();
+        snapshot.ensure_internalized()?;
+        let module = self.module(&store).clone();
+        check_compatibility(snapshot.validate_partial_for_module(&module))?;
+
+        let memories = self.all_memories(store.0).collect::<Vec<_>>();
+        for saved in &snapshot.memories {
+            let (_, memory) = memories
+                .iter()
+                .find(|(index, _)| index.as_u32() == saved.index)
+                .ok_or_else(|| anyhow!("memory {} does not exist in this instance", saved.index))?;
+            restore_memory(&mut store, memory, &saved.data)?;
+        }
+        self.restore_mutable_globals(store.as_context_mut(), &snapshot.globals)?;
+        store.0.restore_host_states(&snapshot.host_states)
+    }
+
+    /// Sets the mutable globals of this instance to the corresponding values
+    /// of `saved`, leaving globals past its end untouched.
+    fn restore_mutable_globals<T>(
+        &self,
+        mut store: StoreContextMut<'_, T>,
+        saved: &[Val],
+    ) -> Result<()> {
+        let globals = self.all_globals(store.0).collect::<Vec<_>>();
+        for ((_, global), saved) in globals.iter().zip(saved) {
+            if global.ty(&store).mutability() == Mutability::Var {
+                global.set(&mut store, saved.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimates the size of a [`Snapshot`] of this instance without copying
+    /// any memory.
+    ///
+    /// The returned estimate matches what [`Snapshot::size_bytes`] and
+    /// [`Snapshot::region_stats`] would report for a snapshot taken right now.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn snapshot_size_estimate(
+        &self,
+        mut store: impl AsContextMut,
+    ) -> Result<SnapshotSizeEstimate> {
+        let store = store.as_context_mut();
+        let module = self.module(&store).clone();
+
+        let regions: Vec<_> = self
+            .all_memories(store.0)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|(index, memory)| MemoryRegionInfo {
+                name: memory_name(&module, index.as_u32()),
+                size_bytes: memory.data_size(&store),
+            })
+            .collect();
+
+        let globals: usize = self
+            .all_globals(store.0)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|(_, global)| val_type_size(global.ty(&store).content()))
+            .sum();
+
+        Ok(SnapshotSizeEstimate {
+            total_bytes: regions.iter().map(|r| r.size_bytes).sum::<usize>() + globals,
+            regions,
+        })
+    }
+
+    /// Captures the values of all mutable globals of this instance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn snapshot_globals_only(&self, mut store: impl AsContextMut) -> Result<GlobalsSnapshot> {
+        let mut store = store.as_context_mut();
+        let mut globals = Vec::new();
+        for (index, global) in self.all_globals(store.0).collect::<Vec<_>>() {
+            if global.ty(&store).mutability() == Mutability::Var {
+                globals.push((index.as_u32(), global.get(&mut store)));
+            }
+        }
+        Ok(GlobalsSnapshot { globals })
+    }
+
+    /// Writes the globals captured in `snapshot` back into this instance,
+    /// leaving its memories untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `snapshot` refers to globals which this instance
+    /// does not have or which are immutable, or if a value has the wrong type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn restore_globals(
+        &self,
+        mut store: impl AsContextMut,
+        snapshot: &GlobalsSnapshot,
+    ) -> Result<()> {
+        let mut store = store.as_context_mut();
+        let globals = self.all_globals(store.0).collect::<Vec<_>>();
+        for (index, val) in snapshot.globals.iter() {
+            let (_, global) = globals
+                .get(*index as usize)
+                .ok_or_else(|| anyhow!("global {index} does not exist in this instance"))?;
+            global.set(&mut store, val.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Copies `len` bytes starting at `offset` out of the memory with index
+    /// `memory_index` of this instance.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist, is shared, or if the
+    /// range is out of bounds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn snapshot_memory_range(
+        &self,
+        mut store: impl AsContextMut,
+        memory_index: u32,
+        offset: usize,
+        len: usize,
+    ) -> Result<MemoryRangeSnapshot> {
+        let mut store = store.as_context_mut();
+        let memory = self.memory_at(&mut store, memory_index)?;
+        let data = memory
+            .data(&store)
+            .get(offset..)
+            .and_then(|s| s.get(..len))
+            .ok_or_else(|| anyhow!("memory range out of bounds"))?
+            .to_vec();
+        Ok(MemoryRangeSnapshot {
+            memory_index,
+            offset,
+            data,
+        })
+    }
+
+    /// Writes the bytes captured in `snapshot` back into the memory they were
+    /// copied from.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist, is shared, or if the
+    /// range is out of bounds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn restore_memory_range(
+        &self,
+        mut store: impl AsContextMut,
+        snapshot: &MemoryRangeSnapshot,
+    ) -> Result<()> {
+        let mut store = store.as_context_mut();
+        let memory = self.memory_at(&mut store, snapshot.memory_index)?;
+        memory
+            .write(&mut store, snapshot.offset, &snapshot.data)
+            .map_err(|_| anyhow!("memory range out of bounds"))
+    }
+
+    /// Returns the non-shared memory with index `index` in this instance's
+    /// memory index space.
+    pub(crate) fn memory_at<T>(
+        &self,
+        store: &mut StoreContextMut<'_, T>,
+        index: u32,
+    ) -> Result<Memory> {
+        let (_, memory) = self
+            .all_memories(store.0)
+            .nth(index as usize)
+            .ok_or_else(|| anyhow!("memory {index} does not exist in this instance"))?;
+        if memory.ty(&*store).is_shared() {
+            bail!("memory {index} is a shared memory");
+        }
+        Ok(memory)
+    }
+}
+
+/// Returns the name used to identify memory `index` of `module` in snapshot
+/// statistics.
+fn memory_name(module: &Module, index: u32) -> String {
+    module
+        .env_module()
+        .exports
+        .iter()
+        .find_map(|(name, export)| match export {
+            EntityIndex::Memory(i) if i.as_u32() == index => Some(name.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(|| format!("memory{index}"))
+}
+
+/// Logs the warnings of `compatibility`, or returns an error listing its
+/// reasons if the snapshot cannot be restored.
+fn check_compatibility(compatibility: SnapshotCompatibility) -> Result<()> {
+    match compatibility {
+        SnapshotCompatibility::Compatible => Ok(()),
+        SnapshotCompatibility::CompatibleWithWarnings(warnings) => {
+            for warning in warnings {
+                log::warn!("restoring snapshot: {warning}");
+            }
+            Ok(())
+        }
+        SnapshotCompatibility::Incompatible(reasons) => bail!(
+            "snapshot does not match the shape of this instance: {}",
+            reasons.join("; ")
+        ),
+    }
+}
+
+/// Writes `saved` to the start of `memory`, growing it as necessary and
+/// zeroing the bytes past the end of `saved`.
+fn restore_memory<T>(
+    store: &mut StoreContextMut<'_, T>,
+    memory: &Memory,
+    saved: &[u8],
+) -> Result<()> {
+    let page_size = wasmtime_environ::WASM_PAGE_SIZE as usize;
+    let current = memory.data_size(&*store);
+    if current < saved.len() {
+        let delta = (saved.len() - current + page_size - 1) / page_size;
+        memory.grow(&mut *store, delta as u64)?;
+    }
+    let data = memory.data_mut(&mut *store);
+    data[..saved.len()].copy_from_slice(saved);
+    data[saved.len()..].fill(0);
+    Ok(())
+}
+
+/// Compares two values without access to a store.
+///
+/// Non-null references compare equal only if they are known to be the same
+/// reference.
+fn val_eq(a: &Val, b: &Val) -> bool {
+    match (a, b) {
+        (Val::I32(x), Val::I32(y)) => x == y,
+        (Val::I64(x), Val::I64(y)) => x == y,
+        (Val::F32(x), Val::F32(y)) => x == y,
+        (Val::F64(x), Val::F64(y)) => x == y,
+        (Val::V128(x), Val::V128(y)) => x.as_u128() == y.as_u128(),
+        (Val::FuncRef(None), Val::FuncRef(None)) => true,
+        (Val::FuncRef(Some(x)), Val::FuncRef(Some(y))) => x.same_store_entry(y),
+        (Val::ExternRef(None), Val::ExternRef(None)) => true,
+        (Val::ExternRef(Some(x)), Val::ExternRef(Some(y))) => x.ptr_eq(y),
+        _ => false,
+    }
+}
+
+/// The number of bytes a value of type `ty` occupies in a snapshot.
+fn val_type_size(ty: &ValType) -> usize {
+    match ty {
+        ValType::I32 | ValType::F32 => 4,
+        ValType::I64 | ValType::F64 => 8,
+        ValType::V128 => 16,
+        ValType::FuncRef | ValType::ExternRef => std::mem::size_of::<usize>(),
+    }
+}
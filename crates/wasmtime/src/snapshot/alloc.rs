@@ -0,0 +1,84 @@
+use super::Snapshot;
+use anyhow::{ensure, Result};
+
+/// A host-side allocator mirroring the heap layout of a wasm module, for
+/// example to share buffers with native code over FFI, which
+/// [`Snapshot::replay_allocations`] brings up to date with a snapshot.
+///
+/// The allocator of the module itself, such as dlmalloc compiled into the
+/// module, keeps its state in linear memory and is restored along with it.
+/// Allocators living on the host are not, and have to be told about the
+/// allocations found in the restored memory.
+pub trait WasmAllocator {
+    /// Records that `size` bytes at `offset` in linear memory are allocated.
+    fn record_allocation(&mut self, offset: usize, size: usize);
+
+    /// Records that the allocation at `offset` in linear memory was freed.
+    fn record_free(&mut self, offset: usize);
+}
+
+/// An allocation or free found in linear memory by the parser passed to
+/// [`Snapshot::replay_allocations`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AllocationRecord {
+    /// `size` bytes at `offset` are allocated.
+    Allocation {
+        /// The offset of the allocation in linear memory.
+        offset: usize,
+        /// The size of the allocation in bytes.
+        size: usize,
+    },
+    /// The allocation at `offset` was freed.
+    Free {
+        /// The offset of the freed allocation in linear memory.
+        offset: usize,
+    },
+}
+
+impl Snapshot {
+    /// Scans memory `memory_index` for the allocations of the wasm module
+    /// and replays them into `allocator`.
+    ///
+    /// The layout of the heap depends on the allocator compiled into the
+    /// module, so `header_scan_fn` is given the contents of the memory and
+    /// returns the records it finds, typically by walking the chunk headers
+    /// of the allocator. The records are replayed in the order they are
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot, or if
+    /// a record lies outside of the memory. In the latter case no record is
+    /// replayed.
+    pub fn replay_allocations(
+        &self,
+        memory_index: u32,
+        header_scan_fn: impl Fn(&[u8]) -> Vec<AllocationRecord>,
+        allocator: &mut dyn WasmAllocator,
+    ) -> Result<()> {
+        let data = self.memory_data(memory_index)?;
+        let records = header_scan_fn(data);
+        for record in records.iter() {
+            let (offset, size) = match *record {
+                AllocationRecord::Allocation { offset, size } => (offset, size),
+                AllocationRecord::Free { offset } => (offset, 0),
+            };
+            ensure!(
+                offset
+                    .checked_add(size)
+                    .map_or(false, |end| offset < data.len() && end <= data.len()),
+                "{record:?} lies outside of memory {memory_index} ({:#x} bytes)",
+                data.len()
+            );
+        }
+        for record in records {
+            match record {
+                AllocationRecord::Allocation { offset, size } => {
+                    allocator.record_allocation(offset, size)
+                }
+                AllocationRecord::Free { offset } => allocator.record_free(offset),
+            }
+        }
+        Ok(())
+    }
+}
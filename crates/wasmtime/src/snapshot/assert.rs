@@ -0,0 +1,112 @@
+use super::{val_eq, Snapshot};
+use crate::Val;
+use anyhow::{bail, Result};
+use std::fmt::Write;
+
+/// The number of mismatches listed in the error of an assertion, past which
+/// only their number is given.
+const MAX_LISTED_MISMATCHES: usize = 16;
+
+impl Snapshot {
+    /// Checks that the globals of this snapshot hold the `expected` values,
+    /// given as pairs of a global index and its value, for tests which
+    /// assert on the state of an instance after a sequence of calls.
+    ///
+    /// Values are compared as in [`Snapshot::diff_report`], so non-null
+    /// references only compare equal if they are known to be the same
+    /// reference.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error listing every global which does not hold its
+    /// expected value or does not exist, one per line.
+    pub fn assert_globals(&self, expected: &[(usize, Val)]) -> Result<()> {
+        let mut mismatches = Vec::new();
+        for (index, expected) in expected {
+            match self.globals.get(*index) {
+                Some(actual) if val_eq(actual, expected) => {}
+                Some(actual) => mismatches.push(format!(
+                    "global {index}: expected {expected:?}, found {actual:?}"
+                )),
+                None => mismatches.push(format!(
+                    "global {index}: expected {expected:?}, but the snapshot has {} globals",
+                    self.globals.len()
+                )),
+            }
+        }
+        if !mismatches.is_empty() {
+            bail!(mismatch_report("globals", mismatches));
+        }
+        Ok(())
+    }
+
+    /// Checks that memory `memory_index` holds `expected` at `offset`, like
+    /// [`Snapshot::assert_globals`] does for globals.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot, if
+    /// the expected bytes extend past its end, or listing every run of bytes
+    /// which differ from the expected ones, one per line.
+    pub fn assert_memory_bytes(
+        &self,
+        memory_index: u32,
+        offset: usize,
+        expected: &[u8],
+    ) -> Result<()> {
+        let data = self.memory_data(memory_index)?;
+        let actual = match offset.checked_add(expected.len()) {
+            Some(end) if end <= data.len() => &data[offset..end],
+            _ => bail!(
+                "{:#x} bytes at {offset:#x} extend past the end of memory {memory_index} \
+                 ({:#x} bytes)",
+                expected.len(),
+                data.len()
+            ),
+        };
+
+        let mut mismatches = Vec::new();
+        let mut i = 0;
+        while i < expected.len() {
+            if actual[i] == expected[i] {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < expected.len() && actual[i] != expected[i] {
+                i += 1;
+            }
+            mismatches.push(format!(
+                "memory {memory_index} at {:#x}..{:#x}: expected {:02x?}, found {:02x?}",
+                offset + start,
+                offset + i,
+                &expected[start..i],
+                &actual[start..i]
+            ));
+        }
+        if !mismatches.is_empty() {
+            bail!(mismatch_report("bytes", mismatches));
+        }
+        Ok(())
+    }
+}
+
+/// Formats the error of an assertion which found `mismatches`.
+fn mismatch_report(what: &str, mismatches: Vec<String>) -> String {
+    let mut report = format!(
+        "snapshot does not hold the expected {what} ({} mismatches)",
+        mismatches.len()
+    );
+    for mismatch in mismatches.iter().take(MAX_LISTED_MISMATCHES) {
+        write!(report, "\n  {mismatch}").unwrap();
+    }
+    if mismatches.len() > MAX_LISTED_MISMATCHES {
+        write!(
+            report,
+            "\n  ... and {} more",
+            mismatches.len() - MAX_LISTED_MISMATCHES
+        )
+        .unwrap();
+    }
+    report
+}
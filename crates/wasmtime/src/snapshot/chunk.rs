@@ -0,0 +1,137 @@
+use super::Snapshot;
+use anyhow::{bail, ensure, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A fixed-size piece of a serialized [`Snapshot`], created with
+/// [`Snapshot::into_chunks`] to transfer a snapshot over an unreliable
+/// network.
+///
+/// Every chunk carries its position and the total number of chunks, so that
+/// [`Snapshot::assemble_from_chunks`] can reassemble chunks received in any
+/// order and a receiver can tell which chunks to request again after an
+/// interrupted transfer. The checksum covers all other fields and detects
+/// chunks damaged in transit.
+#[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    /// The position of this chunk, counting up from 0.
+    pub index: u64,
+    /// The number of chunks the snapshot was divided into.
+    pub total: u64,
+    /// The bytes of the serialized snapshot held by this chunk.
+    pub data: Vec<u8>,
+    /// A Blake3 hash of `index`, `total` and `data`.
+    pub checksum: [u8; 32],
+}
+
+impl SnapshotChunk {
+    fn new(index: u64, total: u64, data: Vec<u8>) -> SnapshotChunk {
+        let checksum = checksum(index, total, &data);
+        SnapshotChunk {
+            index,
+            total,
+            data,
+            checksum,
+        }
+    }
+
+    /// Returns whether the checksum of this chunk matches its contents.
+    pub fn verify_checksum(&self) -> bool {
+        checksum(self.index, self.total, &self.data) == self.checksum
+    }
+}
+
+fn checksum(index: u64, total: u64, data: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&index.to_le_bytes());
+    hasher.update(&total.to_le_bytes());
+    hasher.update(data);
+    *hasher.finalize().as_bytes()
+}
+
+impl Snapshot {
+    /// Serializes this snapshot with [`Snapshot::to_bytes`] and divides the
+    /// result into chunks of `chunk_size_bytes`, of which only the last one
+    /// may be shorter.
+    ///
+    /// The snapshot is reassembled with [`Snapshot::assemble_from_chunks`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot cannot be serialized.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size_bytes` is 0.
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+    pub fn into_chunks(
+        self,
+        chunk_size_bytes: usize,
+    ) -> Result<impl Iterator<Item = SnapshotChunk>> {
+        assert!(chunk_size_bytes > 0, "chunk size must not be zero");
+        let bytes = self.to_bytes()?;
+        let full = bytes.len() / chunk_size_bytes;
+        let total = (full + usize::from(bytes.len() % chunk_size_bytes != 0)).max(1) as u64;
+        Ok((0..total).map(move |index| {
+            let start = (index as usize * chunk_size_bytes).min(bytes.len());
+            let end = (start + chunk_size_bytes).min(bytes.len());
+            SnapshotChunk::new(index, total, bytes[start..end].to_vec())
+        }))
+    }
+
+    /// Reassembles the snapshot divided into `chunks` by
+    /// [`Snapshot::into_chunks`].
+    ///
+    /// The chunks may be given in any order, and a chunk may be given more
+    /// than once, as happens when a transfer is retried.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are no chunks, if a checksum does not match,
+    /// if the chunks disagree on their total or on the contents of a chunk,
+    /// if a chunk is missing, or if the reassembled bytes are not a valid
+    /// snapshot.
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+    pub fn assemble_from_chunks(
+        chunks: impl IntoIterator<Item = SnapshotChunk>,
+    ) -> Result<Snapshot> {
+        let mut total = None;
+        let mut received = BTreeMap::new();
+        for chunk in chunks {
+            ensure!(
+                chunk.verify_checksum(),
+                "checksum of chunk {} does not match",
+                chunk.index
+            );
+            let expected = *total.get_or_insert(chunk.total);
+            ensure!(
+                chunk.total == expected,
+                "chunk {} belongs to a snapshot of {} chunks, not {expected}",
+                chunk.index,
+                chunk.total
+            );
+            ensure!(
+                chunk.index < expected,
+                "chunk {} is out of range for a snapshot of {expected} chunks",
+                chunk.index
+            );
+            if let Some(previous) = received.insert(chunk.index, chunk.data) {
+                ensure!(
+                    received[&chunk.index] == previous,
+                    "chunk {} was received twice with different contents",
+                    chunk.index
+                );
+            }
+        }
+        let total = match total {
+            Some(total) => total,
+            None => bail!("no chunks to assemble a snapshot from"),
+        };
+        if let Some(missing) = (0..total).find(|i| !received.contains_key(i)) {
+            bail!("chunk {missing} of {total} is missing");
+        }
+        let bytes = received.into_values().flatten().collect::<Vec<u8>>();
+        Snapshot::from_bytes(&bytes)
+    }
+}
@@ -0,0 +1,310 @@
+use super::Snapshot;
+use crate::{Module, ValType};
+#[cfg(feature = "component-model")]
+use anyhow::Result;
+use wasmtime_environ::MemoryIndex;
+
+/// Whether a [`Snapshot`] can be restored into instances of a module or
+/// component, as reported by [`Snapshot::validate_for_module`] and
+/// [`Snapshot::validate_for_component`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotCompatibility {
+    /// The snapshot matches exactly.
+    Compatible,
+    /// The snapshot can be restored, but some state will not come from it,
+    /// for example because the new module has more globals than the
+    /// snapshot. Each string describes one such difference.
+    CompatibleWithWarnings(Vec<String>),
+    /// The snapshot cannot be restored. Each string describes one reason.
+    Incompatible(Vec<String>),
+}
+
+impl SnapshotCompatibility {
+    /// Returns whether the snapshot can be restored, possibly with warnings.
+    pub fn is_compatible(&self) -> bool {
+        !matches!(self, SnapshotCompatibility::Incompatible(_))
+    }
+}
+
+impl Snapshot {
+    /// Checks whether this snapshot can be restored into instances of the
+    /// core modules of `component`.
+    ///
+    /// A snapshot is taken from a single core instance, so it is compared
+    /// against each core module of the component in turn and the best match
+    /// is reported: [`SnapshotCompatibility::Compatible`] if any module
+    /// matches exactly, otherwise the warnings of the first module which is
+    /// compatible with warnings, and otherwise the reasons why each module is
+    /// incompatible.
+    #[cfg(feature = "component-model")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "component-model")))]
+    pub fn validate_for_component(
+        &self,
+        component: &crate::component::Component,
+    ) -> SnapshotCompatibility {
+        let mut warnings = None;
+        let mut reasons = Vec::new();
+        for (i, module) in component.static_modules().enumerate() {
+            match self.validate_for_module(module) {
+                SnapshotCompatibility::Compatible => return SnapshotCompatibility::Compatible,
+                SnapshotCompatibility::CompatibleWithWarnings(w) => {
+                    warnings.get_or_insert(w);
+                }
+                SnapshotCompatibility::Incompatible(r) => {
+                    reasons.extend(r.into_iter().map(|r| format!("core module {i}: {r}")));
+                }
+            }
+        }
+        if let Some(warnings) = warnings {
+            return SnapshotCompatibility::CompatibleWithWarnings(warnings);
+        }
+        if reasons.is_empty() {
+            reasons.push("component has no core modules".to_string());
+        }
+        SnapshotCompatibility::Incompatible(reasons)
+    }
+
+    /// Checks whether this snapshot can be restored into instances of
+    /// `module`.
+    ///
+    /// The number and sizes of the memories are checked against the limits
+    /// of the module's memories, and the number and types of the globals
+    /// against its globals. This is the check performed by
+    /// [`Instance::restore`](crate::Instance::restore), which refuses
+    /// incompatible snapshots.
+    pub fn validate_for_module(&self, module: &Module) -> SnapshotCompatibility {
+        self.validate(module, false)
+    }
+
+    /// Same as [`Snapshot::validate_for_module`], but allows the snapshot to
+    /// hold only some of the memories of `module`, as restored by
+    /// [`Instance::restore_partial`](crate::Instance::restore_partial).
+    pub(crate) fn validate_partial_for_module(&self, module: &Module) -> SnapshotCompatibility {
+        self.validate(module, true)
+    }
+
+    fn validate(&self, module: &Module, partial: bool) -> SnapshotCompatibility {
+        let env = module.env_module();
+        let mut warnings = Vec::new();
+        let mut reasons = Vec::new();
+
+        if !partial && env.memory_plans.len() != self.memories.len() {
+            reasons.push(format!(
+                "snapshot has {} memories but the module has {}",
+                self.memories.len(),
+                env.memory_plans.len()
+            ));
+        } else {
+            let page_size = wasmtime_environ::WASM_PAGE_SIZE as u64;
+            for saved in &self.memories {
+                let plan = match env.memory_plans.get(MemoryIndex::from_u32(saved.index)) {
+                    Some(plan) => plan,
+                    None => {
+                        reasons.push(format!(
+                            "memory {} does not exist in the module",
+                            saved.index
+                        ));
+                        continue;
+                    }
+                };
+                let len = saved.len() as u64;
+                if let Some(max) = plan.memory.maximum {
+                    if len > max.saturating_mul(page_size) {
+                        reasons.push(format!(
+                            "memory {} holds {len} bytes but the module allows at most {max} pages",
+                            saved.index
+                        ));
+                    }
+                }
+                if len < plan.memory.minimum.saturating_mul(page_size) {
+                    warnings.push(format!(
+                        "memory {} holds {len} bytes but the module requires at least {} pages; \
+                         the remaining bytes will be zeroed",
+                        saved.index, plan.memory.minimum
+                    ));
+                }
+            }
+        }
+
+        if env.globals.len() < self.globals.len() {
+            reasons.push(format!(
+                "snapshot has {} globals but the module has {}",
+                self.globals.len(),
+                env.globals.len()
+            ));
+        } else {
+            for (i, (global, saved)) in env.globals.values().zip(&self.globals).enumerate() {
+                let expected = ValType::from_wasm_type(&global.wasm_ty);
+                if saved.ty() != expected {
+                    reasons.push(format!(
+                        "global {i} has type {} in the snapshot but {expected} in the module",
+                        saved.ty()
+                    ));
+                }
+            }
+            let new = env.globals.len() - self.globals.len();
+            if new > 0 {
+                warnings.push(format!(
+                    "module has {new} globals not in the snapshot; they will keep their initial values"
+                ));
+            }
+        }
+
+        if !reasons.is_empty() {
+            SnapshotCompatibility::Incompatible(reasons)
+        } else if !warnings.is_empty() {
+            SnapshotCompatibility::CompatibleWithWarnings(warnings)
+        } else {
+            SnapshotCompatibility::Compatible
+        }
+    }
+}
+
+/// The result of comparing two versions of a component with
+/// [`Engine::check_snapshot_compatibility`](crate::Engine::check_snapshot_compatibility).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompatibilityReport {
+    /// Whether snapshots of the old component can be restored into the new
+    /// one, which is the case when there are no blockers.
+    pub is_compatible: bool,
+    /// Differences which do not prevent restoring snapshots but mean that
+    /// some state will not come from them, such as new globals.
+    pub warnings: Vec<String>,
+    /// Differences which prevent restoring snapshots, such as globals whose
+    /// type changed.
+    pub blockers: Vec<String>,
+}
+
+#[cfg(feature = "component-model")]
+impl crate::Engine {
+    /// Checks upfront whether snapshots taken from instances of `old` can
+    /// be restored into instances of `new`, for example before deploying a
+    /// new version of a component.
+    ///
+    /// The core modules of the two components are compared pairwise, in
+    /// order: the number, limits and kinds of their memories and the number
+    /// and types of their globals. Nothing is instantiated. The same rules as
+    /// for [`Snapshot::validate_for_component`] apply, except that the
+    /// comparison is made against the old module's declared types rather
+    /// than against the state of a particular snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either component was not compiled with this
+    /// engine.
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "component-model")))]
+    pub fn check_snapshot_compatibility(
+        &self,
+        old: &crate::component::Component,
+        new: &crate::component::Component,
+    ) -> Result<CompatibilityReport> {
+        let old = old.static_modules().collect::<Vec<_>>();
+        let new = new.static_modules().collect::<Vec<_>>();
+        if old
+            .iter()
+            .chain(&new)
+            .any(|module| !crate::Engine::same(module.engine(), self))
+        {
+            anyhow::bail!("component was not compiled with this engine");
+        }
+
+        let mut report = CompatibilityReport::default();
+        for (i, (old, new)) in old.iter().zip(&new).enumerate() {
+            let (mut warnings, mut blockers) = (Vec::new(), Vec::new());
+            compare_modules(old, new, &mut warnings, &mut blockers);
+            let prefix = |msg| format!("core module {i}: {msg}");
+            report.warnings.extend(warnings.into_iter().map(prefix));
+            report.blockers.extend(blockers.into_iter().map(prefix));
+        }
+        for i in new.len()..old.len() {
+            report.blockers.push(format!("core module {i} was removed"));
+        }
+        for i in old.len()..new.len() {
+            report.warnings.push(format!(
+                "core module {i} is new; it has no state in old snapshots"
+            ));
+        }
+        report.is_compatible = report.blockers.is_empty();
+        Ok(report)
+    }
+}
+
+/// Compares the memories and globals of two versions of a core module,
+/// recording differences which affect restoring snapshots of `old` into
+/// `new`.
+#[cfg(feature = "component-model")]
+fn compare_modules(
+    old: &Module,
+    new: &Module,
+    warnings: &mut Vec<String>,
+    blockers: &mut Vec<String>,
+) {
+    let (old, new) = (old.env_module(), new.env_module());
+
+    if old.memory_plans.len() != new.memory_plans.len() {
+        blockers.push(format!(
+            "memory count changed from {} to {}",
+            old.memory_plans.len(),
+            new.memory_plans.len()
+        ));
+    } else {
+        let plans = old.memory_plans.values().zip(new.memory_plans.values());
+        for (i, (old, new)) in plans.enumerate() {
+            let (old, new) = (&old.memory, &new.memory);
+            if old.memory64 != new.memory64 || old.shared != new.shared {
+                blockers.push(format!("memory {i} changed kind"));
+                continue;
+            }
+            match (old.maximum, new.maximum) {
+                (_, Some(new_max)) if new_max < old.minimum => blockers.push(format!(
+                    "memory {i} allows at most {new_max} pages but held at least {} before",
+                    old.minimum
+                )),
+                (Some(old_max), Some(new_max)) if new_max < old_max => warnings.push(format!(
+                    "memory {i} maximum shrank from {old_max} to {new_max} pages; \
+                     larger snapshots will not fit"
+                )),
+                (None, Some(new_max)) => warnings.push(format!(
+                    "memory {i} is now limited to {new_max} pages; larger snapshots will not fit"
+                )),
+                _ => {}
+            }
+            if new.minimum > old.minimum {
+                warnings.push(format!(
+                    "memory {i} minimum grew from {} to {} pages; \
+                     smaller snapshots will be zero-padded",
+                    old.minimum, new.minimum
+                ));
+            }
+        }
+    }
+
+    if new.globals.len() < old.globals.len() {
+        blockers.push(format!(
+            "global count shrank from {} to {}",
+            old.globals.len(),
+            new.globals.len()
+        ));
+    } else {
+        let globals = old.globals.values().zip(new.globals.values());
+        for (i, (old, new)) in globals.enumerate() {
+            let (old_ty, new_ty) = (
+                ValType::from_wasm_type(&old.wasm_ty),
+                ValType::from_wasm_type(&new.wasm_ty),
+            );
+            if old_ty != new_ty {
+                blockers.push(format!("global {i} changed type from {old_ty} to {new_ty}"));
+            } else if old.mutability && !new.mutability {
+                warnings.push(format!(
+                    "global {i} is no longer mutable; its snapshot value will be ignored"
+                ));
+            }
+        }
+        let added = new.globals.len() - old.globals.len();
+        if added > 0 {
+            warnings.push(format!(
+                "module has {added} new globals; they will keep their initial values"
+            ));
+        }
+    }
+}
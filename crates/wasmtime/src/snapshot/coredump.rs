@@ -0,0 +1,270 @@
+use super::{Snapshot, SnapshotMemory};
+use crate::{Val, V128};
+use anyhow::{anyhow, bail, Result};
+use wasmparser::{DataKind, Operator, Parser, Payload};
+
+impl Snapshot {
+    /// Encodes this snapshot in [the standard Wasm core dump format][spec],
+    /// the same format produced by
+    /// [`WasmCoreDump::serialize`](crate::WasmCoreDump::serialize).
+    ///
+    /// The snapshot is described as a single instance of the first core
+    /// module of `component` it is compatible with, as checked by
+    /// [`Snapshot::validate_for_component`]. Since a snapshot does not
+    /// capture the call stack, the core dump has no stack frames, and
+    /// non-null references held in globals are encoded as null.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if this snapshot is not compatible with any core
+    /// module of `component`.
+    ///
+    /// [spec]: https://github.com/WebAssembly/tool-conventions/blob/main/Coredump.md
+    #[cfg(feature = "component-model")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "component-model")))]
+    pub fn to_coredump_bytes(&self, component: &crate::component::Component) -> Result<Vec<u8>> {
+        let module = match component
+            .static_modules()
+            .find(|module| self.validate_for_module(module).is_compatible())
+        {
+            Some(module) => module,
+            None => bail!(
+                "snapshot is not compatible with any core module of the component: {:?}",
+                self.validate_for_component(component)
+            ),
+        };
+        self.ensure_internalized()?;
+        let env = module.env_module();
+
+        let mut core_dump = wasm_encoder::Module::new();
+        core_dump.section(&wasm_encoder::CoreDumpSection::new("snapshot"));
+
+        let page_size = wasmtime_environ::WASM_PAGE_SIZE as usize;
+        let mut memories = wasm_encoder::MemorySection::new();
+        let mut data = wasm_encoder::DataSection::new();
+        for (plan, saved) in env.memory_plans.values().zip(&self.memories) {
+            let memory_idx = memories.len();
+            memories.memory(wasm_encoder::MemoryType {
+                minimum: (saved.data.len() / page_size) as u64,
+                maximum: plan.memory.maximum,
+                memory64: plan.memory.memory64,
+                shared: plan.memory.shared,
+            });
+
+            // Chunk the data and trim runs of zeroes the same way as
+            // `WasmCoreDump::serialize` does.
+            const CHUNK_SIZE: usize = 4096;
+            for (i, chunk) in saved.data.chunks(CHUNK_SIZE).enumerate() {
+                if let Some(start) = chunk.iter().position(|byte| *byte != 0) {
+                    let end = chunk.iter().rposition(|byte| *byte != 0).unwrap() + 1;
+                    let offset = (i * CHUNK_SIZE + start) as u32;
+                    let offset = wasm_encoder::ConstExpr::i32_const(offset as i32);
+                    data.active(memory_idx, &offset, chunk[start..end].iter().copied());
+                }
+            }
+        }
+        core_dump.section(&memories);
+
+        let mut globals = wasm_encoder::GlobalSection::new();
+        for (global, val) in env.globals.values().zip(&self.globals) {
+            let (val_type, init) = match val {
+                Val::I32(x) => (
+                    wasm_encoder::ValType::I32,
+                    wasm_encoder::ConstExpr::i32_const(*x),
+                ),
+                Val::I64(x) => (
+                    wasm_encoder::ValType::I64,
+                    wasm_encoder::ConstExpr::i64_const(*x),
+                ),
+                Val::F32(x) => (
+                    wasm_encoder::ValType::F32,
+                    wasm_encoder::ConstExpr::f32_const(f32::from_bits(*x)),
+                ),
+                Val::F64(x) => (
+                    wasm_encoder::ValType::F64,
+                    wasm_encoder::ConstExpr::f64_const(f64::from_bits(*x)),
+                ),
+                Val::V128(x) => (
+                    wasm_encoder::ValType::V128,
+                    wasm_encoder::ConstExpr::v128_const(x.as_u128() as i128),
+                ),
+                Val::FuncRef(_) => (
+                    wasm_encoder::ValType::FUNCREF,
+                    wasm_encoder::ConstExpr::ref_null(wasm_encoder::HeapType::Func),
+                ),
+                Val::ExternRef(_) => (
+                    wasm_encoder::ValType::EXTERNREF,
+                    wasm_encoder::ConstExpr::ref_null(wasm_encoder::HeapType::Extern),
+                ),
+            };
+            globals.global(
+                wasm_encoder::GlobalType {
+                    val_type,
+                    mutable: global.mutability,
+                },
+                &init,
+            );
+        }
+        core_dump.section(&globals);
+        core_dump.section(&data);
+
+        let mut modules = wasm_encoder::CoreDumpModulesSection::new();
+        modules.module(module.name().unwrap_or("<anonymous-module-0>"));
+        core_dump.section(&modules);
+
+        let mut instances = wasm_encoder::CoreDumpInstancesSection::new();
+        instances.instance(
+            0,
+            0..self.memories.len() as u32,
+            0..self.globals.len() as u32,
+        );
+        core_dump.section(&instances);
+
+        core_dump.section(&wasm_encoder::CoreDumpStackSection::new("main"));
+
+        Ok(core_dump.finish())
+    }
+
+    /// Loads the state of an instance from a [Wasm core dump][spec], such as
+    /// one written by [`WasmCoreDump::serialize`](crate::WasmCoreDump::serialize)
+    /// or [`Snapshot::to_coredump_bytes`].
+    ///
+    /// If the core dump records instances, the memories and globals of the
+    /// first one are loaded; otherwise all memories and globals of the core
+    /// dump are. The resulting snapshot can be inspected or restored into an
+    /// instance of the same module for post-mortem analysis. Reference-typed
+    /// globals are always null, since core dumps cannot encode references.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` is not a valid core dump.
+    ///
+    /// [spec]: https://github.com/WebAssembly/tool-conventions/blob/main/Coredump.md
+    pub fn from_coredump(data: &[u8]) -> Result<Snapshot> {
+        let page_size = wasmtime_environ::WASM_PAGE_SIZE as u64;
+        let mut is_core_dump = false;
+        let mut memories: Vec<Vec<u8>> = Vec::new();
+        let mut globals = Vec::new();
+        let mut instance = None;
+
+        for payload in Parser::new(0).parse_all(data) {
+            match payload? {
+                Payload::CustomSection(reader) => match reader.name() {
+                    "core" => is_core_dump = true,
+                    "coreinstances" => {
+                        let mut reader = wasmparser::BinaryReader::new_with_offset(
+                            reader.data(),
+                            reader.data_offset(),
+                        );
+                        let section = <wasmparser::CoreDumpInstancesSection as wasmparser::FromReader>::from_reader(&mut reader)?;
+                        if instance.is_none() {
+                            instance = section.instances.into_iter().next();
+                        }
+                    }
+                    _ => {}
+                },
+                Payload::MemorySection(reader) => {
+                    for ty in reader {
+                        let size = ty?.initial.saturating_mul(page_size);
+                        let size = usize::try_from(size)
+                            .map_err(|_| anyhow!("memory in core dump is too large"))?;
+                        memories.push(vec![0; size]);
+                    }
+                }
+                Payload::GlobalSection(reader) => {
+                    for global in reader {
+                        globals.push(const_expr_value(&global?.init_expr)?);
+                    }
+                }
+                Payload::DataSection(reader) => {
+                    for segment in reader {
+                        let segment = segment?;
+                        let (memory_index, offset_expr) = match segment.kind {
+                            DataKind::Active {
+                                memory_index,
+                                offset_expr,
+                            } => (memory_index, offset_expr),
+                            DataKind::Passive => continue,
+                        };
+                        let offset = match const_expr_value(&offset_expr)? {
+                            Val::I32(offset) => offset as u32 as usize,
+                            Val::I64(offset) => offset as usize,
+                            _ => bail!("invalid data segment offset in core dump"),
+                        };
+                        let memory = memories
+                            .get_mut(memory_index as usize)
+                            .ok_or_else(|| anyhow!("data segment for unknown memory"))?;
+                        memory
+                            .get_mut(offset..)
+                            .and_then(|m| m.get_mut(..segment.data.len()))
+                            .ok_or_else(|| anyhow!("data segment out of bounds"))?
+                            .copy_from_slice(segment.data);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !is_core_dump {
+            bail!("not a wasm core dump");
+        }
+
+        let (memory_indices, global_indices) = match instance {
+            Some(instance) => (instance.memories, instance.globals),
+            None => (
+                (0..memories.len() as u32).collect(),
+                (0..globals.len() as u32).collect(),
+            ),
+        };
+        let memories = memory_indices
+            .iter()
+            .enumerate()
+            .map(|(index, i)| {
+                let data = memories
+                    .get(*i as usize)
+                    .ok_or_else(|| anyhow!("instance refers to unknown memory {i}"))?;
+                Ok(SnapshotMemory {
+                    index: index as u32,
+                    name: format!("memory{index}"),
+                    data: data.clone(),
+                    external: None,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let globals = global_indices
+            .iter()
+            .map(|i| {
+                globals
+                    .get(*i as usize)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("instance refers to unknown global {i}"))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Snapshot {
+            memories,
+            globals,
+            host_states: Vec::new(),
+            annotations: Default::default(),
+        })
+    }
+}
+
+/// Evaluates a constant expression of a core dump, which is always a single
+/// constant instruction.
+fn const_expr_value(expr: &wasmparser::ConstExpr<'_>) -> Result<Val> {
+    let mut reader = expr.get_operators_reader();
+    Ok(match reader.read()? {
+        Operator::I32Const { value } => Val::I32(value),
+        Operator::I64Const { value } => Val::I64(value),
+        Operator::F32Const { value } => Val::F32(value.bits()),
+        Operator::F64Const { value } => Val::F64(value.bits()),
+        Operator::V128Const { value } => Val::V128(V128::from(value.i128() as u128)),
+        Operator::RefNull {
+            hty: wasmparser::HeapType::Func,
+        } => Val::FuncRef(None),
+        Operator::RefNull {
+            hty: wasmparser::HeapType::Extern,
+        } => Val::ExternRef(None),
+        op => bail!("unsupported constant expression in core dump: {op:?}"),
+    })
+}
@@ -0,0 +1,153 @@
+use super::Snapshot;
+use crate::{Val, ValType};
+use anyhow::{bail, Result};
+use std::fmt::Write;
+use std::ops::Range;
+use wasmtime_environ::EntityIndex;
+
+/// Runs of fewer zero bytes than this between two non-zero bytes are
+/// reported as part of the same region.
+const REGION_GAP: usize = 16;
+
+/// The number of bytes of each region which are printed.
+const REGION_PREVIEW: usize = 16;
+
+impl Snapshot {
+    /// Renders the contents of this snapshot as text for humans, for
+    /// example to inspect a snapshot while debugging a component.
+    ///
+    /// The snapshot is described as an instance of the first core module of
+    /// `component` it is compatible with, as checked by
+    /// [`Snapshot::validate_for_component`]. Each global is listed with its
+    /// type, value and the name under which the core module exports it, if
+    /// any. Each memory is listed with its name and size, followed by the
+    /// regions of its contents which are not zero and their first bytes.
+    /// Compiled components do not retain DWARF debug information, so regions
+    /// are labelled by address only. Attached host states are listed by name,
+    /// followed by the annotations of the snapshot.
+    ///
+    /// The format is meant to be read and may change between releases.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if this snapshot is not compatible with any core
+    /// module of `component`.
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "component-model")))]
+    pub fn to_wit_debug_string(&self, component: &crate::component::Component) -> Result<String> {
+        let (module_index, module) = match component
+            .static_modules()
+            .enumerate()
+            .find(|(_, module)| self.validate_for_module(module).is_compatible())
+        {
+            Some(found) => found,
+            None => bail!(
+                "snapshot is not compatible with any core module of the component: {:?}",
+                self.validate_for_component(component)
+            ),
+        };
+        self.ensure_internalized()?;
+        let env = module.env_module();
+
+        let mut out = String::new();
+        write!(out, "snapshot of core module {module_index}").unwrap();
+        if let Some(name) = module.name() {
+            write!(out, " `{name}`").unwrap();
+        }
+        out.push('\n');
+
+        writeln!(out, "globals:").unwrap();
+        for ((index, global), val) in env.globals.iter().zip(&self.globals) {
+            let i = index.as_u32();
+            let name = env
+                .exports
+                .iter()
+                .find(|(_, export)| **export == EntityIndex::Global(index))
+                .map_or_else(|| format!("global{i}"), |(name, _)| name.clone());
+            let mutability = if global.mutability { "mut " } else { "" };
+            let ty = ValType::from_wasm_type(&global.wasm_ty);
+            writeln!(
+                out,
+                "  {i}: {name}: {mutability}{ty} = {}",
+                display_val(val)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "memories:").unwrap();
+        let page_size = wasmtime_environ::WASM_PAGE_SIZE as usize;
+        for memory in &self.memories {
+            let len = memory.data.len();
+            writeln!(
+                out,
+                "  {}: {}: {} pages, {len} bytes",
+                memory.index,
+                memory.name,
+                len / page_size,
+            )
+            .unwrap();
+            for region in non_zero_regions(&memory.data) {
+                let data = &memory.data[region.clone()];
+                write!(
+                    out,
+                    "    {:#010x}..{:#010x} ({} bytes):",
+                    region.start,
+                    region.end,
+                    data.len(),
+                )
+                .unwrap();
+                for byte in data.iter().take(REGION_PREVIEW) {
+                    write!(out, " {byte:02x}").unwrap();
+                }
+                if data.len() > REGION_PREVIEW {
+                    out.push_str(" ...");
+                }
+                out.push('\n');
+            }
+        }
+
+        if !self.host_states.is_empty() {
+            writeln!(out, "host states:").unwrap();
+            for (name, state) in &self.host_states {
+                writeln!(out, "  {name}: {} bytes", state.data().len()).unwrap();
+            }
+        }
+
+        if !self.annotations.is_empty() {
+            writeln!(out, "annotations:").unwrap();
+            let mut annotations = self.annotations.iter().collect::<Vec<_>>();
+            annotations.sort();
+            for (key, value) in annotations {
+                writeln!(out, "  {key}: {value}").unwrap();
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn display_val(val: &Val) -> String {
+    match val {
+        Val::I32(x) => x.to_string(),
+        Val::I64(x) => x.to_string(),
+        Val::F32(x) => f32::from_bits(*x).to_string(),
+        Val::F64(x) => f64::from_bits(*x).to_string(),
+        Val::V128(x) => format!("{:#034x}", x.as_u128()),
+        Val::FuncRef(None) | Val::ExternRef(None) => "null".to_string(),
+        Val::FuncRef(Some(_)) | Val::ExternRef(Some(_)) => "<reference>".to_string(),
+    }
+}
+
+/// Returns the ranges of `data` which are not zero, merging ranges which
+/// are separated by fewer than [`REGION_GAP`] zero bytes.
+fn non_zero_regions(data: &[u8]) -> Vec<Range<usize>> {
+    let mut regions: Vec<Range<usize>> = Vec::new();
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == 0 {
+            continue;
+        }
+        match regions.last_mut() {
+            Some(last) if i - last.end < REGION_GAP => last.end = i + 1,
+            _ => regions.push(i..i + 1),
+        }
+    }
+    regions
+}
@@ -0,0 +1,225 @@
+use super::{val_eq, Snapshot, SnapshotMemory, SuspendState};
+use crate::Val;
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// Configuration of [`Snapshot::compute_delta_with`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeltaConfig {
+    /// The granularity, in bytes, at which changes to memories are recorded.
+    ///
+    /// Smaller pages make deltas of scattered writes smaller at the cost of
+    /// more bookkeeping per page. Defaults to 4096.
+    pub page_size: usize,
+}
+
+impl Default for DeltaConfig {
+    fn default() -> DeltaConfig {
+        DeltaConfig { page_size: 4096 }
+    }
+}
+
+/// The changes between two [`Snapshot`]s of the same instance, computed with
+/// [`Snapshot::compute_delta`].
+///
+/// A delta only holds the memory pages and globals which changed, so an
+/// append-only log of deltas is much smaller than a sequence of full
+/// snapshots when only a little state changes between them. Applying a delta
+/// to the snapshot it was computed from with [`SnapshotDelta::apply`]
+/// reconstructs the newer snapshot.
+#[derive(Clone, Debug)]
+pub struct SnapshotDelta {
+    page_size: usize,
+    base_memory_sizes: Vec<usize>,
+    base_globals: usize,
+    memories: Vec<MemoryDelta>,
+    globals: usize,
+    changed_globals: Vec<(u32, Val)>,
+    host_states: Vec<(String, SuspendState)>,
+    annotations: HashMap<String, String>,
+}
+
+/// The changed pages of one memory in a [`SnapshotDelta`].
+#[derive(Clone, Debug)]
+struct MemoryDelta {
+    index: u32,
+    name: String,
+    size: usize,
+    pages: Vec<(usize, Vec<u8>)>,
+}
+
+impl Snapshot {
+    /// Computes the changes from `previous` to this snapshot, at the default
+    /// page granularity.
+    ///
+    /// See [`Snapshot::compute_delta_with`] for details.
+    pub fn compute_delta(&self, previous: &Snapshot) -> Result<SnapshotDelta> {
+        self.compute_delta_with(previous, &DeltaConfig::default())
+    }
+
+    /// Computes the changes from `previous` to this snapshot.
+    ///
+    /// Each memory is compared page by page, with pages of
+    /// `config.page_size` bytes, and only the pages which differ are stored
+    /// in the delta, along with the globals which differ. Pages added by
+    /// growing a memory are only stored if they are not all zeros. Host
+    /// states are small and always stored in full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory of either snapshot is
+    /// [stored in a file](Snapshot::export_memory_to_file).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.page_size` is zero.
+    pub fn compute_delta_with(
+        &self,
+        previous: &Snapshot,
+        config: &DeltaConfig,
+    ) -> Result<SnapshotDelta> {
+        let page_size = config.page_size;
+        assert!(page_size > 0, "page size must be non-zero");
+        self.ensure_internalized()?;
+        previous.ensure_internalized()?;
+
+        let memories = self
+            .memories
+            .iter()
+            .enumerate()
+            .map(|(i, memory)| {
+                let old = previous.memories.get(i).map(|m| &m.data[..]).unwrap_or(&[]);
+                let pages = memory
+                    .data
+                    .chunks(page_size)
+                    .enumerate()
+                    .filter(|(page, data)| {
+                        // Bytes past the end of the old memory are zero after
+                        // it is resized, so only non-zero bytes there count
+                        // as changes.
+                        let old = old.get(page * page_size..).unwrap_or(&[]);
+                        let old = &old[..old.len().min(data.len())];
+                        data[..old.len()] != *old || data[old.len()..].iter().any(|b| *b != 0)
+                    })
+                    .map(|(page, data)| (page, data.to_vec()))
+                    .collect();
+                MemoryDelta {
+                    index: memory.index,
+                    name: memory.name.clone(),
+                    size: memory.data.len(),
+                    pages,
+                }
+            })
+            .collect();
+
+        let changed_globals = self
+            .globals
+            .iter()
+            .enumerate()
+            .filter(|(i, val)| match previous.globals.get(*i) {
+                Some(old) => !val_eq(old, val),
+                None => true,
+            })
+            .map(|(i, val)| (i as u32, val.clone()))
+            .collect();
+
+        Ok(SnapshotDelta {
+            page_size,
+            base_memory_sizes: previous.memories.iter().map(|m| m.data.len()).collect(),
+            base_globals: previous.globals.len(),
+            memories,
+            globals: self.globals.len(),
+            changed_globals,
+            host_states: self.host_states.clone(),
+            annotations: self.annotations.clone(),
+        })
+    }
+}
+
+impl SnapshotDelta {
+    /// Returns the page granularity this delta was computed with.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Returns the number of changed memory pages held by this delta.
+    pub fn changed_pages(&self) -> usize {
+        self.memories.iter().map(|m| m.pages.len()).sum()
+    }
+
+    /// Returns the number of changed globals held by this delta.
+    pub fn changed_globals(&self) -> usize {
+        self.changed_globals.len()
+    }
+
+    /// Reconstructs the snapshot this delta was computed for by applying it
+    /// to a copy of `base`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `base` does not have the shape of the snapshot
+    /// the delta was computed from. Only the number and sizes of memories and
+    /// the number of globals are checked, so applying a delta to a snapshot
+    /// of the same shape but with different contents yields a meaningless
+    /// result. Also returns an error if a memory of `base` is
+    /// [stored in a file](Snapshot::export_memory_to_file).
+    pub fn apply(&self, base: &Snapshot) -> Result<Snapshot> {
+        let mut snapshot = base.clone();
+        self.apply_in_place(&mut snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// Same as [`SnapshotDelta::apply`], but modifies `snapshot` in place
+    /// instead of copying it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error under the same conditions as
+    /// [`SnapshotDelta::apply`], in which case `snapshot` is left unchanged.
+    pub fn apply_in_place(&self, snapshot: &mut Snapshot) -> Result<()> {
+        snapshot.ensure_internalized()?;
+        let sizes_match = snapshot.memories.len() == self.base_memory_sizes.len()
+            && snapshot
+                .memories
+                .iter()
+                .zip(&self.base_memory_sizes)
+                .all(|(m, size)| m.data.len() == *size);
+        if !sizes_match || snapshot.globals.len() != self.base_globals {
+            bail!("snapshot does not match the base of this delta");
+        }
+
+        snapshot.memories.truncate(self.memories.len());
+        for (i, delta) in self.memories.iter().enumerate() {
+            if i == snapshot.memories.len() {
+                snapshot.memories.push(SnapshotMemory {
+                    index: delta.index,
+                    name: delta.name.clone(),
+                    data: Vec::new(),
+                    external: None,
+                });
+            }
+            let memory = &mut snapshot.memories[i];
+            memory.data.resize(delta.size, 0);
+            for (page, data) in delta.pages.iter() {
+                let start = page * self.page_size;
+                memory.data[start..start + data.len()].copy_from_slice(data);
+            }
+        }
+
+        snapshot.globals.truncate(self.globals);
+        for (index, val) in self.changed_globals.iter() {
+            let index = *index as usize;
+            if index < snapshot.globals.len() {
+                snapshot.globals[index] = val.clone();
+            } else {
+                // Globals beyond the base are all changed, so they are
+                // appended in order.
+                snapshot.globals.push(val.clone());
+            }
+        }
+
+        snapshot.host_states.clone_from(&self.host_states);
+        snapshot.annotations.clone_from(&self.annotations);
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+use super::serialize::{decode_globals, encode_annotations, encode_globals, GlobalValue};
+use super::{Snapshot, SnapshotMemory, SuspendState};
+use crate::MemoryRegionInfo;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, bail, Result};
+use serde_derive::{Deserialize, Serialize};
+
+/// A [`Snapshot`] encrypted with AES-256-GCM for storage at rest.
+///
+/// Created with [`Snapshot::encrypt`] and turned back into a [`Snapshot`] with
+/// [`EncryptedSnapshot::decrypt`]. Each memory and the globals are encrypted
+/// independently, so that a corrupted region can be identified. Attached host
+/// states and annotations are encrypted along with the globals. The number,
+/// names and sizes of the memories are stored in the clear in an
+/// authenticated header and can be read with
+/// [`EncryptedSnapshot::region_stats`] without the key.
+#[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-encryption")))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncryptedSnapshot {
+    header: Header,
+    nonce: [u8; 12],
+    memories: Vec<Vec<u8>>,
+    globals: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Trailer {
+    globals: Vec<GlobalValue>,
+    host_states: Vec<(String, Vec<u8>)>,
+    annotations: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Header {
+    memories: Vec<RegionHeader>,
+    globals: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RegionHeader {
+    index: u32,
+    name: String,
+    size: u64,
+}
+
+impl Snapshot {
+    /// Encrypts this snapshot with the AES-256-GCM `key`, using a fresh random
+    /// nonce.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a global holds a non-null reference, which cannot
+    /// be stored outside of its store, or if a memory is
+    /// [stored in a file](Snapshot::export_memory_to_file).
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-encryption")))]
+    pub fn encrypt(&self, key: &[u8; 32]) -> Result<EncryptedSnapshot> {
+        self.ensure_internalized()?;
+        let header = Header {
+            memories: self
+                .memories
+                .iter()
+                .map(|m| RegionHeader {
+                    index: m.index,
+                    name: m.name.clone(),
+                    size: m.data.len() as u64,
+                })
+                .collect(),
+            globals: u32::try_from(self.globals.len())?,
+        };
+        let aad = bincode::serialize(&header)?;
+        let cipher = Aes256Gcm::new(key.into());
+        let nonce: [u8; 12] = Aes256Gcm::generate_nonce(&mut OsRng).into();
+
+        let mut memories = Vec::new();
+        for (i, m) in self.memories.iter().enumerate() {
+            memories.push(seal(&cipher, &nonce, i, &aad, &m.data)?);
+        }
+        let trailer = Trailer {
+            globals: encode_globals(&self.globals)?,
+            host_states: self
+                .host_states
+                .iter()
+                .map(|(name, state)| (name.clone(), state.data().to_vec()))
+                .collect(),
+            annotations: encode_annotations(&self.annotations),
+        };
+        let globals = bincode::serialize(&trailer)?;
+        let globals = seal(&cipher, &nonce, self.memories.len(), &aad, &globals)?;
+
+        Ok(EncryptedSnapshot {
+            header,
+            nonce,
+            memories,
+            globals,
+        })
+    }
+}
+
+impl EncryptedSnapshot {
+    /// Decrypts this snapshot with the AES-256-GCM `key` it was encrypted
+    /// with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `key` is wrong or any part of the snapshot was
+    /// tampered with. The error names the memory region which failed to
+    /// decrypt.
+    pub fn decrypt(&self, key: &[u8; 32]) -> Result<Snapshot> {
+        if self.memories.len() != self.header.memories.len() {
+            bail!("encrypted snapshot does not match its header");
+        }
+        let aad = bincode::serialize(&self.header)?;
+        let cipher = Aes256Gcm::new(key.into());
+
+        let mut memories = Vec::new();
+        for (i, (region, data)) in self.header.memories.iter().zip(&self.memories).enumerate() {
+            let data = open(&cipher, &self.nonce, i, &aad, data).map_err(|_| {
+                anyhow!(
+                    "failed to decrypt memory region {} ({})",
+                    region.index,
+                    region.name
+                )
+            })?;
+            if data.len() as u64 != region.size {
+                bail!("memory region {} does not match its header", region.index);
+            }
+            memories.push(SnapshotMemory {
+                index: region.index,
+                name: region.name.clone(),
+                data,
+                external: None,
+            });
+        }
+
+        let globals = open(
+            &cipher,
+            &self.nonce,
+            self.memories.len(),
+            &aad,
+            &self.globals,
+        )
+        .map_err(|_| anyhow!("failed to decrypt globals"))?;
+        let trailer: Trailer = bincode::deserialize(&globals)?;
+        let globals = decode_globals(trailer.globals);
+        if globals.len() != self.header.globals as usize {
+            bail!("globals do not match the header");
+        }
+        let host_states = trailer
+            .host_states
+            .into_iter()
+            .map(|(name, data)| (name, SuspendState::new(data)))
+            .collect();
+
+        Ok(Snapshot {
+            memories,
+            globals,
+            host_states,
+            annotations: trailer.annotations.into_iter().collect(),
+        })
+    }
+
+    /// Returns size information about each memory of the encrypted snapshot,
+    /// read from its header without decrypting anything.
+    ///
+    /// The header is only authenticated upon [`EncryptedSnapshot::decrypt`].
+    pub fn region_stats(&self) -> Vec<MemoryRegionInfo> {
+        self.header
+            .memories
+            .iter()
+            .map(|m| MemoryRegionInfo {
+                name: m.name.clone(),
+                size_bytes: m.size as usize,
+            })
+            .collect()
+    }
+
+    /// Serializes this encrypted snapshot into bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    /// Deserializes an encrypted snapshot previously serialized with
+    /// [`EncryptedSnapshot::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<EncryptedSnapshot> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Derives the nonce of the `region`th region from the snapshot's random
+/// nonce, so that no two regions are encrypted with the same nonce.
+fn region_nonce(nonce: &[u8; 12], region: usize) -> Nonce<<Aes256Gcm as AeadCore>::NonceSize> {
+    let mut nonce = *nonce;
+    for (n, r) in nonce[8..].iter_mut().zip((region as u32).to_le_bytes()) {
+        *n ^= r;
+    }
+    nonce.into()
+}
+
+fn seal(
+    cipher: &Aes256Gcm,
+    nonce: &[u8; 12],
+    region: usize,
+    aad: &[u8],
+    msg: &[u8],
+) -> Result<Vec<u8>> {
+    cipher
+        .encrypt(&region_nonce(nonce, region), Payload { msg, aad })
+        .map_err(|_| anyhow!("failed to encrypt snapshot"))
+}
+
+fn open(
+    cipher: &Aes256Gcm,
+    nonce: &[u8; 12],
+    region: usize,
+    aad: &[u8],
+    msg: &[u8],
+) -> Result<Vec<u8>, aes_gcm::Error> {
+    cipher.decrypt(&region_nonce(nonce, region), Payload { msg, aad })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Val;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            memories: vec![
+                SnapshotMemory {
+                    index: 0,
+                    name: "mem".to_string(),
+                    data: vec![1, 2, 3, 4],
+                    external: None,
+                },
+                SnapshotMemory {
+                    index: 1,
+                    name: "memory1".to_string(),
+                    data: vec![0; 16],
+                    external: None,
+                },
+            ],
+            globals: vec![
+                Val::I32(7),
+                Val::F64(1.5f64.to_bits()),
+                Val::ExternRef(None),
+            ],
+            host_states: vec![("clock".to_string(), SuspendState::new(vec![9, 9]))],
+            annotations: [("version".to_string(), "2.3.1".to_string())].into(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let key = [42; 32];
+        let snapshot = snapshot();
+        let encrypted = EncryptedSnapshot::from_bytes(&snapshot.encrypt(&key)?.to_bytes())?;
+        assert_eq!(encrypted.region_stats(), snapshot.region_stats());
+
+        let decrypted = encrypted.decrypt(&key)?;
+        assert!(Snapshot::diff_report(&snapshot, &decrypted)?.is_identical());
+        assert_eq!(decrypted.host_states(), snapshot.host_states());
+        assert_eq!(decrypted.get_annotation("version"), Some("2.3.1"));
+        assert!(encrypted.decrypt(&[0; 32]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_region() -> Result<()> {
+        let key = [42; 32];
+        let mut encrypted = snapshot().encrypt(&key)?;
+        encrypted.memories[1][0] ^= 1;
+        let err = encrypted.decrypt(&key).unwrap_err();
+        assert!(err.to_string().contains("memory region 1"), "{err}");
+
+        // The header is authenticated as well.
+        let mut encrypted = snapshot().encrypt(&key)?;
+        encrypted.header.memories[0].name = "other".to_string();
+        assert!(encrypted.decrypt(&key).is_err());
+        Ok(())
+    }
+}
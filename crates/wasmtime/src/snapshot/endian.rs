@@ -0,0 +1,148 @@
+use super::Snapshot;
+use crate::{Val, ValType};
+use anyhow::{bail, ensure, Result};
+use std::ops::Range;
+
+/// The byte order of a host, for converting snapshots between hosts with
+/// [`Snapshot::normalize_globals`] and [`Snapshot::normalize_memory_words`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Endianness {
+    /// The least significant byte comes first, as on x86-64 and AArch64.
+    Little,
+    /// The most significant byte comes first.
+    Big,
+}
+
+impl Endianness {
+    /// Returns the byte order of the current host.
+    pub fn native() -> Endianness {
+        if cfg!(target_endian = "big") {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+}
+
+/// The size of the words swapped by [`Snapshot::normalize_memory_words`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum WordSize {
+    /// 2-byte words.
+    U16,
+    /// 4-byte words.
+    U32,
+    /// 8-byte words.
+    U64,
+}
+
+impl WordSize {
+    fn bytes(self) -> usize {
+        match self {
+            WordSize::U16 => 2,
+            WordSize::U32 => 4,
+            WordSize::U64 => 8,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Returns a copy of this snapshot whose globals are converted from the
+    /// byte order `from` to the byte order `to`, for snapshots whose globals
+    /// were captured as raw bytes on a host of a different byte order.
+    ///
+    /// `global_types` gives the type of every global of the snapshot, in
+    /// index order. Values of type `i32`, `i64`, `f32` and `f64` have their
+    /// bytes reversed, while vectors and references are left as they are.
+    /// Nothing changes if `from` and `to` are the same.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `global_types` does not list as many types as the
+    /// snapshot has globals, or if a global does not hold a value of its
+    /// listed type.
+    pub fn normalize_globals(
+        &self,
+        from: Endianness,
+        to: Endianness,
+        global_types: &[ValType],
+    ) -> Result<Snapshot> {
+        ensure!(
+            global_types.len() == self.globals.len(),
+            "{} global types were given for a snapshot with {} globals",
+            global_types.len(),
+            self.globals.len()
+        );
+        let mut snapshot = self.clone();
+        for (index, (global, ty)) in snapshot.globals.iter_mut().zip(global_types).enumerate() {
+            if global.ty() != *ty {
+                bail!(
+                    "global {index} holds a value of type {}, but type {ty} was given",
+                    global.ty()
+                );
+            }
+            if from == to {
+                continue;
+            }
+            *global = match *global {
+                Val::I32(v) => Val::I32(v.swap_bytes()),
+                Val::I64(v) => Val::I64(v.swap_bytes()),
+                Val::F32(bits) => Val::F32(bits.swap_bytes()),
+                Val::F64(bits) => Val::F64(bits.swap_bytes()),
+                ref v => v.clone(),
+            };
+        }
+        Ok(snapshot)
+    }
+
+    /// Returns a copy of this snapshot in which the words of `word_size`
+    /// bytes within `ranges` of memory `memory_index` are converted from the
+    /// byte order `from` to the byte order `to`.
+    ///
+    /// Linear memory is little-endian on every host, so this is only needed
+    /// for data a module stores in the byte order of the host, such as
+    /// buffers shared with native code. Nothing changes if `from` and `to`
+    /// are the same.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot, or if
+    /// a range extends past its end or does not span a whole number of
+    /// words. In these cases no word is swapped.
+    pub fn normalize_memory_words(
+        &self,
+        memory_index: u32,
+        word_size: WordSize,
+        from: Endianness,
+        to: Endianness,
+        ranges: &[Range<usize>],
+    ) -> Result<Snapshot> {
+        let len = self.memory_data(memory_index)?.len();
+        let size = word_size.bytes();
+        for range in ranges {
+            ensure!(
+                range.start <= range.end && range.end <= len,
+                "range {range:#x?} lies outside of memory {memory_index} ({len:#x} bytes)"
+            );
+            ensure!(
+                range.len() % size == 0,
+                "range {range:#x?} does not span a whole number of {size}-byte words"
+            );
+        }
+
+        let mut snapshot = self.clone();
+        if from == to {
+            return Ok(snapshot);
+        }
+        let memory = snapshot
+            .memories
+            .iter_mut()
+            .find(|m| m.index == memory_index)
+            .unwrap();
+        for range in ranges {
+            for word in memory.data[range.clone()].chunks_exact_mut(size) {
+                word.reverse();
+            }
+        }
+        Ok(snapshot)
+    }
+}
@@ -0,0 +1,198 @@
+use super::{Snapshot, SnapshotMemory};
+#[cfg(feature = "snapshot-hash")]
+use anyhow::Context;
+use anyhow::{bail, Result};
+use serde_derive::{Deserialize, Serialize};
+#[cfg(feature = "snapshot-hash")]
+use std::io;
+#[cfg(feature = "snapshot-hash")]
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The file a memory of a [`Snapshot`] was moved to with
+/// [`Snapshot::export_memory_to_file`], kept in its place.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct ExternalMemory {
+    /// The path the memory was written to.
+    #[cfg_attr(not(feature = "snapshot-hash"), allow(dead_code))]
+    path: PathBuf,
+    /// The size of the memory in bytes.
+    len: u64,
+    /// A Blake3 hash of the contents of the memory.
+    #[cfg_attr(not(feature = "snapshot-hash"), allow(dead_code))]
+    hash: [u8; 32],
+}
+
+impl Snapshot {
+    /// Writes the contents of memory `memory_index` to the file `dest` as raw
+    /// bytes and removes them from this snapshot, for memories too large to
+    /// keep in RAM.
+    ///
+    /// The snapshot keeps the path, size and hash of the file in place of the
+    /// contents, and is then [externalized](Snapshot::is_externalized): it
+    /// can be serialized with [`Snapshot::to_bytes`], but has to be
+    /// [internalized](Snapshot::internalize) before it is restored. Its size
+    /// is still reported by [`Snapshot::size_bytes`] and
+    /// [`Snapshot::region_stats`], and its hash by
+    /// [`Snapshot::compute_hash`] and [`Snapshot::memory_checksum_map`].
+    /// Until then, all other methods which read or modify its contents, such
+    /// as [`Snapshot::memory_regions`] and [`Snapshot::diff_report`], return
+    /// an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot, is
+    /// already stored in a file, or if the file cannot be written. In these
+    /// cases the snapshot is unchanged.
+    #[cfg(feature = "snapshot-hash")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+    pub fn export_memory_to_file(
+        &mut self,
+        memory_index: u32,
+        dest: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        let memory = self.memory_mut(memory_index)?;
+        if memory.external.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("memory {memory_index} is already stored in a file"),
+            ));
+        }
+        std::fs::write(dest.as_ref(), &memory.data)?;
+        memory.external = Some(ExternalMemory {
+            path: dest.as_ref().to_path_buf(),
+            len: memory.data.len() as u64,
+            hash: *blake3::hash(&memory.data).as_bytes(),
+        });
+        memory.data = Vec::new();
+        Ok(())
+    }
+
+    /// Reads the contents of memory `memory_index` back from the file `src`,
+    /// replacing the contents held by this snapshot.
+    ///
+    /// If the memory was moved out with [`Snapshot::export_memory_to_file`],
+    /// the file must hold exactly the bytes which were written, although it
+    /// may have been moved since. Otherwise, the file can hold any raw image
+    /// of the memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot, if the
+    /// file cannot be read, or if its contents do not match those exported.
+    /// In these cases the snapshot is unchanged.
+    #[cfg(feature = "snapshot-hash")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+    pub fn import_memory_from_file(
+        &mut self,
+        memory_index: u32,
+        src: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        let memory = self.memory_mut(memory_index)?;
+        let data = std::fs::read(src.as_ref())?;
+        if let Some(external) = &memory.external {
+            if data.len() as u64 != external.len || *blake3::hash(&data).as_bytes() != external.hash
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "`{}` does not hold the contents of memory {memory_index}",
+                        src.as_ref().display()
+                    ),
+                ));
+            }
+        }
+        memory.data = data;
+        memory.external = None;
+        Ok(())
+    }
+
+    /// Returns whether any memory of this snapshot is stored in a file.
+    pub fn is_externalized(&self) -> bool {
+        self.memories.iter().any(|m| m.external.is_some())
+    }
+
+    /// Returns a copy of this snapshot with the contents of every memory
+    /// stored in a file read back into it.
+    ///
+    /// Relative paths given to [`Snapshot::export_memory_to_file`] are
+    /// resolved against `base_dir`, so that a snapshot can be moved together
+    /// with its memory files. Absolute paths are used as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be read or does not hold the
+    /// contents which were exported to it.
+    #[cfg(feature = "snapshot-hash")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+    pub fn internalize(&self, base_dir: &Path) -> Result<Snapshot> {
+        let mut snapshot = self.clone();
+        for memory in self.memories.iter() {
+            if let Some(external) = &memory.external {
+                let path = base_dir.join(&external.path);
+                snapshot
+                    .import_memory_from_file(memory.index, &path)
+                    .with_context(|| {
+                        format!(
+                            "failed to read memory {} from `{}`",
+                            memory.index,
+                            path.display()
+                        )
+                    })?;
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Fails if any memory of this snapshot is stored in a file.
+    pub(super) fn ensure_internalized(&self) -> Result<()> {
+        self.memories
+            .iter()
+            .try_for_each(|m| m.ensure_internalized())
+    }
+
+    #[cfg(feature = "snapshot-hash")]
+    fn memory_mut(&mut self, memory_index: u32) -> io::Result<&mut SnapshotMemory> {
+        self.memories
+            .iter_mut()
+            .find(|m| m.index == memory_index)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("memory {memory_index} does not exist in this snapshot"),
+                )
+            })
+    }
+}
+
+impl SnapshotMemory {
+    /// Returns the size of this memory in bytes, whether its contents are
+    /// held by the snapshot or stored in a file.
+    pub(super) fn len(&self) -> usize {
+        match &self.external {
+            Some(external) => external.len as usize,
+            None => self.data.len(),
+        }
+    }
+
+    /// Returns a Blake3 hash of the contents of this memory, whether they are
+    /// held by the snapshot or stored in a file.
+    #[cfg(feature = "snapshot-hash")]
+    pub(super) fn content_hash(&self) -> [u8; 32] {
+        match &self.external {
+            Some(external) => external.hash,
+            None => *blake3::hash(&self.data).as_bytes(),
+        }
+    }
+
+    /// Fails if the contents of this memory are stored in a file.
+    pub(super) fn ensure_internalized(&self) -> Result<()> {
+        if self.external.is_some() {
+            bail!(
+                "memory {} of the snapshot is stored in a file; call `Snapshot::internalize` first",
+                self.index
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use super::Snapshot;
+use crate::Val;
+use anyhow::{anyhow, bail, Result};
+use std::ops::Range;
+
+/// Where the live objects of a heap were moved by [`Snapshot::gc_compact`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcRemapping {
+    /// The old address range and new address of each live object, sorted by
+    /// old address.
+    objects: Vec<(Range<usize>, usize)>,
+    heap_end: usize,
+}
+
+impl GcRemapping {
+    /// Returns the new address of `old_address`, or `None` if it does not
+    /// point into a live object.
+    ///
+    /// Pointers into the middle of an object are moved along with it, so
+    /// this can be used to fix up pointers stored inside objects, which the
+    /// collector does not rewrite itself.
+    pub fn remap(&self, old_address: usize) -> Option<usize> {
+        let i = self
+            .objects
+            .partition_point(|(old, _)| old.start <= old_address)
+            .checked_sub(1)?;
+        let (old, new) = &self.objects[i];
+        if old.contains(&old_address) {
+            Some(old_address - old.start + new)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the old and new address of each live object, in ascending
+    /// order.
+    pub fn objects(&self) -> impl ExactSizeIterator<Item = (usize, usize)> + '_ {
+        self.objects.iter().map(|(old, new)| (old.start, *new))
+    }
+
+    /// Returns the address just past the last live object after compaction,
+    /// where the guest's allocator can continue allocating.
+    pub fn heap_end(&self) -> usize {
+        self.heap_end
+    }
+}
+
+impl Snapshot {
+    /// Returns a copy of this snapshot in which the live objects of a
+    /// garbage-collected heap in memory `memory_index` are packed together,
+    /// along with where each object was moved.
+    ///
+    /// The heap is taken to span everything after the first `header_size`
+    /// bytes of the memory, which hold data that is not managed by the
+    /// collector, such as the stack and static data of the module, and are
+    /// left unchanged. `live_roots` holds the address of every live object
+    /// and `record_size_fn` returns the size in bytes of the object at an
+    /// address, given the contents of the memory. Live objects are copied to
+    /// the start of the heap in ascending order of address, and the rest of
+    /// the heap is zeroed.
+    ///
+    /// Objects are not traced, so objects which are only reachable through
+    /// other objects must be listed in `live_roots` as well. Pointers stored
+    /// inside objects are not rewritten either; [`GcRemapping::remap`] can be
+    /// used to fix them up. `i32` and `i64` globals whose value points into a
+    /// live object are moved along with it, so globals holding integers which
+    /// happen to look like such a pointer are changed too.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot, if an
+    /// object is not within the heap, or if two objects overlap.
+    pub fn gc_compact(
+        &self,
+        memory_index: u32,
+        live_roots: &[usize],
+        header_size: usize,
+        record_size_fn: impl Fn(&[u8], usize) -> usize,
+    ) -> Result<(Snapshot, GcRemapping)> {
+        let mut snapshot = self.clone();
+        let memory = snapshot
+            .memories
+            .iter_mut()
+            .find(|m| m.index == memory_index)
+            .ok_or_else(|| anyhow!("memory {memory_index} does not exist in this snapshot"))?;
+        memory.ensure_internalized()?;
+        let len = memory.data.len();
+        if header_size > len {
+            bail!(
+                "header of {header_size:#x} bytes exceeds memory {memory_index} ({len:#x} bytes)"
+            );
+        }
+
+        let mut roots = live_roots.to_vec();
+        roots.sort_unstable();
+        roots.dedup();
+        let mut objects: Vec<(Range<usize>, usize)> = Vec::with_capacity(roots.len());
+        let mut heap_end = header_size;
+        for root in roots {
+            let size = record_size_fn(&memory.data, root);
+            let old = root..root.saturating_add(size);
+            if old.start < header_size || old.end > len {
+                bail!(
+                    "object {:#x}..{:#x} is outside of the heap {header_size:#x}..{len:#x} \
+                     of memory {memory_index}",
+                    old.start,
+                    old.end,
+                );
+            }
+            if let Some((prev, _)) = objects.last() {
+                if old.start < prev.end {
+                    bail!(
+                        "object {:#x}..{:#x} overlaps object {:#x}..{:#x}",
+                        old.start,
+                        old.end,
+                        prev.start,
+                        prev.end,
+                    );
+                }
+            }
+            // Objects only ever move down, so copying them in ascending order
+            // never overwrites one which is yet to be copied.
+            memory.data.copy_within(old.clone(), heap_end);
+            objects.push((old, heap_end));
+            heap_end += size;
+        }
+        memory.data[heap_end..].fill(0);
+
+        let remapping = GcRemapping { objects, heap_end };
+        for global in snapshot.globals.iter_mut() {
+            match global {
+                Val::I32(value) => {
+                    if let Some(new) = remapping.remap(*value as u32 as usize) {
+                        *value = new as u32 as i32;
+                    }
+                }
+                Val::I64(value) => {
+                    if let Some(new) = usize::try_from(*value as u64)
+                        .ok()
+                        .and_then(|old| remapping.remap(old))
+                    {
+                        *value = new as u64 as i64;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok((snapshot, remapping))
+    }
+}
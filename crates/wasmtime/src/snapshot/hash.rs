@@ -0,0 +1,105 @@
+use super::Snapshot;
+use crate::Val;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A Blake3 hash of the contents of a [`Snapshot`], created with
+/// [`Snapshot::compute_hash`].
+///
+/// Snapshots with identical contents have identical hashes, so the hash can
+/// be used as a key for content-addressed storage. It is displayed as
+/// lowercase hex.
+#[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SnapshotHash([u8; 32]);
+
+impl SnapshotHash {
+    /// Returns the raw bytes of this hash.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for SnapshotHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0.iter() {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// Computes a Blake3 hash over the contents of this snapshot.
+    ///
+    /// The memories, globals and attached host states are hashed in index
+    /// order, each memory through the hash of its contents, so that a memory
+    /// [stored in a file](Snapshot::export_memory_to_file) hashes the same as
+    /// when it is held by the snapshot. Non-null references in globals cannot
+    /// be identified outside of their store and only contribute the fact that
+    /// they are non-null.
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+    pub fn compute_hash(&self) -> SnapshotHash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&(self.memories.len() as u64).to_le_bytes());
+        for m in self.memories.iter() {
+            hasher.update(&m.index.to_le_bytes());
+            hasher.update(&(m.len() as u64).to_le_bytes());
+            hasher.update(&m.content_hash());
+        }
+        hash_globals(&mut hasher, &self.globals);
+        hasher.update(&(self.host_states.len() as u64).to_le_bytes());
+        for (name, state) in self.host_states.iter() {
+            hasher.update(&(name.len() as u64).to_le_bytes());
+            hasher.update(name.as_bytes());
+            hasher.update(&(state.data().len() as u64).to_le_bytes());
+            hasher.update(state.data());
+        }
+        SnapshotHash(*hasher.finalize().as_bytes())
+    }
+
+    /// Returns a Blake3 hash of the contents of each memory, by memory index.
+    ///
+    /// Comparing the checksums of two snapshots is much cheaper than
+    /// comparing their memories byte by byte, and memories with equal
+    /// checksums have identical contents. This makes it possible to verify a
+    /// [`Instance::restore`](crate::Instance::restore) by snapshotting again
+    /// and comparing checksums. Memories
+    /// [stored in a file](Snapshot::export_memory_to_file) have the
+    /// checksum of the file's contents.
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+    pub fn memory_checksum_map(&self) -> HashMap<u32, [u8; 32]> {
+        self.memories
+            .iter()
+            .map(|m| (m.index, m.content_hash()))
+            .collect()
+    }
+
+    /// Returns a Blake3 hash of the globals of this snapshot, like
+    /// [`Snapshot::memory_checksum_map`] does for memories.
+    ///
+    /// Non-null references only contribute the fact that they are non-null,
+    /// as in [`Snapshot::compute_hash`].
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-hash")))]
+    pub fn globals_checksum(&self) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hash_globals(&mut hasher, &self.globals);
+        *hasher.finalize().as_bytes()
+    }
+}
+
+fn hash_globals(hasher: &mut blake3::Hasher, globals: &[Val]) {
+    hasher.update(&(globals.len() as u64).to_le_bytes());
+    for g in globals.iter() {
+        match g {
+            Val::I32(i) => hasher.update(&[0]).update(&i.to_le_bytes()),
+            Val::I64(i) => hasher.update(&[1]).update(&i.to_le_bytes()),
+            Val::F32(f) => hasher.update(&[2]).update(&f.to_le_bytes()),
+            Val::F64(f) => hasher.update(&[3]).update(&f.to_le_bytes()),
+            Val::V128(v) => hasher.update(&[4]).update(&v.as_u128().to_le_bytes()),
+            Val::FuncRef(f) => hasher.update(&[5, f.is_some() as u8]),
+            Val::ExternRef(e) => hasher.update(&[6, e.is_some() as u8]),
+        };
+    }
+}
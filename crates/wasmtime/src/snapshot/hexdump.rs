@@ -0,0 +1,158 @@
+use super::Snapshot;
+use crate::{AsContextMut, Instance, Val};
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// The number of bytes on each line of a hex dump.
+const BYTES_PER_LINE: usize = 16;
+
+/// The width of the address column of a hex dump, including the space after
+/// it.
+const ADDRESS_WIDTH: usize = 12;
+
+/// Labels for offsets in the memories of a [`Snapshot`], shown by
+/// [`Snapshot::annotated_hex_dump`].
+///
+/// Labels are either given directly, for example from a symbol table, or
+/// looked up as exported globals holding an address, as for
+/// [`SensitiveRegionMap`](crate::SensitiveRegionMap).
+#[derive(Clone, Debug, Default)]
+pub struct AnnotationMap {
+    labels: BTreeMap<(u32, usize), String>,
+}
+
+impl AnnotationMap {
+    /// Creates an empty map.
+    pub fn new() -> AnnotationMap {
+        AnnotationMap::default()
+    }
+
+    /// Labels the byte at `offset` in memory `memory_index` with `label`,
+    /// replacing any label it had.
+    pub fn label(
+        &mut self,
+        memory_index: u32,
+        offset: usize,
+        label: impl Into<String>,
+    ) -> &mut Self {
+        self.labels.insert((memory_index, offset), label.into());
+        self
+    }
+
+    /// Labels the byte of memory 0 at the address held by the global `name`
+    /// exported by `instance` with `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `instance` does not export an `i32` or `i64`
+    /// global called `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own `instance`.
+    pub fn symbol(
+        &mut self,
+        store: impl AsContextMut,
+        instance: &Instance,
+        name: &str,
+    ) -> Result<&mut Self> {
+        let offset = match instance.read_global(store, name)? {
+            Val::I32(address) => address as u32 as usize,
+            Val::I64(address) => usize::try_from(address as u64)?,
+            other => bail!("global `{name}` holds a {} and not an address", other.ty()),
+        };
+        Ok(self.label(0, offset, name))
+    }
+
+    /// Returns the label of the byte at `offset` in memory `memory_index`,
+    /// if any.
+    pub fn get(&self, memory_index: u32, offset: usize) -> Option<&str> {
+        self.labels
+            .get(&(memory_index, offset))
+            .map(|label| label.as_str())
+    }
+
+    fn in_range(
+        &self,
+        memory_index: u32,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (usize, &str)> + '_ {
+        self.labels
+            .range((memory_index, range.start)..(memory_index, range.end))
+            .map(|((_, offset), label)| (*offset, label.as_str()))
+    }
+}
+
+impl Snapshot {
+    /// Renders the bytes of memory `memory_index` within `range` as a hex
+    /// dump with 16 bytes per line and their ASCII characters alongside,
+    /// for post-mortem analysis of a component.
+    ///
+    /// Every labelled byte of `annotations` is marked on a line below the
+    /// line holding it:
+    ///
+    /// ```text
+    /// 0x00000100  03 00 00 00 00 00 00 00  2a 00 00 00 00 00 00 00  |........*.......|
+    ///             ^ count
+    ///                                      ^ answer
+    /// ```
+    ///
+    /// The format is meant to be read and may change between releases.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot, or if
+    /// `range` extends past its end.
+    pub fn annotated_hex_dump(
+        &self,
+        memory_index: u32,
+        range: Range<usize>,
+        annotations: &AnnotationMap,
+    ) -> Result<String> {
+        let data = self.memory_data(memory_index)?;
+        if range.start > range.end || range.end > data.len() {
+            bail!(
+                "range {range:#x?} lies outside of memory {memory_index} ({:#x} bytes)",
+                data.len()
+            );
+        }
+
+        let mut out = String::new();
+        let mut start = range.start;
+        while start < range.end {
+            let end = range.end.min(start + BYTES_PER_LINE);
+            let bytes = &data[start..end];
+
+            write!(out, "{start:#010x}  ").unwrap();
+            for i in 0..BYTES_PER_LINE {
+                match bytes.get(i) {
+                    Some(byte) => write!(out, "{byte:02x} ").unwrap(),
+                    None => out.push_str("   "),
+                }
+                if i == BYTES_PER_LINE / 2 - 1 {
+                    out.push(' ');
+                }
+            }
+            out.push_str(" |");
+            out.extend(bytes.iter().map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            }));
+            out.push_str("|\n");
+
+            for (offset, label) in annotations.in_range(memory_index, start..end) {
+                let column = ADDRESS_WIDTH
+                    + (offset - start) * 3
+                    + usize::from(offset - start >= BYTES_PER_LINE / 2);
+                writeln!(out, "{:column$}^ {label}", "").unwrap();
+            }
+            start = end;
+        }
+        Ok(out)
+    }
+}
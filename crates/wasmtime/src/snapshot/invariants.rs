@@ -0,0 +1,192 @@
+use super::Snapshot;
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+
+/// A structural property of the memories of a [`Snapshot`], checked with
+/// [`Snapshot::check_invariants`].
+///
+/// Memories are identified by their index within the module's memory index
+/// space, and integers are read in little-endian order, as wasm does.
+pub enum MemoryInvariant {
+    /// The bytes at `offset` equal `expected`.
+    ByteEquals {
+        /// The memory to check.
+        memory: u32,
+        /// The offset of the first byte.
+        offset: usize,
+        /// The expected bytes.
+        expected: Vec<u8>,
+    },
+    /// The `u32` at `offset` equals `expected`.
+    U32Equals {
+        /// The memory to check.
+        memory: u32,
+        /// The offset of the value.
+        offset: usize,
+        /// The expected value.
+        expected: u32,
+    },
+    /// The `u32` at `offset` lies within `lo..=hi`.
+    U32RangeInclusive {
+        /// The memory to check.
+        memory: u32,
+        /// The offset of the value.
+        offset: usize,
+        /// The smallest allowed value.
+        lo: u32,
+        /// The largest allowed value.
+        hi: u32,
+    },
+    /// `check` returns `true` for the contents of `memory`.
+    Custom {
+        /// The memory to check.
+        memory: u32,
+        /// The check, given the whole contents of the memory.
+        check: MemoryCheck,
+    },
+}
+
+/// A custom check of the contents of a memory.
+type MemoryCheck = Box<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+impl fmt::Debug for MemoryInvariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryInvariant::ByteEquals {
+                memory,
+                offset,
+                expected,
+            } => f
+                .debug_struct("ByteEquals")
+                .field("memory", memory)
+                .field("offset", offset)
+                .field("expected", expected)
+                .finish(),
+            MemoryInvariant::U32Equals {
+                memory,
+                offset,
+                expected,
+            } => f
+                .debug_struct("U32Equals")
+                .field("memory", memory)
+                .field("offset", offset)
+                .field("expected", expected)
+                .finish(),
+            MemoryInvariant::U32RangeInclusive {
+                memory,
+                offset,
+                lo,
+                hi,
+            } => f
+                .debug_struct("U32RangeInclusive")
+                .field("memory", memory)
+                .field("offset", offset)
+                .field("lo", lo)
+                .field("hi", hi)
+                .finish(),
+            MemoryInvariant::Custom { memory, .. } => f
+                .debug_struct("Custom")
+                .field("memory", memory)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Checks that the memories of this snapshot satisfy all of
+    /// `invariants`, for example before restoring a snapshot received from
+    /// an untrusted source.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first invariant which does not hold,
+    /// including invariants referring to a memory this snapshot does not
+    /// have or to bytes beyond the end of a memory.
+    pub fn check_invariants(&self, invariants: &[MemoryInvariant]) -> Result<()> {
+        for (i, invariant) in invariants.iter().enumerate() {
+            self.check_invariant(invariant)
+                .map_err(|e| e.context(format!("memory invariant {i} does not hold")))?;
+        }
+        Ok(())
+    }
+
+    fn check_invariant(&self, invariant: &MemoryInvariant) -> Result<()> {
+        match invariant {
+            MemoryInvariant::ByteEquals {
+                memory,
+                offset,
+                expected,
+            } => {
+                let actual = self.invariant_bytes(*memory, *offset, expected.len())?;
+                if actual != &expected[..] {
+                    bail!(
+                        "bytes at offset {offset:#x} of memory {memory} are {actual:02x?}, \
+                         expected {expected:02x?}"
+                    );
+                }
+            }
+            MemoryInvariant::U32Equals {
+                memory,
+                offset,
+                expected,
+            } => {
+                let actual = self.invariant_u32(*memory, *offset)?;
+                if actual != *expected {
+                    bail!(
+                        "u32 at offset {offset:#x} of memory {memory} is {actual}, \
+                         expected {expected}"
+                    );
+                }
+            }
+            MemoryInvariant::U32RangeInclusive {
+                memory,
+                offset,
+                lo,
+                hi,
+            } => {
+                let actual = self.invariant_u32(*memory, *offset)?;
+                if !(*lo..=*hi).contains(&actual) {
+                    bail!(
+                        "u32 at offset {offset:#x} of memory {memory} is {actual}, \
+                         expected a value in {lo}..={hi}"
+                    );
+                }
+            }
+            MemoryInvariant::Custom { memory, check } => {
+                if !check(self.invariant_memory(*memory)?) {
+                    bail!("custom check of memory {memory} failed");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn invariant_memory(&self, memory: u32) -> Result<&[u8]> {
+        let memory = self
+            .memories
+            .iter()
+            .find(|m| m.index == memory)
+            .ok_or_else(|| anyhow!("snapshot has no memory {memory}"))?;
+        memory.ensure_internalized()?;
+        Ok(&memory.data)
+    }
+
+    fn invariant_bytes(&self, memory: u32, offset: usize, len: usize) -> Result<&[u8]> {
+        let data = self.invariant_memory(memory)?;
+        offset
+            .checked_add(len)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| {
+                anyhow!(
+                    "{len} bytes at offset {offset:#x} are out of bounds of memory {memory} \
+                     of {} bytes",
+                    data.len()
+                )
+            })
+    }
+
+    fn invariant_u32(&self, memory: u32, offset: usize) -> Result<u32> {
+        let bytes = self.invariant_bytes(memory, offset, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,110 @@
+use super::Snapshot;
+use anyhow::{anyhow, Result};
+use std::io::{self, BufRead, Cursor, Read, Seek, SeekFrom, Write};
+
+/// Reads the contents of one memory of a [`Snapshot`] as a stream, returned
+/// by [`Snapshot::memory_as_reader`].
+///
+/// The reader borrows the memory rather than copying it, and starts at
+/// offset 0. It implements [`BufRead`] as well, so it can be passed to
+/// deserializers reading from buffered streams without a [`io::BufReader`].
+#[derive(Clone, Debug)]
+pub struct SnapshotMemoryReader<'a> {
+    cursor: Cursor<&'a [u8]>,
+}
+
+/// Writes into one memory of a [`Snapshot`] as a stream, returned by
+/// [`Snapshot::memory_as_writer`].
+///
+/// The writer borrows the memory rather than copying it, and starts at
+/// offset 0. Memories keep their size: writes past the end of the memory
+/// write nothing, so [`Write::write_all`] fails with
+/// [`io::ErrorKind::WriteZero`]. Use [`Snapshot::pad_memory`] first to make
+/// room for more data.
+#[derive(Debug)]
+pub struct SnapshotMemoryWriter<'a> {
+    cursor: Cursor<&'a mut [u8]>,
+}
+
+impl Snapshot {
+    /// Returns a reader over the contents of memory `memory_index`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot.
+    pub fn memory_as_reader(&self, memory_index: u32) -> Result<SnapshotMemoryReader<'_>> {
+        Ok(SnapshotMemoryReader {
+            cursor: Cursor::new(self.memory_data(memory_index)?),
+        })
+    }
+
+    /// Returns a writer over the contents of memory `memory_index`, which
+    /// modifies this snapshot in place.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memory does not exist in this snapshot.
+    pub fn memory_as_writer(&mut self, memory_index: u32) -> Result<SnapshotMemoryWriter<'_>> {
+        let memory = self
+            .memories
+            .iter_mut()
+            .find(|m| m.index == memory_index)
+            .ok_or_else(|| anyhow!("memory {memory_index} does not exist in this snapshot"))?;
+        memory.ensure_internalized()?;
+        Ok(SnapshotMemoryWriter {
+            cursor: Cursor::new(&mut memory.data[..]),
+        })
+    }
+}
+
+impl SnapshotMemoryReader<'_> {
+    /// Returns the offset within the memory of the next byte to read.
+    pub fn position(&self) -> u64 {
+        self.cursor.position()
+    }
+}
+
+impl Read for SnapshotMemoryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+impl BufRead for SnapshotMemoryReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.cursor.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.cursor.consume(amt)
+    }
+}
+
+impl Seek for SnapshotMemoryReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
+
+impl SnapshotMemoryWriter<'_> {
+    /// Returns the offset within the memory of the next byte to write.
+    pub fn position(&self) -> u64 {
+        self.cursor.position()
+    }
+}
+
+impl Write for SnapshotMemoryWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.cursor.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SnapshotMemoryWriter<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
@@ -0,0 +1,137 @@
+use super::external::ExternalMemory;
+use super::serialize::{decode_globals, encode_annotations, encode_globals, GlobalValue};
+use super::{Snapshot, SnapshotMemory, SuspendState};
+use anyhow::{bail, Result};
+use serde::de::{Deserializer, Visitor};
+use serde::ser::Serializer;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+/// Version of the format written by [`Snapshot::to_msgpack`], stored in its
+/// first byte and bumped whenever the layout of [`MsgpackSnapshot`] changes.
+const MSGPACK_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct MsgpackSnapshot {
+    memories: Vec<(u32, String, Bin, Option<ExternalMemory>)>,
+    globals: Vec<GlobalValue>,
+    host_states: Vec<(String, Bin)>,
+    annotations: Vec<(String, String)>,
+}
+
+/// Bytes stored as a MessagePack `bin` rather than as an array of integers.
+struct Bin(Vec<u8>);
+
+impl serde::Serialize for Bin {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Bin {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Bin, D::Error> {
+        struct BinVisitor;
+
+        impl<'de> Visitor<'de> for BinVisitor {
+            type Value = Bin;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a byte array")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Bin, E> {
+                Ok(Bin(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Bin, E> {
+                Ok(Bin(v))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BinVisitor)
+    }
+}
+
+impl Snapshot {
+    /// Serializes this snapshot into MessagePack, which can be turned back
+    /// into a snapshot with [`Snapshot::from_msgpack`].
+    ///
+    /// Unlike the format of [`Snapshot::to_bytes`], this one is documented:
+    /// a version byte, currently 1, followed by a MessagePack array of the
+    /// memories, globals, host states and annotations of the snapshot. Its
+    /// compact encoding of integers also makes it smaller.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a global holds a non-null reference, which cannot
+    /// be stored outside of its store.
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-msgpack")))]
+    pub fn to_msgpack(&self) -> Result<Vec<u8>> {
+        self.ensure_internalized()?;
+        let serialized = MsgpackSnapshot {
+            memories: self
+                .memories
+                .iter()
+                .map(|m| {
+                    (
+                        m.index,
+                        m.name.clone(),
+                        Bin(m.data.clone()),
+                        m.external.clone(),
+                    )
+                })
+                .collect(),
+            globals: encode_globals(&self.globals)?,
+            host_states: self
+                .host_states
+                .iter()
+                .map(|(name, state)| (name.clone(), Bin(state.data().to_vec())))
+                .collect(),
+            annotations: encode_annotations(&self.annotations),
+        };
+        let mut bytes = vec![MSGPACK_VERSION];
+        rmp_serde::encode::write(&mut bytes, &serialized)?;
+        Ok(bytes)
+    }
+
+    /// Deserializes a snapshot previously serialized with
+    /// [`Snapshot::to_msgpack`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `bytes` is malformed or starts with a format
+    /// version this version of Wasmtime does not know.
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "snapshot-msgpack")))]
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Snapshot> {
+        let (version, body) = match bytes.split_first() {
+            Some((version, body)) => (*version, body),
+            None => bail!("empty MessagePack snapshot"),
+        };
+        if version != MSGPACK_VERSION {
+            bail!(
+                "unsupported MessagePack snapshot format version {version} \
+                 (expected {MSGPACK_VERSION})"
+            );
+        }
+        let serialized: MsgpackSnapshot = rmp_serde::from_slice(body)?;
+        Ok(Snapshot {
+            memories: serialized
+                .memories
+                .into_iter()
+                .map(|(index, name, Bin(data), external)| SnapshotMemory {
+                    index,
+                    name,
+                    data,
+                    external,
+                })
+                .collect(),
+            globals: decode_globals(serialized.globals),
+            host_states: serialized
+                .host_states
+                .into_iter()
+                .map(|(name, Bin(data))| (name, SuspendState::new(data)))
+                .collect(),
+            annotations: serialized.annotations.into_iter().collect(),
+        })
+    }
+}
@@ -0,0 +1,269 @@
+use super::serialize::{decode_globals, encode_globals, GlobalValue};
+use crate::{AsContextMut, Instance, Val};
+use anyhow::{anyhow, bail, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+/// Version of the format written by [`CallLog::to_bytes`], bumped whenever
+/// the layout of [`SerializedCallLog`] changes.
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SerializedCallLog {
+    version: u32,
+    entries: Vec<(String, Vec<GlobalValue>, Vec<GlobalValue>)>,
+}
+
+/// A call recorded in a [`CallLog`].
+#[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+#[derive(Clone, Debug)]
+pub struct CallLogEntry {
+    /// The name of the exported function which was called.
+    pub func_name: String,
+    /// The arguments the function was called with.
+    pub params: Vec<Val>,
+    /// The results the function returned.
+    pub results: Vec<Val>,
+}
+
+/// The calls made into an instance through a [`CallLogRecorder`], in order,
+/// which can be replayed with [`Instance::replay_calls_async`].
+#[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+#[derive(Clone, Debug, Default)]
+pub struct CallLog {
+    entries: Vec<CallLogEntry>,
+}
+
+impl CallLog {
+    /// Returns the recorded calls, in the order they were made.
+    pub fn entries(&self) -> &[CallLogEntry] {
+        &self.entries
+    }
+
+    /// Serializes this log into bytes, which can be turned back into a log
+    /// with [`CallLog::from_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an argument or result is a non-null reference,
+    /// which cannot be stored outside of its store.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let serialized = SerializedCallLog {
+            version: VERSION,
+            entries: self
+                .entries
+                .iter()
+                .map(|e| {
+                    Ok((
+                        e.func_name.clone(),
+                        encode_globals(&e.params)?,
+                        encode_globals(&e.results)?,
+                    ))
+                })
+                .collect::<Result<_>>()?,
+        };
+        Ok(bincode::serialize(&serialized)?)
+    }
+
+    /// Deserializes a log previously serialized with [`CallLog::to_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `bytes` is malformed or was written by an
+    /// incompatible version of Wasmtime.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CallLog> {
+        let serialized: SerializedCallLog = bincode::deserialize(bytes)?;
+        if serialized.version != VERSION {
+            bail!(
+                "unsupported call log format version {} (expected {VERSION})",
+                serialized.version
+            );
+        }
+        Ok(CallLog {
+            entries: serialized
+                .entries
+                .into_iter()
+                .map(|(func_name, params, results)| CallLogEntry {
+                    func_name,
+                    params: decode_globals(params),
+                    results: decode_globals(results),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Records the calls made into an instance into a [`CallLog`].
+///
+/// Only calls which return are recorded, so a log replays faithfully as
+/// long as no call made through the recorder traps.
+#[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+#[derive(Debug, Default)]
+pub struct CallLogRecorder {
+    log: CallLog,
+}
+
+impl CallLogRecorder {
+    /// Creates a recorder with an empty log.
+    pub fn new() -> CallLogRecorder {
+        CallLogRecorder::default()
+    }
+
+    /// Calls the function `name` exported by `instance` with `params`, as
+    /// with [`Func::call_async`](crate::Func::call_async), and records the
+    /// call if it returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `instance` does not export a function called
+    /// `name`, or if the call fails for any of the reasons documented on
+    /// [`Func::call_async`](crate::Func::call_async).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` is not asynchronous or does not own `instance`.
+    pub async fn call_async<T>(
+        &mut self,
+        mut store: impl AsContextMut<Data = T>,
+        instance: &Instance,
+        name: &str,
+        params: &[Val],
+    ) -> Result<Vec<Val>>
+    where
+        T: Send,
+    {
+        let mut store = store.as_context_mut();
+        let func = instance
+            .get_func(&mut store, name)
+            .ok_or_else(|| anyhow!("no function `{name}` is exported"))?;
+        let mut results = vec![Val::null(); func.ty(&store).results().len()];
+        func.call_async(&mut store, params, &mut results).await?;
+        self.log.entries.push(CallLogEntry {
+            func_name: name.to_owned(),
+            params: params.to_vec(),
+            results: results.clone(),
+        });
+        Ok(results)
+    }
+
+    /// Returns the calls recorded so far.
+    pub fn log(&self) -> &CallLog {
+        &self.log
+    }
+
+    /// Returns the calls recorded, consuming the recorder.
+    pub fn into_log(self) -> CallLog {
+        self.log
+    }
+}
+
+/// The results of a call replayed by [`Instance::replay_calls_async`].
+#[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+#[derive(Clone, Debug)]
+pub struct CallResult {
+    /// The name of the exported function which was called.
+    pub func_name: String,
+    /// The results the function returned.
+    pub results: Vec<Val>,
+}
+
+/// The error returned by [`Instance::replay_calls_async`] when a replayed
+/// call returns other results than recorded.
+#[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+#[derive(Clone, Debug)]
+pub struct ReplayDivergence {
+    /// The index of the call in the [`CallLog`].
+    pub call_index: usize,
+    /// The results recorded for the call.
+    pub expected: Vec<Val>,
+    /// The results returned by the replayed call.
+    pub actual: Vec<Val>,
+}
+
+impl fmt::Display for ReplayDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "call {} returned {:?} instead of the recorded {:?}",
+            self.call_index, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for ReplayDivergence {}
+
+/// Returns whether `a` and `b` hold the same values, comparing floats by their
+/// bits and references only by whether they are null, since the references
+/// of a log point into the store it was recorded in.
+fn same_results(a: &[Val], b: &[Val]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|pair| match pair {
+            (Val::I32(a), Val::I32(b)) => a == b,
+            (Val::I64(a), Val::I64(b)) => a == b,
+            (Val::F32(a), Val::F32(b)) => a == b,
+            (Val::F64(a), Val::F64(b)) => a == b,
+            (Val::V128(a), Val::V128(b)) => a.as_u128() == b.as_u128(),
+            (Val::FuncRef(a), Val::FuncRef(b)) => a.is_none() == b.is_none(),
+            (Val::ExternRef(a), Val::ExternRef(b)) => a.is_none() == b.is_none(),
+            _ => false,
+        })
+}
+
+impl Instance {
+    /// Makes the calls of `call_log` into this instance again, in order and
+    /// with the same arguments, checking that each returns the results
+    /// recorded for it.
+    ///
+    /// Replaying a log into an instance restored from the
+    /// [`Snapshot`](crate::Snapshot) taken before the calls were recorded
+    /// verifies that the snapshot and the calls reproduce the same
+    /// execution.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ReplayDivergence`] as soon as a call returns other
+    /// results than recorded, in which case the calls after it are not made.
+    /// Also returns an error if this instance does not export a function of
+    /// the log, or if a call fails for any of the reasons documented on
+    /// [`Func::call_async`](crate::Func::call_async).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` is not asynchronous or does not own this instance.
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+    pub async fn replay_calls_async<T>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        call_log: &CallLog,
+    ) -> Result<Vec<CallResult>>
+    where
+        T: Send,
+    {
+        let mut store = store.as_context_mut();
+        let mut replayed = Vec::with_capacity(call_log.entries.len());
+        for (call_index, entry) in call_log.entries.iter().enumerate() {
+            let func = self.get_func(&mut store, &entry.func_name).ok_or_else(|| {
+                anyhow!(
+                    "call {call_index}: no function `{}` is exported",
+                    entry.func_name
+                )
+            })?;
+            let mut results = vec![Val::null(); func.ty(&store).results().len()];
+            func.call_async(&mut store, &entry.params, &mut results)
+                .await?;
+            if !same_results(&entry.results, &results) {
+                return Err(ReplayDivergence {
+                    call_index,
+                    expected: entry.results.clone(),
+                    actual: results,
+                }
+                .into());
+            }
+            replayed.push(CallResult {
+                func_name: entry.func_name.clone(),
+                results,
+            });
+        }
+        Ok(replayed)
+    }
+}
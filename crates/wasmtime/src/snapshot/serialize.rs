@@ -0,0 +1,144 @@
+use super::external::ExternalMemory;
+use super::{Snapshot, SnapshotMemory, SuspendState};
+use crate::Val;
+use anyhow::{bail, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Version of the format written by [`Snapshot::to_bytes`], bumped whenever
+/// the layout of [`SerializedSnapshot`] changes.
+const VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize)]
+struct SerializedSnapshot {
+    version: u32,
+    memories: Vec<(u32, String, Vec<u8>, Option<ExternalMemory>)>,
+    globals: Vec<GlobalValue>,
+    host_states: Vec<(String, Vec<u8>)>,
+    annotations: Vec<(String, String)>,
+}
+
+impl Snapshot {
+    /// Serializes this snapshot into bytes, which can be turned back into a
+    /// snapshot with [`Snapshot::from_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a global holds a non-null reference, which cannot
+    /// be stored outside of its store.
+    ///
+    /// Memories [stored in files](Snapshot::export_memory_to_file) are not
+    /// included; only the location and hash of their file is.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let serialized = SerializedSnapshot {
+            version: VERSION,
+            memories: self
+                .memories
+                .iter()
+                .map(|m| (m.index, m.name.clone(), m.data.clone(), m.external.clone()))
+                .collect(),
+            globals: encode_globals(&self.globals)?,
+            host_states: self
+                .host_states
+                .iter()
+                .map(|(name, state)| (name.clone(), state.data().to_vec()))
+                .collect(),
+            annotations: encode_annotations(&self.annotations),
+        };
+        Ok(bincode::serialize(&serialized)?)
+    }
+
+    /// Deserializes a snapshot previously serialized with
+    /// [`Snapshot::to_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `bytes` is malformed or was written by an
+    /// incompatible version of Wasmtime.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot> {
+        let serialized: SerializedSnapshot = bincode::deserialize(bytes)?;
+        if serialized.version != VERSION {
+            bail!(
+                "unsupported snapshot format version {} (expected {VERSION})",
+                serialized.version
+            );
+        }
+        Ok(Snapshot {
+            memories: serialized
+                .memories
+                .into_iter()
+                .map(|(index, name, data, external)| SnapshotMemory {
+                    index,
+                    name,
+                    data,
+                    external,
+                })
+                .collect(),
+            globals: decode_globals(serialized.globals),
+            host_states: serialized
+                .host_states
+                .into_iter()
+                .map(|(name, data)| (name, SuspendState::new(data)))
+                .collect(),
+            annotations: serialized.annotations.into_iter().collect(),
+        })
+    }
+}
+
+/// The serializable subset of [`Val`].
+#[derive(Serialize, Deserialize)]
+pub(super) enum GlobalValue {
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+    V128(u128),
+    NullFuncRef,
+    NullExternRef,
+}
+
+pub(super) fn encode_globals(globals: &[Val]) -> Result<Vec<GlobalValue>> {
+    globals
+        .iter()
+        .map(|g| {
+            Ok(match g {
+                Val::I32(i) => GlobalValue::I32(*i),
+                Val::I64(i) => GlobalValue::I64(*i),
+                Val::F32(f) => GlobalValue::F32(*f),
+                Val::F64(f) => GlobalValue::F64(*f),
+                Val::V128(v) => GlobalValue::V128(v.as_u128()),
+                Val::FuncRef(None) => GlobalValue::NullFuncRef,
+                Val::ExternRef(None) => GlobalValue::NullExternRef,
+                Val::FuncRef(Some(_)) | Val::ExternRef(Some(_)) => {
+                    bail!("cannot serialize a snapshot with a non-null reference in a global")
+                }
+            })
+        })
+        .collect()
+}
+
+pub(super) fn decode_globals(values: Vec<GlobalValue>) -> Vec<Val> {
+    values
+        .into_iter()
+        .map(|v| match v {
+            GlobalValue::I32(i) => Val::I32(i),
+            GlobalValue::I64(i) => Val::I64(i),
+            GlobalValue::F32(f) => Val::F32(f),
+            GlobalValue::F64(f) => Val::F64(f),
+            GlobalValue::V128(v) => Val::V128(v.into()),
+            GlobalValue::NullFuncRef => Val::FuncRef(None),
+            GlobalValue::NullExternRef => Val::ExternRef(None),
+        })
+        .collect()
+}
+
+/// Returns `annotations` sorted by key, so that equal annotations are always
+/// serialized to the same bytes.
+pub(super) fn encode_annotations(annotations: &HashMap<String, String>) -> Vec<(String, String)> {
+    let mut annotations = annotations
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect::<Vec<_>>();
+    annotations.sort();
+    annotations
+}
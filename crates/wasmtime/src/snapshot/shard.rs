@@ -0,0 +1,353 @@
+use super::serialize::{decode_globals, encode_annotations, encode_globals, GlobalValue};
+use super::{Snapshot, SnapshotMemory, SuspendState};
+use crate::Val;
+use anyhow::{bail, ensure, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Version of the format written by [`SnapshotShard::to_bytes`], bumped
+/// whenever the layout of [`SerializedShard`] changes.
+const VERSION: u32 = 2;
+
+/// One part of a [`Snapshot`] divided with [`Snapshot::split`], for example
+/// to store a snapshot of several gigabytes in files of limited size.
+///
+/// A snapshot is divided into a header shard, holding the globals, host
+/// states, annotations and the layout of the memories, and data shards, each
+/// holding a contiguous range of one memory. Every shard carries its sequence
+/// number, which is 0 for the header, and the total number of shards, so
+/// that [`SnapshotShard::rejoin`] can tell when shards are missing.
+#[derive(Clone, Debug)]
+pub struct SnapshotShard {
+    sequence: u32,
+    total: u32,
+    contents: ShardContents,
+}
+
+#[derive(Clone, Debug)]
+enum ShardContents {
+    Header {
+        /// The index, name and size in bytes of each memory.
+        memories: Vec<(u32, String, usize)>,
+        globals: Vec<Val>,
+        host_states: Vec<(String, SuspendState)>,
+        annotations: HashMap<String, String>,
+    },
+    Data {
+        memory_index: u32,
+        offset: usize,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedShard {
+    version: u32,
+    sequence: u32,
+    total: u32,
+    contents: SerializedContents,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SerializedContents {
+    Header {
+        memories: Vec<(u32, String, usize)>,
+        globals: Vec<GlobalValue>,
+        host_states: Vec<(String, Vec<u8>)>,
+        annotations: Vec<(String, String)>,
+    },
+    Data {
+        memory_index: u32,
+        offset: usize,
+        data: Vec<u8>,
+    },
+}
+
+impl Snapshot {
+    /// Divides this snapshot into a header shard and data shards, splitting
+    /// memories at the given wasm page boundaries.
+    ///
+    /// Each entry of `split_points` is a memory index and the number of wasm
+    /// pages after which that memory is split. Every memory is stored in one
+    /// data shard per range between its split points, in memory index order,
+    /// so a memory without split points is stored in a single data shard.
+    /// The snapshot is reassembled with [`SnapshotShard::rejoin`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory does not exist in this snapshot, if a
+    /// split point is not strictly inside its memory, if a split point is
+    /// given twice, or if a memory is
+    /// [stored in a file](Snapshot::export_memory_to_file).
+    pub fn split(
+        &self,
+        split_points: &[(u32, usize)],
+    ) -> Result<(SnapshotShard, Vec<SnapshotShard>)> {
+        self.ensure_internalized()?;
+        let page_size = wasmtime_environ::WASM_PAGE_SIZE as usize;
+        for (memory_index, _) in split_points {
+            ensure!(
+                self.memories.iter().any(|m| m.index == *memory_index),
+                "memory {memory_index} does not exist in this snapshot"
+            );
+        }
+
+        let mut data = Vec::new();
+        for memory in &self.memories {
+            let len = memory.data.len();
+            let mut offsets = Vec::new();
+            for (_, pages) in split_points.iter().filter(|(i, _)| *i == memory.index) {
+                let offset = pages.checked_mul(page_size).filter(|o| *o > 0 && *o < len);
+                match offset {
+                    Some(offset) => offsets.push(offset),
+                    None => bail!(
+                        "split point at page {pages} is not inside memory {} ({len:#x} bytes)",
+                        memory.index
+                    ),
+                }
+            }
+            offsets.sort_unstable();
+            if let Some(w) = offsets.windows(2).find(|w| w[0] == w[1]) {
+                bail!(
+                    "memory {} is split twice at page {}",
+                    memory.index,
+                    w[0] / page_size
+                );
+            }
+
+            let mut start = 0;
+            for end in offsets.into_iter().chain([len]) {
+                data.push(ShardContents::Data {
+                    memory_index: memory.index,
+                    offset: start,
+                    data: memory.data[start..end].to_vec(),
+                });
+                start = end;
+            }
+        }
+
+        let total = u32::try_from(data.len() + 1)?;
+        let header = SnapshotShard {
+            sequence: 0,
+            total,
+            contents: ShardContents::Header {
+                memories: self
+                    .memories
+                    .iter()
+                    .map(|m| (m.index, m.name.clone(), m.data.len()))
+                    .collect(),
+                globals: self.globals.clone(),
+                host_states: self.host_states.clone(),
+                annotations: self.annotations.clone(),
+            },
+        };
+        let data = data
+            .into_iter()
+            .zip(1..)
+            .map(|(contents, sequence)| SnapshotShard {
+                sequence,
+                total,
+                contents,
+            })
+            .collect();
+        Ok((header, data))
+    }
+}
+
+impl SnapshotShard {
+    /// Reassembles the snapshot divided into `header` and `data` by
+    /// [`Snapshot::split`].
+    ///
+    /// `data` must hold every data shard, in the order they were returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `header` is not a header shard, if a data shard is
+    /// missing, out of order or belongs to a snapshot divided into a
+    /// different number of shards, or if the data shards do not cover the
+    /// memories described by the header.
+    pub fn rejoin(header: &SnapshotShard, data: &[SnapshotShard]) -> Result<Snapshot> {
+        let (memories, globals, host_states, annotations) = match &header.contents {
+            ShardContents::Header {
+                memories,
+                globals,
+                host_states,
+                annotations,
+            } if header.sequence == 0 => (memories, globals, host_states, annotations),
+            _ => bail!("shard {} is not a header shard", header.sequence),
+        };
+        let expected = (header.total as usize).saturating_sub(1);
+        ensure!(
+            data.len() == expected,
+            "expected {expected} data shards but got {}",
+            data.len()
+        );
+
+        let mut shards = data.iter().zip(1..);
+        let mut snapshot_memories = Vec::with_capacity(memories.len());
+        for (index, name, len) in memories {
+            // Every memory has at least one data shard, even if it is empty.
+            let mut contents = Vec::new();
+            loop {
+                let (shard, sequence) = match shards.next() {
+                    Some(next) => next,
+                    None => bail!("data shards end before memory {index} is complete"),
+                };
+                ensure!(
+                    shard.total == header.total,
+                    "shard {} belongs to a snapshot of {} shards, not {}",
+                    shard.sequence,
+                    shard.total,
+                    header.total
+                );
+                ensure!(
+                    shard.sequence == sequence,
+                    "expected shard {sequence} but got shard {}",
+                    shard.sequence
+                );
+                match &shard.contents {
+                    ShardContents::Data {
+                        memory_index,
+                        offset,
+                        data,
+                    } if *memory_index == *index && *offset == contents.len() => {
+                        contents.extend_from_slice(data);
+                    }
+                    _ => bail!(
+                        "shard {sequence} does not continue memory {index} at offset {:#x}",
+                        contents.len()
+                    ),
+                }
+                if contents.len() >= *len {
+                    break;
+                }
+            }
+            ensure!(
+                contents.len() == *len,
+                "data shards hold {:#x} bytes of memory {index} but it has {len:#x}",
+                contents.len()
+            );
+            snapshot_memories.push(SnapshotMemory {
+                index: *index,
+                name: name.clone(),
+                data: contents,
+                external: None,
+            });
+        }
+        if let Some((_, sequence)) = shards.next() {
+            bail!("shard {sequence} is not part of any memory");
+        }
+
+        Ok(Snapshot {
+            memories: snapshot_memories,
+            globals: globals.clone(),
+            host_states: host_states.clone(),
+            annotations: annotations.clone(),
+        })
+    }
+
+    /// Returns the position of this shard, which is 0 for the header shard
+    /// and counts up from 1 for the data shards.
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Returns the number of shards, including the header shard, the
+    /// snapshot was divided into.
+    pub fn total_shards(&self) -> u32 {
+        self.total
+    }
+
+    /// Returns whether this is the header shard of a snapshot.
+    pub fn is_header(&self) -> bool {
+        matches!(self.contents, ShardContents::Header { .. })
+    }
+
+    /// Serializes this shard into bytes, which can be turned back into a
+    /// shard with [`SnapshotShard::from_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if this is a header shard and a global holds a
+    /// non-null reference, which cannot be stored outside of its store.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let contents = match &self.contents {
+            ShardContents::Header {
+                memories,
+                globals,
+                host_states,
+                annotations,
+            } => SerializedContents::Header {
+                memories: memories.clone(),
+                globals: encode_globals(globals)?,
+                host_states: host_states
+                    .iter()
+                    .map(|(name, state)| (name.clone(), state.data().to_vec()))
+                    .collect(),
+                annotations: encode_annotations(annotations),
+            },
+            ShardContents::Data {
+                memory_index,
+                offset,
+                data,
+            } => SerializedContents::Data {
+                memory_index: *memory_index,
+                offset: *offset,
+                data: data.clone(),
+            },
+        };
+        Ok(bincode::serialize(&SerializedShard {
+            version: VERSION,
+            sequence: self.sequence,
+            total: self.total,
+            contents,
+        })?)
+    }
+
+    /// Deserializes a shard previously serialized with
+    /// [`SnapshotShard::to_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `bytes` is malformed or was written by an
+    /// incompatible version of Wasmtime.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SnapshotShard> {
+        let serialized: SerializedShard = bincode::deserialize(bytes)?;
+        if serialized.version != VERSION {
+            bail!(
+                "unsupported snapshot shard format version {} (expected {VERSION})",
+                serialized.version
+            );
+        }
+        let contents = match serialized.contents {
+            SerializedContents::Header {
+                memories,
+                globals,
+                host_states,
+                annotations,
+            } => ShardContents::Header {
+                memories,
+                globals: decode_globals(globals),
+                host_states: host_states
+                    .into_iter()
+                    .map(|(name, data)| (name, SuspendState::new(data)))
+                    .collect(),
+                annotations: annotations.into_iter().collect(),
+            },
+            SerializedContents::Data {
+                memory_index,
+                offset,
+                data,
+            } => ShardContents::Data {
+                memory_index,
+                offset,
+                data,
+            },
+        };
+        Ok(SnapshotShard {
+            sequence: serialized.sequence,
+            total: serialized.total,
+            contents,
+        })
+    }
+}
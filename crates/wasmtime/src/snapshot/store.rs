@@ -0,0 +1,285 @@
+use super::{Snapshot, SnapshotHash};
+use anyhow::{anyhow, bail, Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Storage for [`Snapshot`]s, keyed by a user-chosen id.
+///
+/// Implementations are provided for the filesystem with [`FileSnapshotStore`]
+/// and in memory with [`MemorySnapshotStore`].
+#[cfg_attr(
+    nightlydoc,
+    doc(cfg(all(feature = "async", feature = "snapshot-hash")))
+)]
+#[async_trait::async_trait]
+pub trait SnapshotStore: Send + Sync {
+    /// Saves `snap` under `id` with no tags, replacing any snapshot
+    /// previously saved under the same id.
+    async fn save(&self, id: &str, snap: &Snapshot) -> Result<()> {
+        self.save_with_tags(id, snap, HashMap::new()).await
+    }
+
+    /// Saves `snap` under `id` with the user-defined `tags`, replacing any
+    /// snapshot previously saved under the same id.
+    async fn save_with_tags(
+        &self,
+        id: &str,
+        snap: &Snapshot,
+        tags: HashMap<String, String>,
+    ) -> Result<()>;
+
+    /// Loads the snapshot saved under `id`.
+    async fn load(&self, id: &str) -> Result<Snapshot>;
+
+    /// Returns the metadata of all saved snapshots, sorted by id.
+    async fn list(&self) -> Result<Vec<SnapshotMeta>>;
+
+    /// Returns the metadata of the saved snapshots annotated with `value`
+    /// under `key` by [`Snapshot::with_annotation`], sorted by id.
+    async fn list_by_annotation(&self, key: &str, value: &str) -> Result<Vec<SnapshotMeta>> {
+        let mut list = self.list().await?;
+        list.retain(|meta| meta.annotations.get(key).map(|v| v.as_str()) == Some(value));
+        Ok(list)
+    }
+
+    /// Deletes the snapshot saved under `id`.
+    async fn delete(&self, id: &str) -> Result<()>;
+
+    /// Returns whether a snapshot whose [`Snapshot::compute_hash`] is `hash`
+    /// is saved in this store, under any id.
+    fn has_snapshot(&self, hash: &SnapshotHash) -> bool;
+}
+
+/// Metadata about a snapshot saved in a [`SnapshotStore`].
+#[cfg_attr(
+    nightlydoc,
+    doc(cfg(all(feature = "async", feature = "snapshot-hash")))
+)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+    /// The id the snapshot was saved under.
+    pub id: String,
+    /// When the snapshot was saved.
+    pub created_at: SystemTime,
+    /// The value of [`Snapshot::size_bytes`] for the snapshot.
+    pub size_bytes: usize,
+    /// The value of [`Snapshot::compute_hash`] for the snapshot.
+    pub hash: SnapshotHash,
+    /// The tags the snapshot was saved with.
+    pub tags: HashMap<String, String>,
+    /// The annotations of the snapshot, as returned by
+    /// [`Snapshot::annotations`].
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+impl SnapshotMeta {
+    fn new(id: &str, snap: &Snapshot, tags: HashMap<String, String>) -> SnapshotMeta {
+        SnapshotMeta {
+            id: id.to_string(),
+            created_at: SystemTime::now(),
+            size_bytes: snap.size_bytes(),
+            hash: snap.compute_hash(),
+            tags,
+            annotations: snap.annotations().clone(),
+        }
+    }
+}
+
+/// A [`SnapshotStore`] keeping each snapshot in a separate file of a
+/// directory.
+///
+/// Snapshots are written with [`Snapshot::to_bytes`] to `<id>.snapshot`
+/// within the base directory, and their metadata is kept in a
+/// `manifest.json` file next to them. Ids may only contain ASCII
+/// alphanumerics, `-`, `_` and `.`, and may not start with `.`.
+///
+/// The store has no async runtime of its own: every method accesses its files
+/// with blocking [`std::fs`] calls and completes without yielding, holding the
+/// calling thread until the I/O is done. When used from an async executor
+/// such as Tokio, call it from a context where blocking is acceptable, for
+/// example within `tokio::task::spawn_blocking` or `block_in_place`.
+#[cfg_attr(
+    nightlydoc,
+    doc(cfg(all(feature = "async", feature = "snapshot-hash")))
+)]
+pub struct FileSnapshotStore {
+    base_dir: PathBuf,
+    manifest: Mutex<()>,
+}
+
+impl FileSnapshotStore {
+    /// Creates a store keeping its files in `base_dir`, which is created on
+    /// the first save if it does not exist.
+    pub fn new(base_dir: PathBuf) -> Self {
+        FileSnapshotStore {
+            base_dir,
+            manifest: Mutex::new(()),
+        }
+    }
+
+    fn snapshot_path(&self, id: &str) -> Result<PathBuf> {
+        let valid = !id.is_empty()
+            && !id.starts_with('.')
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            bail!("invalid snapshot id `{id}`");
+        }
+        Ok(self.base_dir.join(format!("{id}.snapshot")))
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.base_dir.join("manifest.json")
+    }
+
+    fn read_manifest(&self) -> Result<Vec<SnapshotMeta>> {
+        let path = self.manifest_path();
+        match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("failed to parse `{}`", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).with_context(|| format!("failed to read `{}`", path.display())),
+        }
+    }
+
+    fn write_manifest(&self, manifest: &[SnapshotMeta]) -> Result<()> {
+        let path = self.manifest_path();
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(manifest)?)
+            .with_context(|| format!("failed to write `{}`", tmp.display()))?;
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("failed to write `{}`", path.display()))
+    }
+}
+
+#[async_trait::async_trait]
+impl SnapshotStore for FileSnapshotStore {
+    async fn save_with_tags(
+        &self,
+        id: &str,
+        snap: &Snapshot,
+        tags: HashMap<String, String>,
+    ) -> Result<()> {
+        let path = self.snapshot_path(id)?;
+        let bytes = snap.to_bytes()?;
+        let _guard = self.manifest.lock().unwrap();
+        std::fs::create_dir_all(&self.base_dir)
+            .with_context(|| format!("failed to create `{}`", self.base_dir.display()))?;
+        std::fs::write(&path, bytes)
+            .with_context(|| format!("failed to write `{}`", path.display()))?;
+
+        let mut manifest = self.read_manifest()?;
+        manifest.retain(|m| m.id != id);
+        manifest.push(SnapshotMeta::new(id, snap, tags));
+        manifest.sort_by(|a, b| a.id.cmp(&b.id));
+        self.write_manifest(&manifest)
+    }
+
+    async fn load(&self, id: &str) -> Result<Snapshot> {
+        let path = self.snapshot_path(id)?;
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read snapshot `{id}`"))?;
+        Snapshot::from_bytes(&bytes).with_context(|| format!("failed to load snapshot `{id}`"))
+    }
+
+    async fn list(&self) -> Result<Vec<SnapshotMeta>> {
+        let _guard = self.manifest.lock().unwrap();
+        self.read_manifest()
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        let path = self.snapshot_path(id)?;
+        let _guard = self.manifest.lock().unwrap();
+        let mut manifest = self.read_manifest()?;
+        let len = manifest.len();
+        manifest.retain(|m| m.id != id);
+        if manifest.len() == len {
+            bail!("no snapshot `{id}` in the store");
+        }
+        self.write_manifest(&manifest)?;
+        std::fs::remove_file(&path)
+            .with_context(|| format!("failed to remove `{}`", path.display()))
+    }
+
+    fn has_snapshot(&self, hash: &SnapshotHash) -> bool {
+        let _guard = self.manifest.lock().unwrap();
+        self.read_manifest()
+            .map(|manifest| manifest.iter().any(|m| m.hash == *hash))
+            .unwrap_or(false)
+    }
+}
+
+/// A [`SnapshotStore`] keeping snapshots in memory, mainly useful for tests.
+#[cfg_attr(
+    nightlydoc,
+    doc(cfg(all(feature = "async", feature = "snapshot-hash")))
+)]
+#[derive(Default)]
+pub struct MemorySnapshotStore {
+    snapshots: Mutex<HashMap<String, (SnapshotMeta, Snapshot)>>,
+}
+
+impl MemorySnapshotStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl SnapshotStore for MemorySnapshotStore {
+    async fn save_with_tags(
+        &self,
+        id: &str,
+        snap: &Snapshot,
+        tags: HashMap<String, String>,
+    ) -> Result<()> {
+        let meta = SnapshotMeta::new(id, snap, tags);
+        self.snapshots
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), (meta, snap.clone()));
+        Ok(())
+    }
+
+    async fn load(&self, id: &str) -> Result<Snapshot> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|(_, snap)| snap.clone())
+            .ok_or_else(|| anyhow!("no snapshot `{id}` in the store"))
+    }
+
+    async fn list(&self) -> Result<Vec<SnapshotMeta>> {
+        let mut list = self
+            .snapshots
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(meta, _)| meta.clone())
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(list)
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        match self.snapshots.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => bail!("no snapshot `{id}` in the store"),
+        }
+    }
+
+    fn has_snapshot(&self, hash: &SnapshotHash) -> bool {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .values()
+            .any(|(meta, _)| meta.hash == *hash)
+    }
+}
@@ -0,0 +1,120 @@
+use super::Snapshot;
+use anyhow::Result;
+use std::ops::Range;
+
+/// The preamble of a wasm module, including its version.
+const WASM_MODULE_PREAMBLE: &[u8] = b"\0asm\x01\0\0\0";
+
+/// The highest id of a known section, past which a scan assumes it has
+/// reached the end of the module.
+const MAX_SECTION_ID: u8 = 13;
+
+/// The prefix of the names of the custom sections holding DWARF.
+const DEBUG_SECTION_PREFIX: &[u8] = b".debug_";
+
+impl Snapshot {
+    /// Returns a copy of this snapshot in which the DWARF debug information
+    /// of wasm modules held in linear memory is zeroed, so that it
+    /// compresses better and can be stored for production use.
+    ///
+    /// The DWARF of the running module, which Wasmtime reads when
+    /// [`Config::debug_info`](crate::Config::debug_info) is enabled, is
+    /// never part of a snapshot since it is kept in the module rather than
+    /// in linear memory. This only affects guests which keep wasm binaries in
+    /// their memory, such as plugin hosts or interpreters: every memory is
+    /// scanned for the preamble of a wasm module, and the contents of each of
+    /// its custom sections whose name starts with `.debug_` are zeroed. The
+    /// section headers and names are kept, so the modules remain well-formed.
+    ///
+    /// Unlike [`Snapshot::zero_sensitive_regions`], the zeroed ranges are
+    /// found by inspecting the contents of the memories. The scan of a module
+    /// ends at the first section which is malformed or extends past the end
+    /// of its memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a memory is
+    /// [stored in a file](Snapshot::export_memory_to_file).
+    pub fn strip_debug_info(&self) -> Result<Snapshot> {
+        self.ensure_internalized()?;
+        let mut snapshot = self.clone();
+        for memory in snapshot.memories.iter_mut() {
+            for range in debug_sections(&memory.data) {
+                memory.data[range].fill(0);
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Returns the ranges holding the contents of the `.debug_*` custom sections
+/// of the wasm modules found in `data`.
+fn debug_sections(data: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = data[pos..]
+        .windows(WASM_MODULE_PREAMBLE.len())
+        .position(|w| w == WASM_MODULE_PREAMBLE)
+    {
+        let module = pos + found + WASM_MODULE_PREAMBLE.len();
+        pos = scan_module(data, module, &mut ranges);
+    }
+    ranges
+}
+
+/// Walks the sections of the module whose first section starts at `pos`,
+/// adding the contents of its debug sections to `ranges`, and returns the
+/// offset at which the module ends.
+fn scan_module(data: &[u8], mut pos: usize, ranges: &mut Vec<Range<usize>>) -> usize {
+    loop {
+        let start = pos;
+        let id = match data.get(pos) {
+            Some(id) if *id <= MAX_SECTION_ID => *id,
+            _ => return start,
+        };
+        pos += 1;
+        let size = match read_var_u32(data, &mut pos) {
+            Some(size) => size as usize,
+            None => return start,
+        };
+        let end = match pos.checked_add(size) {
+            Some(end) if end <= data.len() => end,
+            _ => return start,
+        };
+        if id == 0 {
+            // Custom sections start with a name, so an empty one is not part
+            // of a module but, most likely, zeroed memory following it.
+            let mut name = pos;
+            let name_len = match read_var_u32(data, &mut name) {
+                Some(len) if size > 0 => len as usize,
+                _ => return start,
+            };
+            let name_end = match name.checked_add(name_len) {
+                Some(name_end) if name_end <= end => name_end,
+                _ => return start,
+            };
+            if data[name..name_end].starts_with(DEBUG_SECTION_PREFIX) {
+                ranges.push(name_end..end);
+            }
+        }
+        pos = end;
+    }
+}
+
+/// Reads an unsigned LEB128 encoded `u32` at `pos`, advancing it past the
+/// value.
+fn read_var_u32(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut result = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        if shift == 28 && byte > 0x0f {
+            return None;
+        }
+        result |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+    }
+    None
+}
@@ -0,0 +1,110 @@
+use super::Snapshot;
+use crate::{AsContextMut, Instance, Linker, Module};
+use anyhow::Result;
+
+/// An instance which has been suspended with [`Instance::suspend`].
+///
+/// A suspended instance holds a [`Snapshot`] of the instance's state and
+/// the [`Module`] it was instantiated from, but nothing owned by a
+/// [`Store`](crate::Store). It can therefore outlive the store it was
+/// suspended from and be resumed into a different one with
+/// [`SuspendedInstance::resume`].
+#[derive(Clone, Debug)]
+pub struct SuspendedInstance {
+    module: Module,
+    snapshot: Snapshot,
+}
+
+impl SuspendedInstance {
+    /// Returns the module of the suspended instance.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Returns the snapshot of the suspended instance's state.
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    /// Consumes this handle, returning the snapshot of the suspended
+    /// instance's state.
+    pub fn into_snapshot(self) -> Snapshot {
+        self.snapshot
+    }
+
+    /// Creates a new instance of the suspended instance's module in `store`
+    /// and restores the suspended state into it.
+    ///
+    /// Imports are resolved with `linker`. The start function is not run, as
+    /// with [`InstancePre::instantiate_from_snapshot`](crate::InstancePre::instantiate_from_snapshot).
+    /// Host states attached to the snapshot are not restored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `linker` cannot satisfy the module's imports or if
+    /// instantiation or restoring the snapshot fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` has async support enabled, or if it belongs to a
+    /// different engine than the module.
+    pub fn resume<T>(
+        &self,
+        linker: &Linker<T>,
+        store: impl AsContextMut<Data = T>,
+    ) -> Result<Instance> {
+        linker
+            .instantiate_pre(&self.module)?
+            .instantiate_from_snapshot(store, &self.snapshot)
+    }
+
+    /// Same as [`SuspendedInstance::resume`], but for stores with async
+    /// support enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not have async support enabled, or if it
+    /// belongs to a different engine than the module.
+    #[cfg(feature = "async")]
+    #[cfg_attr(nightlydoc, doc(cfg(feature = "async")))]
+    pub async fn resume_async<T>(
+        &self,
+        linker: &Linker<T>,
+        store: impl AsContextMut<Data = T>,
+    ) -> Result<Instance>
+    where
+        T: Send,
+    {
+        linker
+            .instantiate_pre(&self.module)?
+            .instantiate_from_snapshot_async(store, &self.snapshot)
+            .await
+    }
+}
+
+impl Instance {
+    /// Captures the state of this instance and suspends it.
+    ///
+    /// The returned [`SuspendedInstance`] owns a [`Snapshot`] of the
+    /// instance and is independent of `store`, which may be dropped to
+    /// release the instance's memories and other resources. Instances cannot
+    /// be removed from a store individually, so those resources stay
+    /// allocated for as long as `store` lives. This instance must not be used
+    /// again after it has been suspended, since changes to it are not seen by
+    /// the suspended handle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a snapshot cannot be taken, as documented on
+    /// [`Instance::snapshot`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `store` does not own this instance.
+    pub fn suspend(&self, mut store: impl AsContextMut) -> Result<SuspendedInstance> {
+        let store = store.as_context_mut();
+        let module = self.module(&store).clone();
+        let snapshot = self.snapshot(store)?;
+        Ok(SuspendedInstance { module, snapshot })
+    }
+}
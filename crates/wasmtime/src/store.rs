@@ -81,7 +81,7 @@ use crate::linker::Definition;
 use crate::module::{BareModuleInfo, RegisteredModuleId};
 use crate::trampoline::VMHostGlobalContext;
 use crate::{module::ModuleRegistry, Engine, Module, Trap, Val, ValRaw};
-use crate::{Global, Instance, Memory};
+use crate::{Global, Instance, Memory, Snapshot, SuspendState};
 use anyhow::{anyhow, bail, Result};
 use std::cell::UnsafeCell;
 use std::convert::TryFrom;
@@ -207,6 +207,7 @@ pub struct StoreInner<T> {
     call_hook: Option<CallHookInner<T>>,
     epoch_deadline_behavior:
         Option<Box<dyn FnMut(StoreContextMut<T>) -> Result<UpdateDeadline> + Send + Sync>>,
+    host_state_hooks: Vec<HostStateHook<T>>,
     // for comments about `ManuallyDrop`, see `Store::into_data`
     data: ManuallyDrop<T>,
 }
@@ -226,6 +227,16 @@ pub trait CallHookHandler<T>: Send {
     async fn handle_call_event(&self, t: &mut T, ch: CallHook) -> Result<()>;
 }
 
+type SaveHostState<T> = Box<dyn FnMut(&mut T) -> Result<SuspendState> + Send + Sync>;
+type RestoreHostState<T> = Box<dyn FnMut(&mut T, &SuspendState) -> Result<()> + Send + Sync>;
+
+/// Host state registered with [`Store::snapshot_host_state`].
+struct HostStateHook<T> {
+    name: String,
+    save: SaveHostState<T>,
+    restore: RestoreHostState<T>,
+}
+
 enum CallHookInner<T> {
     Sync(Box<dyn FnMut(&mut T, CallHook) -> Result<()> + Send + Sync>),
     #[cfg(feature = "async")]
@@ -519,6 +530,7 @@ impl<T> Store<T> {
             limiter: None,
             call_hook: None,
             epoch_deadline_behavior: None,
+            host_state_hooks: Vec::new(),
             data: ManuallyDrop::new(data),
         });
 
@@ -773,6 +785,34 @@ impl<T> Store<T> {
         self.inner.call_hook = Some(CallHookInner::Sync(Box::new(hook)));
     }
 
+    /// Registers host state of `T` which is saved into and restored from
+    /// [`Snapshot`](crate::Snapshot)s of instances in this store.
+    ///
+    /// [`Instance::snapshot`] calls `save` and attaches the result to the
+    /// snapshot as the host state `name`, as with
+    /// [`Snapshot::with_host_states`](crate::Snapshot::with_host_states).
+    /// [`Instance::restore`] and [`Instance::restore_partial`] call `restore`
+    /// with the host state `name` of the snapshot, if it has one, after the
+    /// instance itself has been restored. Registering `name` again replaces
+    /// the previous hooks.
+    ///
+    /// If `save` or `restore` return an error, it is returned from the
+    /// snapshot or restore which called it.
+    pub fn snapshot_host_state(
+        &mut self,
+        name: impl Into<String>,
+        save: impl FnMut(&mut T) -> Result<SuspendState> + Send + Sync + 'static,
+        restore: impl FnMut(&mut T, &SuspendState) -> Result<()> + Send + Sync + 'static,
+    ) {
+        let name = name.into();
+        self.inner.host_state_hooks.retain(|hook| hook.name != name);
+        self.inner.host_state_hooks.push(HostStateHook {
+            name,
+            save: Box::new(save),
+            restore: Box::new(restore),
+        });
+    }
+
     /// Returns the [`Engine`] that this store is associated with.
     pub fn engine(&self) -> &Engine {
         self.inner.engine()
@@ -787,6 +827,85 @@ impl<T> Store<T> {
         self.inner.gc()
     }
 
+    /// Overwrites the start of the memory at `memory_index` with `data`.
+    ///
+    /// Memories are indexed across the whole store, host- and wasm-defined,
+    /// in the same order as [`WasmCoreDump::memories`](crate::WasmCoreDump::memories).
+    /// This bypasses the [`Snapshot`](crate::Snapshot) machinery entirely and
+    /// is intended for external tooling such as debuggers or differential
+    /// testing harnesses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no memory at `memory_index`, if it is a
+    /// shared memory, or if `data` is larger than the memory.
+    pub fn inject_memory(&mut self, memory_index: u32, data: &[u8]) -> Result<()> {
+        self.as_context_mut().inject_memory(memory_index, data)
+    }
+
+    /// Returns a copy of the contents of the memory at `memory_index`.
+    ///
+    /// See [`Store::inject_memory`] for how memories are indexed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no memory at `memory_index` or if it is a
+    /// shared memory.
+    pub fn extract_memory(&mut self, memory_index: u32) -> Result<Vec<u8>> {
+        self.as_context_mut().extract_memory(memory_index)
+    }
+
+    /// Overwrites the memories of this store with those of `snapshot`, with
+    /// [`Store::inject_memory`].
+    ///
+    /// Memories are matched by their index across the whole store, so this
+    /// is meant for snapshots taken with [`Store::extract_data`] rather than
+    /// [`Instance::snapshot`]. Memories of the store beyond those of
+    /// `snapshot` are left untouched, as are globals and host states.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, without writing to any memory, if a memory of
+    /// `snapshot` does not exist in this store, is a shared memory or is
+    /// larger than the memory of the store, or if it was moved out of the
+    /// snapshot with [`Snapshot::export_memory_to_file`].
+    pub fn inject_data(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.as_context_mut().inject_data(snapshot)
+    }
+
+    /// Returns a [`Snapshot`] of the contents of all memories of this store,
+    /// with [`Store::extract_memory`].
+    ///
+    /// Memories are indexed as for [`Store::inject_memory`] and named
+    /// `memory{N}` after their index. The snapshot holds no globals or host
+    /// states; it is meant to be written back with [`Store::inject_data`],
+    /// or inspected with the methods of [`Snapshot`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if this store holds a shared memory.
+    pub fn extract_data(&mut self) -> Result<Snapshot> {
+        self.as_context_mut().extract_data()
+    }
+
+    /// Returns the number of frames of functions of `instance`'s module on
+    /// the wasm call stack of this store, or 0 if none of them is running,
+    /// for embedders which check that an instance is idle before taking a
+    /// [`Snapshot`] of it.
+    ///
+    /// This is the same as [`Instance::call_stack_depth`], which describes
+    /// which frames are seen. Wasm is only running while the store is
+    /// borrowed by a host function or an epoch callback, so this is mostly
+    /// useful through [`StoreContext::get_wasm_stack_depth`], for example on
+    /// `caller.as_context()` in a host function.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `instance` does not belong to this store.
+    pub fn get_wasm_stack_depth(&self, instance: &Instance) -> Result<u32> {
+        self.as_context().get_wasm_stack_depth(instance)
+    }
+
     /// Returns the amount of fuel consumed by this store's execution so far.
     ///
     /// If fuel consumption is not enabled via
@@ -1066,6 +1185,16 @@ impl<'a, T> StoreContext<'a, T> {
         self.0.data()
     }
 
+    /// Returns the depth of the wasm call stack of an instance in this store.
+    ///
+    /// For more information see [`Store::get_wasm_stack_depth`].
+    pub fn get_wasm_stack_depth(&self, instance: &Instance) -> Result<u32> {
+        if !instance.comes_from_same_store(self.0) {
+            bail!("instance does not belong to this store");
+        }
+        Ok(instance.call_stack_depth(self))
+    }
+
     /// Returns the fuel consumed by this store.
     ///
     /// For more information see [`Store::fuel_consumed`].
@@ -1108,6 +1237,54 @@ impl<'a, T> StoreContextMut<'a, T> {
         self.0.gc()
     }
 
+    /// Overwrites the start of a memory in this store.
+    ///
+    /// For more information see [`Store::inject_memory`].
+    pub fn inject_memory(&mut self, memory_index: u32, data: &[u8]) -> Result<()> {
+        let memory = self.0.memory_by_index(memory_index)?;
+        memory
+            .write(&mut *self, 0, data)
+            .map_err(|_| anyhow!("data does not fit in memory {memory_index}"))
+    }
+
+    /// Returns a copy of the contents of a memory in this store.
+    ///
+    /// For more information see [`Store::extract_memory`].
+    pub fn extract_memory(&mut self, memory_index: u32) -> Result<Vec<u8>> {
+        let memory = self.0.memory_by_index(memory_index)?;
+        Ok(memory.data(&*self).to_vec())
+    }
+
+    /// Overwrites the memories of this store with those of a snapshot.
+    ///
+    /// For more information see [`Store::inject_data`].
+    pub fn inject_data(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let regions = snapshot.memory_regions()?;
+        let mut memories = Vec::with_capacity(regions.len());
+        for region in &regions {
+            let memory = self.0.memory_by_index(region.index)?;
+            if region.data.len() > memory.data_size(&*self) {
+                bail!("data does not fit in memory {}", region.index);
+            }
+            memories.push(memory);
+        }
+        for (region, memory) in regions.iter().zip(memories) {
+            memory.data_mut(&mut *self)[..region.data.len()].copy_from_slice(region.data);
+        }
+        Ok(())
+    }
+
+    /// Returns a snapshot of the contents of all memories of this store.
+    ///
+    /// For more information see [`Store::extract_data`].
+    pub fn extract_data(&mut self) -> Result<Snapshot> {
+        let count = self.0.all_memories().count();
+        let memories = (0..count as u32)
+            .map(|index| Ok((format!("memory{index}"), self.extract_memory(index)?)))
+            .collect::<Result<_>>()?;
+        Ok(Snapshot::from_parts(memories, Vec::new()))
+    }
+
     /// Returns the fuel consumed by this store.
     ///
     /// For more information see [`Store::fuel_consumed`].
@@ -1222,6 +1399,26 @@ impl<T> StoreInner<T> {
             None => Ok(()),
         }
     }
+
+    /// Saves the host states registered with [`Store::snapshot_host_state`].
+    pub(crate) fn save_host_states(&mut self) -> Result<Vec<(String, SuspendState)>> {
+        self.host_state_hooks
+            .iter_mut()
+            .map(|hook| Ok((hook.name.clone(), (hook.save)(&mut self.data)?)))
+            .collect()
+    }
+
+    /// Restores the host states registered with
+    /// [`Store::snapshot_host_state`] from `states`, skipping those which
+    /// `states` does not contain.
+    pub(crate) fn restore_host_states(&mut self, states: &[(String, SuspendState)]) -> Result<()> {
+        for hook in &mut self.host_state_hooks {
+            if let Some((_, state)) = states.iter().find(|(name, _)| *name == hook.name) {
+                (hook.restore)(&mut self.data, state)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[doc(hidden)]
@@ -1386,6 +1583,19 @@ impl StoreOpaque {
             .map(|memory| unsafe { Memory::from_wasmtime_memory(memory, self) })
     }
 
+    /// Returns the non-shared memory at `index` in the order yielded by
+    /// [`StoreOpaque::all_memories`].
+    pub(crate) fn memory_by_index(&mut self, index: u32) -> Result<Memory> {
+        let memory = self
+            .all_memories()
+            .nth(index as usize)
+            .ok_or_else(|| anyhow!("memory {index} does not exist in this store"))?;
+        if memory.wasmtime_ty(self.store_data()).shared {
+            bail!("memory {index} is a shared memory");
+        }
+        Ok(memory)
+    }
+
     /// Iterate over all globals (host- or Wasm-defined) within this store.
     pub fn all_globals<'a>(&'a mut self) -> impl Iterator<Item = Global> + 'a {
         unsafe {
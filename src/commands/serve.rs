@@ -143,7 +143,7 @@ impl ServeCommand {
         let mut host = Host {
             table: Table::new(),
             ctx: builder.build(),
-            http: WasiHttpCtx,
+            http: WasiHttpCtx::new(),
 
             limits: StoreLimits::default(),
 
@@ -241,3 +241,176 @@ async fn poll_through_wasm_activation() -> Result<()> {
     .await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn call_and_snapshot() -> Result<()> {
+    let component = r#"
+        (component
+            (core module $m
+                (memory (export "mem") 1)
+                (global $count (mut i32) (i32.const 0))
+                (func (export "bump") (result i32)
+                    (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                    (i32.store (i32.const 0) (global.get $count))
+                    (global.get $count))
+            )
+            (core instance $i (instantiate $m))
+            (func (export "bump") (result u32)
+                (canon lift (core func $i "bump"))
+            )
+        )
+    "#;
+
+    let engine = super::async_engine();
+    let component = Component::new(&engine, component)?;
+    let mut store = Store::new(&engine, ());
+    let instance = Linker::new(&engine)
+        .instantiate_async(&mut store, &component)
+        .await?;
+    let bump = instance.get_typed_func::<(), (u32,)>(&mut store, "bump")?;
+
+    let ((count,), snapshot) = bump.call_and_snapshot_async(&mut store, ()).await?;
+    assert_eq!(count, 1);
+    let ((count,), _) = bump.call_and_snapshot_async(&mut store, ()).await?;
+    assert_eq!(count, 2);
+
+    assert_eq!(snapshot.globals()[0].unwrap_i32(), 1);
+    assert_eq!(snapshot.memory_regions()?[0].data[..4], 1u32.to_le_bytes());
+    Ok(())
+}
+
+#[tokio::test]
+async fn auto_snapshot() -> Result<()> {
+    use std::sync::{Arc, Mutex};
+
+    let component = r#"
+        (component
+            (core module $m
+                (global $count (mut i32) (i32.const 0))
+                (func (export "bump") (result i32)
+                    (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                    (global.get $count))
+            )
+            (core instance $i (instantiate $m))
+            (func (export "bump") (result u32)
+                (canon lift (core func $i "bump"))
+            )
+        )
+    "#;
+
+    let engine = super::async_engine();
+    let component = Component::new(&engine, component)?;
+    let mut store = Store::new(&engine, ());
+    let instance = Linker::new(&engine)
+        .instantiate_async(&mut store, &component)
+        .await?;
+
+    let counts = Arc::new(Mutex::new(Vec::new()));
+    let seen = counts.clone();
+    instance.enable_auto_snapshot(&mut store, "bump", 2, move |snapshot| {
+        seen.lock()
+            .unwrap()
+            .push(snapshot.globals()[0].unwrap_i32());
+    })?;
+    assert!(instance
+        .enable_auto_snapshot(&mut store, "bump", 0, |_| {})
+        .is_err());
+    assert!(instance
+        .enable_auto_snapshot(&mut store, "missing", 1, |_| {})
+        .is_err());
+
+    // Calls are counted across lookups of the export.
+    for _ in 0..5 {
+        let bump = instance.get_typed_func::<(), (u32,)>(&mut store, "bump")?;
+        bump.call_async(&mut store, ()).await?;
+        bump.post_return_async(&mut store).await?;
+    }
+    assert_eq!(*counts.lock().unwrap(), [2, 4]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn suspendable_async_host_function() -> Result<()> {
+    use std::future::Future;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use wasmtime::SuspendState;
+
+    /// Pretends to fetch rows one at a time, remembering how many were read.
+    struct Rows(Arc<AtomicU32>);
+
+    impl SuspendableAsyncHostFn<(), (), (u32,)> for Rows {
+        fn call<'a>(
+            &'a self,
+            _: StoreContextMut<'a, ()>,
+            _: (),
+        ) -> Box<dyn Future<Output = Result<(u32,)>> + Send + 'a> {
+            Box::new(async move {
+                tokio::task::yield_now().await;
+                Ok((self.0.fetch_add(1, Ordering::SeqCst) + 1,))
+            })
+        }
+
+        fn suspend(&self) -> Option<SuspendState> {
+            let read = self.0.load(Ordering::SeqCst);
+            Some(SuspendState::new(read.to_le_bytes().to_vec()))
+        }
+
+        fn resume(&self, state: SuspendState) -> Result<()> {
+            let read = u32::from_le_bytes(state.data().try_into()?);
+            self.0.store(read, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    let component = r#"
+        (component
+            (import "db" (instance $db
+                (export "next-row" (func (result u32)))
+            ))
+
+            (core func $next_row (canon lower (func $db "next-row")))
+            (core module $m
+                (import "" "" (func $next_row (result i32)))
+                (export "next-row" (func $next_row))
+            )
+            (core instance $i (instantiate $m
+                (with "" (instance
+                    (export "" (func $next_row))
+                ))
+            ))
+            (func (export "next-row") (result u32)
+                (canon lift (core func $i "next-row"))
+            )
+        )
+    "#;
+
+    let engine = super::async_engine();
+    let component = Component::new(&engine, component)?;
+    let read = Arc::new(AtomicU32::new(0));
+    let mut linker = Linker::new(&engine);
+    linker
+        .instance("db")?
+        .func_wrap_suspendable_async("next-row", Rows(read.clone()))?;
+
+    let mut store = Store::new(&engine, ());
+    let instance = linker.instantiate_async(&mut store, &component).await?;
+    let next_row = instance.get_typed_func::<(), (u32,)>(&mut store, "next-row")?;
+    assert_eq!(next_row.call_async(&mut store, ()).await?, (1,));
+    next_row.post_return_async(&mut store).await?;
+
+    let states = linker.suspend_states();
+    assert_eq!(
+        states,
+        [(
+            "db#next-row".to_string(),
+            SuspendState::new(vec![1, 0, 0, 0])
+        )]
+    );
+
+    read.store(0, Ordering::SeqCst);
+    linker.resume_states(&states)?;
+    assert_eq!(next_row.call_async(&mut store, ()).await?, (2,));
+    next_row.post_return_async(&mut store).await?;
+    Ok(())
+}
@@ -985,3 +985,88 @@ fn use_types_across_component_boundaries() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn suspendable_host_function() -> Result<()> {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use wasmtime::SuspendState;
+
+    struct Counter(Arc<AtomicU32>);
+
+    impl SuspendableHostFn<(), (), (u32,)> for Counter {
+        fn call(&self, _: StoreContextMut<'_, ()>, _: ()) -> Result<(u32,)> {
+            Ok((self.0.fetch_add(1, Ordering::SeqCst) + 1,))
+        }
+
+        fn suspend(&self) -> Option<SuspendState> {
+            let count = self.0.load(Ordering::SeqCst);
+            Some(SuspendState::new(count.to_le_bytes().to_vec()))
+        }
+
+        fn resume(&self, state: SuspendState) -> Result<()> {
+            let count = u32::from_le_bytes(state.data().try_into()?);
+            self.0.store(count, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    let component = r#"
+        (component
+            (import "host" (instance $host
+                (export "next" (func (result u32)))
+            ))
+
+            (core func $next_lower
+                (canon lower (func $host "next"))
+            )
+            (core module $m
+                (import "" "" (func $next (result i32)))
+                (export "next" (func $next))
+            )
+            (core instance $i (instantiate $m
+                (with "" (instance
+                    (export "" (func $next_lower))
+                ))
+            ))
+            (func (export "next") (result u32)
+                (canon lift
+                    (core func $i "next")
+                )
+            )
+        )
+    "#;
+
+    let engine = super::engine();
+    let component = Component::new(&engine, component)?;
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let mut linker = Linker::new(&engine);
+    linker
+        .instance("host")?
+        .func_wrap_suspendable("next", Counter(counter.clone()))?;
+
+    let mut store = Store::new(&engine, ());
+    let instance = linker.instantiate(&mut store, &component)?;
+    let next = instance.get_typed_func::<(), (u32,)>(&mut store, "next")?;
+    assert_eq!(next.call(&mut store, ())?, (1,));
+    next.post_return(&mut store)?;
+    assert_eq!(next.call(&mut store, ())?, (2,));
+    next.post_return(&mut store)?;
+
+    let states = linker.suspend_states();
+    assert_eq!(
+        states,
+        [("host#next".to_string(), SuspendState::new(vec![2, 0, 0, 0]))]
+    );
+
+    counter.store(0, Ordering::SeqCst);
+    linker.resume_states(&states)?;
+    assert_eq!(next.call(&mut store, ())?, (3,));
+    next.post_return(&mut store)?;
+
+    let unknown = [("host#other".to_string(), SuspendState::new(Vec::new()))];
+    assert!(linker.resume_states(&unknown).is_err());
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 use wasmtime::component::*;
-use wasmtime::{Module, Store};
+use wasmtime::{Module, Store, StoreContextMut};
 
 #[test]
 fn instance_exports() -> Result<()> {
@@ -55,3 +55,106 @@ fn instance_exports() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn instance_list_all_exports() -> Result<()> {
+    use wasmtime::component::types::Type;
+
+    let engine = super::engine();
+    let component = r#"
+        (component
+            (core module $m
+                (func (export "add") (param i32 i32) (result i32)
+                    local.get 0
+                    local.get 1
+                    i32.add)
+                (func (export "nop"))
+            )
+            (core instance $i (instantiate $m))
+            (func $add (param "a" u32) (param "b" u32) (result u32)
+                (canon lift (core func $i "add")))
+            (func $nop (canon lift (core func $i "nop")))
+            (instance $api (export "add" (func $add)))
+            (export "api" (instance $api))
+            (export "nop" (func $nop))
+        )
+    "#;
+    let component = Component::new(&engine, component)?;
+    let mut store = Store::new(&engine, ());
+    let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+
+    let exports = instance.list_all_exports(&mut store);
+    let listed = exports
+        .iter()
+        .map(|e| (e.path.join("/"), e.kind))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        listed,
+        [
+            ("api".to_string(), ExportKind::Instance),
+            ("api/add".to_string(), ExportKind::Function),
+            ("nop".to_string(), ExportKind::Function),
+        ]
+    );
+    assert!(exports[0].as_func().is_none());
+    assert_eq!(&*exports[1].params, [Type::U32, Type::U32]);
+    assert_eq!(&*exports[1].results, [Type::U32]);
+    assert!(exports[2].params.is_empty() && exports[2].results.is_empty());
+
+    let add = exports[1].as_func().unwrap();
+    let mut results = [Val::U32(0)];
+    add.call(&mut store, &[Val::U32(1), Val::U32(2)], &mut results)?;
+    add.post_return(&mut store)?;
+    assert_eq!(results, [Val::U32(3)]);
+    Ok(())
+}
+
+#[test]
+fn instance_pre_clone() -> Result<()> {
+    let engine = super::engine();
+    let component = r#"
+        (component
+            (import "host" (func $host (result u32)))
+            (core func $host_lowered (canon lower (func $host)))
+            (core module $m
+                (import "" "host" (func $host (result i32)))
+                (func (export "run") (result i32)
+                    call $host
+                    i32.const 1
+                    i32.add)
+            )
+            (core instance $i (instantiate $m
+                (with "" (instance (export "host" (func $host_lowered))))
+            ))
+            (func (export "run") (result u32)
+                (canon lift (core func $i "run")))
+        )
+    "#;
+    let component = Component::new(&engine, component)?;
+    let mut linker = Linker::new(&engine);
+    linker
+        .root()
+        .func_wrap("host", |store: StoreContextMut<'_, u32>, (): ()| {
+            Ok((*store.data(),))
+        })?;
+    let instance_pre = linker.instantiate_pre(&component)?;
+
+    let workers = (0..4)
+        .map(|i| {
+            let engine = engine.clone();
+            let instance_pre = instance_pre.clone();
+            std::thread::spawn(move || -> Result<u32> {
+                let mut store = Store::new(&engine, i * 10);
+                let instance = instance_pre.instantiate(&mut store)?;
+                let run = instance.get_typed_func::<(), (u32,)>(&mut store, "run")?;
+                Ok(run.call(&mut store, ())?.0)
+            })
+        })
+        .collect::<Vec<_>>();
+    let results = workers
+        .into_iter()
+        .map(|worker| worker.join().unwrap())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(results, [1, 11, 21, 31]);
+    Ok(())
+}
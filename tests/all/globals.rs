@@ -102,3 +102,36 @@ fn v128() -> anyhow::Result<()> {
     assert_eq!(g.get(&mut store).v128(), Some(V128::from(1)));
     Ok(())
 }
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn read_write_exported_global() -> anyhow::Result<()> {
+    let mut store = Store::<()>::default();
+    let module = Module::new(
+        store.engine(),
+        r#"
+            (module
+                (global (export "sp") (mut i32) (i32.const 1024))
+                (global (export "limit") i64 (i64.const 7))
+                (memory (export "mem") 1))
+        "#,
+    )?;
+    let instance = Instance::new(&mut store, &module, &[])?;
+
+    assert_eq!(instance.read_global(&mut store, "sp")?.i32(), Some(1024));
+    instance.write_global(&mut store, "sp", Val::I32(2048))?;
+    assert_eq!(instance.read_global(&mut store, "sp")?.i32(), Some(2048));
+
+    let err = instance.read_global(&mut store, "missing").unwrap_err();
+    assert!(err.to_string().contains("missing"), "{err}");
+    let err = instance.read_global(&mut store, "mem").unwrap_err();
+    assert!(err.to_string().contains("mem"), "{err}");
+    let err = instance
+        .write_global(&mut store, "limit", Val::I64(8))
+        .unwrap_err();
+    assert!(err.to_string().contains("limit"), "{err}");
+    assert!(instance
+        .write_global(&mut store, "sp", Val::I64(1))
+        .is_err());
+    Ok(())
+}
@@ -30,6 +30,7 @@ mod module_serialize;
 mod name;
 mod pooling_allocator;
 mod relocs;
+mod snapshot;
 mod stack_creator;
 mod stack_overflow;
 mod store;
@@ -576,3 +576,31 @@ fn shared_memory_wait_notify() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn memory_inspector() -> Result<()> {
+    let mut store = Store::<()>::default();
+    let module = Module::new(
+        store.engine(),
+        r#"(module (memory 1) (data (i32.const 16) "hello\00"))"#,
+    )?;
+    let instance = Instance::new(&mut store, &module, &[])?;
+
+    assert_eq!(store.read_cstring(&instance, 0, 16, 64)?, "hello");
+    assert!(store.read_cstring(&instance, 0, 16, 5).is_err());
+    assert_eq!(store.read_bytes(&instance, 0, 16..18)?, b"he");
+
+    store.write_u32_le(&instance, 0, 0, 0xdeadbeef)?;
+    assert_eq!(store.read_u32_le(&instance, 0, 0)?, 0xdeadbeef);
+    store.write_bytes(&instance, 0, 4, &[1, 2])?;
+    assert_eq!(
+        store.read_bytes(&instance, 0, 0..6)?,
+        [0xef, 0xbe, 0xad, 0xde, 1, 2]
+    );
+
+    assert!(store.read_u32_le(&instance, 0, 65534).is_err());
+    assert!(store.write_bytes(&instance, 0, 65535, &[1, 2]).is_err());
+    assert!(store.read_bytes(&instance, 1, 0..1).is_err());
+    Ok(())
+}
@@ -0,0 +1,1916 @@
+use anyhow::Result;
+use wasmtime::*;
+
+const COUNTER: &str = r#"
+    (module
+        (memory (export "mem") 1 4)
+        (memory 1)
+        (global $count (export "count") (mut i32) (i32.const 0))
+        (global (export "limit") i64 (i64.const 10))
+        (func (export "bump")
+            (global.set $count (i32.add (global.get $count) (i32.const 1)))
+            (i32.store (i32.const 0) (global.get $count)))
+    )
+"#;
+
+fn new_store() -> Result<Store<()>> {
+    let mut config = Config::new();
+    config.wasm_multi_memory(true);
+    Ok(Store::new(&Engine::new(&config)?, ()))
+}
+
+fn instantiate(store: &mut Store<()>) -> Result<Instance> {
+    let module = Module::new(store.engine(), COUNTER)?;
+    Instance::new(store, &module, &[])
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_restore_roundtrip() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    let count = instance.get_global(&mut store, "count").unwrap();
+
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    bump.call(&mut store, ())?;
+    mem.grow(&mut store, 1)?;
+    assert_eq!(count.get(&mut store).unwrap_i32(), 2);
+
+    instance.restore(&mut store, &snapshot)?;
+    assert_eq!(count.get(&mut store).unwrap_i32(), 1);
+    assert_eq!(&mem.data(&store)[..4], &1i32.to_le_bytes());
+    assert!(mem.data(&store)[65536..].iter().all(|b| *b == 0));
+
+    // Snapshots can be restored into a fresh instance of the same module.
+    let mut store2 = new_store()?;
+    let instance2 = instantiate(&mut store2)?;
+    instance2.restore(&mut store2, &snapshot)?;
+    let count2 = instance2.get_global(&mut store2, "count").unwrap();
+    assert_eq!(count2.get(&mut store2).unwrap_i32(), 1);
+
+    // ... but not into an instance of a different module.
+    let other = Module::new(store2.engine(), "(module (memory 1))")?;
+    let other = Instance::new(&mut store2, &other, &[])?;
+    assert!(other.restore(&mut store2, &snapshot).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_host_state() -> Result<()> {
+    let mut config = Config::new();
+    config.wasm_multi_memory(true);
+    let mut store = Store::new(&Engine::new(&config)?, 7u32);
+    store.snapshot_host_state(
+        "counter",
+        |data| Ok(SuspendState::new(data.to_le_bytes().to_vec())),
+        |data, state| {
+            *data = u32::from_le_bytes(state.data().try_into()?);
+            Ok(())
+        },
+    );
+    let module = Module::new(store.engine(), COUNTER)?;
+    let instance = Instance::new(&mut store, &module, &[])?;
+
+    let snapshot = instance.snapshot(&mut store)?;
+    assert_eq!(snapshot.host_states().len(), 1);
+    assert_eq!(snapshot.host_states()[0].0, "counter");
+
+    *store.data_mut() = 42;
+    instance.restore(&mut store, &snapshot)?;
+    assert_eq!(*store.data(), 7);
+
+    // Snapshots without the host state leave it untouched.
+    *store.data_mut() = 42;
+    instance.restore(&mut store, &snapshot.clone().with_host_states(Vec::new()))?;
+    assert_eq!(*store.data(), 42);
+
+    // Errors of the hooks are returned from the snapshot and the restore.
+    store.snapshot_host_state("counter", |_| anyhow::bail!("save"), |_, _| Ok(()));
+    assert!(instance.snapshot(&mut store).is_err());
+    store.snapshot_host_state(
+        "counter",
+        |_| Ok(SuspendState::new(Vec::new())),
+        |_, _| anyhow::bail!("restore"),
+    );
+    assert!(instance.restore(&mut store, &snapshot).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_size() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+
+    let estimate = instance.snapshot_size_estimate(&mut store)?;
+    assert_eq!(estimate.total_bytes, 2 * 65536 + 4 + 8);
+    assert_eq!(
+        estimate.regions,
+        [
+            MemoryRegionInfo {
+                name: "mem".to_string(),
+                size_bytes: 65536,
+            },
+            MemoryRegionInfo {
+                name: "memory1".to_string(),
+                size_bytes: 65536,
+            },
+        ]
+    );
+
+    let snapshot = instance.snapshot(&mut store)?;
+    assert_eq!(snapshot.size_bytes(), estimate.total_bytes);
+    assert_eq!(snapshot.region_stats(), estimate.regions);
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_globals_and_memory_ranges() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    let count = instance.get_global(&mut store, "count").unwrap();
+
+    bump.call(&mut store, ())?;
+    let globals = instance.snapshot_globals_only(&mut store)?;
+    assert_eq!(globals.globals().len(), 1);
+    let range = instance.snapshot_memory_range(&mut store, 0, 0, 4)?;
+    assert_eq!(range.data(), &1i32.to_le_bytes());
+
+    bump.call(&mut store, ())?;
+    instance.restore_globals(&mut store, &globals)?;
+    assert_eq!(count.get(&mut store).unwrap_i32(), 1);
+    assert_eq!(&mem.data(&store)[..4], &2i32.to_le_bytes());
+
+    instance.restore_memory_range(&mut store, &range)?;
+    assert_eq!(&mem.data(&store)[..4], &1i32.to_le_bytes());
+
+    assert!(instance
+        .snapshot_memory_range(&mut store, 0, 65535, 2)
+        .is_err());
+    assert!(instance.snapshot_memory_range(&mut store, 2, 0, 1).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_diff_report() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+
+    let before = instance.snapshot(&mut store)?;
+    assert!(Snapshot::diff_report(&before, &before)?.is_identical());
+
+    bump.call(&mut store, ())?;
+    let after = instance.snapshot(&mut store)?;
+    let report = Snapshot::diff_report(&before, &after)?;
+    assert!(!report.is_identical());
+    assert_eq!(
+        report.memories,
+        [MemoryDiff {
+            memory_index: 0,
+            name: "mem".to_string(),
+            differing_bytes: 1,
+            first_difference: 0,
+        }]
+    );
+    assert_eq!(report.globals.len(), 1);
+    assert_eq!(report.globals[0].global_index, 0);
+    assert!(report
+        .to_string()
+        .contains("memory 0 (mem): 1 bytes differ"));
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(miri, ignore)]
+async fn call_and_snapshot_async() -> Result<()> {
+    let mut config = Config::new();
+    config.wasm_multi_memory(true).async_support(true);
+    let mut store = Store::new(&Engine::new(&config)?, ());
+    let module = Module::new(store.engine(), COUNTER)?;
+    let instance = Instance::new_async(&mut store, &module, &[]).await?;
+    let bump = instance.get_func(&mut store, "bump").unwrap();
+
+    let (results, snapshot) = instance
+        .call_and_snapshot_async(&mut store, &bump, &[])
+        .await?;
+    assert!(results.is_empty());
+
+    bump.call_async(&mut store, &[], &mut []).await?;
+    let count = instance.get_global(&mut store, "count").unwrap();
+    assert_eq!(count.get(&mut store).unwrap_i32(), 2);
+
+    instance.restore(&mut store, &snapshot)?;
+    assert_eq!(count.get(&mut store).unwrap_i32(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(miri, ignore)]
+async fn replay_calls_async() -> Result<()> {
+    let mut config = Config::new();
+    config.async_support(true);
+    let mut store = Store::new(&Engine::new(&config)?, ());
+    let module = Module::new(
+        store.engine(),
+        r#"
+            (module
+                (global $total (mut i64) (i64.const 0))
+                (func (export "add") (param i64) (result i64)
+                    (global.set $total (i64.add (global.get $total) (local.get 0)))
+                    global.get $total)
+            )
+        "#,
+    )?;
+    let instance = Instance::new_async(&mut store, &module, &[]).await?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let mut recorder = CallLogRecorder::new();
+    for n in [2, 3] {
+        recorder
+            .call_async(&mut store, &instance, "add", &[Val::I64(n)])
+            .await?;
+    }
+    assert!(recorder
+        .call_async(&mut store, &instance, "missing", &[])
+        .await
+        .is_err());
+    let log = CallLog::from_bytes(&recorder.into_log().to_bytes()?)?;
+    assert_eq!(log.entries().len(), 2);
+    assert_eq!(log.entries()[1].func_name, "add");
+    assert_eq!(log.entries()[1].results[0].unwrap_i64(), 5);
+
+    // Replaying from the state the calls were recorded in gives the same
+    // results.
+    instance.restore(&mut store, &snapshot)?;
+    let replayed = instance.replay_calls_async(&mut store, &log).await?;
+    let totals = replayed
+        .iter()
+        .map(|r| r.results[0].unwrap_i64())
+        .collect::<Vec<_>>();
+    assert_eq!(totals, [2, 5]);
+
+    // Replaying from any other state diverges at the first call.
+    let err = instance
+        .replay_calls_async(&mut store, &log)
+        .await
+        .unwrap_err();
+    let divergence = err.downcast::<ReplayDivergence>()?;
+    assert_eq!(divergence.call_index, 0);
+    assert_eq!(divergence.expected[0].unwrap_i64(), 2);
+    assert_eq!(divergence.actual[0].unwrap_i64(), 7);
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(miri, ignore)]
+async fn instantiate_from_snapshot_async() -> Result<()> {
+    let mut config = Config::new();
+    config.async_support(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(
+        &engine,
+        r#"
+            (module
+                (import "host" "init" (func $host_init))
+                (memory (export "mem") 1)
+                (global $state (export "state") (mut i32) (i32.const 0))
+                (func $init
+                    call $host_init
+                    (global.set $state (i32.const 42))
+                    (memory.fill (i32.const 0) (i32.const 7) (i32.const 0x10000)))
+                (start $init)
+            )
+        "#,
+    )?;
+    let mut linker = Linker::<u32>::new(&engine);
+    linker.func_wrap("host", "init", |mut caller: Caller<'_, u32>| {
+        *caller.data_mut() += 1;
+    })?;
+    let pre = linker.instantiate_pre(&module)?;
+
+    let mut store = Store::new(&engine, 0);
+    let instance = pre.instantiate_async(&mut store).await?;
+    assert_eq!(*store.data(), 1);
+    let snapshot = instance.snapshot(&mut store)?;
+
+    // Replicas have the initialized state without running the start function.
+    for _ in 0..2 {
+        let mut store = Store::new(&engine, 0);
+        let replica = pre
+            .instantiate_from_snapshot_async(&mut store, &snapshot)
+            .await?;
+        assert_eq!(*store.data(), 0);
+        let state = replica.get_global(&mut store, "state").unwrap();
+        assert_eq!(state.get(&mut store).unwrap_i32(), 42);
+        let mem = replica.get_memory(&mut store, "mem").unwrap();
+        assert!(mem.data(&store).iter().all(|b| *b == 7));
+    }
+
+    let other = Module::new(&engine, "(module (memory 2))")?;
+    let other = Linker::new(&engine).instantiate_pre(&other)?;
+    let mut store = Store::new(&engine, 0);
+    assert!(other
+        .instantiate_from_snapshot_async(&mut store, &snapshot)
+        .await
+        .is_err());
+    Ok(())
+}
+
+/// Registers the data of `store` as a host state saved into and restored
+/// from snapshots.
+fn register_host_state(store: &mut Store<Vec<u8>>) {
+    store.snapshot_host_state(
+        "data",
+        |data| Ok(SuspendState::new(data.clone())),
+        |data, state| {
+            *data = state.data().to_vec();
+            Ok(())
+        },
+    );
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn instantiate_from_snapshot_restores_host_states() -> Result<()> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, "(module (memory 1))")?;
+    let pre = Linker::new(&engine).instantiate_pre(&module)?;
+
+    let mut store = Store::new(&engine, b"initialized".to_vec());
+    register_host_state(&mut store);
+    let instance = pre.instantiate(&mut store)?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let mut store = Store::new(&engine, Vec::new());
+    register_host_state(&mut store);
+    pre.instantiate_from_snapshot(&mut store, &snapshot)?;
+    assert_eq!(store.data(), b"initialized");
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(miri, ignore)]
+async fn instantiate_from_snapshot_async_restores_host_states() -> Result<()> {
+    let mut config = Config::new();
+    config.async_support(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(&engine, "(module (memory 1))")?;
+    let pre = Linker::new(&engine).instantiate_pre(&module)?;
+
+    let mut store = Store::new(&engine, b"initialized".to_vec());
+    register_host_state(&mut store);
+    let instance = pre.instantiate_async(&mut store).await?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let mut store = Store::new(&engine, Vec::new());
+    register_host_state(&mut store);
+    pre.instantiate_from_snapshot_async(&mut store, &snapshot)
+        .await?;
+    assert_eq!(store.data(), b"initialized");
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_apply_patch() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let patched = snapshot.apply_patch(MemoryPatch {
+        memory_index: 0,
+        writes: vec![(0x10, vec![1, 2, 3]), (0x11, vec![9])],
+    })?;
+    assert_eq!(
+        Snapshot::diff_report(&snapshot, &patched)?.memories.len(),
+        1
+    );
+    instance.restore(&mut store, &patched)?;
+    assert_eq!(&mem.data(&store)[0x10..0x13], &[1, 9, 3]);
+
+    let err = snapshot
+        .apply_patch(MemoryPatch {
+            memory_index: 1,
+            writes: vec![(0, vec![1]), (65535, vec![1, 2])],
+        })
+        .unwrap_err();
+    assert!(err.to_string().contains("offset 0xffff"), "{err}");
+    assert!(snapshot
+        .apply_patch(MemoryPatch {
+            memory_index: 2,
+            writes: vec![],
+        })
+        .is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_truncate_memory() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    mem.grow(&mut store, 1)?;
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?;
+    assert_eq!(snapshot.region_stats()[0].size_bytes, 2 * 65536);
+
+    let unused = |tail: &[u8]| tail.iter().all(|b| *b == 0);
+    let truncated = snapshot.truncate_memory(0, 65536, unused)?;
+    assert_eq!(truncated.region_stats()[0].size_bytes, 65536);
+
+    let padded = truncated.truncate_memory(1, 3 * 65536, |_| false)?;
+    assert_eq!(padded.region_stats()[1].size_bytes, 3 * 65536);
+
+    // The counter lives at address 0, so it cannot be discarded.
+    assert!(snapshot.truncate_memory(0, 0, unused).is_err());
+    assert!(snapshot.truncate_memory(2, 0, unused).is_err());
+
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    instance.restore(&mut store, &truncated)?;
+    let count = instance.get_global(&mut store, "count").unwrap();
+    assert_eq!(count.get(&mut store).unwrap_i32(), 1);
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_restore_partial() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    let count = instance.get_global(&mut store, "count").unwrap();
+
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?.apply_patch(MemoryPatch {
+        memory_index: 1,
+        writes: vec![(0, vec![7])],
+    })?;
+    bump.call(&mut store, ())?;
+
+    // Only the second memory and the globals are restored.
+    let partial = snapshot.retain_only_memories(&[1])?;
+    assert_eq!(partial.region_stats().len(), 1);
+    instance.restore_partial(&mut store, &partial)?;
+    assert_eq!(count.get(&mut store).unwrap_i32(), 1);
+    assert_eq!(&mem.data(&store)[..4], &2i32.to_le_bytes());
+    let after = instance.snapshot(&mut store)?;
+    assert_eq!(after.memory_regions()?[1].data[0], 7);
+
+    // A full snapshot restores every memory, as with `restore`.
+    instance.restore_partial(&mut store, &snapshot)?;
+    assert_eq!(&mem.data(&store)[..4], &1i32.to_le_bytes());
+
+    assert!(snapshot.retain_only_memories(&[2]).is_err());
+    let other = Module::new(store.engine(), "(module (memory 1))")?;
+    let other = Instance::new(&mut store, &other, &[])?;
+    let err = other.restore_partial(&mut store, &partial).unwrap_err();
+    assert!(err.to_string().contains("memory 1"), "{err}");
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_zero_sensitive_regions() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    bump.call(&mut store, ())?;
+    mem.data_mut(&mut store)[8..16].fill(0xff);
+    let mut snapshot = instance.snapshot(&mut store)?;
+
+    // `limit` holds 10, which serves as the address of a symbol here.
+    let mut regions = SensitiveRegionMap::new();
+    regions
+        .range(0, 0..4)
+        .symbol(&mut store, &instance, "limit", 4)?;
+    assert_eq!(regions.ranges(), &[(0, 0..4), (0, 10..14)]);
+    assert!(regions
+        .clone()
+        .symbol(&mut store, &instance, "bump", 4)
+        .is_err());
+
+    let original = snapshot.clone();
+    let err = snapshot
+        .zero_sensitive_regions(&[(0, 0..4), (1, 65535..65537)])
+        .unwrap_err();
+    assert!(err.to_string().contains("memory 1"), "{err}");
+    assert!(snapshot.zero_sensitive_regions(&[(2, 0..0)]).is_err());
+    assert!(Snapshot::diff_report(&original, &snapshot)?.is_identical());
+
+    snapshot.zero_sensitive_regions(regions.ranges())?;
+    instance.restore(&mut store, &snapshot)?;
+    assert_eq!(
+        &mem.data(&store)[..16],
+        &[0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 0, 0, 0xff, 0xff]
+    );
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_overlay() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    mem.data_mut(&mut store)[..8].fill(0xaa);
+    let base = instance.snapshot(&mut store)?;
+    bump.call(&mut store, ())?;
+    mem.data_mut(&mut store)[4..8].fill(0xbb);
+    let overlay = instance.snapshot(&mut store)?;
+
+    let merged = Snapshot::overlay(&base, &overlay, &[(0, 4..6), (0, 5..7)])?;
+    instance.restore(&mut store, &merged)?;
+    assert_eq!(
+        &mem.data(&store)[..8],
+        &[0xaa, 0xaa, 0xaa, 0xaa, 0xbb, 0xbb, 0xbb, 0xaa]
+    );
+    // Globals come from the base snapshot.
+    assert_eq!(merged.globals()[0].unwrap_i32(), 0);
+
+    let err = Snapshot::overlay(&base, &overlay, &[(0, 0..0x10001)]).unwrap_err();
+    assert!(err.to_string().contains("overlay snapshot"), "{err}");
+    assert!(Snapshot::overlay(&base, &overlay, &[(2, 0..1)]).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_rebase() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    let count = instance.get_global(&mut store, "count").unwrap();
+    count.set(&mut store, Val::I32(0x1000))?;
+    let data = mem.data_mut(&mut store);
+    data[0..4].copy_from_slice(&0x1010u32.to_le_bytes());
+    data[4..8].copy_from_slice(&0x3000u32.to_le_bytes());
+    data[8..16].copy_from_slice(&0x1020u64.to_le_bytes());
+    let snapshot = instance.snapshot(&mut store)?;
+
+    // Globals 0 and 1 are `count` and `limit`.
+    let rebased = snapshot.rebase_globals(&[(0, 0x800), (1, -10)])?;
+    instance.restore(&mut store, &rebased)?;
+    assert_eq!(count.get(&mut store).unwrap_i32(), 0x1800);
+    assert!(snapshot.rebase_globals(&[(0, -0x1001)]).is_err());
+    assert!(snapshot
+        .rebase_globals(&[(0, i64::from(u32::MAX))])
+        .is_err());
+    assert!(snapshot.rebase_globals(&[(2, 1)]).is_err());
+
+    let rebased =
+        snapshot.rebase_memory_pointers(0x1000, 0x8000, 0x1000, PointerWidth::U32, &[(0, 0..8)])?;
+    instance.restore(&mut store, &rebased)?;
+    let data = mem.data(&store);
+    assert_eq!(&data[0..4], &0x8010u32.to_le_bytes());
+    assert_eq!(&data[4..8], &0x3000u32.to_le_bytes());
+    assert_eq!(&data[8..16], &0x1020u64.to_le_bytes());
+
+    let rebased = snapshot.rebase_memory_pointers(
+        0x1000,
+        0x8000,
+        0x1000,
+        PointerWidth::U64,
+        &[(0, 8..16)],
+    )?;
+    instance.restore(&mut store, &rebased)?;
+    assert_eq!(&mem.data(&store)[8..16], &0x8020u64.to_le_bytes());
+
+    assert!(snapshot
+        .rebase_memory_pointers(0, u32::MAX as usize, 2, PointerWidth::U32, &[])
+        .is_err());
+    assert!(snapshot
+        .rebase_memory_pointers(0, 0, 1, PointerWidth::U32, &[(1, 65532..65540)])
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(miri, ignore)]
+async fn snapshot_stores() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    let count = instance.get_global(&mut store, "count").unwrap();
+
+    let dir = tempfile::tempdir()?;
+    let stores: [Box<dyn SnapshotStore>; 2] = [
+        Box::new(FileSnapshotStore::new(dir.path().join("snapshots"))),
+        Box::new(MemorySnapshotStore::new()),
+    ];
+    for snapshots in stores.iter() {
+        bump.call(&mut store, ())?;
+        let first = instance.snapshot(&mut store)?;
+        bump.call(&mut store, ())?;
+        let second = instance.snapshot(&mut store)?;
+
+        let tags = [("stage".to_string(), "first".to_string())].into();
+        snapshots.save_with_tags("first", &first, tags).await?;
+        snapshots.save("second", &second).await?;
+
+        assert!(snapshots.has_snapshot(&first.compute_hash()));
+        let list = snapshots.list().await?;
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[1].hash, second.compute_hash());
+        assert_eq!(list[0].id, "first");
+        assert_eq!(list[0].tags["stage"], "first");
+        assert_eq!(list[0].size_bytes, first.size_bytes());
+        assert!(list[1].tags.is_empty());
+
+        let loaded = snapshots.load("first").await?;
+        assert!(Snapshot::diff_report(&first, &loaded)?.is_identical());
+        instance.restore(&mut store, &loaded)?;
+        let expected = count.get(&mut store).unwrap_i32();
+        instance.restore(&mut store, &first)?;
+        assert_eq!(count.get(&mut store).unwrap_i32(), expected);
+
+        snapshots.delete("first").await?;
+        assert!(snapshots.load("first").await.is_err());
+        assert!(snapshots.delete("first").await.is_err());
+        assert!(!snapshots.has_snapshot(&first.compute_hash()));
+        assert_eq!(snapshots.list().await?.len(), 1);
+    }
+
+    let files = FileSnapshotStore::new(dir.path().into());
+    assert!(files
+        .save("../escape", &instance.snapshot(&mut store)?)
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(miri, ignore)]
+async fn snapshot_annotations() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let snapshot = instance
+        .snapshot(&mut store)?
+        .with_annotation("version", "2.3.1")
+        .with_annotation("session", "a")
+        .with_annotation("session", "b");
+    assert_eq!(snapshot.get_annotation("version"), Some("2.3.1"));
+    assert_eq!(snapshot.get_annotation("session"), Some("b"));
+    assert_eq!(snapshot.get_annotation("missing"), None);
+    assert_eq!(snapshot.annotations().len(), 2);
+
+    // Annotations are metadata rather than state.
+    let plain = instance.snapshot(&mut store)?;
+    assert_eq!(snapshot.compute_hash(), plain.compute_hash());
+
+    let roundtrip = Snapshot::from_bytes(&snapshot.to_bytes()?)?;
+    assert_eq!(roundtrip.annotations(), snapshot.annotations());
+    let (header, data) = snapshot.split(&[])?;
+    let rejoined = SnapshotShard::rejoin(&SnapshotShard::from_bytes(&header.to_bytes()?)?, &data)?;
+    assert_eq!(rejoined.annotations(), snapshot.annotations());
+
+    let snapshots = MemorySnapshotStore::new();
+    snapshots.save("annotated", &snapshot).await?;
+    snapshots.save("plain", &plain).await?;
+    let found = snapshots.list_by_annotation("session", "b").await?;
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, "annotated");
+    assert_eq!(found[0].annotations["version"], "2.3.1");
+    assert!(snapshots
+        .list_by_annotation("session", "a")
+        .await?
+        .is_empty());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_compute_hash() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+
+    bump.call(&mut store, ())?;
+    let a = instance.snapshot(&mut store)?.compute_hash();
+    let b = instance.snapshot(&mut store)?.compute_hash();
+    assert_eq!(a, b);
+    assert_eq!(a.to_string().len(), 64);
+
+    // The same state in another store hashes identically as well.
+    let mut store2 = new_store()?;
+    let instance2 = instantiate(&mut store2)?;
+    instance2
+        .get_typed_func::<(), ()>(&mut store2, "bump")?
+        .call(&mut store2, ())?;
+    assert_eq!(instance2.snapshot(&mut store2)?.compute_hash(), a);
+
+    bump.call(&mut store, ())?;
+    let c = instance.snapshot(&mut store)?.compute_hash();
+    assert_ne!(a, c);
+
+    let mut seen = std::collections::HashMap::new();
+    seen.insert(a, "first");
+    assert_eq!(seen.get(&b), Some(&"first"));
+    assert_eq!(seen.get(&c), None);
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_checksums() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+
+    bump.call(&mut store, ())?;
+    let before = instance.snapshot(&mut store)?;
+    let memories = before.memory_checksum_map();
+    assert_eq!(memories.len(), 2);
+    assert_ne!(memories[&0], memories[&1]);
+
+    // Verify a restore by snapshotting again and comparing checksums.
+    bump.call(&mut store, ())?;
+    instance.restore(&mut store, &before)?;
+    let after = instance.snapshot(&mut store)?;
+    assert_eq!(after.memory_checksum_map(), memories);
+    assert_eq!(after.globals_checksum(), before.globals_checksum());
+
+    bump.call(&mut store, ())?;
+    let changed = instance.snapshot(&mut store)?;
+    assert_ne!(changed.memory_checksum_map()[&0], memories[&0]);
+    assert_eq!(changed.memory_checksum_map()[&1], memories[&1]);
+    assert_ne!(changed.globals_checksum(), before.globals_checksum());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_suspend_resume() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    bump.call(&mut store, ())?;
+
+    let suspended = instance.suspend(&mut store)?;
+    let engine = store.engine().clone();
+    drop(store);
+
+    let mut store = Store::new(&engine, ());
+    let linker = Linker::new(&engine);
+    let instance = suspended.resume(&linker, &mut store)?;
+    let count = instance.get_global(&mut store, "count").unwrap();
+    assert_eq!(count.get(&mut store).i32(), Some(2));
+
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    assert_eq!(&mem.data(&store)[..4], &3i32.to_le_bytes());
+
+    // The handle may be resumed any number of times.
+    let mut store = Store::new(&engine, ());
+    let instance = suspended.resume(&linker, &mut store)?;
+    let count = instance.get_global(&mut store, "count").unwrap();
+    assert_eq!(count.get(&mut store).i32(), Some(2));
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_validate_for_component() -> Result<()> {
+    let mut config = Config::new();
+    config.wasm_multi_memory(true).wasm_component_model(true);
+    let engine = Engine::new(&config)?;
+    let mut store = Store::new(&engine, ());
+    let instance = Instance::new(&mut store, &Module::new(&engine, COUNTER)?, &[])?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let component = component::Component::new(
+        &engine,
+        format!(
+            "(component {})",
+            COUNTER.replacen("(module", "(core module", 1)
+        ),
+    )?;
+    assert_eq!(
+        snapshot.validate_for_component(&component),
+        SnapshotCompatibility::Compatible
+    );
+
+    // A newer version of the module with an extra global accepts the
+    // snapshot, leaving the new global at its initial value.
+    let newer = r#"
+        (memory (export "mem") 1 4)
+        (memory 1)
+        (global (export "count") (mut i32) (i32.const 0))
+        (global (export "limit") i64 (i64.const 10))
+        (global (export "extra") (mut i32) (i32.const 7))
+    "#;
+    let component =
+        component::Component::new(&engine, format!("(component (core module {newer}))"))?;
+    let compat = snapshot.validate_for_component(&component);
+    assert!(matches!(
+        compat,
+        SnapshotCompatibility::CompatibleWithWarnings(_)
+    ));
+    assert!(compat.is_compatible());
+    let module = Module::new(&engine, format!("(module {newer})"))?;
+    assert_eq!(snapshot.validate_for_module(&module), compat);
+    let instance = Instance::new(&mut store, &module, &[])?;
+    instance.restore(&mut store, &snapshot)?;
+    let extra = instance.get_global(&mut store, "extra").unwrap();
+    assert_eq!(extra.get(&mut store).unwrap_i32(), 7);
+
+    let component = component::Component::new(
+        &engine,
+        "(component (core module (memory 1)) (core module (global i64 (i64.const 0))))",
+    )?;
+    match snapshot.validate_for_component(&component) {
+        SnapshotCompatibility::Incompatible(reasons) => {
+            assert!(reasons.iter().any(|r| r.starts_with("core module 0")));
+            assert!(reasons.iter().any(|r| r.starts_with("core module 1")));
+        }
+        other => panic!("unexpected compatibility {other:?}"),
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_component_static_memory_layout() -> Result<()> {
+    let mut config = Config::new();
+    config.wasm_multi_memory(true).wasm_component_model(true);
+    let engine = Engine::new(&config)?;
+    let component = component::Component::new(
+        &engine,
+        r#"
+            (component
+                (core module $a
+                    (memory (export "mem") 1 4)
+                    (global (export "count") (mut i32) (i32.const 0))
+                    (global i64 (i64.const 10)))
+                (core module $b
+                    (import "a" "mem" (memory 1))
+                    (memory 2)
+                    (global (mut f64) (f64.const 0)))
+                (core instance $a (instantiate $a))
+                (core instance (instantiate $b (with "a" (instance $a))))
+            )
+        "#,
+    )?;
+    let layout = component.static_memory_layout()?;
+
+    let memories = layout
+        .memories
+        .iter()
+        .map(|m| {
+            (
+                m.instance,
+                m.index,
+                m.ty.minimum(),
+                m.ty.maximum(),
+                m.imported,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        memories,
+        [
+            (0, 0, 1, Some(4), false),
+            (1, 0, 1, None, true),
+            (1, 1, 2, None, false),
+        ]
+    );
+    let globals = layout
+        .globals
+        .iter()
+        .map(|g| {
+            (
+                g.instance,
+                g.index,
+                g.ty.content().clone(),
+                g.ty.mutability(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        globals,
+        [
+            (0, 0, ValType::I32, Mutability::Var),
+            (0, 1, ValType::I64, Mutability::Const),
+            (1, 0, ValType::F64, Mutability::Var),
+        ]
+    );
+    assert!(layout.globals.iter().all(|g| !g.imported));
+
+    let component = component::Component::new(
+        &engine,
+        r#"
+            (component
+                (import "m" (core module $m))
+                (core instance (instantiate $m))
+            )
+        "#,
+    )?;
+    assert!(component.static_memory_layout().is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_check_compatibility() -> Result<()> {
+    let mut config = Config::new();
+    config.wasm_multi_memory(true).wasm_component_model(true);
+    let engine = Engine::new(&config)?;
+    let component = |module: &str| {
+        component::Component::new(&engine, format!("(component (core module {module}))"))
+    };
+    let old = component::Component::new(
+        &engine,
+        format!(
+            "(component {})",
+            COUNTER.replacen("(module", "(core module", 1)
+        ),
+    )?;
+
+    let report = engine.check_snapshot_compatibility(&old, &old)?;
+    assert!(report.is_compatible);
+    assert!(report.warnings.is_empty() && report.blockers.is_empty());
+
+    // A larger minimum memory and an extra global are fine.
+    let newer = component(
+        r#"
+        (memory 2 4)
+        (memory 1)
+        (global (mut i32) (i32.const 0))
+        (global i64 (i64.const 10))
+        (global (mut i32) (i32.const 7))
+        "#,
+    )?;
+    let report = engine.check_snapshot_compatibility(&old, &newer)?;
+    assert!(report.is_compatible);
+    assert_eq!(report.warnings.len(), 2, "{:?}", report.warnings);
+    assert!(report.blockers.is_empty());
+
+    // Changing the type of a global is not.
+    let retyped = component(
+        r#"
+        (memory 1 4)
+        (memory 1)
+        (global (mut i64) (i64.const 0))
+        (global i64 (i64.const 10))
+        "#,
+    )?;
+    let report = engine.check_snapshot_compatibility(&old, &retyped)?;
+    assert!(!report.is_compatible);
+    assert!(report.blockers[0].starts_with("core module 0: global 0"));
+
+    let other = Engine::new(&config)?;
+    assert!(other.check_snapshot_compatibility(&old, &old).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_coredump_roundtrip() -> Result<()> {
+    let mut config = Config::new();
+    config.wasm_multi_memory(true).wasm_component_model(true);
+    let engine = Engine::new(&config)?;
+    let mut store = Store::new(&engine, ());
+    let instance = Instance::new(&mut store, &Module::new(&engine, COUNTER)?, &[])?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let component = component::Component::new(
+        &engine,
+        format!(
+            "(component {})",
+            COUNTER.replacen("(module", "(core module", 1)
+        ),
+    )?;
+    let bytes = snapshot.to_coredump_bytes(&component)?;
+    let loaded = Snapshot::from_coredump(&bytes)?;
+    assert!(Snapshot::diff_report(&snapshot, &loaded)?.is_identical());
+
+    let instance = Instance::new(&mut store, &Module::new(&engine, COUNTER)?, &[])?;
+    instance.restore(&mut store, &loaded)?;
+    let count = instance.get_global(&mut store, "count").unwrap();
+    assert_eq!(count.get(&mut store).unwrap_i32(), 2);
+
+    let other = component::Component::new(&engine, "(component (core module (memory 1)))")?;
+    assert!(snapshot.to_coredump_bytes(&other).is_err());
+    assert!(Snapshot::from_coredump(&wat::parse_str("(module)")?).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_wit_debug_string() -> Result<()> {
+    let mut config = Config::new();
+    config.wasm_multi_memory(true).wasm_component_model(true);
+    let engine = Engine::new(&config)?;
+    let mut store = Store::new(&engine, ());
+    let instance = Instance::new(&mut store, &Module::new(&engine, COUNTER)?, &[])?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let component = component::Component::new(
+        &engine,
+        format!(
+            "(component {})",
+            COUNTER.replacen("(module", "(core module", 1)
+        ),
+    )?;
+    let text = snapshot.to_wit_debug_string(&component)?;
+    assert!(text.starts_with("snapshot of core module 0"), "{text}");
+    assert!(text.contains("  0: count: mut i32 = 2\n"), "{text}");
+    assert!(text.contains("  1: limit: i64 = 10\n"), "{text}");
+    assert!(text.contains("  0: mem: 1 pages, 65536 bytes\n"), "{text}");
+    assert!(
+        text.contains("    0x00000000..0x00000001 (1 bytes): 02\n"),
+        "{text}"
+    );
+    assert!(
+        text.contains("  1: memory1: 1 pages, 65536 bytes\n"),
+        "{text}"
+    );
+
+    let other = component::Component::new(&engine, "(component (core module (memory 1)))")?;
+    assert!(snapshot.to_wit_debug_string(&other).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_apply_memory_transform() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    let original = instance.snapshot(&mut store)?;
+
+    // XOR each byte with a key derived from its position, which is its own
+    // inverse.
+    let scramble = |memory: u32, offset: usize, chunk: &mut [u8]| {
+        for (i, byte) in chunk.iter_mut().enumerate() {
+            *byte ^= (memory as usize + offset + i) as u8 | 0x5a;
+        }
+    };
+
+    let mut seen = Vec::new();
+    let mut scrambled = original.clone();
+    scrambled.apply_memory_transform(10_000, |memory, offset, chunk| {
+        seen.push((memory, offset, chunk.len()));
+        scramble(memory, offset, chunk);
+    })?;
+    let page = 64 * 1024;
+    assert_eq!(seen.len(), 2 * 7);
+    assert_eq!(seen[6], (0, 60_000, page - 60_000));
+    assert_eq!(seen[7], (1, 0, 10_000));
+    assert!(!Snapshot::diff_report(&original, &scrambled)?.is_identical());
+
+    // Unscrambling with a different chunk size restores the original.
+    scrambled.apply_memory_transform(4096, scramble)?;
+    assert!(Snapshot::diff_report(&original, &scrambled)?.is_identical());
+    instance.restore(&mut store, &scrambled)?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    assert_eq!(&mem.data(&store)[..4], &1i32.to_le_bytes());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_split_rejoin() -> Result<()> {
+    let page = 64 * 1024;
+    let big = (0..100 * page).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let snapshot = Snapshot::from_parts(
+        vec![("mem".to_string(), big), ("empty".to_string(), Vec::new())],
+        vec![Val::I32(7), Val::I64(-1)],
+    );
+
+    let (header, data) = snapshot.split(&[(0, 75), (0, 25), (0, 50)])?;
+    assert!(header.is_header());
+    assert_eq!(header.sequence(), 0);
+    assert_eq!(data.len(), 5);
+    for (i, shard) in data.iter().enumerate() {
+        assert!(!shard.is_header());
+        assert_eq!(shard.sequence(), i as u32 + 1);
+        assert_eq!(shard.total_shards(), 6);
+    }
+
+    // Shards survive serialization and rejoin byte for byte.
+    let header = SnapshotShard::from_bytes(&header.to_bytes()?)?;
+    let data = data
+        .iter()
+        .map(|shard| SnapshotShard::from_bytes(&shard.to_bytes()?))
+        .collect::<Result<Vec<_>>>()?;
+    let rejoined = SnapshotShard::rejoin(&header, &data)?;
+    assert!(Snapshot::diff_report(&snapshot, &rejoined)?.is_identical());
+    assert_eq!(rejoined.to_bytes()?, snapshot.to_bytes()?);
+
+    // Missing, reordered and foreign shards are detected.
+    assert!(SnapshotShard::rejoin(&header, &data[1..]).is_err());
+    let mut swapped = data.clone();
+    swapped.swap(0, 1);
+    assert!(SnapshotShard::rejoin(&header, &swapped).is_err());
+    let (_, other) = snapshot.split(&[(0, 10), (0, 20), (0, 30)])?;
+    let mut mixed = data.clone();
+    mixed[1] = other[1].clone();
+    assert!(SnapshotShard::rejoin(&header, &mixed).is_err());
+    assert!(SnapshotShard::rejoin(&data[0], &data[1..]).is_err());
+
+    assert!(snapshot.split(&[(0, 0)]).is_err());
+    assert!(snapshot.split(&[(0, 100)]).is_err());
+    assert!(snapshot.split(&[(0, 10), (0, 10)]).is_err());
+    assert!(snapshot.split(&[(2, 1)]).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_chunks() -> Result<()> {
+    let data = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let snapshot = Snapshot::from_parts(
+        vec![("mem".to_string(), data)],
+        vec![Val::I32(7), Val::I64(-1)],
+    );
+    let bytes = snapshot.to_bytes()?;
+
+    let chunks = snapshot.clone().into_chunks(1000)?.collect::<Vec<_>>();
+    assert_eq!(chunks.len(), (bytes.len() + 999) / 1000);
+    for (i, chunk) in chunks.iter().enumerate() {
+        assert_eq!(chunk.index, i as u64);
+        assert_eq!(chunk.total, chunks.len() as u64);
+        assert!(chunk.verify_checksum());
+    }
+    assert!(chunks[..chunks.len() - 1]
+        .iter()
+        .all(|c| c.data.len() == 1000));
+
+    // Chunks delivered in reverse order, some of them twice, reassemble
+    // into the original snapshot.
+    let mut delivered = chunks.iter().rev().cloned().collect::<Vec<_>>();
+    delivered.push(chunks[2].clone());
+    let assembled = Snapshot::assemble_from_chunks(delivered)?;
+    assert!(Snapshot::diff_report(&snapshot, &assembled)?.is_identical());
+    assert_eq!(assembled.to_bytes()?, bytes);
+
+    // Missing, damaged and foreign chunks are detected.
+    assert!(Snapshot::assemble_from_chunks(chunks[1..].to_vec()).is_err());
+    assert!(Snapshot::assemble_from_chunks(Vec::new()).is_err());
+    let mut damaged = chunks.clone();
+    damaged[3].data[0] ^= 1;
+    assert!(!damaged[3].verify_checksum());
+    assert!(Snapshot::assemble_from_chunks(damaged).is_err());
+    let mut mixed = chunks.clone();
+    mixed[0] = snapshot.clone().into_chunks(500)?.next().unwrap();
+    assert!(Snapshot::assemble_from_chunks(mixed).is_err());
+
+    let single = snapshot
+        .clone()
+        .into_chunks(usize::MAX)?
+        .collect::<Vec<_>>();
+    assert_eq!(single.len(), 1);
+    assert_eq!(single[0].data, bytes);
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_strip_debug_info() -> Result<()> {
+    fn custom_section(name: &str, contents: &[u8]) -> Vec<u8> {
+        let size = 1 + name.len() + contents.len();
+        let mut section = vec![0, size as u8, name.len() as u8];
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(contents);
+        section
+    }
+
+    // A module held in memory by the guest, with a type section, a debug
+    // section and a name section.
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    module.extend_from_slice(&[1, 4, 1, 0x60, 0, 0]);
+    let debug = module.len() + 3 + ".debug_info".len();
+    module.extend(custom_section(".debug_info", &[0xab; 32]));
+    module.extend(custom_section("name", &[0xcd; 8]));
+
+    let mut mem = vec![0xee; 64];
+    mem.extend_from_slice(&module);
+    mem.resize(64 * 1024, 0);
+    let snapshot = Snapshot::from_parts(vec![("mem".to_string(), mem.clone())], vec![]);
+
+    let stripped = snapshot.strip_debug_info()?;
+    let data = stripped.memory_regions()?[0].data;
+    let debug = 64 + debug;
+    assert!(data[debug..debug + 32].iter().all(|b| *b == 0));
+    // Everything else, including the name of the debug section, is kept.
+    assert_eq!(&data[..debug], &mem[..debug]);
+    assert_eq!(&data[debug + 32..], &mem[debug + 32..]);
+
+    // Memories without wasm modules are unchanged.
+    let plain = Snapshot::from_parts(vec![("mem".to_string(), vec![0xab; 1024])], vec![]);
+    assert!(Snapshot::diff_report(&plain, &plain.strip_debug_info()?)?.is_identical());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_delta_chain() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+
+    let base = instance.snapshot(&mut store)?;
+    let mut previous = base.clone();
+    let mut deltas = Vec::new();
+    for i in 0..100 {
+        bump.call(&mut store, ())?;
+        if i == 50 {
+            mem.grow(&mut store, 1)?;
+        }
+        let offset = (i * 997) % mem.data_size(&store);
+        mem.data_mut(&mut store)[offset] = i as u8 + 1;
+        let current = instance.snapshot(&mut store)?;
+        let delta = current.compute_delta(&previous)?;
+        assert!(delta.changed_pages() <= 3);
+        assert_eq!(delta.changed_globals(), 1);
+        deltas.push(delta);
+        previous = current;
+    }
+
+    let mut rebuilt = base.clone();
+    for delta in deltas.iter() {
+        delta.apply_in_place(&mut rebuilt)?;
+    }
+    assert!(Snapshot::diff_report(&previous, &rebuilt)?.is_identical());
+    assert_eq!(rebuilt.size_bytes(), previous.size_bytes());
+
+    // Deltas only apply to a snapshot of the shape they were computed from.
+    let first = deltas[0].apply(&base)?;
+    assert!(deltas[60].apply(&base).is_err());
+    assert!(deltas[1].apply(&first).is_ok());
+
+    let config = DeltaConfig { page_size: 64 };
+    let fine = previous.compute_delta_with(&base, &config)?;
+    assert_eq!(fine.page_size(), 64);
+    assert!(Snapshot::diff_report(&previous, &fine.apply(&base)?)?.is_identical());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_pad_memory_and_extend_globals() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?;
+    let page = 64 * 1024;
+
+    // A newer version of the module needs two pages and an extra global.
+    let newer = Module::new(
+        store.engine(),
+        r#"
+        (module
+            (memory (export "mem") 2 4)
+            (memory 1)
+            (global (export "count") (mut i32) (i32.const 0))
+            (global (export "limit") i64 (i64.const 10))
+            (global (export "extra") (mut i32) (i32.const 0)))
+        "#,
+    )?;
+    let migrated = snapshot
+        .pad_memory(0, 2 * page)?
+        .extend_globals(3, Val::I32(42))?;
+    assert_eq!(migrated.region_stats()[0].size_bytes, 2 * page);
+    assert_eq!(
+        migrated.size_bytes(),
+        snapshot.size_bytes() + page + std::mem::size_of::<i32>()
+    );
+
+    let instance = Instance::new(&mut store, &newer, &[])?;
+    instance.restore(&mut store, &migrated)?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    assert_eq!(&mem.data(&store)[..4], &1i32.to_le_bytes());
+    let extra = instance.get_global(&mut store, "extra").unwrap();
+    assert_eq!(extra.get(&mut store).unwrap_i32(), 42);
+
+    assert!(snapshot.pad_memory(0, page - 1).is_err());
+    assert!(snapshot.pad_memory(7, 2 * page).is_err());
+    assert!(snapshot.extend_globals(1, Val::I32(0)).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_parts_roundtrip() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let regions = snapshot.memory_regions()?;
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions[0].index, 0);
+    assert_eq!(regions[0].name, "mem");
+    assert_eq!(regions[0].minimum_pages, 1);
+    assert_eq!(&regions[0].data[..4], &2i32.to_le_bytes());
+    assert_eq!(regions[1].index, 1);
+    assert_eq!(snapshot.globals().len(), 2);
+    assert_eq!(snapshot.globals()[0].unwrap_i32(), 2);
+
+    // Reassemble the snapshot as a custom deserializer would.
+    let rebuilt = Snapshot::from_parts(
+        regions
+            .iter()
+            .map(|r| (r.name.to_string(), r.data.to_vec()))
+            .collect(),
+        snapshot.globals().to_vec(),
+    );
+    assert!(Snapshot::diff_report(&snapshot, &rebuilt)?.is_identical());
+
+    let instance = instantiate(&mut store)?;
+    instance.restore(&mut store, &rebuilt)?;
+    let count = instance.get_global(&mut store, "count").unwrap();
+    assert_eq!(count.get(&mut store).unwrap_i32(), 2);
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_check_invariants() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    for _ in 0..3 {
+        bump.call(&mut store, ())?;
+    }
+    let snapshot = instance.snapshot(&mut store)?;
+
+    snapshot.check_invariants(&[
+        MemoryInvariant::ByteEquals {
+            memory: 0,
+            offset: 0,
+            expected: vec![3, 0, 0, 0],
+        },
+        MemoryInvariant::U32Equals {
+            memory: 0,
+            offset: 0,
+            expected: 3,
+        },
+        MemoryInvariant::U32RangeInclusive {
+            memory: 0,
+            offset: 0,
+            lo: 1,
+            hi: 10,
+        },
+        MemoryInvariant::Custom {
+            memory: 1,
+            check: Box::new(|data| data.iter().all(|b| *b == 0)),
+        },
+    ])?;
+
+    let err = snapshot
+        .check_invariants(&[
+            MemoryInvariant::U32Equals {
+                memory: 0,
+                offset: 0,
+                expected: 3,
+            },
+            MemoryInvariant::U32RangeInclusive {
+                memory: 0,
+                offset: 0,
+                lo: 4,
+                hi: 10,
+            },
+        ])
+        .unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        "memory invariant 1 does not hold: u32 at offset 0x0 of memory 0 is 3, \
+         expected a value in 4..=10"
+    );
+
+    let page = 64 * 1024;
+    assert!(snapshot
+        .check_invariants(&[MemoryInvariant::U32Equals {
+            memory: 0,
+            offset: page - 2,
+            expected: 0,
+        }])
+        .is_err());
+    assert!(snapshot
+        .check_invariants(&[MemoryInvariant::Custom {
+            memory: 2,
+            check: Box::new(|_| true),
+        }])
+        .is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_gc_compact() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    let count = instance.get_global(&mut store, "count").unwrap();
+    // Each object is a little-endian u32 length followed by that many bytes.
+    let data = mem.data_mut(&mut store);
+    data[..4].copy_from_slice(&[1, 2, 3, 4]);
+    data[0x100..0x108].copy_from_slice(&[4, 0, 0, 0, 0xdd, 0xdd, 0xdd, 0xdd]);
+    data[0x200..0x20c].copy_from_slice(&[8, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+    data[0x300..0x308].copy_from_slice(&[4, 0, 0, 0, 9, 10, 11, 12]);
+    count.set(&mut store, Val::I32(0x304))?;
+    let snapshot = instance.snapshot(&mut store)?;
+    let record_size = |data: &[u8], addr: usize| {
+        4 + u32::from_le_bytes(data[addr..][..4].try_into().unwrap()) as usize
+    };
+
+    let (compacted, remapping) =
+        snapshot.gc_compact(0, &[0x300, 0x200, 0x200], 0x10, record_size)?;
+    assert_eq!(
+        remapping.objects().collect::<Vec<_>>(),
+        [(0x200, 0x10), (0x300, 0x1c)]
+    );
+    assert_eq!(remapping.heap_end(), 0x24);
+    assert_eq!(remapping.remap(0x208), Some(0x18));
+    assert_eq!(remapping.remap(0x100), None);
+    assert_eq!(remapping.remap(0x20c), None);
+
+    instance.restore(&mut store, &compacted)?;
+    let data = mem.data(&store);
+    assert_eq!(&data[..4], &[1, 2, 3, 4]);
+    assert_eq!(
+        &data[0x10..0x24],
+        &[8, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 4, 0, 0, 0, 9, 10, 11, 12]
+    );
+    assert!(data[0x24..].iter().all(|b| *b == 0));
+    assert_eq!(count.get(&mut store).unwrap_i32(), 0x20);
+    // `limit` does not point into a live object.
+    assert_eq!(compacted.globals()[1].unwrap_i64(), 10);
+
+    let err = snapshot
+        .gc_compact(0, &[0x200, 0x204], 0x10, |_, _| 8)
+        .unwrap_err();
+    assert!(err.to_string().contains("overlaps"), "{err}");
+    assert!(snapshot.gc_compact(0, &[0x8], 0x10, record_size).is_err());
+    assert!(snapshot.gc_compact(2, &[], 0x10, record_size).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_page_counts() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    mem.data_mut(&mut store)[0] = 1;
+    mem.data_mut(&mut store)[5 * 4096 + 1] = 2;
+    let mut snapshot = instance.snapshot(&mut store)?;
+
+    assert_eq!(snapshot.page_count(0, 4096)?, 16);
+    assert_eq!(snapshot.page_count(0, 60000)?, 2);
+    assert_eq!(snapshot.non_zero_page_count(0, 4096)?, 2);
+    assert_eq!(snapshot.non_zero_page_count(1, 4096)?, 0);
+    assert_eq!(snapshot.sparsity_ratio(0)?, 1.0);
+    assert_eq!(snapshot.sparsity_ratio(1)?, 0.0);
+    assert!(snapshot.page_count(2, 4096).is_err());
+    assert!(snapshot.sparsity_ratio(2).is_err());
+
+    // Two non-zero bytes make the only wasm page of memory 0 very sparse.
+    assert_eq!(snapshot.zero_fill_sparse_pages(0.0)?, 0);
+    assert_eq!(snapshot.zero_fill_sparse_pages(0.5)?, 1);
+    assert_eq!(snapshot.non_zero_page_count(0, 4096)?, 0);
+
+    mem.data_mut(&mut store).fill(0xff);
+    let mut snapshot = instance.snapshot(&mut store)?;
+    assert_eq!(snapshot.zero_fill_sparse_pages(0.5)?, 0);
+    assert_eq!(snapshot.non_zero_page_count(0, 4096)?, 16);
+    Ok(())
+}
+
+#[test]
+fn snapshot_memory_histogram() -> Result<()> {
+    let mut data = vec![0; 100];
+    data[1] = 1;
+    data[2] = 2;
+    data[3] = 2;
+    data[99] = 7;
+    let snapshot = Snapshot::from_parts(
+        vec![("mem".to_string(), data), ("empty".to_string(), Vec::new())],
+        Vec::new(),
+    );
+
+    let histogram = snapshot.memory_histogram(0, 40)?;
+    assert_eq!(
+        histogram,
+        [
+            BucketStats {
+                byte_range: 0..40,
+                non_zero_bytes: 3,
+                distinct_values: 3,
+            },
+            BucketStats {
+                byte_range: 40..80,
+                non_zero_bytes: 0,
+                distinct_values: 1,
+            },
+            BucketStats {
+                byte_range: 80..100,
+                non_zero_bytes: 1,
+                distinct_values: 2,
+            },
+        ]
+    );
+    assert!(snapshot.memory_histogram(1, 40)?.is_empty());
+    assert!(snapshot.memory_histogram(2, 40).is_err());
+
+    let uniform = Snapshot::from_parts(vec![("mem".to_string(), (0..=255).collect())], Vec::new());
+    assert_eq!(uniform.entropy_estimate(0)?, 8.0);
+    let halves = Snapshot::from_parts(vec![("mem".to_string(), [0, 1].repeat(50))], Vec::new());
+    assert_eq!(halves.entropy_estimate(0)?, 1.0);
+    assert_eq!(snapshot.entropy_estimate(1)?, 0.0);
+    assert!(snapshot.entropy_estimate(0)? < 1.0);
+    assert!(snapshot.entropy_estimate(2).is_err());
+    Ok(())
+}
+
+#[test]
+fn snapshot_memory_reader_and_writer() -> Result<()> {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    let mut snapshot = Snapshot::from_parts(vec![("mem".to_string(), vec![0; 64])], Vec::new());
+    let mut writer = snapshot.memory_as_writer(0)?;
+    writer.seek(SeekFrom::Start(8))?;
+    writer.write_all(br#"{"name":"cart","items":3}"#)?;
+    assert_eq!(writer.position(), 33);
+    writer.seek(SeekFrom::End(-1))?;
+    assert!(writer.write_all(b"too long").is_err());
+
+    let mut reader = snapshot.memory_as_reader(0)?;
+    reader.seek(SeekFrom::Start(8))?;
+    let json = (&mut reader).take(25);
+    let value: serde_json::Value = serde_json::from_reader(json)?;
+    assert_eq!(value["name"], "cart");
+    assert_eq!(value["items"], 3);
+    assert_eq!(reader.position(), 33);
+
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest)?;
+    assert_eq!(rest.len(), 31);
+    assert_eq!(rest[30], b't');
+
+    assert!(snapshot.memory_as_reader(1).is_err());
+    assert!(snapshot.memory_as_writer(1).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_replay_allocations() -> Result<()> {
+    #[derive(Default)]
+    struct Recorder(Vec<(usize, Option<usize>)>);
+
+    impl WasmAllocator for Recorder {
+        fn record_allocation(&mut self, offset: usize, size: usize) {
+            self.0.push((offset, Some(size)));
+        }
+
+        fn record_free(&mut self, offset: usize) {
+            self.0.push((offset, None));
+        }
+    }
+
+    // A toy heap of chunks, each with a header of its size and whether it is
+    // in use, ended by a zero size.
+    let mut heap = vec![0u8; 256];
+    let mut offset = 0;
+    for (size, used) in [(16u32, 1u32), (32, 0), (8, 1)] {
+        heap[offset..offset + 4].copy_from_slice(&size.to_le_bytes());
+        heap[offset + 4..offset + 8].copy_from_slice(&used.to_le_bytes());
+        offset += 8 + size as usize;
+    }
+    let scan = |data: &[u8]| {
+        let mut records = Vec::new();
+        let mut offset = 0;
+        loop {
+            let size = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            if size == 0 {
+                break records;
+            }
+            let used = data[offset + 4] != 0;
+            records.push(if used {
+                AllocationRecord::Allocation {
+                    offset: offset + 8,
+                    size,
+                }
+            } else {
+                AllocationRecord::Free { offset: offset + 8 }
+            });
+            offset += 8 + size;
+        }
+    };
+    let snapshot = Snapshot::from_parts(vec![("heap".to_string(), heap)], Vec::new());
+
+    let mut recorder = Recorder::default();
+    snapshot.replay_allocations(0, scan, &mut recorder)?;
+    assert_eq!(recorder.0, [(8, Some(16)), (32, None), (72, Some(8))]);
+
+    // Records outside of the memory are rejected before any is replayed.
+    let mut recorder = Recorder::default();
+    let out_of_bounds = |_: &[u8]| {
+        vec![
+            AllocationRecord::Allocation { offset: 0, size: 8 },
+            AllocationRecord::Allocation {
+                offset: 250,
+                size: 8,
+            },
+        ]
+    };
+    assert!(snapshot
+        .replay_allocations(0, out_of_bounds, &mut recorder)
+        .is_err());
+    assert!(recorder.0.is_empty());
+    assert!(snapshot
+        .replay_allocations(1, |_| Vec::new(), &mut recorder)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_assertions() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    for _ in 0..3 {
+        bump.call(&mut store, ())?;
+    }
+    let snapshot = instance.snapshot(&mut store)?;
+
+    snapshot.assert_globals(&[(0, Val::I32(3)), (1, Val::I64(10))])?;
+    snapshot.assert_memory_bytes(0, 0, &[3, 0, 0, 0])?;
+    snapshot.assert_memory_bytes(1, 0x100, &[0; 16])?;
+
+    // Every mismatch is listed on its own line.
+    let err = snapshot
+        .assert_globals(&[(0, Val::I32(4)), (1, Val::I64(10)), (2, Val::I32(0))])
+        .unwrap_err()
+        .to_string();
+    let lines = err.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{err}");
+    assert!(lines[1].contains("global 0: expected I32(4), found I32(3)"));
+    assert!(lines[2].contains("global 2"));
+
+    let err = snapshot
+        .assert_memory_bytes(0, 0, &[4, 0, 0, 0, 0, 1, 1])
+        .unwrap_err()
+        .to_string();
+    let lines = err.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{err}");
+    assert!(lines[1].contains("0x0..0x1: expected [04], found [03]"));
+    assert!(lines[2].contains("0x5..0x7: expected [01, 01], found [00, 00]"));
+
+    assert!(snapshot
+        .assert_memory_bytes(0, 64 * 1024 - 2, &[0; 4])
+        .is_err());
+    assert!(snapshot.assert_memory_bytes(2, 0, &[]).is_err());
+    Ok(())
+}
+
+#[test]
+fn snapshot_annotated_hex_dump() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    for _ in 0..3 {
+        bump.call(&mut store, ())?;
+    }
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let mut annotations = AnnotationMap::new();
+    annotations
+        .label(0, 0, "count")
+        .label(0, 9, "padding")
+        .label(1, 0, "other memory");
+    let dump = snapshot.annotated_hex_dump(0, 0..20, &annotations)?;
+    let lines = dump.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4, "{dump}");
+    assert_eq!(
+        lines[0],
+        "0x00000000  03 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|"
+    );
+    assert_eq!(lines[1], format!("{:12}^ count", ""));
+    assert_eq!(lines[2], format!("{:40}^ padding", ""));
+    assert!(lines[3].starts_with("0x00000010  00 00 00 00   "));
+    assert!(lines[3].ends_with("  |....|"));
+
+    assert!(snapshot
+        .annotated_hex_dump(0, 0..0x20_0000, &annotations)
+        .is_err());
+    assert!(snapshot.annotated_hex_dump(7, 0..1, &annotations).is_err());
+    Ok(())
+}
+
+#[test]
+fn snapshot_normalize_endianness() -> Result<()> {
+    use wasmtime::{Endianness, WordSize};
+
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?;
+    let types = [ValType::I32, ValType::I64];
+    let (le, be) = (Endianness::Little, Endianness::Big);
+
+    let swapped = snapshot.normalize_globals(le, be, &types)?;
+    swapped.assert_globals(&[(0, Val::I32(1 << 24)), (1, Val::I64(10 << 56))])?;
+    let restored = swapped.normalize_globals(be, le, &types)?;
+    restored.assert_globals(&[(0, Val::I32(1)), (1, Val::I64(10))])?;
+    assert!(snapshot.normalize_globals(le, be, &types[..1]).is_err());
+    assert!(snapshot
+        .normalize_globals(le, be, &[ValType::I64, ValType::I64])
+        .is_err());
+
+    let swapped = snapshot.normalize_memory_words(0, WordSize::U32, le, be, &[0..8])?;
+    swapped.assert_memory_bytes(0, 0, &[0, 0, 0, 1])?;
+    let restored = swapped.normalize_memory_words(0, WordSize::U32, be, le, &[0..8])?;
+    assert_eq!(
+        restored.memory_regions()?[0].data,
+        snapshot.memory_regions()?[0].data
+    );
+    assert!(snapshot
+        .normalize_memory_words(0, WordSize::U64, le, be, &[0..6])
+        .is_err());
+    assert!(snapshot
+        .normalize_memory_words(0, WordSize::U16, le, be, &[0..0x20_0000])
+        .is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_call_stack_depth() -> Result<()> {
+    let engine = Engine::default();
+    let module = Module::new(
+        &engine,
+        r#"
+            (module
+                (import "" "depth" (func $depth (result i32)))
+                (func $inner (result i32) (call $depth))
+                (func (export "run") (result i32) (call $inner))
+            )
+        "#,
+    )?;
+    let mut store = Store::new(&engine, None::<(Instance, Instance)>);
+    // Reports the depth of the running instance in the tens and that of an
+    // idle instance of another module in the units.
+    let depth = Func::wrap(
+        &mut store,
+        |caller: Caller<'_, Option<(Instance, Instance)>>| -> Result<u32> {
+            let (running, idle) = caller.data().unwrap();
+            let depth = caller.as_context().get_wasm_stack_depth(&running)?;
+            assert_eq!(depth, running.call_stack_depth(&caller));
+            Ok(depth * 10 + idle.call_stack_depth(&caller))
+        },
+    );
+    let instance = Instance::new(&mut store, &module, &[depth.into()])?;
+    let idle = Instance::new(&mut store, &Module::new(&engine, "(module)")?, &[])?;
+    *store.data_mut() = Some((instance, idle));
+    assert_eq!(instance.call_stack_depth(&store), 0);
+
+    let run = instance.get_typed_func::<(), u32>(&mut store, "run")?;
+    assert_eq!(run.call(&mut store, ())?, 20);
+    assert_eq!(instance.call_stack_depth(&store), 0);
+    assert_eq!(store.get_wasm_stack_depth(&instance)?, 0);
+
+    let other = Store::new(&engine, ());
+    assert!(other.get_wasm_stack_depth(&instance).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_external_memory_files() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?;
+    assert!(!snapshot.is_externalized());
+
+    let dir = tempfile::tempdir()?;
+    let mut external = snapshot.clone();
+    external.export_memory_to_file(0, dir.path().join("mem0.bin"))?;
+    assert!(external.is_externalized());
+    assert_eq!(
+        std::fs::read(dir.path().join("mem0.bin"))?,
+        snapshot.memory_regions()?[0].data
+    );
+    assert!(external
+        .export_memory_to_file(0, dir.path().join("again.bin"))
+        .is_err());
+    assert!(external
+        .export_memory_to_file(5, dir.path().join("x"))
+        .is_err());
+
+    // The size of an exported memory is still known, but its contents
+    // cannot be touched.
+    assert_eq!(external.size_bytes(), snapshot.size_bytes());
+    assert_eq!(external.region_stats(), snapshot.region_stats());
+    assert!(external.truncate_memory(0, 0, |_| true).is_err());
+    assert!(external.pad_memory(0, 2 * 65536).is_err());
+    assert!(external.page_count(0, 4096).is_err());
+    assert_eq!(external.page_count(1, 4096)?, 16);
+    assert!(external.memory_regions().is_err());
+    assert!(Snapshot::diff_report(&snapshot, &external).is_err());
+    assert!(external.compute_delta(&snapshot).is_err());
+    assert!(external.strip_debug_info().is_err());
+    assert!(external
+        .clone()
+        .apply_memory_transform(4096, |_, _, _| {})
+        .is_err());
+    assert!(external.clone().zero_fill_sparse_pages(1.0).is_err());
+    // Its hash is that of the file's contents.
+    assert_eq!(external.compute_hash(), snapshot.compute_hash());
+    assert_eq!(
+        external.memory_checksum_map(),
+        snapshot.memory_checksum_map()
+    );
+
+    // The file location survives serialization, and externalized snapshots
+    // cannot be restored until they are internalized.
+    let external = Snapshot::from_bytes(&external.to_bytes()?)?;
+    assert!(external.is_externalized());
+    bump.call(&mut store, ())?;
+    assert!(instance.restore(&mut store, &external).is_err());
+    let internal = external.internalize(dir.path())?;
+    assert!(!internal.is_externalized());
+    assert!(Snapshot::diff_report(&snapshot, &internal)?.is_identical());
+    instance.restore(&mut store, &internal)?;
+    let count = instance.get_global(&mut store, "count").unwrap();
+    assert_eq!(count.get(&mut store).unwrap_i32(), 1);
+
+    // Files which do not hold the exported contents are rejected.
+    let mut moved = external.clone();
+    std::fs::write(dir.path().join("other.bin"), [1, 2, 3])?;
+    assert!(moved
+        .import_memory_from_file(0, dir.path().join("other.bin"))
+        .is_err());
+    assert!(moved.is_externalized());
+    std::fs::rename(dir.path().join("mem0.bin"), dir.path().join("moved.bin"))?;
+    assert!(external.internalize(dir.path()).is_err());
+    moved.import_memory_from_file(0, dir.path().join("moved.bin"))?;
+    assert!(!moved.is_externalized());
+
+    // Memories which were not exported take any raw image.
+    let mut raw = snapshot.clone();
+    raw.import_memory_from_file(1, dir.path().join("other.bin"))?;
+    assert_eq!(raw.memory_regions()?[1].data, [1, 2, 3]);
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn snapshot_msgpack() -> Result<()> {
+    let mut store = new_store()?;
+    let instance = instantiate(&mut store)?;
+    let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+    bump.call(&mut store, ())?;
+    let snapshot = instance.snapshot(&mut store)?;
+
+    let msgpack = snapshot.to_msgpack()?;
+    assert_eq!(msgpack[0], 1);
+    assert!(msgpack.len() < snapshot.to_bytes()?.len());
+    let roundtrip = Snapshot::from_msgpack(&msgpack)?;
+    assert!(Snapshot::diff_report(&snapshot, &roundtrip)?.is_identical());
+    assert_eq!(roundtrip.to_bytes()?, snapshot.to_bytes()?);
+
+    let mut unknown = msgpack.clone();
+    unknown[0] = 2;
+    let err = Snapshot::from_msgpack(&unknown).unwrap_err();
+    assert!(err.to_string().contains("format version 2"), "{err}");
+    assert!(Snapshot::from_msgpack(&[]).is_err());
+    assert!(Snapshot::from_msgpack(&msgpack[..msgpack.len() / 2]).is_err());
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
-use wasmtime::{Engine, Store};
+use wasmtime::{Engine, Instance, Memory, MemoryType, Module, Store};
 
 #[test]
 fn into_inner() {
@@ -20,3 +20,60 @@ fn into_inner() {
     Store::new(&engine, A).into_data();
     assert_eq!(HITS.load(SeqCst), 2);
 }
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn inject_and_extract_memory() -> anyhow::Result<()> {
+    let engine = Engine::default();
+    let mut store = Store::new(&engine, ());
+    let host = Memory::new(&mut store, MemoryType::new(1, None))?;
+    let module = Module::new(&engine, r#"(module (memory (export "mem") 1))"#)?;
+    let instance = Instance::new(&mut store, &module, &[])?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+
+    store.inject_memory(1, b"hello")?;
+    assert_eq!(&mem.data(&store)[..5], b"hello");
+    assert!(host.data(&store).iter().all(|b| *b == 0));
+
+    let extracted = store.extract_memory(1)?;
+    assert_eq!(extracted.len(), 65536);
+    assert_eq!(&extracted[..5], b"hello");
+
+    assert!(store.inject_memory(0, &vec![0; 65537]).is_err());
+    assert!(store.extract_memory(2).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn inject_and_extract_data() -> anyhow::Result<()> {
+    let engine = Engine::default();
+    let mut store = Store::new(&engine, ());
+    Memory::new(&mut store, MemoryType::new(1, None))?;
+    let module = Module::new(&engine, r#"(module (memory (export "mem") 2))"#)?;
+    let instance = Instance::new(&mut store, &module, &[])?;
+    let mem = instance.get_memory(&mut store, "mem").unwrap();
+    mem.data_mut(&mut store)[65536..65541].copy_from_slice(b"hello");
+
+    let snapshot = store.extract_data()?;
+    let regions = snapshot.memory_regions()?;
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions[0].data.len(), 65536);
+    assert_eq!(regions[1].name, "memory1");
+    assert_eq!(&regions[1].data[65536..65541], b"hello");
+    assert!(snapshot.globals().is_empty());
+
+    mem.data_mut(&mut store).fill(0);
+    store.inject_data(&snapshot)?;
+    assert_eq!(&mem.data(&store)[65536..65541], b"hello");
+
+    // Snapshots which do not fit the store are rejected before any memory
+    // is written to.
+    let mut other = Store::new(&engine, ());
+    let small = Memory::new(&mut other, MemoryType::new(1, None))?;
+    Memory::new(&mut other, MemoryType::new(1, None))?;
+    small.data_mut(&mut other)[0] = 1;
+    assert!(other.inject_data(&snapshot).is_err());
+    assert_eq!(small.data(&other)[0], 1);
+    Ok(())
+}